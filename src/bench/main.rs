@@ -0,0 +1,31 @@
+use actix::System;
+use tp2::bench::{self, BenchOptions, USAGE};
+use tp2::config;
+
+/// Entry point of the benchmark.
+///
+/// The size of the cluster is the one of the config, it can be changed with the `FREDDO_ROBOTS` and `FREDDO_SCREENS` environment variables.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let args = match config::init_from_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let options = match BenchOptions::from_args(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let system = System::new();
+    match system.block_on(bench::run(options)) {
+        Ok(report) => println!("{}", report),
+        Err(e) => println!("Error: {}", e),
+    }
+}
@@ -0,0 +1,347 @@
+//! This module contains the benchmark, that starts every robot and screen of the cluster in one process,
+//! submits synthetic orders at a target rate, or the orders of a file at their times, and reports the throughput, the completion latency and the elections.
+
+use actix::Addr;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::common::metrics;
+use crate::common::order::Order;
+use crate::common::simulation;
+use crate::config;
+use crate::ordergen::{next_arrival_ms, OrderDistribution};
+use crate::robot::messages::GetRobotStatus;
+use crate::robot::utils::start_robot;
+use crate::screen::communication::start_headless;
+use crate::screen::order_api::OrderStatus;
+use crate::screen::order_reader::read_timed_orders;
+use crate::screen::payments_gateway::{GetOrderStatus, PaymentsGateway, SubmitOrder};
+
+/// How often the screens are asked for the status of the orders
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub const USAGE: &str =
+    "Usage: bench [--rate <orders/sec>] [--duration <secs>] [--warmup <secs>] [--drain <secs>] [--orders <file>] [--config <file>]";
+
+/// How the benchmark runs: orders are submitted at `rate` per second during `duration`,
+/// after giving the cluster `warmup` to elect a leader, and then it waits up to `drain` for the orders left.
+/// With an `orders` file its orders are submitted instead, each one at its time or, if it has none, at `rate`
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchOptions {
+    pub rate: f64,
+    pub duration: Duration,
+    pub warmup: Duration,
+    pub drain: Duration,
+    pub orders: Option<String>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            rate: 5.0,
+            duration: Duration::from_secs(30),
+            warmup: Duration::from_secs(5),
+            drain: Duration::from_secs(60),
+            orders: None,
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Parses the flags of the benchmark, the ones not given keep their default value
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("Missing value for flag {}", flag))?;
+            if flag == "--orders" {
+                options.orders = Some(value.clone());
+                continue;
+            }
+            let number: f64 = value
+                .parse()
+                .ok()
+                .filter(|number: &f64| number.is_finite() && *number >= 0.0)
+                .ok_or_else(|| format!("{} must be a positive number", flag))?;
+            match flag.as_str() {
+                "--rate" if number > 0.0 => options.rate = number,
+                "--rate" => return Err("--rate must be greater than 0".to_string()),
+                "--duration" => options.duration = Duration::from_secs_f64(number),
+                "--warmup" => options.warmup = Duration::from_secs_f64(number),
+                "--drain" => options.drain = Duration::from_secs_f64(number),
+                _ => return Err(format!("Unknown flag {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Results of a run of the benchmark
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BenchReport {
+    pub robots: usize,
+    pub screens: usize,
+    pub submitted: usize,
+    pub confirmed: usize,
+    pub aborted: usize,
+    pub declined: usize,
+    pub unfinished: usize,
+    /// Time from the first order submitted to the last one finished
+    pub elapsed: Duration,
+    /// Time each confirmed or aborted order took, from the shortest to the longest
+    pub latencies: Vec<Duration>,
+    pub elections: u64,
+}
+
+impl BenchReport {
+    /// Orders prepared or aborted per second
+    pub fn orders_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        (self.confirmed + self.aborted) as f64 / secs
+    }
+
+    /// Returns the latency below which the given fraction of the orders finished
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (fraction * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = |latency: Option<Duration>| match latency {
+            Some(latency) => format!("{} ms", latency.as_millis()),
+            None => "-".to_string(),
+        };
+        writeln!(
+            f,
+            "Cluster:     {} robots, {} screens",
+            self.robots, self.screens
+        )?;
+        writeln!(
+            f,
+            "Orders:      {} submitted, {} confirmed, {} aborted, {} declined, {} unfinished",
+            self.submitted, self.confirmed, self.aborted, self.declined, self.unfinished
+        )?;
+        writeln!(
+            f,
+            "Throughput:  {:.2} orders/sec over {:.1} secs",
+            self.orders_per_second(),
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "Latency:     p50 {}, p95 {}",
+            millis(self.percentile(0.5)),
+            millis(self.percentile(0.95))
+        )?;
+        write!(f, "Elections:   {}", self.elections)
+    }
+}
+
+/// Returns the orders to submit with the time since the start at which each one is submitted,
+/// the ones of the file or synthetic ones of random kinds and flavors of the catalog, arriving at random at the rate
+pub fn schedule(options: &BenchOptions) -> Result<Vec<(Duration, Order)>, String> {
+    let interval = Duration::from_secs_f64(1.0 / options.rate);
+    if let Some(file_name) = &options.orders {
+        let orders = read_timed_orders(file_name)?;
+        return Ok(orders
+            .into_iter()
+            .enumerate()
+            .map(|(i, timed)| {
+                let at = match timed.arrival_ms {
+                    Some(arrival_ms) => Duration::from_millis(arrival_ms),
+                    None => interval * i as u32,
+                };
+                (at, timed.order)
+            })
+            .collect());
+    }
+    let mut rng = simulation::rng("BENCH", 0);
+    let distribution = OrderDistribution::uniform(&config::get().flavors);
+    distribution.validate()?;
+    let mut at = Duration::ZERO;
+    let mut orders = Vec::new();
+    while at < options.duration {
+        orders.push((at, distribution.sample(&mut rng)));
+        at += Duration::from_millis(next_arrival_ms(&mut rng, options.rate));
+    }
+    Ok(orders)
+}
+
+/// Submits the orders given to the screen and asks for their status until they finish,
+/// then sends the status and the time each one took.
+/// The screen is asked one thing at a time, so its mailbox is left for the messages of the cluster
+async fn track_screen(
+    payments_gateway: Addr<PaymentsGateway>,
+    mut orders: UnboundedReceiver<(Order, Instant)>,
+    results: UnboundedSender<(OrderStatus, Duration)>,
+) {
+    let mut pending: Vec<(String, Instant)> = Vec::new();
+    let mut submitting = true;
+    while submitting || !pending.is_empty() {
+        loop {
+            match orders.try_recv() {
+                Ok((order, submitted_at)) => {
+                    if let Ok(Ok(id)) = payments_gateway.send(SubmitOrder::new(order)).await {
+                        pending.push((id, submitted_at));
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    submitting = false;
+                    break;
+                }
+            }
+        }
+        let mut still_pending = Vec::new();
+        for (id, submitted_at) in pending {
+            match payments_gateway.send(GetOrderStatus::new(id.clone())).await {
+                Ok(Some(
+                    status @ (OrderStatus::Confirmed { .. }
+                    | OrderStatus::Aborted { .. }
+                    | OrderStatus::Declined),
+                )) => {
+                    let _ = results.send((status, submitted_at.elapsed()));
+                }
+                Ok(_) => still_pending.push((id, submitted_at)),
+                Err(_) => return,
+            }
+        }
+        pending = still_pending;
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Starts the cluster, submits the orders and waits for them, it has to be called from inside an actix System
+pub async fn run(options: BenchOptions) -> Result<BenchReport, String> {
+    let orders = schedule(&options)?;
+    simulation::start_clock();
+    let mut report = BenchReport {
+        robots: config::number_of_robots(),
+        screens: config::number_of_screens(),
+        ..BenchReport::default()
+    };
+    let (results_sender, mut results) = unbounded_channel();
+    let mut screens = Vec::new();
+    for id in 0..report.screens {
+        let (orders_sender, orders) = unbounded_channel();
+        let payments_gateway = start_headless(id).await;
+        actix::spawn(track_screen(
+            payments_gateway,
+            orders,
+            results_sender.clone(),
+        ));
+        screens.push(orders_sender);
+    }
+    // a robot answers only once it joined the ring, they are started one at a time like the binaries
+    for id in 0..report.robots {
+        let (robot_connection_handler, _) = start_robot(id, None, None);
+        let _ = robot_connection_handler.send(GetRobotStatus()).await;
+    }
+    sleep(options.warmup).await;
+
+    let start = Instant::now();
+    let last_arrival = orders.last().map(|(at, _)| *at).unwrap_or_default();
+    let drain_until = sleep_until(start + last_arrival + options.drain);
+    tokio::pin!(drain_until);
+    let mut orders = orders.into_iter().peekable();
+    let mut finished = 0;
+    let mut last_finished = start;
+
+    while orders.peek().is_some() || finished < report.submitted {
+        let next_arrival = start + orders.peek().map(|(at, _)| *at).unwrap_or_default();
+        tokio::select! {
+            _ = sleep_until(next_arrival), if orders.peek().is_some() => {
+                let Some((_, order)) = orders.next() else {
+                    continue;
+                };
+                let screen = &screens[report.submitted % screens.len()];
+                if screen.send((order, Instant::now())).is_ok() {
+                    report.submitted += 1;
+                }
+            }
+            Some((status, latency)) = results.recv() => {
+                finished += 1;
+                match status {
+                    OrderStatus::Declined => {
+                        report.declined += 1;
+                        continue;
+                    }
+                    OrderStatus::Aborted { .. } => report.aborted += 1,
+                    _ => report.confirmed += 1,
+                }
+                last_finished = Instant::now();
+                report.latencies.push(latency);
+            }
+            _ = &mut drain_until => break,
+        }
+    }
+
+    report.unfinished = report.submitted - finished;
+    report.elapsed = last_finished - start;
+    report.latencies.sort();
+    report.elections = metrics::get().elections();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_the_defaults() {
+        let args: Vec<String> = ["--rate", "20", "--drain", "5"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = BenchOptions::from_args(&args).unwrap();
+        assert_eq!(options.rate, 20.0);
+        assert_eq!(options.drain, Duration::from_secs(5));
+        assert_eq!(options.duration, BenchOptions::default().duration);
+        assert_eq!(options.orders, None);
+
+        let args = vec!["--rate".to_string(), "0".to_string()];
+        assert!(BenchOptions::from_args(&args).is_err());
+        assert!(BenchOptions::from_args(&["--rate".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_synthetic_orders_arrive_during_the_duration() {
+        let options = BenchOptions {
+            rate: 50.0,
+            duration: Duration::from_secs(2),
+            ..BenchOptions::default()
+        };
+        let orders = schedule(&options).unwrap();
+        assert!(!orders.is_empty());
+        assert_eq!(orders[0].0, Duration::ZERO);
+        assert!(orders.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(orders.iter().all(|(at, _)| *at < options.duration));
+    }
+
+    #[test]
+    fn test_percentiles_of_the_latencies() {
+        let report = BenchReport {
+            confirmed: 20,
+            elapsed: Duration::from_secs(4),
+            latencies: (1..=20).map(Duration::from_millis).collect(),
+            ..BenchReport::default()
+        };
+        assert_eq!(report.orders_per_second(), 5.0);
+        assert_eq!(report.percentile(0.5), Some(Duration::from_millis(10)));
+        assert_eq!(report.percentile(0.95), Some(Duration::from_millis(19)));
+        assert_eq!(BenchReport::default().percentile(0.5), None);
+    }
+}
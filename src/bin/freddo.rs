@@ -0,0 +1,14 @@
+use clap::Parser;
+use std::process;
+use tp2::cli::Cli;
+
+/// Entry point of the freddo application.
+///
+/// It runs a robot, a screen, or asks the leader for the state of the cluster, depending on the subcommand.
+/// `freddo --help` lists the subcommands and `freddo <subcommand> --help` their arguments.
+fn main() {
+    if let Err(e) = Cli::parse().run() {
+        println!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,70 @@
+use actix::System;
+use clap::Args;
+
+use crate::dashboard::server::fetch_state;
+use crate::robot::cluster_state::ClusterState;
+
+/// Arguments of the leader status
+#[derive(Args, Debug, PartialEq)]
+pub struct LeaderStatusArgs {
+    /// Prints the state as JSON instead of a summary
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Asks the leader for the state of the cluster, the same one the dashboard shows, and prints it
+pub fn run(args: LeaderStatusArgs) -> Result<(), String> {
+    let state = System::new()
+        .block_on(fetch_state())
+        .map_err(|e| e.to_string())?;
+    if args.json {
+        let json = serde_json::to_string_pretty(&state).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        print!("{}", summary(&state));
+    }
+    Ok(())
+}
+
+/// Writes the state of the cluster in a few lines
+fn summary(state: &ClusterState) -> String {
+    let mut summary = format!("Leader: robot {}\n", state.leader_id);
+    summary += &format!("Available robots: {:?}\n", state.available_robots);
+    summary += &format!("Screens: {:?}\n", state.screens);
+    summary += &format!("Orders on queue: {}\n", state.orders_on_queue.len());
+    for robot in &state.robots_orders {
+        summary += &format!("Robot {} is preparing {:?}\n", robot.robot_id, robot.orders);
+    }
+    for (flavor, amount) in &state.stock {
+        summary += &format!("{}: {} grams\n", flavor, amount);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot::cluster_state::RobotOrders;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_summary_shows_the_leader_and_the_stock() {
+        let state = ClusterState {
+            leader_id: 2,
+            available_robots: vec![0, 1],
+            robots_orders: vec![RobotOrders {
+                robot_id: 1,
+                orders: vec!["a".to_string()],
+            }],
+            orders_on_queue: vec![],
+            stock: BTreeMap::from([("Mint".to_string(), 300)]),
+            screens: vec![0],
+            recent_completions: vec![],
+        };
+
+        let summary = summary(&state);
+        assert!(summary.starts_with("Leader: robot 2\n"));
+        assert!(summary.contains("Robot 1 is preparing [\"a\"]\n"));
+        assert!(summary.contains("Mint: 300 grams\n"));
+    }
+}
@@ -0,0 +1,172 @@
+//! This module contains the command line of the binaries.
+//! The `freddo` binary runs a robot or a screen, or asks the leader for the state of the cluster, with a subcommand.
+//! The `robot` and `screen` binaries take the same arguments as their subcommands.
+
+pub mod leader_status;
+pub mod robot;
+pub mod screen;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::config;
+
+/// Options that every command takes
+#[derive(Args, Debug, PartialEq)]
+pub struct GlobalArgs {
+    /// Configuration file, the FREDDO_CONFIG environment variable is used if it is not given
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<String>,
+    /// Leader shard of the process, the FREDDO_SHARD environment variable is used if it is not given
+    #[arg(long, global = true, value_name = "SHARD")]
+    pub shard: Option<usize>,
+}
+
+impl GlobalArgs {
+    /// Loads the configuration of the shard of the process, the error is the message to show
+    pub fn load_config(&self) -> Result<(), String> {
+        config::init_from_path(self.config.clone(), self.shard).map_err(|e| e.to_string())
+    }
+}
+
+/// Heladería Freddo: robots that prepare ice cream orders and screens that take them
+#[derive(Parser, Debug, PartialEq)]
+#[command(name = "freddo", version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Runs a robot of the ring
+    Robot(robot::RobotArgs),
+    /// Runs a screen that takes orders and charges them
+    Screen(screen::ScreenArgs),
+    /// Asks the leader for the state of the cluster and shows it
+    LeaderStatus(leader_status::LeaderStatusArgs),
+}
+
+impl Cli {
+    /// Loads the configuration and runs the command, the error is the message to show
+    pub fn run(self) -> Result<(), String> {
+        self.global.load_config()?;
+        match self.command {
+            Command::Robot(args) => robot::run(args),
+            Command::Screen(args) => screen::run(args),
+            Command::LeaderStatus(args) => leader_status::run(args),
+        }
+    }
+}
+
+/// Command line of the `robot` binary
+#[derive(Parser, Debug, PartialEq)]
+#[command(name = "robot", version, about = "Runs a robot of the ring")]
+pub struct RobotCli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    #[command(flatten)]
+    pub args: robot::RobotArgs,
+}
+
+/// Command line of the `screen` binary
+#[derive(Parser, Debug, PartialEq)]
+#[command(
+    name = "screen",
+    version,
+    about = "Runs a screen that takes orders and charges them"
+)]
+pub struct ScreenCli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    #[command(flatten)]
+    pub args: screen::ScreenArgs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_command_line_is_valid() {
+        Cli::command().debug_assert();
+        RobotCli::command().debug_assert();
+        ScreenCli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_subcommands_are_parsed() {
+        let cli = Cli::try_parse_from(["freddo", "robot", "2", "--config", "a.json"]).unwrap();
+        assert_eq!(cli.global.config, Some("a.json".to_string()));
+        assert_eq!(cli.global.shard, None);
+        assert_eq!(
+            cli.command,
+            Command::Robot(robot::RobotArgs {
+                id: 2,
+                wait_for: None,
+                wait_timeout: robot::DEFAULT_WAIT_TIMEOUT_SECS,
+                ms_per_gram: None,
+            })
+        );
+
+        let cli = Cli::try_parse_from([
+            "freddo",
+            "robot",
+            "0",
+            "--wait-for",
+            "3",
+            "--ms-per-gram",
+            "5",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Robot(robot::RobotArgs {
+                wait_for: Some(3),
+                ms_per_gram: Some(5),
+                ..
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["freddo", "screen", "1", "orders.txt", "--watch"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Screen(screen::ScreenArgs {
+                id: 1,
+                file: "orders.txt".to_string(),
+                watch: true,
+                validate: false,
+                menu: vec![],
+                menu_from_leader: false,
+                kiosk: false,
+            })
+        );
+
+        let cli = Cli::try_parse_from([
+            "freddo",
+            "screen",
+            "0",
+            "orders.txt",
+            "--menu",
+            "mint,lemon",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Screen(screen::ScreenArgs { menu, .. }) if menu == ["mint", "lemon"]
+        ));
+
+        let cli =
+            Cli::try_parse_from(["freddo", "screen", "0", "orders.txt", "--shard", "1"]).unwrap();
+        assert_eq!(cli.global.shard, Some(1));
+
+        let cli = Cli::try_parse_from(["freddo", "leader-status", "--json"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::LeaderStatus(leader_status::LeaderStatusArgs { json: true })
+        );
+        assert!(Cli::try_parse_from(["freddo", "robot", "two"]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use actix::prelude::*;
+use clap::Args;
+use std::time::Duration;
+
+use crate::common::health;
+use crate::common::log;
+use crate::common::metrics;
+use crate::config;
+use crate::robot::admin::{self, AdminTargets};
+use crate::robot::messages::{ClusterWait, GetLeader, LeaveRing, WriteConsumptionReport};
+use crate::robot::utils::start_robot;
+
+/// Seconds a robot waits for the cluster by default
+pub const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 30;
+
+/// Arguments of a robot
+#[derive(Args, Debug, PartialEq)]
+pub struct RobotArgs {
+    /// Id of the robot, less than the number of robots of the cluster
+    pub id: usize,
+    /// Waits until this many robots, counting this one, are up before joining the ring,
+    /// so a cluster started at once has a single first leader
+    #[arg(long, value_name = "N")]
+    pub wait_for: Option<usize>,
+    /// Seconds to wait for the robots of `--wait-for`, after them the robot joins the ring anyway
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_WAIT_TIMEOUT_SECS)]
+    pub wait_timeout: u64,
+    /// Milliseconds the arm takes to scoop a gram, instead of the ones of the config
+    #[arg(long, value_name = "MS")]
+    pub ms_per_gram: Option<usize>,
+}
+
+/// Runs the robot until its system is stopped, with its metrics, health endpoint and control socket if they are enabled
+/// Ctrl+C leaves the ring gracefully, a second Ctrl+C exits right away.
+/// If the robot runs the leader, it writes the consumption report before leaving
+pub fn run(args: RobotArgs) -> Result<(), String> {
+    let id = args.id;
+    if id >= config::number_of_robots() {
+        return Err(format!(
+            "Invalid Robot ID, it must be less than {}",
+            config::number_of_robots()
+        ));
+    }
+    let wait_for = match args.wait_for {
+        Some(robots) if robots == 0 || robots > config::number_of_robots() => {
+            return Err(format!(
+                "Invalid --wait-for, it must be between 1 and {}",
+                config::number_of_robots()
+            ));
+        }
+        Some(robots) => Some(ClusterWait {
+            robots,
+            timeout: Duration::from_secs(args.wait_timeout),
+        }),
+        None => None,
+    };
+    if args.ms_per_gram == Some(0) {
+        return Err("Invalid --ms-per-gram, it must be at least 1".to_string());
+    }
+
+    let system = System::new();
+    system.block_on(async {
+        let metrics_config = &config::get().metrics;
+        if metrics_config.enabled {
+            actix::spawn(metrics::serve(metrics_config.robot_addr(id)));
+        }
+        let health_config = &config::get().health;
+        if health_config.enabled {
+            actix::spawn(health::serve(health_config.robot_addr(id)));
+        }
+
+        let (robot_connection_handler, o_manager) = start_robot(id, wait_for, args.ms_per_gram);
+        let admin_config = &config::get().admin;
+        if admin_config.enabled {
+            actix::spawn(admin::serve(
+                admin_config.robot_addr(id),
+                AdminTargets {
+                    rch: robot_connection_handler.clone(),
+                    order_manager: o_manager.clone(),
+                },
+            ));
+        }
+        actix::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            log::info(
+                "Main",
+                "Leaving the ring, press Ctrl+C again to exit right away",
+            );
+            if config::get().report.on_shutdown {
+                if let Ok(Some(leader)) = robot_connection_handler.send(GetLeader()).await {
+                    match leader.send(WriteConsumptionReport()).await {
+                        Ok(Err(e)) => log::error("Main", e),
+                        Err(e) => log::send_error("Main", "WriteConsumptionReport", &e.to_string()),
+                        Ok(Ok(_)) => {}
+                    }
+                }
+            }
+            if let Err(e) = robot_connection_handler.try_send(LeaveRing()) {
+                log::send_error("Main", "LeaveRing", &e.to_string());
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                System::current().stop();
+            }
+        });
+    });
+
+    system
+        .run()
+        .map_err(|e| format!("Error Running System: {:?}", e))
+}
@@ -0,0 +1,110 @@
+use actix::System;
+use clap::Args;
+
+use crate::common::health;
+use crate::common::metrics;
+use crate::config;
+use crate::screen::communication::start_actors_and_connections;
+use crate::screen::menu::Menu;
+use crate::screen::order_reader::OrderReader;
+
+/// Directory where the order files are
+const ORDERS_DIR: &str = "./src/orders_samples";
+
+/// Arguments of a screen
+#[derive(Args, Debug, PartialEq)]
+pub struct ScreenArgs {
+    /// Id of the screen, less than the number of screens of the cluster
+    pub id: usize,
+    /// Order file, or directory, in the orders_samples directory
+    pub file: String,
+    /// Follows the order file, or directory, for new orders from the start
+    #[arg(long)]
+    pub watch: bool,
+    /// Only checks the order file and exits, with an error if it is not valid
+    #[arg(long)]
+    pub validate: bool,
+    /// Flavors the screen offers, separated by commas, every one of the catalog if none are given
+    #[arg(long, value_delimiter = ',')]
+    pub menu: Vec<String>,
+    /// Asks the robot leader which flavors have stock when connecting, and takes the others off the menu
+    #[arg(long)]
+    pub menu_from_leader: bool,
+    /// Shows a terminal UI with the orders and the connection with the leader, and a form for new orders, instead of the logs
+    #[arg(long)]
+    pub kiosk: bool,
+}
+
+/// Runs the screen until its orders are processed, or only checks its order file with `--validate`
+pub fn run(args: ScreenArgs) -> Result<(), String> {
+    if args.id >= config::number_of_screens() {
+        return Err(format!(
+            "Invalid Screen ID, it must be less than {}",
+            config::number_of_screens()
+        ));
+    }
+    let order_file = format!("{}/{}", ORDERS_DIR, args.file);
+    let menu = Menu::parse(&args.menu, args.menu_from_leader)?;
+
+    if args.kiosk && !cfg!(feature = "tui") {
+        return Err("This screen was built without the kiosk, enable the tui feature".to_string());
+    }
+
+    if args.validate {
+        return match validate_orders(&order_file) {
+            true => Ok(()),
+            false => Err(format!("{} is not valid", order_file)),
+        };
+    }
+
+    System::new().block_on(async {
+        let metrics_config = &config::get().metrics;
+        if metrics_config.enabled {
+            actix::spawn(metrics::serve(metrics_config.screen_addr(args.id)));
+        }
+        let health_config = &config::get().health;
+        if health_config.enabled {
+            actix::spawn(health::serve(health_config.screen_addr(args.id)));
+        }
+        start_actors_and_connections(args.id, order_file, args.watch, menu, args.kiosk).await;
+    });
+    Ok(())
+}
+
+/// Checks every line of the order file and prints the lines that are not valid orders,
+/// and the grams asked of each flavor compared with its stock.
+///
+/// Returns true if all the orders are valid and there is stock for them.
+fn validate_orders(order_file: &str) -> bool {
+    let report = match OrderReader::validate(order_file) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("Could not read {}: {}", order_file, e);
+            return false;
+        }
+    };
+    let catalog = &config::get().flavors;
+
+    for (line, reason) in &report.malformed {
+        println!("Line {}: {}", line, reason);
+    }
+    let mut grams: Vec<_> = report.grams.iter().collect();
+    grams.sort_by_key(|(flavor, _)| flavor.to_string());
+    for (flavor, grams) in grams {
+        let stock = catalog.initial_amount(flavor).unwrap_or(0);
+        println!("{}: {} grams of {} in stock", flavor, grams, stock);
+    }
+    for (flavor, grams, stock) in report.over_stock(catalog) {
+        println!(
+            "Not enough {}: {} grams more than the stock",
+            flavor,
+            grams - stock
+        );
+    }
+    println!(
+        "{} valid orders, {} malformed lines",
+        report.orders,
+        report.malformed.len()
+    );
+    report.is_valid(catalog)
+}
@@ -0,0 +1,194 @@
+//! Fault injection, to test how the cluster recovers from failures.
+//! The connections write through `write_all` and their actors ask `crash` for every message they receive,
+//! each of them at a point with a name and the id of the robot or screen it belongs to.
+//! Built with the `chaos` feature and enabled in the config, the faults are rolled with the probabilities of the config,
+//! and a test can `script` the exact faults of a point, which are used before rolling any.
+//! Without the feature, the writes go straight to the socket and nothing crashes.
+
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// A fault injected at a point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The message is not written, but the write succeeds
+    Drop,
+    /// The message is written after the delay
+    Delay(Duration),
+    /// The socket is closed instead of writing the message
+    Close,
+    /// The actor of the connection stops
+    Crash,
+}
+
+/// Writes the message, unless a fault is injected at the point.
+/// A crash scripted on a write closes the socket, since the actor can not be stopped from here
+pub async fn write_all<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    msg: &[u8],
+    point: &str,
+    id: usize,
+) -> io::Result<()> {
+    match next_fault(point, id, false) {
+        None => writer.write_all(msg).await,
+        Some(Fault::Drop) => Ok(()),
+        Some(Fault::Delay(delay)) => {
+            tokio::time::sleep(delay).await;
+            writer.write_all(msg).await
+        }
+        Some(Fault::Close) | Some(Fault::Crash) => {
+            let _ = writer.shutdown().await;
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("Closed by chaos at {} {}", point, id),
+            ))
+        }
+    }
+}
+
+/// Returns true if the actor at the point has to crash, only scripted crashes are taken from the script here
+pub fn crash(point: &str, id: usize) -> bool {
+    matches!(next_fault(point, id, true), Some(Fault::Crash))
+}
+
+#[cfg(not(feature = "chaos"))]
+fn next_fault(_point: &str, _id: usize, _receiving: bool) -> Option<Fault> {
+    None
+}
+
+#[cfg(feature = "chaos")]
+pub use self::injection::{clear, script};
+
+#[cfg(feature = "chaos")]
+use self::injection::next_fault;
+
+#[cfg(feature = "chaos")]
+mod injection {
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Duration;
+
+    use super::Fault;
+    use crate::common::log;
+    use crate::common::simulation;
+    use crate::config;
+
+    /// The scripted faults of a point and its generator, so its rolls are reproducible in simulation mode
+    struct PointState {
+        scripted: VecDeque<Option<Fault>>,
+        rng: StdRng,
+    }
+
+    static POINTS: OnceLock<Mutex<HashMap<(String, usize), PointState>>> = OnceLock::new();
+
+    fn with_point<T>(point: &str, id: usize, f: impl FnOnce(&mut PointState) -> T) -> T {
+        let points = POINTS.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut points = points
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let state = points
+            .entry((point.to_string(), id))
+            .or_insert_with(|| PointState {
+                scripted: VecDeque::new(),
+                rng: simulation::rng(&format!("CHAOS {}", point), id),
+            });
+        f(state)
+    }
+
+    /// Sets the next faults of the point, in order, `None` lets a message through.
+    /// They are used even if chaos is disabled in the config, and replace the ones scripted before
+    pub fn script(point: &str, id: usize, faults: Vec<Option<Fault>>) {
+        with_point(point, id, |state| state.scripted = faults.into());
+    }
+
+    /// Forgets the scripted faults of every point
+    pub fn clear() {
+        if let Some(points) = POINTS.get() {
+            let mut points = points
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            points.clear();
+        }
+    }
+
+    /// Takes the next scripted fault of the point, or rolls one if chaos is enabled.
+    /// A point that receives messages only takes crashes, the other faults are for writes
+    pub(super) fn next_fault(point: &str, id: usize, receiving: bool) -> Option<Fault> {
+        let chaos = &config::get().chaos;
+        let fault = with_point(point, id, |state| {
+            match state.scripted.front() {
+                Some(Some(Fault::Crash)) | Some(None) => return state.scripted.pop_front()?,
+                Some(Some(_)) if !receiving => return state.scripted.pop_front()?,
+                Some(Some(_)) => return None,
+                None => {}
+            }
+            if !chaos.enabled {
+                return None;
+            }
+            let roll: f64 = state.rng.gen();
+            if receiving {
+                return (roll < chaos.crash_probability).then_some(Fault::Crash);
+            }
+            if roll < chaos.drop_probability {
+                Some(Fault::Drop)
+            } else if roll < chaos.drop_probability + chaos.delay_probability {
+                let delay = state.rng.gen_range(0..=chaos.max_delay_ms);
+                Some(Fault::Delay(Duration::from_millis(delay)))
+            } else if roll
+                < chaos.drop_probability + chaos.delay_probability + chaos.close_probability
+            {
+                Some(Fault::Close)
+            } else {
+                None
+            }
+        });
+        if let Some(fault) = fault {
+            log::warn("CHAOS", format!("{:?} at {} {}", fault, point, id));
+        }
+        fault
+    }
+}
+
+#[cfg(all(test, feature = "chaos"))]
+mod tests {
+    use super::*;
+
+    #[actix::test]
+    async fn test_scripted_faults_are_injected_in_order() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        script(
+            "TEST",
+            0,
+            vec![
+                Some(Fault::Drop),
+                None,
+                Some(Fault::Delay(Duration::ZERO)),
+                Some(Fault::Close),
+            ],
+        );
+        write_all(&mut writer, b"a", "TEST", 0).await.unwrap();
+        write_all(&mut writer, b"b", "TEST", 0).await.unwrap();
+        write_all(&mut writer, b"c", "TEST", 0).await.unwrap();
+        assert!(write_all(&mut writer, b"d", "TEST", 0).await.is_err());
+
+        let mut received = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut received)
+            .await
+            .unwrap();
+        assert_eq!(received, "bc");
+    }
+
+    #[test]
+    fn test_crash_only_takes_scripted_crashes() {
+        script("TEST CRASH", 1, vec![Some(Fault::Drop)]);
+        assert!(!crash("TEST CRASH", 1));
+        script("TEST CRASH", 1, vec![None, Some(Fault::Crash)]);
+        assert!(!crash("TEST CRASH", 1));
+        assert!(crash("TEST CRASH", 1));
+        assert!(!crash("TEST CRASH", 1));
+        assert!(!crash("TEST CRASH", 2));
+    }
+}
@@ -0,0 +1,315 @@
+//! Framing of the messages sent over the TCP connections between robots and screens.
+//! By default every message is serialized with bincode and prefixed with its length as a big endian `u32`.
+//! With `"wire_format": "Lines"` in the network config every message is a line of JSON instead, which can be read by hand.
+//! It is not a way to talk to processes of another version: every process of the system has to use the same format
+//! and the same version of the protocol.
+
+use std::{error::Error, fmt, io, pin::Pin};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio_stream::{wrappers::LinesStream, Stream, StreamExt};
+use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
+
+use crate::config;
+
+/// Size in bytes of the length header of a binary frame
+const LENGTH_HEADER_SIZE: usize = 4;
+/// Biggest payload accepted in a binary frame, bigger frames are rejected by the reader
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Stream of the payloads received on a connection, without their framing
+pub type Frames = Pin<Box<dyn Stream<Item = Result<Vec<u8>, io::Error>> + Send>>;
+
+#[derive(Debug, PartialEq)]
+pub enum CodecError {
+    ErrorEncoding(String),
+    ErrorDecoding(String),
+    FrameTooLarge(usize),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::ErrorEncoding(err) => write!(f, "Could not encode message: {}", err),
+            CodecError::ErrorDecoding(err) => write!(f, "Could not decode message: {}", err),
+            CodecError::FrameTooLarge(len) => write!(
+                f,
+                "Message of {} bytes is bigger than the max frame length of {} bytes",
+                len, MAX_FRAME_LENGTH
+            ),
+        }
+    }
+}
+impl Error for CodecError {}
+
+/// Turns messages into the bytes written to a connection and back
+pub trait Codec {
+    /// Serializes the message and adds its framing
+    fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Deserializes the payload of a single frame
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> Result<T, CodecError>;
+
+    /// Splits what is read from the connection into frames
+    fn frames<R: AsyncRead + Send + 'static>(&self, reader: R) -> Frames;
+}
+
+/// One JSON message per line, the format used before the binary framing
+pub struct LineCodec;
+
+impl Codec for LineCodec {
+    fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        let mut bytes =
+            serde_json::to_vec(msg).map_err(|err| CodecError::ErrorEncoding(err.to_string()))?;
+        bytes.push(b'\n');
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(frame).map_err(|err| CodecError::ErrorDecoding(err.to_string()))
+    }
+
+    fn frames<R: AsyncRead + Send + 'static>(&self, reader: R) -> Frames {
+        let lines = LinesStream::new(BufReader::new(reader).lines());
+        Box::pin(lines.map(|line| line.map(String::into_bytes)))
+    }
+}
+
+/// bincode messages prefixed with their length
+pub struct BinaryCodec;
+
+impl BinaryCodec {
+    /// Adds the length to a message already serialized with bincode
+    fn frame(payload: &[u8]) -> Result<Vec<u8>, CodecError> {
+        if payload.len() > MAX_FRAME_LENGTH {
+            return Err(CodecError::FrameTooLarge(payload.len()));
+        }
+        let mut bytes = Vec::with_capacity(LENGTH_HEADER_SIZE + payload.len());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        Ok(bytes)
+    }
+}
+
+impl Codec for BinaryCodec {
+    fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        let payload =
+            bincode::serialize(msg).map_err(|err| CodecError::ErrorEncoding(err.to_string()))?;
+        BinaryCodec::frame(&payload)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(frame).map_err(|err| CodecError::ErrorDecoding(err.to_string()))
+    }
+
+    fn frames<R: AsyncRead + Send + 'static>(&self, reader: R) -> Frames {
+        let codec = LengthDelimitedCodec::builder()
+            .length_field_length(LENGTH_HEADER_SIZE)
+            .max_frame_length(MAX_FRAME_LENGTH)
+            .new_codec();
+        Box::pin(FramedRead::new(reader, codec).map(|frame| frame.map(|bytes| bytes.to_vec())))
+    }
+}
+
+/// Format of the messages on the wire, chosen in the network config. Both carry the same messages of the same version
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WireFormat {
+    Lines,
+    #[default]
+    Binary,
+}
+
+impl Codec for WireFormat {
+    fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Lines => LineCodec.encode(msg),
+            WireFormat::Binary => BinaryCodec.encode(msg),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> Result<T, CodecError> {
+        match self {
+            WireFormat::Lines => LineCodec.decode(frame),
+            WireFormat::Binary => BinaryCodec.decode(frame),
+        }
+    }
+
+    fn frames<R: AsyncRead + Send + 'static>(&self, reader: R) -> Frames {
+        match self {
+            WireFormat::Lines => LineCodec.frames(reader),
+            WireFormat::Binary => BinaryCodec.frames(reader),
+        }
+    }
+}
+
+impl WireFormat {
+    /// Encodes a message already serialized with bincode as a `T`, it is only decoded again with the line format
+    pub fn encode_bincode<T: Serialize + DeserializeOwned>(
+        &self,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Binary => BinaryCodec::frame(payload),
+            WireFormat::Lines => LineCodec.encode(&BinaryCodec.decode::<T>(payload)?),
+        }
+    }
+
+    /// Reads a single frame, without reading any byte after it.
+    /// It is used for the first message of a connection, before the reader is handed to `frames`
+    pub async fn read_frame<R: AsyncRead + Unpin>(&self, reader: &mut R) -> io::Result<Vec<u8>> {
+        match self {
+            WireFormat::Lines => {
+                let mut line = Vec::new();
+                let mut byte = [0; 1];
+                loop {
+                    reader.read_exact(&mut byte).await?;
+                    if byte[0] == b'\n' {
+                        return Ok(line);
+                    }
+                    line.push(byte[0]);
+                }
+            }
+            WireFormat::Binary => {
+                let mut header = [0; LENGTH_HEADER_SIZE];
+                reader.read_exact(&mut header).await?;
+                let len = u32::from_be_bytes(header) as usize;
+                if len > MAX_FRAME_LENGTH {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        CodecError::FrameTooLarge(len),
+                    ));
+                }
+                let mut payload = vec![0; len];
+                reader.read_exact(&mut payload).await?;
+                Ok(payload)
+            }
+        }
+    }
+}
+
+/// Encodes the message with the wire format of the config
+pub fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>, CodecError> {
+    config::get().network.wire_format.encode(msg)
+}
+
+/// Encodes a message already serialized with bincode with the wire format of the config
+pub fn encode_bincode<T: Serialize + DeserializeOwned>(
+    payload: &[u8],
+) -> Result<Vec<u8>, CodecError> {
+    config::get()
+        .network
+        .wire_format
+        .encode_bincode::<T>(payload)
+}
+
+/// Decodes a frame with the wire format of the config
+pub fn decode<T: DeserializeOwned>(frame: &[u8]) -> Result<T, CodecError> {
+    config::get().network.wire_format.decode(frame)
+}
+
+/// Reads a single frame with the wire format of the config
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    config::get().network.wire_format.read_frame(reader).await
+}
+
+/// Splits the reader into frames with the wire format of the config
+pub fn frames<R: AsyncRead + Send + 'static>(reader: R) -> Frames {
+    config::get().network.wire_format.frames(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use crate::protocol::screen_messages::ScreenMessage;
+
+    fn sample_messages() -> Vec<ScreenMessage> {
+        vec![
+            ScreenMessage::PrepareNewOrder {
+                screen_id: 1,
+                order_id: "abc".to_string(),
+                order: Order::new_cucurucho(FlavorID::Mint),
+                deadline_ms: Some(3000),
+                source: None,
+            },
+            ScreenMessage::RequestRobotLeaderConnection { screen_id: 2 },
+        ]
+    }
+
+    async fn roundtrip(format: WireFormat) -> Vec<ScreenMessage> {
+        let mut bytes = Vec::new();
+        for msg in sample_messages() {
+            bytes.extend(format.encode(&msg).unwrap());
+        }
+        let frames: Vec<Vec<u8>> = format
+            .frames(std::io::Cursor::new(bytes))
+            .map(|frame| frame.unwrap())
+            .collect()
+            .await;
+        frames
+            .iter()
+            .map(|frame| format.decode(frame).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_binary_frames_roundtrip() {
+        assert_eq!(roundtrip(WireFormat::Binary).await, sample_messages());
+    }
+
+    #[tokio::test]
+    async fn test_lines_frames_roundtrip() {
+        assert_eq!(roundtrip(WireFormat::Lines).await, sample_messages());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_leaves_the_next_frames_unread() {
+        for format in [WireFormat::Binary, WireFormat::Lines] {
+            let mut bytes = Vec::new();
+            for msg in sample_messages() {
+                bytes.extend(format.encode(&msg).unwrap());
+            }
+            let mut reader = std::io::Cursor::new(bytes);
+            let first = format.read_frame(&mut reader).await.unwrap();
+            let first: ScreenMessage = format.decode(&first).unwrap();
+            assert_eq!(first, sample_messages()[0]);
+
+            let rest: Vec<Vec<u8>> = format
+                .frames(reader)
+                .map(|frame| frame.unwrap())
+                .collect()
+                .await;
+            let second: ScreenMessage = format.decode(&rest[0]).unwrap();
+            assert_eq!(rest.len(), 1);
+            assert_eq!(second, sample_messages()[1]);
+        }
+    }
+
+    #[test]
+    fn test_message_serialized_with_bincode_gets_the_framing_of_each_format() {
+        for format in [WireFormat::Binary, WireFormat::Lines] {
+            for msg in sample_messages() {
+                let payload = bincode::serialize(&msg).unwrap();
+                assert_eq!(
+                    format.encode_bincode::<ScreenMessage>(&payload).unwrap(),
+                    format.encode(&msg).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_frame_starts_with_payload_length() {
+        let bytes = BinaryCodec.encode(&7u64).unwrap();
+        assert_eq!(bytes[..LENGTH_HEADER_SIZE], 8u32.to_be_bytes());
+        assert_eq!(bytes.len(), LENGTH_HEADER_SIZE + 8);
+    }
+
+    #[test]
+    fn test_garbage_frame_is_a_decoding_error() {
+        let result: Result<ScreenMessage, CodecError> = BinaryCodec.decode(&[255, 255]);
+        assert!(matches!(result, Err(CodecError::ErrorDecoding(_))));
+    }
+}
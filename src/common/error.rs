@@ -0,0 +1,81 @@
+//! Errors shared by the robots and the screens.
+//! The helpers that open connections or send messages return them instead of only logging,
+//! so the actor that owns the connection decides whether to retry, escalate or give up.
+
+use std::{error::Error, fmt, io};
+
+use actix::MailboxError;
+
+use crate::common::codec::CodecError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FreddoError {
+    /// A connection could not be opened, or it was closed
+    Connection(String),
+    /// A message could not be encoded or decoded
+    Serialization(String),
+    /// A message could not be delivered to an actor
+    Send(String),
+    /// The other side sent something that does not follow the protocol
+    Protocol(String),
+}
+
+impl FreddoError {
+    /// Returns true if trying again later may work, a message that can not be serialized or
+    /// a peer that does not follow the protocol will fail the same way
+    pub fn is_transient(&self) -> bool {
+        matches!(self, FreddoError::Connection(_) | FreddoError::Send(_))
+    }
+}
+
+impl fmt::Display for FreddoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FreddoError::Connection(err) => write!(f, "Connection error: {}", err),
+            FreddoError::Serialization(err) => write!(f, "Serialization error: {}", err),
+            FreddoError::Send(err) => write!(f, "Could not send message: {}", err),
+            FreddoError::Protocol(err) => write!(f, "Protocol error: {}", err),
+        }
+    }
+}
+
+impl Error for FreddoError {}
+
+impl From<io::Error> for FreddoError {
+    fn from(err: io::Error) -> Self {
+        FreddoError::Connection(err.to_string())
+    }
+}
+
+impl From<CodecError> for FreddoError {
+    fn from(err: CodecError) -> Self {
+        FreddoError::Serialization(err.to_string())
+    }
+}
+
+impl From<MailboxError> for FreddoError {
+    fn from(err: MailboxError) -> Self {
+        FreddoError::Send(err.to_string())
+    }
+}
+
+impl<T> From<actix::prelude::SendError<T>> for FreddoError {
+    fn from(err: actix::prelude::SendError<T>) -> Self {
+        FreddoError::Send(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_connection_and_send_errors_are_transient() {
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert!(FreddoError::from(refused).is_transient());
+        assert!(FreddoError::Send("mailbox full".to_string()).is_transient());
+        let garbage = CodecError::ErrorDecoding("garbage".to_string());
+        assert!(!FreddoError::from(garbage).is_transient());
+        assert!(!FreddoError::Protocol("unexpected frame".to_string()).is_transient());
+    }
+}
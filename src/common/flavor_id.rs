@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+/// A flavor of ice cream, the standard ones or a custom one added in the catalog of the config.
+/// It travels and is stored as its name, so a custom flavor does not need any code change
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Hash)]
+#[serde(try_from = "String", into = "String")]
 pub enum FlavorID {
     Chocolate,
     Vanilla,
@@ -11,32 +15,134 @@ pub enum FlavorID {
     Pistachio,
     DulceDeLeche,
     Lemon,
+    Custom(String),
 }
 
+impl FlavorID {
+    /// Flavors the shop always knows, a custom flavor can not take their names
+    pub const STANDARD: [FlavorID; 7] = [
+        FlavorID::Chocolate,
+        FlavorID::Vanilla,
+        FlavorID::Strawberry,
+        FlavorID::Mint,
+        FlavorID::Pistachio,
+        FlavorID::DulceDeLeche,
+        FlavorID::Lemon,
+    ];
+
+    /// Gets the name of the flavor, which is how it is written in the config, the orders and the messages
+    pub fn name(&self) -> &str {
+        match self {
+            FlavorID::Chocolate => "Chocolate",
+            FlavorID::Vanilla => "Vanilla",
+            FlavorID::Strawberry => "Strawberry",
+            FlavorID::Mint => "Mint",
+            FlavorID::Pistachio => "Pistachio",
+            FlavorID::DulceDeLeche => "DulceDeLeche",
+            FlavorID::Lemon => "Lemon",
+            FlavorID::Custom(name) => name,
+        }
+    }
+}
+
+/// Error type for a name that can not be a flavor
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseFlavorError {
+    Empty,
+    /// The name has a character that the encoding of the tokens uses as separator
+    InvalidCharacter(String),
+}
+
+impl fmt::Display for ParseFlavorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseFlavorError::Empty => write!(f, "The name of a flavor can not be empty"),
+            ParseFlavorError::InvalidCharacter(name) => {
+                write!(f, "The flavor {} can not have commas or line breaks", name)
+            }
+        }
+    }
+}
+
+impl Error for ParseFlavorError {}
+
 impl FromStr for FlavorID {
-    type Err = ();
+    type Err = ParseFlavorError;
 
+    /// Parses a standard flavor by its name, any other name is a custom flavor
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "Chocolate" => Ok(FlavorID::Chocolate),
-            "Vanilla" => Ok(FlavorID::Vanilla),
-            "Strawberry" => Ok(FlavorID::Strawberry),
-            "Mint" => Ok(FlavorID::Mint),
-            _ => Err(()),
+        let name = s.trim();
+        if name.is_empty() {
+            return Err(ParseFlavorError::Empty);
+        }
+        if name.contains([',', '\n']) {
+            return Err(ParseFlavorError::InvalidCharacter(name.to_string()));
+        }
+        Ok(Self::STANDARD
+            .into_iter()
+            .find(|flavor| flavor.name() == name)
+            .unwrap_or_else(|| FlavorID::Custom(name.to_string())))
+    }
+}
+
+impl TryFrom<String> for FlavorID {
+    type Error = ParseFlavorError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<FlavorID> for String {
+    fn from(flavor: FlavorID) -> Self {
+        match flavor {
+            FlavorID::Custom(name) => name,
+            flavor => flavor.name().to_string(),
         }
     }
 }
 
 impl fmt::Display for FlavorID {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            FlavorID::Chocolate => write!(f, "Chocolate"),
-            FlavorID::Vanilla => write!(f, "Vanilla"),
-            FlavorID::Strawberry => write!(f, "Strawberry"),
-            FlavorID::Mint => write!(f, "Mint"),
-            FlavorID::Pistachio => write!(f, "Pistachio"),
-            FlavorID::DulceDeLeche => write!(f, "DulceDeLeche"),
-            FlavorID::Lemon => write!(f, "Lemon"),
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_standard_flavor_is_parsed_from_its_name() {
+        for flavor in FlavorID::STANDARD {
+            assert_eq!(flavor.to_string().parse(), Ok(flavor));
         }
     }
+
+    #[test]
+    fn test_other_names_are_custom_flavors() {
+        assert_eq!("Peach".parse(), Ok(FlavorID::Custom("Peach".to_string())));
+        assert_eq!("".parse::<FlavorID>(), Err(ParseFlavorError::Empty));
+        assert!("Peach,Melba".parse::<FlavorID>().is_err());
+    }
+
+    #[test]
+    fn test_flavors_are_serialized_as_their_names() {
+        let flavors = vec![
+            FlavorID::DulceDeLeche,
+            FlavorID::Custom("Peach".to_string()),
+        ];
+        let json = serde_json::to_string(&flavors).unwrap();
+        assert_eq!(json, r#"["DulceDeLeche","Peach"]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<FlavorID>>(&json).unwrap(),
+            flavors
+        );
+
+        let bytes = bincode::serialize(&flavors).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Vec<FlavorID>>(&bytes).unwrap(),
+            flavors
+        );
+    }
 }
@@ -0,0 +1,105 @@
+//! Protection of the listeners against peers that connect and do not say who they are.
+//! Each handshake is read in its own task with a timeout, and only a few of them can be pending at a time,
+//! so a slow or dead peer can not keep a listener from accepting the other connections.
+
+use colored::Colorize;
+use std::fmt::Display;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::common::log;
+use crate::common::metrics;
+use crate::config;
+
+/// Handshakes a listener is reading, the slot of each one is freed when its permit is dropped
+#[derive(Clone)]
+pub struct PendingHandshakes {
+    name: &'static str,
+    slots: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl PendingHandshakes {
+    /// Takes the limits of the network config, `name` is the target of the logs
+    pub fn new(name: &'static str) -> Self {
+        let network = &config::get().network;
+        Self::with_limits(
+            name,
+            network.max_pending_handshakes,
+            Duration::from_millis(network.handshake_timeout_ms),
+        )
+    }
+
+    pub fn with_limits(name: &'static str, max_pending: usize, timeout: Duration) -> Self {
+        Self {
+            name,
+            slots: Arc::new(Semaphore::new(max_pending)),
+            timeout,
+        }
+    }
+
+    /// Takes a slot for the handshake of a new connection.
+    /// Returns none if too many handshakes are pending, then the connection has to be closed
+    pub fn admit(&self, peer: SocketAddr) -> Option<OwnedSemaphorePermit> {
+        match self.slots.clone().try_acquire_owned() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                self.drop_connection(peer, "too_many", "too many handshakes are pending");
+                None
+            }
+        }
+    }
+
+    /// Reads the handshake of the connection.
+    /// Returns none if it could not be read or took longer than the timeout, then the connection has to be closed
+    pub async fn read<T, E: Display>(
+        &self,
+        peer: SocketAddr,
+        handshake: impl Future<Output = Result<T, E>>,
+    ) -> Option<T> {
+        match tokio::time::timeout(self.timeout, handshake).await {
+            Ok(Ok(handshake)) => Some(handshake),
+            Ok(Err(e)) => {
+                let reason = format!("could not read its handshake: {}", e);
+                self.drop_connection(peer, "error", &reason);
+                None
+            }
+            Err(_) => {
+                let reason = format!("it sent no handshake in {} ms", self.timeout.as_millis());
+                self.drop_connection(peer, "timeout", &reason);
+                None
+            }
+        }
+    }
+
+    fn drop_connection(&self, peer: SocketAddr, kind: &str, reason: &str) {
+        metrics::get().handshake_dropped(kind);
+        let line = format!("Closing the connection of {}, {}", peer, reason);
+        log::warn(self.name, line.red());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slow_handshakes_are_dropped_and_free_their_slot() {
+        tokio::time::pause();
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let handshakes = PendingHandshakes::with_limits("TEST", 1, Duration::from_millis(50));
+
+        let slot = handshakes.admit(peer).unwrap();
+        assert!(handshakes.admit(peer).is_none());
+        let never = std::future::pending::<Result<u8, String>>();
+        assert_eq!(handshakes.read(peer, never).await, None);
+        drop(slot);
+
+        let _slot = handshakes.admit(peer).unwrap();
+        let quick = async { Ok::<u8, String>(7) };
+        assert_eq!(handshakes.read(peer, quick).await, Some(7));
+    }
+}
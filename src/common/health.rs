@@ -0,0 +1,188 @@
+//! Health of a process, for the healthchecks of docker-compose or Kubernetes.
+//! `GET /live` answers 200 while the main actor of the process keeps beating, and 503 once it stopped beating
+//! for a while, so a wedged process is restarted. `GET /ready` answers 200 once the process listens for its peers
+//! and is part of the cluster: a robot that leads or is connected to its leader, or a screen the leader is connected to.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::common::log;
+use crate::config;
+
+static HEALTH: OnceLock<Health> = OnceLock::new();
+
+/// What the process knows of its own health
+#[derive(Debug)]
+pub struct Health {
+    started: Instant,
+    listening: AtomicBool,
+    joined: AtomicBool,
+    last_beat_ms: AtomicU64,
+}
+
+/// Health of the process, as answered by the endpoint
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HealthStatus {
+    pub live: bool,
+    pub ready: bool,
+    pub listening: bool,
+    pub joined: bool,
+    pub since_last_beat_ms: u64,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            listening: AtomicBool::new(false),
+            joined: AtomicBool::new(false),
+            last_beat_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Marks that the process listens for its peers
+    pub fn listening(&self) {
+        self.listening.store(true, Ordering::Relaxed);
+    }
+
+    /// Marks that the main actor of the process is still handling its messages, and if it is part of the cluster
+    pub fn beat(&self, joined: bool) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_beat_ms.store(now, Ordering::Relaxed);
+        self.joined.store(joined, Ordering::Relaxed);
+    }
+
+    /// Returns the health of the process, it is not live if it did not beat within `stale`
+    pub fn status(&self, stale: Duration) -> HealthStatus {
+        let now = self.started.elapsed().as_millis() as u64;
+        let since_last_beat_ms = now.saturating_sub(self.last_beat_ms.load(Ordering::Relaxed));
+        let live = since_last_beat_ms < stale.as_millis() as u64;
+        let listening = self.listening.load(Ordering::Relaxed);
+        let joined = self.joined.load(Ordering::Relaxed);
+        HealthStatus {
+            live,
+            ready: live && listening && joined,
+            listening,
+            joined,
+            since_last_beat_ms,
+        }
+    }
+}
+
+/// Gets the health of the process
+pub fn get() -> &'static Health {
+    HEALTH.get_or_init(Health::new)
+}
+
+/// Makes the actor beat for the process while it runs, if the health endpoint is enabled.
+/// `joined` tells from the actor if the process is part of the cluster
+pub fn watch<A>(ctx: &mut Context<A>, joined: fn(&A) -> bool)
+where
+    A: Actor<Context = Context<A>>,
+{
+    let health = &config::get().health;
+    if !health.enabled {
+        return;
+    }
+    let interval = Duration::from_millis(health.beat_interval_ms);
+    ctx.run_interval(interval, move |actor, _| get().beat(joined(actor)));
+}
+
+/// Returns the status code and the body that answer the path
+fn answer(path: &str, status: &HealthStatus) -> (u16, String) {
+    let body = serde_json::to_string(status).unwrap_or_default();
+    let healthy = match path {
+        "/live" => status.live,
+        "/ready" => status.ready,
+        _ => return (404, body),
+    };
+    match healthy {
+        true => (200, body),
+        false => (503, body),
+    }
+}
+
+/// Serves the health of the process over HTTP on the given address
+pub async fn serve(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            let line = format!("Could not listen on {}: {}", addr, e);
+            log::error("HEALTH", line.red());
+            return;
+        }
+    };
+    let line = format!("Serving health on http://{}/live and /ready", addr);
+    log::info("HEALTH", line.bright_blue());
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer_check(stream));
+    }
+}
+
+/// Answers a single healthcheck with the current health
+async fn answer_check(mut stream: TcpStream) {
+    let mut buf = [0; 1024];
+    let read = match stream.read(&mut buf).await {
+        Ok(read) => read,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let stale = Duration::from_millis(config::get().health.stale_ms);
+    let (code, body) = answer(path, &get().status(stale));
+    let reason = match code {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_process_is_ready_once_it_listens_and_joins() {
+        let health = Health::new();
+        let stale = Duration::from_secs(60);
+        assert_eq!(answer("/live", &health.status(stale)).0, 200);
+        assert_eq!(answer("/ready", &health.status(stale)).0, 503);
+
+        health.listening();
+        health.beat(true);
+        assert!(health.status(stale).ready);
+        assert_eq!(answer("/ready", &health.status(stale)).0, 200);
+        assert_eq!(answer("/metrics", &health.status(stale)).0, 404);
+
+        health.beat(false);
+        assert_eq!(answer("/ready", &health.status(stale)).0, 503);
+    }
+
+    #[test]
+    fn test_a_process_that_stopped_beating_is_not_live() {
+        let health = Health::new();
+        health.listening();
+        health.beat(true);
+        std::thread::sleep(Duration::from_millis(5));
+        let status = health.status(Duration::from_millis(1));
+        assert!(!status.live);
+        assert!(!status.ready);
+        assert_eq!(answer("/live", &status).0, 503);
+    }
+}
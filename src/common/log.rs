@@ -0,0 +1,403 @@
+//! Logging layer shared by the robots and the screens.
+//! Every line has a level and a target, the short name of the actor that writes it (e.g. `RCH`, `RL`, `GTW`),
+//! so operators can filter the logs of each actor from the config.
+//! Lines are written to stdout or to a file, as colored text or as JSON. The lines for stdout can be redirected, as the kiosk of a screen does.
+//! The lines about an order carry its trace id, next to the target or as the `trace_id` field of the JSON.
+//! A line equal to the last one of its target is not written again within the dedup window of the target,
+//! the repeats are summed up in one line when the target writes another line or the window ends,
+//! a thread of the logger looks for the windows that ended every `FLUSH_INTERVAL`.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use colored::{ColoredString, Colorize};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    self,
+    logging::{LogFormat, LoggingConfig},
+};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// How often the logger sums up the repeats of the windows that ended
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Severity of a log line, from the most to the least important
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+/// A log line as it is written in the JSON format
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp_ms: u64,
+    level: Level,
+    target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<&'a str>,
+    message: &'a str,
+}
+
+/// The last line written by a target, and how many times it was repeated since
+struct Repeated {
+    level: Level,
+    message: String,
+    since: Instant,
+    count: u64,
+}
+
+/// Collapses the lines that a target repeats within its window
+#[derive(Default)]
+struct Dedup {
+    last: HashMap<String, Repeated>,
+}
+
+impl Dedup {
+    /// Returns whether the line has to be written, and the summary of the repeats of the previous line of the
+    /// target, if the line is a different one or the window of the previous one ended
+    fn check(
+        &mut self,
+        level: Level,
+        target: &str,
+        message: &str,
+        window: Duration,
+        now: Instant,
+    ) -> (bool, Option<(Level, String)>) {
+        if window.is_zero() {
+            return (true, None);
+        }
+        if let Some(last) = self.last.get_mut(target) {
+            let same = last.level == level && last.message == message;
+            if same && now.duration_since(last.since) < window {
+                last.count += 1;
+                return (false, None);
+            }
+        }
+        let repeated = Repeated {
+            level,
+            message: message.to_string(),
+            since: now,
+            count: 0,
+        };
+        let summary = self
+            .last
+            .insert(target.to_string(), repeated)
+            .filter(|last| last.count > 0)
+            .map(|last| {
+                let line = format!("message repeated {} times: {}", last.count, last.message);
+                (last.level, line)
+            });
+        (true, summary)
+    }
+
+    /// Forgets the last line of every target whose window ended, and returns the summary of the ones that were repeated,
+    /// so the last burst of a target is summed up even if it writes nothing else
+    fn expired(
+        &mut self,
+        now: Instant,
+        window: impl Fn(&str) -> Duration,
+    ) -> Vec<(String, Level, String)> {
+        let ended: Vec<String> = self
+            .last
+            .iter()
+            .filter(|(target, last)| now.duration_since(last.since) >= window(target))
+            .map(|(target, _)| target.clone())
+            .collect();
+        ended
+            .into_iter()
+            .filter_map(|target| {
+                let last = self.last.remove(&target)?;
+                let line = format!("message repeated {} times: {}", last.count, last.message);
+                (last.count > 0).then_some((target, last.level, line))
+            })
+            .collect()
+    }
+}
+
+/// Writes the log lines of the process, with the configuration given at startup
+struct Logger {
+    config: LoggingConfig,
+    output: Mutex<Box<dyn Write + Send>>,
+    to_terminal: AtomicBool,
+    dedup: Mutex<Dedup>,
+}
+
+impl Logger {
+    fn new(config: LoggingConfig) -> Self {
+        let file = config.file.as_ref().and_then(|path| {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("Could not open log file {}: {}", path, e);
+                    None
+                }
+            }
+        });
+        let to_terminal = file.is_none();
+        let output: Box<dyn Write + Send> = match file {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stdout()),
+        };
+        Self {
+            config,
+            output: Mutex::new(output),
+            to_terminal: AtomicBool::new(to_terminal),
+            dedup: Mutex::new(Dedup::default()),
+        }
+    }
+
+    /// Writes the line, the lines of an order are never collapsed since each one has its own trace id
+    fn write(&self, level: Level, target: &str, trace_id: Option<&str>, msg: ColoredString) {
+        if !self.config.enabled(target, level) {
+            return;
+        }
+        if trace_id.is_some() {
+            self.write_line(level, target, trace_id, msg);
+            return;
+        }
+        let window = self.config.dedup_window(target);
+        let (write, summary) = match self.dedup.lock() {
+            Ok(mut dedup) => dedup.check(level, target, &msg, window, Instant::now()),
+            Err(_) => (true, None),
+        };
+        if let Some((level, summary)) = summary {
+            self.write_line(level, target, None, summary.into());
+        }
+        if write {
+            self.write_line(level, target, None, msg);
+        }
+    }
+
+    /// Writes the summary of the repeats of every window that ended
+    fn flush_repeats(&self) {
+        let expired = match self.dedup.lock() {
+            Ok(mut dedup) => {
+                dedup.expired(Instant::now(), |target| self.config.dedup_window(target))
+            }
+            Err(_) => return,
+        };
+        for (target, level, summary) in expired {
+            self.write_line(level, &target, None, summary.into());
+        }
+    }
+
+    fn write_line(&self, level: Level, target: &str, trace_id: Option<&str>, msg: ColoredString) {
+        let prefix = match trace_id {
+            Some(trace_id) => format!("[{}] [{}]", target, trace_id),
+            None => format!("[{}]", target),
+        };
+        let line = match self.config.format {
+            LogFormat::Pretty if self.to_terminal.load(Ordering::Relaxed) => {
+                format!("{} {}", prefix, msg)
+            }
+            LogFormat::Pretty => format!("{} {}", prefix, &*msg),
+            LogFormat::Json => json_line(level, target, trace_id, &msg),
+        };
+        if let Ok(mut output) = self.output.lock() {
+            let _ = writeln!(output, "{}", line);
+        }
+    }
+}
+
+/// Writes the lines meant for stdout to the given output instead, without colors.
+/// Returns false if the lines are written to a file, then they are left there
+pub fn redirect_terminal(output: Box<dyn Write + Send>) -> bool {
+    let logger = logger();
+    if !logger.to_terminal.load(Ordering::Relaxed) {
+        return false;
+    }
+    if let Ok(mut current) = logger.output.lock() {
+        *current = output;
+    }
+    logger.to_terminal.store(false, Ordering::Relaxed);
+    true
+}
+
+fn json_line(level: Level, target: &str, trace_id: Option<&str>, message: &str) -> String {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let line = JsonLine {
+        timestamp_ms,
+        level,
+        target,
+        trace_id,
+        message,
+    };
+    serde_json::to_string(&line).unwrap_or_else(|_| message.to_string())
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| {
+        let config = config::get().logging.clone();
+        let dedups = config.dedup_window_ms > 0 || config.dedup_targets.values().any(|ms| *ms > 0);
+        if dedups {
+            thread::spawn(|| loop {
+                thread::sleep(FLUSH_INTERVAL);
+                logger().flush_repeats();
+            });
+        }
+        Logger::new(config)
+    })
+}
+
+/// Writes a line with the given level, the message keeps its colors when it is written to a terminal
+pub fn log(level: Level, target: &str, msg: impl Into<ColoredString>) {
+    logger().write(level, target, None, msg.into());
+}
+
+/// Writes a line about an order with its trace id, an empty one is left out
+pub fn traced(level: Level, target: &str, trace_id: &str, msg: impl Into<ColoredString>) {
+    let trace_id = Some(trace_id).filter(|trace_id| !trace_id.is_empty());
+    logger().write(level, target, trace_id, msg.into());
+}
+
+pub fn error(target: &str, msg: impl Into<ColoredString>) {
+    log(Level::Error, target, msg);
+}
+
+pub fn warn(target: &str, msg: impl Into<ColoredString>) {
+    log(Level::Warn, target, msg);
+}
+
+pub fn info(target: &str, msg: impl Into<ColoredString>) {
+    log(Level::Info, target, msg);
+}
+
+pub fn debug(target: &str, msg: impl Into<ColoredString>) {
+    log(Level::Debug, target, msg);
+}
+
+/// Logs an error when trying to send a message.
+pub fn send_error(target: &str, msg: &str, e: &str) {
+    let line = format!(
+        "Error trying to send {} Message. Message dumped\n {}",
+        msg, e
+    );
+    error(target, line.red());
+}
+
+/// Logs an error when trying to create a message.
+pub fn create_error(target: &str, msg: &str, e: &str) {
+    let line = format!(
+        "Error trying to create the {} Message. Message dumped\n {}",
+        msg, e
+    );
+    error(target, line.red());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_line_has_level_target_and_message() {
+        let line = json_line(Level::Warn, "RCH", None, "Retrying");
+        assert!(line.contains(r#""level":"Warn""#));
+        assert!(line.contains(r#""target":"RCH""#));
+        assert!(line.contains(r#""message":"Retrying""#));
+        assert!(!line.contains("trace_id"));
+
+        let line = json_line(Level::Info, "OM", Some("00ab"), "Scooping");
+        assert!(line.contains(r#""trace_id":"00ab""#));
+    }
+
+    #[test]
+    fn test_repeated_lines_are_collapsed_within_the_window() {
+        let mut dedup = Dedup::default();
+        let window = Duration::from_secs(1);
+        let start = Instant::now();
+        let line = "Could not connect";
+
+        assert_eq!(
+            dedup.check(Level::Warn, "RCH", line, window, start),
+            (true, None)
+        );
+        for i in 1..=3 {
+            let now = start + Duration::from_millis(i * 100);
+            assert_eq!(
+                dedup.check(Level::Warn, "RCH", line, window, now),
+                (false, None)
+            );
+        }
+        assert_eq!(
+            dedup.check(Level::Warn, "RL", line, window, start),
+            (true, None)
+        );
+        let (write, summary) = dedup.check(Level::Warn, "RCH", "Connected", window, start);
+        assert!(write);
+        assert_eq!(
+            summary,
+            Some((
+                Level::Warn,
+                "message repeated 3 times: Could not connect".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_line_is_written_again_after_the_window() {
+        let mut dedup = Dedup::default();
+        let window = Duration::from_secs(1);
+        let start = Instant::now();
+
+        dedup.check(Level::Warn, "RCH", "Retrying", window, start);
+        dedup.check(Level::Warn, "RCH", "Retrying", window, start);
+        let later = start + Duration::from_secs(2);
+        let (write, summary) = dedup.check(Level::Warn, "RCH", "Retrying", window, later);
+        assert!(write);
+        assert!(summary.is_some());
+        assert_eq!(
+            dedup.check(Level::Warn, "RCH", "Retrying", Duration::ZERO, later),
+            (true, None)
+        );
+    }
+
+    #[test]
+    fn test_burst_is_summed_up_when_the_window_ends_in_silence() {
+        let mut dedup = Dedup::default();
+        let window = |_: &str| Duration::from_secs(1);
+        let start = Instant::now();
+        for _ in 0..4 {
+            dedup.check(Level::Warn, "RCH", "Retrying", window(""), start);
+        }
+        dedup.check(Level::Info, "RL", "Leading", window(""), start);
+
+        assert!(dedup
+            .expired(start + Duration::from_millis(500), window)
+            .is_empty());
+        assert_eq!(
+            dedup.expired(start + Duration::from_secs(1), window),
+            vec![(
+                "RCH".to_string(),
+                Level::Warn,
+                "message repeated 3 times: Retrying".to_string()
+            )]
+        );
+        assert!(dedup
+            .expired(start + Duration::from_secs(2), window)
+            .is_empty());
+        assert_eq!(
+            dedup.check(
+                Level::Warn,
+                "RCH",
+                "Retrying",
+                window(""),
+                start + Duration::from_secs(2)
+            ),
+            (true, None)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Watching the mailboxes of the busiest actors of a process.
+//! Actix does not tell how many messages an actor has waiting, so a probe is sent to it every while and the
+//! time it waits to be handled is taken as how loaded the mailbox is. Their mailboxes are bounded, and the
+//! messages that can be lost, like a backup that a newer one replaces, are dropped when it is full.
+
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use actix::WeakAddr;
+use colored::Colorize;
+use std::time::{Duration, Instant};
+
+use crate::common::log;
+use crate::common::metrics;
+use crate::config;
+
+/// Message that measures how long an actor takes to get to it.
+/// Only the time it waited in the mailbox matters, so every watched actor handles it doing nothing
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Probe;
+
+/// Bounds the mailbox of the actor and starts watching it, if it is enabled in the config
+pub fn watch<A>(name: &'static str, ctx: &mut Context<A>)
+where
+    A: Actor<Context = Context<A>> + Handler<Probe>,
+{
+    let mailbox = &config::get().mailbox;
+    ctx.set_mailbox_capacity(mailbox.capacity);
+    if mailbox.sample_interval_ms == 0 {
+        return;
+    }
+    let interval = Duration::from_millis(mailbox.sample_interval_ms);
+    let warn_delay = Duration::from_millis(mailbox.warn_delay_ms);
+    actix::spawn(sample(
+        name,
+        ctx.address().downgrade(),
+        interval,
+        warn_delay,
+    ));
+}
+
+/// Sends a probe to the actor every interval until it stops, warning when one waits more than `warn_delay`.
+/// It keeps a weak address, so the actor still stops once nobody else has its address
+async fn sample<A>(name: &'static str, addr: WeakAddr<A>, interval: Duration, warn_delay: Duration)
+where
+    A: Actor + Handler<Probe>,
+    A::Context: ToEnvelope<A, Probe>,
+{
+    loop {
+        tokio::time::sleep(interval).await;
+        let sent_at = Instant::now();
+        let probe = match addr.upgrade() {
+            Some(addr) => addr.send(Probe),
+            None => return,
+        };
+        if probe.await.is_err() {
+            return;
+        }
+        let delay = sent_at.elapsed();
+        metrics::get().mailbox_sampled(name, delay);
+        if delay > warn_delay {
+            metrics::get().mailbox_overloaded(name);
+            let line = format!(
+                "The mailbox of {} is overloaded, a message waited {} ms",
+                name,
+                delay.as_millis()
+            );
+            log::warn("MAILBOX", line.bright_red());
+        }
+    }
+}
+
+/// Sends a low priority message without waiting, dropping it if the mailbox of the actor is full
+pub fn send_or_shed<A, M>(addr: &Addr<A>, msg: M, name: &str)
+where
+    A: Actor + Handler<M>,
+    A::Context: ToEnvelope<A, M>,
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    match addr.try_send(msg) {
+        Ok(()) => {}
+        Err(SendError::Full(_)) => {
+            metrics::get().message_shed(name);
+            let line = format!("Dropping {}, the mailbox is full", name);
+            log::warn("MAILBOX", line.yellow());
+        }
+        Err(SendError::Closed(_)) => log::send_error("MAILBOX", name, "the actor is stopped"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Slow(usize);
+
+    impl Actor for Slow {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Probe> for Slow {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Probe, _ctx: &mut Context<Self>) {
+            self.0 += 1;
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "usize")]
+    struct Handled;
+
+    impl Handler<Handled> for Slow {
+        type Result = usize;
+
+        fn handle(&mut self, _msg: Handled, _ctx: &mut Context<Self>) -> usize {
+            self.0
+        }
+    }
+
+    #[actix::test]
+    async fn test_messages_past_the_capacity_are_shed() {
+        let slow = Slow::create(|ctx| {
+            ctx.set_mailbox_capacity(1);
+            Slow(0)
+        });
+        for _ in 0..5 {
+            send_or_shed(&slow, Probe, "Probe");
+        }
+        assert!(slow.send(Handled).await.unwrap() < 5);
+    }
+
+    #[actix::test]
+    async fn test_probe_wait_is_sampled() {
+        tokio::time::pause();
+        let slow = Slow(0).start();
+        let interval = Duration::from_millis(10);
+        actix::spawn(sample("slow", slow.downgrade(), interval, Duration::ZERO));
+        tokio::time::sleep(Duration::from_millis(35)).await;
+        assert_eq!(slow.send(Handled).await.unwrap(), 3);
+    }
+}
@@ -0,0 +1,512 @@
+//! Metrics of a process, exposed in the Prometheus text format over HTTP.
+//! Each robot and screen keeps its own counters, so every process has to be scraped.
+
+use crate::common::log;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::common::flavor_id::FlavorID;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Counters and gauges of the process
+#[derive(Debug, Default)]
+pub struct Metrics {
+    orders_received: AtomicU64,
+    orders_completed: AtomicU64,
+    orders_aborted: AtomicU64,
+    orders_refunded: AtomicU64,
+    scoops_failed: AtomicU64,
+    elections: AtomicU64,
+    retries: Mutex<BTreeMap<String, u64>>,
+    token_round_trips: AtomicU64,
+    token_round_trip_micros: AtomicU64,
+    tokens_last_seen: Mutex<HashMap<(FlavorID, usize), Instant>>,
+    stock: Mutex<BTreeMap<(String, usize), usize>>,
+    persistence_writes: AtomicU64,
+    persistence_overflows: AtomicU64,
+    low_stock_alerts: AtomicU64,
+    screen_messages_rejected: Mutex<BTreeMap<String, u64>>,
+    mailbox_delays: Mutex<BTreeMap<String, u64>>,
+    mailbox_overloads: Mutex<BTreeMap<String, u64>>,
+    messages_shed: Mutex<BTreeMap<String, u64>>,
+    backup_bytes_raw: Mutex<BTreeMap<String, u64>>,
+    backup_bytes_sent: Mutex<BTreeMap<String, u64>>,
+    handshakes_dropped: Mutex<BTreeMap<String, u64>>,
+    stock_violations: Mutex<BTreeMap<String, u64>>,
+    shop_orders: Mutex<BTreeMap<(String, String), u64>>,
+    shop_grams: Mutex<BTreeMap<(String, String), u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn order_received(&self, amount: usize) {
+        self.orders_received
+            .fetch_add(amount as u64, Ordering::Relaxed);
+    }
+
+    pub fn order_completed(&self) {
+        self.orders_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn order_aborted(&self) {
+        self.orders_aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn order_refunded(&self) {
+        self.orders_refunded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn scoop_failed(&self) {
+        self.scoops_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn election_started(&self) {
+        self.elections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns how many elections were started by the process
+    pub fn elections(&self) -> u64 {
+        self.elections.load(Ordering::Relaxed)
+    }
+
+    /// Registers a retry of the given path, such as a connection or a message that could not be sent
+    pub fn retried(&self, path: &str) {
+        if let Ok(mut retries) = self.retries.lock() {
+            *retries.entry(path.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers that the given amount of writes were flushed to disk by the persistence writer
+    pub fn persistence_flushed(&self, writes: usize) {
+        self.persistence_writes
+            .fetch_add(writes as u64, Ordering::Relaxed);
+    }
+
+    /// Registers a write that the persistence writer could not take, because its mailbox was full, so it was written right away
+    pub fn persistence_overflowed(&self) {
+        self.persistence_overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Registers that a flavor ran below its low watermark
+    pub fn low_stock_alerted(&self) {
+        self.low_stock_alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Registers a message of a screen that the leader did not handle, with the reason, such as a duplicate
+    pub fn screen_message_rejected(&self, reason: &str) {
+        if let Ok(mut rejected) = self.screen_messages_rejected.lock() {
+            *rejected.entry(reason.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers how long the last probe waited in the mailbox of the actor
+    pub fn mailbox_sampled(&self, actor: &str, delay: Duration) {
+        if let Ok(mut delays) = self.mailbox_delays.lock() {
+            delays.insert(actor.to_string(), delay.as_micros() as u64);
+        }
+    }
+
+    /// Registers that a probe waited too long in the mailbox of the actor
+    pub fn mailbox_overloaded(&self, actor: &str) {
+        if let Ok(mut overloads) = self.mailbox_overloads.lock() {
+            *overloads.entry(actor.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers a low priority message that was dropped because the mailbox of its actor was full
+    pub fn message_shed(&self, message: &str) {
+        if let Ok(mut shed) = self.messages_shed.lock() {
+            *shed.entry(message.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers an order of the shop with how it went: placed, completed, aborted or rejected
+    pub fn shop_order(&self, shop: &str, outcome: &str) {
+        if let Ok(mut orders) = self.shop_orders.lock() {
+            *orders
+                .entry((shop.to_string(), outcome.to_string()))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// Registers the grams of the flavor taken by a completed order of the shop
+    pub fn shop_grams_used(&self, shop: &str, flavor: &FlavorID, grams: usize) {
+        if let Ok(mut used) = self.shop_grams.lock() {
+            *used
+                .entry((shop.to_string(), flavor.to_string()))
+                .or_insert(0) += grams as u64;
+        }
+    }
+
+    /// Registers a connection that was closed before it said who was connecting, and why
+    pub fn handshake_dropped(&self, reason: &str) {
+        if let Ok(mut dropped) = self.handshakes_dropped.lock() {
+            *dropped.entry(reason.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers a flavor whose tokens broke the invariant of the stock accounts, `kind` tells how
+    pub fn stock_violation(&self, kind: &str) {
+        if let Ok(mut violations) = self.stock_violations.lock() {
+            *violations.entry(kind.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Registers a backup of the given kind that was sent, with its size before and after it was compressed
+    pub fn backup_sent(&self, kind: &str, raw: usize, sent: usize) {
+        for (counters, bytes) in [
+            (&self.backup_bytes_raw, raw),
+            (&self.backup_bytes_sent, sent),
+        ] {
+            if let Ok(mut counters) = counters.lock() {
+                *counters.entry(kind.to_string()).or_insert(0) += bytes as u64;
+            }
+        }
+    }
+
+    /// Registers that a token, the given shard of the flavor, arrived to this robot with the given amount.
+    /// The time since the last time the same token was seen is counted as a round trip of the ring
+    pub fn token_seen(&self, flavor: FlavorID, shard: usize, amount: usize) {
+        let now = Instant::now();
+        if let Ok(mut last_seen) = self.tokens_last_seen.lock() {
+            if let Some(previous) = last_seen.insert((flavor.clone(), shard), now) {
+                let micros = now.duration_since(previous).as_micros() as u64;
+                self.token_round_trips.fetch_add(1, Ordering::Relaxed);
+                self.token_round_trip_micros
+                    .fetch_add(micros, Ordering::Relaxed);
+            }
+        }
+        if let Ok(mut stock) = self.stock.lock() {
+            stock.insert((flavor.to_string(), shard), amount);
+        }
+    }
+
+    /// Returns the grams left of each flavor, adding up all its tokens as last seen by this process
+    pub fn stock(&self) -> BTreeMap<String, usize> {
+        let mut total = BTreeMap::new();
+        if let Ok(stock) = self.stock.lock() {
+            for ((flavor, _), amount) in stock.iter() {
+                *total.entry(flavor.clone()).or_insert(0) += amount;
+            }
+        }
+        total
+    }
+
+    /// Renders all the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "freddo_orders_received_total",
+            "Orders received by this process",
+            self.orders_received.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_orders_completed_total",
+            "Orders completed by this process",
+            self.orders_completed.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_orders_aborted_total",
+            "Orders aborted by this process",
+            self.orders_aborted.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_orders_refunded_total",
+            "Orders refunded by this process, since they were aborted after being confirmed",
+            self.orders_refunded.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_scoops_failed_total",
+            "Scoops that failed because the arm jammed",
+            self.scoops_failed.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_elections_total",
+            "Leader elections started by this process",
+            self.elections.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_persistence_writes_total",
+            "Writes flushed to disk by the persistence writer",
+            self.persistence_writes.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_persistence_overflows_total",
+            "Writes the persistence writer could not take, which were written right away",
+            self.persistence_overflows.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "freddo_low_stock_alerts_total",
+            "Times a flavor ran below its low watermark",
+            self.low_stock_alerts.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_retries_total Retries made by this process, by what was retried"
+        );
+        let _ = writeln!(out, "# TYPE freddo_retries_total counter");
+        if let Ok(retries) = self.retries.lock() {
+            for (path, count) in retries.iter() {
+                let _ = writeln!(out, "freddo_retries_total{{path=\"{}\"}} {}", path, count);
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_screen_messages_rejected_total Messages of the screens the leader did not handle, by reason"
+        );
+        let _ = writeln!(out, "# TYPE freddo_screen_messages_rejected_total counter");
+        if let Ok(rejected) = self.screen_messages_rejected.lock() {
+            for (reason, count) in rejected.iter() {
+                let _ = writeln!(
+                    out,
+                    "freddo_screen_messages_rejected_total{{reason=\"{}\"}} {}",
+                    reason, count
+                );
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_mailbox_delay_seconds Time the last probe waited in the mailbox of each actor"
+        );
+        let _ = writeln!(out, "# TYPE freddo_mailbox_delay_seconds gauge");
+        if let Ok(delays) = self.mailbox_delays.lock() {
+            for (actor, micros) in delays.iter() {
+                let _ = writeln!(
+                    out,
+                    "freddo_mailbox_delay_seconds{{actor=\"{}\"}} {}",
+                    actor,
+                    *micros as f64 / 1_000_000.0
+                );
+            }
+        }
+        write_labeled_counters(
+            &mut out,
+            "freddo_mailbox_overloads_total",
+            "Probes that waited too long in the mailbox of each actor",
+            "actor",
+            &self.mailbox_overloads,
+        );
+        write_labeled_counters(
+            &mut out,
+            "freddo_messages_shed_total",
+            "Low priority messages dropped because the mailbox of their actor was full",
+            "message",
+            &self.messages_shed,
+        );
+        write_labeled_counters(
+            &mut out,
+            "freddo_backup_raw_bytes_total",
+            "Bytes of the backups sent, before they were compressed",
+            "kind",
+            &self.backup_bytes_raw,
+        );
+        write_labeled_counters(
+            &mut out,
+            "freddo_backup_sent_bytes_total",
+            "Bytes of the backups sent, after they were compressed",
+            "kind",
+            &self.backup_bytes_sent,
+        );
+        write_labeled_counters(
+            &mut out,
+            "freddo_handshakes_dropped_total",
+            "Connections closed before they said who was connecting",
+            "reason",
+            &self.handshakes_dropped,
+        );
+        write_labeled_counters(
+            &mut out,
+            "freddo_stock_violations_total",
+            "Flavors whose tokens did not have the grams the leader expected",
+            "kind",
+            &self.stock_violations,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_shop_orders_total Orders of each shop handled by the leader, by how they went"
+        );
+        let _ = writeln!(out, "# TYPE freddo_shop_orders_total counter");
+        if let Ok(orders) = self.shop_orders.lock() {
+            for ((shop, outcome), count) in orders.iter() {
+                let _ = writeln!(
+                    out,
+                    "freddo_shop_orders_total{{shop=\"{}\",outcome=\"{}\"}} {}",
+                    shop, outcome, count
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP freddo_shop_grams_used_total Grams of each flavor taken by the completed orders of each shop"
+        );
+        let _ = writeln!(out, "# TYPE freddo_shop_grams_used_total counter");
+        if let Ok(used) = self.shop_grams.lock() {
+            for ((shop, flavor), grams) in used.iter() {
+                let _ = writeln!(
+                    out,
+                    "freddo_shop_grams_used_total{{shop=\"{}\",flavor=\"{}\"}} {}",
+                    shop, flavor, grams
+                );
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_token_round_trip_seconds Time it takes a token to go around the ring"
+        );
+        let _ = writeln!(out, "# TYPE freddo_token_round_trip_seconds summary");
+        let _ = writeln!(
+            out,
+            "freddo_token_round_trip_seconds_sum {}",
+            self.token_round_trip_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(
+            out,
+            "freddo_token_round_trip_seconds_count {}",
+            self.token_round_trips.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP freddo_flavor_stock_grams Grams left of each flavor, as last seen by this process"
+        );
+        let _ = writeln!(out, "# TYPE freddo_flavor_stock_grams gauge");
+        for (flavor, amount) in self.stock().iter() {
+            let _ = writeln!(
+                out,
+                "freddo_flavor_stock_grams{{flavor=\"{}\"}} {}",
+                flavor, amount
+            );
+        }
+        out
+    }
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Writes a counter with one value for each label
+fn write_labeled_counters(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    counters: &Mutex<BTreeMap<String, u64>>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    if let Ok(counters) = counters.lock() {
+        for (value, count) in counters.iter() {
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count);
+        }
+    }
+}
+
+/// Gets the metrics of the process
+pub fn get() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Serves the metrics of the process over HTTP on the given address, any path returns them
+pub async fn serve(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            let line = format!("Could not listen on {}: {}", addr, e);
+            log::error("METRICS", line.red());
+            return;
+        }
+    };
+    let line = format!("Serving metrics on http://{}/metrics", addr);
+    log::info("METRICS", line.bright_blue());
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer_scrape(stream));
+    }
+}
+
+/// Answers a single scrape request with the current metrics
+async fn answer_scrape(mut stream: TcpStream) {
+    let mut buf = [0; 1024];
+    if stream.read(&mut buf).await.is_err() {
+        return;
+    }
+    let body = get().render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_stock() {
+        let metrics = Metrics::new();
+        metrics.order_received(2);
+        metrics.order_completed();
+        metrics.order_aborted();
+        metrics.token_seen(FlavorID::Mint, 0, 300);
+        metrics.token_seen(FlavorID::Mint, 0, 100);
+        metrics.token_seen(FlavorID::Mint, 1, 50);
+        metrics.retried("leader_connection");
+        metrics.persistence_overflowed();
+        metrics.low_stock_alerted();
+        metrics.screen_message_rejected("duplicate");
+        metrics.mailbox_sampled("leader", Duration::from_millis(500));
+        metrics.mailbox_overloaded("leader");
+        metrics.message_shed("RelayBackup");
+        metrics.backup_sent("leader", 1000, 200);
+        metrics.shop_order("centro", "placed");
+        metrics.shop_grams_used("centro", &FlavorID::Mint, 250);
+
+        let out = metrics.render();
+        assert!(out.contains("freddo_orders_received_total 2"));
+        assert!(out.contains("freddo_orders_completed_total 1"));
+        assert!(out.contains("freddo_orders_aborted_total 1"));
+        assert!(out.contains("freddo_token_round_trip_seconds_count 1"));
+        assert!(out.contains("freddo_retries_total{path=\"leader_connection\"} 1"));
+        assert!(out.contains("freddo_persistence_overflows_total 1"));
+        assert!(out.contains("freddo_low_stock_alerts_total 1"));
+        assert!(out.contains("freddo_screen_messages_rejected_total{reason=\"duplicate\"} 1"));
+        assert!(out.contains("freddo_flavor_stock_grams{flavor=\"Mint\"} 150"));
+        assert!(out.contains("freddo_mailbox_delay_seconds{actor=\"leader\"} 0.5"));
+        assert!(out.contains("freddo_mailbox_overloads_total{actor=\"leader\"} 1"));
+        assert!(out.contains("freddo_messages_shed_total{message=\"RelayBackup\"} 1"));
+        assert!(out.contains("freddo_backup_raw_bytes_total{kind=\"leader\"} 1000"));
+        assert!(out.contains("freddo_backup_sent_bytes_total{kind=\"leader\"} 200"));
+        assert!(out.contains("freddo_shop_orders_total{shop=\"centro\",outcome=\"placed\"} 1"));
+        assert!(out.contains("freddo_shop_grams_used_total{shop=\"centro\",flavor=\"Mint\"} 250"));
+    }
+}
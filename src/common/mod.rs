@@ -1,5 +1,16 @@
+pub mod chaos;
+pub mod codec;
+pub mod error;
 pub mod flavor_id;
+pub mod handshake;
+pub mod health;
+pub mod log;
+pub mod mailbox;
+pub mod metrics;
 pub mod order;
-pub mod robot_messages;
-pub mod screen_messages;
+pub mod order_source;
+pub mod persistence_writer;
+pub mod registry;
+pub mod simulation;
 pub mod utils;
+pub mod webhook;
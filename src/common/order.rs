@@ -1,70 +1,268 @@
 use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
 
 use crate::common::flavor_id::FlavorID;
+use crate::config;
+use crate::config::sizes::OrderSize;
 
 pub const KILO: usize = 1000;
-const MEDIO: usize = KILO / 2;
-const CUARTO: usize = KILO / 4;
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub enum Order {
-    Cucurucho((FlavorID, usize)),
-    Cuarto(Vec<(FlavorID, usize)>),
-    Medio(Vec<(FlavorID, usize)>),
-    Kilo(Vec<(FlavorID, usize)>),
+    Cucurucho(Scoop),
+    Cuarto(Vec<Scoop>),
+    Medio(Vec<Scoop>),
+    Kilo(Vec<Scoop>),
 }
 
+/// A flavor of an order with its grams, and the flavors that can be served instead if it runs out, in order of preference.
+/// The substitutes can be left out, so `["Mint", 250]` is still a valid scoop
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct Scoop(pub FlavorID, pub usize, #[serde(default)] pub Vec<FlavorID>);
+
+impl Scoop {
+    pub fn new(flavor: FlavorID, grams: usize) -> Self {
+        Scoop(flavor, grams, vec![])
+    }
+}
+
+/// A flavor that was served instead of the one asked, since it ran out
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Substitution {
+    pub flavor: FlavorID,
+    pub substitute: FlavorID,
+}
+
+/// Why an order can not be prepared as it is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrderValidationError {
+    /// The order has no flavors
+    NoFlavors { kind: &'static str },
+    /// The order has more flavors than its size allows
+    TooManyFlavors {
+        kind: &'static str,
+        max: usize,
+        given: usize,
+    },
+    /// A flavor appears more than once, only the constructors merge them
+    DuplicatedFlavor(FlavorID),
+    /// A flavor is asked with no grams
+    EmptyScoop(FlavorID),
+    /// The flavors weigh more than the size of the order
+    TooHeavy {
+        kind: &'static str,
+        max: usize,
+        given: usize,
+    },
+    /// A substitute of the flavor is the flavor itself or it is repeated
+    InvalidSubstitute {
+        flavor: FlavorID,
+        substitute: FlavorID,
+    },
+}
+
+impl fmt::Display for OrderValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderValidationError::NoFlavors { kind } => {
+                write!(f, "A {} needs at least one flavor", kind)
+            }
+            OrderValidationError::TooManyFlavors { kind, max, given } => write!(
+                f,
+                "A {} can have up to {} flavors, {} were given",
+                kind, max, given
+            ),
+            OrderValidationError::DuplicatedFlavor(flavor) => {
+                write!(f, "The flavor {} is repeated", flavor)
+            }
+            OrderValidationError::EmptyScoop(flavor) => {
+                write!(f, "The flavor {} has no grams", flavor)
+            }
+            OrderValidationError::TooHeavy { kind, max, given } => write!(
+                f,
+                "A {} weighs up to {} grams, {} were asked",
+                kind, max, given
+            ),
+            OrderValidationError::InvalidSubstitute { flavor, substitute } => write!(
+                f,
+                "The flavor {} can not be a substitute of {} more than once, nor of itself",
+                substitute, flavor
+            ),
+        }
+    }
+}
+
+impl Error for OrderValidationError {}
+
 impl Order {
+    /// Creates a cucurucho of the flavor, with the grams of its size in the `sizes` config
     pub fn new_cucurucho(flavor: FlavorID) -> Self {
-        Order::Cucurucho((flavor, CUARTO))
+        Order::Cucurucho(Scoop::new(flavor, config::get().sizes.cucurucho.grams))
+    }
+
+    /// Creates a cuarto with as many flavors as its size allows, the grams are split between them
+    pub fn new_cuarto(flavors: Vec<FlavorID>) -> Result<Self, OrderValidationError> {
+        split_grams("cuarto", config::get().sizes.cuarto, flavors).map(Order::Cuarto)
+    }
+
+    /// Creates a medio with as many flavors as its size allows, the grams are split between them
+    pub fn new_medio(flavors: Vec<FlavorID>) -> Result<Self, OrderValidationError> {
+        split_grams("medio", config::get().sizes.medio, flavors).map(Order::Medio)
+    }
+
+    /// Creates a kilo with as many flavors as its size allows, the grams are split between them
+    pub fn new_kilo(flavors: Vec<FlavorID>) -> Result<Self, OrderValidationError> {
+        split_grams("kilo", config::get().sizes.kilo, flavors).map(Order::Kilo)
+    }
+
+    pub fn get_flavors(&self) -> Vec<(FlavorID, usize)> {
+        self.scoops()
+            .iter()
+            .map(|Scoop(flavor, grams, _)| (flavor.clone(), *grams))
+            .collect()
     }
 
-    pub fn new_cuarto(flavors: Vec<FlavorID>) -> Result<Self, String> {
-        if flavors.len() > 2 {
-            Err("A cuarto can have up to 2 flavors.".to_string())
-        } else {
-            let flavors_needed = [(flavors[0], CUARTO / 2), (flavors[1], CUARTO / 2)].to_vec();
-            Ok(Order::Cuarto(flavors_needed))
+    /// Returns the substitutes of each flavor of the order that has any
+    pub fn get_substitutes(&self) -> Vec<(FlavorID, Vec<FlavorID>)> {
+        self.scoops()
+            .iter()
+            .filter(|Scoop(_, _, substitutes)| !substitutes.is_empty())
+            .map(|Scoop(flavor, _, substitutes)| (flavor.clone(), substitutes.clone()))
+            .collect()
+    }
+
+    /// Sets the flavors that can be served instead of the flavor if it runs out, the first ones are preferred
+    pub fn with_substitutes(mut self, flavor: FlavorID, substitutes: Vec<FlavorID>) -> Self {
+        let scoops = match &mut self {
+            Order::Cucurucho(scoop) => std::slice::from_mut(scoop),
+            Order::Cuarto(scoops) | Order::Medio(scoops) | Order::Kilo(scoops) => scoops,
+        };
+        if let Some(scoop) = scoops.iter_mut().find(|scoop| scoop.0 == flavor) {
+            scoop.2 = substitutes;
         }
+        self
     }
 
-    pub fn new_medio(flavors: Vec<FlavorID>) -> Result<Self, String> {
-        if flavors.len() > 3 {
-            Err("A medio can have up to 3 flavors".to_string())
-        } else {
-            let flavors_needed = [
-                (flavors[1], MEDIO / 3_usize),
-                (flavors[2], MEDIO / 3_usize),
-                (flavors[0], MEDIO / 3_usize),
-            ]
-            .to_vec();
-            Ok(Order::Medio(flavors_needed))
+    /// Returns the order of the same size with only the flavors and the substitutes that are kept, none if no flavor is
+    pub fn only(&self, keep: impl Fn(&FlavorID) -> bool) -> Option<Order> {
+        let scoops: Vec<Scoop> = self
+            .scoops()
+            .iter()
+            .filter(|Scoop(flavor, _, _)| keep(flavor))
+            .map(|Scoop(flavor, grams, substitutes)| {
+                let substitutes = substitutes.iter().filter(|s| keep(s)).cloned().collect();
+                Scoop(flavor.clone(), *grams, substitutes)
+            })
+            .collect();
+        if scoops.is_empty() {
+            return None;
         }
+        Some(match self {
+            Order::Cucurucho(_) => Order::Cucurucho(scoops.into_iter().next()?),
+            Order::Cuarto(_) => Order::Cuarto(scoops),
+            Order::Medio(_) => Order::Medio(scoops),
+            Order::Kilo(_) => Order::Kilo(scoops),
+        })
     }
 
-    pub fn new_kilo(flavors: Vec<FlavorID>) -> Result<Self, String> {
-        if flavors.len() > 4 {
-            Err("A kilo can have up to 4 flavors.".to_string())
-        } else {
-            let flavors_needed = [
-                (flavors[0], CUARTO),
-                (flavors[1], CUARTO),
-                (flavors[2], CUARTO),
-                (flavors[3], CUARTO),
-            ]
-            .to_vec();
-            Ok(Order::Kilo(flavors_needed))
+    fn scoops(&self) -> &[Scoop] {
+        match self {
+            Order::Cucurucho(scoop) => std::slice::from_ref(scoop),
+            Order::Cuarto(scoops) | Order::Medio(scoops) | Order::Kilo(scoops) => scoops,
         }
     }
 
-    pub fn get_flavors(&self) -> Vec<(FlavorID, usize)> {
+    /// Returns the name and the size of the kind of order
+    fn kind(&self) -> (&'static str, OrderSize) {
+        let sizes = &config::get().sizes;
         match self {
-            Order::Cucurucho(flavor) => vec![*flavor],
-            Order::Cuarto(flavors) => flavors.clone(),
-            Order::Medio(flavors) => flavors.clone(),
-            Order::Kilo(flavors) => flavors.clone(),
+            Order::Cucurucho(_) => ("cucurucho", sizes.cucurucho),
+            Order::Cuarto(_) => ("cuarto", sizes.cuarto),
+            Order::Medio(_) => ("medio", sizes.medio),
+            Order::Kilo(_) => ("kilo", sizes.kilo),
+        }
+    }
+
+    /// Checks an order that was not made by the constructors, such as one read from a file
+    pub fn validate(&self) -> Result<(), OrderValidationError> {
+        let (kind, size) = self.kind();
+        let (max_grams, max_flavors) = (size.grams, size.max_flavors);
+        let flavors = self.get_flavors();
+        if flavors.is_empty() {
+            return Err(OrderValidationError::NoFlavors { kind });
+        }
+        if flavors.len() > max_flavors {
+            return Err(OrderValidationError::TooManyFlavors {
+                kind,
+                max: max_flavors,
+                given: flavors.len(),
+            });
+        }
+        for (i, (flavor, grams)) in flavors.iter().enumerate() {
+            if *grams == 0 {
+                return Err(OrderValidationError::EmptyScoop(flavor.clone()));
+            }
+            if flavors[..i].iter().any(|(other, _)| other == flavor) {
+                return Err(OrderValidationError::DuplicatedFlavor(flavor.clone()));
+            }
+        }
+        for Scoop(flavor, _, substitutes) in self.scoops() {
+            for (i, substitute) in substitutes.iter().enumerate() {
+                if substitute == flavor || substitutes[..i].contains(substitute) {
+                    return Err(OrderValidationError::InvalidSubstitute {
+                        flavor: flavor.clone(),
+                        substitute: substitute.clone(),
+                    });
+                }
+            }
+        }
+        let total: usize = flavors.iter().map(|(_, grams)| grams).sum();
+        if total > max_grams {
+            return Err(OrderValidationError::TooHeavy {
+                kind,
+                max: max_grams,
+                given: total,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Splits the grams of an order between its flavors, the first flavors get the grams left by the division.
+/// A flavor given more than once gets the grams of every time it was given, and counts once for the flavors of the size
+fn split_grams(
+    kind: &'static str,
+    size: OrderSize,
+    flavors: Vec<FlavorID>,
+) -> Result<Vec<Scoop>, OrderValidationError> {
+    let (total, max) = (size.grams, size.max_flavors);
+    if flavors.is_empty() {
+        return Err(OrderValidationError::NoFlavors { kind });
+    }
+    let distinct = flavors
+        .iter()
+        .enumerate()
+        .filter(|(i, flavor)| !flavors[..*i].contains(flavor))
+        .count();
+    if distinct > max {
+        return Err(OrderValidationError::TooManyFlavors {
+            kind,
+            max,
+            given: distinct,
+        });
+    }
+    let share = total / flavors.len();
+    let left = total % flavors.len();
+    let mut split: Vec<Scoop> = Vec::new();
+    for (i, flavor) in flavors.into_iter().enumerate() {
+        let grams = share + usize::from(i < left);
+        match split.iter_mut().find(|scoop| scoop.0 == flavor) {
+            Some(scoop) => scoop.1 += grams,
+            None => split.push(Scoop::new(flavor, grams)),
         }
     }
+    Ok(split)
 }
 
 #[cfg(test)]
@@ -73,9 +271,20 @@ mod tests {
 
     #[test]
     fn test_new_cuarto_failure() {
-        let flavors = vec![FlavorID::Chocolate, FlavorID::Vanilla, FlavorID::Chocolate];
+        let flavors = vec![FlavorID::Chocolate, FlavorID::Vanilla, FlavorID::Mint];
         let order = Order::new_cuarto(flavors);
-        assert_eq!(order, Err("A cuarto can have up to 2 flavors.".to_string()));
+        assert_eq!(
+            order,
+            Err(OrderValidationError::TooManyFlavors {
+                kind: "cuarto",
+                max: 2,
+                given: 3
+            })
+        );
+        assert_eq!(
+            Order::new_cuarto(vec![]),
+            Err(OrderValidationError::NoFlavors { kind: "cuarto" })
+        );
     }
 
     #[test]
@@ -87,7 +296,10 @@ mod tests {
             FlavorID::DulceDeLeche,
         ];
         let order = Order::new_medio(flavors);
-        assert_eq!(order, Err("A medio can have up to 3 flavors".to_string()));
+        assert!(matches!(
+            order,
+            Err(OrderValidationError::TooManyFlavors { max: 3, .. })
+        ));
     }
 
     #[test]
@@ -100,6 +312,130 @@ mod tests {
             FlavorID::Lemon,
         ];
         let order = Order::new_kilo(flavors);
-        assert_eq!(order, Err("A kilo can have up to 4 flavors.".to_string()));
+        assert!(matches!(
+            order,
+            Err(OrderValidationError::TooManyFlavors { max: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_grams_are_split_and_duplicates_merged() {
+        let cuarto = Order::new_cuarto(vec![FlavorID::Mint]).unwrap();
+        assert_eq!(cuarto, Order::Cuarto(vec![Scoop::new(FlavorID::Mint, 250)]));
+
+        let medio =
+            Order::new_medio(vec![FlavorID::Mint, FlavorID::Lemon, FlavorID::Mint]).unwrap();
+        assert_eq!(
+            medio,
+            Order::Medio(vec![
+                Scoop::new(FlavorID::Mint, 333),
+                Scoop::new(FlavorID::Lemon, 167)
+            ])
+        );
+        assert!(medio.validate().is_ok());
+    }
+
+    #[test]
+    fn test_repeated_flavors_count_once_in_every_size() {
+        let (choc, van, mint, lemon) = (
+            FlavorID::Chocolate,
+            FlavorID::Vanilla,
+            FlavorID::Mint,
+            FlavorID::Lemon,
+        );
+        let cuarto = Order::new_cuarto(vec![choc.clone(), van.clone(), choc.clone()]).unwrap();
+        assert_eq!(
+            cuarto,
+            Order::Cuarto(vec![
+                Scoop::new(choc.clone(), 167),
+                Scoop::new(van.clone(), 83)
+            ])
+        );
+        assert!(cuarto.validate().is_ok());
+        assert_eq!(
+            Order::new_cuarto(vec![choc.clone(), van.clone(), mint.clone(), choc.clone()]),
+            Err(OrderValidationError::TooManyFlavors {
+                kind: "cuarto",
+                max: 2,
+                given: 3
+            })
+        );
+
+        let medio = vec![mint.clone(), lemon.clone(), choc.clone(), mint.clone()];
+        assert_eq!(Order::new_medio(medio).unwrap().get_flavors().len(), 3);
+        let medio = vec![
+            mint.clone(),
+            lemon.clone(),
+            choc.clone(),
+            van.clone(),
+            mint.clone(),
+        ];
+        assert!(matches!(
+            Order::new_medio(medio),
+            Err(OrderValidationError::TooManyFlavors { given: 4, .. })
+        ));
+
+        let kilo = vec![
+            choc.clone(),
+            van.clone(),
+            mint.clone(),
+            lemon.clone(),
+            choc.clone(),
+        ];
+        let kilo = Order::new_kilo(kilo).unwrap();
+        assert_eq!(kilo.get_flavors().len(), 4);
+        assert!(kilo.validate().is_ok());
+        let kilo = vec![choc.clone(), van, mint, lemon, FlavorID::DulceDeLeche, choc];
+        assert!(matches!(
+            Order::new_kilo(kilo),
+            Err(OrderValidationError::TooManyFlavors { given: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_orders_read_from_files_are_checked() {
+        let repeated = Order::Cuarto(vec![
+            Scoop::new(FlavorID::Mint, 125),
+            Scoop::new(FlavorID::Mint, 125),
+        ]);
+        assert_eq!(
+            repeated.validate(),
+            Err(OrderValidationError::DuplicatedFlavor(FlavorID::Mint))
+        );
+        let heavy = Order::Cucurucho(Scoop::new(FlavorID::Mint, 300));
+        assert!(matches!(
+            heavy.validate(),
+            Err(OrderValidationError::TooHeavy { max: 250, .. })
+        ));
+        assert_eq!(
+            Order::Kilo(vec![]).validate(),
+            Err(OrderValidationError::NoFlavors { kind: "kilo" })
+        );
+    }
+
+    #[test]
+    fn test_substitutes_are_optional_and_checked() {
+        let order: Order =
+            serde_json::from_str(r#"{"Cuarto":[["Mint",125],["Lemon",125]]}"#).unwrap();
+        assert!(order.get_substitutes().is_empty());
+
+        let order = order.with_substitutes(FlavorID::Mint, vec![FlavorID::Chocolate]);
+        assert_eq!(
+            order.get_substitutes(),
+            vec![(FlavorID::Mint, vec![FlavorID::Chocolate])]
+        );
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+        let bytes = bincode::serialize(&order).unwrap();
+        assert_eq!(bincode::deserialize::<Order>(&bytes).unwrap(), order);
+
+        let itself = order.with_substitutes(FlavorID::Lemon, vec![FlavorID::Lemon]);
+        assert_eq!(
+            itself.validate(),
+            Err(OrderValidationError::InvalidSubstitute {
+                flavor: FlavorID::Lemon,
+                substitute: FlavorID::Lemon
+            })
+        );
     }
 }
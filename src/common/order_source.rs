@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+
+/// How an order got to its screen
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderChannel {
+    /// Read from the file of orders of the screen, or from it while it is watched
+    File,
+    /// Typed in the terminal of the screen
+    Terminal,
+    /// Submitted through the HTTP API of the screen
+    Api,
+    /// Left by a screen that died, the screen that has its backup took it
+    Failover,
+}
+
+/// Where and when an order was placed, it goes with the order from its capture until it is finished.
+/// `trace_id` is written in every log line about the order, in the screen, the leader and the robot that prepares it,
+/// so the whole life of an order can be found with a single grep. It is empty for the orders of an older screen.
+/// `shop` is the shop of the screen, when the cluster serves more than one, and it stays with the order if another screen takes it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrderSource {
+    pub screen_id: usize,
+    pub channel: OrderChannel,
+    pub placed_at_ms: u64,
+    #[serde(default)]
+    pub trace_id: String,
+    #[serde(default)]
+    pub shop: Option<String>,
+}
+
+impl OrderSource {
+    /// Creates the source of an order placed now, with a new trace id and the shop of the screen
+    pub fn new(screen_id: usize, channel: OrderChannel) -> Self {
+        Self {
+            screen_id,
+            channel,
+            placed_at_ms: now_ms(),
+            trace_id: format!("{:016x}", rand::random::<u64>()),
+            shop: config::get().tenants.shop_of(screen_id).map(str::to_string),
+        }
+    }
+
+    /// Returns the milliseconds since the order was placed, by the clock of this process
+    pub fn elapsed_ms(&self) -> u64 {
+        now_ms().saturating_sub(self.placed_at_ms)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
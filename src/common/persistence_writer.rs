@@ -0,0 +1,324 @@
+//! Writes to disk of a process, done by an actor in its own arbiter so the other actors never block on a file.
+//! The lines appended to a file and the snapshots that replace a file are batched, and each batch is
+//! synced to disk before the next one. Only the last snapshot of a file is written if several arrive in a batch.
+
+use crate::common::log;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix::dev::SendError;
+use actix::prelude::*;
+
+use crate::common::metrics;
+use crate::config;
+
+/// Writer of each actix system, so the robots of a same process share it
+static WRITER: Mutex<Option<(usize, Addr<PersistenceWriter>)>> = Mutex::new(None);
+
+/// Appends each line to the end of the file, a line break is added after each one
+pub fn append_lines(path: &Path, lines: &[String], fsync: bool) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    file.write_all(content.as_bytes())?;
+    if fsync {
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// Replaces the file with the content.
+/// It is first written to a temporary file and then renamed, so a crash never leaves a half written file
+pub fn write_atomically(path: &Path, content: &str, fsync: bool) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    if fsync {
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Batches the writes of the process and flushes them to disk
+#[derive(Debug)]
+pub struct PersistenceWriter {
+    appends: HashMap<PathBuf, Vec<String>>,
+    snapshots: HashMap<PathBuf, String>,
+    pending: usize,
+    flush_interval: Duration,
+    batch_size: usize,
+    mailbox_capacity: usize,
+    fsync: bool,
+}
+
+impl PersistenceWriter {
+    pub fn new(
+        flush_interval: Duration,
+        batch_size: usize,
+        mailbox_capacity: usize,
+        fsync: bool,
+    ) -> Self {
+        Self {
+            appends: HashMap::new(),
+            snapshots: HashMap::new(),
+            pending: 0,
+            flush_interval,
+            batch_size,
+            mailbox_capacity,
+            fsync,
+        }
+    }
+
+    /// Creates a writer with the persistence config
+    pub fn from_config() -> Self {
+        let persistence = &config::get().persistence;
+        Self::new(
+            Duration::from_millis(persistence.flush_interval_ms),
+            persistence.batch_size,
+            persistence.mailbox_capacity,
+            persistence.fsync,
+        )
+    }
+
+    /// Writes every pending write to disk, returns how many files could not be written
+    fn flush(&mut self) -> usize {
+        if self.pending == 0 {
+            return 0;
+        }
+        let mut failed = 0;
+        for (path, lines) in self.appends.drain() {
+            if let Err(e) = append_lines(&path, &lines, self.fsync) {
+                log::error("PW", format!("Could not write {}: {}", path.display(), e));
+                failed += 1;
+            }
+        }
+        for (path, content) in self.snapshots.drain() {
+            if let Err(e) = write_atomically(&path, &content, self.fsync) {
+                log::error("PW", format!("Could not write {}: {}", path.display(), e));
+                failed += 1;
+            }
+        }
+        metrics::get().persistence_flushed(self.pending);
+        self.pending = 0;
+        failed
+    }
+
+    fn flush_if_full(&mut self) {
+        if self.pending >= self.batch_size {
+            self.flush();
+        }
+    }
+}
+
+impl Actor for PersistenceWriter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(self.mailbox_capacity);
+        ctx.run_interval(self.flush_interval, |actor, _| {
+            actor.flush();
+        });
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.flush();
+        Running::Stop
+    }
+}
+
+/// AppendLine is a message that adds a line at the end of a file
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct AppendLine {
+    pub path: PathBuf,
+    pub line: String,
+}
+
+impl Handler<AppendLine> for PersistenceWriter {
+    type Result = ();
+
+    fn handle(&mut self, msg: AppendLine, _: &mut Self::Context) -> Self::Result {
+        self.appends.entry(msg.path).or_default().push(msg.line);
+        self.pending += 1;
+        self.flush_if_full();
+    }
+}
+
+/// WriteSnapshot is a message that replaces the content of a file
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct WriteSnapshot {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl Handler<WriteSnapshot> for PersistenceWriter {
+    type Result = ();
+
+    fn handle(&mut self, msg: WriteSnapshot, _: &mut Self::Context) -> Self::Result {
+        self.snapshots.insert(msg.path, msg.content);
+        self.pending += 1;
+        self.flush_if_full();
+    }
+}
+
+/// Flush is a message that writes every pending write right away, it answers how many files could not be written
+#[derive(Message, Debug)]
+#[rtype(result = "usize")]
+pub struct Flush();
+
+impl Handler<Flush> for PersistenceWriter {
+    type Result = usize;
+
+    fn handle(&mut self, _: Flush, _: &mut Self::Context) -> Self::Result {
+        self.flush()
+    }
+}
+
+/// Gets the writer of the current actix system, it is started in its own arbiter the first time.
+/// None if there is no actix system running
+pub fn writer() -> Option<Addr<PersistenceWriter>> {
+    let system = System::try_current()?;
+    let mut writer = WRITER.lock().ok()?;
+    match writer.as_ref() {
+        Some((id, addr)) if *id == system.id() && addr.connected() => Some(addr.clone()),
+        _ => {
+            let addr = PersistenceWriter::start_in_arbiter(&Arbiter::new().handle(), |_| {
+                PersistenceWriter::from_config()
+            });
+            *writer = Some((system.id(), addr.clone()));
+            Some(addr)
+        }
+    }
+}
+
+/// Appends the line to the file through the writer of the process.
+/// If there is no writer, or its mailbox is full, the line is written right away
+pub fn append(path: PathBuf, line: String) {
+    let msg = AppendLine { path, line };
+    let msg = match writer() {
+        Some(writer) => match writer.try_send(msg) {
+            Ok(()) => return,
+            Err(SendError::Full(msg)) => {
+                metrics::get().persistence_overflowed();
+                msg
+            }
+            Err(SendError::Closed(msg)) => msg,
+        },
+        None => msg,
+    };
+    let fsync = config::get().persistence.fsync;
+    if let Err(e) = append_lines(&msg.path, &[msg.line], fsync) {
+        log::error(
+            "PW",
+            format!("Could not write {}: {}", msg.path.display(), e),
+        );
+    }
+}
+
+/// Replaces the file with the content through the writer of the process.
+/// If there is no writer, or its mailbox is full, the file is written right away
+pub fn snapshot(path: PathBuf, content: String) {
+    let msg = WriteSnapshot { path, content };
+    let msg = match writer() {
+        Some(writer) => match writer.try_send(msg) {
+            Ok(()) => return,
+            Err(SendError::Full(msg)) => {
+                metrics::get().persistence_overflowed();
+                msg
+            }
+            Err(SendError::Closed(msg)) => msg,
+        },
+        None => msg,
+    };
+    let fsync = config::get().persistence.fsync;
+    if let Err(e) = write_atomically(&msg.path, &msg.content, fsync) {
+        log::error(
+            "PW",
+            format!("Could not write {}: {}", msg.path.display(), e),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "freddo_persistence_writer_{}_{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[actix::test]
+    async fn test_writes_are_batched_until_flushed() {
+        let dir = test_dir("batch");
+        let _ = fs::remove_dir_all(&dir);
+        let journal = dir.join("journal.jsonl");
+        let backup = dir.join("backup.json");
+        let writer = PersistenceWriter::new(Duration::from_secs(60), 10, 10, false).start();
+
+        for line in ["a", "b"] {
+            writer
+                .send(AppendLine {
+                    path: journal.clone(),
+                    line: line.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        for content in ["first", "last"] {
+            writer
+                .send(WriteSnapshot {
+                    path: backup.clone(),
+                    content: content.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        assert!(!journal.exists());
+
+        assert_eq!(writer.send(Flush()).await.unwrap(), 0);
+        assert_eq!(fs::read_to_string(&journal).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "last");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[actix::test]
+    async fn test_full_batch_is_flushed_right_away() {
+        let dir = test_dir("full");
+        let _ = fs::remove_dir_all(&dir);
+        let journal = dir.join("journal.jsonl");
+        let writer = PersistenceWriter::new(Duration::from_secs(60), 2, 10, false).start();
+
+        for line in ["a", "b", "c"] {
+            writer
+                .send(AppendLine {
+                    path: journal.clone(),
+                    line: line.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(fs::read_to_string(&journal).unwrap(), "a\nb\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,98 @@
+//! Registry of the actors of a process by well-known names, so an actor can reach another one without
+//! being given its address by a setter message after both are started.
+//! Every robot has its own registry, since the benchmark and the tests run several robots in a process.
+
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The OrderManager of the robot
+pub const ORDER_MANAGER: &str = "order_manager";
+/// The RobotConnectionHandler of the robot
+pub const RCH: &str = "rch";
+/// The RobotLeader the robot runs, only while it is the leader
+pub const LEADER: &str = "leader";
+
+/// Addresses of the actors by name, cloning it gives another handle to the same registry
+#[derive(Clone, Default)]
+pub struct Registry {
+    actors: Arc<Mutex<HashMap<&'static str, Box<dyn Any + Send>>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the actor under the name, replacing the one that had it
+    pub fn register<A: Actor>(&self, name: &'static str, addr: Addr<A>) {
+        if let Ok(mut actors) = self.actors.lock() {
+            actors.insert(name, Box::new(addr));
+        }
+    }
+
+    /// Forgets the actor of the name
+    pub fn unregister(&self, name: &'static str) {
+        if let Ok(mut actors) = self.actors.lock() {
+            actors.remove(name);
+        }
+    }
+
+    /// Returns the address of the actor of the name, None if there is none or it is another kind of actor
+    pub fn addr<A: Actor>(&self, name: &str) -> Option<Addr<A>> {
+        let actors = self.actors.lock().ok()?;
+        actors.get(name)?.downcast_ref::<Addr<A>>().cloned()
+    }
+
+    /// Returns the recipient of the messages `M` of the actor of the name
+    pub fn recipient<A, M>(&self, name: &str) -> Option<Recipient<M>>
+    where
+        A: Actor + Handler<M>,
+        A::Context: ToEnvelope<A, M>,
+        M: Message + Send + 'static,
+        M::Result: Send,
+    {
+        self.addr::<A>(name).map(Addr::recipient)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(usize);
+
+    impl Actor for Counter {
+        type Context = Context<Self>;
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "usize")]
+    struct Add(usize);
+
+    impl Handler<Add> for Counter {
+        type Result = usize;
+
+        fn handle(&mut self, msg: Add, _ctx: &mut Context<Self>) -> usize {
+            self.0 += msg.0;
+            self.0
+        }
+    }
+
+    #[actix::test]
+    async fn test_actors_are_found_by_name() {
+        let registry = Registry::new();
+        assert!(registry.addr::<Counter>(RCH).is_none());
+
+        registry.register(RCH, Counter(1).start());
+        let other = registry.clone();
+        let recipient = other.recipient::<Counter, Add>(RCH).unwrap();
+        assert_eq!(recipient.send(Add(2)).await.unwrap(), 3);
+        assert!(registry.addr::<Counter>(ORDER_MANAGER).is_none());
+
+        registry.unregister(RCH);
+        assert!(other.addr::<Counter>(RCH).is_none());
+    }
+}
@@ -1,11 +1,152 @@
+use std::net::{Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::common::error::FreddoError;
+use crate::common::simulation;
+use crate::config;
+
 pub const SCREEN_PREVIOUS: char = 's';
 pub const ROBOT: char = 'r';
 pub const SCREEN_NEXT: char = 'n';
 
+/// How long a connection to one of the addresses of a host is waited for before trying the next one
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Returns the port of the node with the given id, the base port plus the id.
+/// It is None if it would be past the last port, or if it would be 0, which binds a random port
+pub fn node_port(base: u16, id: usize) -> Option<u16> {
+    u16::try_from(id)
+        .ok()
+        .and_then(|id| base.checked_add(id))
+        .filter(|port| *port != 0)
+}
+
+/// Returns the address of the node with the given id, on the base port plus the id.
+/// The ports of the nodes of the cluster are checked when the config is loaded, a node out of range gets port 0
+pub fn node_addr(host: &str, base: u16, id: usize) -> String {
+    host_port(host, node_port(base, id).unwrap_or(0))
+}
+
+/// Returns the host without the brackets of an IPv6 address, in lowercase, so the same host is always written the same way
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    match unbracketed.parse::<Ipv6Addr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => unbracketed.to_lowercase(),
+    }
+}
+
+/// Joins a host and a port into an address. An IPv6 address goes between brackets,
+/// an IPv4 address or a hostname is written as it is
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = normalize_host(host);
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Resolves an address, a hostname can give several addresses of either family
+pub async fn resolve(addr: &str) -> Result<Vec<SocketAddr>, FreddoError> {
+    let addrs: Vec<SocketAddr> = lookup_host(addr)
+        .await
+        .map_err(|e| FreddoError::Connection(format!("Could not resolve {}: {}", addr, e)))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(FreddoError::Connection(format!(
+            "{} does not resolve to any address",
+            addr
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Connects to an address, trying every address its host resolves to until one answers within `CONNECT_TIMEOUT`
+pub async fn connect(addr: &str) -> Result<TcpStream, FreddoError> {
+    let mut last_error = None;
+    for socket in resolve(addr).await? {
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(socket)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e.to_string()),
+            Err(_) => last_error = Some(format!("{} did not answer", socket)),
+        }
+    }
+    let error = last_error.unwrap_or_default();
+    Err(FreddoError::Connection(format!(
+        "Could not connect to {}: {}",
+        addr, error
+    )))
+}
+
+/// Returns the address where the robot with the given id listens when it is the leader.
 pub fn id_to_leader_addr(id: usize) -> String {
-    "127.0.0.1:369".to_owned() + &*id.to_string()
+    config::get().network.leader_addr(id)
 }
 
+/// Returns the address of the screen with the given id.
 pub fn id_to_screen_addr(id: usize) -> String {
-    "127.0.0.1:700".to_owned() + &*id.to_string()
+    config::get().network.screen_addr(id)
+}
+
+/// Returns true if something that failed `attempt` times can be tried again, following the retry policy
+pub fn should_retry(attempt: u32) -> bool {
+    config::get().retry.should_retry(attempt)
+}
+
+/// Time to wait before retrying something that failed `attempt` times, following the retry policy.
+/// The jitter is reproducible in simulation mode
+pub fn retry_backoff(attempt: u32) -> Duration {
+    let mut rng = simulation::rng("RETRY", attempt as usize);
+    config::get().retry.delay(attempt, &mut rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_ports_past_the_range_are_not_given() {
+        assert_eq!(node_port(8070, 12), Some(8082));
+        assert_eq!(node_port(u16::MAX - 1, 1), Some(u16::MAX));
+        assert_eq!(node_port(u16::MAX, 1), None);
+        assert_eq!(node_port(0, 0), None);
+        assert_eq!(node_port(1, usize::MAX), None);
+        assert_eq!(node_addr("127.0.0.1", 9100, 10), "127.0.0.1:9110");
+    }
+
+    #[test]
+    fn test_ipv6_hosts_are_bracketed() {
+        assert_eq!(host_port("::1", 8070), "[::1]:8070");
+        assert_eq!(host_port("[::1]", 8070), "[::1]:8070");
+        assert_eq!(host_port("FE80:0:0::1", 80), "[fe80::1]:80");
+        assert_eq!(host_port("10.0.0.5", 80), "10.0.0.5:80");
+        assert_eq!(host_port("Robot-1.local", 80), "robot-1.local:80");
+        assert_eq!(node_addr("::1", 9100, 10), "[::1]:9110");
+    }
+
+    #[actix::test]
+    async fn test_addresses_of_every_family_are_resolved() {
+        let ipv6 = resolve(&host_port("::1", 8070)).await.unwrap();
+        assert_eq!(ipv6, vec!["[::1]:8070".parse().unwrap()]);
+        let ipv4 = resolve(&host_port("127.0.0.1", 8070)).await.unwrap();
+        assert_eq!(ipv4, vec!["127.0.0.1:8070".parse().unwrap()]);
+        let hostname = resolve(&host_port("localhost", 8070)).await.unwrap();
+        assert!(hostname.iter().all(|addr| addr.ip().is_loopback()));
+        assert!(resolve("not a host:80").await.is_err());
+    }
+
+    #[actix::test]
+    async fn test_a_hostname_is_connected_to() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accept = tokio::spawn(async move { listener.accept().await.map(|_| ()) });
+        assert!(connect(&host_port("localhost", port)).await.is_ok());
+        assert!(accept.await.unwrap().is_ok());
+    }
 }
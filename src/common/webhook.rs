@@ -0,0 +1,272 @@
+//! Webhook of the processes, so other systems, like a point of sale, can react to what happens without reading the output.
+//! Each event is posted as JSON to the configured URL, and posted again with the retry policy
+//! if the receiver can not be reached or answers with a server error.
+
+use crate::common::log;
+use crate::common::metrics;
+use crate::common::utils::{connect, retry_backoff, should_retry};
+use crate::config;
+use colored::Colorize;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header with the signature of the body, `sha256=` followed by the HMAC-SHA256 of the secret in hex
+pub const SIGNATURE_HEADER: &str = "X-Freddo-Signature";
+
+/// Biggest answer that is read, only its status line is used
+const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+
+/// Address, host and path of an `http://` URL
+#[derive(Debug, PartialEq)]
+struct Endpoint {
+    addr: String,
+    host: String,
+    path: String,
+}
+
+/// Splits a URL, only `http://` is supported and the port is 80 if it has none.
+/// An IPv6 host goes between brackets, its port is the one after the closing bracket
+fn parse_url(url: &str) -> Option<Endpoint> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let after_host = match (host.starts_with('['), host.rfind(']')) {
+        (true, Some(end)) => &host[end..],
+        (false, None) => host,
+        _ => return None,
+    };
+    let addr = match after_host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+    Some(Endpoint {
+        addr,
+        host: host.to_string(),
+        path: path.to_string(),
+    })
+}
+
+/// Returns the signature of the body with the secret, as it goes in the signature header
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    let mut signature = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{:02x}", byte);
+    }
+    signature
+}
+
+/// Posts the body to the endpoint and returns the status code of the answer
+async fn post(endpoint: &Endpoint, body: &str, signature: Option<&str>) -> Result<u16, String> {
+    let mut stream = connect(&endpoint.addr).await.map_err(|e| e.to_string())?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        endpoint.path,
+        endpoint.host,
+        body.len()
+    );
+    if let Some(signature) = signature {
+        request += &format!("{}: {}\r\n", SIGNATURE_HEADER, signature);
+    }
+    request += "\r\n";
+    request += body;
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut raw = Vec::new();
+    let mut buf = [0; 1024];
+    while !raw.contains(&b'\n') && raw.len() < MAX_RESPONSE_SIZE {
+        let read = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8_lossy(&raw)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| "Malformed answer".to_string())
+}
+
+/// Posts the event to the URL until it is taken or the retry policy gives up, `subject` names it in the logs.
+/// A client error is not retried, posting the same body again would get the same answer.
+/// Returns true if the event was taken
+pub async fn deliver<T: Serialize>(
+    url: &str,
+    secret: Option<&str>,
+    timeout: Duration,
+    event: &T,
+    subject: &str,
+) -> bool {
+    let endpoint = match parse_url(url) {
+        Some(endpoint) => endpoint,
+        None => {
+            log::error("WH", format!("Invalid webhook URL {}", url).red());
+            return false;
+        }
+    };
+    let body = match serde_json::to_string(event) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error("WH", format!("Could not encode {}: {}", subject, e).red());
+            return false;
+        }
+    };
+    let signature = secret.map(|secret| sign(secret, &body));
+
+    let mut attempt = 0;
+    loop {
+        let request = tokio::time::timeout(timeout, post(&endpoint, &body, signature.as_deref()));
+        let error = match request.await {
+            Ok(Ok(code)) if (200..300).contains(&code) => return true,
+            Ok(Ok(code)) if (400..500).contains(&code) => {
+                let line = format!("Webhook rejected {} with {}", subject, code);
+                log::error("WH", line.red());
+                return false;
+            }
+            Ok(Ok(code)) => format!("answered {}", code),
+            Ok(Err(e)) => e,
+            Err(_) => format!("did not answer in {} ms", timeout.as_millis()),
+        };
+        if !should_retry(attempt) {
+            let line = format!("Gave up posting {}, the webhook {}", subject, error);
+            log::error("WH", line.red());
+            return false;
+        }
+        let line = format!("Webhook {} for {}, retrying", error, subject);
+        log::warn("WH", line.yellow());
+        metrics::get().retried("webhook");
+        tokio::time::sleep(retry_backoff(attempt)).await;
+        attempt += 1;
+    }
+}
+
+/// Posts the event to the webhook of the config, in the background.
+/// Nothing is posted if there is no webhook
+pub fn send<T: Serialize + 'static>(event: T, subject: String) {
+    let webhook = &config::get().webhook;
+    let url = match &webhook.url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let secret = webhook.secret.clone();
+    let timeout = Duration::from_millis(webhook.timeout_ms);
+    actix::spawn(async move {
+        deliver(&url, secret.as_deref(), timeout, &event, &subject).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use tokio::net::TcpListener;
+
+    #[derive(Serialize, Deserialize)]
+    struct Event {
+        order_id: String,
+    }
+
+    fn event() -> Event {
+        Event {
+            order_id: "a".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_urls_are_split_with_the_default_port() {
+        assert_eq!(
+            parse_url("http://pos.local/hooks/orders"),
+            Some(Endpoint {
+                addr: "pos.local:80".to_string(),
+                host: "pos.local".to_string(),
+                path: "/hooks/orders".to_string(),
+            })
+        );
+        assert_eq!(parse_url("http://127.0.0.1:8080").unwrap().path, "/");
+        assert_eq!(parse_url("https://pos.local/"), None);
+    }
+
+    #[test]
+    fn test_ipv6_hosts_keep_their_brackets() {
+        assert_eq!(
+            parse_url("http://[::1]/hook"),
+            Some(Endpoint {
+                addr: "[::1]:80".to_string(),
+                host: "[::1]".to_string(),
+                path: "/hook".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_url("http://[::1]:8080/hook").unwrap().addr,
+            "[::1]:8080"
+        );
+        assert_eq!(parse_url("http://[::1/hook"), None);
+    }
+
+    #[test]
+    fn test_signature_is_the_hmac_of_the_body() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    /// Answers each request with the next status code and returns the requests it read
+    async fn receiver(codes: Vec<u16>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/orders", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for code in codes {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..read]).to_string());
+                let answer = format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", code);
+                stream.write_all(answer.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[actix::test]
+    async fn test_event_is_posted_again_after_a_server_error() {
+        let (url, handle) = receiver(vec![503, 200]).await;
+        let event = event();
+
+        assert!(deliver(&url, Some("secret"), Duration::from_secs(1), &event, "a").await);
+        let requests = handle.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let body = serde_json::to_string(&event).unwrap();
+        assert!(requests[1].starts_with("POST /orders HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with(&body));
+        let header = format!("{}: {}\r\n", SIGNATURE_HEADER, sign("secret", &body));
+        assert!(requests[1].contains(&header));
+    }
+
+    #[actix::test]
+    async fn test_client_errors_are_not_retried() {
+        let (url, handle) = receiver(vec![400]).await;
+
+        assert!(!deliver(&url, None, Duration::from_secs(1), &event(), "a").await);
+        assert_eq!(handle.await.unwrap().len(), 1);
+    }
+}
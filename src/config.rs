@@ -1,3 +0,0 @@
-pub const MAX_NUMBER_OF_ROBOTS: usize = 4;
-
-pub const MAX_NUMBER_OF_SCREENS: usize = 3;
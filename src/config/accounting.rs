@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_CHECK_INTERVAL_MS: u64 = 5000;
+pub const DEFAULT_CONFIRM_CHECKS: usize = 3;
+
+/// Configuration of the stock accounts kept by the leader.
+/// Every `check_interval_ms` milliseconds the grams it expects of each flavor are checked against the ones seen on
+/// its tokens, 0 disables the checks. A difference is only a violation once it stays the same for `confirm_checks` checks
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AccountingConfig {
+    pub check_interval_ms: u64,
+    pub confirm_checks: usize,
+}
+
+impl Default for AccountingConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_ms: DEFAULT_CHECK_INTERVAL_MS,
+            confirm_checks: DEFAULT_CONFIRM_CHECKS,
+        }
+    }
+}
+
+impl AccountingConfig {
+    /// Checks that a difference needs at least one check to be a violation
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.confirm_checks == 0 {
+            return Err(ConfigError::InvalidValue(
+                "accounting.confirm_checks must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
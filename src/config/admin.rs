@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::node_addr;
+use crate::config::network::DEFAULT_HOST;
+
+pub const DEFAULT_ROBOT_ADMIN_BASE_PORT: u16 = 9400;
+
+/// Configuration of the control socket of each robot.
+/// Each robot listens on the base port plus its id
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub robot_base_port: u16,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: DEFAULT_HOST.to_string(),
+            robot_base_port: DEFAULT_ROBOT_ADMIN_BASE_PORT,
+        }
+    }
+}
+
+impl AdminConfig {
+    /// Returns the address of the control socket of the robot with the given id
+    pub fn robot_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.robot_base_port, id)
+    }
+}
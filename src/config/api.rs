@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::node_addr;
+use crate::config::network::DEFAULT_HOST;
+
+pub const DEFAULT_SCREEN_API_BASE_PORT: u16 = 9500;
+
+/// Configuration of the HTTP API where each screen takes orders.
+/// Each screen listens on the base port plus its id
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub screen_base_port: u16,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: DEFAULT_HOST.to_string(),
+            screen_base_port: DEFAULT_SCREEN_API_BASE_PORT,
+        }
+    }
+}
+
+impl ApiConfig {
+    /// Returns the address of the API of the screen with the given id
+    pub fn screen_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.screen_base_port, id)
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::protocol::compression::Compression;
+
+pub const DEFAULT_FULL_EVERY: usize = 20;
+pub const DEFAULT_SCREEN_REPLICAS: usize = 1;
+pub const DEFAULT_SCREEN_COALESCE_MS: u64 = 100;
+
+/// Configuration of the backups.
+/// Between full backups the leader only sends to the robots what changed since the previous one,
+/// so one of every `full_every` backups is full. With `full_every` in 1 every backup is full.
+/// Each screen keeps its backup in the next `screen_replicas` screens of the ring, and sends in a single backup
+/// the changes it makes within `screen_coalesce_ms`. With `screen_coalesce_ms` in 0 each change is sent right away.
+/// The backups are compressed with `compression` when the process that gets them can read it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub full_every: usize,
+    pub screen_replicas: usize,
+    pub screen_coalesce_ms: u64,
+    pub compression: Compression,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            full_every: DEFAULT_FULL_EVERY,
+            screen_replicas: DEFAULT_SCREEN_REPLICAS,
+            screen_coalesce_ms: DEFAULT_SCREEN_COALESCE_MS,
+            compression: Compression::None,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// Checks that some backups are full and every screen has its backup somewhere
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.full_every == 0 {
+            return Err(ConfigError::InvalidValue(
+                "backup.full_every must be at least 1".to_string(),
+            ));
+        }
+        if self.screen_replicas == 0 {
+            return Err(ConfigError::InvalidValue(
+                "backup.screen_replicas must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_CHAOS_MAX_DELAY_MS: u64 = 500;
+
+/// Configuration of the faults injected to test how the cluster recovers, only used when built with the `chaos` feature.
+/// When it is enabled, every write of the connections is dropped, delayed up to `max_delay_ms`
+/// or has its socket closed with the given probabilities, and every message a connection actor receives
+/// can make it crash with `crash_probability`. The faults are reproducible in simulation mode
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub drop_probability: f64,
+    pub delay_probability: f64,
+    pub max_delay_ms: u64,
+    pub close_probability: f64,
+    pub crash_probability: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            drop_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay_ms: DEFAULT_CHAOS_MAX_DELAY_MS,
+            close_probability: 0.0,
+            crash_probability: 0.0,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Checks that every probability is between 0 and 1, and that a write does not get more than one fault
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let probabilities = [
+            ("drop_probability", self.drop_probability),
+            ("delay_probability", self.delay_probability),
+            ("close_probability", self.close_probability),
+            ("crash_probability", self.crash_probability),
+        ];
+        for (name, probability) in probabilities {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "chaos.{} must be between 0 and 1",
+                    name
+                )));
+            }
+        }
+        if self.drop_probability + self.delay_probability + self.close_probability > 1.0 {
+            return Err(ConfigError::InvalidValue(
+                "the write probabilities of chaos can not add up to more than 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
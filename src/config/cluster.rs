@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::config::flavors::FlavorCatalog;
+use crate::config::ConfigError;
+
+pub const DEFAULT_NUMBER_OF_ROBOTS: usize = 4;
+pub const DEFAULT_NUMBER_OF_SCREENS: usize = 3;
+pub const DEFAULT_SHARD_PORT_OFFSET: usize = 1000;
+
+/// Biggest cluster size accepted, the ids of the robots are sent as a single byte during the handshakes
+/// and the bytes from here on are used for other purposes, like the dashboard connection
+pub const MAX_CLUSTER_SIZE: usize = 100;
+
+/// Environment variable that overrides the number of robots of the config
+pub const ROBOTS_ENV_VAR: &str = "FREDDO_ROBOTS";
+
+/// Environment variable that overrides the number of screens of the config
+pub const SCREENS_ENV_VAR: &str = "FREDDO_SCREENS";
+
+/// Environment variable with the leader shard a process belongs to
+pub const SHARD_ENV_VAR: &str = "FREDDO_SHARD";
+
+/// Algorithm the robots follow to elect a leader, every robot of the cluster has to use the same one
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ElectionAlgorithm {
+    /// Every robot adds itself to the candidates, and the one that started the election chooses among all of them
+    #[default]
+    Ring,
+    /// A robot stronger than every candidate replaces them, so only the strongest one is passed around the ring
+    Bully,
+}
+
+/// Size of the cluster, the robots have ids from 0 to `robots - 1` and the screens from 0 to `screens - 1`.
+/// With `leader_shards` the flavors are split among several leaders: each shard is a ring of robots and screens
+/// of its own, with its own election and the tokens of its flavors. A process belongs to the shard given with
+/// `--shard` or `FREDDO_SHARD`, whose ports are the ones of the config plus `shard_port_offset` for each shard before it.
+/// A screen takes orders with the flavors of any shard, its leader passes each part of them to the leader of its shard
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub robots: usize,
+    pub screens: usize,
+    pub election: ElectionAlgorithm,
+    pub leader_shards: Vec<Vec<FlavorID>>,
+    pub shard_port_offset: usize,
+    #[serde(skip)]
+    pub shard: usize,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            robots: DEFAULT_NUMBER_OF_ROBOTS,
+            screens: DEFAULT_NUMBER_OF_SCREENS,
+            election: ElectionAlgorithm::default(),
+            leader_shards: Vec::new(),
+            shard_port_offset: DEFAULT_SHARD_PORT_OFFSET,
+            shard: 0,
+        }
+    }
+}
+
+impl ClusterConfig {
+    /// Checks that there is at least one node of each kind, that the ids and the shards fit in the handshakes,
+    /// that no shard is empty or shares a flavor with another one and that the process belongs to one of them
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (name, size) in [("robots", self.robots), ("screens", self.screens)] {
+            if size == 0 || size > MAX_CLUSTER_SIZE {
+                return Err(ConfigError::InvalidValue(format!(
+                    "cluster.{} must be between 1 and {}",
+                    name, MAX_CLUSTER_SIZE
+                )));
+            }
+        }
+        if self.shards() > MAX_CLUSTER_SIZE {
+            return Err(ConfigError::InvalidValue(format!(
+                "cluster.leader_shards: there can not be more than {} shards",
+                MAX_CLUSTER_SIZE
+            )));
+        }
+        for (shard, flavors) in self.leader_shards.iter().enumerate() {
+            if flavors.is_empty() {
+                return Err(ConfigError::InvalidValue(format!(
+                    "cluster.leader_shards: shard {} has no flavors",
+                    shard
+                )));
+            }
+            for (i, flavor) in flavors.iter().enumerate() {
+                if flavors[..i].contains(flavor) || self.shard_of(flavor) != Some(shard) {
+                    return Err(ConfigError::InvalidValue(format!(
+                        "cluster.leader_shards: {} is in more than one place",
+                        flavor
+                    )));
+                }
+            }
+        }
+        if self.shard >= self.shards() {
+            return Err(ConfigError::InvalidValue(format!(
+                "the shard must be lower than {}, the number of leader shards",
+                self.shards()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that every flavor of the catalog is in a shard and that every flavor of a shard is in the catalog
+    pub fn validate_shards(&self, catalog: &FlavorCatalog) -> Result<(), ConfigError> {
+        if self.leader_shards.is_empty() {
+            return Ok(());
+        }
+        if let Some(stock) = catalog
+            .flavors()
+            .iter()
+            .find(|stock| self.shard_of(&stock.flavor).is_none())
+        {
+            return Err(ConfigError::InvalidValue(format!(
+                "cluster.leader_shards: {} is not in any shard",
+                stock.flavor
+            )));
+        }
+        match self
+            .leader_shards
+            .iter()
+            .flatten()
+            .find(|flavor| catalog.initial_amount(flavor).is_none())
+        {
+            Some(flavor) => Err(ConfigError::InvalidValue(format!(
+                "cluster.leader_shards: {} is not in the catalog",
+                flavor
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how many leaders the flavors are split among, 1 without shards
+    pub fn shards(&self) -> usize {
+        self.leader_shards.len().max(1)
+    }
+
+    /// Returns the shard that leads the flavor, none if it is in none of them
+    pub fn shard_of(&self, flavor: &FlavorID) -> Option<usize> {
+        self.leader_shards
+            .iter()
+            .position(|flavors| flavors.contains(flavor))
+    }
+
+    /// Returns true if a flavor of the order is led by another shard, so its leader has to prepare it
+    pub fn needs_routing(&self, order: &Order) -> bool {
+        order.get_flavors().iter().any(|(flavor, _)| {
+            self.shard_of(flavor)
+                .is_some_and(|shard| shard != self.shard)
+        })
+    }
+
+    /// Returns the part of the order that each shard leads, by shard. A flavor in no shard goes in the part
+    /// of the shard of this process, whose leader rejects it
+    pub fn split(&self, order: &Order) -> Vec<(usize, Order)> {
+        (0..self.shards())
+            .filter_map(|shard| {
+                let part =
+                    order.only(|flavor| self.shard_of(flavor).unwrap_or(self.shard) == shard)?;
+                Some((shard, part))
+            })
+            .collect()
+    }
+
+    /// Returns the flavors led by the other shards
+    pub fn flavors_of_other_shards(&self) -> Vec<FlavorID> {
+        self.leader_shards
+            .iter()
+            .enumerate()
+            .filter(|(shard, _)| *shard != self.shard)
+            .flat_map(|(_, flavors)| flavors.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the number of the screen among the screens of every shard, so two shards never give
+    /// the same order id or pickup code
+    pub fn screen_number(&self, screen_id: usize) -> usize {
+        self.shard * self.screens + screen_id
+    }
+
+    /// Returns the screen id the leader of the shard takes when it connects to the leader of another one
+    /// to pass on the parts of its orders, the ids from `screens` on are not used by any screen
+    pub fn link_screen_id(&self, shard: usize) -> usize {
+        self.screens + shard
+    }
+
+    /// Replaces the sizes and the shard with the ones of the environment variables, if they are set
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(robots) = read_env_size(ROBOTS_ENV_VAR)? {
+            self.robots = robots;
+        }
+        if let Some(screens) = read_env_size(SCREENS_ENV_VAR)? {
+            self.screens = screens;
+        }
+        if let Some(shard) = read_env_size(SHARD_ENV_VAR)? {
+            self.shard = shard;
+        }
+        Ok(())
+    }
+}
+
+fn read_env_size(var: &str) -> Result<Option<usize>, ConfigError> {
+    match std::env::var(var) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidValue(format!("{} must be a number", var))),
+        Err(_) => Ok(None),
+    }
+}
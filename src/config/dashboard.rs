@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::host_port;
+use crate::config::network::DEFAULT_HOST;
+
+pub const DEFAULT_DASHBOARD_PORT: u16 = 9300;
+
+/// Configuration of the dashboard binary, the HTTP address it listens on
+/// and how often the page asks it for the state of the cluster
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct DashboardConfig {
+    pub host: String,
+    pub port: u16,
+    pub refresh_ms: u64,
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_DASHBOARD_PORT,
+            refresh_ms: 1000,
+        }
+    }
+}
+
+impl DashboardConfig {
+    /// Returns the address where the dashboard is served
+    pub fn addr(&self) -> String {
+        host_port(&self.host, self.port)
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_MAX_AGE_MS: u64 = 600_000;
+pub const DEFAULT_CHECK_INTERVAL_MS: u64 = 5000;
+
+/// Configuration of the results the leader could not send to their screens.
+/// Every `check_interval_ms` the leader tries again to send them, and a result that was tried `max_attempts` times,
+/// or that is waiting for more than `max_age_ms`, is moved to the dead letters, where it waits until it is replayed.
+/// A limit in 0 is no limit
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeadLetterConfig {
+    pub max_attempts: u32,
+    pub max_age_ms: u64,
+    pub check_interval_ms: u64,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            max_age_ms: DEFAULT_MAX_AGE_MS,
+            check_interval_ms: DEFAULT_CHECK_INTERVAL_MS,
+        }
+    }
+}
+
+impl DeadLetterConfig {
+    /// Returns true if the result has to be moved to the dead letters
+    pub fn is_dead(&self, attempts: u32, age_ms: u64) -> bool {
+        (self.max_attempts > 0 && attempts >= self.max_attempts)
+            || (self.max_age_ms > 0 && age_ms >= self.max_age_ms)
+    }
+
+    /// Checks that the results are not tried again in a busy loop
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.check_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "dead_letter.check_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
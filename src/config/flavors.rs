@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::flavor_id::FlavorID;
+use crate::config::ConfigError;
+
+/// Amount of grams each flavor starts with when it is not configured
+pub const DEFAULT_INITIAL_AMOUNT: usize = 4000;
+
+/// A flavor of the catalog with the grams the shop starts with.
+/// When the flavor has less than `low_watermark` grams left the leader raises an alert, and with `reject_when_low`
+/// it also rejects the new orders that need it. A `low_watermark` of 0 never raises it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FlavorStock {
+    pub flavor: FlavorID,
+    pub amount: usize,
+    #[serde(default)]
+    pub low_watermark: usize,
+    #[serde(default)]
+    pub reject_when_low: bool,
+}
+
+impl FlavorStock {
+    pub fn new(flavor: FlavorID, amount: usize) -> Self {
+        Self {
+            flavor,
+            amount,
+            low_watermark: 0,
+            reject_when_low: false,
+        }
+    }
+}
+
+/// Catalog of the flavors that are served, one token is created for each of them when the first leader starts
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct FlavorCatalog {
+    flavors: Vec<FlavorStock>,
+}
+
+impl Default for FlavorCatalog {
+    fn default() -> Self {
+        Self::new(vec![
+            FlavorStock::new(FlavorID::Chocolate, DEFAULT_INITIAL_AMOUNT - 2800),
+            FlavorStock::new(FlavorID::Vanilla, DEFAULT_INITIAL_AMOUNT),
+            FlavorStock::new(FlavorID::Strawberry, DEFAULT_INITIAL_AMOUNT),
+            FlavorStock::new(FlavorID::Mint, DEFAULT_INITIAL_AMOUNT),
+            FlavorStock::new(FlavorID::Pistachio, DEFAULT_INITIAL_AMOUNT),
+            FlavorStock::new(FlavorID::DulceDeLeche, DEFAULT_INITIAL_AMOUNT),
+            FlavorStock::new(FlavorID::Lemon, DEFAULT_INITIAL_AMOUNT),
+        ])
+    }
+}
+
+impl FlavorCatalog {
+    pub fn new(flavors: Vec<FlavorStock>) -> Self {
+        Self { flavors }
+    }
+
+    /// Gets all the flavors of the catalog
+    pub fn flavors(&self) -> &[FlavorStock] {
+        &self.flavors
+    }
+
+    /// Gets the initial amount of a flavor, if it is part of the catalog
+    pub fn initial_amount(&self, flavor: &FlavorID) -> Option<usize> {
+        self.flavors
+            .iter()
+            .find(|stock| stock.flavor == *flavor)
+            .map(|stock| stock.amount)
+    }
+
+    /// Returns the catalog with only the given flavors, in the order of the catalog
+    pub fn only(&self, flavors: &[FlavorID]) -> FlavorCatalog {
+        let flavors = self
+            .flavors
+            .iter()
+            .filter(|stock| flavors.contains(&stock.flavor));
+        Self::new(flavors.cloned().collect())
+    }
+
+    /// Gets the flavors of the catalog that raise an alert when they run low
+    pub fn watermarks(&self) -> impl Iterator<Item = &FlavorStock> {
+        self.flavors.iter().filter(|stock| stock.low_watermark > 0)
+    }
+
+    /// Checks that the catalog is not empty, that no flavor is repeated and that a flavor only rejects orders with a watermark
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.flavors.is_empty() {
+            return Err(ConfigError::InvalidValue(
+                "The flavor catalog can not be empty".to_string(),
+            ));
+        }
+        for (i, stock) in self.flavors.iter().enumerate() {
+            if self.flavors[..i].iter().any(|s| s.flavor == stock.flavor) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "Flavor {} is repeated in the catalog",
+                    stock.flavor
+                )));
+            }
+            if stock.reject_when_low && stock.low_watermark == 0 {
+                return Err(ConfigError::InvalidValue(format!(
+                    "Flavor {} rejects orders when low, but it has no low_watermark",
+                    stock.flavor
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_is_parsed_from_a_list() {
+        let catalog: FlavorCatalog = serde_json::from_str(
+            r#"[{"flavor": "Mint", "amount": 100}, {"flavor": "Lemon", "amount": 200}, {"flavor": "Peach", "amount": 300}]"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.initial_amount(&FlavorID::Mint), Some(100));
+        assert_eq!(catalog.initial_amount(&FlavorID::Lemon), Some(200));
+        assert_eq!(
+            catalog.initial_amount(&FlavorID::Custom("Peach".to_string())),
+            Some(300)
+        );
+        assert_eq!(catalog.initial_amount(&FlavorID::Chocolate), None);
+    }
+
+    #[test]
+    fn test_repeated_flavor_is_invalid() {
+        let catalog = FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 100),
+            FlavorStock::new(FlavorID::Mint, 200),
+        ]);
+        assert!(catalog.validate().is_err());
+    }
+
+    #[test]
+    fn test_rejecting_without_a_watermark_is_invalid() {
+        let catalog: FlavorCatalog = serde_json::from_str(
+            r#"[{"flavor": "Mint", "amount": 100, "low_watermark": 20}, {"flavor": "Lemon", "amount": 200, "reject_when_low": true}]"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.watermarks().count(), 1);
+        assert!(catalog.validate().is_err());
+    }
+
+    #[test]
+    fn test_empty_catalog_is_invalid() {
+        assert!(FlavorCatalog::new(vec![]).validate().is_err());
+    }
+}
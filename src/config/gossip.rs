@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+/// Configuration of the gossip between the screens.
+/// Every `interval_ms` each screen tells the next one which screens it knows are alive and who the leader is.
+/// A screen that is not heard of, directly or through others, for `member_timeout_ms` is no longer alive
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct GossipConfig {
+    pub interval_ms: u64,
+    pub member_timeout_ms: u64,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 1000,
+            member_timeout_ms: 5000,
+        }
+    }
+}
+
+impl GossipConfig {
+    /// Checks that the gossip is sent and that a screen is not dropped between two gossips
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "gossip.interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.member_timeout_ms <= self.interval_ms {
+            return Err(ConfigError::InvalidValue(
+                "gossip.member_timeout_ms must be greater than gossip.interval_ms".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
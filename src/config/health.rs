@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::node_addr;
+use crate::config::network::DEFAULT_HOST;
+use crate::config::ConfigError;
+
+pub const DEFAULT_ROBOT_HEALTH_BASE_PORT: u16 = 9600;
+pub const DEFAULT_SCREEN_HEALTH_BASE_PORT: u16 = 9700;
+pub const DEFAULT_HEALTH_BEAT_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_HEALTH_STALE_MS: u64 = 5000;
+
+/// Configuration of the HTTP endpoint where each process tells if it is alive and ready.
+/// Each process listens on the base port of its kind plus its id.
+/// The main actor of the process beats every `beat_interval_ms`, and the process is taken as wedged
+/// when it did not beat for `stale_ms`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub robot_base_port: u16,
+    pub screen_base_port: u16,
+    pub beat_interval_ms: u64,
+    pub stale_ms: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: DEFAULT_HOST.to_string(),
+            robot_base_port: DEFAULT_ROBOT_HEALTH_BASE_PORT,
+            screen_base_port: DEFAULT_SCREEN_HEALTH_BASE_PORT,
+            beat_interval_ms: DEFAULT_HEALTH_BEAT_INTERVAL_MS,
+            stale_ms: DEFAULT_HEALTH_STALE_MS,
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Returns the address of the health endpoint of the robot with the given id
+    pub fn robot_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.robot_base_port, id)
+    }
+
+    /// Returns the address of the health endpoint of the screen with the given id
+    pub fn screen_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.screen_base_port, id)
+    }
+
+    /// Checks that the process beats, and more often than it is taken as wedged
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.beat_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "health.beat_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.stale_ms <= self.beat_interval_ms {
+            return Err(ConfigError::InvalidValue(
+                "health.stale_ms must be longer than health.beat_interval_ms".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
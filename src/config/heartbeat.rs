@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Configuration of the heartbeats each robot sends to the next robot of the ring.
+/// A robot that does not hear from its previous robot for `timeout_ms` drops the connection.
+/// The leader pings each screen with the same interval, and a screen that does not answer within the timeout is dead
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 1000,
+            timeout_ms: 5000,
+        }
+    }
+}
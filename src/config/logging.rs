@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::common::log::Level;
+
+/// Format in which the log lines are written
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colored lines meant to be read on a terminal
+    #[default]
+    Pretty,
+    /// One JSON object per line, meant to be piped to other tools
+    Json,
+}
+
+pub const DEFAULT_DEDUP_WINDOW_MS: u64 = 5000;
+
+/// Configuration of the logs of each process.
+/// `targets` overrides the level of specific actors, e.g. `{"RCH": "Debug"}`.
+/// A line equal to the last one of its target within `dedup_window_ms` is not written, it is counted and
+/// summed up in a single line. `dedup_targets` overrides the window of specific actors, `0` turns it off
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: Level,
+    pub format: LogFormat,
+    pub file: Option<String>,
+    pub targets: HashMap<String, Level>,
+    pub dedup_window_ms: u64,
+    pub dedup_targets: HashMap<String, u64>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: Level::default(),
+            format: LogFormat::default(),
+            file: None,
+            targets: HashMap::new(),
+            dedup_window_ms: DEFAULT_DEDUP_WINDOW_MS,
+            dedup_targets: HashMap::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Gets how long the repeated lines of the target are collapsed, zero if they are all written
+    pub fn dedup_window(&self, target: &str) -> Duration {
+        let window_ms = self
+            .dedup_targets
+            .get(target)
+            .copied()
+            .unwrap_or(self.dedup_window_ms);
+        Duration::from_millis(window_ms)
+    }
+
+    /// Checks if a line of the given level and target has to be written
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let max_level = self.targets.get(target).copied().unwrap_or(self.level);
+        level <= max_level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_level_overrides_global_level() {
+        let logging: LoggingConfig =
+            serde_json::from_str(r#"{"level": "Warn", "targets": {"RCH": "Debug"}}"#).unwrap();
+        assert!(logging.enabled("RCH", Level::Debug));
+        assert!(!logging.enabled("RL", Level::Info));
+        assert!(logging.enabled("RL", Level::Error));
+    }
+
+    #[test]
+    fn test_target_dedup_window_overrides_global_window() {
+        let logging: LoggingConfig =
+            serde_json::from_str(r#"{"dedup_targets": {"RCH": 0, "RL": 200}}"#).unwrap();
+        assert_eq!(logging.dedup_window("RCH"), Duration::ZERO);
+        assert_eq!(logging.dedup_window("RL"), Duration::from_millis(200));
+        assert_eq!(
+            logging.dedup_window("GTW"),
+            Duration::from_millis(DEFAULT_DEDUP_WINDOW_MS)
+        );
+    }
+}
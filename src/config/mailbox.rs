@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_CAPACITY: usize = 16;
+pub const DEFAULT_SAMPLE_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_WARN_DELAY_MS: u64 = 250;
+
+/// Configuration of the mailboxes of the busiest actors, the robot leader, the order manager and the payments gateway.
+/// Each one takes up to `capacity` messages sent without waiting; past that, a backup sent to it is dropped,
+/// since a newer one follows. Every `sample_interval_ms` (0 disables it) a probe is sent to each of them,
+/// and if it waits more than `warn_delay_ms` in the mailbox the actor is taken as overloaded
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MailboxConfig {
+    pub capacity: usize,
+    pub sample_interval_ms: u64,
+    pub warn_delay_ms: u64,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            sample_interval_ms: DEFAULT_SAMPLE_INTERVAL_MS,
+            warn_delay_ms: DEFAULT_WARN_DELAY_MS,
+        }
+    }
+}
+
+impl MailboxConfig {
+    /// Checks that every mailbox takes at least one message
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.capacity == 0 {
+            return Err(ConfigError::InvalidValue(
+                "mailbox.capacity must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_DEFROST_MS: u64 = 3000;
+
+/// Configuration of the defrost windows of the robots.
+/// Every `defrost_every_ms` milliseconds a robot stops scooping for `defrost_ms` milliseconds,
+/// the leader gives it no orders meanwhile. With `defrost_every_ms` in 0 the robots never defrost
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub defrost_every_ms: u64,
+    pub defrost_ms: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            defrost_every_ms: 0,
+            defrost_ms: DEFAULT_DEFROST_MS,
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    /// Checks that a robot that defrosts is not always defrosting
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.defrost_every_ms == 0 {
+            return Ok(());
+        }
+        if self.defrost_ms == 0 || self.defrost_ms >= self.defrost_every_ms {
+            return Err(ConfigError::InvalidValue(
+                "maintenance.defrost_ms must be more than 0 and less than maintenance.defrost_every_ms".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
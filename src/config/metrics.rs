@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::node_addr;
+use crate::config::network::DEFAULT_HOST;
+
+pub const DEFAULT_ROBOT_METRICS_BASE_PORT: u16 = 9100;
+pub const DEFAULT_SCREEN_METRICS_BASE_PORT: u16 = 9200;
+
+/// Configuration of the HTTP endpoint where each process exposes its metrics.
+/// Each process listens on the base port of its kind plus its id
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub robot_base_port: u16,
+    pub screen_base_port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: DEFAULT_HOST.to_string(),
+            robot_base_port: DEFAULT_ROBOT_METRICS_BASE_PORT,
+            screen_base_port: DEFAULT_SCREEN_METRICS_BASE_PORT,
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Returns the address of the metrics endpoint of the robot with the given id
+    pub fn robot_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.robot_base_port, id)
+    }
+
+    /// Returns the address of the metrics endpoint of the screen with the given id
+    pub fn screen_addr(&self, id: usize) -> String {
+        node_addr(&self.host, self.screen_base_port, id)
+    }
+}
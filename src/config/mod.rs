@@ -0,0 +1,794 @@
+//! This module contains the configuration of the system.
+//! The configuration can be loaded at startup from a JSON file, whose path is given
+//! with the `--config` flag or the `FREDDO_CONFIG` environment variable.
+//! If no file is given, the default values are used.
+
+pub mod accounting;
+pub mod admin;
+pub mod api;
+pub mod backup;
+pub mod chaos;
+pub mod cluster;
+pub mod dashboard;
+pub mod dead_letter;
+pub mod flavors;
+pub mod gossip;
+pub mod health;
+pub mod heartbeat;
+pub mod logging;
+pub mod mailbox;
+pub mod maintenance;
+pub mod metrics;
+pub mod network;
+pub mod orders;
+pub mod payments;
+pub mod persistence;
+pub mod report;
+pub mod restock;
+pub mod retry;
+pub mod scoops;
+pub mod security;
+pub mod simulation;
+pub mod sizes;
+pub mod tenants;
+pub mod tokens;
+pub mod trace;
+pub mod webhook;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::common::utils::{host_port, node_port, normalize_host};
+use crate::config::accounting::AccountingConfig;
+use crate::config::admin::AdminConfig;
+use crate::config::api::ApiConfig;
+use crate::config::backup::BackupConfig;
+use crate::config::chaos::ChaosConfig;
+use crate::config::cluster::ClusterConfig;
+use crate::config::dashboard::DashboardConfig;
+use crate::config::dead_letter::DeadLetterConfig;
+use crate::config::flavors::FlavorCatalog;
+use crate::config::gossip::GossipConfig;
+use crate::config::health::HealthConfig;
+use crate::config::heartbeat::HeartbeatConfig;
+use crate::config::logging::LoggingConfig;
+use crate::config::mailbox::MailboxConfig;
+use crate::config::maintenance::MaintenanceConfig;
+use crate::config::metrics::MetricsConfig;
+use crate::config::network::NetworkConfig;
+use crate::config::orders::OrdersConfig;
+use crate::config::payments::PaymentsConfig;
+use crate::config::persistence::PersistenceConfig;
+use crate::config::report::ReportConfig;
+use crate::config::restock::RestockConfig;
+use crate::config::retry::RetryPolicy;
+use crate::config::scoops::ScoopsConfig;
+use crate::config::security::SecurityConfig;
+use crate::config::simulation::SimulationConfig;
+use crate::config::sizes::SizesConfig;
+use crate::config::tenants::TenantsConfig;
+use crate::config::tokens::TokensConfig;
+use crate::config::trace::TraceConfig;
+use crate::config::webhook::WebhookConfig;
+
+/// Environment variable with the path of the configuration file
+pub const CONFIG_ENV_VAR: &str = "FREDDO_CONFIG";
+
+/// CLI flag with the path of the configuration file
+pub const CONFIG_FLAG: &str = "--config";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Error type for the configuration loading
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    CouldNotReadFile(String),
+    ErrorParsing(String),
+    MissingFlagValue(String),
+    InvalidValue(String),
+    AlreadyLoaded,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::CouldNotReadFile(err) => write!(f, "Could not read config file: {}", err),
+            ConfigError::ErrorParsing(err) => write!(f, "Could not parse config file: {}", err),
+            ConfigError::MissingFlagValue(flag) => write!(f, "Missing value for flag {}", flag),
+            ConfigError::InvalidValue(err) => write!(f, "Invalid configuration: {}", err),
+            ConfigError::AlreadyLoaded => write!(f, "The configuration was already loaded"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Struct with all the configurable values of the system
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub cluster: ClusterConfig,
+    pub network: NetworkConfig,
+    pub flavors: FlavorCatalog,
+    pub persistence: PersistenceConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub restock: RestockConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub orders: OrdersConfig,
+    pub dashboard: DashboardConfig,
+    pub simulation: SimulationConfig,
+    pub admin: AdminConfig,
+    pub tokens: TokensConfig,
+    pub scoops: ScoopsConfig,
+    pub api: ApiConfig,
+    pub retry: RetryPolicy,
+    pub gossip: GossipConfig,
+    pub trace: TraceConfig,
+    pub security: SecurityConfig,
+    pub chaos: ChaosConfig,
+    pub webhook: WebhookConfig,
+    pub backup: BackupConfig,
+    pub maintenance: MaintenanceConfig,
+    pub dead_letter: DeadLetterConfig,
+    pub report: ReportConfig,
+    pub mailbox: MailboxConfig,
+    pub sizes: SizesConfig,
+    pub accounting: AccountingConfig,
+    pub payments: PaymentsConfig,
+    pub health: HealthConfig,
+    pub tenants: TenantsConfig,
+}
+
+impl Config {
+    /// Parses a configuration from a JSON string
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let config: Config =
+            serde_json::from_str(json).map_err(|err| ConfigError::ErrorParsing(err.to_string()))?;
+        config.cluster.validate()?;
+        config.network.validate()?;
+        config.flavors.validate()?;
+        config.cluster.validate_shards(&config.flavors)?;
+        config.orders.validate()?;
+        config.tokens.validate()?;
+        config.scoops.validate()?;
+        config.retry.validate()?;
+        config.gossip.validate()?;
+        config.trace.validate()?;
+        config.security.validate()?;
+        config.chaos.validate()?;
+        config.simulation.validate()?;
+        config.persistence.validate()?;
+        config.webhook.validate()?;
+        config.backup.validate()?;
+        config.maintenance.validate()?;
+        config.dead_letter.validate()?;
+        config.report.validate()?;
+        config.mailbox.validate()?;
+        config.sizes.validate()?;
+        config.accounting.validate()?;
+        config.payments.validate()?;
+        config.health.validate()?;
+        config.tenants.validate()?;
+        config.validate_ports()?;
+        Ok(config)
+    }
+
+    /// Checks that every node of the cluster gets a port for each of its sockets, and that no two sockets
+    /// share a port of the same host, counting the ones of every leader shard. The sockets of the metrics,
+    /// the health endpoint, the control socket and the API are only checked if they are enabled
+    pub fn validate_ports(&self) -> Result<(), ConfigError> {
+        let shards = self.cluster.shards();
+        let mut sockets: Vec<(String, String, Option<u16>)> = Vec::new();
+        for shard in 0..shards {
+            let config = self.clone().for_shard(shard);
+            for (name, host, port) in config.sockets() {
+                let name = match shards {
+                    1 => name,
+                    _ => format!("{} of shard {}", name, shard),
+                };
+                sockets.push((name, host, port));
+            }
+        }
+
+        let mut used: HashMap<(String, u16), String> = HashMap::new();
+        for (name, host, port) in sockets {
+            let port = port.ok_or_else(|| {
+                ConfigError::InvalidValue(format!(
+                    "the {} has no port, its base port plus its id is past {}",
+                    name,
+                    u16::MAX
+                ))
+            })?;
+            let host = normalize_host(&host);
+            if let Some(other) = used.insert((host.clone(), port), name.clone()) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "the {} and the {} both use {}",
+                    other,
+                    name,
+                    host_port(&host, port)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the name, the host and the port of every socket of the nodes of the cluster
+    fn sockets(&self) -> Vec<(String, String, Option<u16>)> {
+        let mut sockets = Vec::new();
+        for id in 0..self.cluster.robots {
+            let robot = self.network.robot(id);
+            let ports = [robot.port, robot.leader_port].map(|port| Some(port).filter(|p| *p != 0));
+            sockets.push((format!("robot {}", id), robot.host.clone(), ports[0]));
+            sockets.push((format!("leader of robot {}", id), robot.host, ports[1]));
+            if self.metrics.enabled {
+                let port = node_port(self.metrics.robot_base_port, id);
+                let name = format!("metrics of robot {}", id);
+                sockets.push((name, self.metrics.host.clone(), port));
+            }
+            if self.health.enabled {
+                let port = node_port(self.health.robot_base_port, id);
+                let name = format!("health endpoint of robot {}", id);
+                sockets.push((name, self.health.host.clone(), port));
+            }
+            if self.admin.enabled {
+                let port = node_port(self.admin.robot_base_port, id);
+                let name = format!("control socket of robot {}", id);
+                sockets.push((name, self.admin.host.clone(), port));
+            }
+        }
+        for id in 0..self.cluster.screens {
+            let screen = self.network.screen(id);
+            let port = Some(screen.port).filter(|p| *p != 0);
+            sockets.push((format!("screen {}", id), screen.host, port));
+            if self.metrics.enabled {
+                let port = node_port(self.metrics.screen_base_port, id);
+                let name = format!("metrics of screen {}", id);
+                sockets.push((name, self.metrics.host.clone(), port));
+            }
+            if self.health.enabled {
+                let port = node_port(self.health.screen_base_port, id);
+                let name = format!("health endpoint of screen {}", id);
+                sockets.push((name, self.health.host.clone(), port));
+            }
+            if self.api.enabled {
+                let port = node_port(self.api.screen_base_port, id);
+                let name = format!("API of screen {}", id);
+                sockets.push((name, self.api.host.clone(), port));
+            }
+        }
+        sockets
+    }
+
+    /// Returns the config of the processes of a leader shard, from the config as it was loaded.
+    /// The catalog only has the flavors of the shard, every port is moved `shard_port_offset` for each shard
+    /// before it and the data and the files of the shard are kept apart. Shard 0 keeps the ports and files of the config
+    pub fn for_shard(mut self, shard: usize) -> Config {
+        self.cluster.shard = shard;
+        if let Some(flavors) = self.cluster.leader_shards.get(shard) {
+            self.flavors = self.flavors.only(flavors);
+        }
+        if shard == 0 {
+            return self;
+        }
+        let offset = self.cluster.shard_port_offset;
+        let moved = |port: u16| port_of_shard(port, 0, shard, offset);
+        self.network.robots = (0..self.cluster.robots)
+            .map(|id| self.network.robot(id))
+            .map(|mut robot| {
+                (robot.port, robot.leader_port) = (moved(robot.port), moved(robot.leader_port));
+                robot
+            })
+            .collect();
+        self.network.screens = (0..self.cluster.screens)
+            .map(|id| self.network.screen(id))
+            .map(|mut screen| {
+                screen.port = moved(screen.port);
+                screen
+            })
+            .collect();
+        for port in [
+            &mut self.metrics.robot_base_port,
+            &mut self.metrics.screen_base_port,
+            &mut self.health.robot_base_port,
+            &mut self.health.screen_base_port,
+            &mut self.admin.robot_base_port,
+            &mut self.api.screen_base_port,
+            &mut self.dashboard.port,
+        ] {
+            *port = moved(*port);
+        }
+        let dir = Path::new(&self.persistence.data_dir).join(format!("shard_{}", shard));
+        self.persistence.data_dir = dir.to_string_lossy().to_string();
+        self.report.file = file_of_shard(&self.report.file, shard);
+        for file in [&mut self.trace.file, &mut self.logging.file]
+            .into_iter()
+            .flatten()
+        {
+            *file = file_of_shard(file, shard);
+        }
+        self
+    }
+
+    /// Returns where each robot of the leader shard listens when it leads it, from the config of the shard of this process
+    pub fn leader_addrs_of_shard(&self, shard: usize) -> Vec<String> {
+        let offset = self.cluster.shard_port_offset;
+        (0..self.cluster.robots)
+            .map(|id| self.network.robot(id))
+            .map(|robot| {
+                let port = port_of_shard(robot.leader_port, self.cluster.shard, shard, offset);
+                host_port(&robot.host, port)
+            })
+            .collect()
+    }
+
+    /// Reads and parses a configuration file
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| ConfigError::CouldNotReadFile(format!("{}: {}", path, err)))?;
+        Self::from_json(&json)
+    }
+}
+
+/// Moves a port of the shard `from` to the one it has in the shard `to`, a port that is 0 or out of range is 0
+fn port_of_shard(port: u16, from: usize, to: usize, offset: usize) -> u16 {
+    if port == 0 {
+        return 0;
+    }
+    (port as usize)
+        .checked_add(to.saturating_mul(offset))
+        .and_then(|port| port.checked_sub(from.saturating_mul(offset)))
+        .and_then(|port| u16::try_from(port).ok())
+        .unwrap_or(0)
+}
+
+/// Returns the path of a file of the shard, with the shard before the extension
+fn file_of_shard(path: &str, shard: usize) -> String {
+    let path = Path::new(path);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => format!(
+            "{}_shard{}.{}",
+            stem.to_string_lossy(),
+            shard,
+            extension.to_string_lossy()
+        ),
+        _ => format!("{}_shard{}", path.to_string_lossy(), shard),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// Sets the global configuration, it can only be done once
+pub fn init(config: Config) -> Result<(), ConfigError> {
+    CONFIG.set(config).map_err(|_| ConfigError::AlreadyLoaded)
+}
+
+/// Gets the global configuration, if none was loaded it uses the default one
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Returns how many robots the cluster has
+pub fn number_of_robots() -> usize {
+    get().cluster.robots
+}
+
+/// Returns how many screens the cluster has
+pub fn number_of_screens() -> usize {
+    get().cluster.screens
+}
+
+/// Loads the configuration given by the `--config` flag or the `FREDDO_CONFIG` environment variable.
+/// Returns the arguments without the config flag, so the binaries can keep parsing their positional arguments.
+pub fn init_from_args(args: &[String]) -> Result<Vec<String>, ConfigError> {
+    let mut remaining = Vec::new();
+    let mut path = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == CONFIG_FLAG {
+            match iter.next() {
+                Some(value) => path = Some(value.clone()),
+                None => return Err(ConfigError::MissingFlagValue(CONFIG_FLAG.to_string())),
+            }
+        } else {
+            remaining.push(arg.clone());
+        }
+    }
+
+    init_from_path(path, None)?;
+    Ok(remaining)
+}
+
+/// Loads the configuration of the given file, or of the `FREDDO_CONFIG` environment variable if none is given.
+/// The default one is loaded if there is neither. The process belongs to the given leader shard,
+/// or to the one of the `FREDDO_SHARD` environment variable, and it gets the config of that shard
+pub fn init_from_path(path: Option<String>, shard: Option<usize>) -> Result<(), ConfigError> {
+    let mut config = match path.or_else(|| std::env::var(CONFIG_ENV_VAR).ok()) {
+        Some(path) => Config::from_file(&path)?,
+        None => Config::default(),
+    };
+    config.cluster.apply_env()?;
+    if let Some(shard) = shard {
+        config.cluster.shard = shard;
+    }
+    config.cluster.validate()?;
+    config.validate_ports()?;
+    let shard = config.cluster.shard;
+    init(config.for_shard(shard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::{Order, Scoop};
+
+    #[test]
+    fn test_empty_json_uses_defaults() {
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_invalid_json_fails() {
+        let config = Config::from_json("{ network: ");
+        assert!(matches!(config, Err(ConfigError::ErrorParsing(_))));
+    }
+
+    #[test]
+    fn test_invalid_flavor_catalog_fails() {
+        let config = Config::from_json(r#"{"flavors": []}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_zero_concurrent_orders_fails() {
+        let config = Config::from_json(r#"{"orders": {"max_concurrent": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_dashboard_port_is_read() {
+        let config = Config::from_json(r#"{"dashboard": {"port": 8080}}"#).unwrap();
+        assert_eq!(config.dashboard.addr(), "127.0.0.1:8080");
+        assert_eq!(config.dashboard.refresh_ms, 1000);
+    }
+
+    #[test]
+    fn test_token_owners_are_read() {
+        let config = Config::from_json(
+            r#"{"tokens": {"start": "Owners", "owners": {"Mint": 1}, "warm_up_ms": 200}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.tokens.start, tokens::TokenStart::Owners);
+        assert_eq!(config.tokens.owners.get("Mint"), Some(&1));
+        assert_eq!(config.tokens.warm_up_ms, 200);
+        let config = Config::from_json(r#"{"tokens": {"start": "Owners"}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_order_id_scheme_is_read() {
+        let config = Config::from_json(r#"{"orders": {"id_scheme": "screenid-seq"}}"#).unwrap();
+        assert_eq!(config.orders.id_scheme, orders::OrderIdScheme::ScreenSeq);
+        let config = Config::from_json("{}").unwrap();
+        assert_eq!(config.orders.id_scheme, orders::OrderIdScheme::Uuid);
+        assert!(Config::from_json(r#"{"orders": {"id_scheme": "seq"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_dead_letter_limits_are_read() {
+        let config =
+            Config::from_json(r#"{"dead_letter": {"max_attempts": 0, "max_age_ms": 1000}}"#)
+                .unwrap();
+        assert!(!config.dead_letter.is_dead(100, 999));
+        assert!(config.dead_letter.is_dead(0, 1000));
+        assert!(DeadLetterConfig::default().is_dead(5, 0));
+        let config = Config::from_json(r#"{"dead_letter": {"check_interval_ms": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_screen_replicas_are_read() {
+        let config = Config::from_json(r#"{"backup": {"screen_replicas": 2}}"#).unwrap();
+        assert_eq!(config.backup.screen_replicas, 2);
+        assert_eq!(config.backup.full_every, backup::DEFAULT_FULL_EVERY);
+        let config = Config::from_json(r#"{"backup": {"screen_replicas": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_report_format_is_read() {
+        let config = Config::from_json(r#"{"report": {"format": "csv"}}"#).unwrap();
+        assert_eq!(config.report.format, report::ReportFormat::Csv);
+        assert!(config.report.on_shutdown);
+        let config = Config::from_json(r#"{"report": {"file": ""}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_mailbox_without_capacity_fails() {
+        let config = Config::from_json(r#"{"mailbox": {"warn_delay_ms": 100}}"#).unwrap();
+        assert_eq!(config.mailbox.warn_delay_ms, 100);
+        assert_eq!(config.mailbox.capacity, mailbox::DEFAULT_CAPACITY);
+        let config = Config::from_json(r#"{"mailbox": {"capacity": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_empty_batches_fail() {
+        let config = Config::from_json(r#"{"orders": {"batch_size": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_stream_chunks_over_the_waiting_limit_fail() {
+        let json = r#"{"orders": {"stream_chunk": 50, "stream_max_waiting": 10}}"#;
+        let config = Config::from_json(json);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+        let json = r#"{"orders": {"stream_chunk": 50}}"#;
+        assert_eq!(Config::from_json(json).unwrap().orders.stream_chunk, 50);
+    }
+
+    #[test]
+    fn test_cluster_size_is_read_and_checked() {
+        let config = Config::from_json(r#"{"cluster": {"robots": 6}}"#).unwrap();
+        assert_eq!(config.cluster.robots, 6);
+        assert_eq!(config.cluster.screens, 3);
+
+        let config = Config::from_json(r#"{"cluster": {"robots": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_jam_probability_over_one_fails() {
+        let config = Config::from_json(r#"{"scoops": {"jam_probability": 1.5}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_shrinking_retry_delays_fail() {
+        let config = Config::from_json(r#"{"retry": {"backoff_factor": 0.5}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_scoop_speed_of_each_robot_is_read() {
+        let config =
+            Config::from_json(r#"{"scoops": {"ms_per_gram": 8, "robots_ms_per_gram": {"2": 20}}}"#)
+                .unwrap();
+        assert_eq!(config.scoops.ms_per_gram_of(1), 8);
+        assert_eq!(config.scoops.ms_per_gram_of(2), 20);
+
+        let config = Config::from_json(r#"{"scoops": {"robots_ms_per_gram": {"1": 0}}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_gossip_slower_than_its_timeout_fails() {
+        let config = Config::from_json(r#"{"gossip": {"interval_ms": 5000}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_shops_sharing_a_screen_fail() {
+        let json = r#"{"tenants": {"shops": [
+            {"id": "centro", "screens": [0, 1], "quotas": {"Mint": 5000}},
+            {"id": "palermo", "screens": [2]}
+        ]}}"#;
+        let tenants = Config::from_json(json).unwrap().tenants;
+        assert_eq!(tenants.shop_of(1), Some("centro"));
+        assert_eq!(tenants.shop_of(3), None);
+        assert_eq!(tenants.quota("centro", "Mint"), Some(5000));
+        assert_eq!(tenants.quota("palermo", "Mint"), None);
+
+        let json =
+            r#"{"tenants": {"shops": [{"id": "a", "screens": [0]}, {"id": "b", "screens": [0]}]}}"#;
+        let config = Config::from_json(json);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_health_beating_slower_than_it_goes_stale_fails() {
+        let config = Config::from_json(r#"{"health": {"beat_interval_ms": 5000}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_chaos_faults_over_one_fail() {
+        let config =
+            Config::from_json(r#"{"chaos": {"drop_probability": 0.6, "close_probability": 0.6}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_virtual_time_outside_the_simulation_fails() {
+        let config = Config::from_json(r#"{"simulation": {"virtual_time": true}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+        let config = Config::from_json(r#"{"simulation": {"enabled": true, "time_scale": 0}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_webhook_without_http_url_fails() {
+        let config = Config::from_json(r#"{"webhook": {"url": "https://pos.local/orders"}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_defrost_as_long_as_its_period_fails() {
+        let config =
+            Config::from_json(r#"{"maintenance": {"defrost_every_ms": 3000, "defrost_ms": 3000}}"#);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_ports_past_the_range_fail() {
+        let config = Config::from_json(
+            r#"{"cluster": {"robots": 20}, "admin": {"enabled": true, "robot_base_port": 65530}}"#,
+        );
+        assert!(
+            matches!(config, Err(ConfigError::InvalidValue(e)) if e.contains("control socket of robot 6"))
+        );
+        let config = Config::from_json(r#"{"admin": {"robot_base_port": 65530}}"#);
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn test_sockets_sharing_a_port_fail() {
+        let config =
+            Config::from_json(r#"{"cluster": {"robots": 12}, "metrics": {"enabled": true}}"#);
+        assert!(config.is_ok());
+        let config = Config::from_json(
+            r#"{"cluster": {"robots": 12}, "metrics": {"enabled": true, "robot_base_port": 9195}}"#,
+        );
+        assert!(
+            matches!(config, Err(ConfigError::InvalidValue(e)) if e.contains("127.0.0.1:9200"))
+        );
+    }
+
+    #[test]
+    fn test_the_same_ipv6_host_is_found_with_or_without_brackets() {
+        let config = Config::from_json(
+            r#"{"cluster": {"robots": 2}, "network": {"robots": [
+                {"id": 0, "host": "::1", "port": 9000, "leader_port": 9100},
+                {"id": 1, "host": "[::1]", "port": 9000, "leader_port": 9101}
+            ]}}"#,
+        );
+        assert!(matches!(config, Err(ConfigError::InvalidValue(e)) if e.contains("[::1]:9000")));
+    }
+
+    const SHARDS: &str = r#""leader_shards": [
+        ["Chocolate", "Vanilla", "Strawberry"],
+        ["Mint", "Pistachio", "DulceDeLeche", "Lemon"]
+    ]"#;
+
+    #[test]
+    fn test_each_shard_gets_its_flavors_ports_and_files() {
+        let json = format!(r#"{{"cluster": {{{}}}}}"#, SHARDS);
+        let config = Config::from_json(&json).unwrap();
+        assert_eq!(config.cluster.shards(), 2);
+        let first = config.clone().for_shard(0);
+        assert_eq!(first.network.robot(0), config.network.robot(0));
+        assert!(first.flavors.initial_amount(&FlavorID::Mint).is_none());
+
+        let second = config.clone().for_shard(1);
+        assert_eq!(second.cluster.shard, 1);
+        assert!(second.flavors.initial_amount(&FlavorID::Mint).is_some());
+        assert!(second.flavors.initial_amount(&FlavorID::Vanilla).is_none());
+        let (robot, moved) = (config.network.robot(2), second.network.robot(2));
+        assert_eq!(moved.port, robot.port + 1000);
+        assert_eq!(moved.leader_port, robot.leader_port + 1000);
+        assert_eq!(
+            second.network.screen(1).port,
+            config.network.screen(1).port + 1000
+        );
+        assert_eq!(
+            Path::new(&second.persistence.data_dir),
+            Path::new(&config.persistence.data_dir).join("shard_1")
+        );
+        assert!(second
+            .report
+            .file
+            .ends_with("consumption_report_shard1.json"));
+    }
+
+    #[test]
+    fn test_orders_are_split_among_the_shards_of_their_flavors() {
+        let json = format!(r#"{{"cluster": {{{}}}}}"#, SHARDS);
+        let cluster = Config::from_json(&json).unwrap().for_shard(1).cluster;
+        let order = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        assert!(!cluster.needs_routing(&order));
+        assert_eq!(cluster.split(&order), vec![(1, order)]);
+
+        let order = Order::new_medio(vec![FlavorID::Mint, FlavorID::Vanilla, FlavorID::Lemon])
+            .unwrap()
+            .with_substitutes(FlavorID::Vanilla, vec![FlavorID::Mint, FlavorID::Chocolate]);
+        assert!(cluster.needs_routing(&order));
+        let parts = cluster.split(&order);
+        assert_eq!(
+            parts[0],
+            (
+                0,
+                Order::Medio(vec![Scoop(
+                    FlavorID::Vanilla,
+                    167,
+                    vec![FlavorID::Chocolate]
+                )])
+            )
+        );
+        assert_eq!(
+            parts[1],
+            (
+                1,
+                Order::Medio(vec![
+                    Scoop::new(FlavorID::Mint, 167),
+                    Scoop::new(FlavorID::Lemon, 166)
+                ])
+            )
+        );
+        assert_eq!(cluster.screen_number(2), cluster.screens + 2);
+        assert_eq!(cluster.link_screen_id(0), cluster.screens);
+    }
+
+    #[test]
+    fn test_a_shard_finds_the_leaders_of_the_other_shards() {
+        let json = format!(r#"{{"cluster": {{{}}}}}"#, SHARDS);
+        let config = Config::from_json(&json).unwrap();
+        let (first, second) = (config.clone().for_shard(0), config.for_shard(1));
+        assert_eq!(
+            first.leader_addrs_of_shard(1),
+            second.leader_addrs_of_shard(1)
+        );
+        assert_eq!(
+            second.leader_addrs_of_shard(0),
+            first.leader_addrs_of_shard(0)
+        );
+        assert_eq!(
+            second.leader_addrs_of_shard(1)[2],
+            second.network.leader_addr(2)
+        );
+        assert_ne!(
+            first.leader_addrs_of_shard(1)[2],
+            first.network.leader_addr(2)
+        );
+    }
+
+    #[test]
+    fn test_invalid_shards_fail() {
+        let repeated = r#"{"cluster": {"leader_shards": [["Mint"], ["Mint", "Lemon"]]}}"#;
+        let missing = r#"{"cluster": {"leader_shards": [["Mint"], ["Lemon"]]}}"#;
+        let empty = r#"{"cluster": {"leader_shards": [
+            ["Chocolate", "Vanilla", "Strawberry", "Mint", "Pistachio", "DulceDeLeche", "Lemon"], []
+        ]}}"#;
+        for json in [repeated, missing, empty] {
+            let config = Config::from_json(json);
+            assert!(
+                matches!(config, Err(ConfigError::InvalidValue(_))),
+                "{}",
+                json
+            );
+        }
+        let json = format!(r#"{{"cluster": {{{}}}}}"#, SHARDS);
+        let mut config = Config::from_json(&json).unwrap();
+        config.cluster.shard = 2;
+        assert!(config.cluster.validate().is_err());
+    }
+
+    #[test]
+    fn test_shards_sharing_a_port_fail() {
+        let json = format!(r#"{{"cluster": {{{}, "shard_port_offset": 1}}}}"#, SHARDS);
+        let config = Config::from_json(&json);
+        assert!(matches!(config, Err(ConfigError::InvalidValue(e)) if e.contains("of shard 1")));
+    }
+
+    #[test]
+    fn test_missing_file_fails() {
+        let config = Config::from_file("non_existent_config.json");
+        assert!(matches!(config, Err(ConfigError::CouldNotReadFile(_))));
+    }
+}
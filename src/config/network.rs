@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::codec::WireFormat;
+use crate::common::utils::{host_port, node_port};
+use crate::config::cluster::{DEFAULT_NUMBER_OF_ROBOTS, DEFAULT_NUMBER_OF_SCREENS};
+use crate::config::ConfigError;
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_ROBOT_BASE_PORT: u16 = 8070;
+pub const DEFAULT_LEADER_BASE_PORT: u16 = 3690;
+pub const DEFAULT_SCREEN_BASE_PORT: u16 = 7000;
+pub const DEFAULT_HANDSHAKE_TIMEOUT_MS: u64 = 5000;
+pub const DEFAULT_MAX_PENDING_HANDSHAKES: usize = 32;
+
+/// Address of a robot process.
+/// A robot listens on `port` for the other robots, and on `leader_port` when it becomes the leader
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RobotAddress {
+    pub id: usize,
+    pub host: String,
+    pub port: u16,
+    pub leader_port: u16,
+}
+
+/// Address of a screen process
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScreenAddress {
+    pub id: usize,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Describes where every robot and screen of the system can be reached.
+/// A host can be an IPv4 address, an IPv6 address, with or without brackets, or a hostname that is resolved when connecting.
+/// Nodes that are not listed use the default localhost address for their id, on the default base port plus the id.
+/// `wire_format` selects how messages are framed on every connection.
+/// A listener closes a connection that does not say who is connecting within `handshake_timeout_ms`, and
+/// closes the new ones right away while `max_pending_handshakes` connections have not said it yet
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub robots: Vec<RobotAddress>,
+    pub screens: Vec<ScreenAddress>,
+    pub wire_format: WireFormat,
+    pub handshake_timeout_ms: u64,
+    pub max_pending_handshakes: usize,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            robots: (0..DEFAULT_NUMBER_OF_ROBOTS)
+                .map(default_robot_address)
+                .collect(),
+            screens: (0..DEFAULT_NUMBER_OF_SCREENS)
+                .map(default_screen_address)
+                .collect(),
+            wire_format: WireFormat::default(),
+            handshake_timeout_ms: DEFAULT_HANDSHAKE_TIMEOUT_MS,
+            max_pending_handshakes: DEFAULT_MAX_PENDING_HANDSHAKES,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Checks that every node has a host, that the handshakes have time to be read and that at least one can be read at a time
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let hosts = self
+            .robots
+            .iter()
+            .map(|robot| (robot.id, &robot.host, "robot"));
+        let hosts = hosts.chain(self.screens.iter().map(|s| (s.id, &s.host, "screen")));
+        for (id, host, kind) in hosts {
+            if host.trim().is_empty() || host.contains(char::is_whitespace) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "network: the {} {} has an invalid host {:?}",
+                    kind, id, host
+                )));
+            }
+        }
+        if self.handshake_timeout_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "network.handshake_timeout_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.max_pending_handshakes == 0 {
+            return Err(ConfigError::InvalidValue(
+                "network.max_pending_handshakes must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the robot with the given id
+    pub fn robot_addr(&self, id: usize) -> String {
+        let robot = self.robot(id);
+        host_port(&robot.host, robot.port)
+    }
+
+    /// Returns the address where the robot with the given id listens when it is the leader
+    pub fn leader_addr(&self, id: usize) -> String {
+        let robot = self.robot(id);
+        host_port(&robot.host, robot.leader_port)
+    }
+
+    /// Returns the address of the screen with the given id
+    pub fn screen_addr(&self, id: usize) -> String {
+        let screen = self.screen(id);
+        host_port(&screen.host, screen.port)
+    }
+
+    /// Returns where the screen with the given id listens, the default one if it is not listed
+    pub fn screen(&self, id: usize) -> ScreenAddress {
+        self.screens
+            .iter()
+            .find(|screen| screen.id == id)
+            .cloned()
+            .unwrap_or_else(|| default_screen_address(id))
+    }
+
+    /// Returns where the robot with the given id listens, the default one if it is not listed
+    pub fn robot(&self, id: usize) -> RobotAddress {
+        self.robots
+            .iter()
+            .find(|robot| robot.id == id)
+            .cloned()
+            .unwrap_or_else(|| default_robot_address(id))
+    }
+}
+
+/// Default address of the robot, its ports are 0 if they are past the port range, which the config rejects
+fn default_robot_address(id: usize) -> RobotAddress {
+    RobotAddress {
+        id,
+        host: DEFAULT_HOST.to_string(),
+        port: node_port(DEFAULT_ROBOT_BASE_PORT, id).unwrap_or(0),
+        leader_port: node_port(DEFAULT_LEADER_BASE_PORT, id).unwrap_or(0),
+    }
+}
+
+fn default_screen_address(id: usize) -> ScreenAddress {
+    ScreenAddress {
+        id,
+        host: DEFAULT_HOST.to_string(),
+        port: node_port(DEFAULT_SCREEN_BASE_PORT, id).unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_addresses_are_localhost() {
+        let network = NetworkConfig::default();
+        assert_eq!(network.robot_addr(2), "127.0.0.1:8072");
+        assert_eq!(network.leader_addr(2), "127.0.0.1:3692");
+        assert_eq!(network.screen_addr(1), "127.0.0.1:7001");
+    }
+
+    #[test]
+    fn test_configured_addresses_are_used() {
+        let network: NetworkConfig = serde_json::from_str(
+            r#"{
+                "robots": [{"id": 0, "host": "10.0.0.5", "port": 9000, "leader_port": 9100}],
+                "screens": [{"id": 1, "host": "10.0.0.6", "port": 9200}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(network.robot_addr(0), "10.0.0.5:9000");
+        assert_eq!(network.leader_addr(0), "10.0.0.5:9100");
+        assert_eq!(network.screen_addr(1), "10.0.0.6:9200");
+    }
+
+    #[test]
+    fn test_a_cluster_can_mix_address_families() {
+        let network: NetworkConfig = serde_json::from_str(
+            r#"{
+                "robots": [
+                    {"id": 0, "host": "10.0.0.5", "port": 9000, "leader_port": 9100},
+                    {"id": 1, "host": "::1", "port": 9001, "leader_port": 9101},
+                    {"id": 2, "host": "[fd00::2]", "port": 9002, "leader_port": 9102}
+                ],
+                "screens": [{"id": 0, "host": "screens.freddo.local", "port": 9200}]
+            }"#,
+        )
+        .unwrap();
+        assert!(network.validate().is_ok());
+        assert_eq!(network.robot_addr(0), "10.0.0.5:9000");
+        assert_eq!(network.robot_addr(1), "[::1]:9001");
+        assert_eq!(network.leader_addr(2), "[fd00::2]:9102");
+        assert_eq!(network.screen_addr(0), "screens.freddo.local:9200");
+    }
+
+    #[test]
+    fn test_nodes_without_a_host_fail() {
+        let network: NetworkConfig =
+            serde_json::from_str(r#"{"screens": [{"id": 0, "host": " ", "port": 9200}]}"#).unwrap();
+        assert!(network.validate().is_err());
+    }
+
+    #[test]
+    fn test_missing_nodes_fall_back_to_default() {
+        let network: NetworkConfig =
+            serde_json::from_str(r#"{"robots": [], "screens": []}"#).unwrap();
+        assert_eq!(network.robot_addr(3), "127.0.0.1:8073");
+        assert_eq!(network.screen_addr(0), "127.0.0.1:7000");
+    }
+
+    #[test]
+    fn test_handshakes_without_time_or_slots_fail() {
+        let network: NetworkConfig =
+            serde_json::from_str(r#"{"handshake_timeout_ms": 0}"#).unwrap();
+        assert!(network.validate().is_err());
+        let network: NetworkConfig =
+            serde_json::from_str(r#"{"max_pending_handshakes": 0}"#).unwrap();
+        assert!(network.validate().is_err());
+        assert!(NetworkConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_lines_wire_format_can_be_configured() {
+        let network: NetworkConfig = serde_json::from_str(r#"{"wire_format": "Lines"}"#).unwrap();
+        assert_eq!(network.wire_format, WireFormat::Lines);
+        assert_eq!(NetworkConfig::default().wire_format, WireFormat::Binary);
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_MAX_CONCURRENT_ORDERS: usize = 2;
+pub const DEFAULT_RESULT_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_MAX_RETRIES: usize = 2;
+pub const DEFAULT_BATCH_SIZE: usize = 10;
+pub const DEFAULT_BATCH_WINDOW_MS: u64 = 20;
+pub const DEFAULT_WATCH_INTERVAL_MS: u64 = 500;
+pub const DEFAULT_MAX_QUEUED: usize = 100;
+pub const DEFAULT_BUSY_RETRY_MS: u64 = 1000;
+pub const DEFAULT_MAX_BUSY_RETRIES: usize = 5;
+pub const DEFAULT_COMPLETED_CACHE: usize = 64;
+pub const DEFAULT_STREAM_CHUNK: usize = 0;
+pub const DEFAULT_STREAM_MAX_WAITING: usize = 1000;
+pub const DEFAULT_STOCK_SNAPSHOT_MS: u64 = 2000;
+
+/// How a screen names the orders it captures.
+/// `Uuid` gives every order a random id, `screenid-seq` gives them `<screen id>-<number>`, counting from 1,
+/// so two runs that capture the same orders name them the same and their logs can be compared
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderIdScheme {
+    #[default]
+    Uuid,
+    #[serde(rename = "screenid-seq")]
+    ScreenSeq,
+}
+
+/// Configuration of how orders are prepared by the robots.
+/// The leader gives each robot up to `max_concurrent` orders at the same time.
+/// A screen that does not get the result of an order after `result_timeout_secs` sends it again to the leader,
+/// up to `max_retries` times, and then aborts the payment. A timeout of 0 disables the retries.
+/// A screen sends together the orders captured within `batch_window_ms`, up to `batch_size` orders per message.
+/// In watch mode a screen looks for new orders in its file every `watch_interval_ms`.
+/// The leader rejects new orders while it has `max_queued` orders waiting for a robot (0 means no limit),
+/// a screen sends a rejected order again after `busy_retry_ms`, up to `max_busy_retries` times, and then aborts the payment.
+/// If `deadline_ms` is set, a screen gives it to every order it sends, and the leader aborts an order
+/// that the robot it was assigned to did not finish within it.
+/// `id_scheme` is how the screens name the orders they capture.
+/// Each robot remembers its last `completed_cache` completed orders, and answers one of them that is assigned to it again
+/// with its result instead of preparing it twice.
+/// With `stream_chunk` above 0 a screen reads its file `stream_chunk` orders at a time instead of all at once,
+/// and does not read more while its payments gateway has over `stream_max_waiting` orders waiting
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct OrdersConfig {
+    pub max_concurrent: usize,
+    pub result_timeout_secs: u64,
+    pub max_retries: usize,
+    pub batch_size: usize,
+    pub batch_window_ms: u64,
+    pub watch_interval_ms: u64,
+    pub max_queued: usize,
+    pub busy_retry_ms: u64,
+    pub max_busy_retries: usize,
+    pub deadline_ms: Option<u64>,
+    pub id_scheme: OrderIdScheme,
+    pub completed_cache: usize,
+    pub stream_chunk: usize,
+    pub stream_max_waiting: usize,
+    pub stock_snapshot_ms: u64,
+}
+
+impl Default for OrdersConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: DEFAULT_MAX_CONCURRENT_ORDERS,
+            result_timeout_secs: DEFAULT_RESULT_TIMEOUT_SECS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_window_ms: DEFAULT_BATCH_WINDOW_MS,
+            watch_interval_ms: DEFAULT_WATCH_INTERVAL_MS,
+            max_queued: DEFAULT_MAX_QUEUED,
+            busy_retry_ms: DEFAULT_BUSY_RETRY_MS,
+            max_busy_retries: DEFAULT_MAX_BUSY_RETRIES,
+            deadline_ms: None,
+            id_scheme: OrderIdScheme::Uuid,
+            completed_cache: DEFAULT_COMPLETED_CACHE,
+            stream_chunk: DEFAULT_STREAM_CHUNK,
+            stream_max_waiting: DEFAULT_STREAM_MAX_WAITING,
+            stock_snapshot_ms: DEFAULT_STOCK_SNAPSHOT_MS,
+        }
+    }
+}
+
+impl OrdersConfig {
+    /// Checks that every robot can prepare at least one order, every batch carries at least one order
+    /// and the watched files and rejected orders are not retried in a busy loop, nor orders given no time to be prepared.
+    /// A streamed file must fit at least a chunk in the gateway, or it would never be read
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_concurrent == 0 {
+            return Err(ConfigError::InvalidValue(
+                "orders.max_concurrent must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size == 0 {
+            return Err(ConfigError::InvalidValue(
+                "orders.batch_size must be at least 1".to_string(),
+            ));
+        }
+        if self.watch_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "orders.watch_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.busy_retry_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "orders.busy_retry_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.deadline_ms == Some(0) {
+            return Err(ConfigError::InvalidValue(
+                "orders.deadline_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.stream_chunk > 0 && self.stream_max_waiting < self.stream_chunk {
+            return Err(ConfigError::InvalidValue(
+                "orders.stream_max_waiting must be at least orders.stream_chunk".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
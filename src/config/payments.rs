@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_DECLINE_RATE: f64 = 0.1;
+pub const DEFAULT_PROCESSING_MS: u64 = 2000;
+
+/// Configuration of the payments of the screens.
+/// Each card takes `processing_ms` milliseconds to be charged and is declined with probability `decline_rate`.
+/// Besides, every `decline_every`-th card is declined (0 disables it), and so are the orders whose id is in `decline_ids`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PaymentsConfig {
+    pub decline_rate: f64,
+    pub processing_ms: u64,
+    pub decline_every: usize,
+    pub decline_ids: Vec<String>,
+}
+
+impl Default for PaymentsConfig {
+    fn default() -> Self {
+        Self {
+            decline_rate: DEFAULT_DECLINE_RATE,
+            processing_ms: DEFAULT_PROCESSING_MS,
+            decline_every: 0,
+            decline_ids: Vec::new(),
+        }
+    }
+}
+
+impl PaymentsConfig {
+    /// Checks that the decline rate is a probability
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.decline_rate) {
+            return Err(ConfigError::InvalidValue(
+                "payments.decline_rate must be between 0 and 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_DATA_DIR: &str = "./data";
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 50;
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+pub const DEFAULT_MAILBOX_CAPACITY: usize = 1024;
+
+/// Configuration of the files each process writes to disk.
+/// The writes are batched by the `PersistenceWriter` of the process, which flushes them every `flush_interval_ms`
+/// or once `batch_size` are waiting. If `mailbox_capacity` writes are waiting to be received, the next ones are
+/// written right away by whoever sends them
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PersistenceConfig {
+    pub enabled: bool,
+    pub data_dir: String,
+    pub flush_interval_ms: u64,
+    pub batch_size: usize,
+    pub mailbox_capacity: usize,
+    pub fsync: bool,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            data_dir: DEFAULT_DATA_DIR.to_string(),
+            flush_interval_ms: DEFAULT_FLUSH_INTERVAL_MS,
+            batch_size: DEFAULT_BATCH_SIZE,
+            mailbox_capacity: DEFAULT_MAILBOX_CAPACITY,
+            fsync: true,
+        }
+    }
+}
+
+impl PersistenceConfig {
+    /// Checks that the writes are flushed at some point and can be queued
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.flush_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "persistence.flush_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size == 0 || self.mailbox_capacity == 0 {
+            return Err(ConfigError::InvalidValue(
+                "persistence.batch_size and persistence.mailbox_capacity must be at least 1"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_REPORT_FILE: &str = "./data/consumption_report.json";
+
+/// Format of the file of the consumption report
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Configuration of the consumption report of the leader.
+/// The report is written to `file` in the given `format` when the admin asks for it,
+/// and when the robot of the leader is shut down if `on_shutdown` is set
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ReportConfig {
+    pub file: String,
+    pub format: ReportFormat,
+    pub on_shutdown: bool,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            file: DEFAULT_REPORT_FILE.to_string(),
+            format: ReportFormat::Json,
+            on_shutdown: true,
+        }
+    }
+}
+
+impl ReportConfig {
+    /// Checks that the report has somewhere to be written
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.file.trim().is_empty() {
+            return Err(ConfigError::InvalidValue(
+                "report.file can not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::order::KILO;
+
+/// Configuration of the periodic restocking done by the leader.
+/// Every `interval_secs` seconds each flavor of the catalog gets `amount` more grams, 0 disables it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RestockConfig {
+    pub interval_secs: u64,
+    pub amount: usize,
+}
+
+impl Default for RestockConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            amount: KILO,
+        }
+    }
+}
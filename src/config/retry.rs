@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_BASE_DELAY_MS: u64 = 200;
+pub const DEFAULT_BACKOFF_FACTOR: f64 = 2.0;
+/// Longest wait between two attempts, whatever the factor is
+const MAX_DELAY_MS: u64 = 60_000;
+
+/// Policy shared by every retry of the robots and screens.
+/// Something that failed is tried again up to `max_attempts` times, the first retry waits `base_delay_ms`
+/// and every other one waits `backoff_factor` times the previous one, plus up to `jitter_ms` milliseconds chosen at random
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub backoff_factor: f64,
+    pub jitter_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay_ms: DEFAULT_BASE_DELAY_MS,
+            backoff_factor: DEFAULT_BACKOFF_FACTOR,
+            jitter_ms: 0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns true if something that already failed `attempt` times can be tried again
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Time to wait after `attempt` failures, without the jitter
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let millis = self.base_delay_ms as f64 * self.backoff_factor.powi(attempt as i32);
+        Duration::from_millis(millis.min(MAX_DELAY_MS as f64) as u64)
+    }
+
+    /// Time to wait after `attempt` failures, with the jitter taken from the given generator
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let jitter = match self.jitter_ms {
+            0 => 0,
+            jitter_ms => rng.gen_range(0..=jitter_ms),
+        };
+        self.base_delay(attempt) + Duration::from_millis(jitter)
+    }
+
+    /// Longest time that the first wait and every retry can take
+    pub fn window(&self) -> Duration {
+        let retries = self.max_attempts + 1;
+        (0..retries)
+            .map(|attempt| self.base_delay(attempt))
+            .sum::<Duration>()
+            + self.base_delay(0)
+            + Duration::from_millis(self.jitter_ms * (retries as u64 + 1))
+    }
+
+    /// Checks that the waits never get shorter and the first one is not zero
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.base_delay_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "retry.base_delay_ms must be at least 1".to_string(),
+            ));
+        }
+        if !(self.backoff_factor >= 1.0 && self.backoff_factor.is_finite()) {
+            return Err(ConfigError::InvalidValue(
+                "retry.backoff_factor must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::simulation;
+
+    #[test]
+    fn test_delays_grow_by_the_factor_and_stay_within_the_jitter() {
+        let policy = RetryPolicy {
+            backoff_factor: 3.0,
+            jitter_ms: 50,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.base_delay(0), Duration::from_millis(200));
+        assert_eq!(policy.base_delay(2), Duration::from_millis(1800));
+        assert_eq!(policy.base_delay(40), Duration::from_millis(MAX_DELAY_MS));
+
+        let mut rng = simulation::seeded_rng(1, "RETRY", 0);
+        let delay = policy.delay(1, &mut rng);
+        assert!(delay >= Duration::from_millis(600) && delay <= Duration::from_millis(650));
+        assert!(policy.should_retry(2) && !policy.should_retry(3));
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_SCOOP_RETRIES: u32 = 2;
+pub const DEFAULT_MS_PER_GRAM: usize = 10;
+
+/// Configuration of the failures of the robot arms, used to simulate faults.
+/// Every scoop jams with probability `jam_probability`, and then nothing is served. Each scoop also takes
+/// up to `jitter_ms` milliseconds more than usual. An order whose scoops of a flavor jam more than `max_retries` times is aborted.
+/// A robot takes `ms_per_gram` milliseconds to scoop a gram, unless `robots_ms_per_gram` has another speed for its id
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ScoopsConfig {
+    pub jam_probability: f64,
+    pub jitter_ms: u64,
+    pub max_retries: u32,
+    pub ms_per_gram: usize,
+    pub robots_ms_per_gram: HashMap<usize, usize>,
+}
+
+impl Default for ScoopsConfig {
+    fn default() -> Self {
+        Self {
+            jam_probability: 0.0,
+            jitter_ms: 0,
+            max_retries: DEFAULT_SCOOP_RETRIES,
+            ms_per_gram: DEFAULT_MS_PER_GRAM,
+            robots_ms_per_gram: HashMap::new(),
+        }
+    }
+}
+
+impl ScoopsConfig {
+    /// Milliseconds the robot takes to scoop a gram
+    pub fn ms_per_gram_of(&self, robot_id: usize) -> usize {
+        self.robots_ms_per_gram
+            .get(&robot_id)
+            .copied()
+            .unwrap_or(self.ms_per_gram)
+    }
+
+    /// Checks that the jam probability is a probability and that every robot takes some time to scoop
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.jam_probability) {
+            return Err(ConfigError::InvalidValue(
+                "scoops.jam_probability must be between 0 and 1".to_string(),
+            ));
+        }
+        if self.ms_per_gram == 0 || self.robots_ms_per_gram.values().any(|ms| *ms == 0) {
+            return Err(ConfigError::InvalidValue(
+                "scoops.ms_per_gram must be at least 1 for every robot".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
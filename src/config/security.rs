@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+/// Configuration of how the backups of the leader travel to the robots.
+/// If `backup_key` is set, every backup is signed with it and a robot only keeps a backup whose signature is valid,
+/// so another process on the network can not make the robots take over with a state it made up.
+/// With `encrypt_backups` the backups are also encrypted with the key, so the orders can not be read on the way
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SecurityConfig {
+    pub backup_key: Option<String>,
+    pub encrypt_backups: bool,
+}
+
+impl SecurityConfig {
+    /// Checks that the key is not empty, and that there is a key to encrypt with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.backup_key.as_deref() == Some("") {
+            return Err(ConfigError::InvalidValue(
+                "security.backup_key can not be empty".to_string(),
+            ));
+        }
+        if self.encrypt_backups && self.backup_key.is_none() {
+            return Err(ConfigError::InvalidValue(
+                "security.encrypt_backups needs a security.backup_key".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+/// Configuration of the simulation mode.
+/// When it is enabled every random choice comes from a generator seeded with `seed`,
+/// so running the same scenario twice takes the same decisions.
+/// With `virtual_time` the bench pauses the tokio clock and moves it `time_scale` times faster than the real one,
+/// it needs the `simulation` feature
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub enabled: bool,
+    pub seed: u64,
+    pub virtual_time: bool,
+    pub time_scale: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seed: 0,
+            virtual_time: false,
+            time_scale: 10,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.virtual_time && !self.enabled {
+            return Err(ConfigError::InvalidValue(
+                "simulation.virtual_time needs simulation.enabled".to_string(),
+            ));
+        }
+        if self.time_scale == 0 {
+            return Err(ConfigError::InvalidValue(
+                "simulation.time_scale must be greater than 0".to_string(),
+            ));
+        }
+        if self.virtual_time && !cfg!(feature = "simulation") {
+            return Err(ConfigError::InvalidValue(
+                "simulation.virtual_time needs the simulation feature".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_CUCURUCHO: OrderSize = OrderSize::new(250, 1);
+pub const DEFAULT_CUARTO: OrderSize = OrderSize::new(250, 2);
+pub const DEFAULT_MEDIO: OrderSize = OrderSize::new(500, 3);
+pub const DEFAULT_KILO: OrderSize = OrderSize::new(1000, 4);
+
+/// Grams of a size of order and the most flavors they can be split in
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrderSize {
+    pub grams: usize,
+    pub max_flavors: usize,
+}
+
+impl OrderSize {
+    pub const fn new(grams: usize, max_flavors: usize) -> Self {
+        Self { grams, max_flavors }
+    }
+}
+
+/// Configuration of the sizes of the orders, the constructors of the orders split the grams of each size
+/// between its flavors, and the orders read from files or sent by a leader are checked against them.
+/// Every process of the system should use the same sizes
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SizesConfig {
+    pub cucurucho: OrderSize,
+    pub cuarto: OrderSize,
+    pub medio: OrderSize,
+    pub kilo: OrderSize,
+}
+
+impl Default for SizesConfig {
+    fn default() -> Self {
+        Self {
+            cucurucho: DEFAULT_CUCURUCHO,
+            cuarto: DEFAULT_CUARTO,
+            medio: DEFAULT_MEDIO,
+            kilo: DEFAULT_KILO,
+        }
+    }
+}
+
+impl SizesConfig {
+    /// Returns every size with its name, from the smallest kind of order to the biggest
+    pub fn all(&self) -> [(&'static str, OrderSize); 4] {
+        [
+            ("cucurucho", self.cucurucho),
+            ("cuarto", self.cuarto),
+            ("medio", self.medio),
+            ("kilo", self.kilo),
+        ]
+    }
+
+    /// Checks that every size takes a flavor and has a gram for each flavor, and that a cucurucho has a single flavor
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.cucurucho.max_flavors != 1 {
+            return Err(ConfigError::InvalidValue(
+                "sizes.cucurucho.max_flavors must be 1".to_string(),
+            ));
+        }
+        for (name, size) in self.all() {
+            if size.max_flavors == 0 || size.grams < size.max_flavors {
+                return Err(ConfigError::InvalidValue(format!(
+                    "sizes.{} must take at least one flavor and have a gram for each one",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes_without_grams_for_their_flavors_fail() {
+        let sizes: SizesConfig =
+            serde_json::from_str(r#"{"kilo": {"grams": 900, "max_flavors": 5}}"#).unwrap();
+        assert_eq!(sizes.kilo, OrderSize::new(900, 5));
+        assert_eq!(sizes.medio, DEFAULT_MEDIO);
+        assert!(sizes.validate().is_ok());
+
+        let sizes: SizesConfig =
+            serde_json::from_str(r#"{"cuarto": {"grams": 1, "max_flavors": 2}}"#).unwrap();
+        assert!(sizes.validate().is_err());
+        let sizes: SizesConfig =
+            serde_json::from_str(r#"{"cucurucho": {"grams": 300, "max_flavors": 2}}"#).unwrap();
+        assert!(sizes.validate().is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::config::ConfigError;
+
+/// An ice cream shop served by the cluster, with the screens that take its orders.
+/// `quotas` are the most grams of each flavor, by its name, that the orders of the shop can take while a leader leads.
+/// A flavor without a quota has no limit
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ShopConfig {
+    pub id: String,
+    pub screens: Vec<usize>,
+    pub quotas: BTreeMap<String, usize>,
+}
+
+/// Configuration of the shops that share the cluster.
+/// Without shops the cluster serves a single one, its screens have no shop and no quota is checked
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TenantsConfig {
+    pub shops: Vec<ShopConfig>,
+}
+
+impl TenantsConfig {
+    /// Returns the id of the shop of the screen, if it has one
+    pub fn shop_of(&self, screen_id: usize) -> Option<&str> {
+        self.shops
+            .iter()
+            .find(|shop| shop.screens.contains(&screen_id))
+            .map(|shop| shop.id.as_str())
+    }
+
+    /// Returns the quota of the flavor for the shop, if it has one
+    pub fn quota(&self, shop: &str, flavor: &str) -> Option<usize> {
+        self.shops
+            .iter()
+            .find(|s| s.id == shop)
+            .and_then(|s| s.quotas.get(flavor).copied())
+    }
+
+    /// Checks that every shop has an id of its own and that no screen belongs to two shops
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut ids = HashSet::new();
+        let mut screens = HashSet::new();
+        for shop in &self.shops {
+            if shop.id.trim().is_empty() {
+                return Err(ConfigError::InvalidValue(
+                    "tenants.shops must have an id".to_string(),
+                ));
+            }
+            if !ids.insert(shop.id.as_str()) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "tenants.shops has the shop {} twice",
+                    shop.id
+                )));
+            }
+            if let Some(screen) = shop.screens.iter().find(|screen| !screens.insert(**screen)) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "tenants.shops gives screen {} to more than one shop",
+                    screen
+                )));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_TOKENS_PER_FLAVOR: usize = 1;
+pub const DEFAULT_REBALANCE_THRESHOLD: usize = 500;
+pub const DEFAULT_IDLE_PAUSE_MS: u64 = 500;
+pub const DEFAULT_RESERVATION_TIMEOUT_MS: u64 = 60_000;
+pub const DEFAULT_WARM_UP_MS: u64 = 1000;
+/// Most tokens a flavor can be split into
+pub const MAX_TOKENS_PER_FLAVOR: usize = 16;
+
+/// Robots where the first leader starts the tokens
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TokenStart {
+    /// Every token starts at the robot of the leader
+    #[default]
+    Leader,
+    /// The tokens are dealt one by one to the robots connected to the leader, its own robot included
+    RoundRobin,
+    /// The tokens of each flavor start at the robot the `owners` of the config give it,
+    /// or at the robot of the leader if the flavor has none or its robot is not connected
+    Owners,
+}
+
+/// Configuration of the flavor tokens.
+/// The stock of each flavor is split into `per_flavor` tokens, so that many robots can scoop it at the same time.
+/// When a token has `rebalance_threshold` grams more than the poorest token of its flavor, the leader moves grams between them.
+/// A token that no robot needed in a whole round of the ring waits `idle_pause_ms` at the leader's robot before going on.
+/// The grams a robot reserves in a token are released after `reservation_timeout_ms`, 0 keeps them until the robot releases them.
+/// `start` tells where the first leader starts the tokens. Unless they start at its own robot, it waits `warm_up_ms`
+/// for the robots to connect before spreading them
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TokensConfig {
+    pub per_flavor: usize,
+    pub rebalance_threshold: usize,
+    pub idle_pause_ms: u64,
+    pub reservation_timeout_ms: u64,
+    pub start: TokenStart,
+    pub owners: BTreeMap<String, usize>,
+    pub warm_up_ms: u64,
+}
+
+impl Default for TokensConfig {
+    fn default() -> Self {
+        Self {
+            per_flavor: DEFAULT_TOKENS_PER_FLAVOR,
+            rebalance_threshold: DEFAULT_REBALANCE_THRESHOLD,
+            idle_pause_ms: DEFAULT_IDLE_PAUSE_MS,
+            reservation_timeout_ms: DEFAULT_RESERVATION_TIMEOUT_MS,
+            start: TokenStart::default(),
+            owners: BTreeMap::new(),
+            warm_up_ms: DEFAULT_WARM_UP_MS,
+        }
+    }
+}
+
+impl TokensConfig {
+    /// Checks that every flavor has at least one token and not too many, and that the owners are given if they are used
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.per_flavor == 0 || self.per_flavor > MAX_TOKENS_PER_FLAVOR {
+            return Err(ConfigError::InvalidValue(format!(
+                "tokens.per_flavor must be between 1 and {}",
+                MAX_TOKENS_PER_FLAVOR
+            )));
+        }
+        if self.start == TokenStart::Owners && self.owners.is_empty() {
+            return Err(ConfigError::InvalidValue(
+                "tokens.owners must give a robot to some flavor when tokens.start is Owners"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_TRACE_DUMP_INTERVAL_MS: u64 = 5000;
+
+/// Configuration of the trace of the tokens.
+/// If `file` is set, the leader's robot writes the last hops of every token to it every `dump_interval_ms`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TraceConfig {
+    pub file: Option<String>,
+    pub dump_interval_ms: u64,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            dump_interval_ms: DEFAULT_TRACE_DUMP_INTERVAL_MS,
+        }
+    }
+}
+
+impl TraceConfig {
+    /// Checks that the trace is not written in a busy loop
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.dump_interval_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "trace.dump_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
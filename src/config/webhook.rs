@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+
+pub const DEFAULT_WEBHOOK_TIMEOUT_MS: u64 = 2000;
+
+/// Configuration of the webhook where each screen posts the result of its orders, and the leader robot its stock alerts.
+/// If `url` is set, every confirmed or aborted order is posted to it as JSON, retried with the retry policy if it fails.
+/// If `secret` is set, the body is signed with an HMAC-SHA256 of it, so the receiver can check who sent it.
+/// Only `http://` URLs are supported
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            secret: None,
+            timeout_ms: DEFAULT_WEBHOOK_TIMEOUT_MS,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Checks that the URL can be posted to, and that the secret and the timeout are not empty
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(url) = &self.url {
+            if !url.starts_with("http://") || url.len() == "http://".len() {
+                return Err(ConfigError::InvalidValue(
+                    "webhook.url must be an http:// URL".to_string(),
+                ));
+            }
+        }
+        if self.secret.as_deref() == Some("") {
+            return Err(ConfigError::InvalidValue(
+                "webhook.secret can not be empty".to_string(),
+            ));
+        }
+        if self.timeout_ms == 0 {
+            return Err(ConfigError::InvalidValue(
+                "webhook.timeout_ms must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
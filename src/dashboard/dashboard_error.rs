@@ -0,0 +1,25 @@
+use std::fmt;
+
+/// Error type for the dashboard
+#[derive(Debug, PartialEq)]
+pub enum DashboardError {
+    NoLeaderFound(),
+    ErrorDecoding(String),
+    ErrorEncoding(String),
+}
+
+impl fmt::Display for DashboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DashboardError::NoLeaderFound() => write!(f, "Could not find the leader"),
+            DashboardError::ErrorDecoding(err) => {
+                write!(f, "Could not decode the state of the cluster: {}", err)
+            }
+            DashboardError::ErrorEncoding(err) => {
+                write!(f, "Could not encode the state of the cluster: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DashboardError {}
@@ -0,0 +1,13 @@
+use tp2::config;
+use tp2::dashboard::server;
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(e) = config::init_from_args(&args) {
+        println!("Error: {}", e);
+        return;
+    }
+
+    server::serve(config::get().dashboard.addr()).await;
+}
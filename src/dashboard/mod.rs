@@ -0,0 +1,5 @@
+//! This module contains the dashboard, a small HTTP server that asks the leader for the state of the cluster.
+//! It serves that state as JSON on `/api/state` and a page that shows it on `/`.
+
+pub mod dashboard_error;
+pub mod server;
@@ -0,0 +1,121 @@
+use crate::common::log;
+use colored::Colorize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+
+use crate::common::codec;
+use crate::common::utils::{connect, id_to_leader_addr};
+use crate::config;
+use crate::dashboard::dashboard_error::DashboardError;
+use crate::protocol;
+use crate::robot::cluster_state::ClusterState;
+use crate::robot::utils::DASHBOARD_CONNECTION;
+
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// Asks the leader for the state of the cluster.
+/// The leader is not known, so every robot is tried until one answers
+pub async fn fetch_state() -> Result<ClusterState, DashboardError> {
+    for id in 0..config::number_of_robots() {
+        let mut stream = match connect(&id_to_leader_addr(id)).await {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if protocol::write_version(&mut stream).await.is_err()
+            || stream.write_all(&[DASHBOARD_CONNECTION]).await.is_err()
+        {
+            continue;
+        }
+        let (read_half, _write_half) = stream.into_split();
+        if let Some(Ok(frame)) = codec::frames(read_half).next().await {
+            return codec::decode(&frame)
+                .map_err(|err| DashboardError::ErrorDecoding(err.to_string()));
+        }
+    }
+    Err(DashboardError::NoLeaderFound())
+}
+
+/// Returns the status, content type and body of the answer to a request of the path
+async fn route(path: &str) -> (&'static str, &'static str, String) {
+    match path {
+        "/" | "/index.html" => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            INDEX_HTML.replace(
+                "{{REFRESH_MS}}",
+                &config::get().dashboard.refresh_ms.to_string(),
+            ),
+        ),
+        "/api/state" => match fetch_state().await.and_then(|state| {
+            serde_json::to_string(&state)
+                .map_err(|err| DashboardError::ErrorEncoding(err.to_string()))
+        }) {
+            Ok(json) => ("200 OK", "application/json", json),
+            Err(e) => (
+                "503 Service Unavailable",
+                "application/json",
+                format!(
+                    "{{\"error\": {}}}",
+                    serde_json::to_string(&e.to_string()).unwrap_or_else(|_| "\"\"".to_string())
+                ),
+            ),
+        },
+        _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+    }
+}
+
+/// Serves the dashboard over HTTP on the given address
+pub async fn serve(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            let line = format!("Could not listen on {}: {}", addr, e);
+            log::error("DASH", line.red());
+            return;
+        }
+    };
+    let line = format!("Serving the dashboard on http://{}/", addr);
+    log::info("DASH", line.bright_blue());
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer_request(stream));
+    }
+}
+
+/// Answers a single HTTP request, only the path of the request line is used
+async fn answer_request(mut stream: TcpStream) {
+    let mut buf = [0; 1024];
+    let read = match stream.read(&mut buf).await {
+        Ok(read) => read,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, content_type, body) = route(path).await;
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_index_and_unknown_paths() {
+        let (status, content_type, body) = route("/").await;
+        assert_eq!(status, "200 OK");
+        assert!(content_type.starts_with("text/html"));
+        assert!(!body.contains("{{REFRESH_MS}}"));
+
+        let (status, _, _) = route("/nope").await;
+        assert_eq!(status, "404 Not Found");
+    }
+}
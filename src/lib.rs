@@ -1,4 +1,15 @@
+#[cfg(feature = "actors")]
+pub mod bench;
+#[cfg(feature = "actors")]
+pub mod cli;
 pub mod common;
 pub mod config;
+#[cfg(feature = "actors")]
+pub mod dashboard;
+#[cfg(feature = "actors")]
+pub mod ordergen;
+pub mod protocol;
+#[cfg(feature = "actors")]
 pub mod robot;
+#[cfg(feature = "actors")]
 pub mod screen;
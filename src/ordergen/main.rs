@@ -0,0 +1,42 @@
+use std::process;
+use tp2::config;
+use tp2::ordergen::{self, OrderGenOptions, USAGE};
+
+/// Entry point of the order generator.
+///
+/// The orders are written to the `--output` file, or to the standard output, one per line.
+/// The flavors are the ones of the catalog of the config.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let args = match config::init_from_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let options = match OrderGenOptions::from_args(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            println!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let written = ordergen::generate(&options, &config::get().flavors).and_then(|orders| {
+        match &options.output {
+            Some(file_name) => ordergen::write_orders(&orders, file_name),
+            None => {
+                orders
+                    .iter()
+                    .for_each(|order| println!("{}", order.to_line()));
+                Ok(())
+            }
+        }
+    });
+    if let Err(e) = written {
+        println!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,314 @@
+//! This module contains the order generator, that writes files of synthetic orders like the ones of `orders_samples`,
+//! with the sizes and flavors drawn from configurable weights and, optionally, the time each order arrives.
+//! The benchmark draws its orders from here too.
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::common::simulation;
+use crate::config;
+use crate::config::flavors::FlavorCatalog;
+use crate::screen::order_reader::TimedOrder;
+
+pub const USAGE: &str =
+    "Usage: ordergen [--count <orders>] [--seed <seed>] [--sizes <cucurucho,cuarto,medio,kilo>] [--popularity <flavor=weight,...>] [--rate <orders/sec>] [--output <file>] [--config <file>]";
+
+/// How likely each size of order and each flavor is
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderDistribution {
+    /// Weights of a cucurucho, a cuarto, a medio and a kilo
+    pub sizes: [u32; 4],
+    /// Weight of each flavor, the flavors with weight 0 are never chosen
+    pub flavors: Vec<(FlavorID, u32)>,
+}
+
+impl OrderDistribution {
+    /// Every size and every flavor of the catalog equally likely
+    pub fn uniform(catalog: &FlavorCatalog) -> Self {
+        Self {
+            sizes: [1; 4],
+            flavors: catalog
+                .flavors()
+                .iter()
+                .map(|stock| (stock.flavor.clone(), 1))
+                .collect(),
+        }
+    }
+
+    /// Gives the flavors their weight, the flavors of the catalog not given keep theirs
+    pub fn with_popularity(mut self, popularity: &HashMap<FlavorID, u32>) -> Self {
+        for (flavor, weight) in self.flavors.iter_mut() {
+            if let Some(popular) = popularity.get(flavor) {
+                *weight = *popular;
+            }
+        }
+        self
+    }
+
+    /// Returns an error if no size or no flavor can be chosen
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sizes.iter().all(|weight| *weight == 0) {
+            return Err("At least one size must have a weight greater than 0".to_string());
+        }
+        if self.flavors.iter().all(|(_, weight)| *weight == 0) {
+            return Err("At least one flavor must have a weight greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// Draws an order, the distribution has to be valid.
+    /// The flavors of an order are different, so an order takes at most as many flavors as can be chosen
+    pub fn sample(&self, rng: &mut StdRng) -> Order {
+        let kind = WeightedIndex::new(self.sizes)
+            .map(|sizes| sizes.sample(rng))
+            .unwrap_or(0);
+        let mut candidates: Vec<(FlavorID, u32)> = self
+            .flavors
+            .iter()
+            .filter(|(_, weight)| *weight > 0)
+            .cloned()
+            .collect();
+        let most = config::get().sizes.all()[kind].1.max_flavors;
+        let max_flavors = most.min(candidates.len()).max(1);
+        let amount = rng.gen_range(1..=max_flavors);
+        let mut chosen = Vec::new();
+        while chosen.len() < amount {
+            let Ok(weights) = WeightedIndex::new(candidates.iter().map(|(_, weight)| *weight))
+            else {
+                break;
+            };
+            chosen.push(candidates.remove(weights.sample(rng)).0);
+        }
+        let first = chosen.first().cloned().unwrap_or(FlavorID::Chocolate);
+        let order = match kind {
+            1 => Order::new_cuarto(chosen),
+            2 => Order::new_medio(chosen),
+            3 => Order::new_kilo(chosen),
+            _ => Ok(Order::new_cucurucho(first.clone())),
+        };
+        order.unwrap_or_else(|_| Order::new_cucurucho(first))
+    }
+}
+
+/// What the generator writes: `count` orders drawn from the weights given, with the random generator of `seed`.
+/// With a `rate` every order has the time it arrives, with the orders arriving at random at that rate on average
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderGenOptions {
+    pub count: usize,
+    pub seed: u64,
+    pub sizes: [u32; 4],
+    pub popularity: HashMap<FlavorID, u32>,
+    pub rate: Option<f64>,
+    pub output: Option<String>,
+}
+
+impl Default for OrderGenOptions {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            seed: 0,
+            sizes: [1; 4],
+            popularity: HashMap::new(),
+            rate: None,
+            output: None,
+        }
+    }
+}
+
+/// Parses a weight, a number that is not negative
+fn parse_weight(flag: &str, value: &str) -> Result<u32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} takes weights that are positive integers", flag))
+}
+
+impl OrderGenOptions {
+    /// Parses the flags of the generator, the ones not given keep their default value
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("Missing value for flag {}", flag))?;
+            match flag.as_str() {
+                "--count" => {
+                    options.count = value
+                        .parse()
+                        .map_err(|_| "--count must be a positive integer".to_string())?
+                }
+                "--seed" => {
+                    options.seed = value
+                        .parse()
+                        .map_err(|_| "--seed must be a positive integer".to_string())?
+                }
+                "--sizes" => {
+                    let weights = value
+                        .split(',')
+                        .map(|weight| parse_weight(flag, weight))
+                        .collect::<Result<Vec<u32>, String>>()?;
+                    options.sizes = weights
+                        .try_into()
+                        .map_err(|_| "--sizes takes 4 weights".to_string())?;
+                }
+                "--popularity" => {
+                    for entry in value.split(',') {
+                        let (flavor, weight) = entry
+                            .split_once('=')
+                            .ok_or_else(|| format!("{} is not <flavor>=<weight>", entry))?;
+                        let flavor: FlavorID =
+                            flavor.trim().parse().map_err(|e| format!("{}", e))?;
+                        options
+                            .popularity
+                            .insert(flavor, parse_weight(flag, weight)?);
+                    }
+                }
+                "--rate" => match value.parse::<f64>() {
+                    Ok(rate) if rate.is_finite() && rate > 0.0 => options.rate = Some(rate),
+                    _ => return Err("--rate must be greater than 0".to_string()),
+                },
+                "--output" => options.output = Some(value.clone()),
+                _ => return Err(format!("Unknown flag {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The distribution of the orders over the flavors of the catalog
+    pub fn distribution(&self, catalog: &FlavorCatalog) -> Result<OrderDistribution, String> {
+        if let Some(flavor) = self
+            .popularity
+            .keys()
+            .find(|flavor| catalog.initial_amount(flavor).is_none())
+        {
+            return Err(format!("{} is not in the catalog", flavor));
+        }
+        let distribution = OrderDistribution {
+            sizes: self.sizes,
+            ..OrderDistribution::uniform(catalog)
+        }
+        .with_popularity(&self.popularity);
+        distribution.validate()?;
+        Ok(distribution)
+    }
+}
+
+/// Draws the time until the next order, with the orders arriving at random at `rate` per second on average
+pub fn next_arrival_ms(rng: &mut StdRng, rate: f64) -> u64 {
+    let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+    (-uniform.ln() * 1000.0 / rate).round() as u64
+}
+
+/// Generates the orders, the same options and catalog always give the same orders
+pub fn generate(
+    options: &OrderGenOptions,
+    catalog: &FlavorCatalog,
+) -> Result<Vec<TimedOrder>, String> {
+    let distribution = options.distribution(catalog)?;
+    let mut rng = simulation::seeded_rng(options.seed, "ORDERGEN", 0);
+    let mut arrival_ms = 0;
+    let orders = (0..options.count)
+        .map(|_| {
+            let order = distribution.sample(&mut rng);
+            let arrival = options.rate.map(|rate| {
+                arrival_ms += next_arrival_ms(&mut rng, rate);
+                arrival_ms
+            });
+            TimedOrder::new(order, arrival)
+        })
+        .collect();
+    Ok(orders)
+}
+
+/// Writes the orders one per line, in the format the screens read
+pub fn write_orders(orders: &[TimedOrder], file_name: &str) -> Result<(), String> {
+    let lines: Vec<String> = orders.iter().map(TimedOrder::to_line).collect();
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    fs::write(file_name, contents).map_err(|e| format!("Could not write {}: {}", file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::flavors::FlavorStock;
+    use crate::screen::order_reader::parse_timed_order_line;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_flags_override_the_defaults() {
+        let options = OrderGenOptions::from_args(&args(&[
+            "--count",
+            "3",
+            "--sizes",
+            "1,0,0,2",
+            "--popularity",
+            "Mint=5,Lemon=0",
+        ]))
+        .unwrap();
+        assert_eq!(options.count, 3);
+        assert_eq!(options.sizes, [1, 0, 0, 2]);
+        assert_eq!(options.popularity.get(&FlavorID::Mint), Some(&5));
+        assert_eq!(options.popularity.get(&FlavorID::Lemon), Some(&0));
+        assert_eq!(options.rate, None);
+
+        assert!(OrderGenOptions::from_args(&args(&["--sizes", "1,2"])).is_err());
+        assert!(OrderGenOptions::from_args(&args(&["--rate", "0"])).is_err());
+        assert!(OrderGenOptions::from_args(&args(&["--popularity", "Mint"])).is_err());
+    }
+
+    #[test]
+    fn test_only_the_weighted_sizes_and_flavors_are_generated() {
+        let catalog = FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 100),
+            FlavorStock::new(FlavorID::Lemon, 100),
+        ]);
+        let options = OrderGenOptions {
+            count: 50,
+            sizes: [0, 0, 0, 1],
+            popularity: HashMap::from([(FlavorID::Lemon, 0)]),
+            ..OrderGenOptions::default()
+        };
+        let orders = generate(&options, &catalog).unwrap();
+        assert_eq!(orders.len(), 50);
+        for timed in &orders {
+            assert_eq!(timed.order.get_flavors(), vec![(FlavorID::Mint, 1000)]);
+            assert_eq!(timed.arrival_ms, None);
+        }
+        assert_eq!(generate(&options, &catalog).unwrap(), orders);
+
+        let options = OrderGenOptions {
+            popularity: HashMap::from([(FlavorID::Mint, 0), (FlavorID::Lemon, 0)]),
+            ..options
+        };
+        assert!(generate(&options, &catalog).is_err());
+    }
+
+    #[test]
+    fn test_arrivals_go_forward_and_are_read_back() {
+        let options = OrderGenOptions {
+            count: 20,
+            rate: Some(10.0),
+            ..OrderGenOptions::default()
+        };
+        let orders = generate(&options, &FlavorCatalog::default()).unwrap();
+        let arrivals: Vec<u64> = orders.iter().filter_map(|o| o.arrival_ms).collect();
+        assert_eq!(arrivals.len(), 20);
+        assert!(arrivals.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        for timed in &orders {
+            assert_eq!(parse_timed_order_line(&timed.to_line()).as_ref(), Ok(timed));
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! Incremental backups of the leader.
+//! The leader numbers every backup it sends, and instead of the whole state it sends what changed since the previous one:
+//! the orders added to or removed from the queue, the robots and the results waiting for a screen, the free slots
+//! of the robots, the orders passed to other leader shards, the new states of the ledger and the counters of the shops
+//! if they changed. One of every `backup.full_every` backups is sent whole.
+//! A robot applies each change to the backup it has, and only takes the result for an election if it follows the
+//! previous backup it got and its digest matches the one of the leader, otherwise it asks the leader for a whole one.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::protocol::leader_backup::LeaderBackup;
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::order_ledger::OrderState;
+use crate::protocol::order_waiting::OrderWaiting;
+use crate::protocol::shop_counters::ShopCounters;
+
+/// An item of a list of the backup, known by the id of its order
+pub trait Keyed {
+    fn key(&self) -> &str;
+}
+
+impl Keyed for OrderInfo {
+    fn key(&self) -> &str {
+        &self.order_id
+    }
+}
+
+impl Keyed for OrderWaiting {
+    fn key(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Changes of a list of orders, the orders that left it and the ones added before and after the ones that stayed
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ListDelta<T> {
+    removed: Vec<String>,
+    front: Vec<T>,
+    back: Vec<T>,
+}
+
+impl<T: Keyed + Clone + PartialEq> ListDelta<T> {
+    /// Returns the changes from the old list to the new one, None if the orders that stayed were reordered
+    fn between(old: &[T], new: &[T]) -> Option<Self> {
+        let new_keys: HashSet<&str> = new.iter().map(|item| item.key()).collect();
+        let (kept, removed): (Vec<&T>, Vec<&T>) =
+            old.iter().partition(|item| new_keys.contains(item.key()));
+        let start = match kept.first() {
+            Some(first) => new.iter().position(|item| item.key() == first.key())?,
+            None => 0,
+        };
+        let end = start + kept.len();
+        if end > new.len() || !new[start..end].iter().eq(kept.iter().copied()) {
+            return None;
+        }
+        Some(Self {
+            removed: removed.iter().map(|item| item.key().to_string()).collect(),
+            front: new[..start].to_vec(),
+            back: new[end..].to_vec(),
+        })
+    }
+
+    fn apply(&self, list: Vec<T>) -> Vec<T> {
+        let removed: HashSet<&str> = self.removed.iter().map(String::as_str).collect();
+        let mut applied = self.front.clone();
+        applied.extend(
+            list.into_iter()
+                .filter(|item| !removed.contains(item.key())),
+        );
+        applied.extend(self.back.iter().cloned());
+        applied
+    }
+}
+
+/// What changed in the backup of the leader since the previous one
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BackupDelta {
+    available_robots: Option<Vec<usize>>,
+    screens: Option<Vec<usize>>,
+    queue: ListDelta<OrderInfo>,
+    robots_orders: Vec<(usize, ListDelta<OrderInfo>)>,
+    gone_robots: Vec<usize>,
+    waiting: ListDelta<OrderWaiting>,
+    routed: ListDelta<OrderInfo>,
+    ledger: Vec<(String, OrderState)>,
+    shops: Option<BTreeMap<String, ShopCounters>>,
+}
+
+impl BackupDelta {
+    /// Returns the changes from the old backup to the new one, None if they can not be told as changes
+    pub fn between(old: &LeaderBackup, new: &LeaderBackup) -> Option<Self> {
+        let old_queue: Vec<OrderInfo> = old.orders_on_queue.iter().cloned().collect();
+        let new_queue: Vec<OrderInfo> = new.orders_on_queue.iter().cloned().collect();
+        let mut robots_orders = Vec::new();
+        for (robot_id, orders) in &new.robots_orders {
+            let old_orders = old.robots_orders.get(robot_id);
+            if old_orders != Some(orders) {
+                let old_orders = old_orders.map_or(&[][..], |orders| &orders[..]);
+                robots_orders.push((*robot_id, ListDelta::between(old_orders, orders)?));
+            }
+        }
+        let delta = Self {
+            available_robots: changed(&old.available_robots, &new.available_robots),
+            screens: changed(&old.screens, &new.screens),
+            queue: ListDelta::between(&old_queue, &new_queue)?,
+            robots_orders,
+            gone_robots: old
+                .robots_orders
+                .keys()
+                .filter(|robot_id| !new.robots_orders.contains_key(robot_id))
+                .copied()
+                .collect(),
+            waiting: ListDelta::between(&old.orders_to_be_sent, &new.orders_to_be_sent)?,
+            routed: ListDelta::between(&old.routed, &new.routed)?,
+            ledger: new
+                .ledger
+                .entries()
+                .filter(|(order_id, state)| old.ledger.state(order_id).as_ref() != Some(*state))
+                .map(|(order_id, state)| (order_id.clone(), state.clone()))
+                .collect(),
+            shops: (old.shops != new.shops).then(|| new.shops.clone()),
+        };
+        // a change that does not give back the new backup, like an order moved inside a list, goes whole
+        match delta.apply(old) == *new {
+            true => Some(delta),
+            false => None,
+        }
+    }
+
+    /// Returns the backup with the changes applied
+    pub fn apply(&self, backup: &LeaderBackup) -> LeaderBackup {
+        let mut backup = backup.clone();
+        if let Some(available_robots) = &self.available_robots {
+            backup.available_robots = available_robots.clone();
+        }
+        if let Some(screens) = &self.screens {
+            backup.screens = screens.clone();
+        }
+        let queue: Vec<OrderInfo> = backup.orders_on_queue.into_iter().collect();
+        backup.orders_on_queue = VecDeque::from(self.queue.apply(queue));
+        for robot_id in &self.gone_robots {
+            backup.robots_orders.remove(robot_id);
+        }
+        for (robot_id, delta) in &self.robots_orders {
+            let orders = backup.robots_orders.remove(robot_id).unwrap_or_default();
+            backup.robots_orders.insert(*robot_id, delta.apply(orders));
+        }
+        backup.orders_to_be_sent = self.waiting.apply(backup.orders_to_be_sent);
+        backup.routed = self.routed.apply(backup.routed);
+        for (order_id, state) in &self.ledger {
+            backup.ledger.record(order_id, state.clone());
+        }
+        if let Some(shops) = &self.shops {
+            backup.shops = shops.clone();
+        }
+        backup
+    }
+}
+
+fn changed(old: &[usize], new: &[usize]) -> Option<Vec<usize>> {
+    match old == new {
+        true => None,
+        false => Some(new.to_vec()),
+    }
+}
+
+/// Returns a digest of the backup that does not depend on the order its maps are walked
+pub fn digest(backup: &LeaderBackup) -> [u8; 32] {
+    let mut robots_orders: Vec<(&usize, &Vec<OrderInfo>)> = backup.robots_orders.iter().collect();
+    robots_orders.sort_by_key(|(robot_id, _)| **robot_id);
+    let ledger: Vec<(&String, &OrderState)> = backup.ledger.entries().collect();
+    let canonical = (
+        &backup.available_robots,
+        &backup.orders_on_queue,
+        robots_orders,
+        &backup.screens,
+        &backup.orders_to_be_sent,
+        ledger,
+        &backup.shops,
+        &backup.routed,
+    );
+    let bytes = bincode::serialize(&canonical).unwrap_or_default();
+    Sha256::digest(bytes).into()
+}
+
+/// A backup as the leader sends it, the whole backup or the changes since the previous one with the digest of the result
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum BackupUpdate {
+    Full {
+        seq: u64,
+        backup: LeaderBackup,
+    },
+    Delta {
+        seq: u64,
+        delta: BackupDelta,
+        digest: [u8; 32],
+    },
+}
+
+impl BackupUpdate {
+    pub fn seq(&self) -> u64 {
+        match self {
+            BackupUpdate::Full { seq, .. } | BackupUpdate::Delta { seq, .. } => *seq,
+        }
+    }
+}
+
+/// Numbers the backups of the leader and turns them into updates, the first one and one of every `full_every` are whole
+#[derive(Debug)]
+pub struct BackupStream {
+    seq: u64,
+    last: Option<LeaderBackup>,
+    since_full: usize,
+    full_every: usize,
+}
+
+impl BackupStream {
+    pub fn new(full_every: usize) -> Self {
+        Self {
+            seq: 0,
+            last: None,
+            since_full: 0,
+            full_every,
+        }
+    }
+
+    /// Returns the update that takes the robots from the previous backup to this one
+    pub fn next(&mut self, backup: LeaderBackup) -> BackupUpdate {
+        self.seq += 1;
+        let delta = match &self.last {
+            Some(last) if self.since_full + 1 < self.full_every => {
+                BackupDelta::between(last, &backup)
+            }
+            _ => None,
+        };
+        let update = match delta {
+            Some(delta) => {
+                self.since_full += 1;
+                BackupUpdate::Delta {
+                    seq: self.seq,
+                    digest: digest(&backup),
+                    delta,
+                }
+            }
+            None => {
+                self.since_full = 0;
+                BackupUpdate::Full {
+                    seq: self.seq,
+                    backup: backup.clone(),
+                }
+            }
+        };
+        self.last = Some(backup);
+        update
+    }
+
+    /// Returns the last backup whole, for a robot that does not have the previous ones
+    pub fn full(&self) -> Option<BackupUpdate> {
+        self.last.as_ref().map(|backup| BackupUpdate::Full {
+            seq: self.seq,
+            backup: backup.clone(),
+        })
+    }
+}
+
+/// Error type for an update that can not be applied to the backup of the robot
+#[derive(Debug, PartialEq)]
+pub enum BackupDeltaError {
+    /// The update does not follow the last one the robot applied
+    Gap { expected: Option<u64>, got: u64 },
+    /// The backup the changes were applied to is not the one of the leader
+    DigestMismatch { seq: u64 },
+}
+
+impl fmt::Display for BackupDeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupDeltaError::Gap {
+                expected: Some(expected),
+                got,
+            } => write!(f, "Expected backup {}, got backup {}", expected, got),
+            BackupDeltaError::Gap {
+                expected: None,
+                got,
+            } => write!(
+                f,
+                "Got the changes of backup {} without a whole backup",
+                got
+            ),
+            BackupDeltaError::DigestMismatch { seq } => {
+                write!(f, "Backup {} does not match the one of the leader", seq)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BackupDeltaError {}
+
+/// Rebuilds the backups of the leader of an epoch from its updates, on the robots
+#[derive(Debug, Default)]
+pub struct BackupAssembler {
+    last: Option<(u64, u64)>,
+    asked_for_full: bool,
+}
+
+impl BackupAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the backup the update of the leader of the epoch gives, applied to the current one if it has changes.
+    /// After an error only a whole backup is taken
+    pub fn apply(
+        &mut self,
+        current: Option<&LeaderBackup>,
+        epoch: u64,
+        update: BackupUpdate,
+    ) -> Result<LeaderBackup, BackupDeltaError> {
+        let seq = update.seq();
+        let backup = match update {
+            BackupUpdate::Full { backup, .. } => backup,
+            BackupUpdate::Delta { delta, digest, .. } => {
+                let expected = self
+                    .last
+                    .filter(|(last_epoch, _)| *last_epoch == epoch)
+                    .map(|(_, last_seq)| last_seq + 1);
+                let current = match current {
+                    Some(current) if expected == Some(seq) => current,
+                    _ => {
+                        self.last = None;
+                        return Err(BackupDeltaError::Gap { expected, got: seq });
+                    }
+                };
+                let backup = delta.apply(current);
+                if self::digest(&backup) != digest {
+                    self.last = None;
+                    return Err(BackupDeltaError::DigestMismatch { seq });
+                }
+                backup
+            }
+        };
+        self.last = Some((epoch, seq));
+        self.asked_for_full = false;
+        Ok(backup)
+    }
+
+    /// Returns true the first time it is called after an error, so the leader is asked once for a whole backup
+    pub fn ask_for_full(&mut self) -> bool {
+        !std::mem::replace(&mut self.asked_for_full, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use crate::protocol::order_ledger::OrderLedger;
+    use std::collections::HashMap;
+
+    fn order(id: &str) -> OrderInfo {
+        OrderInfo {
+            order: Order::new_cuarto(vec![FlavorID::Mint]).unwrap(),
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms: None,
+            source: None,
+        }
+    }
+
+    /// The ledger of the leader only grows, so the orders are seen in the order of their ids
+    fn backup(queue: &[&str], robot_1: &[&str]) -> LeaderBackup {
+        let mut ids: Vec<&str> = queue.iter().chain(robot_1).copied().collect();
+        ids.sort();
+        let mut ledger = OrderLedger::new();
+        for id in ids {
+            ledger.created(id);
+        }
+        LeaderBackup::new(
+            vec![1, 2],
+            vec![0],
+            queue.iter().map(|id| order(id)).collect(),
+            HashMap::from([(1, robot_1.iter().map(|id| order(id)).collect())]),
+            vec![],
+            ledger,
+        )
+    }
+
+    #[test]
+    fn test_changes_give_back_the_new_backup() {
+        let old = backup(&["a", "b", "c"], &[]);
+        let mut new = backup(&["z", "b", "c", "d"], &["a"]);
+        new.available_robots = vec![2];
+        new.ledger.completed("a");
+        new.shops
+            .entry("centro".to_string())
+            .or_default()
+            .reserved
+            .insert("Mint".to_string(), 250);
+        new.routed.push(order("r"));
+
+        let delta = BackupDelta::between(&old, &new).unwrap();
+        assert_eq!(delta.apply(&old), new);
+        assert_eq!(digest(&delta.apply(&old)), digest(&new));
+
+        let reordered = backup(&["c", "b"], &[]);
+        assert_eq!(BackupDelta::between(&old, &reordered), None);
+    }
+
+    #[test]
+    fn test_one_of_every_full_every_backups_is_whole() {
+        let mut stream = BackupStream::new(3);
+        let queue = ["a", "b", "c", "d"];
+        let kinds: Vec<bool> = (1..=queue.len())
+            .map(|len| match stream.next(backup(&queue[..len], &[])) {
+                BackupUpdate::Full { .. } => true,
+                BackupUpdate::Delta { .. } => false,
+            })
+            .collect();
+        assert_eq!(kinds, vec![true, false, false, true]);
+        assert_eq!(stream.full().map(|full| full.seq()), Some(4));
+    }
+
+    #[test]
+    fn test_robot_only_applies_changes_that_follow_its_backup() {
+        let mut stream = BackupStream::new(10);
+        let mut assembler = BackupAssembler::new();
+        let first = stream.next(backup(&["a"], &[]));
+        let second = stream.next(backup(&["a", "b"], &[]));
+        let third = stream.next(backup(&["b"], &["a"]));
+
+        let current = assembler.apply(None, 1, first).unwrap();
+        assert_eq!(
+            assembler.apply(Some(&current), 1, third.clone()),
+            Err(BackupDeltaError::Gap {
+                expected: Some(2),
+                got: 3
+            })
+        );
+        assert!(assembler.ask_for_full());
+        assert!(!assembler.ask_for_full());
+        assert_eq!(
+            assembler.apply(Some(&current), 1, second),
+            Err(BackupDeltaError::Gap {
+                expected: None,
+                got: 2
+            })
+        );
+
+        let current = assembler.apply(None, 1, stream.full().unwrap()).unwrap();
+        assert_eq!(current, backup(&["b"], &["a"]));
+        let fourth = stream.next(backup(&[], &["a", "b"]));
+        assert_eq!(
+            assembler.apply(Some(&current), 1, fourth),
+            Ok(backup(&[], &["a", "b"]))
+        );
+        assert!(assembler.ask_for_full());
+    }
+}
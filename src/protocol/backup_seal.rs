@@ -0,0 +1,247 @@
+//! Sealing of the backups the leader sends to the robots, whole or as the changes since the previous one.
+//! A backup is signed with an HMAC-SHA256 of the shared key, and can be encrypted with ChaCha20 before it is signed,
+//! so a robot only takes for an election a backup that a leader with the key sent.
+//! It is compressed before it is encrypted, encrypted bytes do not compress.
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::config::security::SecurityConfig;
+use crate::protocol::compression::Compression;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_SIZE: usize = 12;
+
+/// Error type for a backup that can not be sealed or opened
+#[derive(Debug, PartialEq)]
+pub enum BackupSealError {
+    ErrorParsing(String),
+    /// The backup has no signature, and the robot has a key
+    Unsigned,
+    /// The signature does not match, the backup was changed or signed with another key
+    InvalidSignature,
+    /// The backup is encrypted, and the robot has no key to decrypt it
+    MissingKey,
+}
+
+impl fmt::Display for BackupSealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupSealError::ErrorParsing(err) => write!(f, "Could not parse backup: {}", err),
+            BackupSealError::Unsigned => write!(f, "The backup is not signed"),
+            BackupSealError::InvalidSignature => {
+                write!(f, "The signature of the backup is not valid")
+            }
+            BackupSealError::MissingKey => write!(f, "The backup is encrypted and there is no key"),
+        }
+    }
+}
+
+impl std::error::Error for BackupSealError {}
+
+/// A backup as it travels to the robots, its payload is the encoded backup, compressed with `compression` and
+/// encrypted if `nonce` is set. The signature covers the epoch of the leader too, so a backup can not be passed as
+/// one of another epoch
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SealedBackup {
+    payload: Vec<u8>,
+    nonce: Option<[u8; NONCE_SIZE]>,
+    signature: Option<Vec<u8>>,
+    #[serde(default)]
+    compression: Compression,
+}
+
+impl SealedBackup {
+    /// Encodes and compresses the backup, encrypting and signing it if the config has a key
+    pub fn seal<T: Serialize>(
+        backup: &T,
+        epoch: u64,
+        security: &SecurityConfig,
+        compression: Compression,
+    ) -> Result<Self, BackupSealError> {
+        let encoded = bincode::serialize(backup)
+            .map_err(|err| BackupSealError::ErrorParsing(err.to_string()))?;
+        let mut payload = compression
+            .compress(&encoded)
+            .map_err(|err| BackupSealError::ErrorParsing(err.to_string()))?;
+        let key = match &security.backup_key {
+            Some(key) => key,
+            None => {
+                return Ok(Self {
+                    payload,
+                    nonce: None,
+                    signature: None,
+                    compression,
+                })
+            }
+        };
+        let nonce = if security.encrypt_backups {
+            let nonce: [u8; NONCE_SIZE] = rand::random();
+            apply_cipher(key, &nonce, &mut payload);
+            Some(nonce)
+        } else {
+            None
+        };
+        let mut sealed = Self {
+            payload,
+            nonce,
+            signature: None,
+            compression,
+        };
+        sealed.signature = Some(sealed.mac(key, epoch).finalize().into_bytes().to_vec());
+        Ok(sealed)
+    }
+
+    /// Checks the signature and decrypts the backup.
+    /// A robot with a key only opens backups signed with it, a robot without one can not open encrypted backups
+    pub fn open<T: DeserializeOwned>(
+        &self,
+        epoch: u64,
+        security: &SecurityConfig,
+    ) -> Result<T, BackupSealError> {
+        let mut payload = self.payload.clone();
+        if let Some(key) = &security.backup_key {
+            let signature = self.signature.as_ref().ok_or(BackupSealError::Unsigned)?;
+            self.mac(key, epoch)
+                .verify_slice(signature)
+                .map_err(|_| BackupSealError::InvalidSignature)?;
+            if let Some(nonce) = &self.nonce {
+                apply_cipher(key, nonce, &mut payload);
+            }
+        } else if self.nonce.is_some() {
+            return Err(BackupSealError::MissingKey);
+        }
+        let payload = self
+            .compression
+            .decompress(&payload)
+            .map_err(|err| BackupSealError::ErrorParsing(err.to_string()))?;
+        bincode::deserialize(&payload).map_err(|err| BackupSealError::ErrorParsing(err.to_string()))
+    }
+
+    /// Returns how many bytes of backup travel, after it was compressed
+    pub fn payload_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    /// Returns the HMAC of the epoch, the compression, the nonce and the payload, ready to be finished or verified.
+    /// Without compression it is left out, so the signature is the same as before backups were compressed
+    fn mac(&self, key: &str, epoch: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&derive_key(key, b"mac"))
+            .expect("HMAC takes keys of any size");
+        mac.update(&epoch.to_le_bytes());
+        if self.compression != Compression::None {
+            mac.update(&[self.compression as u8]);
+        }
+        if let Some(nonce) = &self.nonce {
+            mac.update(nonce);
+        }
+        mac.update(&self.payload);
+        mac
+    }
+}
+
+/// Derives a key for each use of the shared key, so the same bytes are not used to encrypt and to sign
+fn derive_key(key: &str, purpose: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(purpose);
+    hasher.update(key.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Encrypts or decrypts the payload in place, ChaCha20 does the same both ways
+fn apply_cipher(key: &str, nonce: &[u8; NONCE_SIZE], payload: &mut [u8]) {
+    let key = derive_key(key, b"encryption");
+    let mut cipher = ChaCha20::new(&key.into(), nonce.into());
+    cipher.apply_keystream(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::leader_backup::LeaderBackup;
+    use crate::protocol::order_ledger::OrderLedger;
+    use std::collections::{HashMap, VecDeque};
+
+    fn backup() -> LeaderBackup {
+        LeaderBackup::new(
+            vec![1, 2],
+            vec![0],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        )
+    }
+
+    fn security(key: Option<&str>, encrypt_backups: bool) -> SecurityConfig {
+        SecurityConfig {
+            backup_key: key.map(|key| key.to_string()),
+            encrypt_backups,
+        }
+    }
+
+    #[test]
+    fn test_signed_backup_is_only_opened_with_its_key_and_epoch() {
+        let with_key = security(Some("secret"), false);
+        let sealed = SealedBackup::seal(&backup(), 3, &with_key, Compression::None).unwrap();
+        assert_eq!(sealed.open(3, &with_key), Ok(backup()));
+        assert_eq!(
+            sealed.open::<LeaderBackup>(4, &with_key),
+            Err(BackupSealError::InvalidSignature)
+        );
+        assert_eq!(
+            sealed.open::<LeaderBackup>(3, &security(Some("other"), false)),
+            Err(BackupSealError::InvalidSignature)
+        );
+
+        let unsigned =
+            SealedBackup::seal(&backup(), 3, &security(None, false), Compression::None).unwrap();
+        assert_eq!(
+            unsigned.open::<LeaderBackup>(3, &with_key),
+            Err(BackupSealError::Unsigned)
+        );
+    }
+
+    #[test]
+    fn test_encrypted_backup_can_not_be_read_or_changed() {
+        let encrypted = security(Some("secret"), true);
+        let sealed = SealedBackup::seal(&backup(), 1, &encrypted, Compression::None).unwrap();
+        assert_ne!(sealed.payload, bincode::serialize(&backup()).unwrap());
+        assert_eq!(sealed.open(1, &encrypted), Ok(backup()));
+        assert_eq!(
+            sealed.open::<LeaderBackup>(1, &security(None, false)),
+            Err(BackupSealError::MissingKey)
+        );
+
+        let mut tampered = sealed.clone();
+        tampered.payload[0] ^= 1;
+        assert_eq!(
+            tampered.open::<LeaderBackup>(1, &encrypted),
+            Err(BackupSealError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_compressed_backup_is_opened_and_its_compression_is_signed() {
+        let encrypted = security(Some("secret"), true);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let sealed = SealedBackup::seal(&backup(), 2, &encrypted, compression).unwrap();
+            assert_eq!(sealed.open(2, &encrypted), Ok(backup()));
+
+            let mut tampered = sealed.clone();
+            tampered.compression = Compression::None;
+            assert_eq!(
+                tampered.open::<LeaderBackup>(2, &encrypted),
+                Err(BackupSealError::InvalidSignature)
+            );
+        }
+        let plain = SealedBackup::seal(&backup(), 2, &security(None, false), Compression::Zstd);
+        assert_eq!(plain.unwrap().open(2, &security(None, false)), Ok(backup()));
+    }
+}
@@ -0,0 +1,87 @@
+//! Compression of the backups on the wire, the leader backups sent to the robots and the screen backups sent
+//! to the next screen. Each side tells the other which ones it can read when they connect, and the sender only
+//! compresses with the one in its config if the other side can read it.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// Level used by zstd, its default one
+const ZSTD_LEVEL: i32 = 0;
+
+/// Algorithm a backup is compressed with
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Every algorithm this process can read
+    pub const SUPPORTED: [Compression; 3] =
+        [Compression::None, Compression::Gzip, Compression::Zstd];
+
+    /// Returns the algorithm to send with, the preferred one if the other side can read it or none otherwise
+    pub fn negotiate(preferred: Compression, accepted: &[Compression]) -> Compression {
+        match accepted.contains(&preferred) {
+            true => preferred,
+            false => Compression::None,
+        }
+    }
+
+    /// Compresses the bytes, without compression they are returned as they are
+    pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL),
+        }
+    }
+
+    /// Decompresses bytes compressed with this algorithm
+    pub fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Compression::Zstd => zstd::decode_all(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_bytes_come_back_the_same_and_smaller() {
+        let bytes = "Chocolate Mint Lemon ".repeat(500).into_bytes();
+        for compression in Compression::SUPPORTED {
+            let compressed = compression.compress(&bytes).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < bytes.len() / 10);
+            }
+            assert_eq!(compression.decompress(&compressed).unwrap(), bytes);
+        }
+        assert!(Compression::Gzip.decompress(&bytes).is_err());
+        assert_eq!(
+            Compression::negotiate(Compression::Zstd, &[Compression::None]),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::negotiate(Compression::Gzip, &Compression::SUPPORTED),
+            Compression::Gzip
+        );
+    }
+}
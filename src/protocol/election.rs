@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Id of an election round, the epoch the elected leader will have and the robot that started it.
+/// Rounds are ordered by epoch and then by initiator, so of several rounds started at once only the greatest completes
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ElectionId {
+    pub epoch: u64,
+    pub initiator: usize,
+}
+
+impl fmt::Display for ElectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.epoch, self.initiator)
+    }
+}
@@ -0,0 +1,410 @@
+use crate::common::flavor_id::FlavorID;
+use crate::common::log;
+use crate::config::flavors::FlavorCatalog;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies a token, the stock of a flavor can be split into many tokens, each one with its shard number
+pub type TokenKey = (FlavorID, usize);
+
+/// Error type for grams a token can not give
+#[derive(Debug, PartialEq, Eq)]
+pub enum StockError {
+    /// The token has fewer grams than the ones asked
+    NotEnough { asked: usize, left: usize },
+}
+
+impl fmt::Display for StockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StockError::NotEnough { asked, left } => {
+                write!(f, "Asked for {} grams, only {} left", asked, left)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StockError {}
+
+/// Most hops a token remembers, the oldest ones are overwritten
+pub const MAX_TOKEN_HOPS: usize = 16;
+
+/// A robot the token went through and when it got there, in milliseconds since the Unix epoch
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenHop {
+    pub robot_id: usize,
+    pub at_ms: u64,
+}
+
+/// The last hops of a token, kept in a fixed array so the token does not grow as it moves around
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HopLog {
+    hops: [TokenHop; MAX_TOKEN_HOPS],
+    recorded: usize,
+}
+
+impl HopLog {
+    /// Records a hop, overwriting the oldest one if the log is full
+    pub fn record(&mut self, hop: TokenHop) {
+        self.hops[self.recorded % MAX_TOKEN_HOPS] = hop;
+        self.recorded += 1;
+    }
+
+    /// Returns the hops remembered, from the oldest to the newest
+    pub fn hops(&self) -> Vec<TokenHop> {
+        let start = self.recorded.saturating_sub(MAX_TOKEN_HOPS);
+        (start..self.recorded)
+            .map(|hop| self.hops[hop % MAX_TOKEN_HOPS])
+            .collect()
+    }
+
+    /// Returns the robots the token went through since it last left the robot, if its last hop is the robot.
+    /// None if the robot is not in the hops before, like when the ring is longer than the log
+    pub fn round_of(&self, robot_id: usize) -> Option<Vec<usize>> {
+        let hops = self.hops();
+        let (last, earlier) = hops.split_last()?;
+        if last.robot_id != robot_id {
+            return None;
+        }
+        let left = earlier.iter().rposition(|hop| hop.robot_id == robot_id)?;
+        Some(earlier[left + 1..].iter().map(|hop| hop.robot_id).collect())
+    }
+}
+
+/// Grams of a token that a robot holds for an order until every flavor of the order is reserved, when it scoops them.
+/// It records when it was made, so the reservation of a robot that died does not hold the grams forever
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reservation {
+    pub robot_id: usize,
+    pub order_id: String,
+    pub amount: usize,
+    pub at_ms: u64,
+}
+
+/// Struct that represents a Flavor Token
+/// The epoch grows every time the token is recovered, so stale copies can be told apart.
+/// It carries the last robots it went through, to trace how it moves around the ring,
+/// whether a robot needed it since the round of the ring started, so an idle token can be paced,
+/// and the grams reserved by the robots, that no other order can take
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FlavorToken {
+    id: FlavorID,
+    shard: usize,
+    amount: usize,
+    epoch: u64,
+    #[serde(default)]
+    hops: HopLog,
+    #[serde(default)]
+    worked: bool,
+    #[serde(default)]
+    reservations: Vec<Reservation>,
+}
+
+/// Milliseconds since the Unix epoch
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Grams that the given shard of a flavor starts with, the ones that can not be split evenly go to the first shard
+pub fn shard_amount(total: usize, shards: usize, shard: usize) -> usize {
+    let shards = shards.max(1);
+    let amount = total / shards;
+    if shard == 0 {
+        amount + total % shards
+    } else {
+        amount
+    }
+}
+
+impl FlavorToken {
+    pub fn new(id: FlavorID, amount: usize) -> Self {
+        Self::with_epoch(id, amount, 0)
+    }
+
+    /// Creates a token recovered in the given epoch
+    pub fn with_epoch(id: FlavorID, amount: usize, epoch: u64) -> Self {
+        Self {
+            id,
+            shard: 0,
+            amount,
+            epoch,
+            hops: HopLog::default(),
+            worked: false,
+            reservations: Vec::new(),
+        }
+    }
+
+    /// Sets the shard of the flavor that the token is
+    pub fn in_shard(mut self, shard: usize) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    /// Creates the tokens of each flavor of the catalog, its initial amount is split between them
+    pub fn from_catalog(catalog: &FlavorCatalog, per_flavor: usize) -> Vec<Self> {
+        catalog
+            .flavors()
+            .iter()
+            .flat_map(|stock| {
+                (0..per_flavor.max(1)).map(move |shard| {
+                    Self::new(
+                        stock.flavor.clone(),
+                        shard_amount(stock.amount, per_flavor, shard),
+                    )
+                    .in_shard(shard)
+                })
+            })
+            .collect()
+    }
+
+    /// Serializes the FlavorToken into a string
+    pub fn serialize(&self) -> String {
+        format!("{},{},", self.id, self.amount)
+    }
+
+    /// Serializes the FlavorToken into a byte vector
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.serialize().into_bytes()
+    }
+
+    /// Deserializes a string into a FlavorToken
+    pub fn decode(instance: String) -> Option<Self> {
+        let parts: Vec<&str> = instance.split(',').collect();
+
+        if parts.len() != 3 {
+            log::error("FT", "Error No se pudo deserializar correctamente");
+            return None; // Error No se pudo deserializar correctamente
+        }
+
+        Some(Self::new(
+            FlavorID::from_str(parts[0]).ok()?,
+            parts[1].trim_end_matches('\n').parse::<usize>().ok()?,
+        ))
+    }
+
+    /// Serves a certain amount of ice cream, returns the grams left or an error if the token has fewer grams than asked
+    pub fn try_serve(&mut self, serve_amount: usize) -> Result<usize, StockError> {
+        self.amount = self
+            .amount
+            .checked_sub(serve_amount)
+            .ok_or(StockError::NotEnough {
+                asked: serve_amount,
+                left: self.amount,
+            })?;
+        Ok(self.amount)
+    }
+
+    /// Adds a certain amount of ice cream, when the flavor is restocked
+    pub fn restock(&mut self, amount: usize) {
+        self.amount += amount;
+    }
+
+    /// Lowers the amount if the given one is smaller, when a stale copy of the token is merged
+    pub fn keep_lowest_amount(&mut self, amount: usize) {
+        self.amount = self.amount.min(amount);
+    }
+
+    /// Check if the FlavorToken can serve a certain amount of ice cream, without the grams reserved.
+    /// The last grams it has can be served
+    pub fn can_serve(&self, serve_amount: usize) -> bool {
+        serve_amount <= self.available()
+    }
+
+    /// Get the amount of ice cream that is not reserved
+    pub fn available(&self) -> usize {
+        let reserved: usize = self.reservations.iter().map(|r| r.amount).sum();
+        self.amount.saturating_sub(reserved)
+    }
+
+    /// Reserves an amount for an order of the robot, the caller checks that it can be served
+    pub fn reserve(&mut self, robot_id: usize, order_id: &str, amount: usize) {
+        self.reservations.push(Reservation {
+            robot_id,
+            order_id: order_id.to_string(),
+            amount,
+            at_ms: now_ms(),
+        });
+    }
+
+    /// Releases the reservation of an order of the robot, returns the amount it held if it had one
+    pub fn release(&mut self, robot_id: usize, order_id: &str) -> Option<usize> {
+        let i = self
+            .reservations
+            .iter()
+            .position(|r| r.robot_id == robot_id && r.order_id == order_id)?;
+        Some(self.reservations.remove(i).amount)
+    }
+
+    /// Releases the reservations older than the timeout, 0 never releases them. Returns how many were released
+    pub fn release_expired(&mut self, timeout_ms: u64) -> usize {
+        if timeout_ms == 0 {
+            return 0;
+        }
+        let now = now_ms();
+        self.release_where(|r| now.saturating_sub(r.at_ms) > timeout_ms)
+    }
+
+    /// Releases the reservations that match, returns how many were released
+    pub fn release_where<F: FnMut(&Reservation) -> bool>(&mut self, mut released: F) -> usize {
+        let before = self.reservations.len();
+        self.reservations.retain(|r| !released(r));
+        before - self.reservations.len()
+    }
+
+    /// Get the ID of the FlavorToken
+    pub fn get_id(&self) -> FlavorID {
+        self.id.clone()
+    }
+
+    /// Get the amount of ice cream the FlavorToken has
+    pub fn get_amnt(&self) -> usize {
+        self.amount
+    }
+
+    /// Get the recovery epoch of the FlavorToken
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Get the shard of its flavor that the FlavorToken is
+    pub fn get_shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Returns the other robots of the ring, the ones the token went through since it left the robot
+    pub fn round_of(&self, robot_id: usize) -> Option<Vec<usize>> {
+        self.hops.round_of(robot_id)
+    }
+
+    /// Records that the token got to the robot now
+    pub fn record_hop(&mut self, robot_id: usize) {
+        self.hops.record(TokenHop {
+            robot_id,
+            at_ms: now_ms(),
+        });
+    }
+
+    /// Marks that a robot needed the token in this round of the ring
+    pub fn mark_worked(&mut self) {
+        self.worked = true;
+    }
+
+    /// Returns whether a robot needed the token in this round of the ring, and starts a new round
+    pub fn take_worked(&mut self) -> bool {
+        std::mem::take(&mut self.worked)
+    }
+
+    /// Get the last hops of the FlavorToken, from the oldest to the newest
+    pub fn get_hops(&self) -> Vec<TokenHop> {
+        self.hops.hops()
+    }
+
+    /// Get the key that identifies the FlavorToken
+    pub fn key(&self) -> TokenKey {
+        (self.id.clone(), self.shard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::flavors::FlavorStock;
+
+    #[test]
+    fn test_decodes_what_it_serializes() {
+        for flavor in [FlavorID::Lemon, FlavorID::Custom("Peach".to_string())] {
+            let token = FlavorToken::new(flavor, 300);
+            assert_eq!(FlavorToken::decode(token.serialize()), Some(token));
+        }
+        assert_eq!(FlavorToken::decode("Lemon,300".to_string()), None);
+    }
+
+    #[test]
+    fn test_stock_is_split_between_the_tokens_of_a_flavor() {
+        let catalog = FlavorCatalog::new(vec![FlavorStock::new(FlavorID::Mint, 1001)]);
+        let tokens = FlavorToken::from_catalog(&catalog, 2);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].key(), (FlavorID::Mint, 0));
+        assert_eq!(tokens[0].get_amnt(), 501);
+        assert_eq!(tokens[1].key(), (FlavorID::Mint, 1));
+        assert_eq!(tokens[1].get_amnt(), 500);
+    }
+
+    #[test]
+    fn test_reserved_grams_can_not_be_served() {
+        let mut token = FlavorToken::new(FlavorID::Mint, 500);
+        token.reserve(1, "a", 300);
+        assert_eq!(token.available(), 200);
+        assert!(!token.can_serve(250));
+        assert_eq!(token.release(2, "a"), None);
+        assert_eq!(token.release(1, "a"), Some(300));
+        assert!(token.can_serve(250));
+
+        token.reserve(1, "b", 100);
+        assert!(token.can_serve(400));
+        assert!(!token.can_serve(401));
+        assert_eq!(token.release_expired(0), 0);
+        assert_eq!(token.release_where(|r| r.order_id == "b"), 1);
+        assert_eq!(token.available(), 500);
+    }
+
+    #[test]
+    fn test_a_token_serves_up_to_its_last_gram() {
+        let mut token = FlavorToken::new(FlavorID::Mint, 500);
+        assert_eq!(token.try_serve(250), Ok(250));
+        assert_eq!(
+            token.try_serve(300),
+            Err(StockError::NotEnough {
+                asked: 300,
+                left: 250
+            })
+        );
+        assert_eq!(token.get_amnt(), 250);
+        assert!(token.can_serve(250));
+        assert_eq!(token.try_serve(250), Ok(0));
+    }
+
+    #[test]
+    fn test_work_is_forgotten_when_a_round_starts() {
+        let mut token = FlavorToken::new(FlavorID::Mint, 100);
+        assert!(!token.take_worked());
+        token.mark_worked();
+        assert!(token.take_worked());
+        assert!(!token.take_worked());
+    }
+
+    #[test]
+    fn test_only_the_last_hops_are_kept() {
+        let mut log = HopLog::default();
+        assert!(log.hops().is_empty());
+        for robot_id in 0..MAX_TOKEN_HOPS + 3 {
+            log.record(TokenHop {
+                robot_id,
+                at_ms: robot_id as u64,
+            });
+        }
+        let hops = log.hops();
+        assert_eq!(hops.len(), MAX_TOKEN_HOPS);
+        assert_eq!(hops[0].robot_id, 3);
+        assert_eq!(hops[MAX_TOKEN_HOPS - 1].robot_id, MAX_TOKEN_HOPS + 2);
+    }
+
+    #[test]
+    fn test_the_round_is_read_from_the_hops() {
+        let mut token = FlavorToken::new(FlavorID::Mint, 100);
+        token.record_hop(1);
+        assert_eq!(token.round_of(1), None);
+        for robot_id in [3, 0, 1] {
+            token.record_hop(robot_id);
+        }
+        assert_eq!(token.round_of(1), Some(vec![3, 0]));
+        assert_eq!(token.round_of(0), None);
+        token.record_hop(1);
+        assert_eq!(token.round_of(1), Some(vec![]));
+    }
+}
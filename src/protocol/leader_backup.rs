@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::order_ledger::OrderLedger;
+use crate::protocol::order_waiting::OrderWaiting;
+use crate::protocol::shop_counters::ShopCounters;
+
+/// Struct to store the leader backup information
+/// Allows a new leader to recover the previous leader state
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LeaderBackup {
+    pub available_robots: Vec<usize>,
+    pub orders_on_queue: VecDeque<OrderInfo>,
+    pub robots_orders: HashMap<usize, Vec<OrderInfo>>,
+    pub screens: Vec<usize>,
+    pub orders_to_be_sent: Vec<OrderWaiting>,
+    pub ledger: OrderLedger,
+    #[serde(default)]
+    pub shops: BTreeMap<String, ShopCounters>,
+    /// Orders with flavors of other leader shards, whose parts were passed to the leaders of those shards
+    #[serde(default)]
+    pub routed: Vec<OrderInfo>,
+}
+
+impl LeaderBackup {
+    pub fn new(
+        available_robots: Vec<usize>,
+        screens: Vec<usize>,
+        orders_on_queue: VecDeque<OrderInfo>,
+        robots_orders: HashMap<usize, Vec<OrderInfo>>,
+        orders_to_be_sent: Vec<OrderWaiting>,
+        ledger: OrderLedger,
+    ) -> Self {
+        Self {
+            available_robots,
+            orders_on_queue,
+            robots_orders,
+            screens,
+            orders_to_be_sent,
+            ledger,
+            shops: BTreeMap::new(),
+            routed: Vec::new(),
+        }
+    }
+
+    /// Sets the counters of the shops the leader keeps
+    pub fn with_shops(mut self, shops: BTreeMap<String, ShopCounters>) -> Self {
+        self.shops = shops;
+        self
+    }
+
+    /// Sets the orders whose parts were passed to the leaders of other shards
+    pub fn with_routed(mut self, routed: Vec<OrderInfo>) -> Self {
+        self.routed = routed;
+        self
+    }
+}
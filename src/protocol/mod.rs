@@ -0,0 +1,661 @@
+//! This module contains the wire types of the system: the commands between the robots, the messages between
+//! the screens and the leader, and the backups and tokens they carry.
+//! It only depends on `common` and `config`, so a dashboard or a test harness can parse the protocol without the
+//! actors, by building the crate with `--no-default-features`.
+//! Any change to how one of these types is serialized has to bump `PROTOCOL_VERSION`, the tests pin the encoding.
+//! Every connection between two processes starts with the version, so a peer that speaks another one is turned down
+//! instead of failing to parse its messages later.
+
+pub mod backup_delta;
+pub mod backup_seal;
+pub mod compression;
+pub mod election;
+pub mod flavor_token;
+pub mod leader_backup;
+pub mod order_info;
+pub mod order_ledger;
+pub mod order_waiting;
+pub mod robot_command;
+pub mod robot_messages;
+pub mod screen_messages;
+pub mod shop_counters;
+pub mod token_backup;
+
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Version of the encoding of the wire types, every process of the system has to speak the same one
+pub const PROTOCOL_VERSION: u32 = 15;
+
+/// Writes the version of the protocol this process speaks, the first bytes of every handshake
+pub async fn write_version<W: AsyncWrite + Unpin>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&PROTOCOL_VERSION.to_be_bytes()).await
+}
+
+/// Reads the version of the protocol the peer speaks, it is an error if it is not the one of this process
+pub async fn read_version<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<()> {
+    let mut version = [0; 4];
+    reader.read_exact(&mut version).await?;
+    match u32::from_be_bytes(version) {
+        PROTOCOL_VERSION => Ok(()),
+        theirs => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "it speaks version {} of the protocol and this process speaks version {}",
+                theirs, PROTOCOL_VERSION
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::codec::{BinaryCodec, Codec, LineCodec};
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::{Order, Substitution};
+    use crate::common::order_source::{OrderChannel, OrderSource};
+    use crate::config::security::SecurityConfig;
+    use crate::protocol::backup_seal::SealedBackup;
+    use crate::protocol::compression::Compression;
+    use crate::protocol::election::ElectionId;
+    use crate::protocol::flavor_token::FlavorToken;
+    use crate::protocol::leader_backup::LeaderBackup;
+    use crate::protocol::order_info::OrderInfo;
+    use crate::protocol::order_ledger::OrderLedger;
+    use crate::protocol::robot_command::RobotCommand;
+    use crate::protocol::robot_messages::{AbortReason, RobotMessage};
+    use crate::protocol::screen_messages::{BackupView, ScreenMessage};
+    use crate::protocol::shop_counters::ShopCounters;
+    use crate::protocol::token_backup::TokenBackup;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::collections::{BTreeMap, HashMap, VecDeque};
+    use std::fmt::Debug;
+
+    const CASES: usize = 200;
+
+    fn flavor(rng: &mut StdRng) -> FlavorID {
+        match rng.gen_range(0..=FlavorID::STANDARD.len()) {
+            i if i < FlavorID::STANDARD.len() => FlavorID::STANDARD[i].clone(),
+            _ => FlavorID::Custom(format!("Custom {}", rng.gen::<u16>())),
+        }
+    }
+
+    fn flavors(rng: &mut StdRng, max: usize) -> Vec<FlavorID> {
+        (0..rng.gen_range(1..=max)).map(|_| flavor(rng)).collect()
+    }
+
+    fn order(rng: &mut StdRng) -> Order {
+        match rng.gen_range(0..4) {
+            0 => Order::new_cucurucho(flavor(rng)),
+            1 => Order::new_cuarto(flavors(rng, 2)).unwrap(),
+            2 => Order::new_medio(flavors(rng, 3)).unwrap(),
+            _ => Order::new_kilo(flavors(rng, 4)).unwrap(),
+        }
+    }
+
+    fn order_id(rng: &mut StdRng) -> String {
+        format!("{:x}", rng.gen::<u64>())
+    }
+
+    fn source(rng: &mut StdRng) -> Option<OrderSource> {
+        rng.gen_bool(0.5).then(|| OrderSource {
+            screen_id: rng.gen_range(0..8),
+            channel: [
+                OrderChannel::File,
+                OrderChannel::Terminal,
+                OrderChannel::Api,
+                OrderChannel::Failover,
+            ][rng.gen_range(0..4)],
+            placed_at_ms: rng.gen(),
+            trace_id: format!("{:016x}", rng.gen::<u64>()),
+            shop: rng
+                .gen_bool(0.5)
+                .then(|| format!("shop {}", rng.gen::<u8>())),
+        })
+    }
+
+    fn reason(rng: &mut StdRng) -> AbortReason {
+        [
+            AbortReason::OutOfStock,
+            AbortReason::ScoopFailed,
+            AbortReason::TimedOut,
+            AbortReason::LowStock,
+            AbortReason::UnknownFlavor,
+            AbortReason::NoFlavors,
+            AbortReason::WrongSize,
+            AbortReason::OverQuota,
+        ][rng.gen_range(0..8)]
+    }
+
+    fn substitutions(rng: &mut StdRng) -> Vec<Substitution> {
+        (0..rng.gen_range(0..3))
+            .map(|_| Substitution {
+                flavor: flavor(rng),
+                substitute: flavor(rng),
+            })
+            .collect()
+    }
+
+    fn candidates(rng: &mut StdRng) -> Vec<(usize, bool)> {
+        (0..rng.gen_range(0..6))
+            .map(|_| (rng.gen_range(0..16), rng.gen()))
+            .collect()
+    }
+
+    fn token(rng: &mut StdRng) -> FlavorToken {
+        let mut token = FlavorToken::with_epoch(flavor(rng), rng.gen_range(0..10_000), rng.gen());
+        for robot_id in 0..rng.gen_range(0..4) {
+            token.record_hop(robot_id);
+        }
+        if rng.gen_bool(0.5) {
+            token.reserve(rng.gen_range(0..8), &order_id(rng), rng.gen_range(0..500));
+        }
+        token
+    }
+
+    fn compressions(rng: &mut StdRng) -> Vec<Compression> {
+        Compression::SUPPORTED
+            .into_iter()
+            .filter(|_| rng.gen_bool(0.5))
+            .collect()
+    }
+
+    fn sealed_backup(rng: &mut StdRng) -> SealedBackup {
+        let backup = LeaderBackup::new(
+            vec![rng.gen_range(0..8)],
+            vec![rng.gen_range(0..8)],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        );
+        let compression = Compression::SUPPORTED[rng.gen_range(0..3)];
+        SealedBackup::seal(&backup, rng.gen(), &SecurityConfig::default(), compression).unwrap()
+    }
+
+    fn robot_command(rng: &mut StdRng) -> RobotCommand {
+        match rng.gen_range(0..17) {
+            0 => RobotCommand::NewLeaderConnection {
+                leader_id: rng.gen_range(0..16),
+                epoch: rng.gen(),
+            },
+            1 => RobotCommand::LeaderId {
+                leader: rng.gen_bool(0.5).then(|| rng.gen_range(0..16)),
+            },
+            2 => RobotCommand::ReceiveLeaderBackup {
+                backup: sealed_backup(rng),
+                epoch: rng.gen(),
+            },
+            3 => RobotCommand::TokenMessage {
+                token: Box::new(token(rng)),
+            },
+            4 => RobotCommand::TokenBackupMsg {
+                token_backup: TokenBackup::new(
+                    token(rng).key(),
+                    rng.gen_range(0..10_000),
+                    rng.gen_range(0..16),
+                    rng.gen(),
+                ),
+            },
+            5 => RobotCommand::NewElection {
+                election: ElectionId {
+                    epoch: rng.gen(),
+                    initiator: rng.gen_range(0..16),
+                },
+                hops: rng.gen_range(0..16),
+                candidates: candidates(rng),
+            },
+            6 => RobotCommand::NewOrder {
+                order: order(rng),
+                order_id: order_id(rng),
+                trace_id: format!("{:016x}", rng.gen::<u64>()),
+                epoch: rng.gen(),
+            },
+            7 => RobotCommand::OrderComplete {
+                result: rng.gen(),
+                order_id: order_id(rng),
+                substitutions: substitutions(rng),
+            },
+            8 => RobotCommand::OrderNotFinished {
+                result: rng.gen(),
+                order_id: order_id(rng),
+                flavor: flavor(rng),
+                reason: reason(rng),
+            },
+            9 => RobotCommand::RestockFlavor {
+                flavor: flavor(rng),
+                amount: rng.gen_range(0..10_000),
+            },
+            10 => RobotCommand::TakeLeadership {
+                backup: sealed_backup(rng),
+                next_epoch: rng.gen(),
+                epoch: rng.gen(),
+            },
+            11 => RobotCommand::ScoopSpeed {
+                robot_id: rng.gen_range(0..16),
+                ms_per_gram: rng.gen_range(0..100),
+                compressions: compressions(rng),
+            },
+            12 => RobotCommand::RobotIdInUse {
+                robot_id: rng.gen_range(0..16),
+            },
+            13 => RobotCommand::OrdersInFlight {
+                robot_id: rng.gen_range(0..16),
+                orders: (0..rng.gen_range(0..4)).map(|_| order_id(rng)).collect(),
+            },
+            14 => RobotCommand::ResumeOrders {
+                resume: (0..rng.gen_range(0..4)).map(|_| order_id(rng)).collect(),
+                drop: (0..rng.gen_range(0..4)).map(|_| order_id(rng)).collect(),
+                epoch: rng.gen(),
+            },
+            15 => RobotCommand::StartTokens {
+                tokens: (0..rng.gen_range(0..4)).map(|_| token(rng)).collect(),
+                epoch: rng.gen(),
+            },
+            _ => RobotCommand::RobotUnavailable {
+                robot_id: rng.gen_range(0..16),
+                until: rng.gen(),
+            },
+        }
+    }
+
+    fn screen_message(rng: &mut StdRng) -> ScreenMessage {
+        match rng.gen_range(0..11) {
+            0 => ScreenMessage::PrepareNewOrder {
+                screen_id: rng.gen_range(0..8),
+                order_id: order_id(rng),
+                order: order(rng),
+                deadline_ms: rng.gen_bool(0.5).then(|| rng.gen()),
+                source: source(rng),
+            },
+            1 => {
+                let orders: Vec<(String, Order)> = (0..rng.gen_range(0..4))
+                    .map(|_| (order_id(rng), order(rng)))
+                    .collect();
+                let sources = orders
+                    .iter()
+                    .filter_map(|(id, _)| source(rng).map(|source| (id.clone(), source)))
+                    .collect();
+                ScreenMessage::PrepareNewOrderBatch {
+                    screen_id: rng.gen_range(0..8),
+                    orders,
+                    deadline_ms: rng.gen_bool(0.5).then(|| rng.gen()),
+                    sources,
+                }
+            }
+            2 => ScreenMessage::TakeMyBackup {
+                orders_to_process: (0..rng.gen_range(0..3)).map(|_| order(rng)).collect(),
+                orders_processing: (0..rng.gen_range(0..3))
+                    .map(|_| (order_id(rng), order(rng)))
+                    .collect(),
+                orders_pending_to_send: (0..rng.gen_range(0..3))
+                    .map(|_| (order_id(rng), order(rng)))
+                    .collect(),
+                id_backup: rng.gen_range(0..8),
+                version: rng.gen(),
+                hops_left: rng.gen_range(0..8),
+                sender: rng.gen_range(0..8),
+            },
+            3 => ScreenMessage::MembershipGossip {
+                screen_id: rng.gen_range(0..8),
+                members: (0..rng.gen_range(0..4))
+                    .map(|_| (rng.gen_range(0..8), rng.gen()))
+                    .collect(),
+                leader: rng.gen_bool(0.5).then(|| (rng.gen_range(0..16), rng.gen())),
+            },
+            4 => ScreenMessage::ScreenRejoined {
+                screen_id: rng.gen_range(0..8),
+                order_ids: (0..rng.gen_range(0..4)).map(|_| order_id(rng)).collect(),
+            },
+            5 => ScreenMessage::Sequenced {
+                nonce: rng.gen(),
+                seq: rng.gen(),
+                message: Box::new(ScreenMessage::QueryStatus {
+                    screen_id: rng.gen_range(0..8),
+                    order_id: order_id(rng),
+                }),
+            },
+            6 => ScreenMessage::QueryMenu {
+                screen_id: rng.gen_range(0..8),
+            },
+            7 => ScreenMessage::AcceptsCompression {
+                compressions: compressions(rng),
+            },
+            8 => ScreenMessage::CompressedBackup {
+                compression: Compression::SUPPORTED[rng.gen_range(0..3)],
+                payload: (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect(),
+            },
+            9 => ScreenMessage::OrderPickedUp {
+                screen_id: rng.gen_range(0..8),
+                order_id: order_id(rng),
+                waited_ms: rng.gen(),
+            },
+            _ => ScreenMessage::Pong { epoch: rng.gen() },
+        }
+    }
+
+    fn robot_message(rng: &mut StdRng) -> RobotMessage {
+        match rng.gen_range(0..9) {
+            0 => RobotMessage::NewLeader {
+                leader_id: rng.gen_range(0..16),
+                epoch: rng.gen(),
+            },
+            1 => RobotMessage::OrderPrepared {
+                order_id: order_id(rng),
+                substitutions: substitutions(rng),
+                epoch: rng.gen(),
+            },
+            2 => RobotMessage::OrderAbortedAfterConfirm {
+                order_id: order_id(rng),
+                error: reason(rng).describe(&flavor(rng)),
+                reason: reason(rng),
+                epoch: rng.gen(),
+            },
+            3 => RobotMessage::QueueStatus {
+                order_id: order_id(rng),
+                orders_ahead: rng.gen_bool(0.5).then(|| rng.gen_range(0..100)),
+                queued: rng.gen_range(0..100),
+                epoch: rng.gen(),
+            },
+            4 => RobotMessage::OrdersReclaimed {
+                taken_from: (0..rng.gen_range(0..3))
+                    .map(|_| (rng.gen_range(0..8), vec![order_id(rng)]))
+                    .collect(),
+                finished: (0..rng.gen_range(0..3)).map(|_| order_id(rng)).collect(),
+                epoch: rng.gen(),
+            },
+            5 => RobotMessage::Menu {
+                in_stock: (0..rng.gen_range(0..4)).map(|_| flavor(rng)).collect(),
+                epoch: rng.gen(),
+            },
+            6 => RobotMessage::StockSnapshot {
+                grams: (0..rng.gen_range(0..4))
+                    .map(|_| (flavor(rng), rng.gen_range(0..5000)))
+                    .collect(),
+                epoch: rng.gen(),
+            },
+            7 => RobotMessage::OrderAborted {
+                order_id: order_id(rng),
+                error: reason(rng).describe(&flavor(rng)),
+                reason: reason(rng),
+                flavor: rng.gen_bool(0.5).then(|| flavor(rng)),
+                epoch: rng.gen(),
+            },
+            _ => RobotMessage::Ping { epoch: rng.gen() },
+        }
+    }
+
+    /// Encodes and decodes random messages with both codecs, every one has to come back equal
+    fn assert_roundtrip<T, F>(seed: u64, generate: F)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+        F: Fn(&mut StdRng) -> T,
+    {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..CASES {
+            let msg = generate(&mut rng);
+
+            let line = LineCodec.encode(&msg).unwrap();
+            let decoded: T = LineCodec.decode(&line[..line.len() - 1]).unwrap();
+            assert_eq!(decoded, msg);
+
+            let frame = BinaryCodec.encode(&msg).unwrap();
+            let decoded: T = BinaryCodec.decode(&frame[4..]).unwrap();
+            assert_eq!(decoded, msg);
+        }
+    }
+
+    #[test]
+    fn test_robot_commands_roundtrip() {
+        assert_roundtrip(1, robot_command);
+    }
+
+    #[test]
+    fn test_screen_messages_roundtrip() {
+        assert_roundtrip(2, screen_message);
+    }
+
+    #[test]
+    fn test_robot_messages_roundtrip() {
+        assert_roundtrip(3, robot_message);
+    }
+
+    #[test]
+    fn test_compressed_backup_carries_the_message() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let backup = ScreenMessage::TakeMyBackup {
+            orders_to_process: (0..200).map(|_| order(&mut rng)).collect(),
+            orders_processing: HashMap::new(),
+            orders_pending_to_send: vec![],
+            id_backup: 1,
+            version: 7,
+            hops_left: 0,
+            sender: 1,
+        };
+        let (compressed, raw) = backup.compress(Compression::Zstd).unwrap();
+        assert!(compressed.to_bytes().unwrap().len() < raw);
+        assert_eq!(compressed.decompress().unwrap(), backup);
+    }
+
+    #[test]
+    fn test_borrowed_backup_is_encoded_like_the_message() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let waiting: Vec<Order> = (0..3).map(|_| order(&mut rng)).collect();
+        let processing = HashMap::from([("a".to_string(), order(&mut rng))]);
+        let pending = vec![("b".to_string(), order(&mut rng))];
+        let view = BackupView {
+            orders_to_process: waiting.iter().collect(),
+            orders_processing: &processing,
+            orders_pending_to_send: &pending,
+            id_backup: 1,
+            version: 7,
+            hops_left: 2,
+            sender: 3,
+        };
+        let backup = ScreenMessage::TakeMyBackup {
+            orders_to_process: waiting.clone(),
+            orders_processing: processing.clone(),
+            orders_pending_to_send: pending.clone(),
+            id_backup: 1,
+            version: 7,
+            hops_left: 2,
+            sender: 3,
+        };
+        assert_eq!(view.encode().unwrap(), bincode::serialize(&backup).unwrap());
+        assert_eq!(
+            BinaryCodec.encode(&view).unwrap(),
+            BinaryCodec.encode(&backup).unwrap()
+        );
+        assert_eq!(
+            LineCodec.encode(&view).unwrap(),
+            LineCodec.encode(&backup).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encoding_of_the_protocol_version() {
+        assert_eq!(PROTOCOL_VERSION, 15, "update the encodings below");
+        let order = ScreenMessage::PrepareNewOrder {
+            screen_id: 1,
+            order_id: "a".to_string(),
+            order: Order::new_cucurucho(FlavorID::Mint),
+            deadline_ms: None,
+            source: Some(OrderSource {
+                screen_id: 1,
+                channel: OrderChannel::Api,
+                placed_at_ms: 5,
+                trace_id: "00ab".to_string(),
+                shop: Some("centro".to_string()),
+            }),
+        };
+        let command = RobotCommand::OrderNotFinished {
+            result: false,
+            order_id: "a".to_string(),
+            flavor: FlavorID::Lemon,
+            reason: AbortReason::TimedOut,
+        };
+        let new_order = RobotCommand::NewOrder {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: "a".to_string(),
+            trace_id: "00ab".to_string(),
+            epoch: 2,
+        };
+        let message = RobotMessage::OrderPrepared {
+            order_id: "a".to_string(),
+            substitutions: vec![],
+            epoch: 2,
+        };
+        let menu = RobotMessage::Menu {
+            in_stock: vec![FlavorID::Mint],
+            epoch: 2,
+        };
+        let snapshot = RobotMessage::StockSnapshot {
+            grams: vec![(FlavorID::Mint, 750)],
+            epoch: 2,
+        };
+        let accepts = ScreenMessage::AcceptsCompression {
+            compressions: vec![Compression::None, Compression::Zstd],
+        };
+        let election = RobotCommand::NewElection {
+            election: ElectionId {
+                epoch: 3,
+                initiator: 1,
+            },
+            hops: 1,
+            candidates: vec![(1, true), (2, false)],
+        };
+        let no_flavors = RobotCommand::OrderNotFinished {
+            result: false,
+            order_id: "a".to_string(),
+            flavor: FlavorID::Mint,
+            reason: AbortReason::NoFlavors,
+        };
+        let unknown_flavor = RobotCommand::OrderNotFinished {
+            result: false,
+            order_id: "a".to_string(),
+            flavor: FlavorID::Mint,
+            reason: AbortReason::UnknownFlavor,
+        };
+        let id_in_use = RobotCommand::RobotIdInUse { robot_id: 1 };
+        let in_flight = RobotCommand::OrdersInFlight {
+            robot_id: 1,
+            orders: vec!["a".to_string()],
+        };
+        let resume = RobotCommand::ResumeOrders {
+            resume: vec!["a".to_string()],
+            drop: vec!["b".to_string()],
+            epoch: 2,
+        };
+        let picked_up = ScreenMessage::OrderPickedUp {
+            screen_id: 1,
+            order_id: "a".to_string(),
+            waited_ms: 30,
+        };
+        let start_tokens = RobotCommand::StartTokens {
+            tokens: vec![],
+            epoch: 2,
+        };
+        let counters = ShopCounters {
+            placed: 2,
+            completed: 1,
+            aborted: 0,
+            rejected: 0,
+            used: BTreeMap::from([("Mint".to_string(), 250)]),
+            reserved: BTreeMap::new(),
+        };
+        let backup = LeaderBackup::new(
+            vec![],
+            vec![],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        )
+        .with_shops(BTreeMap::from([("centro".to_string(), counters)]))
+        .with_routed(vec![OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Lemon),
+            order_id: "b".to_string(),
+            screen_id: 1,
+            deadline_ms: None,
+            source: None,
+        }]);
+        let aborted = RobotMessage::OrderAborted {
+            order_id: "a".to_string(),
+            error: AbortReason::OutOfStock.describe(&FlavorID::Lemon),
+            reason: AbortReason::OutOfStock,
+            flavor: Some(FlavorID::Lemon),
+            epoch: 2,
+        };
+        assert_eq!(
+            serde_json::to_string(&order).unwrap(),
+            r#"{"PrepareNewOrder":{"screen_id":1,"order_id":"a","order":{"Cucurucho":["Mint",250,[]]},"deadline_ms":null,"source":{"screen_id":1,"channel":"Api","placed_at_ms":5,"trace_id":"00ab","shop":"centro"}}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&command).unwrap(),
+            r#"{"OrderNotFinished":{"result":false,"order_id":"a","flavor":"Lemon","reason":"TimedOut"}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&new_order).unwrap(),
+            r#"{"NewOrder":{"order":{"Cucurucho":["Mint",250,[]]},"order_id":"a","trace_id":"00ab","epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"OrderPrepared":{"order_id":"a","substitutions":[],"epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&menu).unwrap(),
+            r#"{"Menu":{"in_stock":["Mint"],"epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            r#"{"StockSnapshot":{"grams":[["Mint",750]],"epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&accepts).unwrap(),
+            r#"{"AcceptsCompression":{"compressions":["None","Zstd"]}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&election).unwrap(),
+            r#"{"NewElection":{"election":{"epoch":3,"initiator":1},"hops":1,"candidates":[[1,true],[2,false]]}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&no_flavors).unwrap(),
+            r#"{"OrderNotFinished":{"result":false,"order_id":"a","flavor":"Mint","reason":"NoFlavors"}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&unknown_flavor).unwrap(),
+            r#"{"OrderNotFinished":{"result":false,"order_id":"a","flavor":"Mint","reason":"UnknownFlavor"}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&id_in_use).unwrap(),
+            r#"{"RobotIdInUse":{"robot_id":1}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&in_flight).unwrap(),
+            r#"{"OrdersInFlight":{"robot_id":1,"orders":["a"]}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&resume).unwrap(),
+            r#"{"ResumeOrders":{"resume":["a"],"drop":["b"],"epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&picked_up).unwrap(),
+            r#"{"OrderPickedUp":{"screen_id":1,"order_id":"a","waited_ms":30}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&start_tokens).unwrap(),
+            r#"{"StartTokens":{"tokens":[],"epoch":2}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&backup).unwrap(),
+            r#"{"available_robots":[],"orders_on_queue":[],"robots_orders":{},"screens":[],"orders_to_be_sent":[],"ledger":{"states":{},"seen":[]},"shops":{"centro":{"placed":2,"completed":1,"aborted":0,"rejected":0,"used":{"Mint":250},"reserved":{}}},"routed":[{"order":{"Cucurucho":["Lemon",250,[]]},"order_id":"b","screen_id":1,"deadline_ms":null,"source":null}]}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&aborted).unwrap(),
+            r#"{"OrderAborted":{"order_id":"a","error":"Order Aborted because of insuficient amount of: Lemon","reason":"OutOfStock","flavor":"Lemon","epoch":2}}"#
+        );
+    }
+}
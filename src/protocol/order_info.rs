@@ -0,0 +1,27 @@
+use crate::common::order::Order;
+use crate::common::order_source::OrderSource;
+use serde::{Deserialize, Serialize};
+
+/// Struct to store the information of an order
+/// Holds the order and the order id and the screen id
+/// and how long a robot has to prepare it once it is assigned, if it has a deadline
+/// and where it was placed, if the screen told it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrderInfo {
+    pub order: Order,
+    pub order_id: String,
+    pub screen_id: usize,
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+    #[serde(default)]
+    pub source: Option<OrderSource>,
+}
+
+impl OrderInfo {
+    /// Gets the id that traces the order in the logs, empty if the screen did not give one
+    pub fn trace_id(&self) -> &str {
+        self.source
+            .as_ref()
+            .map_or("", |source| source.trace_id.as_str())
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::common::flavor_id::FlavorID;
+
+/// How many orders the ledger remembers, the oldest ones are forgotten first
+pub const LEDGER_CAPACITY: usize = 1000;
+
+/// State of an order seen by the leader
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderState {
+    InProgress,
+    Completed,
+    Aborted(FlavorID),
+}
+
+/// Ledger with the ids of the orders seen by the leader and their final state
+/// It is part of the leader backup, so an order delivered twice is only prepared once even after a failover
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct OrderLedger {
+    states: HashMap<String, OrderState>,
+    seen: VecDeque<String>,
+}
+
+impl OrderLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the state of the order, None if it was never seen
+    pub fn state(&self, order_id: &str) -> Option<OrderState> {
+        self.states.get(order_id).cloned()
+    }
+
+    pub fn created(&mut self, order_id: &str) {
+        if self
+            .states
+            .insert(order_id.to_string(), OrderState::InProgress)
+            .is_none()
+        {
+            self.seen.push_back(order_id.to_string());
+        }
+        while self.seen.len() > LEDGER_CAPACITY {
+            if let Some(oldest) = self.seen.pop_front() {
+                self.states.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn completed(&mut self, order_id: &str) {
+        self.finish(order_id, OrderState::Completed);
+    }
+
+    pub fn aborted(&mut self, order_id: &str, flavor: FlavorID) {
+        self.finish(order_id, OrderState::Aborted(flavor));
+    }
+
+    /// Sets the state of the order, it is seen first if it was not in the ledger
+    pub fn record(&mut self, order_id: &str, state: OrderState) {
+        self.created(order_id);
+        self.finish(order_id, state);
+    }
+
+    /// Returns the orders with their state, from the oldest one seen
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &OrderState)> {
+        self.seen
+            .iter()
+            .filter_map(|order_id| self.states.get_key_value(order_id))
+    }
+
+    fn finish(&mut self, order_id: &str, state: OrderState) {
+        if let Some(current) = self.states.get_mut(order_id) {
+            *current = state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_keeps_the_final_state() {
+        let mut ledger = OrderLedger::new();
+        assert_eq!(ledger.state("1"), None);
+
+        ledger.created("1");
+        ledger.created("2");
+        assert_eq!(ledger.state("1"), Some(OrderState::InProgress));
+
+        ledger.completed("1");
+        ledger.aborted("2", FlavorID::Mint);
+        assert_eq!(ledger.state("1"), Some(OrderState::Completed));
+        assert_eq!(ledger.state("2"), Some(OrderState::Aborted(FlavorID::Mint)));
+    }
+
+    #[test]
+    fn test_oldest_orders_are_forgotten() {
+        let mut ledger = OrderLedger::new();
+        for i in 0..=LEDGER_CAPACITY {
+            ledger.created(&i.to_string());
+        }
+        assert_eq!(ledger.state("0"), None);
+        assert_eq!(
+            ledger.state(&LEDGER_CAPACITY.to_string()),
+            Some(OrderState::InProgress)
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Substitution;
+use crate::protocol::robot_messages::AbortReason;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+
+/// Struct to store the information of an order that is waiting for a new screen
+pub struct OrderWaiting {
+    pub order_result: bool,
+    pub id: String,
+    pub screen_id: usize,
+    pub flavor: Option<FlavorID>,
+    #[serde(default)]
+    pub reason: AbortReason,
+    #[serde(default)]
+    pub substitutions: Vec<Substitution>,
+    /// Times the leader tried to send it to a screen and could not
+    #[serde(default)]
+    pub attempts: u32,
+    /// When it was stashed, in milliseconds since the Unix epoch, 0 if it is not known
+    #[serde(default)]
+    pub stashed_at_ms: u64,
+}
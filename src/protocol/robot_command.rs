@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+use crate::common::codec;
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::{Order, Substitution};
+use crate::protocol::backup_seal::SealedBackup;
+use crate::protocol::compression::Compression;
+use crate::protocol::election::ElectionId;
+use crate::protocol::flavor_token::FlavorToken;
+use crate::protocol::robot_messages::AbortReason;
+use crate::protocol::token_backup::TokenBackup;
+
+#[derive(Debug)]
+pub enum RobotCommandError {
+    ErrorParsing(String),
+}
+
+impl fmt::Display for RobotCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl Error for RobotCommandError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RobotCommand {
+    /// First frame of a connection to the ring port, sent by the robot that will be the next one of the listener
+    NewNextRobot {
+        robot_id: usize,
+    },
+    /// First frame of a connection to the ring port, sent by the robot that will be the previous one of the listener.
+    /// It is answered with `LeaderId`
+    NewPreviousRobot {
+        robot_id: usize,
+    },
+    /// First frame of a connection to the ring port, sent by the leader
+    NewLeaderConnection {
+        leader_id: usize,
+        epoch: u64,
+    },
+    /// First frame of the control lane, a second connection opened by the previous robot of the listener
+    /// that only carries the elections, the new leaders and the robots leaving the ring
+    NewControlLane {
+        robot_id: usize,
+    },
+    /// First frame of a connection that only checks that the robot is up, nothing else is sent through it
+    Probe {
+        robot_id: usize,
+    },
+    /// The leader known by the robot, if any
+    LeaderId {
+        leader: Option<usize>,
+    },
+    ReceiveLeaderBackup {
+        backup: SealedBackup,
+        epoch: u64,
+    },
+    /// The token is boxed since it carries its hops, the other commands are much smaller
+    TokenMessage {
+        token: Box<FlavorToken>,
+    },
+    TokenBackupMsg {
+        token_backup: TokenBackup,
+    },
+    NewLeader {
+        leader: usize,
+        epoch: u64,
+    },
+    /// The hops are how many robots passed the round on since the one that started it
+    NewElection {
+        election: ElectionId,
+        hops: usize,
+        candidates: Vec<(usize, bool)>,
+    },
+    /// The trace id of the order goes with it, so the robot writes it in its logs
+    NewOrder {
+        order: Order,
+        order_id: String,
+        #[serde(default)]
+        trace_id: String,
+        epoch: u64,
+    },
+    OrderComplete {
+        result: bool,
+        order_id: String,
+        substitutions: Vec<Substitution>,
+    },
+    OrderNotFinished {
+        result: bool,
+        order_id: String,
+        flavor: FlavorID,
+        reason: AbortReason,
+    },
+    RestockFlavor {
+        flavor: FlavorID,
+        amount: usize,
+    },
+    Heartbeat {
+        from: usize,
+    },
+    LeaveRing {
+        robot_id: usize,
+    },
+    /// The robot is defrosting and can not scoop until `until`, in milliseconds since the Unix epoch
+    RobotUnavailable {
+        robot_id: usize,
+        until: u64,
+    },
+    /// Sent by a robot when it connects to a leader, with how many milliseconds it takes to scoop a gram
+    /// and the compressions of the backups it can read
+    ScoopSpeed {
+        robot_id: usize,
+        ms_per_gram: usize,
+        #[serde(default)]
+        compressions: Vec<Compression>,
+    },
+    /// The robot could not apply the changes of a backup, the leader sends it the last backup whole
+    FullBackupNeeded {
+        robot_id: usize,
+    },
+    /// Answer to a leader whose epoch is older than the one the robot knows, with the newest epoch
+    StaleLeader {
+        epoch: u64,
+    },
+    /// The order passed its deadline, the robot drops it without answering, the leader already aborted it
+    CancelOrder {
+        order_id: String,
+        epoch: u64,
+    },
+    /// The leader hands its leadership to the robot, with its last backup, the robot becomes the leader of `next_epoch`
+    TakeLeadership {
+        backup: SealedBackup,
+        next_epoch: u64,
+        epoch: u64,
+    },
+    /// The leader handed its leadership to another robot, which will connect as the leader of `next_epoch`
+    LeaderTransferred {
+        leader: usize,
+        next_epoch: u64,
+        epoch: u64,
+    },
+    /// Answer of the leader to a robot that connects with the id of a robot that is still connected,
+    /// the connection is closed after it
+    RobotIdInUse {
+        robot_id: usize,
+    },
+    /// Sent by a robot when it connects to a leader, with the orders it was preparing before it restarted or lost the connection
+    OrdersInFlight {
+        robot_id: usize,
+        orders: Vec<String>,
+    },
+    /// Answer of the leader to `OrdersInFlight`, the orders the robot goes on preparing and the ones it drops
+    ResumeOrders {
+        resume: Vec<String>,
+        drop: Vec<String>,
+        epoch: u64,
+    },
+    /// The first leader starts these tokens at the robot, instead of at its own robot
+    StartTokens {
+        tokens: Vec<FlavorToken>,
+        epoch: u64,
+    },
+}
+
+impl RobotCommand {
+    pub fn from_bytes(frame: &[u8]) -> Result<Self, RobotCommandError> {
+        codec::decode(frame).map_err(|err| RobotCommandError::ErrorParsing(err.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RobotCommandError> {
+        codec::encode(self).map_err(|err| RobotCommandError::ErrorParsing(err.to_string()))
+    }
+}
@@ -0,0 +1,189 @@
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::codec;
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Substitution;
+
+#[derive(Debug)]
+pub enum RobotMessageError {
+    ErrorParsing(String),
+}
+
+impl fmt::Display for RobotMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl Error for RobotMessageError {}
+
+/// Why a robot could not prepare an order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AbortReason {
+    /// There was not enough of the flavor left
+    #[default]
+    OutOfStock,
+    /// The arm of the robot jammed every time it tried to scoop the flavor
+    ScoopFailed,
+    /// The robot did not finish the order before its deadline
+    TimedOut,
+    /// The leader did not take the order because the flavor is below its low watermark
+    LowStock,
+    /// The leader did not take the order because the flavor has no token, it is not in the catalog
+    UnknownFlavor,
+    /// The leader did not take the order because it has no flavors
+    NoFlavors,
+    /// The robot did not prepare the order because it does not fit the sizes of its config
+    WrongSize,
+    /// The leader did not take the order because its shop used up its quota of the flavor
+    OverQuota,
+}
+
+impl AbortReason {
+    /// Returns the error shown to the client for an order aborted by this reason while serving the flavor
+    pub fn describe(&self, flavor: &FlavorID) -> String {
+        match self {
+            AbortReason::OutOfStock => {
+                format!("Order Aborted because of insuficient amount of: {}", flavor)
+            }
+            AbortReason::ScoopFailed => {
+                format!(
+                    "Order Aborted because the robot could not scoop: {}",
+                    flavor
+                )
+            }
+            AbortReason::TimedOut => {
+                format!(
+                    "Order Aborted because it was not prepared in time, it was waiting for: {}",
+                    flavor
+                )
+            }
+            AbortReason::LowStock => {
+                format!(
+                    "Order Rejected because the stock is running low of: {}",
+                    flavor
+                )
+            }
+            AbortReason::UnknownFlavor => {
+                format!("Order Rejected because there is no such flavor: {}", flavor)
+            }
+            AbortReason::NoFlavors => "Order Rejected because it has no flavors".to_string(),
+            AbortReason::WrongSize => {
+                format!(
+                    "Order Rejected because it does not fit its size, with: {}",
+                    flavor
+                )
+            }
+            AbortReason::OverQuota => {
+                format!(
+                    "Order Rejected because the shop used up its quota of: {}",
+                    flavor
+                )
+            }
+        }
+    }
+}
+
+/// Messages of the leader to a screen, each one carries the epoch of the leader that sent it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RobotMessage {
+    /// First message of a leader that connects to the screen, with the id of its robot
+    NewLeader {
+        leader_id: usize,
+        epoch: u64,
+    },
+    /// The order is ready, with the flavors that were replaced by a substitute since they ran out
+    OrderPrepared {
+        order_id: String,
+        substitutions: Vec<Substitution>,
+        epoch: u64,
+    },
+    /// The order was not prepared, with the flavor that could not be served, none if the order was turned away as a whole
+    OrderAborted {
+        order_id: String,
+        error: String,
+        reason: AbortReason,
+        #[serde(default)]
+        flavor: Option<FlavorID>,
+        epoch: u64,
+    },
+    /// A robot aborted the order after the leader had told the screen it was prepared, its payment has to be refunded
+    OrderAbortedAfterConfirm {
+        order_id: String,
+        error: String,
+        reason: AbortReason,
+        epoch: u64,
+    },
+    OrderRejectedBusy {
+        order_id: String,
+        epoch: u64,
+    },
+    OrdersReleased {
+        order_ids: Vec<String>,
+        epoch: u64,
+    },
+    /// Health check of the connection, the screen answers with a Pong
+    Ping {
+        epoch: u64,
+    },
+    /// The leader handed its leadership to another robot, which will connect as the leader of `next_epoch`
+    LeaderTransferred {
+        leader_id: usize,
+        next_epoch: u64,
+        epoch: u64,
+    },
+    /// Answer to a QueryStatus, how many orders are ahead of the order (0 if a robot is preparing it)
+    /// and how many are queued. None if the leader does not have the order
+    QueueStatus {
+        order_id: String,
+        orders_ahead: Option<usize>,
+        queued: usize,
+        epoch: u64,
+    },
+    /// Answer to a ScreenRejoined, the screens that had claimed the orders captured by the screen, which go back to it,
+    /// and the orders that another screen already finished
+    OrdersReclaimed {
+        taken_from: Vec<(usize, Vec<String>)>,
+        finished: Vec<String>,
+        epoch: u64,
+    },
+    /// Answer to a QueryMenu, the flavors of the catalog that have stock and are not rejected for running low
+    Menu {
+        in_stock: Vec<FlavorID>,
+        epoch: u64,
+    },
+    /// Grams left of each flavor of the catalog as last seen by the leader's robot, sent every `orders.stock_snapshot_ms`
+    StockSnapshot {
+        grams: Vec<(FlavorID, usize)>,
+        epoch: u64,
+    },
+}
+
+impl RobotMessage {
+    /// Returns the epoch of the leader that sent the message
+    pub fn epoch(&self) -> u64 {
+        match self {
+            RobotMessage::NewLeader { epoch, .. }
+            | RobotMessage::OrderPrepared { epoch, .. }
+            | RobotMessage::OrderAborted { epoch, .. }
+            | RobotMessage::OrderAbortedAfterConfirm { epoch, .. }
+            | RobotMessage::OrderRejectedBusy { epoch, .. }
+            | RobotMessage::OrdersReleased { epoch, .. }
+            | RobotMessage::LeaderTransferred { epoch, .. }
+            | RobotMessage::QueueStatus { epoch, .. }
+            | RobotMessage::OrdersReclaimed { epoch, .. }
+            | RobotMessage::Menu { epoch, .. }
+            | RobotMessage::StockSnapshot { epoch, .. }
+            | RobotMessage::Ping { epoch } => *epoch,
+        }
+    }
+
+    pub fn from_bytes(frame: &[u8]) -> Result<Self, RobotMessageError> {
+        codec::decode(frame).map_err(|err| RobotMessageError::ErrorParsing(err.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RobotMessageError> {
+        codec::encode(self).map_err(|err| RobotMessageError::ErrorParsing(err.to_string()))
+    }
+}
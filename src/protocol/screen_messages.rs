@@ -0,0 +1,207 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use serde::ser::SerializeStructVariant;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::common::codec;
+use crate::common::order::Order;
+use crate::common::order_source::OrderSource;
+use crate::protocol::compression::Compression;
+
+#[derive(Debug)]
+pub enum ScreenMessageError {
+    ErrorParsing(String),
+}
+
+impl fmt::Display for ScreenMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl Error for ScreenMessageError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ScreenMessage {
+    /// The deadline is how long a robot has to prepare the order once the leader gives it, if any
+    /// The source tells where and when the order was placed, if the screen knows it
+    PrepareNewOrder {
+        screen_id: usize,
+        order_id: String,
+        order: Order,
+        #[serde(default)]
+        deadline_ms: Option<u64>,
+        #[serde(default)]
+        source: Option<OrderSource>,
+    },
+    PrepareNewOrderBatch {
+        screen_id: usize,
+        orders: Vec<(String, Order)>,
+        #[serde(default)]
+        deadline_ms: Option<u64>,
+        #[serde(default)]
+        sources: HashMap<String, OrderSource>,
+    },
+    /// The backup of the screen `id_backup`, written by the screen `sender`.
+    /// It is relayed to the next screen while `hops_left` is more than 0, and only a newer `version` replaces it
+    TakeMyBackup {
+        orders_to_process: Vec<Order>,
+        orders_processing: HashMap<String, Order>,
+        orders_pending_to_send: Vec<(String, Order)>,
+        id_backup: usize,
+        #[serde(default)]
+        version: u64,
+        #[serde(default)]
+        hops_left: usize,
+        #[serde(default)]
+        sender: usize,
+    },
+    RequestRobotLeaderConnection {
+        screen_id: usize,
+    },
+    GiveMeThisScreenOrders {
+        my_id: usize,
+        death_id: usize,
+    },
+    ScreenRejoined {
+        screen_id: usize,
+        order_ids: Vec<String>,
+    },
+    /// Answer to a leader whose epoch is older than the newest one the screen knows
+    StaleLeader {
+        epoch: u64,
+    },
+    /// Sent to the next screen, the screens known to be alive with their heartbeats and the leader with its epoch
+    MembershipGossip {
+        screen_id: usize,
+        members: Vec<(usize, u64)>,
+        leader: Option<(usize, u64)>,
+    },
+    /// Answer to a Ping of the leader, with the epoch it carried
+    Pong {
+        epoch: u64,
+    },
+    /// Asks the leader how many orders are ahead of the order, it answers with a QueueStatus
+    QueryStatus {
+        screen_id: usize,
+        order_id: String,
+    },
+    /// Asks the leader which flavors of the catalog have stock, it answers with a Menu
+    QueryMenu {
+        screen_id: usize,
+    },
+    /// First frame the next screen sends back on the connection, with the compressions of the backups it can read
+    AcceptsCompression {
+        compressions: Vec<Compression>,
+    },
+    /// A `TakeMyBackup` encoded with bincode and compressed, sent instead of it once the next screen accepts the compression
+    CompressedBackup {
+        compression: Compression,
+        payload: Vec<u8>,
+    },
+    /// A message to the leader numbered by the screen, so the leader drops the copies of it.
+    /// `nonce` is drawn for each connection with the leader and `seq` grows with every message of the connection
+    Sequenced {
+        nonce: u64,
+        seq: u64,
+        message: Box<ScreenMessage>,
+    },
+    /// The order was handed to the customer, `waited_ms` after it was ready
+    OrderPickedUp {
+        screen_id: usize,
+        order_id: String,
+        waited_ms: u64,
+    },
+}
+
+impl ScreenMessage {
+    pub fn from_bytes(frame: &[u8]) -> Result<Self, ScreenMessageError> {
+        codec::decode(frame).map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ScreenMessageError> {
+        codec::encode(self).map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))
+    }
+
+    /// Wraps the message in a `CompressedBackup`, returned with the size of the message before it was compressed
+    pub fn compress(
+        &self,
+        compression: Compression,
+    ) -> Result<(ScreenMessage, usize), ScreenMessageError> {
+        let encoded = bincode::serialize(self)
+            .map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))?;
+        let compressed = ScreenMessage::compressed(&encoded, compression)?;
+        Ok((compressed, encoded.len()))
+    }
+
+    /// Returns the `CompressedBackup` of a message already encoded with bincode
+    pub fn compressed(
+        encoded: &[u8],
+        compression: Compression,
+    ) -> Result<ScreenMessage, ScreenMessageError> {
+        let payload = compression
+            .compress(encoded)
+            .map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))?;
+        Ok(ScreenMessage::CompressedBackup {
+            compression,
+            payload,
+        })
+    }
+
+    /// Returns the message a `CompressedBackup` carries, any other message is returned as it is
+    pub fn decompress(self) -> Result<ScreenMessage, ScreenMessageError> {
+        match self {
+            ScreenMessage::CompressedBackup {
+                compression,
+                payload,
+            } => {
+                let encoded = compression
+                    .decompress(&payload)
+                    .map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))?;
+                bincode::deserialize(&encoded)
+                    .map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))
+            }
+            message => Ok(message),
+        }
+    }
+}
+
+/// A `TakeMyBackup` that borrows the orders from the screen that writes it, so they are not copied to encode it.
+/// It is encoded exactly as the message
+pub struct BackupView<'a> {
+    pub orders_to_process: Vec<&'a Order>,
+    pub orders_processing: &'a HashMap<String, Order>,
+    pub orders_pending_to_send: &'a [(String, Order)],
+    pub id_backup: usize,
+    pub version: u64,
+    pub hops_left: usize,
+    pub sender: usize,
+}
+
+/// Position of `TakeMyBackup` among the variants of `ScreenMessage`
+const TAKE_MY_BACKUP_INDEX: u32 = 2;
+
+impl Serialize for BackupView<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut variant = serializer.serialize_struct_variant(
+            "ScreenMessage",
+            TAKE_MY_BACKUP_INDEX,
+            "TakeMyBackup",
+            7,
+        )?;
+        variant.serialize_field("orders_to_process", &self.orders_to_process)?;
+        variant.serialize_field("orders_processing", self.orders_processing)?;
+        variant.serialize_field("orders_pending_to_send", self.orders_pending_to_send)?;
+        variant.serialize_field("id_backup", &self.id_backup)?;
+        variant.serialize_field("version", &self.version)?;
+        variant.serialize_field("hops_left", &self.hops_left)?;
+        variant.serialize_field("sender", &self.sender)?;
+        variant.end()
+    }
+}
+
+impl BackupView<'_> {
+    /// Returns the backup encoded with bincode, the way a `CompressedBackup` carries it
+    pub fn encode(&self) -> Result<Vec<u8>, ScreenMessageError> {
+        bincode::serialize(self).map_err(|err| ScreenMessageError::ErrorParsing(err.to_string()))
+    }
+}
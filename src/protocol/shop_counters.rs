@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Orders of a shop counted by the leader, and the grams of each flavor they used and reserved
+/// It is part of the leader backup, so a new leader keeps counting the quota of the shop where the old one left it
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ShopCounters {
+    pub placed: u64,
+    pub completed: u64,
+    pub aborted: u64,
+    pub rejected: u64,
+    /// Grams of each flavor taken by the orders completed
+    pub used: BTreeMap<String, usize>,
+    /// Grams of each flavor the orders in progress will take
+    pub reserved: BTreeMap<String, usize>,
+}
@@ -1,22 +1,29 @@
 use crate::common::flavor_id::FlavorID;
+use crate::protocol::flavor_token::TokenKey;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 
 /// Struct to store the information of a token that needs to be recovered
-/// Holds the flavor_id, amount and the robot_id that has started the recovery process
+/// Holds the flavor_id and shard of the token, amount, the robot_id that has started the recovery process
+/// and the epoch the recovered token will have
 pub struct TokenBackup {
     flavor_id: FlavorID,
+    shard: usize,
     amount: usize,
     start_robot_id: usize,
+    epoch: u64,
 }
 
 impl TokenBackup {
-    pub fn new(flavor_id: FlavorID, amount: usize, start_robot_id: usize) -> TokenBackup {
+    pub fn new(key: TokenKey, amount: usize, start_robot_id: usize, epoch: u64) -> TokenBackup {
+        let (flavor_id, shard) = key;
         TokenBackup {
             flavor_id,
+            shard,
             amount,
             start_robot_id,
+            epoch,
         }
     }
 
@@ -39,6 +46,16 @@ impl TokenBackup {
 
     /// Gets the flavor_id of the token
     pub fn get_flavor_id(&self) -> FlavorID {
-        self.flavor_id
+        self.flavor_id.clone()
+    }
+
+    /// Gets the key of the token being recovered
+    pub fn key(&self) -> TokenKey {
+        (self.flavor_id.clone(), self.shard)
+    }
+
+    /// Gets the epoch of the recovery
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
     }
 }
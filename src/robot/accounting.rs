@@ -0,0 +1,283 @@
+//! Double-entry accounting of the stock, kept by the leader.
+//! Each flavor has the grams the leader expects on its tokens: the ones the account was opened with, plus the
+//! restocks, minus the grams of the completed orders. From time to time they are checked against the grams seen on the
+//! tokens that go through the robot of the leader. Tokens and results travel at their own pace, so only the flavors
+//! that no robot is preparing are checked, and a difference has to stay the same for a few checks to break the invariant.
+//! The accounts are not part of the backup, a new leader opens each flavor with the grams it sees on its tokens.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use crate::common::order::{Order, Substitution};
+use crate::config::flavors::FlavorCatalog;
+use crate::robot::maintenance::now_ms;
+
+/// Violations kept to be shown by the admin
+const MAX_VIOLATIONS: usize = 32;
+
+/// How the grams on the tokens differ from the expected ones
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// There are fewer grams than expected, scoops were served for orders that did not complete or a token was lost
+    Missing,
+    /// There are more grams than expected, a token may have been duplicated
+    Surplus,
+}
+
+impl Discrepancy {
+    /// Label of the discrepancy in the metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            Discrepancy::Missing => "missing",
+            Discrepancy::Surplus => "surplus",
+        }
+    }
+}
+
+/// A flavor whose tokens do not have the grams the leader expects
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Violation {
+    pub flavor: String,
+    pub expected: i64,
+    pub observed: usize,
+    pub discrepancy: Discrepancy,
+    pub at_ms: u64,
+}
+
+/// Balance of a flavor, the expected grams are not known until its account is opened
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FlavorBalance {
+    pub flavor: String,
+    pub expected: Option<i64>,
+    pub observed: Option<usize>,
+    pub restocked: usize,
+    pub consumed: usize,
+}
+
+/// The balance of every flavor and the last violations found, answered to the admin
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StockAccountsReport {
+    pub flavors: Vec<FlavorBalance>,
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Debug, Default)]
+struct Account {
+    opening: Option<usize>,
+    restocked: usize,
+    consumed: usize,
+    observed: Option<usize>,
+    difference: i64,
+    streak: usize,
+    reported: bool,
+}
+
+impl Account {
+    fn expected(&self) -> Option<i64> {
+        self.opening
+            .map(|opening| (opening + self.restocked) as i64 - self.consumed as i64)
+    }
+}
+
+/// Accounts of the stock of every flavor
+#[derive(Debug)]
+pub struct StockAccounts {
+    accounts: BTreeMap<String, Account>,
+    confirm_checks: usize,
+    violations: VecDeque<Violation>,
+}
+
+impl StockAccounts {
+    pub fn new(confirm_checks: usize) -> Self {
+        Self {
+            accounts: BTreeMap::new(),
+            confirm_checks,
+            violations: VecDeque::new(),
+        }
+    }
+
+    fn account(&mut self, flavor: &str) -> &mut Account {
+        self.accounts.entry(flavor.to_string()).or_default()
+    }
+
+    /// Opens every flavor of the catalog with its initial amount, when the leader starts the tokens
+    pub fn open(&mut self, catalog: &FlavorCatalog) {
+        for stock in catalog.flavors() {
+            *self.account(stock.flavor.name()) = Account {
+                opening: Some(stock.amount),
+                ..Account::default()
+            };
+        }
+    }
+
+    /// Credits the grams added to a token of the flavor
+    pub fn restocked(&mut self, flavor: &str, grams: usize) {
+        let account = self.account(flavor);
+        if account.opening.is_some() {
+            account.restocked += grams;
+        }
+    }
+
+    /// Debits the grams of a completed order from the flavors that were served, the substitute if there was one
+    pub fn consumed(&mut self, order: &Order, substitutions: &[Substitution]) {
+        for (flavor, grams) in order.get_flavors() {
+            let served = substitutions
+                .iter()
+                .find(|s| s.flavor == flavor)
+                .map(|s| &s.substitute)
+                .unwrap_or(&flavor);
+            let account = self.account(served.name());
+            if account.opening.is_some() {
+                account.consumed += grams;
+            }
+        }
+    }
+
+    /// Checks the grams seen on the tokens of each flavor against the expected ones, and returns the new violations.
+    /// The flavors in `busy` are being prepared, their tokens may not show the scoops of the orders yet.
+    /// A flavor that is not open is opened with the grams seen on its tokens once they are the same in two checks
+    pub fn check(
+        &mut self,
+        observed: &BTreeMap<String, usize>,
+        busy: &HashSet<String>,
+    ) -> Vec<Violation> {
+        let confirm_checks = self.confirm_checks;
+        let mut found = vec![];
+        for (flavor, grams) in observed {
+            let account = self.account(flavor);
+            let previous = account.observed.replace(*grams);
+            if busy.contains(flavor) {
+                if account.opening.is_none() {
+                    account.observed = None;
+                }
+                continue;
+            }
+            let expected = match account.expected() {
+                Some(expected) => expected,
+                None => {
+                    if previous == Some(*grams) {
+                        account.opening = Some(*grams);
+                    }
+                    continue;
+                }
+            };
+            let difference = *grams as i64 - expected;
+            if difference != account.difference {
+                account.difference = difference;
+                account.streak = 0;
+                account.reported = false;
+            }
+            if difference == 0 {
+                continue;
+            }
+            account.streak += 1;
+            if account.streak < confirm_checks || account.reported {
+                continue;
+            }
+            account.reported = true;
+            found.push(Violation {
+                flavor: flavor.clone(),
+                expected,
+                observed: *grams,
+                discrepancy: match difference < 0 {
+                    true => Discrepancy::Missing,
+                    false => Discrepancy::Surplus,
+                },
+                at_ms: now_ms(),
+            });
+        }
+        for violation in &found {
+            if self.violations.len() == MAX_VIOLATIONS {
+                self.violations.pop_front();
+            }
+            self.violations.push_back(violation.clone());
+        }
+        found
+    }
+
+    /// Returns the balance of every flavor and the last violations
+    pub fn report(&self) -> StockAccountsReport {
+        StockAccountsReport {
+            flavors: self
+                .accounts
+                .iter()
+                .map(|(flavor, account)| FlavorBalance {
+                    flavor: flavor.clone(),
+                    expected: account.expected(),
+                    observed: account.observed,
+                    restocked: account.restocked,
+                    consumed: account.consumed,
+                })
+                .collect(),
+            violations: self.violations.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::config::flavors::FlavorStock;
+
+    fn stock(mint: usize, lemon: usize) -> BTreeMap<String, usize> {
+        BTreeMap::from([("Mint".to_string(), mint), ("Lemon".to_string(), lemon)])
+    }
+
+    #[test]
+    fn test_lasting_differences_are_violations_once() {
+        let mut accounts = StockAccounts::new(2);
+        accounts.open(&FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 1000),
+            FlavorStock::new(FlavorID::Lemon, 1000),
+        ]));
+        let order = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        let substitution = Substitution {
+            flavor: FlavorID::Lemon,
+            substitute: FlavorID::Mint,
+        };
+        accounts.consumed(&order, &[substitution]);
+        accounts.restocked("Lemon", 100);
+        let none = HashSet::new();
+
+        assert!(accounts.check(&stock(750, 1100), &none).is_empty());
+        assert!(accounts.check(&stock(700, 1300), &none).is_empty());
+        let busy = HashSet::from(["Mint".to_string()]);
+        assert!(accounts.check(&stock(600, 1250), &busy).is_empty());
+        let violations = accounts.check(&stock(700, 1250), &none);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            (violations[0].expected, violations[0].discrepancy),
+            (1100, Discrepancy::Surplus)
+        );
+        assert_eq!(
+            (violations[1].expected, violations[1].discrepancy),
+            (750, Discrepancy::Missing)
+        );
+        assert!(accounts.check(&stock(700, 1250), &none).is_empty());
+        assert_eq!(accounts.report().violations.len(), 2);
+    }
+
+    #[test]
+    fn test_flavors_are_opened_with_the_grams_seen_twice() {
+        let mut accounts = StockAccounts::new(1);
+        let busy = HashSet::from(["Lemon".to_string()]);
+        accounts.consumed(&Order::new_cucurucho(FlavorID::Mint), &[]);
+
+        assert!(accounts.check(&stock(800, 500), &busy).is_empty());
+        assert!(accounts.check(&stock(800, 500), &busy).is_empty());
+        let balances = accounts.report().flavors;
+        assert_eq!(
+            (balances[0].flavor.as_str(), balances[0].expected),
+            ("Lemon", None)
+        );
+        assert_eq!(
+            (balances[1].flavor.as_str(), balances[1].expected),
+            ("Mint", Some(800))
+        );
+
+        accounts.consumed(&Order::new_cucurucho(FlavorID::Mint), &[]);
+        assert!(accounts.check(&stock(550, 500), &HashSet::new()).is_empty());
+        assert_eq!(accounts.check(&stock(500, 500), &HashSet::new()).len(), 1);
+    }
+}
@@ -0,0 +1,426 @@
+//! Control socket of a robot, used for operations and to drive chaos tests.
+//! Every line received is a command and is answered with a single line of JSON.
+
+use crate::common::log;
+use actix::Addr;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::common::flavor_id::FlavorID;
+use crate::robot::messages::{
+    AbortOrder, GetAssignments, GetDeadLetters, GetHoldings, GetLeader, GetOrderIds, GetQueueDepth,
+    GetRobotStatus, GetScreenStats, GetShopStats, GetStashedResults, GetStockAccounts,
+    GetTokenTrace, LeaveRing, ReplayDeadLetters, StartElection, TransferLeadership,
+    WriteConsumptionReport,
+};
+use crate::robot::order_manager::OrderManager;
+use crate::robot::ring_state::RingState;
+use crate::robot::robot_connection_handler::RobotConnectionHandler;
+use crate::robot::robot_leader::RobotLeader;
+
+/// State of the robot in the ring and the orders it is preparing
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RobotStatus {
+    pub robot_id: usize,
+    pub state: RingState,
+    pub leader_id: Option<usize>,
+    pub leader_epoch: u64,
+    pub next_robot_id: Option<usize>,
+    pub is_leader: bool,
+    pub leaving: bool,
+    pub orders_in_progress: Vec<String>,
+}
+
+/// Last time a token went through the robot
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Holding {
+    pub flavor: FlavorID,
+    pub shard: usize,
+    pub amount: usize,
+    pub epoch: u64,
+    pub seen_ms_ago: u64,
+}
+
+/// Commands accepted by the control socket
+#[derive(Debug, PartialEq)]
+pub enum AdminCommand {
+    Status,
+    Holdings,
+    Trace,
+    AbortOrder(String),
+    LeaveRing,
+    ForceElection,
+    TransferLeadership(usize),
+    QueueDepth,
+    ScreenStats,
+    ShopStats,
+    Assignments,
+    StashedResults,
+    DeadLetters,
+    ReplayDeadLetters(Option<String>),
+    ConsumptionReport,
+    StockAccounts,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AdminError {
+    UnknownCommand(String),
+    MissingArgument(String),
+    InvalidArgument(String),
+    OrderNotAborted(String),
+    NotLeader,
+    TransferRejected(String),
+    ActorNotAvailable(String),
+    ReportNotWritten(String),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdminError::UnknownCommand(cmd) => write!(
+                f,
+                "Unknown command {:?}, the commands are: status, holdings, trace, abort-order <id>, leave-ring, force-election, transfer-leadership <id>, queue-depth, screen-stats, shop-stats, assignments, stashed-results, dead-letters, replay-dead-letters [id], consumption-report, stock-accounts",
+                cmd
+            ),
+            AdminError::MissingArgument(cmd) => write!(f, "Missing argument for {}", cmd),
+            AdminError::InvalidArgument(arg) => write!(f, "Invalid argument {:?}", arg),
+            AdminError::OrderNotAborted(id) => write!(
+                f,
+                "Order {} is not in progress or it is already being served",
+                id
+            ),
+            AdminError::NotLeader => write!(f, "The robot is not the leader"),
+            AdminError::TransferRejected(err) => {
+                write!(f, "The leadership was not transferred: {}", err)
+            }
+            AdminError::ActorNotAvailable(err) => write!(f, "Could not reach the robot: {}", err),
+            AdminError::ReportNotWritten(err) => {
+                write!(f, "The consumption report was not written: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl FromStr for AdminCommand {
+    type Err = AdminError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        match command {
+            "status" => Ok(AdminCommand::Status),
+            "holdings" => Ok(AdminCommand::Holdings),
+            "trace" => Ok(AdminCommand::Trace),
+            "abort-order" => parts
+                .next()
+                .map(|id| AdminCommand::AbortOrder(id.to_string()))
+                .ok_or_else(|| AdminError::MissingArgument(command.to_string())),
+            "leave-ring" => Ok(AdminCommand::LeaveRing),
+            "force-election" => Ok(AdminCommand::ForceElection),
+            "queue-depth" => Ok(AdminCommand::QueueDepth),
+            "screen-stats" => Ok(AdminCommand::ScreenStats),
+            "shop-stats" => Ok(AdminCommand::ShopStats),
+            "assignments" => Ok(AdminCommand::Assignments),
+            "stashed-results" => Ok(AdminCommand::StashedResults),
+            "dead-letters" => Ok(AdminCommand::DeadLetters),
+            "consumption-report" => Ok(AdminCommand::ConsumptionReport),
+            "stock-accounts" => Ok(AdminCommand::StockAccounts),
+            "replay-dead-letters" => Ok(AdminCommand::ReplayDeadLetters(
+                parts.next().map(str::to_string),
+            )),
+            "transfer-leadership" => {
+                let arg = parts
+                    .next()
+                    .ok_or_else(|| AdminError::MissingArgument(command.to_string()))?;
+                arg.parse()
+                    .map(AdminCommand::TransferLeadership)
+                    .map_err(|_| AdminError::InvalidArgument(arg.to_string()))
+            }
+            _ => Err(AdminError::UnknownCommand(command.to_string())),
+        }
+    }
+}
+
+/// Answer to a command, `result` is set when it succeeds and `error` when it fails
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AdminResponse {
+    pub ok: bool,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+impl AdminResponse {
+    fn from_result<T: Serialize>(result: Result<T, AdminError>) -> Self {
+        match result.map(serde_json::to_value) {
+            Ok(Ok(value)) => Self {
+                ok: true,
+                result: Some(value),
+                error: None,
+            },
+            Ok(Err(e)) => Self::error(e.to_string()),
+            Err(e) => Self::error(e.to_string()),
+        }
+    }
+
+    fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Actors of the robot that the commands talk to
+#[derive(Clone)]
+pub struct AdminTargets {
+    pub rch: Addr<RobotConnectionHandler>,
+    pub order_manager: Addr<OrderManager>,
+}
+
+impl AdminTargets {
+    /// Gets the leader that the robot runs, the commands about the orders of the cluster are only answered by it
+    async fn leader(&self) -> Result<Addr<RobotLeader>, AdminError> {
+        self.rch
+            .send(GetLeader())
+            .await
+            .map_err(|e| AdminError::ActorNotAvailable(e.to_string()))?
+            .ok_or(AdminError::NotLeader)
+    }
+
+    /// Runs a command and returns its answer
+    pub async fn run(&self, command: AdminCommand) -> AdminResponse {
+        let unavailable = |e: actix::MailboxError| AdminError::ActorNotAvailable(e.to_string());
+        match command {
+            AdminCommand::Status => {
+                let status = async {
+                    let mut status = self.rch.send(GetRobotStatus()).await.map_err(unavailable)?;
+                    status.orders_in_progress = self
+                        .order_manager
+                        .send(GetOrderIds())
+                        .await
+                        .map_err(unavailable)?;
+                    Ok(status)
+                };
+                AdminResponse::from_result(status.await)
+            }
+            AdminCommand::Holdings => AdminResponse::from_result(
+                self.order_manager
+                    .send(GetHoldings())
+                    .await
+                    .map_err(unavailable),
+            ),
+            AdminCommand::Trace => AdminResponse::from_result(
+                self.rch.send(GetTokenTrace()).await.map_err(unavailable),
+            ),
+            AdminCommand::AbortOrder(order_id) => {
+                let aborted = self
+                    .order_manager
+                    .send(AbortOrder {
+                        order_id: order_id.clone(),
+                    })
+                    .await
+                    .map_err(unavailable)
+                    .and_then(|aborted| match aborted {
+                        true => Ok(order_id),
+                        false => Err(AdminError::OrderNotAborted(order_id)),
+                    });
+                AdminResponse::from_result(aborted)
+            }
+            AdminCommand::LeaveRing => AdminResponse::from_result(
+                self.rch
+                    .try_send(LeaveRing())
+                    .map_err(|e| AdminError::ActorNotAvailable(e.to_string())),
+            ),
+            AdminCommand::ForceElection => AdminResponse::from_result(
+                self.rch
+                    .try_send(StartElection())
+                    .map_err(|e| AdminError::ActorNotAvailable(e.to_string())),
+            ),
+            AdminCommand::TransferLeadership(target_robot) => {
+                let transferred = async {
+                    self.leader()
+                        .await?
+                        .send(TransferLeadership { target_robot })
+                        .await
+                        .map_err(unavailable)?
+                        .map_err(AdminError::TransferRejected)?;
+                    Ok(target_robot)
+                };
+                AdminResponse::from_result(transferred.await)
+            }
+            AdminCommand::QueueDepth => {
+                let depth = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetQueueDepth()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(depth.await)
+            }
+            AdminCommand::ScreenStats => {
+                let stats = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetScreenStats()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(stats.await)
+            }
+            AdminCommand::ShopStats => {
+                let stats = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetShopStats()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(stats.await)
+            }
+            AdminCommand::Assignments => {
+                let assignments = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetAssignments()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(assignments.await)
+            }
+            AdminCommand::StashedResults => {
+                let stashed = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetStashedResults()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(stashed.await)
+            }
+            AdminCommand::DeadLetters => {
+                let letters = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetDeadLetters()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(letters.await)
+            }
+            AdminCommand::ReplayDeadLetters(order_id) => {
+                let replayed = async {
+                    let leader = self.leader().await?;
+                    leader
+                        .send(ReplayDeadLetters { order_id })
+                        .await
+                        .map_err(unavailable)
+                };
+                AdminResponse::from_result(replayed.await)
+            }
+            AdminCommand::ConsumptionReport => {
+                let report = async {
+                    let leader = self.leader().await?;
+                    leader
+                        .send(WriteConsumptionReport())
+                        .await
+                        .map_err(unavailable)?
+                        .map_err(AdminError::ReportNotWritten)
+                };
+                AdminResponse::from_result(report.await)
+            }
+            AdminCommand::StockAccounts => {
+                let accounts = async {
+                    let leader = self.leader().await?;
+                    leader.send(GetStockAccounts()).await.map_err(unavailable)
+                };
+                AdminResponse::from_result(accounts.await)
+            }
+        }
+    }
+}
+
+/// Serves the control socket on the given address
+pub async fn serve(addr: String, targets: AdminTargets) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            let line = format!("Could not listen on {}: {}", addr, e);
+            log::error("ADMIN", line.red());
+            return;
+        }
+    };
+    let line = format!("Control socket listening on {}", addr);
+    log::info("ADMIN", line.bright_blue());
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer_commands(stream, targets.clone()));
+    }
+}
+
+/// Answers every command of the connection until it is closed
+async fn answer_commands(stream: TcpStream, targets: AdminTargets) {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        log::info("ADMIN", format!("Command received: {}", line.trim()));
+        let response = match line.parse::<AdminCommand>() {
+            Ok(command) => targets.run(command).await,
+            Err(e) => AdminResponse::error(e.to_string()),
+        };
+        let mut json = serde_json::to_string(&response)
+            .unwrap_or_else(|e| format!("{{\"ok\": false, \"error\": \"{}\"}}", e));
+        json.push('\n');
+        if write_half.write_all(json.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_parsed() {
+        assert_eq!("status".parse(), Ok(AdminCommand::Status));
+        assert_eq!("trace".parse(), Ok(AdminCommand::Trace));
+        assert_eq!(
+            " abort-order  abc ".parse(),
+            Ok(AdminCommand::AbortOrder("abc".to_string()))
+        );
+        assert_eq!(
+            "abort-order".parse::<AdminCommand>(),
+            Err(AdminError::MissingArgument("abort-order".to_string()))
+        );
+        assert_eq!("queue-depth".parse(), Ok(AdminCommand::QueueDepth));
+        assert_eq!("screen-stats".parse(), Ok(AdminCommand::ScreenStats));
+        assert_eq!("shop-stats".parse(), Ok(AdminCommand::ShopStats));
+        assert_eq!(
+            "consumption-report".parse(),
+            Ok(AdminCommand::ConsumptionReport)
+        );
+        assert_eq!("stock-accounts".parse(), Ok(AdminCommand::StockAccounts));
+        assert_eq!(
+            "replay-dead-letters".parse(),
+            Ok(AdminCommand::ReplayDeadLetters(None))
+        );
+        assert_eq!(
+            "replay-dead-letters 0-7".parse(),
+            Ok(AdminCommand::ReplayDeadLetters(Some("0-7".to_string())))
+        );
+        assert_eq!(
+            "transfer-leadership 2".parse(),
+            Ok(AdminCommand::TransferLeadership(2))
+        );
+        assert_eq!(
+            "transfer-leadership two".parse::<AdminCommand>(),
+            Err(AdminError::InvalidArgument("two".to_string()))
+        );
+        assert!(matches!(
+            "reboot".parse::<AdminCommand>(),
+            Err(AdminError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_command_has_no_result() {
+        let response =
+            AdminResponse::from_result::<()>(Err(AdminError::OrderNotAborted("abc".to_string())));
+        assert!(!response.ok);
+        assert!(response.result.is_none());
+        assert!(response.error.unwrap().contains("abc"));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::common::log;
+use crate::common::persistence_writer;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::protocol::leader_backup::LeaderBackup;
+use crate::robot::errors::BackupStoreError;
+
+/// Returns the path of the snapshot file of the robot with the given id
+pub fn snapshot_path(data_dir: &str, robot_id: usize) -> PathBuf {
+    Path::new(data_dir).join(format!("leader_backup_{}.json", robot_id))
+}
+
+/// Writes the backup to the snapshot file of the robot.
+/// The snapshot is first written to a temporary file and then renamed, so a crash never leaves a half written snapshot
+pub fn save_snapshot(
+    data_dir: &str,
+    robot_id: usize,
+    backup: &LeaderBackup,
+) -> Result<(), BackupStoreError> {
+    let json =
+        serde_json::to_string(backup).map_err(|e| BackupStoreError::ErrorParsing(e.to_string()))?;
+    let fsync = config::get().persistence.fsync;
+    persistence_writer::write_atomically(&snapshot_path(data_dir, robot_id), &json, fsync)
+        .map_err(|e| BackupStoreError::CouldNotWrite(e.to_string()))
+}
+
+/// Reads the snapshot file of the robot, returns None if the robot never stored one
+pub fn load_snapshot(
+    data_dir: &str,
+    robot_id: usize,
+) -> Result<Option<LeaderBackup>, BackupStoreError> {
+    let json = match fs::read_to_string(snapshot_path(data_dir, robot_id)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(BackupStoreError::CouldNotRead(e.to_string())),
+    };
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| BackupStoreError::ErrorParsing(e.to_string()))
+}
+
+/// Stores the backup on disk if persistence is enabled in the config.
+/// It is written by the persistence writer of the process, so the caller does not wait for the disk
+pub fn persist_leader_backup(robot_id: usize, backup: &LeaderBackup) {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return;
+    }
+    match serde_json::to_string(backup) {
+        Ok(json) => {
+            persistence_writer::snapshot(snapshot_path(&persistence.data_dir, robot_id), json)
+        }
+        Err(e) => log::error("BS", format!("Error! {}", e)),
+    }
+}
+
+/// Loads the backup stored on disk if persistence is enabled in the config
+pub fn restore_leader_backup(robot_id: usize) -> Option<LeaderBackup> {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return None;
+    }
+    match load_snapshot(&persistence.data_dir, robot_id) {
+        Ok(backup) => backup,
+        Err(e) => {
+            log::error("BS", format!("Error! {}", e));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use crate::protocol::order_info::OrderInfo;
+    use crate::protocol::order_ledger::OrderLedger;
+    use std::collections::{HashMap, VecDeque};
+
+    fn test_dir(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "freddo_backup_store_{}_{}",
+                name,
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_saved_snapshot_is_loaded_back() {
+        let dir = test_dir("roundtrip");
+        let mut orders_on_queue = VecDeque::new();
+        orders_on_queue.push_back(OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: "1".to_string(),
+            screen_id: 0,
+            deadline_ms: None,
+            source: None,
+        });
+        let backup = LeaderBackup::new(
+            vec![1, 2],
+            vec![0],
+            orders_on_queue,
+            HashMap::new(),
+            Vec::new(),
+            OrderLedger::new(),
+        );
+
+        save_snapshot(&dir, 3, &backup).unwrap();
+        assert_eq!(load_snapshot(&dir, 3).unwrap(), Some(backup));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_missing_snapshot_is_none() {
+        let dir = test_dir("missing");
+        assert_eq!(load_snapshot(&dir, 0).unwrap(), None);
+    }
+}
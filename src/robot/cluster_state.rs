@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::flavor_id::FlavorID;
+use crate::protocol::order_info::OrderInfo;
+
+/// How many finished orders the leader remembers for the dashboard
+pub const RECENT_COMPLETIONS: usize = 20;
+
+/// An order that a robot finished, successfully or not
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Completion {
+    pub order_id: String,
+    pub robot_id: usize,
+    pub completed: bool,
+    pub flavor: Option<FlavorID>,
+    pub timestamp_ms: u64,
+}
+
+impl Completion {
+    pub fn new(order_id: String, robot_id: usize, flavor: Option<FlavorID>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            order_id,
+            robot_id,
+            completed: flavor.is_none(),
+            flavor,
+            timestamp_ms,
+        }
+    }
+}
+
+/// The orders a robot is preparing
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RobotOrders {
+    pub robot_id: usize,
+    pub orders: Vec<String>,
+}
+
+/// Snapshot of the state of the cluster as seen by the leader, it is what the dashboard shows
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ClusterState {
+    pub leader_id: usize,
+    pub available_robots: Vec<usize>,
+    pub robots_orders: Vec<RobotOrders>,
+    pub orders_on_queue: Vec<OrderInfo>,
+    pub stock: BTreeMap<String, usize>,
+    pub screens: Vec<usize>,
+    pub recent_completions: Vec<Completion>,
+}
+
+/// Adds the completion to the recent ones, the oldest are forgotten first
+pub fn push_completion(recent: &mut VecDeque<Completion>, completion: Completion) {
+    recent.push_back(completion);
+    while recent.len() > RECENT_COMPLETIONS {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_last_completions_are_kept() {
+        let mut recent = VecDeque::new();
+        for i in 0..=RECENT_COMPLETIONS {
+            push_completion(&mut recent, Completion::new(i.to_string(), 0, None));
+        }
+        assert_eq!(recent.len(), RECENT_COMPLETIONS);
+        assert_eq!(recent.front().unwrap().order_id, "1");
+
+        let aborted = Completion::new("x".to_string(), 1, Some(FlavorID::Mint));
+        assert!(!aborted.completed);
+    }
+}
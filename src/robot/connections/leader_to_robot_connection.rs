@@ -1,15 +1,19 @@
+use crate::common::chaos;
+use crate::common::log;
+use crate::common::mailbox;
 use actix::prelude::*;
-use tokio::io::AsyncWriteExt;
 use tokio::net::tcp::OwnedWriteHalf;
 
+use crate::protocol::flavor_token::FlavorToken;
 use crate::robot::messages::*;
 use crate::robot::robot_leader::RobotLeader;
-use crate::robot::utils::{print_create_error, print_send_error};
 
 /// Actor that represents the connection between the Leader and a Robot
+/// Every command it sends carries the epoch of the leader, so the robot can reject a leader that was replaced
 pub struct LeaderToRobotConnection {
     leader: Addr<RobotLeader>,
     my_id: usize,
+    epoch: u64,
     write_half: Option<OwnedWriteHalf>,
 }
 
@@ -21,14 +25,27 @@ impl LeaderToRobotConnection {
     pub fn new(
         leader: Addr<RobotLeader>,
         my_id: usize,
+        epoch: u64,
         write_half: Option<OwnedWriteHalf>,
     ) -> Self {
         Self {
             leader,
             my_id,
+            epoch,
             write_half,
         }
     }
+
+    /// Gives the leader back the tokens that could not be started at the robot
+    fn tokens_not_started(&self, all_tokens: Vec<FlavorToken>) {
+        let robot_id = self.my_id;
+        if let Err(e) = self.leader.try_send(TokensNotStarted {
+            robot_id,
+            all_tokens,
+        }) {
+            log::send_error("LTR", "TokensNotStarted", &e.to_string());
+        }
+    }
 }
 
 impl Handler<Harakiri> for LeaderToRobotConnection {
@@ -54,18 +71,26 @@ impl Handler<SendNewOrder> for LeaderToRobotConnection {
         let order_msg = RobotCommand::NewOrder {
             order: msg.new_order,
             order_id: msg.order_id,
+            trace_id: msg.trace_id,
+            epoch: self.epoch,
         }
-        .to_string();
-        let msg: String;
+        .to_bytes();
+        let msg: Vec<u8>;
         match order_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
+                let robot_id = self.my_id;
                 if let Some(mut write_half) = self.write_half.take() {
                     async move {
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!(
-                                "[LTR] Error trying to send NewOrder Message. Message dumped\n{}",
-                                e
+                        if let Err(e) =
+                            chaos::write_all(&mut write_half, &msg, "LTR", robot_id).await
+                        {
+                            log::error(
+                                "LTR",
+                                format!(
+                                    "Error trying to send NewOrder Message. Message dumped\n{}",
+                                    e
+                                ),
                             );
                         }
                         write_half
@@ -78,8 +103,205 @@ impl Handler<SendNewOrder> for LeaderToRobotConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[LTR]", "NewOrder", &e.to_string());
+                log::create_error("LTR", "NewOrder", &e.to_string());
+            }
+        }
+    }
+}
+
+/// Tells the robot to drop an order that passed its deadline, if it can not be sent the order is dropped
+/// anyway, since the leader does not wait for its result anymore
+impl Handler<CancelOrder> for LeaderToRobotConnection {
+    type Result = ();
+    fn handle(&mut self, msg: CancelOrder, ctx: &mut Self::Context) -> Self::Result {
+        let cancel_msg = match (RobotCommand::CancelOrder {
+            order_id: msg.order_id,
+            epoch: self.epoch,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("LTR", "CancelOrder", &e.to_string());
+                return;
+            }
+        };
+        let robot_id = self.my_id;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) =
+                    chaos::write_all(&mut write_half, &cancel_msg, "LTR", robot_id).await
+                {
+                    log::error(
+                        "LTR",
+                        format!("Error trying to send CancelOrder Message: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the robot which of its orders in flight it resumes and which ones it drops
+impl Handler<ResumeOrders> for LeaderToRobotConnection {
+    type Result = ();
+    fn handle(&mut self, msg: ResumeOrders, ctx: &mut Self::Context) -> Self::Result {
+        let resume_msg = match (RobotCommand::ResumeOrders {
+            resume: msg.resume,
+            drop: msg.drop,
+            epoch: self.epoch,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("LTR", "ResumeOrders", &e.to_string());
+                return;
+            }
+        };
+        let robot_id = self.my_id;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) =
+                    chaos::write_all(&mut write_half, &resume_msg, "LTR", robot_id).await
+                {
+                    log::error(
+                        "LTR",
+                        format!("Error trying to send ResumeOrders Message: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Starts the tokens of the first leader at the robot, if they can not be sent they go back to the leader
+impl Handler<StartTokens> for LeaderToRobotConnection {
+    type Result = ();
+    fn handle(&mut self, msg: StartTokens, ctx: &mut Self::Context) -> Self::Result {
+        let tokens_msg = match (RobotCommand::StartTokens {
+            tokens: msg.all_tokens.clone(),
+            epoch: self.epoch,
+        })
+        .to_bytes()
+        {
+            Ok(t_msg) => t_msg,
+            Err(e) => {
+                log::create_error("LTR", "StartTokens", &e.to_string());
+                self.tokens_not_started(msg.all_tokens);
+                return;
             }
+        };
+        let robot_id = self.my_id;
+        let Some(mut write_half) = self.write_half.take() else {
+            self.tokens_not_started(msg.all_tokens);
+            return;
+        };
+        async move {
+            let written = chaos::write_all(&mut write_half, &tokens_msg, "LTR", robot_id).await;
+            (write_half, written)
+        }
+        .into_actor(self)
+        .map(move |(w_half, written), actor, _| {
+            actor.write_half = Some(w_half);
+            if let Err(e) = written {
+                log::error(
+                    "LTR",
+                    format!("Error trying to send StartTokens Message: {}", e),
+                );
+                actor.tokens_not_started(msg.all_tokens);
+            }
+        })
+        .wait(ctx);
+    }
+}
+
+/// Hands the leadership to the robot with the last backup of the leader
+impl Handler<HandOffLeadership> for LeaderToRobotConnection {
+    type Result = ();
+    fn handle(&mut self, msg: HandOffLeadership, ctx: &mut Self::Context) -> Self::Result {
+        let handoff_msg = match (RobotCommand::TakeLeadership {
+            backup: msg.backup,
+            next_epoch: msg.next_epoch,
+            epoch: self.epoch,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("LTR", "TakeLeadership", &e.to_string());
+                return;
+            }
+        };
+        let robot_id = self.my_id;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) =
+                    chaos::write_all(&mut write_half, &handoff_msg, "LTR", robot_id).await
+                {
+                    log::error(
+                        "LTR",
+                        format!("Error trying to send TakeLeadership Message: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the robot which robot takes the leadership, if it can not be sent the robot finds out by an election
+impl Handler<AnnounceLeader> for LeaderToRobotConnection {
+    type Result = ();
+    fn handle(&mut self, msg: AnnounceLeader, ctx: &mut Self::Context) -> Self::Result {
+        let announce_msg = match (RobotCommand::LeaderTransferred {
+            leader: msg.leader_id,
+            next_epoch: msg.next_epoch,
+            epoch: self.epoch,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("LTR", "LeaderTransferred", &e.to_string());
+                return;
+            }
+        };
+        let robot_id = self.my_id;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) =
+                    chaos::write_all(&mut write_half, &announce_msg, "LTR", robot_id).await
+                {
+                    log::error(
+                        "LTR",
+                        format!("Error trying to send LeaderTransferred Message: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
         }
     }
 }
@@ -93,17 +315,27 @@ impl Handler<SendLeaderBackup> for LeaderToRobotConnection {
         //     self.my_id.clone()
         // );
         // println!("{}", line.bright_magenta());
-        let backup_msg = RobotCommand::ReceiveLeaderBackup { backup: msg.backup }.to_string();
-        let msg: String;
+        let backup_msg = RobotCommand::ReceiveLeaderBackup {
+            backup: msg.backup,
+            epoch: self.epoch,
+        }
+        .to_bytes();
+        let msg: Vec<u8>;
         match backup_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
+                let robot_id = self.my_id;
                 if let Some(mut write_half) = self.write_half.take() {
                     async move {
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!(
-                                "[LTR] Error trying to send LeaderBackup Message. Message dumped\n{}",
-                                e
+                        if let Err(e) =
+                            chaos::write_all(&mut write_half, &msg, "LTR", robot_id).await
+                        {
+                            log::error(
+                                "LTR",
+                                format!(
+                                    "Error trying to send LeaderBackup Message. Message dumped\n{}",
+                                    e
+                                ),
                             );
                         }
                         write_half
@@ -116,34 +348,44 @@ impl Handler<SendLeaderBackup> for LeaderToRobotConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[LTR]", "ReceiveLeaderBackup", &e.to_string());
+                log::create_error("LTR", "ReceiveLeaderBackup", &e.to_string());
             }
         }
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for LeaderToRobotConnection {
-    fn handle(&mut self, data: Result<String, std::io::Error>, _ctx: &mut Self::Context) {
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for LeaderToRobotConnection {
+    fn handle(&mut self, data: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
+        if chaos::crash("LTR", self.my_id) {
+            ctx.stop();
+            return;
+        }
         match data {
             Ok(t) => {
-                match RobotCommand::from_string(&t).map_err(|err| err.to_string()) {
+                match RobotCommand::from_bytes(&t).map_err(|err| err.to_string()) {
                     Ok(msg) => {
                         match msg {
-                            RobotCommand::OrderComplete { result, order_id } => {
+                            RobotCommand::OrderComplete {
+                                result,
+                                order_id,
+                                substitutions,
+                            } => {
                                 // let line = format!("[LTR]: Recibi un mensaje de orden completada {:?}, con el id {:?}", result, order_id);
                                 // println!("{}", line.bright_magenta());
                                 if let Err(e) = self.leader.try_send(GetCompletedOrder {
                                     order_result: result,
                                     order_id,
                                     robot_id: self.my_id,
+                                    substitutions,
                                 }) {
-                                    print_send_error("[LTR]", "GetCompletedOrder", &e.to_string());
+                                    log::send_error("LTR", "GetCompletedOrder", &e.to_string());
                                 }
                             }
                             RobotCommand::OrderNotFinished {
                                 result,
                                 order_id,
                                 flavor,
+                                reason,
                             } => {
                                 // let line = format!("[LTR]: Recibi un mensaje de orden abortada {:?}, con el id {:?}", result, order_id);
                                 // println!("{}", line.bright_magenta());
@@ -152,22 +394,64 @@ impl StreamHandler<Result<String, std::io::Error>> for LeaderToRobotConnection {
                                     order_id,
                                     robot_id: self.my_id,
                                     flavor,
+                                    reason,
+                                }) {
+                                    log::send_error("LTR", "GetCompletedOrder", &e.to_string());
+                                }
+                            }
+                            RobotCommand::LeaveRing { robot_id } => {
+                                if let Err(e) = self.leader.try_send(RobotLeaving { robot_id }) {
+                                    log::send_error("LTR", "RobotLeaving", &e.to_string());
+                                }
+                            }
+                            RobotCommand::RobotUnavailable { robot_id, until } => {
+                                if let Err(e) =
+                                    self.leader.try_send(RobotUnavailable { robot_id, until })
+                                {
+                                    log::send_error("LTR", "RobotUnavailable", &e.to_string());
+                                }
+                            }
+                            RobotCommand::ScoopSpeed {
+                                robot_id,
+                                ms_per_gram,
+                                compressions,
+                            } => {
+                                if let Err(e) = self.leader.try_send(ScoopSpeed {
+                                    robot_id,
+                                    ms_per_gram,
+                                    compressions,
                                 }) {
-                                    print_send_error("[LTR]", "GetCompletedOrder", &e.to_string());
+                                    log::send_error("LTR", "ScoopSpeed", &e.to_string());
+                                }
+                            }
+                            RobotCommand::OrdersInFlight { robot_id, orders } => {
+                                if let Err(e) =
+                                    self.leader.try_send(OrdersInFlight { robot_id, orders })
+                                {
+                                    log::send_error("LTR", "OrdersInFlight", &e.to_string());
+                                }
+                            }
+                            RobotCommand::FullBackupNeeded { robot_id } => {
+                                let resend = ResendBackup { robot_id };
+                                mailbox::send_or_shed(&self.leader, resend, "ResendBackup");
+                            }
+                            RobotCommand::StaleLeader { epoch } => {
+                                if let Err(e) = self.leader.try_send(StepDown { epoch }) {
+                                    log::send_error("LTR", "StepDown", &e.to_string());
                                 }
                             }
-                            _ => {
-                                println!("[LTR]: Error! Did not understand StreamHandler message. I got: {}", t);
+                            other => {
+                                log::error("LTR", format!("Error! Did not understand StreamHandler message. I got: {:?}", other));
                             }
                         }
                     }
                     Err(e) => {
-                        println!("Error parsing message:  {}", e);
+                        log::error("LTR", format!("Error parsing message:  {}", e));
                     }
                 }
             }
             Err(e) => {
-                println!("[LTR] Error! Connection with robot died! : {}", e);
+                log::error("LTR", format!("Error! Connection with robot died! : {}", e));
             }
         }
     }
@@ -177,7 +461,7 @@ impl StreamHandler<Result<String, std::io::Error>> for LeaderToRobotConnection {
         if let Err(e) = self.leader.try_send(RobotDied {
             robot_id: self.my_id,
         }) {
-            print_send_error("[LTR]", "Harakiri", &e.to_string());
+            log::send_error("LTR", "Harakiri", &e.to_string());
         }
     }
 }
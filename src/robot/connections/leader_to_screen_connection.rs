@@ -1,35 +1,269 @@
+use crate::common::chaos;
+use crate::common::log;
+use crate::common::metrics;
 use actix::prelude::*;
 use colored::*;
-use tokio::io::AsyncWriteExt;
+use std::collections::HashSet;
+use std::time::Duration;
 use tokio::net::tcp::OwnedWriteHalf;
+use tokio::time::Instant;
 
-use crate::common::robot_messages::*;
-use crate::common::screen_messages::*;
+use crate::config;
+use crate::protocol::robot_messages::*;
+use crate::protocol::screen_messages::*;
 use crate::robot::messages::*;
 use crate::robot::robot_leader::RobotLeader;
-use crate::robot::utils::{print_create_error, print_send_error};
+use crate::robot::screen_sequences::{ScreenSequences, SequenceCheck};
 
 /// Actor that represents the connection between the RobotLeader and a Screen
+/// Every message it writes carries the epoch of the leader, so the screen can reject an old leader.
+/// The screen is pinged every heartbeat interval, if nothing is heard from it within the timeout it is taken as dead.
+/// The numbered messages of the screen that the leader already got are dropped
 pub struct LeaderToScreenConnection {
     leader: Addr<RobotLeader>,
     write_half: Option<OwnedWriteHalf>,
     screen_id: usize,
+    leader_id: usize,
+    epoch: u64,
+    last_heard: Instant,
+    dead: bool,
+    sequences: ScreenSequences,
+    nonces: HashSet<u64>,
 }
 
 impl Actor for LeaderToScreenConnection {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let leader_id = self.leader_id;
+        let epoch = self.epoch;
+        self.send_message(RobotMessage::NewLeader { leader_id, epoch }, ctx);
+        let interval = Duration::from_millis(config::get().heartbeat.interval_ms);
+        ctx.run_interval(interval, |actor, ctx| actor.check_health(ctx));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for nonce in self.nonces.drain() {
+            self.sequences.close(self.screen_id, nonce);
+        }
+    }
 }
 
 impl LeaderToScreenConnection {
     pub fn new(
         screen_id: usize,
         leader: Addr<RobotLeader>,
+        leader_id: usize,
+        epoch: u64,
         write_half: Option<OwnedWriteHalf>,
     ) -> Self {
         Self {
             screen_id,
             leader,
             write_half,
+            leader_id,
+            epoch,
+            last_heard: Instant::now(),
+            dead: false,
+            sequences: ScreenSequences::new(),
+            nonces: HashSet::new(),
+        }
+    }
+
+    /// Checks the numbers of the messages with the ones of the other connections of the leader
+    pub fn with_sequences(mut self, sequences: ScreenSequences) -> Self {
+        self.sequences = sequences;
+        self
+    }
+
+    /// Pings the screen, or takes it as dead if it did not answer within the timeout
+    fn check_health(&mut self, ctx: &mut Context<Self>) {
+        let timeout = Duration::from_millis(config::get().heartbeat.timeout_ms);
+        if self.last_heard.elapsed() < timeout {
+            let epoch = self.epoch;
+            self.send_message(RobotMessage::Ping { epoch }, ctx);
+            return;
+        }
+        let line = format!(
+            "Screen {} did not answer for {} ms",
+            self.screen_id,
+            timeout.as_millis()
+        );
+        log::warn("SC", line.bright_purple());
+        self.screen_died();
+        ctx.stop();
+    }
+
+    /// Tells the leader that the screen died, only once even if the stream also finishes
+    fn screen_died(&mut self) {
+        if self.dead {
+            return;
+        }
+        self.dead = true;
+        if let Err(e) = self.leader.try_send(ScreenDied {
+            screen_id: self.screen_id,
+        }) {
+            log::send_error("SC", "ScreenDied", &e.to_string());
+        }
+    }
+
+    /// Writes a message that is not stashed if it can not be sent
+    fn send_message(&mut self, msg: RobotMessage, ctx: &mut Context<Self>) {
+        let msg = match msg.to_bytes() {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::create_error("SC", "RobotMessage", &e.to_string());
+                return;
+            }
+        };
+        let screen_id = self.screen_id;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = chaos::write_all(&mut write_half, &msg, "LTS", screen_id).await {
+                    log::warn(
+                        "SC",
+                        format!("Error trying to send a message to Screen: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+
+    /// Hands the message of the screen to the leader.
+    /// A numbered message is handed only if its number is newer than the last one of its connection
+    /// and the connection was not closed, the rest are dropped and counted.
+    /// The connections of the messages are closed when this one stops
+    fn handle_screen_message(&mut self, msg: ScreenMessage) {
+        match msg {
+            ScreenMessage::Sequenced {
+                nonce,
+                seq,
+                message,
+            } => match self.sequences.check(self.screen_id, nonce, seq) {
+                SequenceCheck::Accepted => {
+                    self.nonces.insert(nonce);
+                    self.handle_screen_message(*message)
+                }
+                rejected => {
+                    let line = format!(
+                        "Dropping message {} of Screen {}: {}",
+                        seq,
+                        self.screen_id,
+                        rejected.as_str()
+                    );
+                    log::warn("SC", line.bright_purple());
+                    metrics::get().screen_message_rejected(rejected.as_str());
+                }
+            },
+            ScreenMessage::PrepareNewOrder {
+                order_id,
+                order,
+                screen_id,
+                deadline_ms,
+                source,
+            } => {
+                // let line =
+                //     format!("[SC]: Recibi un mensaje de orden {:?}", order_id);
+                // println!("{}", line.bright_purple());
+                if let Err(e) = self.leader.try_send(CreateNewOrder {
+                    id: order_id,
+                    new_order: order,
+                    screen_id,
+                    deadline_ms,
+                    source,
+                }) {
+                    log::send_error("SC", "GetNewOrder", &e.to_string());
+                }
+            }
+            ScreenMessage::PrepareNewOrderBatch {
+                screen_id,
+                orders,
+                deadline_ms,
+                sources,
+            } => {
+                if let Err(e) = self.leader.try_send(CreateNewOrderBatch {
+                    orders,
+                    screen_id,
+                    deadline_ms,
+                    sources,
+                }) {
+                    log::send_error("SC", "CreateNewOrderBatch", &e.to_string());
+                }
+            }
+            ScreenMessage::RequestRobotLeaderConnection { screen_id } => {
+                if let Err(e) = self.leader.try_send(ConnectToNewScreen { screen_id }) {
+                    log::send_error("SC", "ConnectToNewScreen", &e.to_string());
+                }
+            }
+            ScreenMessage::ScreenRejoined {
+                screen_id,
+                order_ids,
+            } => {
+                if let Err(e) = self.leader.try_send(ScreenRejoined {
+                    screen_id,
+                    order_ids,
+                }) {
+                    log::send_error("SC", "ScreenRejoined", &e.to_string());
+                }
+            }
+            ScreenMessage::StaleLeader { epoch } => {
+                if let Err(e) = self.leader.try_send(StepDown { epoch }) {
+                    log::send_error("SC", "StepDown", &e.to_string());
+                }
+            }
+            ScreenMessage::GiveMeThisScreenOrders { my_id, death_id } => {
+                if let Err(e) = self.leader.try_send(ChangeScreen {
+                    original_screen_id: death_id,
+                    new_screen_id: my_id,
+                }) {
+                    log::send_error("SC", "ConnectToNewScreen", &e.to_string());
+                }
+            }
+            ScreenMessage::QueryStatus {
+                screen_id,
+                order_id,
+            } => {
+                if let Err(e) = self.leader.try_send(QueryOrderStatus {
+                    screen_id,
+                    order_id,
+                }) {
+                    log::send_error("SC", "QueryOrderStatus", &e.to_string());
+                }
+            }
+            ScreenMessage::QueryMenu { screen_id } => {
+                if let Err(e) = self.leader.try_send(QueryMenu { screen_id }) {
+                    log::send_error("SC", "QueryMenu", &e.to_string());
+                }
+            }
+            ScreenMessage::OrderPickedUp {
+                screen_id,
+                order_id,
+                waited_ms,
+            } => {
+                if let Err(e) = self.leader.try_send(OrderPickedUp {
+                    screen_id,
+                    order_id,
+                    waited_ms,
+                }) {
+                    log::send_error("SC", "OrderPickedUp", &e.to_string());
+                }
+            }
+            ScreenMessage::Pong { .. } => {}
+            other => {
+                log::error(
+                    "SC",
+                    format!(
+                        "Error! Did not understand StreamHandler message. I got: {:?}",
+                        other
+                    ),
+                );
+            }
         }
     }
 }
@@ -42,66 +276,30 @@ impl Handler<Harakiri> for LeaderToScreenConnection {
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for LeaderToScreenConnection {
-    fn handle(&mut self, data: Result<String, std::io::Error>, _ctx: &mut Self::Context) {
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for LeaderToScreenConnection {
+    fn handle(&mut self, data: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
+        if chaos::crash("LTS", self.screen_id) {
+            ctx.stop();
+            return;
+        }
         match data {
             Ok(t) => {
-                match ScreenMessage::from_string(&t).map_err(|err| err.to_string()) {
-                    Ok(msg) => {
-                        match msg {
-                            ScreenMessage::PrepareNewOrder {
-                                order_id,
-                                order,
-                                screen_id,
-                            } => {
-                                // let line =
-                                //     format!("[SC]: Recibi un mensaje de orden {:?}", order_id);
-                                // println!("{}", line.bright_purple());
-                                if let Err(e) = self.leader.try_send(CreateNewOrder {
-                                    id: order_id,
-                                    new_order: order,
-                                    screen_id,
-                                }) {
-                                    print_send_error("[SC]", "GetNewOrder", &e.to_string());
-                                }
-                            }
-                            ScreenMessage::RequestRobotLeaderConnection { screen_id } => {
-                                if let Err(e) =
-                                    self.leader.try_send(ConnectToNewScreen { screen_id })
-                                {
-                                    print_send_error("[SC]", "ConnectToNewScreen", &e.to_string());
-                                }
-                            }
-                            ScreenMessage::GiveMeThisScreenOrders { my_id, death_id } => {
-                                if let Err(e) = self.leader.try_send(ChangeScreen {
-                                    original_screen_id: death_id,
-                                    new_screen_id: my_id,
-                                }) {
-                                    print_send_error("[SC]", "ConnectToNewScreen", &e.to_string());
-                                }
-                            }
-                            _ => {
-                                println!("[SC]: Error! Did not understand StreamHandler message. I got: {}", t);
-                            }
-                        }
-                    }
+                self.last_heard = Instant::now();
+                match ScreenMessage::from_bytes(&t).map_err(|err| err.to_string()) {
+                    Ok(msg) => self.handle_screen_message(msg),
                     Err(e) => {
-                        println!("[SC]: Error parsing message: {}", e);
+                        log::error("SC", format!("Error parsing message: {}", e));
                     }
                 }
             }
             Err(e) => {
-                println!("Error! Screen died\n{}", e);
+                log::error("SC", format!("Error! Screen died\n{}", e));
             }
         }
     }
 
     fn finished(&mut self, _ctx: &mut Self::Context) {
-        if let Err(e) = self.leader.try_send(ScreenDied {
-            screen_id: self.screen_id,
-        }) {
-            print_send_error("[SC]", "ScreenDied", &e.to_string());
-        }
+        self.screen_died();
     }
 }
 
@@ -110,32 +308,42 @@ impl Handler<OrderPrepared> for LeaderToScreenConnection {
     fn handle(&mut self, msg: OrderPrepared, ctx: &mut Self::Context) -> Self::Result {
         let result = msg.order_result;
         let order_id = msg.id;
+        let substitutions = msg.substitutions;
 
         let order_msg = RobotMessage::OrderPrepared {
             order_id: order_id.clone(),
+            substitutions: substitutions.clone(),
+            epoch: self.epoch,
         }
-        .to_string();
+        .to_bytes();
 
-        let msg: String;
+        let msg: Vec<u8>;
         match order_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
                 if let Some(mut write_half) = self.write_half.take() {
                     let leader = self.leader.clone();
                     let screen_id = self.screen_id;
                     async move {
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!(
-                                "[SC] Error trying to send OrderResult to Screen. Stashing...: {}",
-                                e
+                        if let Err(e) =
+                            chaos::write_all(&mut write_half, &msg, "LTS", screen_id).await
+                        {
+                            log::warn(
+                                "SC",
+                                format!(
+                                    "Error trying to send OrderResult to Screen. Stashing...: {}",
+                                    e
+                                ),
                             );
                             if let Err(e) = leader.try_send(AddOrderToBeSent {
                                 id: order_id,
                                 order_result: result,
                                 screen_id,
                                 flavor: None,
+                                reason: AbortReason::default(),
+                                substitutions,
                             }) {
-                                print_send_error("[SC]", "ConnectToNewScreen", &e.to_string());
+                                log::send_error("SC", "ConnectToNewScreen", &e.to_string());
                             }
                         }
                         write_half
@@ -148,54 +356,174 @@ impl Handler<OrderPrepared> for LeaderToScreenConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[SC]", "OrderCompleated", &e.to_string());
+                log::create_error("SC", "OrderCompleated", &e.to_string());
             }
         }
     }
 }
 
+/// Tells the screen that the leader is busy, if it can not be sent the screen finds out by its result timer
+impl Handler<OrderRejectedBusy> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: OrderRejectedBusy, ctx: &mut Self::Context) -> Self::Result {
+        let order_msg = RobotMessage::OrderRejectedBusy {
+            order_id: msg.id,
+            epoch: self.epoch,
+        };
+        self.send_message(order_msg, ctx);
+    }
+}
+
+/// Tells the screen that the order was not taken, its payment is aborted and it is not sent again
+impl Handler<OrderRejectedUnservable> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: OrderRejectedUnservable, ctx: &mut Self::Context) -> Self::Result {
+        let order_msg = RobotMessage::OrderAborted {
+            order_id: msg.id,
+            error: msg.error,
+            reason: msg.reason,
+            flavor: msg.flavor,
+            epoch: self.epoch,
+        };
+        self.send_message(order_msg, ctx);
+    }
+}
+
+/// Tells the screen that an order it confirmed was aborted, so its payment is refunded
+impl Handler<OrderAbortedAfterConfirm> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: OrderAbortedAfterConfirm, ctx: &mut Self::Context) -> Self::Result {
+        let order_msg = RobotMessage::OrderAbortedAfterConfirm {
+            order_id: msg.id,
+            error: msg.reason.describe(&msg.flavor),
+            reason: msg.reason,
+            epoch: self.epoch,
+        };
+        self.send_message(order_msg, ctx);
+    }
+}
+
+/// Tells the screen which robot takes the leadership, so it waits for it instead of the current leader
+impl Handler<AnnounceLeader> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: AnnounceLeader, ctx: &mut Self::Context) -> Self::Result {
+        let order_msg = RobotMessage::LeaderTransferred {
+            leader_id: msg.leader_id,
+            next_epoch: msg.next_epoch,
+            epoch: self.epoch,
+        };
+        self.send_message(order_msg, ctx);
+    }
+}
+
+/// Tells the screen how many orders are ahead of one of its orders
+impl Handler<SendQueueStatus> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: SendQueueStatus, ctx: &mut Self::Context) -> Self::Result {
+        let status_msg = RobotMessage::QueueStatus {
+            order_id: msg.order_id,
+            orders_ahead: msg.orders_ahead,
+            queued: msg.queued,
+            epoch: self.epoch,
+        };
+        self.send_message(status_msg, ctx);
+    }
+}
+
+/// Tells the screen which flavors have stock
+impl Handler<SendMenu> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: SendMenu, ctx: &mut Self::Context) -> Self::Result {
+        let menu = RobotMessage::Menu {
+            in_stock: msg.in_stock,
+            epoch: self.epoch,
+        };
+        self.send_message(menu, ctx);
+    }
+}
+
+/// Tells the screen the grams left of each flavor
+impl Handler<SendStockSnapshot> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: SendStockSnapshot, ctx: &mut Self::Context) -> Self::Result {
+        let snapshot = RobotMessage::StockSnapshot {
+            grams: msg.grams,
+            epoch: self.epoch,
+        };
+        self.send_message(snapshot, ctx);
+    }
+}
+
+/// Tells the screen that rejoined which screens had claimed its orders
+impl Handler<SendOrdersReclaimed> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: SendOrdersReclaimed, ctx: &mut Self::Context) -> Self::Result {
+        let reclaimed = RobotMessage::OrdersReclaimed {
+            taken_from: msg.taken_from,
+            finished: msg.finished,
+            epoch: self.epoch,
+        };
+        self.send_message(reclaimed, ctx);
+    }
+}
+
+/// Tells the screen to forget orders that another screen handles
+impl Handler<ReleaseOrders> for LeaderToScreenConnection {
+    type Result = ();
+    fn handle(&mut self, msg: ReleaseOrders, ctx: &mut Self::Context) -> Self::Result {
+        let order_ids = msg.order_ids;
+        let epoch = self.epoch;
+        self.send_message(RobotMessage::OrdersReleased { order_ids, epoch }, ctx);
+    }
+}
+
 impl Handler<OrderAborted> for LeaderToScreenConnection {
     type Result = ();
     fn handle(&mut self, msg: OrderAborted, ctx: &mut Self::Context) -> Self::Result {
-        let line = "[SC]: Recibi un mensaje de orden aborted".to_string();
-        println!("{}", line.bright_green());
+        let line = "Recibi un mensaje de orden aborted".to_string();
+        log::info("SC", line.bright_green());
 
         let result = msg.order_result;
         let order_id = msg.id;
         let flavor_id = msg.flavor;
-
-        let error_msg = format!(
-            "Order Aborted because of insuficient amount of: {}",
-            flavor_id
-        )
-        .to_string();
+        let reason = msg.reason;
 
         let order_msg = RobotMessage::OrderAborted {
             order_id: order_id.clone(),
-            error: error_msg,
+            error: reason.describe(&flavor_id),
+            reason,
+            flavor: Some(flavor_id.clone()),
+            epoch: self.epoch,
         }
-        .to_string();
+        .to_bytes();
 
-        let msg: String;
+        let msg: Vec<u8>;
         match order_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
                 if let Some(mut write_half) = self.write_half.take() {
                     let leader = self.leader.clone();
                     let screen_id = self.screen_id;
                     async move {
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!(
-                                "[SC] Error trying to send OrderResult to Screen. Stashing...: {}",
-                                e
+                        if let Err(e) =
+                            chaos::write_all(&mut write_half, &msg, "LTS", screen_id).await
+                        {
+                            log::warn(
+                                "SC",
+                                format!(
+                                    "Error trying to send OrderResult to Screen. Stashing...: {}",
+                                    e
+                                ),
                             );
                             if let Err(e) = leader.try_send(AddOrderToBeSent {
                                 id: order_id,
                                 order_result: result,
                                 screen_id,
                                 flavor: Some(flavor_id),
+                                reason,
+                                substitutions: vec![],
                             }) {
-                                print_send_error("[SC]", "ConnectToNewScreen", &e.to_string());
+                                log::send_error("SC", "ConnectToNewScreen", &e.to_string());
                             }
                         }
                         write_half
@@ -208,7 +536,7 @@ impl Handler<OrderAborted> for LeaderToScreenConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[SC]", "OrderCompleated", &e.to_string());
+                log::create_error("SC", "OrderCompleated", &e.to_string());
             }
         }
     }
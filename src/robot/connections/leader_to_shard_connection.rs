@@ -0,0 +1,160 @@
+use crate::common::chaos;
+use crate::common::log;
+use actix::prelude::*;
+use colored::*;
+use tokio::net::tcp::OwnedWriteHalf;
+
+use crate::protocol::robot_messages::*;
+use crate::protocol::screen_messages::*;
+use crate::robot::messages::*;
+use crate::robot::robot_leader::RobotLeader;
+use crate::robot::shard_routes::PartResult;
+
+/// Actor that represents the connection between the RobotLeader and the leader of a shard.
+/// The leader passes the parts of its orders to the other one as if it were a screen, with the link screen id of its shard,
+/// and this actor answers the pings and hands the results of the parts to the leader.
+/// When the connection is closed the leader opens it again and passes the parts that were not answered
+pub struct LeaderToShardConnection {
+    leader: Addr<RobotLeader>,
+    write_half: Option<OwnedWriteHalf>,
+    shard: usize,
+    screen_id: usize,
+}
+
+impl Actor for LeaderToShardConnection {
+    type Context = Context<Self>;
+}
+
+impl LeaderToShardConnection {
+    pub fn new(
+        shard: usize,
+        screen_id: usize,
+        leader: Addr<RobotLeader>,
+        write_half: Option<OwnedWriteHalf>,
+    ) -> Self {
+        Self {
+            leader,
+            write_half,
+            shard,
+            screen_id,
+        }
+    }
+
+    /// Writes a message to the leader of the shard, a part that is not written is passed again when the connection is opened
+    fn send_message(&mut self, msg: ScreenMessage, ctx: &mut Context<Self>) {
+        let msg = match msg.to_bytes() {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::create_error("LSH", "ScreenMessage", &e.to_string());
+                return;
+            }
+        };
+        let shard = self.shard;
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = chaos::write_all(&mut write_half, &msg, "LSH", shard).await {
+                    log::warn(
+                        "LSH",
+                        format!(
+                            "Error trying to send a message to the leader of shard {}: {}",
+                            shard, e
+                        ),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+
+    /// Hands the result of a part to the leader
+    fn part_finished(&self, part_id: String, result: PartResult) {
+        if let Err(e) = self.leader.try_send(PartFinished { part_id, result }) {
+            log::send_error("LSH", "PartFinished", &e.to_string());
+        }
+    }
+
+    fn handle_robot_message(&mut self, msg: RobotMessage, ctx: &mut Context<Self>) {
+        match msg {
+            RobotMessage::Ping { epoch } => self.send_message(ScreenMessage::Pong { epoch }, ctx),
+            RobotMessage::OrderPrepared {
+                order_id,
+                substitutions,
+                ..
+            } => self.part_finished(order_id, PartResult::Prepared(substitutions)),
+            RobotMessage::OrderAborted {
+                order_id,
+                reason,
+                flavor,
+                ..
+            } => self.part_finished(order_id, PartResult::Aborted(flavor, reason)),
+            RobotMessage::OrderRejectedBusy { order_id, .. } => {
+                self.part_finished(order_id, PartResult::Busy)
+            }
+            RobotMessage::OrderAbortedAfterConfirm {
+                order_id, error, ..
+            } => {
+                let line = format!(
+                    "Part {} was aborted by the leader of shard {} after it was prepared: {}",
+                    order_id, self.shard, error
+                );
+                log::warn("LSH", line.yellow());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Handler<Harakiri> for LeaderToShardConnection {
+    type Result = ();
+    fn handle(&mut self, _msg: Harakiri, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for LeaderToShardConnection {
+    fn handle(&mut self, data: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
+        match data {
+            Ok(frame) => match RobotMessage::from_bytes(&frame) {
+                Ok(msg) => self.handle_robot_message(msg, ctx),
+                Err(e) => log::error("LSH", format!("Error parsing message: {}", e)),
+            },
+            Err(e) => {
+                let line = format!("Error! The leader of shard {} died\n{}", self.shard, e);
+                log::error("LSH", line);
+            }
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        if let Err(e) = self.leader.try_send(ShardLinkClosed { shard: self.shard }) {
+            log::send_error("LSH", "ShardLinkClosed", &e.to_string());
+        }
+        ctx.stop();
+    }
+}
+
+/// Passes a part of an order to the leader of the shard, as a new order of the link screen
+impl Handler<PassPart> for LeaderToShardConnection {
+    type Result = ();
+    fn handle(&mut self, msg: PassPart, ctx: &mut Self::Context) -> Self::Result {
+        let part = msg.part;
+        let line = format!(
+            "Passing part {} to the leader of shard {}",
+            part.id, self.shard
+        );
+        log::info("LSH", line.bright_cyan());
+        let order = ScreenMessage::PrepareNewOrder {
+            screen_id: self.screen_id,
+            order_id: part.id,
+            order: part.order,
+            deadline_ms: part.deadline_ms,
+            source: None,
+        };
+        self.send_message(order, ctx);
+    }
+}
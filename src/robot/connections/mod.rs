@@ -2,5 +2,6 @@
 
 pub mod leader_to_robot_connection;
 pub mod leader_to_screen_connection;
+pub mod leader_to_shard_connection;
 pub mod robot_to_leader_connection;
 pub mod robot_to_robot_connection;
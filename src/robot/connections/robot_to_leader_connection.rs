@@ -1,3 +1,4 @@
+use crate::common::log;
 use actix::prelude::*;
 use colored::*;
 use tokio::io::AsyncWriteExt;
@@ -5,12 +6,11 @@ use tokio::net::tcp::OwnedWriteHalf;
 
 use crate::robot::messages::*;
 use crate::robot::robot_connection_handler::RobotConnectionHandler;
-use crate::robot::utils::{print_create_error, print_send_error};
 
 /// Actor that handles the connection between a robot and the leader.
 pub struct RobotToLeaderConnection {
     rch: Addr<RobotConnectionHandler>,
-    // my_id: usize,
+    leader_id: usize,
     write_half: Option<OwnedWriteHalf>,
 }
 
@@ -19,8 +19,16 @@ impl Actor for RobotToLeaderConnection {
 }
 
 impl RobotToLeaderConnection {
-    pub fn new(rch: Addr<RobotConnectionHandler>, write_half: Option<OwnedWriteHalf>) -> Self {
-        Self { rch, write_half }
+    pub fn new(
+        rch: Addr<RobotConnectionHandler>,
+        leader_id: usize,
+        write_half: Option<OwnedWriteHalf>,
+    ) -> Self {
+        Self {
+            rch,
+            leader_id,
+            write_half,
+        }
     }
 }
 
@@ -38,17 +46,21 @@ impl Handler<OrderPrepared> for RobotToLeaderConnection {
         let order_msg = RobotCommand::OrderComplete {
             result: result_msg.order_result,
             order_id: result_msg.id.clone(),
+            substitutions: result_msg.substitutions.clone(),
         }
-        .to_string();
-        let msg: String;
+        .to_bytes();
+        let msg: Vec<u8>;
         match order_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
                 if let Some(mut write_half) = self.write_half.take() {
                     async move {
                         let mut could_send = true;
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!("[RTLC] Error trying to send OrderPrepared to Leader: {}", e);
+                        if let Err(e) = write_half.write_all(&msg).await {
+                            log::error(
+                                "RTLC",
+                                format!("Error trying to send OrderPrepared to Leader: {}", e),
+                            );
                             could_send = false;
                         }
                         (could_send, write_half)
@@ -60,8 +72,9 @@ impl Handler<OrderPrepared> for RobotToLeaderConnection {
                             if let Err(e) = actor.rch.try_send(OrderPrepared {
                                 order_result: result_msg.order_result,
                                 id: result_msg.id,
+                                substitutions: result_msg.substitutions,
                             }) {
-                                print_send_error("[RTLC]", "OrderPrepared", &e.to_string());
+                                log::send_error("RTLC", "OrderPrepared", &e.to_string());
                             }
                         }
                     })
@@ -69,7 +82,7 @@ impl Handler<OrderPrepared> for RobotToLeaderConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[RTLC]", "OrderPrepared", &e.to_string());
+                log::create_error("RTLC", "OrderPrepared", &e.to_string());
             }
         }
     }
@@ -81,18 +94,22 @@ impl Handler<OrderAborted> for RobotToLeaderConnection {
         let order_msg = RobotCommand::OrderNotFinished {
             result: result_msg.order_result,
             order_id: result_msg.id.clone(),
-            flavor: result_msg.flavor,
+            flavor: result_msg.flavor.clone(),
+            reason: result_msg.reason,
         }
-        .to_string();
-        let msg: String;
+        .to_bytes();
+        let msg: Vec<u8>;
         match order_msg {
             Ok(r_msg) => {
-                msg = r_msg + "\n";
+                msg = r_msg;
                 if let Some(mut write_half) = self.write_half.take() {
                     async move {
                         let mut could_send = true;
-                        if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-                            println!("[RTLC] Error trying to send OrderAborted to Leader: {}", e);
+                        if let Err(e) = write_half.write_all(&msg).await {
+                            log::error(
+                                "RTLC",
+                                format!("Error trying to send OrderAborted to Leader: {}", e),
+                            );
                             could_send = false;
                         }
                         (could_send, write_half)
@@ -105,8 +122,9 @@ impl Handler<OrderAborted> for RobotToLeaderConnection {
                                 order_result: result_msg.order_result,
                                 id: result_msg.id,
                                 flavor: result_msg.flavor,
+                                reason: result_msg.reason,
                             }) {
-                                print_send_error("[RTLC]", "OrderAborted", &e.to_string());
+                                log::send_error("RTLC", "OrderAborted", &e.to_string());
                             }
                         }
                     })
@@ -114,53 +132,267 @@ impl Handler<OrderAborted> for RobotToLeaderConnection {
                 }
             }
             Err(e) => {
-                print_create_error("[RTLC]", "OrderAborted", &e.to_string());
+                log::create_error("RTLC", "OrderAborted", &e.to_string());
+            }
+        }
+    }
+}
+
+impl Handler<RobotLeaving> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: RobotLeaving, ctx: &mut Self::Context) -> Self::Result {
+        let leave_msg = match (RobotCommand::LeaveRing {
+            robot_id: msg.robot_id,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "LeaveRing", &e.to_string());
+                return;
+            }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&leave_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send LeaveRing to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the leader that the robot is defrosting
+impl Handler<RobotUnavailable> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: RobotUnavailable, ctx: &mut Self::Context) -> Self::Result {
+        let unavailable_msg = match (RobotCommand::RobotUnavailable {
+            robot_id: msg.robot_id,
+            until: msg.until,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "RobotUnavailable", &e.to_string());
+                return;
+            }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&unavailable_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send RobotUnavailable to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the leader how fast the robot scoops
+impl Handler<ScoopSpeed> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: ScoopSpeed, ctx: &mut Self::Context) -> Self::Result {
+        let speed_msg = match (RobotCommand::ScoopSpeed {
+            robot_id: msg.robot_id,
+            ms_per_gram: msg.ms_per_gram,
+            compressions: msg.compressions,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "ScoopSpeed", &e.to_string());
+                return;
+            }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&speed_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send ScoopSpeed to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Asks the leader for its last backup whole
+impl Handler<AskForFullBackup> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: AskForFullBackup, ctx: &mut Self::Context) -> Self::Result {
+        let ask_msg = match (RobotCommand::FullBackupNeeded {
+            robot_id: msg.robot_id,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "FullBackupNeeded", &e.to_string());
+                return;
+            }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&ask_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send FullBackupNeeded to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the leader that its epoch is stale, the connection is kept until the leader closes it when it steps down
+impl Handler<RejectStaleLeader> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: RejectStaleLeader, ctx: &mut Self::Context) -> Self::Result {
+        let stale_msg = match (RobotCommand::StaleLeader { epoch: msg.epoch }).to_bytes() {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "StaleLeader", &e.to_string());
+                return;
+            }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&stale_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send StaleLeader to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
+        }
+    }
+}
+
+/// Tells the leader the orders the robot was preparing
+impl Handler<OrdersInFlight> for RobotToLeaderConnection {
+    type Result = ();
+    fn handle(&mut self, msg: OrdersInFlight, ctx: &mut Self::Context) -> Self::Result {
+        let in_flight_msg = match (RobotCommand::OrdersInFlight {
+            robot_id: msg.robot_id,
+            orders: msg.orders,
+        })
+        .to_bytes()
+        {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RTLC", "OrdersInFlight", &e.to_string());
+                return;
             }
+        };
+        if let Some(mut write_half) = self.write_half.take() {
+            async move {
+                if let Err(e) = write_half.write_all(&in_flight_msg).await {
+                    log::error(
+                        "RTLC",
+                        format!("Error trying to send OrdersInFlight to Leader: {}", e),
+                    );
+                }
+                write_half
+            }
+            .into_actor(self)
+            .map(|w_half, actor, _| {
+                actor.write_half = Some(w_half);
+            })
+            .wait(ctx);
         }
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for RobotToLeaderConnection {
-    fn handle(&mut self, data: Result<String, std::io::Error>, _ctx: &mut Self::Context) {
+/// The commands of the leader go to the RCH with the epoch of the leader, which checks it before running them
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for RobotToLeaderConnection {
+    fn handle(&mut self, data: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
         match data {
             Ok(t) => {
-                match RobotCommand::from_string(&t).map_err(|err| err.to_string()) {
-                    Ok(msg) => match msg {
-                        RobotCommand::NewOrder { order, order_id } => {
-                            if let Err(e) = self.rch.try_send(GetNewOrder {
-                                new_order: order,
-                                id: order_id,
-                            }) {
-                                print_send_error("[RTLC]", "GetNewOrder", &e.to_string());
+                match RobotCommand::from_bytes(&t).map_err(|err| err.to_string()) {
+                    Ok(msg) => {
+                        match msg {
+                            RobotCommand::NewOrder { epoch, .. }
+                            | RobotCommand::ReceiveLeaderBackup { epoch, .. }
+                            | RobotCommand::CancelOrder { epoch, .. }
+                            | RobotCommand::TakeLeadership { epoch, .. }
+                            | RobotCommand::LeaderTransferred { epoch, .. }
+                            | RobotCommand::ResumeOrders { epoch, .. }
+                            | RobotCommand::StartTokens { epoch, .. } => {
+                                if let Err(e) = self.rch.try_send(LeaderCommand {
+                                    epoch,
+                                    command: msg,
+                                    connection: ctx.address(),
+                                }) {
+                                    log::send_error("RTLC", "LeaderCommand", &e.to_string());
+                                }
                             }
-                        }
-                        RobotCommand::ReceiveLeaderBackup { backup } => {
-                            // let line = format!("[RTLC]: New message from Leader: ReceiveLeaderBackup");
-                            // println!("{}", line.bright_green());
-                            if let Err(e) = self.rch.try_send(StoreBackup { backup }) {
-                                print_send_error("[RTLC]", "StoreBackup", &e.to_string());
+                            RobotCommand::RobotIdInUse { robot_id } => {
+                                if let Err(e) = self.rch.try_send(RobotIdInUse { robot_id }) {
+                                    log::send_error("RTLC", "RobotIdInUse", &e.to_string());
+                                }
+                            }
+                            other => {
+                                log::error("RTLC", format!("Error! Did not understand StreamHandler message. I got: {:?}", other));
                             }
                         }
-                        _ => {
-                            println!("[RTLC]: Error! Did not understand StreamHandler message. I got: {}", t);
-                        }
-                    },
+                    }
                     Err(e) => {
-                        println!("[RTLC]: Error parsing message: {}", e);
+                        log::error("RTLC", format!("Error parsing message: {}", e));
                     }
                 }
             }
             Err(e) => {
-                let line = format!("[RTLC]: Leader failed!\n{:?}", e);
-                println!("{}", line.bright_red());
+                let line = format!("Leader failed!\n{:?}", e);
+                log::error("RTLC", line.bright_red());
             }
         }
     }
 
+    /// The RCH tries to connect to the leader again before starting an election
     fn finished(&mut self, _ctx: &mut Self::Context) {
-        println!("[RTLC] Leader failed!");
-        if let Err(e) = self.rch.try_send(StartElection()) {
-            print_send_error("[RTLC]", "StartElection", &e.to_string());
+        log::error("RTLC", "Lost the connection with the leader!");
+        if let Err(e) = self.rch.try_send(ConnectionLost {
+            peer: Peer::Leader(self.leader_id),
+        }) {
+            log::send_error("RTLC", "ConnectionLost", &e.to_string());
         }
     }
 }
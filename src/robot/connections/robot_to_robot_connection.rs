@@ -1,25 +1,69 @@
+use crate::common::log;
 use actix::prelude::*;
 use colored::*;
+use std::time::Duration;
 use tokio::net::tcp::OwnedWriteHalf;
+use tokio::time::Instant;
+
+use crate::config;
 
 use crate::robot::messages::*;
 use crate::robot::robot_connection_handler::RobotConnectionHandler;
-use crate::robot::utils::print_send_error;
 
 /// Actor that handles the connection between two robots.
+/// The control lane of the previous robot is read by another one, which is not dropped when it is silent,
+/// since the heartbeats go through the ring connection
 #[allow(dead_code)]
 pub struct RobotToRobotConnection {
     rch: Addr<RobotConnectionHandler>,
     write_half: Option<OwnedWriteHalf>,
+    last_heard: Instant,
+    control_lane: bool,
 }
 
 impl Actor for RobotToRobotConnection {
     type Context = Context<Self>;
+
+    /// Checks periodically that the previous robot is still sending messages,
+    /// if it is silent for longer than the heartbeat timeout the connection is dropped
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.control_lane {
+            return;
+        }
+        let heartbeat = &config::get().heartbeat;
+        let timeout = Duration::from_millis(heartbeat.timeout_ms);
+        ctx.run_interval(
+            Duration::from_millis(heartbeat.interval_ms),
+            move |actor, ctx| {
+                if actor.last_heard.elapsed() > timeout {
+                    let line = format!(
+                        "The previous robot was silent for {} ms, dropping the connection",
+                        timeout.as_millis()
+                    );
+                    log::warn("RTR", line.yellow());
+                    ctx.stop();
+                }
+            },
+        );
+    }
 }
 
 impl RobotToRobotConnection {
     pub fn new(rch: Addr<RobotConnectionHandler>, write_half: Option<OwnedWriteHalf>) -> Self {
-        Self { rch, write_half }
+        Self {
+            rch,
+            write_half,
+            last_heard: Instant::now(),
+            control_lane: false,
+        }
+    }
+
+    /// Creates the connection that reads the control lane of the previous robot
+    pub fn control_lane(rch: Addr<RobotConnectionHandler>, write_half: OwnedWriteHalf) -> Self {
+        Self {
+            control_lane: true,
+            ..Self::new(rch, Some(write_half))
+        }
     }
 }
 
@@ -31,53 +75,81 @@ impl Handler<Harakiri> for RobotToRobotConnection {
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for RobotToRobotConnection {
-    fn handle(&mut self, data: Result<String, std::io::Error>, _ctx: &mut Self::Context) {
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for RobotToRobotConnection {
+    fn handle(&mut self, data: Result<Vec<u8>, std::io::Error>, _ctx: &mut Self::Context) {
+        if data.is_ok() {
+            self.last_heard = Instant::now();
+        }
         match data {
-            Ok(t) => match RobotCommand::from_string(&t).map_err(|err| err.to_string()) {
+            Ok(t) => match RobotCommand::from_bytes(&t).map_err(|err| err.to_string()) {
                 Ok(msg) => match msg {
+                    RobotCommand::Heartbeat { .. } => {}
                     RobotCommand::TokenMessage { token } => {
                         if let Err(e) = self.rch.try_send(TransferToken {
-                            flavor_token: token,
+                            flavor_token: *token,
                         }) {
-                            print_send_error("[RTR]", "TransferToken", &e.to_string());
+                            log::send_error("RTR", "TransferToken", &e.to_string());
                         }
                     }
                     RobotCommand::TokenBackupMsg { token_backup } => {
                         if let Err(e) = self.rch.try_send(GetTokenBackup { token_backup }) {
-                            print_send_error("[RTR]", "TokenBackupMsg", &e.to_string());
+                            log::send_error("RTR", "TokenBackupMsg", &e.to_string());
+                        }
+                    }
+                    RobotCommand::RestockFlavor { flavor, amount } => {
+                        if let Err(e) = self.rch.try_send(RestockFlavor { flavor, amount }) {
+                            log::send_error("RTR", "RestockFlavor", &e.to_string());
                         }
                     }
-                    RobotCommand::NewLeader { leader } => {
+                    RobotCommand::NewLeader { leader, epoch } => {
                         // let line = format!("[RTR] Recibi un mensaje de nuevo lider {}", leader);
                         // println!("{}", line.bright_green());
-                        if let Err(e) = self.rch.try_send(NewLeaderElected { leader_id: leader }) {
-                            print_send_error("[RTR]", "ReceiveNewLeader", &e.to_string());
+                        if let Err(e) = self.rch.try_send(NewLeaderElected {
+                            leader_id: leader,
+                            epoch,
+                        }) {
+                            log::send_error("RTR", "ReceiveNewLeader", &e.to_string());
                         }
                     }
-                    RobotCommand::NewElection { candidates } => {
+                    RobotCommand::NewElection {
+                        election,
+                        hops,
+                        candidates,
+                    } => {
                         // let line = format!("[RTR] Recibi un mensaje de eleccion {:?}", candidates);
                         // println!("{}", line.bright_green());
-                        if let Err(e) = self.rch.try_send(ReceiveNewElection { candidates }) {
-                            print_send_error("[RTR]", "ReceiveNewElection", &e.to_string());
+                        if let Err(e) = self.rch.try_send(ReceiveNewElection {
+                            election,
+                            hops,
+                            candidates,
+                        }) {
+                            log::send_error("RTR", "ReceiveNewElection", &e.to_string());
+                        }
+                    }
+                    RobotCommand::LeaveRing { robot_id } => {
+                        if let Err(e) = self.rch.try_send(RobotLeaving { robot_id }) {
+                            log::send_error("RTR", "RobotLeaving", &e.to_string());
                         }
                     }
-                    _ => {
-                        println!(
-                            "[RTR]: Error! Did not understand StreamHandler message. I got: {}",
-                            t
+                    other => {
+                        log::error(
+                            "RTR",
+                            format!(
+                                "Error! Did not understand StreamHandler message. I got: {:?}",
+                                other
+                            ),
                         );
                     }
                 },
                 Err(e) => {
-                    let line = format!("[RTR]: Error parsing message\n{}", e);
-                    println!("{}", line.red());
+                    let line = format!("Error parsing message\n{}", e);
+                    log::error("RTR", line.red());
                 }
             },
             Err(e) => {
                 // println!("Error receiving message: {}", e);
-                let line = format!("[RTR]: Error! The other robot died!\n{}", e);
-                println!("{}", line.red());
+                let line = format!("Error! The other robot died!\n{}", e);
+                log::error("RTR", line.red());
             }
         }
     }
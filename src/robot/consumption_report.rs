@@ -0,0 +1,224 @@
+//! Report of how much of each flavor the orders consumed, written by the leader when the admin asks for it
+//! or when its robot is shut down.
+//! The grams are counted from the orders the leader saw finish, and the stock left is the one of the last
+//! tokens that went through the robot of the leader.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use crate::common::order::Substitution;
+use crate::common::persistence_writer;
+use crate::config::report::ReportFormat;
+use crate::protocol::order_info::OrderInfo;
+use crate::robot::maintenance::now_ms;
+
+/// What was consumed of a flavor
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FlavorConsumption {
+    pub flavor: String,
+    pub grams_consumed: usize,
+    pub orders_served: u64,
+    pub orders_aborted: u64,
+    /// Grams left as last seen in the tokens, if a token of the flavor went through the robot of the leader
+    pub remaining_grams: Option<usize>,
+}
+
+/// The robot that completed the most orders
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BusiestRobot {
+    pub robot_id: usize,
+    pub orders_served: u64,
+}
+
+/// Consumption of every flavor since the leader took over
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConsumptionReport {
+    pub leader_id: usize,
+    pub generated_at_ms: u64,
+    pub flavors: Vec<FlavorConsumption>,
+    pub busiest_robot: Option<BusiestRobot>,
+}
+
+impl ConsumptionReport {
+    /// Writes the report as CSV, a line per flavor and a last one with the busiest robot
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "flavor,grams_consumed,orders_served,orders_aborted,remaining_grams\n".to_string();
+        for f in &self.flavors {
+            let remaining = f.remaining_grams.map(|g| g.to_string()).unwrap_or_default();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{}",
+                f.flavor, f.grams_consumed, f.orders_served, f.orders_aborted, remaining
+            );
+        }
+        if let Some(robot) = &self.busiest_robot {
+            let _ = writeln!(
+                csv,
+                "# busiest robot: {} with {} orders served",
+                robot.robot_id, robot.orders_served
+            );
+        }
+        csv
+    }
+
+    /// Writes the report to the file in the given format, replacing what it had
+    pub fn write(&self, path: &Path, format: ReportFormat) -> io::Result<()> {
+        let content = match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+            ReportFormat::Csv => self.to_csv(),
+        };
+        persistence_writer::write_atomically(path, &content, false)
+    }
+}
+
+/// Counters of the consumption kept by the leader.
+/// The grams served since this robot became the leader, the report of a new leader only covers its own term
+#[derive(Default)]
+pub struct ConsumptionBook {
+    flavors: BTreeMap<String, FlavorConsumption>,
+    robots: BTreeMap<usize, u64>,
+}
+
+impl ConsumptionBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn flavor(&mut self, name: &str) -> &mut FlavorConsumption {
+        self.flavors
+            .entry(name.to_string())
+            .or_insert_with(|| FlavorConsumption {
+                flavor: name.to_string(),
+                ..FlavorConsumption::default()
+            })
+    }
+
+    /// Counts a finished order of the robot.
+    /// The grams of a completed order are counted for the flavor that was served, the substitute if there was one
+    pub fn finished(
+        &mut self,
+        robot_id: usize,
+        order: &OrderInfo,
+        completed: bool,
+        substitutions: &[Substitution],
+    ) {
+        if completed {
+            *self.robots.entry(robot_id).or_insert(0) += 1;
+        }
+        for (flavor, grams) in order.order.get_flavors() {
+            let served = substitutions
+                .iter()
+                .find(|s| s.flavor == flavor)
+                .map(|s| &s.substitute)
+                .unwrap_or(&flavor);
+            if !completed {
+                self.flavor(served.name()).orders_aborted += 1;
+                continue;
+            }
+            let consumption = self.flavor(served.name());
+            consumption.grams_consumed += grams;
+            consumption.orders_served += 1;
+        }
+    }
+
+    /// Builds the report with the stock left of each flavor, every flavor of the stock is listed even if
+    /// no order asked for it
+    pub fn report(&self, leader_id: usize, stock: &BTreeMap<String, usize>) -> ConsumptionReport {
+        let mut flavors = self.flavors.clone();
+        for (name, amount) in stock {
+            flavors
+                .entry(name.clone())
+                .or_insert_with(|| FlavorConsumption {
+                    flavor: name.clone(),
+                    ..FlavorConsumption::default()
+                })
+                .remaining_grams = Some(*amount);
+        }
+        let busiest_robot = self
+            .robots
+            .iter()
+            .max_by_key(|(robot_id, served)| (**served, std::cmp::Reverse(**robot_id)))
+            .map(|(robot_id, served)| BusiestRobot {
+                robot_id: *robot_id,
+                orders_served: *served,
+            });
+        ConsumptionReport {
+            leader_id,
+            generated_at_ms: now_ms(),
+            flavors: flavors.into_values().collect(),
+            busiest_robot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+
+    fn order(id: &str, flavors: Vec<FlavorID>) -> OrderInfo {
+        OrderInfo {
+            order: Order::new_medio(flavors).unwrap(),
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_grams_are_counted_for_the_flavor_served() {
+        let mut book = ConsumptionBook::new();
+        let substitution = Substitution {
+            flavor: FlavorID::Mint,
+            substitute: FlavorID::Lemon,
+        };
+        book.finished(1, &order("1", vec![FlavorID::Mint]), true, &[substitution]);
+        book.finished(2, &order("2", vec![FlavorID::Mint]), true, &[]);
+        book.finished(2, &order("3", vec![FlavorID::Mint]), false, &[]);
+
+        let stock = BTreeMap::from([("Mint".to_string(), 300), ("Vanilla".to_string(), 900)]);
+        let report = book.report(0, &stock);
+        let names: Vec<&str> = report.flavors.iter().map(|f| f.flavor.as_str()).collect();
+        assert_eq!(names, vec!["Lemon", "Mint", "Vanilla"]);
+        assert_eq!(
+            (
+                report.flavors[0].grams_consumed,
+                report.flavors[0].remaining_grams
+            ),
+            (500, None)
+        );
+        assert_eq!(
+            (
+                report.flavors[1].orders_served,
+                report.flavors[1].orders_aborted,
+                report.flavors[1].remaining_grams
+            ),
+            (1, 1, Some(300))
+        );
+        assert_eq!(report.flavors[2].orders_served, 0);
+        assert_eq!(
+            report.busiest_robot,
+            Some(BusiestRobot {
+                robot_id: 1,
+                orders_served: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_csv_has_a_line_per_flavor() {
+        let mut book = ConsumptionBook::new();
+        book.finished(3, &order("1", vec![FlavorID::Mint]), true, &[]);
+        let csv = book.report(0, &BTreeMap::new()).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[1], "Mint,500,1,0,");
+        assert!(lines[2].contains("busiest robot: 3"));
+    }
+}
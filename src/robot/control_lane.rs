@@ -0,0 +1,162 @@
+//! Lane for the control messages of the ring, a second connection with the next robot that only carries
+//! the elections, the new leaders and the robots leaving the ring.
+//! The ring connection is written by the RobotConnectionHandler one message at a time, so a control message sent while
+//! a token or a backup is being written waits for it. The lane has its own task writing its frames, so they are never
+//! queued behind those payloads.
+
+use crate::common::log;
+use actix::prelude::*;
+use colored::*;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::common::chaos;
+use crate::common::error::FreddoError;
+use crate::common::utils::connect;
+use crate::robot::messages::*;
+use crate::robot::utils::{id_to_robot_addr, write_handshake};
+
+/// Sending side of the lane to the next robot
+#[derive(Debug)]
+pub struct ControlLane {
+    next_id: usize,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl ControlLane {
+    /// Connects to the robot and starts the task that writes the frames given to the lane.
+    /// If a frame can not be written, it and the ones after it are handed back in a ControlLaneClosed,
+    /// so they are sent through the ring connection instead
+    pub async fn open(
+        my_id: usize,
+        next_id: usize,
+        closed: Recipient<ControlLaneClosed>,
+    ) -> Result<Self, FreddoError> {
+        let stream = connect(&id_to_robot_addr(next_id)).await?;
+        Self::start(stream, my_id, next_id, closed).await
+    }
+
+    /// Sends the handshake of the lane through the stream and starts the task that writes its frames
+    async fn start(
+        mut stream: TcpStream,
+        my_id: usize,
+        next_id: usize,
+        closed: Recipient<ControlLaneClosed>,
+    ) -> Result<Self, FreddoError> {
+        write_handshake(
+            &mut stream,
+            RobotCommand::NewControlLane { robot_id: my_id },
+        )
+        .await?;
+        let (read_half, mut write_half) = stream.into_split();
+        let (frames, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        tokio::spawn(async move {
+            let mut unsent = Vec::new();
+            while let Some(frame) = receiver.recv().await {
+                let mut buff: [u8; 1] = [0; 1];
+                let gone = matches!(read_half.try_read(buff.as_mut()), Ok(0));
+                if gone
+                    || chaos::write_all(&mut write_half, &frame, "CTL", my_id)
+                        .await
+                        .is_err()
+                {
+                    unsent.push(frame);
+                    break;
+                }
+            }
+            receiver.close();
+            while let Ok(frame) = receiver.try_recv() {
+                unsent.push(frame);
+            }
+            let _ = write_half.shutdown().await;
+            if unsent.is_empty() {
+                return;
+            }
+            let line = format!(
+                "The control lane to Robot {} closed, {} messages go through the ring",
+                next_id,
+                unsent.len()
+            );
+            log::warn("CTL", line.yellow());
+            if let Err(e) = closed.try_send(ControlLaneClosed { next_id, unsent }) {
+                log::send_error("CTL", "ControlLaneClosed", &e.to_string());
+            }
+        });
+
+        let line = format!("Opened the control lane to Robot {}", next_id);
+        log::info("CTL", line.bright_cyan());
+        Ok(Self { next_id, frames })
+    }
+
+    /// Returns the robot the lane is connected to
+    pub fn next_id(&self) -> usize {
+        self.next_id
+    }
+
+    /// Gives the frame to the task of the lane, it is given back if the lane is already closed
+    pub fn send(&self, frame: Vec<u8>) -> Result<(), Vec<u8>> {
+        self.frames.send(frame).map_err(|e| e.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::codec;
+    use crate::protocol;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::UnboundedSender;
+
+    struct Closed(UnboundedSender<ControlLaneClosed>);
+
+    impl Actor for Closed {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<ControlLaneClosed> for Closed {
+        type Result = ();
+        fn handle(&mut self, msg: ControlLaneClosed, _ctx: &mut Self::Context) {
+            let _ = self.0.send(msg);
+        }
+    }
+
+    fn frame(robot_id: usize) -> Vec<u8> {
+        RobotCommand::LeaveRing { robot_id }.to_bytes().unwrap()
+    }
+
+    #[actix::test]
+    async fn test_frames_go_back_to_the_ring_when_the_lane_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (sender, mut closed) = mpsc::unbounded_channel();
+        let recipient = Closed(sender).start().recipient();
+        let lane = ControlLane::start(stream, 0, 7, recipient).await.unwrap();
+        let (mut next, _) = listener.accept().await.unwrap();
+
+        assert!(lane.send(frame(3)).is_ok());
+        protocol::read_version(&mut next).await.unwrap();
+        let handshake = codec::read_frame(&mut next).await.unwrap();
+        assert_eq!(
+            RobotCommand::from_bytes(&handshake).unwrap(),
+            RobotCommand::NewControlLane { robot_id: 0 }
+        );
+        let sent = codec::read_frame(&mut next).await.unwrap();
+        assert_eq!(
+            RobotCommand::from_bytes(&sent).unwrap(),
+            RobotCommand::LeaveRing { robot_id: 3 }
+        );
+
+        drop(next);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(lane.send(frame(4)).is_ok());
+        let closed = closed.recv().await.unwrap();
+        assert_eq!(closed.next_id, 7);
+        assert_eq!(closed.unsent, vec![frame(4)]);
+        assert_eq!(lane.send(frame(5)), Err(frame(5)));
+    }
+}
@@ -0,0 +1,186 @@
+//! Results the leader gave up sending to their screens, kept until an operator replays them.
+//! With persistence enabled they are also appended to a file of the data directory that every leader shares,
+//! so the ones buried by a leader can be replayed by the next one.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::common::log;
+use crate::common::persistence_writer;
+use crate::config;
+use crate::protocol::order_waiting::OrderWaiting;
+use crate::robot::maintenance::now_ms;
+
+const DEAD_LETTERS_FILE: &str = "dead_letters.jsonl";
+
+/// A result that could not be sent, with when it was given up
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub buried_at_ms: u64,
+    pub result: OrderWaiting,
+}
+
+/// Returns the path of the dead letters inside the data directory
+pub fn dead_letters_path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(DEAD_LETTERS_FILE)
+}
+
+/// Reads the dead letters of the file, a line that can not be parsed is skipped
+fn read(path: &Path) -> Vec<DeadLetter> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            log::error("DLQ", format!("Could not read the dead letters: {}", e));
+            return Vec::new();
+        }
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(letter) => Some(letter),
+            Err(e) => {
+                log::warn("DLQ", format!("Skipping dead letter line: {}", e));
+                None
+            }
+        })
+        .collect()
+}
+
+/// The dead letters known by the leader, and the file where they are kept if there is one
+#[derive(Debug, Default)]
+pub struct DeadLetterBox {
+    letters: Vec<DeadLetter>,
+    path: Option<PathBuf>,
+}
+
+impl DeadLetterBox {
+    /// Keeps the dead letters only in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the dead letters in the file too, starting with the ones it already has
+    pub fn with_file(path: PathBuf) -> Self {
+        Self {
+            letters: read(&path),
+            path: Some(path),
+        }
+    }
+
+    /// Uses the file of the data directory if persistence is enabled in the config
+    pub fn from_config() -> Self {
+        let persistence = &config::get().persistence;
+        match persistence.enabled {
+            true => Self::with_file(dead_letters_path(&persistence.data_dir)),
+            false => Self::new(),
+        }
+    }
+
+    /// Returns the dead letters, from the oldest one
+    pub fn letters(&self) -> &[DeadLetter] {
+        &self.letters
+    }
+
+    /// Gives up sending the result
+    pub fn bury(&mut self, result: OrderWaiting) {
+        let letter = DeadLetter {
+            buried_at_ms: now_ms(),
+            result,
+        };
+        if let Some(path) = &self.path {
+            match serde_json::to_string(&letter) {
+                Ok(line) => persistence_writer::append(path.clone(), line),
+                Err(e) => log::error("DLQ", format!("Error! {}", e)),
+            }
+        }
+        self.letters.push(letter);
+    }
+
+    /// Takes the dead letter of the order, or every one if no order is given, to send them again.
+    /// They are taken out of the file, so they are not replayed twice
+    pub fn take(&mut self, order_id: Option<&str>) -> Vec<OrderWaiting> {
+        let (taken, kept): (Vec<DeadLetter>, Vec<DeadLetter>) = std::mem::take(&mut self.letters)
+            .into_iter()
+            .partition(|letter| order_id.is_none_or(|id| letter.result.id == id));
+        self.letters = kept;
+        if taken.is_empty() {
+            return Vec::new();
+        }
+        if let Some(path) = &self.path {
+            let mut content = String::new();
+            for letter in &self.letters {
+                if let Ok(line) = serde_json::to_string(letter) {
+                    content.push_str(&line);
+                    content.push('\n');
+                }
+            }
+            let fsync = config::get().persistence.fsync;
+            if let Err(e) = persistence_writer::write_atomically(path, &content, fsync) {
+                log::error("DLQ", format!("Could not write the dead letters: {}", e));
+            }
+        }
+        taken.into_iter().map(|letter| letter.result).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::robot_messages::AbortReason;
+
+    fn result(id: &str) -> OrderWaiting {
+        OrderWaiting {
+            order_result: true,
+            id: id.to_string(),
+            screen_id: 1,
+            flavor: None,
+            reason: AbortReason::default(),
+            substitutions: vec![],
+            attempts: 5,
+            stashed_at_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_dead_letters_are_kept_in_the_file_until_they_are_replayed() {
+        let dir = std::env::temp_dir().join(format!("freddo_dead_letters_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DEAD_LETTERS_FILE);
+        let line = serde_json::to_string(&DeadLetter {
+            buried_at_ms: 1,
+            result: result("a"),
+        })
+        .unwrap();
+        persistence_writer::append_lines(
+            &path,
+            &[line.clone(), line.replace("\"a\"", "\"b\"")],
+            false,
+        )
+        .unwrap();
+
+        let mut letters = DeadLetterBox::with_file(path.clone());
+        assert_eq!(letters.letters().len(), 2);
+        assert_eq!(letters.take(Some("b")), vec![result("b")]);
+        assert!(letters.take(Some("b")).is_empty());
+
+        let letters = DeadLetterBox::with_file(path);
+        assert_eq!(letters.letters().len(), 1);
+        assert_eq!(letters.letters()[0].result.id, "a");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_every_dead_letter_is_replayed_without_an_order() {
+        let mut letters = DeadLetterBox::new();
+        letters.bury(result("a"));
+        letters.bury(result("b"));
+        let ids: Vec<String> = letters.take(None).into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(letters.letters().is_empty());
+    }
+}
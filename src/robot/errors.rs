@@ -1,23 +1,99 @@
 use std::fmt::{self};
 
-/// Error type for robot connection
+use crate::robot::ring_state::{RingEvent, RingState};
+
+/// Error type for the leader backup snapshots stored on disk
+#[derive(Debug)]
+pub enum BackupStoreError {
+    CouldNotWrite(String),
+    CouldNotRead(String),
+    ErrorParsing(String),
+}
+
+impl fmt::Display for BackupStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupStoreError::CouldNotWrite(err) => {
+                write!(f, "Could not write backup snapshot: {}", err)
+            }
+            BackupStoreError::CouldNotRead(err) => {
+                write!(f, "Could not read backup snapshot: {}", err)
+            }
+            BackupStoreError::ErrorParsing(err) => {
+                write!(f, "Could not parse backup snapshot: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BackupStoreError {}
+
+/// Error type for the order journal stored on disk
+#[derive(Debug)]
+pub enum OrderJournalError {
+    CouldNotWrite(String),
+    CouldNotRead(String),
+    ErrorParsing(String),
+}
+
+impl fmt::Display for OrderJournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderJournalError::CouldNotWrite(err) => {
+                write!(f, "Could not write order journal: {}", err)
+            }
+            OrderJournalError::CouldNotRead(err) => {
+                write!(f, "Could not read order journal: {}", err)
+            }
+            OrderJournalError::ErrorParsing(err) => {
+                write!(f, "Could not parse order journal entry: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderJournalError {}
+
+/// Error type for the orders in flight of a robot stored on disk
 #[derive(Debug)]
-pub enum RobotConnectionError {
-    ColudNotConnectToRobot(String),
-    NoRobotsAvailableError(),
+pub enum InFlightError {
+    CouldNotWrite(String),
+    CouldNotRead(String),
+    ErrorParsing(String),
 }
 
-impl fmt::Display for RobotConnectionError {
+impl fmt::Display for InFlightError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            RobotConnectionError::ColudNotConnectToRobot(err) => {
-                write!(f, "Could not connect to robot: {}", err)
+            InFlightError::CouldNotWrite(err) => {
+                write!(f, "Could not write the orders in flight: {}", err)
+            }
+            InFlightError::CouldNotRead(err) => {
+                write!(f, "Could not read the orders in flight: {}", err)
+            }
+            InFlightError::ErrorParsing(err) => {
+                write!(f, "Could not parse the orders in flight: {}", err)
             }
-            RobotConnectionError::NoRobotsAvailableError() => {
-                write!(f, "No robots available to connect")
+        }
+    }
+}
+
+impl std::error::Error for InFlightError {}
+
+/// Error type for the changes of the place of a robot in the ring
+#[derive(Debug, PartialEq, Eq)]
+pub enum RingStateError {
+    InvalidTransition { from: RingState, event: RingEvent },
+}
+
+impl fmt::Display for RingStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RingStateError::InvalidTransition { from, event } => {
+                write!(f, "{:?} is not valid while {}", event, from)
             }
         }
     }
 }
 
-impl std::error::Error for RobotConnectionError {}
+impl std::error::Error for RingStateError {}
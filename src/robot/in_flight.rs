@@ -0,0 +1,104 @@
+//! The orders a robot is preparing, kept on disk so a robot that restarts can resume them.
+//! The file is replaced every time the orders change, and only if persistence is enabled in the config.
+//! When the robot connects to a leader it tells it the orders it has, and the leader answers which ones it can resume.
+
+use crate::common::log;
+use crate::common::persistence_writer;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::robot::errors::InFlightError;
+use crate::robot::order_in_progress::OrderProgress;
+
+/// Returns the path of the file with the orders in flight of the robot with the given id
+pub fn in_flight_path(data_dir: &str, robot_id: usize) -> PathBuf {
+    Path::new(data_dir).join(format!("in_flight_{}.json", robot_id))
+}
+
+/// Writes the orders to the file of the robot, first to a temporary file that is then renamed
+pub fn save_in_flight(
+    data_dir: &str,
+    robot_id: usize,
+    orders: &[OrderProgress],
+) -> Result<(), InFlightError> {
+    let json =
+        serde_json::to_string(orders).map_err(|e| InFlightError::ErrorParsing(e.to_string()))?;
+    let fsync = config::get().persistence.fsync;
+    persistence_writer::write_atomically(&in_flight_path(data_dir, robot_id), &json, fsync)
+        .map_err(|e| InFlightError::CouldNotWrite(e.to_string()))
+}
+
+/// Reads the file of the robot, it has no orders if it never stored one
+pub fn load_in_flight(
+    data_dir: &str,
+    robot_id: usize,
+) -> Result<Vec<OrderProgress>, InFlightError> {
+    let json = match fs::read_to_string(in_flight_path(data_dir, robot_id)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(InFlightError::CouldNotRead(e.to_string())),
+    };
+    serde_json::from_str(&json).map_err(|e| InFlightError::ErrorParsing(e.to_string()))
+}
+
+/// Stores the orders on disk if persistence is enabled in the config, through the persistence writer of the process
+pub fn persist_in_flight(robot_id: usize, orders: &[OrderProgress]) {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return;
+    }
+    match serde_json::to_string(orders) {
+        Ok(json) => {
+            persistence_writer::snapshot(in_flight_path(&persistence.data_dir, robot_id), json)
+        }
+        Err(e) => log::error("IF", format!("Error! {}", e)),
+    }
+}
+
+/// Loads the orders stored on disk if persistence is enabled in the config
+pub fn restore_in_flight(robot_id: usize) -> Vec<OrderProgress> {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return vec![];
+    }
+    match load_in_flight(&persistence.data_dir, robot_id) {
+        Ok(orders) => orders,
+        Err(e) => {
+            log::error("IF", format!("Error! {}", e));
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Substitution;
+
+    #[test]
+    fn test_saved_orders_are_loaded_back() {
+        let dir = std::env::temp_dir()
+            .join(format!("freddo_in_flight_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        assert!(load_in_flight(&dir, 2).unwrap().is_empty());
+
+        let orders = vec![OrderProgress {
+            order_id: "0-4".to_string(),
+            trace_id: "00ab".to_string(),
+            flavors_needed: vec![(FlavorID::Lemon, 250)],
+            substitutes: vec![(FlavorID::Lemon, vec![FlavorID::Mint])],
+            substitutions: vec![Substitution {
+                flavor: FlavorID::Chocolate,
+                substitute: FlavorID::Vanilla,
+            }],
+            scoops_failed: 1,
+        }];
+        save_in_flight(&dir, 2, &orders).unwrap();
+        assert_eq!(load_in_flight(&dir, 2).unwrap(), orders);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,4 +1,54 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::cluster::ElectionAlgorithm;
+use crate::protocol::election::ElectionId;
+
+/// LeaderElectionStrategy is the logic of an election, apart from the messages that carry it around the ring.
+/// The candidates are pairs of the id of a robot and whether it has a valid backup of the leader.
+/// The robot that starts an election sends the candidates of `start_election` to its next robot, and each robot
+/// that receives them either finishes the round and chooses the leader, or passes on the candidates of `add_candidate`.
+pub trait LeaderElectionStrategy: Debug {
+    /// Start the election process, returns the candidates to send to the next robot.
+    fn start_election(&mut self) -> Vec<(usize, bool)>;
+
+    /// Returns the candidates to send to the next robot, after this one took part in the election.
+    fn add_candidate(&mut self, candidates: Vec<(usize, bool)>) -> Vec<(usize, bool)>;
+
+    /// Check if the candidates already went around the ring, so this robot has to choose the leader.
+    fn check_round_finished(&self, candidates: Vec<(usize, bool)>) -> bool;
+
+    /// Check if the candidates are the ones a round started by `initiator` carries after `hops` robots passed it on.
+    fn round_is_consistent(
+        &self,
+        initiator: usize,
+        hops: usize,
+        candidates: &[(usize, bool)],
+    ) -> bool;
+
+    /// Most hops a round goes through before it finishes, in a ring of `robots` robots.
+    fn max_hops(&self, robots: usize) -> usize;
+
+    /// Choose the leader among the candidates of a finished round.
+    fn choose_leader(&mut self, candidates: Vec<(usize, bool)>) -> usize;
+
+    /// Validate the backup of the current robot.
+    fn validate_backup(&mut self);
+
+    /// Invalidate the backup of the current robot, it missed changes of the leader.
+    fn invalidate_backup(&mut self);
+}
+
+/// Returns the strategy of the given algorithm for the robot
+pub fn elector_for(algorithm: ElectionAlgorithm, my_id: usize) -> Box<dyn LeaderElectionStrategy> {
+    match algorithm {
+        ElectionAlgorithm::Ring => Box::new(LeaderElector::new(my_id)),
+        ElectionAlgorithm::Bully => Box::new(BullyElector::new(my_id)),
+    }
+}
 
 /// LeaderElector is a struct that is used to elect a leader among a group of robots using the token ring algorithm.
 /// It checks both the ID and the backup status of the robots to choose the leader.
@@ -15,9 +65,44 @@ impl LeaderElector {
             valid_backup: false,
         }
     }
+}
+
+impl LeaderElectionStrategy for LeaderElector {
+    /// Start the election process by adding the current robot to the candidates list.
+    fn start_election(&mut self) -> Vec<(usize, bool)> {
+        vec![(self.my_id, self.valid_backup)]
+    }
+
+    /// Add the current robot to the candidates list.
+    fn add_candidate(&mut self, mut candidates: Vec<(usize, bool)>) -> Vec<(usize, bool)> {
+        candidates.push((self.my_id, self.valid_backup));
+        candidates
+    }
+
+    /// Check if the round is finished by checking if the current robot is already a candidate.
+    fn check_round_finished(&self, candidates: Vec<(usize, bool)>) -> bool {
+        candidates
+            .iter()
+            .any(|(candidate, _)| *candidate == self.my_id)
+    }
+
+    /// Every robot the round went through added itself, starting with the one that started it.
+    fn round_is_consistent(
+        &self,
+        initiator: usize,
+        hops: usize,
+        candidates: &[(usize, bool)],
+    ) -> bool {
+        candidates.first().map(|(id, _)| *id) == Some(initiator) && candidates.len() == hops + 1
+    }
+
+    /// The round finishes once it gets back to a robot that added itself, at most when it goes around once.
+    fn max_hops(&self, robots: usize) -> usize {
+        robots.saturating_sub(1)
+    }
 
     /// Choose the leader among the candidates, based on the ID and the backup status of the robots.
-    pub fn choose_leader(&mut self, candidates: Vec<(usize, bool)>) -> usize {
+    fn choose_leader(&mut self, candidates: Vec<(usize, bool)>) -> usize {
         let mut new_leader_id = self.my_id;
         let mut has_valid_backup = self.valid_backup;
 
@@ -31,30 +116,520 @@ impl LeaderElector {
         new_leader_id
     }
 
-    /// Start the election process by adding the current robot to the candidates list.
-    pub fn start_election(&mut self) -> Vec<(usize, bool)> {
-        let new_candidates = vec![(self.my_id, self.valid_backup)];
-        new_candidates
+    fn validate_backup(&mut self) {
+        self.valid_backup = true;
     }
 
-    /// Add the current robot to the candidates list.
-    pub fn add_candidate(&mut self, mut candidates: Vec<(usize, bool)>) -> Vec<(usize, bool)> {
-        candidates.push((self.my_id, self.valid_backup));
+    fn invalidate_backup(&mut self) {
+        self.valid_backup = false;
+    }
+}
+
+/// BullyElector elects the strongest robot, the one with a valid backup and the highest ID, like the bully algorithm.
+/// A robot stronger than every candidate takes the election over by replacing them with itself, and a weaker one
+/// passes them on untouched, so only one candidate travels and the round finishes when it gets back to the strongest robot.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BullyElector {
+    pub my_id: usize,
+    pub valid_backup: bool,
+}
+
+impl BullyElector {
+    pub fn new(my_id: usize) -> Self {
+        Self {
+            my_id,
+            valid_backup: false,
+        }
+    }
+
+    /// Returns true if this robot is stronger than every candidate
+    fn stronger_than(&self, candidates: &[(usize, bool)]) -> bool {
         candidates
+            .iter()
+            .all(|&(id, valid_backup)| (self.valid_backup, self.my_id) > (valid_backup, id))
     }
+}
 
-    /// Check if the round is finished by checking if the current robot is the leader.
-    pub fn check_round_finished(&self, candidates: Vec<(usize, bool)>) -> bool {
-        for (candidate, _) in candidates {
-            if candidate == self.my_id {
-                return true;
-            }
+impl LeaderElectionStrategy for BullyElector {
+    fn start_election(&mut self) -> Vec<(usize, bool)> {
+        vec![(self.my_id, self.valid_backup)]
+    }
+
+    /// Replace the candidates with the current robot if it is stronger than all of them.
+    fn add_candidate(&mut self, candidates: Vec<(usize, bool)>) -> Vec<(usize, bool)> {
+        if self.stronger_than(&candidates) {
+            return vec![(self.my_id, self.valid_backup)];
         }
-        false
+        candidates
     }
 
-    /// Validate the backup of the current robot.
-    pub fn validate_backup(&mut self) {
+    /// Check if the round is finished by checking if the current robot is the one that took the election over.
+    fn check_round_finished(&self, candidates: Vec<(usize, bool)>) -> bool {
+        candidates
+            .iter()
+            .any(|(candidate, _)| *candidate == self.my_id)
+    }
+
+    /// Only one candidate travels, and it is the robot that started the round until another one takes it over.
+    fn round_is_consistent(
+        &self,
+        initiator: usize,
+        hops: usize,
+        candidates: &[(usize, bool)],
+    ) -> bool {
+        match candidates {
+            [(id, _)] => hops > 0 || *id == initiator,
+            _ => false,
+        }
+    }
+
+    /// The strongest robot can be right before the one that started the round, so it may go around twice.
+    fn max_hops(&self, robots: usize) -> usize {
+        (2 * robots).saturating_sub(1)
+    }
+
+    /// Choose the strongest robot, the current one if no candidate is stronger.
+    fn choose_leader(&mut self, candidates: Vec<(usize, bool)>) -> usize {
+        candidates
+            .into_iter()
+            .chain([(self.my_id, self.valid_backup)])
+            .max_by_key(|&(id, valid_backup)| (valid_backup, id))
+            .map_or(self.my_id, |(id, _)| id)
+    }
+
+    fn validate_backup(&mut self) {
         self.valid_backup = true;
     }
+
+    fn invalidate_backup(&mut self) {
+        self.valid_backup = false;
+    }
+}
+
+/// Checks the starter, hops and candidates of a round that arrived, in a ring of up to `robots` robots.
+/// A round that went through more robots than a round goes through in the ring, that has a robot twice or whose candidates do not match
+/// its starter and hops was tampered with or mixed up with another one, so it is ignored
+pub fn check_round_metadata(
+    elector: &dyn LeaderElectionStrategy,
+    election: ElectionId,
+    hops: usize,
+    candidates: &[(usize, bool)],
+    robots: usize,
+) -> Result<(), String> {
+    if candidates.is_empty() {
+        return Err("it has no candidates".to_string());
+    }
+    if hops > elector.max_hops(robots) {
+        return Err(format!(
+            "it went through {} robots of a ring of {}",
+            hops + 1,
+            robots
+        ));
+    }
+    let mut seen = HashSet::new();
+    if let Some((twice, _)) = candidates.iter().find(|(id, _)| !seen.insert(*id)) {
+        return Err(format!("robot {} is a candidate twice", twice));
+    }
+    if !elector.round_is_consistent(election.initiator, hops, candidates) {
+        return Err(format!(
+            "its candidates do not match a round started by robot {} after {} hops",
+            election.initiator, hops
+        ));
+    }
+    Ok(())
+}
+
+/// What a robot does with a round of an election it receives
+#[derive(Debug, PartialEq)]
+pub enum RoundCheck {
+    /// The round is older than the leader or than the round the robot takes part in, it is dropped
+    Stale,
+    /// The round is the one the robot takes part in
+    Current,
+    /// The round came back with the same candidates the robot passed on, so the robot that had to finish it died
+    Returned,
+}
+
+/// ElectionRounds keeps the round of the election a robot takes part in, so overlapping rounds are merged into one.
+/// A robot joins a round greater than its own and drops the smaller ones, so the smaller rounds die at the first robot
+/// that already joined a greater one. A round that does not finish within the timeout is forgotten, so a lost round
+/// does not block the next ones.
+#[derive(Debug)]
+pub struct ElectionRounds {
+    current: Option<ElectionId>,
+    last_epoch: u64,
+    forwarded: Option<Vec<(usize, bool)>>,
+    since: Instant,
+    timeout: Duration,
+}
+
+impl ElectionRounds {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            current: None,
+            last_epoch: 0,
+            forwarded: None,
+            since: Instant::now(),
+            timeout,
+        }
+    }
+
+    /// Starts a round with the candidates the robot sends.
+    /// Its epoch is after the one of the leader and of every round seen, so it is not dropped for one that died
+    pub fn start(
+        &mut self,
+        my_id: usize,
+        leader_epoch: u64,
+        candidates: &[(usize, bool)],
+    ) -> ElectionId {
+        let id = ElectionId {
+            epoch: leader_epoch.max(self.last_epoch) + 1,
+            initiator: my_id,
+        };
+        self.current = Some(id);
+        self.last_epoch = id.epoch;
+        self.forwarded = Some(candidates.to_vec());
+        self.since = Instant::now();
+        id
+    }
+
+    /// Checks a round that arrived with the given candidates, joining it if it is greater than the current one
+    pub fn check(
+        &mut self,
+        id: ElectionId,
+        candidates: &[(usize, bool)],
+        leader_epoch: u64,
+    ) -> RoundCheck {
+        if id.epoch <= leader_epoch {
+            return RoundCheck::Stale;
+        }
+        match self.in_progress(leader_epoch) {
+            Some(current) if id < current => RoundCheck::Stale,
+            Some(current) if current == id => {
+                if self.forwarded.as_deref() == Some(candidates) {
+                    RoundCheck::Returned
+                } else {
+                    RoundCheck::Current
+                }
+            }
+            _ => {
+                self.current = Some(id);
+                self.last_epoch = self.last_epoch.max(id.epoch);
+                self.forwarded = None;
+                self.since = Instant::now();
+                RoundCheck::Current
+            }
+        }
+    }
+
+    /// Registers the candidates the robot passed on in the current round
+    pub fn forwarded(&mut self, candidates: &[(usize, bool)]) {
+        self.forwarded = Some(candidates.to_vec());
+    }
+
+    /// Returns true if the robot started or passed on the current round.
+    /// A robot that restarted while the round went around is a candidate of it without having taken part
+    pub fn took_part(&self) -> bool {
+        self.forwarded.is_some()
+    }
+
+    /// Returns the round the robot takes part in, if it is newer than the leader and was joined within the timeout
+    pub fn in_progress(&self, leader_epoch: u64) -> Option<ElectionId> {
+        let current = self.current?;
+        (current.epoch > leader_epoch && self.since.elapsed() < self.timeout).then_some(current)
+    }
+
+    /// Ends the current round, once the leader was chosen or the round was dropped
+    pub fn finish(&mut self) {
+        self.current = None;
+        self.forwarded = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Electors of the robots of a ring, in the order the candidates travel.
+    /// A partition is simulated with a ring of only the robots that can reach each other, as the dead robots are skipped
+    fn ring(
+        algorithm: ElectionAlgorithm,
+        robots: &[(usize, bool)],
+    ) -> Vec<Box<dyn LeaderElectionStrategy>> {
+        robots
+            .iter()
+            .map(|&(id, valid_backup)| {
+                let mut elector = elector_for(algorithm, id);
+                if valid_backup {
+                    elector.validate_backup();
+                }
+                elector
+            })
+            .collect()
+    }
+
+    /// Runs an election started by the robot at the given position of the ring, without sockets.
+    /// Returns the leader chosen, the robot that chose it and the most candidates that were sent at once
+    fn run_election(
+        electors: &mut [Box<dyn LeaderElectionStrategy>],
+        starter: usize,
+        ids: &[usize],
+    ) -> (usize, usize, usize) {
+        let mut candidates = electors[starter].start_election();
+        let mut most_candidates = candidates.len();
+        let mut position = (starter + 1) % electors.len();
+        let election = ElectionId {
+            epoch: 1,
+            initiator: ids[starter],
+        };
+        for hops in 0..2 * electors.len() + 1 {
+            let elector = &mut electors[position];
+            let metadata =
+                check_round_metadata(elector.as_ref(), election, hops, &candidates, ids.len());
+            assert_eq!(metadata, Ok(()));
+            if elector.check_round_finished(candidates.clone()) {
+                return (
+                    elector.choose_leader(candidates),
+                    ids[position],
+                    most_candidates,
+                );
+            }
+            candidates = elector.add_candidate(candidates);
+            most_candidates = most_candidates.max(candidates.len());
+            position = (position + 1) % electors.len();
+        }
+        panic!("The election never finished");
+    }
+
+    /// Runs an election in the ring of the given robots with both algorithms
+    fn elect_with_both(robots: &[(usize, bool)], starter: usize) -> [(usize, usize, usize); 2] {
+        let ids: Vec<usize> = robots.iter().map(|(id, _)| *id).collect();
+        [ElectionAlgorithm::Ring, ElectionAlgorithm::Bully]
+            .map(|algorithm| run_election(&mut ring(algorithm, robots), starter, &ids))
+    }
+
+    #[test]
+    fn test_ring_prefers_a_valid_backup_over_a_higher_id() {
+        let mut elector = LeaderElector::new(1);
+        assert_eq!(elector.choose_leader(vec![(3, false), (2, true)]), 2);
+        assert_eq!(elector.choose_leader(vec![(3, false), (0, false)]), 1);
+    }
+
+    #[test]
+    fn test_bully_replaces_weaker_candidates() {
+        let mut strong = BullyElector::new(2);
+        strong.validate_backup();
+        assert_eq!(strong.add_candidate(vec![(3, false)]), vec![(2, true)]);
+
+        let mut weak = BullyElector::new(1);
+        assert_eq!(weak.add_candidate(vec![(3, false)]), vec![(3, false)]);
+        assert!(!weak.check_round_finished(vec![(3, false)]));
+    }
+
+    #[test]
+    fn test_both_algorithms_elect_the_same_leader_from_any_starter() {
+        let robots = [(0, true), (1, true), (2, false), (3, true), (4, false)];
+        for starter in 0..robots.len() {
+            let [ring, bully] = elect_with_both(&robots, starter);
+            assert_eq!(ring.0, 3);
+            assert_eq!(bully.0, 3);
+            assert_eq!(ring.1, robots[starter].0);
+            assert_eq!(bully.1, 3);
+            assert_eq!(ring.2, robots.len());
+            assert_eq!(bully.2, 1);
+        }
+    }
+
+    #[test]
+    fn test_each_side_of_a_partition_elects_its_own_leader() {
+        let robots = [(0, true), (1, true), (2, true), (3, true)];
+        let (left, right) = robots.split_at(2);
+
+        assert_eq!(elect_with_both(left, 0).map(|result| result.0), [1, 1]);
+        assert_eq!(elect_with_both(right, 1).map(|result| result.0), [3, 3]);
+    }
+
+    #[test]
+    fn test_a_lone_robot_elects_itself_without_a_backup() {
+        assert_eq!(
+            elect_with_both(&[(2, false)], 0).map(|result| result.0),
+            [2, 2]
+        );
+    }
+
+    /// Runs the rounds started at once by the robots at the given positions of the ring, interleaving their messages.
+    /// Returns the rounds that finished, with the leader each one chose
+    fn run_overlapping_rounds(
+        algorithm: ElectionAlgorithm,
+        robots: &[(usize, bool)],
+        starters: &[usize],
+    ) -> Vec<(ElectionId, usize)> {
+        let mut electors = ring(algorithm, robots);
+        let mut rounds: Vec<ElectionRounds> = robots
+            .iter()
+            .map(|_| ElectionRounds::new(Duration::from_secs(60)))
+            .collect();
+        let mut messages = VecDeque::new();
+        for &starter in starters {
+            let candidates = electors[starter].start_election();
+            let round = rounds[starter].start(robots[starter].0, 0, &candidates);
+            messages.push_back(((starter + 1) % robots.len(), round, 0, candidates));
+        }
+        let mut finished = Vec::new();
+        while let Some((position, round, hops, candidates)) = messages.pop_front() {
+            let elector = electors[position].as_ref();
+            let metadata = check_round_metadata(elector, round, hops, &candidates, robots.len());
+            assert_eq!(metadata, Ok(()));
+            match rounds[position].check(round, &candidates, 0) {
+                RoundCheck::Stale => continue,
+                _ if electors[position].check_round_finished(candidates.clone()) => {
+                    assert!(rounds[position].took_part());
+                    rounds[position].finish();
+                    finished.push((round, electors[position].choose_leader(candidates)));
+                }
+                RoundCheck::Returned => panic!("Round {} came back unfinished", round),
+                RoundCheck::Current => {
+                    let candidates = electors[position].add_candidate(candidates);
+                    rounds[position].forwarded(&candidates);
+                    let next = (position + 1) % robots.len();
+                    messages.push_back((next, round, hops + 1, candidates));
+                }
+            }
+        }
+        finished
+    }
+
+    #[test]
+    fn test_overlapping_rounds_merge_into_the_greatest() {
+        let robots = [(0, true), (1, true), (2, false), (3, true), (4, false)];
+        let greatest = ElectionId {
+            epoch: 1,
+            initiator: 4,
+        };
+        for algorithm in [ElectionAlgorithm::Ring, ElectionAlgorithm::Bully] {
+            let finished = run_overlapping_rounds(algorithm, &robots, &[1, 4, 2]);
+            assert_eq!(finished, vec![(greatest, 3)]);
+        }
+    }
+
+    #[test]
+    fn test_rounds_older_than_the_leader_are_dropped() {
+        let mut rounds = ElectionRounds::new(Duration::from_secs(60));
+        let old = ElectionId {
+            epoch: 3,
+            initiator: 2,
+        };
+        assert_eq!(rounds.check(old, &[(2, true)], 3), RoundCheck::Stale);
+
+        let round = rounds.start(1, 3, &[(1, true)]);
+        assert_eq!(round.epoch, 4);
+        assert_eq!(rounds.in_progress(3), Some(round));
+        assert_eq!(rounds.in_progress(4), None);
+    }
+
+    #[test]
+    fn test_a_round_that_comes_back_untouched_was_lost() {
+        let mut rounds = ElectionRounds::new(Duration::from_secs(60));
+        let round = ElectionId {
+            epoch: 1,
+            initiator: 3,
+        };
+        assert_eq!(rounds.check(round, &[(3, true)], 0), RoundCheck::Current);
+        rounds.forwarded(&[(3, true)]);
+        assert_eq!(rounds.check(round, &[(3, true)], 0), RoundCheck::Returned);
+
+        rounds.finish();
+        assert_eq!(rounds.start(1, 0, &[(1, false)]).epoch, 2);
+    }
+
+    #[test]
+    fn test_a_round_that_does_not_finish_in_time_is_forgotten() {
+        let mut rounds = ElectionRounds::new(Duration::ZERO);
+        rounds.start(4, 0, &[(4, true)]);
+        assert_eq!(rounds.in_progress(0), None);
+
+        let smaller = ElectionId {
+            epoch: 1,
+            initiator: 0,
+        };
+        assert_eq!(rounds.check(smaller, &[(0, true)], 0), RoundCheck::Current);
+    }
+
+    #[test]
+    fn test_rounds_with_inconsistent_metadata_are_ignored() {
+        let ring = LeaderElector::new(0);
+        let bully = BullyElector::new(0);
+        let round = ElectionId {
+            epoch: 2,
+            initiator: 1,
+        };
+        let check = |elector: &dyn LeaderElectionStrategy, hops, candidates: &[(usize, bool)]| {
+            check_round_metadata(elector, round, hops, candidates, 4).is_ok()
+        };
+        assert!(check(&ring, 2, &[(1, true), (2, false), (3, true)]));
+        assert!(!check(&ring, 2, &[(1, true), (2, false), (2, false)]));
+        assert!(!check(&ring, 2, &[(2, false), (1, true), (3, true)]));
+        assert!(!check(&ring, 1, &[(1, true), (2, false), (3, true)]));
+        assert!(!check(&ring, 0, &[]));
+
+        assert!(check(&bully, 0, &[(1, true)]));
+        assert!(check(&bully, 3, &[(3, true)]));
+        assert!(!check(&bully, 0, &[(3, true)]));
+        assert!(check(&bully, 7, &[(3, true)]));
+        assert!(!check(&bully, 8, &[(3, true)]));
+        assert!(!check(
+            &ring,
+            4,
+            &[(1, true), (2, false), (3, true), (0, true), (4, true)]
+        ));
+        assert!(!check(&bully, 1, &[(3, true), (2, true)]));
+    }
+
+    #[test]
+    fn test_a_robot_that_rejoined_mid_election_does_not_finish_the_round() {
+        let round = ElectionId {
+            epoch: 1,
+            initiator: 1,
+        };
+        let candidates = [(1, true), (2, true), (3, false)];
+        let elector = LeaderElector::new(2);
+        assert!(check_round_metadata(&elector, round, 2, &candidates, 4).is_ok());
+        assert!(elector.check_round_finished(candidates.to_vec()));
+
+        // robot 2 restarted after passing the round on, so it does not remember it
+        let mut restarted = ElectionRounds::new(Duration::from_secs(60));
+        assert_eq!(restarted.check(round, &candidates, 0), RoundCheck::Current);
+        assert!(!restarted.took_part());
+
+        let mut stayed = ElectionRounds::new(Duration::from_secs(60));
+        stayed.check(round, &candidates[..1], 0);
+        stayed.forwarded(&candidates[..2]);
+        assert_eq!(stayed.check(round, &candidates, 0), RoundCheck::Current);
+        assert!(stayed.took_part());
+    }
+
+    #[test]
+    fn test_a_robot_that_joins_mid_election_takes_part_in_the_round() {
+        let robots = [(0, true), (1, false), (3, true)];
+        let mut electors = ring(ElectionAlgorithm::Ring, &robots);
+        let round = ElectionId {
+            epoch: 1,
+            initiator: 0,
+        };
+        let candidates = electors[0].start_election();
+        let candidates = electors[1].add_candidate(candidates);
+
+        // robot 2 joins the ring between robots 1 and 3 while the round goes around
+        let mut joined = elector_for(ElectionAlgorithm::Ring, 2);
+        joined.validate_backup();
+        assert!(check_round_metadata(joined.as_ref(), round, 1, &candidates, 4).is_ok());
+        assert!(!joined.check_round_finished(candidates.clone()));
+        let candidates = joined.add_candidate(candidates);
+        let candidates = electors[2].add_candidate(candidates);
+
+        assert!(check_round_metadata(electors[0].as_ref(), round, 3, &candidates, 4).is_ok());
+        assert!(electors[0].check_round_finished(candidates.clone()));
+        assert_eq!(electors[0].choose_leader(candidates), 3);
+    }
 }
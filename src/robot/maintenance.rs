@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::maintenance::MaintenanceConfig;
+
+/// Milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Time until the first defrost of the robot, or None if the robots do not defrost.
+/// The robots are staggered by their id, so they do not all defrost at the same time
+pub fn first_defrost_in(robot_id: usize, config: &MaintenanceConfig) -> Option<Duration> {
+    if config.defrost_every_ms == 0 {
+        return None;
+    }
+    let offset = (robot_id as u64 * config.defrost_ms) % config.defrost_every_ms;
+    Some(Duration::from_millis(config.defrost_every_ms + offset))
+}
+
+/// Robots that told the leader they are defrosting, with until when, in milliseconds since the Unix epoch.
+/// They are not part of the backup, a robot defrosting when the leader changes may get orders before it ends
+#[derive(Default)]
+pub struct Unavailability {
+    until: HashMap<usize, u64>,
+}
+
+impl Unavailability {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the robot as unavailable until the given time, returns how long until it is available again
+    pub fn mark(&mut self, robot_id: usize, until: u64, now: u64) -> Duration {
+        self.until.insert(robot_id, until);
+        Duration::from_millis(until.saturating_sub(now))
+    }
+
+    /// Returns true if the robot is still defrosting
+    pub fn is_unavailable(&self, robot_id: usize, now: u64) -> bool {
+        self.until.get(&robot_id).is_some_and(|until| *until > now)
+    }
+
+    /// Forgets the robot if its window already ended, returns true if it did
+    pub fn resume(&mut self, robot_id: usize, now: u64) -> bool {
+        match self.until.get(&robot_id) {
+            Some(until) if *until <= now => {
+                self.until.remove(&robot_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets the robot, like when it leaves the ring
+    pub fn remove(&mut self, robot_id: usize) {
+        self.until.remove(&robot_id);
+    }
+
+    /// Returns the free slots of the robots that are not defrosting
+    pub fn assignable(&self, available_robots: &[usize], now: u64) -> Vec<usize> {
+        available_robots
+            .iter()
+            .copied()
+            .filter(|robot_id| !self.is_unavailable(*robot_id, now))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_robots_defrost_at_different_times() {
+        let config = MaintenanceConfig {
+            defrost_every_ms: 10_000,
+            defrost_ms: 2_000,
+        };
+        assert_eq!(
+            first_defrost_in(0, &config),
+            Some(Duration::from_millis(10_000))
+        );
+        assert_eq!(
+            first_defrost_in(2, &config),
+            Some(Duration::from_millis(14_000))
+        );
+        assert_eq!(first_defrost_in(1, &MaintenanceConfig::default()), None);
+    }
+
+    #[test]
+    fn test_defrosting_robots_are_not_assignable_until_their_window_ends() {
+        let mut unavailability = Unavailability::new();
+        let wait = unavailability.mark(1, 1_500, 1_000);
+        assert_eq!(wait, Duration::from_millis(500));
+        assert_eq!(unavailability.assignable(&[0, 1, 1, 2], 1_000), vec![0, 2]);
+
+        assert!(!unavailability.resume(1, 1_200));
+        assert!(unavailability.resume(1, 1_500));
+        assert_eq!(unavailability.assignable(&[0, 1], 1_500), vec![0, 1]);
+    }
+}
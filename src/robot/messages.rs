@@ -1,78 +1,36 @@
 use actix::{Addr, Message};
-use serde::{Deserialize, Serialize};
-use std::{error::Error, fmt};
+use std::collections::HashMap;
+use std::time::Duration;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 
+use crate::common::error::FreddoError;
 use crate::common::flavor_id::FlavorID;
-use crate::common::order::Order;
-use crate::robot::flavor_token::FlavorToken;
-use crate::robot::leader_backup::LeaderBackup;
-use crate::robot::order_manager::OrderManager;
-use crate::robot::robot_connection_handler::RobotConnectionHandler;
-use crate::robot::token_backup::TokenBackup;
+use crate::common::order::{Order, Substitution};
+use crate::common::order_source::OrderSource;
+use crate::protocol::backup_seal::SealedBackup;
+use crate::protocol::compression::Compression;
+use crate::protocol::election::ElectionId;
+use crate::protocol::flavor_token::FlavorToken;
+use crate::protocol::robot_messages::AbortReason;
+use crate::protocol::token_backup::TokenBackup;
+use crate::robot::accounting::StockAccountsReport;
+use crate::robot::admin::{Holding, RobotStatus};
+use crate::robot::cluster_state::ClusterState;
+use crate::robot::connections::robot_to_leader_connection::RobotToLeaderConnection;
+use crate::robot::consumption_report::ConsumptionReport;
+use crate::robot::dead_letters::DeadLetter;
+use crate::robot::queue_status::{Assignment, QueueDepth, StashedResult};
+use crate::robot::robot_leader::RobotLeader;
+use crate::robot::screen_stats::ScreenStats;
+use crate::robot::shard_routes::{Part, PartResult};
+use crate::robot::shop_book::ShopStats;
+use crate::robot::stock_watch::StockLevel;
+use crate::robot::token_trace::TokenTrace;
+
+pub use crate::protocol::robot_command::{RobotCommand, RobotCommandError};
 
 /// All the messages that can be sent between the Actors
 
-#[derive(Debug)]
-pub enum RobotCommandError {
-    ErrorParsing(String),
-}
-
-impl fmt::Display for RobotCommandError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-impl Error for RobotCommandError {}
-
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub enum RobotCommand {
-    NewRobot,
-    NewPreviousRobot,
-    GetLeaderId,
-    ReceiveLeaderBackup {
-        backup: LeaderBackup,
-    },
-    NewNextRobot {
-        next_robot: usize,
-    },
-    TokenMessage {
-        token: FlavorToken,
-    },
-    TokenBackupMsg {
-        token_backup: TokenBackup,
-    },
-    NewLeader {
-        leader: usize,
-    },
-    NewElection {
-        candidates: Vec<(usize, bool)>,
-    },
-    NewOrder {
-        order: Order,
-        order_id: String,
-    },
-    OrderComplete {
-        result: bool,
-        order_id: String,
-    },
-    OrderNotFinished {
-        result: bool,
-        order_id: String,
-        flavor: FlavorID,
-    },
-}
-
-impl RobotCommand {
-    pub fn from_string(msg: &str) -> Result<Self, RobotCommandError> {
-        serde_json::from_str(msg).map_err(|err| RobotCommandError::ErrorParsing(err.to_string()))
-    }
-
-    pub fn to_string(&self) -> Result<String, RobotCommandError> {
-        serde_json::to_string(self).map_err(|err| RobotCommandError::ErrorParsing(err.to_string()))
-    }
-}
-
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ConnectToScreen {
@@ -101,7 +59,9 @@ pub struct SendFirstToken {
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct TimerWentOff();
+pub struct TimerWentOff {
+    pub order_id: String,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -116,16 +76,101 @@ pub struct ChangeScreen {
     pub new_screen_id: usize,
 }
 
+/// Other end of a connection opened by a robot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Peer {
+    Leader(usize),
+    Screen(usize),
+    /// The leader of a shard, which the leader passes the parts of its orders to
+    Shard(usize),
+}
+
+/// Tells the actor that owns a connection that it could not be opened, so it decides whether to retry or escalate
+/// `attempt` counts the retries already made
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ConnectionFailed {
+    pub peer: Peer,
+    pub error: FreddoError,
+    pub attempt: u32,
+}
+
+/// Asks the OrderManager for the ids of the orders it is preparing and of the ones it restored from disk
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct GetOrdersInFlight();
+
+/// Tells the leader the orders the robot was preparing, it goes through the connection with the leader
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrdersInFlight {
+    pub robot_id: usize,
+    pub orders: Vec<String>,
+}
+
+/// The orders in flight of a robot that the leader lets it resume, and the ones it has to drop
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResumeOrders {
+    pub resume: Vec<String>,
+    pub drop: Vec<String>,
+}
+
+/// The leader turned down the connection of the robot, another robot with its id is connected
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RobotIdInUse {
+    pub robot_id: usize,
+}
+
+/// Tells the actor that owns a connection that it was closed, so it tries to open it again before giving up on the peer
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ConnectionLost {
+    pub peer: Peer,
+}
+
+/// A screen connected to the leader again, with the ids of the orders it still has captured
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ScreenRejoined {
+    pub screen_id: usize,
+    pub order_ids: Vec<String>,
+}
+
+/// Tells a screen that rejoined which screens had claimed its orders and which of them were already finished
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendOrdersReclaimed {
+    pub taken_from: Vec<(usize, Vec<String>)>,
+    pub finished: Vec<String>,
+}
+
+/// Tells a screen to forget orders that are handled by another screen
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct StoreBackup {
-    pub backup: LeaderBackup,
+pub struct ReleaseOrders {
+    pub order_ids: Vec<String>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SendLeaderBackup {
-    pub backup: LeaderBackup,
+    pub backup: SealedBackup,
+}
+
+/// A robot could not apply the changes of a backup and needs the last backup whole
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResendBackup {
+    pub robot_id: usize,
+}
+
+/// Asks the leader for the last backup whole, the robot could not apply the changes of one
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AskForFullBackup {
+    pub robot_id: usize,
 }
 
 #[derive(Message)]
@@ -153,6 +198,8 @@ pub struct ConnectToNewScreen {
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ReceiveNewElection {
+    pub election: ElectionId,
+    pub hops: usize,
     pub candidates: Vec<(usize, bool)>,
 }
 
@@ -161,6 +208,7 @@ pub struct ReceiveNewElection {
 pub struct SetNewLeader {
     pub leader_id: usize,
     pub by_election: bool,
+    pub epoch: u64,
 }
 
 #[derive(Message)]
@@ -169,27 +217,120 @@ pub struct AddNewLeader {
     pub write_half: OwnedWriteHalf,
     pub read_half: OwnedReadHalf,
     pub leader_id: usize,
+    pub epoch: u64,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct NewLeaderElected {
     pub leader_id: usize,
+    pub epoch: u64,
 }
 
+/// A command sent by the leader, the RCH only runs it if the epoch of the leader is not older than the one it knows
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct AddNewRobot {
+pub struct LeaderCommand {
+    pub epoch: u64,
+    pub command: RobotCommand,
+    pub connection: Addr<RobotToLeaderConnection>,
+}
+
+/// Tells the leader on the other end of the connection that there is a leader with a newer epoch
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RejectStaleLeader {
+    pub epoch: u64,
+}
+
+/// Tells the leader that a robot or screen knows a newer epoch, so another leader was elected
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StepDown {
+    pub epoch: u64,
+}
+
+/// Tells the robot that its leader stepped down since a leader with a newer epoch exists
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaderSteppedDown {
+    pub epoch: u64,
+}
+
+/// The control lane opened by the previous robot, the robot reads the control messages from it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AddControlLane {
     pub robot_id: usize,
-    pub write_half: OwnedWriteHalf,
     pub read_half: OwnedReadHalf,
-    pub asked: bool,
+    pub write_half: OwnedWriteHalf,
+}
+
+/// The control lane to the next robot was closed, with the frames it could not write, which go through the ring
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct ControlLaneClosed {
+    pub next_id: usize,
+    pub unsent: Vec<Vec<u8>>,
+}
+
+/// Tells the robot the leader it runs, so the commands for the leader can reach it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaderStarted {
+    pub leader: Addr<RobotLeader>,
+}
+
+/// Tells the leader that a flavor ran below its low watermark or got back above it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StockLevelChanged {
+    pub level: StockLevel,
+}
+
+/// Asks the robot for the leader it runs, there is none if it is not the leader
+#[derive(Message)]
+#[rtype(result = "Option<Addr<RobotLeader>>")]
+pub struct GetLeader();
+
+/// Asks the leader to hand its leadership to the robot without an election, the result says why it can not
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct TransferLeadership {
+    pub target_robot: usize,
+}
+
+/// Sends the last backup of the leader to the robot that takes its leadership in the given epoch
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct HandOffLeadership {
+    pub backup: SealedBackup,
+    pub next_epoch: u64,
+}
+
+/// Tells a robot or screen which robot takes the leadership in the given epoch
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AnnounceLeader {
+    pub leader_id: usize,
+    pub next_epoch: u64,
+}
+
+/// Tells the robot that its leader handed the leadership to another robot, so it follows the new leader
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeadershipTransferred {
+    pub leader_id: usize,
+    pub next_epoch: u64,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SetOrderManager {
-    pub order_manager: Addr<OrderManager>,
+pub struct AddNewRobot {
+    pub robot_id: usize,
+    pub write_half: OwnedWriteHalf,
+    pub read_half: OwnedReadHalf,
+    pub asked: bool,
 }
 
 #[derive(Message)]
@@ -210,15 +351,25 @@ pub struct TransferToken {
 pub struct GetTokenBack {
     pub flavor_token: FlavorToken,
 }
+
+/// Tells the OrderManager that the scoop failed, the token comes back without anything served
+/// The reason is `ScoopFailed` if the arm jammed, or `OutOfStock` if the token had fewer grams than the ones asked
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ScoopFailed {
+    pub flavor_token: FlavorToken,
+    pub reason: AbortReason,
+}
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct AbortCurrentOrder {}
+pub struct AbortCurrentOrders {}
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct OrderPrepared {
     pub order_result: bool,
     pub id: String,
+    pub substitutions: Vec<Substitution>,
 }
 
 #[derive(Message)]
@@ -227,6 +378,55 @@ pub struct OrderAborted {
     pub order_result: bool,
     pub id: String,
     pub flavor: FlavorID,
+    pub reason: AbortReason,
+}
+
+/// Tells a screen that an order it was told was prepared got aborted afterwards
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrderAbortedAfterConfirm {
+    pub id: String,
+    pub flavor: FlavorID,
+    pub reason: AbortReason,
+}
+
+/// Tells a screen that the leader has too many orders on queue to take the order now
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrderRejectedBusy {
+    pub id: String,
+}
+
+/// Tells a screen that the order can not be served with the flavors of the catalog
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrderRejectedUnservable {
+    pub id: String,
+    pub reason: AbortReason,
+    pub error: String,
+    pub flavor: Option<FlavorID>,
+}
+
+/// Passes a part of an order to the leader of its shard
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PassPart {
+    pub part: Part,
+}
+
+/// Tells the leader what the leader of a shard answered about a part of an order
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PartFinished {
+    pub part_id: String,
+    pub result: PartResult,
+}
+
+/// Tells the leader that its connection with the leader of the shard was closed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ShardLinkClosed {
+    pub shard: usize,
 }
 
 #[derive(Message)]
@@ -234,6 +434,7 @@ pub struct OrderAborted {
 pub struct GetNewOrder {
     pub new_order: Order,
     pub id: String,
+    pub trace_id: String,
 }
 
 #[derive(Message)]
@@ -242,6 +443,17 @@ pub struct CreateNewOrder {
     pub new_order: Order,
     pub id: String,
     pub screen_id: usize,
+    pub deadline_ms: Option<u64>,
+    pub source: Option<OrderSource>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CreateNewOrderBatch {
+    pub orders: Vec<(String, Order)>,
+    pub screen_id: usize,
+    pub deadline_ms: Option<u64>,
+    pub sources: HashMap<String, OrderSource>,
 }
 
 #[derive(Message)]
@@ -249,6 +461,7 @@ pub struct CreateNewOrder {
 pub struct SendNewOrder {
     pub new_order: Order,
     pub order_id: String,
+    pub trace_id: String,
 }
 
 #[derive(Message)]
@@ -257,6 +470,7 @@ pub struct GetCompletedOrder {
     pub order_result: bool,
     pub order_id: String,
     pub robot_id: usize,
+    pub substitutions: Vec<Substitution>,
 }
 
 #[derive(Message)]
@@ -266,6 +480,7 @@ pub struct GetAbortedOrder {
     pub order_id: String,
     pub robot_id: usize,
     pub flavor: FlavorID,
+    pub reason: AbortReason,
 }
 
 #[derive(Message)]
@@ -275,6 +490,8 @@ pub struct AddOrderToBeSent {
     pub id: String,
     pub screen_id: usize,
     pub flavor: Option<FlavorID>,
+    pub reason: AbortReason,
+    pub substitutions: Vec<Substitution>,
 }
 
 #[derive(Message)]
@@ -283,9 +500,195 @@ pub struct RobotDied {
     pub robot_id: usize,
 }
 
+/// Makes the robot join the ring, if `wait_for` is set it first waits for the rest of the cluster
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinRing {
+    pub wait_for: Option<ClusterWait>,
+}
+
+/// How many robots, counting itself, a robot waits to be reachable before joining the ring, and for how long at most
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterWait {
+    pub robots: usize,
+    pub timeout: Duration,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveRing();
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RobotLeaving {
+    pub robot_id: usize,
+}
+
+/// Tells the leader that the robot is defrosting until `until`, in milliseconds since the Unix epoch
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RobotUnavailable {
+    pub robot_id: usize,
+    pub until: u64,
+}
+
+/// Tells the leader how many milliseconds the robot takes to scoop a gram, and the compressions of the backups it reads
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ScoopSpeed {
+    pub robot_id: usize,
+    pub ms_per_gram: usize,
+    pub compressions: Vec<Compression>,
+}
+
+/// Tells the OrderManager to stop scooping for a while, the tokens go through the robot untouched
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct JoinRing();
+pub struct Defrost {
+    pub duration: Duration,
+}
+
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct GetOrdersInProgress();
+
+#[derive(Message)]
+#[rtype(result = "ClusterState")]
+pub struct GetClusterState();
+
+/// Asks the leader how many orders it has in each stage
+#[derive(Message)]
+#[rtype(result = "QueueDepth")]
+pub struct GetQueueDepth();
+
+/// Asks the leader how the orders of each screen went
+#[derive(Message)]
+#[rtype(result = "Vec<ScreenStats>")]
+pub struct GetScreenStats();
+
+/// Asks the leader how the orders of each shop went and the grams they took
+#[derive(Message)]
+#[rtype(result = "Vec<ShopStats>")]
+pub struct GetShopStats();
+
+/// Asks the leader for the balance of the stock of each flavor and the invariant violations found
+#[derive(Message)]
+#[rtype(result = "StockAccountsReport")]
+pub struct GetStockAccounts();
+
+/// Tells the leader that the robot it runs added grams of a restock to a token of the flavor
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StockRestocked {
+    pub flavor: FlavorID,
+    pub grams: usize,
+}
+
+/// Asks the leader for the orders the robots are preparing
+#[derive(Message)]
+#[rtype(result = "Vec<Assignment>")]
+pub struct GetAssignments();
+
+/// Asks the leader for the results it could not send to their screens yet
+#[derive(Message)]
+#[rtype(result = "Vec<StashedResult>")]
+pub struct GetStashedResults();
+
+/// Asks the leader for the results it gave up sending to their screens
+#[derive(Message)]
+#[rtype(result = "Vec<DeadLetter>")]
+pub struct GetDeadLetters();
+
+/// Tells the leader to try again to send the result of the order it gave up sending, or every one if no order is given.
+/// It answers how many results it took back
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct ReplayDeadLetters {
+    pub order_id: Option<String>,
+}
+
+/// Tells the leader to write the consumption report to the file of the config, it answers the report written
+#[derive(Message)]
+#[rtype(result = "Result<ConsumptionReport, String>")]
+pub struct WriteConsumptionReport();
+
+/// A screen handed an order to the customer, `waited_ms` after it was ready
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrderPickedUp {
+    pub screen_id: usize,
+    pub order_id: String,
+    pub waited_ms: u64,
+}
+
+/// A screen asks which flavors of the catalog have stock
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueryMenu {
+    pub screen_id: usize,
+}
+
+/// Tells the screen which flavors of the catalog have stock
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendMenu {
+    pub in_stock: Vec<FlavorID>,
+}
+
+/// Tells the screen the grams left of each flavor of the catalog
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendStockSnapshot {
+    pub grams: Vec<(FlavorID, usize)>,
+}
+
+/// A screen asks how many orders are ahead of one of its orders
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueryOrderStatus {
+    pub screen_id: usize,
+    pub order_id: String,
+}
+
+/// Tells the screen how many orders are ahead of its order, None if the leader does not have it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendQueueStatus {
+    pub order_id: String,
+    pub orders_ahead: Option<usize>,
+    pub queued: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "RobotStatus")]
+pub struct GetRobotStatus();
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct GetOrderIds();
+
+#[derive(Message)]
+#[rtype(result = "Vec<Holding>")]
+pub struct GetHoldings();
+
+/// Asks for the trace of the tokens, it is empty if the robot is not the leader's
+#[derive(Message)]
+#[rtype(result = "Vec<TokenTrace>")]
+pub struct GetTokenTrace();
+
+/// Aborts an order in progress, the result says if it was aborted
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct AbortOrder {
+    pub order_id: String,
+}
+
+/// Drops an order that the leader aborted since it passed its deadline, nothing is answered for it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CancelOrder {
+    pub order_id: String,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -293,6 +696,14 @@ pub struct StartTokens {
     pub all_tokens: Vec<FlavorToken>,
 }
 
+/// Tokens the connection with a robot could not start at it, the leader starts them at its own robot
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TokensNotStarted {
+    pub robot_id: usize,
+    pub all_tokens: Vec<FlavorToken>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct AddPreviousRobot {
@@ -311,10 +722,11 @@ pub struct AddNextRobot {
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SetRobotConnectionHandler {
-    pub rch_address: Addr<RobotConnectionHandler>,
-}
+pub struct Harakiri();
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Harakiri();
+pub struct RestockFlavor {
+    pub flavor: FlavorID,
+    pub amount: usize,
+}
@@ -1,17 +1,36 @@
 //! This module contains the robot logic.
 //! The robot is the main component of the system, it is responsible for managing the orders and the connections with the other robots.
 
+pub mod accounting;
+pub mod admin;
+pub mod backup_store;
+pub mod cluster_state;
 pub mod connections;
+pub mod consumption_report;
+pub mod control_lane;
+pub mod dead_letters;
 pub mod errors;
-pub mod flavor_token;
-pub mod leader_backup;
+pub mod in_flight;
 pub mod leader_elector;
+pub mod maintenance;
 pub mod messages;
-pub mod order_info;
+pub mod order_in_progress;
+pub mod order_journal;
 pub mod order_manager;
 pub mod order_preparer;
-pub mod order_waiting;
+pub mod queue_status;
+pub mod restock;
+pub mod ring_state;
 pub mod robot_connection_handler;
 pub mod robot_leader;
-pub mod token_backup;
+pub mod scheduler;
+pub mod screen_sequences;
+pub mod screen_stats;
+pub mod shard_routes;
+pub mod shop_book;
+pub mod stock_watch;
+pub mod token_balancer;
+pub mod token_epochs;
+pub mod token_placement;
+pub mod token_trace;
 pub mod utils;
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::log;
+use crate::common::order::Substitution;
+use crate::protocol::flavor_token::TokenKey;
+
+/// What is left of an order in progress, it is kept on disk so a robot that restarts can resume the order
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OrderProgress {
+    pub order_id: String,
+    pub trace_id: String,
+    pub flavors_needed: Vec<(FlavorID, usize)>,
+    pub substitutes: Vec<(FlavorID, Vec<FlavorID>)>,
+    pub substitutions: Vec<Substitution>,
+    pub scoops_failed: u32,
+}
+
+/// An order that the OrderManager is preparing
+/// It keeps the flavors that are still needed, the token where each one is reserved, how many of its scoops failed
+/// and the channel of its own lost token timer.
+/// A flavor that runs out is replaced by its next substitute, if it has any left
+/// The trace id of the order is written in the lines logged about it
+pub struct OrderInProgress {
+    pub order_id: String,
+    pub trace_id: String,
+    pub flavors_needed: Vec<(FlavorID, usize)>,
+    reserved: HashMap<FlavorID, TokenKey>,
+    substitutes: Vec<(FlavorID, Vec<FlavorID>)>,
+    substitutions: Vec<Substitution>,
+    scoops_failed: u32,
+    timer: Option<mpsc::Sender<usize>>,
+}
+
+impl OrderInProgress {
+    pub fn new(
+        order_id: String,
+        flavors_needed: Vec<(FlavorID, usize)>,
+        substitutes: Vec<(FlavorID, Vec<FlavorID>)>,
+        timer: mpsc::Sender<usize>,
+    ) -> Self {
+        Self {
+            order_id,
+            trace_id: String::new(),
+            flavors_needed,
+            reserved: HashMap::new(),
+            substitutes,
+            substitutions: vec![],
+            scoops_failed: 0,
+            timer: Some(timer),
+        }
+    }
+
+    /// Resumes an order from its progress, the flavors it has to reserve again
+    pub fn resume(progress: OrderProgress, timer: mpsc::Sender<usize>) -> Self {
+        Self {
+            order_id: progress.order_id,
+            trace_id: progress.trace_id,
+            flavors_needed: progress.flavors_needed,
+            reserved: HashMap::new(),
+            substitutes: progress.substitutes,
+            substitutions: progress.substitutions,
+            scoops_failed: progress.scoops_failed,
+            timer: Some(timer),
+        }
+    }
+
+    /// Returns what is left of the order
+    pub fn progress(&self) -> OrderProgress {
+        OrderProgress {
+            order_id: self.order_id.clone(),
+            trace_id: self.trace_id.clone(),
+            flavors_needed: self.flavors_needed.clone(),
+            substitutes: self.substitutes.clone(),
+            substitutions: self.substitutions.clone(),
+            scoops_failed: self.scoops_failed,
+        }
+    }
+
+    /// Sets the trace id of the order
+    pub fn with_trace_id(mut self, trace_id: String) -> Self {
+        self.trace_id = trace_id;
+        self
+    }
+
+    /// Returns the amount of the flavor that the order still needs, if any
+    pub fn amount_needed(&self, flavor: &FlavorID) -> Option<usize> {
+        self.flavors_needed
+            .iter()
+            .find(|(id, _)| id == flavor)
+            .map(|(_, amount)| *amount)
+    }
+
+    /// Removes the flavor from the ones needed, since it is being scooped
+    pub fn remove_flavor(&mut self, flavor: &FlavorID) {
+        self.flavors_needed.retain(|(id, _)| id != flavor);
+        self.reserved.remove(flavor);
+    }
+
+    /// Records that the flavor is reserved in the token
+    pub fn reserve(&mut self, flavor: FlavorID, key: TokenKey) {
+        self.reserved.insert(flavor, key);
+    }
+
+    /// Forgets the reservation of the flavor, it has to be reserved again
+    pub fn unreserve(&mut self, flavor: &FlavorID) {
+        self.reserved.remove(flavor);
+    }
+
+    /// Returns the token where the flavor is reserved, if it is
+    pub fn reservation(&self, flavor: &FlavorID) -> Option<&TokenKey> {
+        self.reserved.get(flavor)
+    }
+
+    /// Returns true if every flavor needed, but the given one, is reserved
+    pub fn others_reserved(&self, flavor: &FlavorID) -> bool {
+        self.flavors_needed
+            .iter()
+            .all(|(id, _)| id == flavor || self.reserved.contains_key(id))
+    }
+
+    /// Needs the flavor again since its scoop failed, returns how many scoops of the order failed
+    pub fn scoop_failed(&mut self, flavor: FlavorID, amount: usize) -> u32 {
+        self.reserved.remove(&flavor);
+        self.flavors_needed.push((flavor, amount));
+        self.scoops_failed += 1;
+        self.scoops_failed
+    }
+
+    /// Replaces the flavor, that ran out, with its first substitute that the order does not need already.
+    /// The substitute takes the place of the flavor, with the substitutes that were after it.
+    /// Returns the substitute, or None if the flavor has none left and the order has to be aborted
+    pub fn substitute(&mut self, flavor: &FlavorID) -> Option<FlavorID> {
+        let i = self.substitutes.iter().position(|(id, _)| id == flavor)?;
+        let (_, mut left) = self.substitutes.remove(i);
+        while !left.is_empty() {
+            let substitute = left.remove(0);
+            if self.amount_needed(&substitute).is_some() {
+                continue;
+            }
+            for (id, _) in self
+                .flavors_needed
+                .iter_mut()
+                .filter(|(id, _)| id == flavor)
+            {
+                *id = substitute.clone();
+            }
+            self.substitutes.push((substitute.clone(), left));
+            let original = self
+                .substitutions
+                .iter()
+                .position(|s| s.substitute == *flavor)
+                .map(|i| self.substitutions.remove(i).flavor)
+                .unwrap_or_else(|| flavor.clone());
+            self.substitutions.push(Substitution {
+                flavor: original,
+                substitute: substitute.clone(),
+            });
+            return Some(substitute);
+        }
+        None
+    }
+
+    /// Returns the flavors that were replaced by a substitute, with the one that was finally served
+    pub fn substitutions(&self) -> Vec<Substitution> {
+        self.substitutions.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.flavors_needed.is_empty()
+    }
+
+    /// Replaces the timer of the order, used when the previous one went off
+    pub fn set_timer(&mut self, timer: mpsc::Sender<usize>) {
+        self.timer = Some(timer);
+    }
+
+    /// Updates the timer, since the order received a token it was expecting
+    pub fn update_timer(&self) {
+        if let Some(timer) = &self.timer {
+            if let Err(e) = timer.try_send(0) {
+                log::send_error("OM", "Channel Communication Update", &e.to_string());
+            }
+        }
+    }
+
+    /// Ends the timer, the order is done or aborted
+    pub fn end_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.try_send(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_is_finished_when_all_flavors_are_removed() {
+        let (sender, _receiver) = mpsc::channel(10);
+        let mut order = OrderInProgress::new(
+            "1".to_string(),
+            vec![(FlavorID::Mint, 500), (FlavorID::Lemon, 500)],
+            vec![],
+            sender,
+        );
+        assert_eq!(order.amount_needed(&FlavorID::Lemon), Some(500));
+        assert_eq!(order.amount_needed(&FlavorID::Chocolate), None);
+
+        order.remove_flavor(&FlavorID::Mint);
+        assert!(!order.is_finished());
+        order.remove_flavor(&FlavorID::Lemon);
+        assert!(order.is_finished());
+    }
+
+    #[test]
+    fn test_flavor_is_scooped_once_the_others_are_reserved() {
+        let (sender, _receiver) = mpsc::channel(10);
+        let mut order = OrderInProgress::new(
+            "1".to_string(),
+            vec![(FlavorID::Mint, 125), (FlavorID::Lemon, 125)],
+            vec![],
+            sender,
+        );
+        assert!(!order.others_reserved(&FlavorID::Lemon));
+        order.reserve(FlavorID::Mint, (FlavorID::Mint, 0));
+        assert!(order.others_reserved(&FlavorID::Lemon));
+        assert_eq!(
+            order.reservation(&FlavorID::Mint),
+            Some(&(FlavorID::Mint, 0))
+        );
+
+        order.remove_flavor(&FlavorID::Mint);
+        assert_eq!(order.reservation(&FlavorID::Mint), None);
+    }
+
+    #[test]
+    fn test_flavor_is_replaced_by_its_substitutes_in_order() {
+        let (sender, _receiver) = mpsc::channel(10);
+        let mut order = OrderInProgress::new(
+            "1".to_string(),
+            vec![(FlavorID::Mint, 125), (FlavorID::Lemon, 125)],
+            vec![(
+                FlavorID::Mint,
+                vec![FlavorID::Lemon, FlavorID::Chocolate, FlavorID::Vanilla],
+            )],
+            sender,
+        );
+        assert_eq!(order.substitute(&FlavorID::Lemon), None);
+        assert_eq!(order.substitute(&FlavorID::Mint), Some(FlavorID::Chocolate));
+        assert_eq!(order.amount_needed(&FlavorID::Chocolate), Some(125));
+        assert_eq!(
+            order.substitute(&FlavorID::Chocolate),
+            Some(FlavorID::Vanilla)
+        );
+        assert_eq!(
+            order.substitutions(),
+            vec![Substitution {
+                flavor: FlavorID::Mint,
+                substitute: FlavorID::Vanilla
+            }]
+        );
+        assert_eq!(order.substitute(&FlavorID::Vanilla), None);
+    }
+}
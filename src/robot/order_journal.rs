@@ -0,0 +1,229 @@
+//! Append-only journal of the orders handled by the leaders, one JSON entry per line.
+//! Every leader appends to the same file, so after a failover the new leader can replay it
+//! and find the orders that were created but are missing from the backup it received.
+
+use crate::common::log;
+use crate::common::persistence_writer;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::flavor_id::FlavorID;
+use crate::config;
+use crate::protocol::order_info::OrderInfo;
+use crate::robot::errors::OrderJournalError;
+
+const JOURNAL_FILE: &str = "order_journal.jsonl";
+
+/// Something that happened to an order
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum JournalEvent {
+    Created {
+        order: OrderInfo,
+    },
+    Completed {
+        order_id: String,
+        robot_id: usize,
+    },
+    Aborted {
+        order_id: String,
+        robot_id: usize,
+        flavor: FlavorID,
+    },
+    /// The customer took the order from the screen, it closes the order
+    PickedUp {
+        order_id: String,
+        screen_id: usize,
+    },
+}
+
+/// A line of the journal
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct JournalEntry {
+    pub timestamp_ms: u64,
+    pub event: JournalEvent,
+}
+
+impl JournalEntry {
+    pub fn new(event: JournalEvent) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            event,
+        }
+    }
+}
+
+/// Returns the path of the journal inside the data directory
+pub fn journal_path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(JOURNAL_FILE)
+}
+
+/// Appends an entry at the end of the journal
+pub fn append(data_dir: &str, entry: &JournalEntry) -> Result<(), OrderJournalError> {
+    let line =
+        serde_json::to_string(entry).map_err(|e| OrderJournalError::ErrorParsing(e.to_string()))?;
+    let fsync = config::get().persistence.fsync;
+    persistence_writer::append_lines(&journal_path(data_dir), &[line], fsync)
+        .map_err(|e| OrderJournalError::CouldNotWrite(e.to_string()))
+}
+
+/// Reads every entry of the journal, in the order they were written.
+/// A line that can not be parsed, like one cut by a crash while it was written, is skipped
+pub fn replay(data_dir: &str) -> Result<Vec<JournalEntry>, OrderJournalError> {
+    let content = match fs::read_to_string(journal_path(data_dir)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(OrderJournalError::CouldNotRead(e.to_string())),
+    };
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn("OJ", format!("Skipping journal line: {}", e)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the orders that were created but never completed, aborted nor picked up, in the order they were created
+pub fn unfinished_orders(entries: &[JournalEntry]) -> Vec<OrderInfo> {
+    let mut created: Vec<OrderInfo> = Vec::new();
+    let mut finished: HashSet<&str> = HashSet::new();
+    for entry in entries {
+        match &entry.event {
+            JournalEvent::Created { order } => created.push(order.clone()),
+            JournalEvent::Completed { order_id, .. }
+            | JournalEvent::Aborted { order_id, .. }
+            | JournalEvent::PickedUp { order_id, .. } => {
+                finished.insert(order_id);
+            }
+        }
+    }
+
+    let mut seen: HashSet<String> = HashSet::new();
+    created
+        .into_iter()
+        .filter(|order| !finished.contains(order.order_id.as_str()))
+        .filter(|order| seen.insert(order.order_id.clone()))
+        .collect()
+}
+
+/// Returns the unfinished orders of the journal that are not in the known ones, these were lost during a failover
+pub fn lost_orders(entries: &[JournalEntry], known: &HashSet<String>) -> Vec<OrderInfo> {
+    unfinished_orders(entries)
+        .into_iter()
+        .filter(|order| !known.contains(&order.order_id))
+        .collect()
+}
+
+/// Appends the event to the journal if persistence is enabled in the config.
+/// It is written by the persistence writer of the process, so the caller does not wait for the disk
+pub fn record(event: JournalEvent) {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return;
+    }
+    match serde_json::to_string(&JournalEntry::new(event)) {
+        Ok(line) => persistence_writer::append(journal_path(&persistence.data_dir), line),
+        Err(e) => log::error("OJ", format!("Error! {}", e)),
+    }
+}
+
+/// Replays the journal if persistence is enabled in the config and returns the orders lost during a failover
+pub fn recover_lost_orders(known: &HashSet<String>) -> Vec<OrderInfo> {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled {
+        return Vec::new();
+    }
+    match replay(&persistence.data_dir) {
+        Ok(entries) => lost_orders(&entries, known),
+        Err(e) => {
+            log::error("OJ", format!("Error! {}", e));
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::order::Order;
+    use std::io::Write;
+
+    fn test_dir(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "freddo_order_journal_{}_{}",
+                name,
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn created(order_id: &str) -> JournalEntry {
+        JournalEntry::new(JournalEvent::Created {
+            order: OrderInfo {
+                order: Order::new_cucurucho(FlavorID::Mint),
+                order_id: order_id.to_string(),
+                screen_id: 0,
+                deadline_ms: None,
+                source: None,
+            },
+        })
+    }
+
+    #[test]
+    fn test_appended_entries_are_replayed_in_order() {
+        let dir = test_dir("replay");
+        let _ = fs::remove_dir_all(&dir);
+        let entries = vec![
+            created("1"),
+            JournalEntry::new(JournalEvent::Completed {
+                order_id: "1".to_string(),
+                robot_id: 2,
+            }),
+        ];
+        for entry in &entries {
+            append(&dir, entry).unwrap();
+        }
+        fs::OpenOptions::new()
+            .append(true)
+            .open(journal_path(&dir))
+            .unwrap()
+            .write_all(b"{\"timestamp_ms\": 1, \"ev")
+            .unwrap();
+
+        assert_eq!(replay(&dir).unwrap(), entries);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_lost_orders_are_unfinished_and_unknown() {
+        let entries = vec![
+            created("1"),
+            created("2"),
+            created("3"),
+            JournalEntry::new(JournalEvent::Aborted {
+                order_id: "1".to_string(),
+                robot_id: 0,
+                flavor: FlavorID::Mint,
+            }),
+        ];
+        let known: HashSet<String> = ["2".to_string()].into_iter().collect();
+
+        let lost: Vec<String> = lost_orders(&entries, &known)
+            .into_iter()
+            .map(|o| o.order_id)
+            .collect();
+        assert_eq!(lost, vec!["3".to_string()]);
+    }
+}
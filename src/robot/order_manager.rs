@@ -3,255 +3,727 @@ use actix::Message;
 use actix::{Actor, Addr, AsyncContext, Context, Handler};
 use actix::{ContextFutureSpawner, WrapFuture};
 use colored::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{self};
+use tokio::time::Instant;
 
 use crate::common::flavor_id::FlavorID;
-use crate::robot::flavor_token::FlavorToken;
+use crate::common::log::{self, Level};
+use crate::common::mailbox::{self, Probe};
+use crate::common::metrics;
+use crate::common::order::Substitution;
+use crate::common::registry::{self, Registry};
+use crate::protocol::flavor_token::{shard_amount, FlavorToken, TokenKey};
+use crate::protocol::robot_messages::AbortReason;
+use crate::protocol::token_backup::TokenBackup;
+use crate::robot::admin::Holding;
+use crate::robot::in_flight;
 use crate::robot::messages::{
-    GetNewOrder, GetTokenBack, GetTokenBackup, OrderAborted, OrderPrepared, ScoopFlavor,
-    SendTokenBackup, SetRobotConnectionHandler, TransferToken,
+    GetNewOrder, GetOrdersInFlight, GetTokenBack, GetTokenBackup, OrderAborted, OrderPrepared,
+    ResumeOrders, ScoopFailed, ScoopFlavor, SendTokenBackup, TransferToken,
 };
+use crate::robot::order_in_progress::{OrderInProgress, OrderProgress};
 use crate::robot::order_preparer::OrderPreparer;
 use crate::robot::robot_connection_handler::RobotConnectionHandler;
-use crate::robot::token_backup::TokenBackup;
-use crate::robot::utils::{print_send_error, token_lost_timeout};
+use crate::robot::utils::{token_lost_timeout, token_timeout};
 
-use super::messages::{AbortCurrentOrder, TimerWentOff};
-use super::utils::INITIAL_AMOUNT;
+use super::messages::{
+    AbortCurrentOrders, AbortOrder, CancelOrder, Defrost, GetHoldings, GetOrderIds,
+    GetOrdersInProgress, TimerWentOff,
+};
+use crate::config::{self, flavors::DEFAULT_INITIAL_AMOUNT};
 
-/// Actor that manages the order, it receives the order from the RCH and sends the tokens needed to the OrderPreparer
-/// If it receives a token, it checks if it can serve the order, and sends it to the OrderPreparer if it can, if not, it sends it back to the RCH
-/// It also sends the tokens back to the RCH when the order is ready or aborted
-/// When the timer goes off, it is alerted of one or more lost tokens, and starts the recovery process
+/// Actor that manages the orders of the robot, it receives the orders from the RCH and sends the tokens needed to the OrderPreparer
+/// It can prepare up to a few orders at the same time, each one with the flavors it still needs and its own timer,
+/// so a token that one order is waiting for does not stop the others from progressing
+/// If it receives a token, it checks which orders need it, and sends it to the OrderPreparer to serve all of them, if none does, it sends it back to the RCH
+/// It also sends the tokens back to the RCH when the scoops are served
+/// If a scoop fails, the orders need the flavor again and wait for the next token, unless they failed too many times
+/// When the timer of an order goes off, it is alerted of one or more lost tokens, and starts the recovery process
+/// While the robot defrosts it scoops nothing, the tokens are sent back to the RCH as soon as they are seen
+/// The time it waits for a token before taking it for lost follows the robots the tokens go through in a round of the ring
+/// It remembers the last orders it completed, so an order a new leader assigns again after the result was lost is answered right away
+/// The orders in progress can be kept on disk, a robot that restarts holds the ones it finds there until the leader
+/// says which ones it can resume, and resumes an order the leader assigns to it again with the flavors it still needed
 pub struct OrderManager {
-    flavors_needed: Vec<(FlavorID, usize)>,
-    order_id: String,
-    scooping: bool,
-    aborted: bool,
+    orders: Vec<OrderInProgress>,
+    scooping: Option<Vec<(String, usize)>>,
+    recovering: HashSet<TokenKey>,
     order_preparer: Addr<OrderPreparer>,
-    robot_connection_handler: Option<Addr<RobotConnectionHandler>>,
-    tokens_backup: HashMap<FlavorID, FlavorToken>,
-    sender: Option<mpsc::Sender<usize>>,
+    registry: Registry,
+    tokens_backup: HashMap<TokenKey, FlavorToken>,
+    tokens_seen_at: HashMap<TokenKey, Instant>,
+    defrosting_until: Option<Instant>,
+    ring: Option<Vec<usize>>,
+    token_timeout_ms: Arc<AtomicU64>,
     rch_id: usize,
+    completed: VecDeque<(String, Vec<Substitution>)>,
+    restored: Vec<OrderProgress>,
+    persist: bool,
 }
 
 impl Actor for OrderManager {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        mailbox::watch("order_manager", ctx);
+    }
+}
+
+impl Handler<Probe> for OrderManager {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Probe, _ctx: &mut Self::Context) -> Self::Result {}
 }
 
 impl OrderManager {
     pub fn new(order_preparer: Addr<OrderPreparer>, rch_id: usize) -> Self {
         Self {
-            flavors_needed: vec![],
-            order_id: String::new(),
-            scooping: false,
-            aborted: false,
+            orders: vec![],
+            scooping: None,
+            recovering: HashSet::new(),
             order_preparer,
-            robot_connection_handler: None,
+            registry: Registry::new(),
             tokens_backup: HashMap::new(),
-            sender: None,
+            tokens_seen_at: HashMap::new(),
+            defrosting_until: None,
+            ring: None,
+            token_timeout_ms: Arc::new(AtomicU64::new(
+                token_timeout(rch_id, None).as_millis() as u64
+            )),
             rch_id,
+            completed: VecDeque::new(),
+            restored: vec![],
+            persist: false,
+        }
+    }
+
+    /// Keeps the orders in progress on disk, and restores the ones of the last run of the robot
+    pub fn with_orders_in_flight(self) -> Self {
+        let restored = in_flight::restore_in_flight(self.rch_id);
+        if !restored.is_empty() {
+            let ids: Vec<&str> = restored.iter().map(|o| o.order_id.as_str()).collect();
+            let line = format!("Found orders {:?} in progress before the restart", ids);
+            log::info("OM", line.blue());
+        }
+        let mut manager = self.with_restored_orders(restored);
+        manager.persist = true;
+        manager
+    }
+
+    /// Holds the orders of a previous run until the leader says which ones the robot resumes
+    pub fn with_restored_orders(mut self, restored: Vec<OrderProgress>) -> Self {
+        self.restored = restored;
+        self
+    }
+
+    /// Stores the orders in progress, and the restored ones, on disk.
+    /// Nothing is stored while a token is being scooped, the flavors served are stored once it comes back
+    fn save_in_flight(&self) {
+        if !self.persist || self.scooping.is_some() {
+            return;
         }
+        let orders: Vec<OrderProgress> = self
+            .orders
+            .iter()
+            .map(|o| o.progress())
+            .chain(self.restored.iter().cloned())
+            .collect();
+        in_flight::persist_in_flight(self.rch_id, &orders);
+    }
+
+    /// Takes the restored order with the id, if there is one
+    fn take_restored(&mut self, order_id: &str) -> Option<OrderProgress> {
+        let i = self.restored.iter().position(|o| o.order_id == order_id)?;
+        Some(self.restored.remove(i))
+    }
+
+    /// Goes on preparing a restored order with the flavors it still needed
+    fn resume_order(&mut self, progress: OrderProgress, ctx: &mut Context<Self>) {
+        let line = format!(
+            "Resuming order {} with {:?} left",
+            progress.order_id, progress.flavors_needed
+        );
+        log::traced(Level::Info, "OM", &progress.trace_id, line.purple());
+        let order_id = progress.order_id.clone();
+        let timer = self.start_timer(order_id.clone(), ctx);
+        let order = OrderInProgress::resume(progress, timer);
+        let finished = order.is_finished();
+        self.orders.push(order);
+        if finished {
+            self.send_order_prepared(order_id, true);
+        }
+    }
+
+    /// Finds the RCH and the other actors of the robot in the registry
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Returns the RCH of the robot, if it is registered
+    fn rch(&self) -> Option<Addr<RobotConnectionHandler>> {
+        self.registry.addr(registry::RCH)
     }
 
     /// Returns the token to the RCH
     fn return_token(&mut self, t: FlavorToken) {
-        self.tokens_backup.insert(t.get_id(), t);
-        match self.robot_connection_handler {
+        self.tokens_backup.insert(t.key(), t.clone());
+        match self.rch() {
             Some(ref rch) => {
                 if let Err(e) = rch.try_send(GetTokenBack { flavor_token: t }) {
-                    print_send_error("[OM]", "GetTokenBack", &e.to_string());
+                    log::send_error("OM", "GetTokenBack", &e.to_string());
                 }
             }
-            None => println!(
-                "{}",
-                "[OM] Error: There is not a RCH to give the token to".red()
-            ),
+            None => log::error("OM", "Error: There is not a RCH to give the token to".red()),
+        }
+    }
+
+    /// Updates the time to wait for a token with the robots it went through since it left the robot,
+    /// so a small ring recovers its tokens sooner than the whole cluster of the config would
+    fn track_ring(&mut self, token: &FlavorToken) {
+        let ring = match token.round_of(self.rch_id) {
+            Some(mut ring) => {
+                ring.sort();
+                ring.dedup();
+                ring
+            }
+            None => return,
+        };
+        if self.ring.as_ref() == Some(&ring) {
+            return;
+        }
+        let timeout = token_timeout(self.rch_id, Some(&ring));
+        let line = format!(
+            "The ring has {} other robots, waiting {} ms for a token before taking it for lost",
+            ring.len(),
+            timeout.as_millis()
+        );
+        log::info("OM", line.blue());
+        self.token_timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+        self.ring = Some(ring);
+    }
+
+    /// Returns the current time to wait for a token before taking it for lost
+    fn token_timeout(&self) -> Duration {
+        Duration::from_millis(self.token_timeout_ms.load(Ordering::Relaxed))
+    }
+
+    /// Returns true while the robot is defrosting
+    fn defrosting(&self) -> bool {
+        self.defrosting_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Returns the trace id of the order in progress, empty if it has none
+    fn trace_of(&self, order_id: &str) -> String {
+        self.orders
+            .iter()
+            .find(|o| o.order_id == order_id)
+            .map(|o| o.trace_id.clone())
+            .unwrap_or_default()
+    }
+
+    /// Removes the order from the ones in progress and ends its timer
+    fn remove_order(&mut self, order_id: &str) {
+        if let Some(i) = self.orders.iter().position(|o| o.order_id == order_id) {
+            self.orders.remove(i).end_timer();
+        }
+    }
+
+    /// Sends the order prepared message to the RCH, with the flavors that were replaced by a substitute
+    fn send_order_prepared(&mut self, order_id: String, result: bool) {
+        let substitutions = self
+            .orders
+            .iter()
+            .find(|o| o.order_id == order_id)
+            .map(|o| o.substitutions())
+            .unwrap_or_default();
+        let trace_id = self.trace_of(&order_id);
+        self.remove_order(&order_id);
+        metrics::get().order_completed();
+        let line = format!("Order {} prepared successfully!", order_id);
+        log::traced(
+            Level::Info,
+            "OM",
+            &trace_id,
+            line.black().on_bright_yellow(),
+        );
+        self.remember_completed(&order_id, &substitutions);
+        self.send_prepared_to_rch(order_id, result, substitutions);
+    }
+
+    /// Keeps the order among the last `orders.completed_cache` completed ones
+    fn remember_completed(&mut self, order_id: &str, substitutions: &[Substitution]) {
+        let capacity = config::get().orders.completed_cache;
+        if capacity == 0 {
+            return;
+        }
+        if self.completed.len() >= capacity {
+            self.completed.pop_front();
         }
+        self.completed
+            .push_back((order_id.to_string(), substitutions.to_vec()));
     }
 
-    /// Sends the order prepared message to the RCH
-    fn send_order_prepared(&mut self, result: bool) {
-        self.end_timer();
-        let line = format!("[OM] Order {} prepared successfully!", self.order_id);
-        println!("{}", line.black().on_bright_yellow());
+    /// Returns the substitutions of the order if it is one of the last completed ones
+    fn completed_substitutions(&self, order_id: &str) -> Option<Vec<Substitution>> {
+        self.completed
+            .iter()
+            .find(|(id, _)| id == order_id)
+            .map(|(_, substitutions)| substitutions.clone())
+    }
 
-        match self.robot_connection_handler {
+    /// Sends the result of a prepared order to the RCH
+    fn send_prepared_to_rch(
+        &mut self,
+        order_id: String,
+        result: bool,
+        substitutions: Vec<Substitution>,
+    ) {
+        match self.rch() {
             Some(ref rch) => {
                 if let Err(e) = rch.try_send(OrderPrepared {
                     order_result: result,
-                    id: self.order_id.clone(),
+                    id: order_id,
+                    substitutions,
                 }) {
-                    print_send_error("[OM]", "OrderPrepared", &e.to_string());
+                    log::send_error("OM", "OrderPrepared", &e.to_string());
                 }
             }
-            None => println!(
-                "{}",
-                "[OM] Error: There is not a RCH to give order to".red()
-            ),
+            None => log::error("OM", "Error: There is not a RCH to give order to".red()),
         }
     }
 
     /// Sends the order aborted message to the RCH
-    fn send_order_aborted(&mut self, result: bool, flavor_id: FlavorID) {
-        self.end_timer();
-        let line = format!("[OM] Order {} aborted!", self.order_id);
-        println!("{}", line.on_bright_red().black());
+    fn send_order_aborted(
+        &mut self,
+        order_id: String,
+        result: bool,
+        flavor_id: FlavorID,
+        reason: AbortReason,
+    ) {
+        let trace_id = self.trace_of(&order_id);
+        self.remove_order(&order_id);
+        metrics::get().order_aborted();
+        let line = format!("Order {} aborted! ({:?})", order_id, reason);
+        log::traced(Level::Warn, "OM", &trace_id, line.on_bright_red().black());
 
-        match self.robot_connection_handler {
+        match self.rch() {
             Some(ref rch) => {
                 if let Err(e) = rch.try_send(OrderAborted {
                     order_result: result,
-                    id: self.order_id.clone(),
+                    id: order_id,
                     flavor: flavor_id,
+                    reason,
                 }) {
-                    print_send_error("[OM]", "OrderAborted", &e.to_string());
+                    log::send_error("OM", "OrderAborted", &e.to_string());
                 }
             }
-            None => println!(
-                "{}",
-                "[OM] Error: There is not a RCH to give order to".red()
-            ),
-        }
-    }
-
-    /// Ends the timer
-    fn end_timer(&mut self) {
-        if let Some(send_ref) = &self.sender {
-            if send_ref.clone().try_send(1).is_err() {
-                // println!("[OM] Error: Could not send message to timer");
-            }
+            None => log::error("OM", "Error: There is not a RCH to give order to".red()),
         }
     }
 
-    /// Updates the timer, since the OM received a token it was expecting
-    fn update_timer(&mut self) {
-        if let Some(send_ref) = &self.sender {
-            if let Err(e) = send_ref.clone().try_send(0) {
-                print_send_error("[OM]", "Channel Communication Update", &e.to_string());
-            }
-        }
-    }
+    /// Starts the lost token timer of an order
+    fn start_timer(&mut self, order_id: String, ctx: &mut Context<Self>) -> mpsc::Sender<usize> {
+        let (sndr, receiver) = mpsc::channel::<usize>(10);
+        let addr = ctx.address();
+        let timeout_ms = self.token_timeout_ms.clone();
 
-    /// Gets the index of the flavor in the flavors_needed vector
-    fn get_flavor_index(&mut self, token: FlavorToken) -> isize {
-        for (i, (id, _)) in self.flavors_needed.iter().enumerate() {
-            if *id == token.get_id() {
-                return i as isize;
-            }
-        }
-        -1
+        async move { token_lost_timeout(receiver, addr, order_id, timeout_ms).await }
+            .into_actor(self)
+            .spawn(ctx);
+        sndr
     }
 
-    /// Checks if the flavor is needed in the order, and if it can serve the amount needed
-    fn check_needed(&mut self, token: FlavorToken) -> usize {
-        if self.scooping {
+    /// Checks which orders need the flavor, and returns the amount needed to serve all of them
+    /// An order first reserves in the tokens the amount it needs of each flavor, and scoops them once all of them are reserved,
+    /// so an order aborted because a flavor ran out did not waste the scoops of the others. The flavor that completes
+    /// the reservations of an order is scooped right away.
+    /// The orders are served in the order they arrived, the ones that need more than what is left in the token are aborted,
+    /// unless another token of the flavor was last seen with enough to serve them, in that case they wait for it,
+    /// or the flavor has a substitute left, in that case they wait for a token of the substitute.
+    /// The reservations of the robot for orders it does not have anymore are released, as the ones that expired
+    fn check_needed(&mut self, token: &mut FlavorToken) -> usize {
+        if self.scooping.is_some() {
             return 0;
         }
 
-        let i = self.get_flavor_index(token);
-
-        if i == -1 {
-            return 0;
-        }
+        let flavor = token.get_id();
+        let key = token.key();
+        let robot_id = self.rch_id;
+        let orders = &self.orders;
+        token.release_where(|r| {
+            r.robot_id == robot_id
+                && !orders
+                    .iter()
+                    .any(|o| o.order_id == r.order_id && o.reservation(&flavor) == Some(&key))
+        });
+        token.release_expired(config::get().tokens.reservation_timeout_ms);
 
-        let amount_needed = self.flavors_needed[i as usize];
+        let tokens_backup = &self.tokens_backup;
+        let other_token_can_serve = |amount: usize| {
+            tokens_backup.values().any(|other| {
+                other.get_id() == flavor && other.key() != key && other.can_serve(amount)
+            })
+        };
+        let mut total = 0;
+        let mut served = vec![];
+        let mut aborted = vec![];
 
-        if !token.can_serve(amount_needed.1) {
-            let line = format!("[OM] Not enough flavor left in {}!", token.get_id());
-            println!("{}", line.blue());
-            self.flavors_needed.clear();
-            self.send_order_aborted(false, token.get_id());
-            return 0;
+        for order in self.orders.iter_mut() {
+            let amount = match order.amount_needed(&flavor) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            if let Some(reserved_in) = order.reservation(&flavor) {
+                if *reserved_in != key || !order.others_reserved(&flavor) {
+                    continue;
+                }
+                token.release(robot_id, &order.order_id);
+                order.unreserve(&flavor);
+            }
+            if !token.can_serve(total + amount) {
+                if other_token_can_serve(amount) {
+                    continue;
+                }
+                match order.substitute(&flavor) {
+                    Some(substitute) => {
+                        let line = format!(
+                            "Not enough {} for order {}, trying with {}",
+                            flavor, order.order_id, substitute
+                        );
+                        log::traced(Level::Info, "OM", &order.trace_id, line.blue());
+                        order.update_timer();
+                    }
+                    None => aborted.push(order.order_id.clone()),
+                }
+                continue;
+            }
+            order.update_timer();
+            if !order.others_reserved(&flavor) {
+                token.reserve(robot_id, &order.order_id, amount);
+                order.reserve(flavor.clone(), key.clone());
+                continue;
+            }
+            order.remove_flavor(&flavor);
+            let line = format!(
+                "Scooping {} grams of {} for order {}",
+                amount, flavor, order.order_id
+            );
+            log::traced(Level::Info, "OM", &order.trace_id, line.blue());
+            total += amount;
+            served.push((order.order_id.clone(), amount));
         }
 
-        self.scooping = true;
-        self.flavors_needed.remove(i as usize);
+        for order_id in aborted {
+            let line = format!("Not enough flavor left in {}!", flavor);
+            log::info("OM", line.blue());
+            self.send_order_aborted(order_id, false, flavor.clone(), AbortReason::OutOfStock);
+        }
 
-        amount_needed.1
+        if !served.is_empty() {
+            self.scooping = Some(served);
+        }
+        total
     }
 }
 
-/// Handles the TransferToken message, it receives a token from the RCH and checks if it can serve the order
-/// If it can, it sends the token to the OrderPreparer, if not, it sends it back to the RCH
+/// Handles the TransferToken message, it receives a token from the RCH and checks which orders need it
+/// If any does, it sends the token to the OrderPreparer, if not, it sends it back to the RCH
 impl Handler<TransferToken> for OrderManager {
     type Result = ();
     fn handle(&mut self, msg: TransferToken, _ctx: &mut Self::Context) -> Self::Result {
-        let token = msg.flavor_token;
-        self.tokens_backup.insert(token.get_id(), token);
+        let mut token = msg.flavor_token;
+        if self
+            .orders
+            .iter()
+            .any(|o| o.amount_needed(&token.get_id()).is_some())
+        {
+            token.mark_worked();
+        }
+        metrics::get().token_seen(token.get_id(), token.get_shard(), token.get_amnt());
+        self.tokens_backup.insert(token.key(), token.clone());
+        self.tokens_seen_at.insert(token.key(), Instant::now());
+        self.recovering.remove(&token.key());
+        self.track_ring(&token);
 
-        let amount_needed = self.check_needed(token);
+        if self.defrosting() {
+            // the token was seen, so the orders waiting for it do not take it for lost
+            let flavor = token.get_id();
+            for order in self.orders.iter() {
+                if order.amount_needed(&flavor).is_some() {
+                    order.update_timer();
+                }
+            }
+            self.return_token(token);
+            return;
+        }
+
+        let amount_needed = self.check_needed(&mut token);
+        self.save_in_flight();
 
         if amount_needed == 0 {
             self.return_token(token)
-        } else {
-            self.update_timer();
-            if let Err(e) = self.order_preparer.try_send(ScoopFlavor {
-                flavor_token: token,
-                amount: amount_needed,
-            }) {
-                print_send_error("[OM]", "ScoopFlavor", &e.to_string());
-            }
+        } else if let Err(e) = self.order_preparer.try_send(ScoopFlavor {
+            flavor_token: token,
+            amount: amount_needed,
+        }) {
+            log::send_error("OM", "ScoopFlavor", &e.to_string());
         }
     }
 }
 
 /// Handles the GetTokenBack message, it receives a token from the OrderPreparer and returns it to the RCH,
-/// If the order is ready, it sends the OrderPrepared message to the RCH
+/// If any of the orders that were served is ready, it sends the OrderPrepared message to the RCH
 impl Handler<GetTokenBack> for OrderManager {
     type Result = ();
     fn handle(&mut self, msg: GetTokenBack, _ctx: &mut Self::Context) -> Self::Result {
         let token = msg.flavor_token;
-        self.scooping = false;
+        let served = self.scooping.take().unwrap_or_default();
 
         self.return_token(token);
 
-        if self.flavors_needed.is_empty() && !self.aborted {
-            self.send_order_prepared(true);
+        for (order_id, _) in served {
+            let finished = self
+                .orders
+                .iter()
+                .any(|o| o.order_id == order_id && o.is_finished());
+            if finished {
+                self.send_order_prepared(order_id, true);
+            }
         }
+        self.save_in_flight();
     }
 }
 
-/// Handles the AbortCurrentOrder message, it aborts the current order
-impl Handler<AbortCurrentOrder> for OrderManager {
+/// Handles the ScoopFailed message, the token comes back from the OrderPreparer with nothing served
+/// If the arm jammed, the orders that were being served need the flavor again, the ones whose scoops failed too many times are aborted.
+/// If the token did not have the grams, the orders ran out of the flavor and are aborted
+impl Handler<ScoopFailed> for OrderManager {
     type Result = ();
+    fn handle(&mut self, msg: ScoopFailed, _ctx: &mut Self::Context) -> Self::Result {
+        let token = msg.flavor_token;
+        let flavor = token.get_id();
+        let served = self.scooping.take().unwrap_or_default();
+        let out_of_stock = msg.reason == AbortReason::OutOfStock;
+        if !out_of_stock {
+            metrics::get().scoop_failed();
+        }
+
+        self.return_token(token);
 
-    fn handle(&mut self, _msg: AbortCurrentOrder, _ctx: &mut Self::Context) -> Self::Result {
-        self.flavors_needed.clear();
-        self.scooping = false;
-        self.aborted = true;
-        self.end_timer();
+        let max_retries = config::get().scoops.max_retries;
+        let mut aborted = vec![];
+        for (order_id, amount) in served {
+            if out_of_stock {
+                aborted.push(order_id);
+            } else if let Some(order) = self.orders.iter_mut().find(|o| o.order_id == order_id) {
+                if order.scoop_failed(flavor.clone(), amount) > max_retries {
+                    aborted.push(order_id);
+                }
+            }
+        }
+        for order_id in aborted {
+            self.send_order_aborted(order_id, false, flavor.clone(), msg.reason);
+        }
+        self.save_in_flight();
     }
 }
 
-/// Handles the SetRobotConnectionHandler message, it sets the RCH address
-impl Handler<SetRobotConnectionHandler> for OrderManager {
+/// Handles the AbortCurrentOrders message, it aborts every order in progress and forgets the restored ones
+impl Handler<AbortCurrentOrders> for OrderManager {
     type Result = ();
 
-    fn handle(&mut self, msg: SetRobotConnectionHandler, _ctx: &mut Self::Context) -> Self::Result {
-        self.robot_connection_handler = Some(msg.rch_address);
+    fn handle(&mut self, _msg: AbortCurrentOrders, _ctx: &mut Self::Context) -> Self::Result {
+        for mut order in self.orders.drain(..) {
+            order.end_timer();
+        }
+        self.restored.clear();
+        self.scooping = None;
+        self.save_in_flight();
+    }
+}
+
+/// Handles the GetOrdersInProgress message, it returns how many orders are still being prepared
+impl Handler<GetOrdersInProgress> for OrderManager {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetOrdersInProgress, _ctx: &mut Self::Context) -> Self::Result {
+        self.orders.len()
+    }
+}
+
+/// Handles the GetOrderIds message, it returns the ids of the orders being prepared
+impl Handler<GetOrderIds> for OrderManager {
+    type Result = Vec<String>;
+
+    fn handle(&mut self, _msg: GetOrderIds, _ctx: &mut Self::Context) -> Self::Result {
+        self.orders.iter().map(|o| o.order_id.clone()).collect()
+    }
+}
+
+/// Handles the GetHoldings message, it returns the last amount seen of every token that went through the robot
+impl Handler<GetHoldings> for OrderManager {
+    type Result = Vec<Holding>;
+
+    fn handle(&mut self, _msg: GetHoldings, _ctx: &mut Self::Context) -> Self::Result {
+        let mut holdings: Vec<Holding> = self
+            .tokens_backup
+            .values()
+            .map(|token| Holding {
+                flavor: token.get_id(),
+                shard: token.get_shard(),
+                amount: token.get_amnt(),
+                epoch: token.get_epoch(),
+                seen_ms_ago: self
+                    .tokens_seen_at
+                    .get(&token.key())
+                    .map(|seen| seen.elapsed().as_millis() as u64)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        holdings.sort_by_key(|holding| holding.seen_ms_ago);
+        holdings
+    }
+}
+
+/// Handles the AbortOrder message, it aborts an order in progress as if its next flavor had run out
+/// An order whose flavors are all being served can not be aborted
+impl Handler<AbortOrder> for OrderManager {
+    type Result = bool;
+
+    fn handle(&mut self, msg: AbortOrder, _ctx: &mut Self::Context) -> Self::Result {
+        let flavor = self
+            .orders
+            .iter()
+            .find(|o| o.order_id == msg.order_id)
+            .and_then(|o| o.flavors_needed.first().map(|(flavor, _)| flavor.clone()));
+        match flavor {
+            Some(flavor) => {
+                self.send_order_aborted(msg.order_id, false, flavor, AbortReason::OutOfStock);
+                self.save_in_flight();
+                true
+            }
+            None => false,
+        }
     }
 }
 
-/// Handles the GetNewOrder message, it receives a new order from the RCH and starts the order process, starting the timer
+/// Handles the CancelOrder message, the order is dropped without telling the leader, that already aborted it
+/// If its flavors are being served, the scoops finish but the order is not reported as prepared
+impl Handler<CancelOrder> for OrderManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: CancelOrder, _ctx: &mut Self::Context) -> Self::Result {
+        let line = format!("Order {} passed its deadline, dropping it", msg.order_id);
+        log::warn("OM", line.yellow());
+        self.remove_order(&msg.order_id);
+        self.take_restored(&msg.order_id);
+        self.save_in_flight();
+    }
+}
+
+/// Handles the Defrost message, the robot stops scooping until the window ends
+impl Handler<Defrost> for OrderManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Defrost, _ctx: &mut Self::Context) -> Self::Result {
+        let line = format!("Defrosting for {} ms", msg.duration.as_millis());
+        log::info("OM", line.bright_blue());
+        self.defrosting_until = Some(Instant::now() + msg.duration);
+    }
+}
+
+/// Handles the GetNewOrder message, it receives a new order from the RCH and adds it to the orders in progress, starting its timer
 impl Handler<GetNewOrder> for OrderManager {
     type Result = ();
 
     fn handle(&mut self, msg: GetNewOrder, ctx: &mut Self::Context) -> Self::Result {
-        self.flavors_needed = msg.new_order.get_flavors();
-        self.order_id = msg.id;
-        let line = format!("[OM] Got a new order with {:?}", self.flavors_needed);
-        println!("{}", line.purple());
+        if self.orders.iter().any(|o| o.order_id == msg.id) {
+            let line = format!("Order {} is already in progress", msg.id);
+            log::traced(Level::Warn, "OM", &msg.trace_id, line.yellow());
+            return;
+        }
+        if let Some(substitutions) = self.completed_substitutions(&msg.id) {
+            let line = format!("Order {} was already prepared, sending its result", msg.id);
+            log::traced(Level::Warn, "OM", &msg.trace_id, line.yellow());
+            self.send_prepared_to_rch(msg.id, true, substitutions);
+            return;
+        }
+        if let Some(progress) = self.take_restored(&msg.id) {
+            self.resume_order(progress, ctx);
+            self.save_in_flight();
+            return;
+        }
+        if let Err(e) = msg.new_order.validate() {
+            let line = format!(
+                "Order {} does not fit the sizes of the config: {}",
+                msg.id, e
+            );
+            log::traced(Level::Warn, "OM", &msg.trace_id, line.yellow());
+            let flavor = match msg.new_order.get_flavors().into_iter().next() {
+                Some((flavor, _)) => flavor,
+                None => FlavorID::Chocolate,
+            };
+            self.send_order_aborted(msg.id, false, flavor, AbortReason::WrongSize);
+            return;
+        }
+        let flavors_needed = msg.new_order.get_flavors();
+        metrics::get().order_received(1);
+        let line = format!("Got new order {} with {:?}", msg.id, flavors_needed);
+        log::traced(Level::Info, "OM", &msg.trace_id, line.purple());
 
-        let (sndr, receiver) = mpsc::channel::<usize>(10);
+        let timer = self.start_timer(msg.id.clone(), ctx);
+        self.orders.push(
+            OrderInProgress::new(
+                msg.id,
+                flavors_needed,
+                msg.new_order.get_substitutes(),
+                timer,
+            )
+            .with_trace_id(msg.trace_id),
+        );
+        self.save_in_flight();
+    }
+}
 
-        self.sender = Some(sndr);
-        let addr = ctx.address();
+/// Handles the GetOrdersInFlight message, it returns the ids of the orders in progress and of the restored ones
+impl Handler<GetOrdersInFlight> for OrderManager {
+    type Result = Vec<String>;
 
-        async move { token_lost_timeout(receiver, addr).await }
-            .into_actor(self)
-            .spawn(ctx);
+    fn handle(&mut self, _msg: GetOrdersInFlight, _ctx: &mut Self::Context) -> Self::Result {
+        self.orders
+            .iter()
+            .map(|o| o.order_id.clone())
+            .chain(self.restored.iter().map(|o| o.order_id.clone()))
+            .collect()
+    }
+}
+
+/// Handles the ResumeOrders message, the answer of the leader to the orders in flight of the robot.
+/// The restored orders it lets the robot resume are prepared with the flavors they still needed,
+/// the ones it gave to another robot or that are finished are dropped
+impl Handler<ResumeOrders> for OrderManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: ResumeOrders, ctx: &mut Self::Context) -> Self::Result {
+        for order_id in msg.drop {
+            let line = format!("Order {} is not mine anymore, dropping it", order_id);
+            log::warn("OM", line.yellow());
+            self.remove_order(&order_id);
+            self.take_restored(&order_id);
+        }
+        for order_id in msg.resume {
+            if let Some(progress) = self.take_restored(&order_id) {
+                self.resume_order(progress, ctx);
+            }
+        }
+        self.save_in_flight();
     }
 }
 
@@ -261,77 +733,93 @@ impl Handler<GetTokenBackup> for OrderManager {
     fn handle(&mut self, msg: GetTokenBackup, _ctx: &mut Self::Context) -> Self::Result {
         let mut token_backup = msg.token_backup;
 
-        if let Some(flavor_token) = self.tokens_backup.get(&token_backup.get_flavor_id()) {
+        if let Some(flavor_token) = self.tokens_backup.get(&token_backup.key()) {
             token_backup.change_amount_if_necessary(flavor_token.get_amnt());
         }
 
-        match self.robot_connection_handler {
+        match self.rch() {
             Some(ref rch) => {
                 if let Err(e) = rch.try_send(SendTokenBackup {
                     token_backup: token_backup.clone(),
                 }) {
-                    print_send_error("[OM]", "SendTockenBackup", &e.to_string());
+                    log::send_error("OM", "SendTockenBackup", &e.to_string());
                 }
             }
-            None => println!(
-                "{}",
-                "[OM] Error: There is not a RCH to give the token to".red()
-            ),
+            None => log::error("OM", "Error: There is not a RCH to give the token to".red()),
         }
     }
 }
 
-/// Handles the TimerWentOff message, it is alerted that one or more tokens were lost, and starts the recovery process
+/// Handles the TimerWentOff message, it is alerted that one or more tokens needed by an order were lost, and starts the recovery process
+/// Every token of the flavor that has not gone through the robot lately is recovered,
+/// a token that is already being recovered for another order is not recovered twice
 impl Handler<TimerWentOff> for OrderManager {
     type Result = ();
 
-    fn handle(&mut self, _msg: TimerWentOff, ctx: &mut Self::Context) -> Self::Result {
-        match self.robot_connection_handler {
+    fn handle(&mut self, msg: TimerWentOff, ctx: &mut Self::Context) -> Self::Result {
+        let flavors_needed = match self.orders.iter().find(|o| o.order_id == msg.order_id) {
+            Some(order) => order.flavors_needed.clone(),
+            None => return,
+        };
+
+        match self.rch() {
             Some(ref rch) => {
-                for (flavor_id, _) in &self.flavors_needed {
-                    println!("[OM]: Lost Token: {}", flavor_id);
-                    let amount: usize;
-                    if let Some(amnt) = self.tokens_backup.get(flavor_id) {
-                        amount = amnt.get_amnt();
-                    } else {
-                        amount = INITIAL_AMOUNT;
+                let per_flavor = config::get().tokens.per_flavor;
+                let keys = flavors_needed.iter().flat_map(|(flavor_id, _)| {
+                    (0..per_flavor).map(move |shard| (flavor_id.clone(), shard))
+                });
+                for key in keys {
+                    let seen_lately = self
+                        .tokens_seen_at
+                        .get(&key)
+                        .is_some_and(|seen| seen.elapsed() < self.token_timeout());
+                    if seen_lately || !self.recovering.insert(key.clone()) {
+                        continue;
                     }
-                    let token_backup = TokenBackup::new(*flavor_id, amount, self.rch_id);
+                    log::warn("OM", format!("Lost Token: {} ({})", key.0, key.1));
+                    let (amount, epoch) = match self.tokens_backup.get(&key) {
+                        Some(token) => (token.get_amnt(), token.get_epoch()),
+                        None => {
+                            let initial = config::get()
+                                .flavors
+                                .initial_amount(&key.0)
+                                .unwrap_or(DEFAULT_INITIAL_AMOUNT);
+                            (shard_amount(initial, per_flavor, key.1), 0)
+                        }
+                    };
+                    let token_backup = TokenBackup::new(key, amount, self.rch_id, epoch + 1);
 
                     if let Err(e) = rch.try_send(SendTokenBackup {
                         token_backup: token_backup.clone(),
                     }) {
-                        print_send_error("[OM]", "SendTockenBackup", &e.to_string());
+                        log::send_error("OM", "SendTockenBackup", &e.to_string());
                     }
                 }
             }
-            None => println!(
-                "{}",
-                "[OM] Error: There is not a RCH to give the token to".red()
-            ),
+            None => log::error("OM", "Error: There is not a RCH to give the token to".red()),
         }
 
-        let (sndr, receiver) = mpsc::channel::<usize>(10);
-
-        self.sender = Some(sndr);
-        let addr = ctx.address();
-
-        async move { token_lost_timeout(receiver, addr).await }
-            .into_actor(self)
-            .spawn(ctx);
+        let timer = self.start_timer(msg.order_id.clone(), ctx);
+        if let Some(order) = self.orders.iter_mut().find(|o| o.order_id == msg.order_id) {
+            order.set_timer(timer);
+        }
     }
 }
 
 #[cfg(test)]
 #[derive(Message)]
 #[rtype(result = "Vec<(FlavorID, usize)>")]
-pub struct GetFlavorsNeeded();
+pub struct GetFlavorsNeeded(pub String);
 
 #[cfg(test)]
 impl Handler<GetFlavorsNeeded> for OrderManager {
     type Result = Vec<(FlavorID, usize)>;
-    fn handle(&mut self, _msg: GetFlavorsNeeded, _ctx: &mut Self::Context) -> Self::Result {
-        self.flavors_needed.clone()
+    fn handle(&mut self, msg: GetFlavorsNeeded, _ctx: &mut Self::Context) -> Self::Result {
+        self.orders
+            .iter()
+            .find(|o| o.order_id == msg.0)
+            .map(|o| o.flavors_needed.clone())
+            .unwrap_or_default()
     }
 }
 
@@ -339,35 +827,101 @@ impl Handler<GetFlavorsNeeded> for OrderManager {
 mod tests {
 
     use super::*;
-    use crate::common::order::Order;
+    use crate::common::order::{Order, Scoop};
+    use crate::common::registry::{self, Registry};
 
     #[actix::test]
     async fn order_arrived_properly() {
-        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new().start();
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
         let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
         o_manager
             .send(GetNewOrder {
                 new_order: Order::new_cucurucho(FlavorID::Chocolate),
                 id: "1".to_string(),
+                trace_id: String::new(),
             })
             .await
             .unwrap();
-        let flavors_needed = o_manager.send(GetFlavorsNeeded()).await.unwrap();
+        let flavors_needed = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
         assert_eq!(flavors_needed, vec![(FlavorID::Chocolate, 250)]);
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 1);
+    }
+
+    #[actix::test]
+    async fn order_that_does_not_fit_its_size_is_not_prepared() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::Cucurucho(Scoop::new(FlavorID::Chocolate, 900)),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+    }
+
+    #[actix::test]
+    async fn order_aborted_by_id() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        let abort = |order_id: &str| AbortOrder {
+            order_id: order_id.to_string(),
+        };
+        assert!(!o_manager.send(abort("2")).await.unwrap());
+        assert!(o_manager.send(abort("1")).await.unwrap());
+        assert!(o_manager.send(GetOrderIds()).await.unwrap().is_empty());
+    }
+
+    #[actix::test]
+    async fn order_cancelled_by_the_leader() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(CancelOrder {
+                order_id: "1".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(o_manager.send(GetOrderIds()).await.unwrap().is_empty());
     }
 
     #[actix::test]
     async fn order_arrived_and_token_arrived() {
-        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new().start();
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
         let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
         o_manager
             .send(GetNewOrder {
                 new_order: Order::new_cucurucho(FlavorID::Chocolate),
                 id: "1".to_string(),
+                trace_id: String::new(),
             })
             .await
             .unwrap();
-        let flavors_needed = o_manager.send(GetFlavorsNeeded()).await.unwrap();
+        let flavors_needed = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
         assert_eq!(flavors_needed, vec![(FlavorID::Chocolate, 250)]);
         o_manager
             .send(TransferToken {
@@ -375,22 +929,90 @@ mod tests {
             })
             .await
             .unwrap();
-        let flavors_needed = o_manager.send(GetFlavorsNeeded()).await.unwrap();
+        let flavors_needed = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
         assert_eq!(flavors_needed, vec![]);
     }
 
+    #[actix::test]
+    async fn order_for_the_last_grams_of_the_token_is_served() {
+        tokio::time::pause();
+        let registry = Registry::new();
+        let order_preparer = OrderPreparer::new(0)
+            .with_registry(registry.clone())
+            .start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer, 0).start();
+        registry.register(registry::ORDER_MANAGER, o_manager.clone());
+        let new_order = || GetNewOrder {
+            new_order: Order::new_cucurucho(FlavorID::Chocolate),
+            id: "1".to_string(),
+            trace_id: String::new(),
+        };
+        o_manager.send(new_order()).await.unwrap();
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Chocolate, 250),
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+
+        // the order is remembered as completed, not aborted, and the token came back empty
+        o_manager.send(new_order()).await.unwrap();
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+        let holdings = o_manager.send(GetHoldings()).await.unwrap();
+        assert_eq!(holdings.len(), 1);
+        assert_eq!(holdings[0].amount, 0);
+    }
+
+    #[actix::test]
+    async fn token_without_the_grams_aborts_its_orders_as_out_of_stock() {
+        let o_manager = OrderManager::new(OrderPreparer::new(0).start(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        let token = FlavorToken::new(FlavorID::Chocolate, 1000);
+        o_manager
+            .send(TransferToken {
+                flavor_token: token.clone(),
+            })
+            .await
+            .unwrap();
+        // a single failure is enough, it is not retried as a jam would be
+        o_manager
+            .send(ScoopFailed {
+                flavor_token: token,
+                reason: AbortReason::OutOfStock,
+            })
+            .await
+            .unwrap();
+        assert!(o_manager.send(GetOrderIds()).await.unwrap().is_empty());
+    }
+
     #[actix::test]
     async fn order_arrived_and_incorrect_token_arrived_and_token_returned() {
-        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new().start();
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
         let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
         o_manager
             .send(GetNewOrder {
                 new_order: Order::new_cucurucho(FlavorID::Chocolate),
                 id: "1".to_string(),
+                trace_id: String::new(),
             })
             .await
             .unwrap();
-        let flavors_needed = o_manager.send(GetFlavorsNeeded()).await.unwrap();
+        let flavors_needed = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
         assert_eq!(flavors_needed, vec![(FlavorID::Chocolate, 250)]);
         o_manager
             .send(GetTokenBack {
@@ -398,7 +1020,267 @@ mod tests {
             })
             .await
             .unwrap();
-        let flavors_needed = o_manager.send(GetFlavorsNeeded()).await.unwrap();
+        let flavors_needed = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
         assert_eq!(flavors_needed, vec![(FlavorID::Chocolate, 250)]);
     }
+
+    #[actix::test]
+    async fn token_serves_one_order_while_other_waits() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Mint),
+                id: "2".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Mint, 1000),
+            })
+            .await
+            .unwrap();
+        let waiting = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
+        let served = o_manager
+            .send(GetFlavorsNeeded("2".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(waiting, vec![(FlavorID::Chocolate, 250)]);
+        assert_eq!(served, vec![]);
+    }
+
+    #[actix::test]
+    async fn order_waits_for_another_token_of_the_flavor() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Mint, 500).in_shard(1),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Mint),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Mint, 100),
+            })
+            .await
+            .unwrap();
+        let waiting = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(waiting, vec![(FlavorID::Mint, 250)]);
+    }
+
+    #[actix::test]
+    async fn order_waits_for_a_substitute_when_the_flavor_runs_out() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        let order = Order::new_cucurucho(FlavorID::Mint)
+            .with_substitutes(FlavorID::Mint, vec![FlavorID::Lemon]);
+        o_manager
+            .send(GetNewOrder {
+                new_order: order,
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        let empty_mint = TransferToken {
+            flavor_token: FlavorToken::new(FlavorID::Mint, 100),
+        };
+        o_manager.send(empty_mint).await.unwrap();
+        let waiting = o_manager
+            .send(GetFlavorsNeeded("1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(waiting, vec![(FlavorID::Lemon, 250)]);
+
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Lemon, 100),
+            })
+            .await
+            .unwrap();
+        assert!(o_manager.send(GetOrderIds()).await.unwrap().is_empty());
+    }
+
+    #[actix::test]
+    async fn flavors_are_scooped_once_all_of_them_are_reserved() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let mut o_manager = OrderManager::new(order_preparer, 0);
+        let (sender, _receiver) = mpsc::channel(10);
+        o_manager.orders.push(OrderInProgress::new(
+            "1".to_string(),
+            vec![(FlavorID::Mint, 250), (FlavorID::Lemon, 250)],
+            vec![],
+            sender,
+        ));
+        let mut mint = FlavorToken::new(FlavorID::Mint, 1000);
+        assert_eq!(o_manager.check_needed(&mut mint), 0);
+        assert_eq!(mint.available(), 750);
+
+        let mut lemon = FlavorToken::new(FlavorID::Lemon, 1000);
+        assert_eq!(o_manager.check_needed(&mut lemon), 250);
+        o_manager.scooping = None;
+
+        assert_eq!(o_manager.check_needed(&mut mint), 250);
+        assert_eq!(mint.available(), 1000);
+    }
+
+    #[actix::test]
+    async fn reservations_of_an_aborted_order_are_released() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let mut o_manager = OrderManager::new(order_preparer, 0);
+        let (sender, _receiver) = mpsc::channel(10);
+        o_manager.orders.push(OrderInProgress::new(
+            "1".to_string(),
+            vec![(FlavorID::Mint, 250), (FlavorID::Lemon, 250)],
+            vec![],
+            sender,
+        ));
+        let mut mint = FlavorToken::new(FlavorID::Mint, 1000);
+        o_manager.check_needed(&mut mint);
+        let mut lemon = FlavorToken::new(FlavorID::Lemon, 100);
+        assert_eq!(o_manager.check_needed(&mut lemon), 0);
+        assert!(o_manager.orders.is_empty());
+
+        assert_eq!(o_manager.check_needed(&mut mint), 0);
+        assert_eq!(mint.available(), 1000);
+        assert_eq!(mint.get_amnt(), 1000);
+    }
+
+    #[actix::test]
+    async fn order_is_aborted_after_too_many_failed_scoops() {
+        let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(0).start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer.clone(), 0).start();
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        let token = FlavorToken::new(FlavorID::Chocolate, 1000);
+        for _ in 0..config::get().scoops.max_retries {
+            o_manager
+                .send(TransferToken {
+                    flavor_token: token.clone(),
+                })
+                .await
+                .unwrap();
+            o_manager
+                .send(ScoopFailed {
+                    flavor_token: token.clone(),
+                    reason: AbortReason::ScoopFailed,
+                })
+                .await
+                .unwrap();
+            let flavors_needed = o_manager
+                .send(GetFlavorsNeeded("1".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(flavors_needed, vec![(FlavorID::Chocolate, 250)]);
+        }
+
+        o_manager
+            .send(TransferToken {
+                flavor_token: token.clone(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(ScoopFailed {
+                flavor_token: token,
+                reason: AbortReason::ScoopFailed,
+            })
+            .await
+            .unwrap();
+        assert!(o_manager.send(GetOrderIds()).await.unwrap().is_empty());
+    }
+
+    #[actix::test]
+    async fn order_is_prepared_on_virtual_time() {
+        tokio::time::pause();
+        let registry = Registry::new();
+        let order_preparer = OrderPreparer::new(0)
+            .with_registry(registry.clone())
+            .start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer, 0).start();
+        registry.register(registry::ORDER_MANAGER, o_manager.clone());
+        o_manager
+            .send(GetNewOrder {
+                new_order: Order::new_cucurucho(FlavorID::Chocolate),
+                id: "1".to_string(),
+                trace_id: String::new(),
+            })
+            .await
+            .unwrap();
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Chocolate, 1000),
+            })
+            .await
+            .unwrap();
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 1);
+
+        // the scoop takes seconds, but the clock jumps straight to the end of it
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+    }
+
+    #[actix::test]
+    async fn completed_order_assigned_again_is_not_prepared_twice() {
+        tokio::time::pause();
+        let registry = Registry::new();
+        let order_preparer = OrderPreparer::new(0)
+            .with_registry(registry.clone())
+            .start();
+        let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer, 0).start();
+        registry.register(registry::ORDER_MANAGER, o_manager.clone());
+        let new_order = || GetNewOrder {
+            new_order: Order::new_cucurucho(FlavorID::Chocolate),
+            id: "1".to_string(),
+            trace_id: String::new(),
+        };
+        o_manager.send(new_order()).await.unwrap();
+        o_manager.send(new_order()).await.unwrap();
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 1);
+        o_manager
+            .send(TransferToken {
+                flavor_token: FlavorToken::new(FlavorID::Chocolate, 1000),
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+
+        o_manager.send(new_order()).await.unwrap();
+        assert_eq!(o_manager.send(GetOrdersInProgress()).await.unwrap(), 0);
+    }
 }
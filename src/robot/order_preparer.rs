@@ -1,23 +1,34 @@
-use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use crate::common::log;
+use actix::{Actor, AsyncContext, Context, Handler, Message};
 use colored::*;
+use rand::rngs::StdRng;
+use rand::Rng;
 use std::time::Duration;
 
-use crate::robot::flavor_token::FlavorToken;
-use crate::robot::messages::{GetTokenBack, ScoopFlavor, SetOrderManager};
+use crate::common::registry::{self, Registry};
+use crate::common::simulation;
+use crate::config;
+use crate::protocol::flavor_token::FlavorToken;
+use crate::protocol::robot_messages::AbortReason;
+use crate::robot::messages::{GetTokenBack, ScoopFailed, ScoopFlavor};
 use crate::robot::order_manager::OrderManager;
-use crate::robot::utils::print_send_error;
-
-//Scoop time is calculated as SCOOP_TIME_FACTOR * amount needed
-pub const SCOOP_TIME_FACTOR: usize = 10;
 
+/// The token is given back once the scoop is over, `failure` is set if nothing could be served
 #[derive(Message)]
 #[rtype(result = "()")]
-struct ReturnToken(pub FlavorToken);
+struct ReturnToken {
+    token: FlavorToken,
+    failure: Option<AbortReason>,
+}
 
 /// OrderPreparer is an actor that serves the ice cream scoops
-#[derive(Default)]
+/// The arm can jam with the probability set in the configuration, then the token goes back untouched,
+/// as it does when the token has fewer grams than the ones asked, then the orders ran out of the flavor
+/// Scooping takes `ms_per_gram` milliseconds for every gram served
 pub struct OrderPreparer {
-    order_manager: Option<Addr<OrderManager>>,
+    registry: Registry,
+    rng: StdRng,
+    ms_per_gram: usize,
 }
 
 impl Actor for OrderPreparer {
@@ -25,18 +36,34 @@ impl Actor for OrderPreparer {
 }
 
 impl OrderPreparer {
-    pub fn new() -> Self {
+    pub fn new(robot_id: usize) -> Self {
         Self {
-            order_manager: None,
+            registry: Registry::new(),
+            rng: simulation::rng("OP", robot_id),
+            ms_per_gram: config::get().scoops.ms_per_gram_of(robot_id),
         }
     }
-}
 
-impl Handler<SetOrderManager> for OrderPreparer {
-    type Result = ();
+    /// Finds the OrderManager of the robot in the registry
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Replaces the speed of the arm, like the one given by flag
+    pub fn with_ms_per_gram(mut self, ms_per_gram: usize) -> Self {
+        self.ms_per_gram = ms_per_gram;
+        self
+    }
 
-    fn handle(&mut self, msg: SetOrderManager, _ctx: &mut Self::Context) -> Self::Result {
-        self.order_manager = Some(msg.order_manager);
+    /// Time the arm takes to scoop the amount, with the jitter of the configuration
+    fn scoop_time(&mut self, amount: usize) -> Duration {
+        let jitter_ms = config::get().scoops.jitter_ms;
+        let jitter = match jitter_ms {
+            0 => 0,
+            max => self.rng.gen_range(0..=max),
+        };
+        Duration::from_millis((amount * self.ms_per_gram) as u64 + jitter)
     }
 }
 
@@ -44,18 +71,37 @@ impl Handler<SetOrderManager> for OrderPreparer {
 impl Handler<ScoopFlavor> for OrderPreparer {
     type Result = ();
 
-    fn handle(&mut self, msg: ScoopFlavor, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ScoopFlavor, ctx: &mut Self::Context) -> Self::Result {
         let mut flavor = msg.flavor_token;
         let amnt = msg.amount;
+        let scoop_time = self.scoop_time(amnt);
+
+        let mut failure = None;
+        if self.rng.gen_bool(config::get().scoops.jam_probability) {
+            failure = Some(AbortReason::ScoopFailed);
+            let line = format!(
+                "The arm jammed scooping {} grams of {}",
+                amnt,
+                flavor.get_id()
+            );
+            log::warn("OP", line.bright_red());
+        } else {
+            let line = format!("Scooping {} grams of {}", amnt, flavor.get_id());
+            log::info("OP", line.bright_blue());
+            // a token without the grams comes back with nothing served, the orders ran out of the flavor
+            if let Err(e) = flavor.try_serve(amnt) {
+                let line = format!("Could not scoop {}: {}", flavor.get_id(), e);
+                log::error("OP", line.red());
+                failure = Some(AbortReason::OutOfStock);
+            }
+        }
 
-        let line = format!("[OP] Scooping {} grams of {}", amnt, flavor.get_id());
-        println!("{}", line.bright_blue());
-
-        flavor.serve(amnt);
-
-        _ctx.notify_later(
-            ReturnToken(flavor),
-            Duration::from_millis((amnt * SCOOP_TIME_FACTOR) as u64),
+        ctx.notify_later(
+            ReturnToken {
+                token: flavor,
+                failure,
+            },
+            scoop_time,
         );
     }
 }
@@ -65,26 +111,37 @@ impl Handler<ReturnToken> for OrderPreparer {
     type Result = ();
 
     fn handle(&mut self, msg: ReturnToken, _ctx: &mut Self::Context) -> Self::Result {
-        let token = msg.0;
+        let token = msg.token;
 
         let line = format!(
-            "[OP] Returning {} Token with {} grams",
+            "Returning {} Token with {} grams",
             token.get_id(),
             token.get_amnt()
         );
-        println!("{}", line.bright_purple());
-
-        match self.order_manager {
-            Some(ref om) => {
+        log::info("OP", line.bright_purple());
+
+        match (
+            self.registry.addr::<OrderManager>(registry::ORDER_MANAGER),
+            msg.failure,
+        ) {
+            (Some(ref om), Some(reason)) => {
+                if let Err(e) = om.try_send(ScoopFailed {
+                    flavor_token: token,
+                    reason,
+                }) {
+                    log::send_error("OP", "ScoopFailed", &e.to_string());
+                }
+            }
+            (Some(ref om), None) => {
                 if let Err(e) = om.try_send(GetTokenBack {
                     flavor_token: token,
                 }) {
-                    print_send_error("[OP]", "GetTokenBack", &e.to_string());
+                    log::send_error("OP", "GetTokenBack", &e.to_string());
                 }
             }
-            None => println!(
-                "{}",
-                "[OP] Error: There is not an OrderManager to give the token to ".red()
+            (None, _) => log::error(
+                "OP",
+                "Error: There is not an OrderManager to give the token to ".red(),
             ),
         }
     }
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::order_waiting::OrderWaiting;
+
+/// How many orders the leader has in each stage
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct QueueDepth {
+    pub queued: usize,
+    pub assigned: usize,
+    pub stashed: usize,
+    pub free_slots: usize,
+}
+
+/// An order that a robot is preparing, with how long ago it was given to the robot
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Assignment {
+    pub order_id: String,
+    pub robot_id: usize,
+    pub screen_id: usize,
+    pub assigned_ms_ago: Option<u64>,
+}
+
+/// A result that the leader could not send to its screen yet
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StashedResult {
+    pub order_id: String,
+    pub screen_id: usize,
+    pub completed: bool,
+}
+
+/// Returns how many orders are ahead of the order, 0 if a robot is already preparing it.
+/// None if the leader has not got it or it is already finished
+pub fn orders_ahead(
+    orders_on_queue: &VecDeque<OrderInfo>,
+    robots_orders: &HashMap<usize, Vec<OrderInfo>>,
+    order_id: &str,
+) -> Option<usize> {
+    if robots_orders
+        .values()
+        .flatten()
+        .any(|o| o.order_id == order_id)
+    {
+        return Some(0);
+    }
+    orders_on_queue.iter().position(|o| o.order_id == order_id)
+}
+
+/// Lists the orders the robots are preparing, sorted by robot and by order
+pub fn assignments(
+    robots_orders: &HashMap<usize, Vec<OrderInfo>>,
+    assigned_at: &HashMap<String, Instant>,
+    now: Instant,
+) -> Vec<Assignment> {
+    let mut assignments: Vec<Assignment> = robots_orders
+        .iter()
+        .flat_map(|(robot_id, orders)| {
+            orders.iter().map(move |order| Assignment {
+                order_id: order.order_id.clone(),
+                robot_id: *robot_id,
+                screen_id: order.screen_id,
+                assigned_ms_ago: assigned_at
+                    .get(&order.order_id)
+                    .map(|at| now.saturating_duration_since(*at).as_millis() as u64),
+            })
+        })
+        .collect();
+    assignments.sort_by(|a, b| (a.robot_id, &a.order_id).cmp(&(b.robot_id, &b.order_id)));
+    assignments
+}
+
+/// Lists the results stashed for their screens, in the order they were stashed
+pub fn stashed_results(orders_to_be_sent: &[OrderWaiting]) -> Vec<StashedResult> {
+    orders_to_be_sent
+        .iter()
+        .map(|order| StashedResult {
+            order_id: order.id.clone(),
+            screen_id: order.screen_id,
+            completed: order.flavor.is_none(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use std::time::Duration;
+
+    fn order(id: &str) -> OrderInfo {
+        OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: id.to_string(),
+            screen_id: 1,
+            deadline_ms: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_orders_ahead_counts_the_queue_before_the_order() {
+        let queue: VecDeque<OrderInfo> = vec![order("a"), order("b"), order("c")].into();
+        let robots_orders = HashMap::from([(2, vec![order("x")])]);
+
+        assert_eq!(orders_ahead(&queue, &robots_orders, "a"), Some(0));
+        assert_eq!(orders_ahead(&queue, &robots_orders, "c"), Some(2));
+        assert_eq!(orders_ahead(&queue, &robots_orders, "x"), Some(0));
+        assert_eq!(orders_ahead(&queue, &robots_orders, "z"), None);
+    }
+
+    #[test]
+    fn test_assignments_are_sorted_by_robot() {
+        let now = Instant::now();
+        let robots_orders = HashMap::from([(3, vec![order("b")]), (1, vec![order("c")])]);
+        let assigned_at = HashMap::from([("b".to_string(), now - Duration::from_millis(40))]);
+
+        let assignments = assignments(&robots_orders, &assigned_at, now);
+        assert_eq!(assignments[0].order_id, "c");
+        assert_eq!(assignments[0].assigned_ms_ago, None);
+        assert_eq!(assignments[1].robot_id, 3);
+        assert_eq!(assignments[1].assigned_ms_ago, Some(40));
+    }
+}
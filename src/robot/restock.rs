@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::common::flavor_id::FlavorID;
+use crate::protocol::flavor_token::FlavorToken;
+
+/// Restocks requested to the leader's robot that are waiting for their token to pass by.
+/// The token may be anywhere in the ring, or being scooped, so the grams are only added when it arrives
+#[derive(Debug, Default)]
+pub struct PendingRestocks {
+    pending: HashMap<FlavorID, usize>,
+}
+
+impl PendingRestocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a restock of a flavor, restocks of the same flavor are accumulated
+    pub fn add(&mut self, flavor: FlavorID, amount: usize) {
+        *self.pending.entry(flavor).or_insert(0) += amount;
+    }
+
+    /// Adds the pending grams of the flavor of the token to it, returns the amount restocked
+    pub fn apply(&mut self, token: &mut FlavorToken) -> usize {
+        match self.pending.remove(&token.get_id()) {
+            Some(amount) => {
+                token.restock(amount);
+                amount
+            }
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restock_is_applied_once() {
+        let mut restocks = PendingRestocks::new();
+        restocks.add(FlavorID::Mint, 500);
+        let mut token = FlavorToken::new(FlavorID::Mint, 0);
+
+        assert_eq!(restocks.apply(&mut token), 500);
+        assert_eq!(restocks.apply(&mut token), 0);
+        assert_eq!(token.get_amnt(), 500);
+    }
+
+    #[test]
+    fn test_restocks_of_other_flavors_are_kept() {
+        let mut restocks = PendingRestocks::new();
+        restocks.add(FlavorID::Mint, 500);
+        restocks.add(FlavorID::Mint, 250);
+        let mut lemon = FlavorToken::new(FlavorID::Lemon, 100);
+        let mut mint = FlavorToken::new(FlavorID::Mint, 100);
+
+        assert_eq!(restocks.apply(&mut lemon), 0);
+        assert_eq!(restocks.apply(&mut mint), 750);
+        assert_eq!(mint.get_amnt(), 850);
+    }
+
+    #[test]
+    fn test_restock_while_the_token_is_being_scooped() {
+        let mut restocks = PendingRestocks::new();
+        let mut token = FlavorToken::new(FlavorID::Chocolate, 1000);
+        restocks.apply(&mut token);
+
+        // The token is being scooped when the restock arrives
+        restocks.add(FlavorID::Chocolate, 2000);
+        token.try_serve(250).unwrap();
+
+        // The grams are added the next time the token passes by
+        restocks.apply(&mut token);
+        assert_eq!(token.get_amnt(), 2750);
+    }
+}
@@ -0,0 +1,154 @@
+//! Where a robot stands in the ring. Every change of leader goes through `RingState::next`, which matches each state
+//! with each event, so a new state or event does not compile until all its transitions are decided,
+//! and a transition that makes no sense in the current state is turned down instead of applied.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::robot::errors::RingStateError;
+
+/// Where the robot stands in the ring
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RingState {
+    /// Connecting to its previous and next robots, it does not know the leader yet
+    Joining,
+    /// Part of the ring, following the leader run by another robot
+    InRing { leader_id: usize },
+    /// Running the leader of the ring
+    LeaderSelf,
+    /// Taking part in an election, with the last leader it followed until a new one shows up
+    Electing { last_leader: Option<usize> },
+}
+
+/// What moves the robot from one state to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RingEvent {
+    /// Another robot leads the ring
+    Follow(usize),
+    /// The robot leads the ring
+    Lead,
+    /// The robot starts or joins a round of an election
+    Elect,
+    /// The leader the robot ran stepped down, a newer one exists somewhere
+    StepDown,
+}
+
+impl fmt::Display for RingState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RingState::Joining => write!(f, "joining"),
+            RingState::InRing { leader_id } => write!(f, "in the ring of Leader {}", leader_id),
+            RingState::LeaderSelf => write!(f, "leading"),
+            RingState::Electing { .. } => write!(f, "electing"),
+        }
+    }
+}
+
+impl RingState {
+    /// Returns the state the event moves the robot to, or an error if the event is not valid in the current state
+    pub fn next(self, my_id: usize, event: RingEvent) -> Result<RingState, RingStateError> {
+        let invalid = || Err(RingStateError::InvalidTransition { from: self, event });
+        match (self, event) {
+            (_, RingEvent::Follow(leader_id)) if leader_id == my_id => invalid(),
+            (RingState::Joining, RingEvent::Follow(leader_id))
+            | (RingState::InRing { .. }, RingEvent::Follow(leader_id))
+            | (RingState::LeaderSelf, RingEvent::Follow(leader_id))
+            | (RingState::Electing { .. }, RingEvent::Follow(leader_id)) => {
+                Ok(RingState::InRing { leader_id })
+            }
+            (RingState::Joining, RingEvent::Lead)
+            | (RingState::InRing { .. }, RingEvent::Lead)
+            | (RingState::Electing { .. }, RingEvent::Lead) => Ok(RingState::LeaderSelf),
+            (RingState::LeaderSelf, RingEvent::Lead) => invalid(),
+            (RingState::Joining, RingEvent::Elect) => Ok(RingState::Electing { last_leader: None }),
+            (RingState::InRing { leader_id }, RingEvent::Elect) => Ok(RingState::Electing {
+                last_leader: Some(leader_id),
+            }),
+            (RingState::LeaderSelf, RingEvent::Elect) => Ok(RingState::Electing {
+                last_leader: Some(my_id),
+            }),
+            (RingState::Electing { last_leader }, RingEvent::Elect) => {
+                Ok(RingState::Electing { last_leader })
+            }
+            (RingState::LeaderSelf, RingEvent::StepDown) => {
+                Ok(RingState::Electing { last_leader: None })
+            }
+            (RingState::Electing { last_leader }, RingEvent::StepDown)
+                if last_leader == Some(my_id) =>
+            {
+                Ok(RingState::Electing { last_leader: None })
+            }
+            (RingState::Joining, RingEvent::StepDown)
+            | (RingState::InRing { .. }, RingEvent::StepDown)
+            | (RingState::Electing { .. }, RingEvent::StepDown) => invalid(),
+        }
+    }
+
+    /// Returns the leader the robot follows, itself if it leads, or the last one it followed while electing
+    pub fn leader_id(&self, my_id: usize) -> Option<usize> {
+        match self {
+            RingState::Joining => None,
+            RingState::InRing { leader_id } => Some(*leader_id),
+            RingState::LeaderSelf => Some(my_id),
+            RingState::Electing { last_leader } => *last_leader,
+        }
+    }
+
+    /// Returns true if the robot runs the leader, also while an election it takes part in has not replaced it
+    pub fn leads(&self, my_id: usize) -> bool {
+        self.leader_id(my_id) == Some(my_id)
+    }
+
+    /// Returns true if the robot takes commands from a leader run by another robot
+    pub fn follows(&self, my_id: usize) -> bool {
+        match self {
+            RingState::InRing { .. } => true,
+            RingState::Electing { last_leader } => *last_leader != Some(my_id),
+            RingState::Joining | RingState::LeaderSelf => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_robot_joins_follows_and_is_elected() {
+        let joining = RingState::Joining;
+        assert_eq!(joining.leader_id(1), None);
+
+        let in_ring = joining.next(1, RingEvent::Follow(0)).unwrap();
+        assert_eq!(in_ring, RingState::InRing { leader_id: 0 });
+        assert!(in_ring.follows(1));
+
+        let electing = in_ring.next(1, RingEvent::Elect).unwrap();
+        assert_eq!(electing.leader_id(1), Some(0));
+        assert_eq!(electing.next(1, RingEvent::Elect), Ok(electing));
+
+        let leading = electing.next(1, RingEvent::Lead).unwrap();
+        assert!(leading.leads(1));
+        assert!(!leading.follows(1));
+    }
+
+    #[test]
+    fn test_transitions_that_make_no_sense_are_turned_down() {
+        let in_ring = RingState::InRing { leader_id: 0 };
+        assert_eq!(
+            in_ring.next(1, RingEvent::StepDown),
+            Err(RingStateError::InvalidTransition {
+                from: in_ring,
+                event: RingEvent::StepDown
+            })
+        );
+        assert!(RingState::LeaderSelf.next(1, RingEvent::Lead).is_err());
+        assert!(RingState::Joining.next(1, RingEvent::Follow(1)).is_err());
+
+        let electing = RingState::LeaderSelf.next(1, RingEvent::Elect).unwrap();
+        assert!(electing.leads(1));
+        let stepped_down = electing.next(1, RingEvent::StepDown).unwrap();
+        assert_eq!(stepped_down.leader_id(1), None);
+        assert!(stepped_down.next(1, RingEvent::StepDown).is_err());
+    }
+}
@@ -1,40 +1,86 @@
+use crate::common::log;
 use actix::prelude::*;
 use colored::*;
-use rand::Rng;
 use tokio::io::AsyncWriteExt;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio_stream::wrappers::LinesStream;
 
+use crate::common::codec;
 use crate::common::flavor_id::FlavorID;
-use crate::config::MAX_NUMBER_OF_ROBOTS;
+use crate::common::health;
+use crate::common::metrics;
+use crate::common::registry::{self, Registry};
+use crate::common::simulation;
+use crate::common::utils::{retry_backoff, should_retry};
+use crate::config;
+use crate::protocol::backup_delta::{BackupAssembler, BackupUpdate};
+use crate::protocol::backup_seal::SealedBackup;
+use crate::protocol::compression::Compression;
+use crate::protocol::election::ElectionId;
+use crate::protocol::flavor_token::{FlavorToken, TokenKey};
+use crate::protocol::leader_backup::LeaderBackup;
+use crate::protocol::token_backup::TokenBackup;
+use crate::robot::admin::RobotStatus;
+use crate::robot::backup_store::{persist_leader_backup, restore_leader_backup};
 use crate::robot::connections::robot_to_leader_connection::RobotToLeaderConnection;
 use crate::robot::connections::robot_to_robot_connection::RobotToRobotConnection;
-use crate::robot::flavor_token::FlavorToken;
-use crate::robot::leader_backup::LeaderBackup;
-use crate::robot::leader_elector::LeaderElector;
+use crate::robot::control_lane::ControlLane;
+use crate::robot::leader_elector::{
+    check_round_metadata, elector_for, ElectionRounds, LeaderElectionStrategy, RoundCheck,
+};
+use crate::robot::maintenance;
 use crate::robot::messages::*;
 use crate::robot::order_manager::OrderManager;
+use crate::robot::restock::PendingRestocks;
+use crate::robot::ring_state::{RingEvent, RingState};
 use crate::robot::robot_leader::RobotLeader;
-use crate::robot::token_backup::TokenBackup;
+use crate::robot::stock_watch::StockWatch;
+use crate::robot::token_balancer::TokenBalancer;
+use crate::robot::token_epochs::TokenEpochs;
+use crate::robot::token_trace::TokenTracer;
 use crate::robot::utils::*;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
 
 /// Actor that handles the connection of a robot with the other robots and the leader
 /// It is in charge of sending the token to the next robot and the finished orders to the leader
-/// It also handles all the messages necessary for the election of a new leader
+/// It also handles all the messages necessary for the election of a new leader, following the algorithm of the config
 /// It also handles the communication needed to recover a lost token
+/// When it leaves the ring, it finishes its orders, forwards every token it gets and waits for its previous robot to connect to its next one
+/// Every election raises the epoch of the leader, the commands of a leader with an older epoch than the newest one seen are rejected
+/// When it connects to a leader it tells it how many milliseconds its arm takes to scoop a gram
+/// Overlapping elections are merged, only the greatest round started at once completes and no round is started while another one runs
+/// Its place in the ring is a RingState, the messages that are not valid in that state are turned down
 pub struct RobotConnectionHandler {
     my_id: usize,
+    ms_per_gram: usize,
     order_manager: Addr<OrderManager>,
-    leader_id: usize,
+    state: RingState,
+    leader_epoch: u64,
+    result_retries: HashMap<String, u32>,
     leader: Option<Addr<RobotToLeaderConnection>>,
+    registry: Registry,
     previous_robot: Option<Addr<RobotToRobotConnection>>,
     next_robot: Option<OwnedWriteHalf>,
     next_robot_read: Option<OwnedReadHalf>,
-    next_robot_id: usize,
+    next_robot_id: Option<usize>,
+    control_lane: Option<ControlLane>,
+    opening_control_lane: bool,
+    previous_control_lane: Option<Addr<RobotToRobotConnection>>,
     leader_backup: Option<LeaderBackup>,
-    leader_elector: LeaderElector,
-    token_backup_msg: Vec<FlavorID>,
+    backup_assembler: BackupAssembler,
+    leader_elector: Box<dyn LeaderElectionStrategy>,
+    election_rounds: ElectionRounds,
+    token_backup_msg: Vec<TokenKey>,
+    token_epochs: TokenEpochs,
+    token_balancer: TokenBalancer,
+    token_tracer: TokenTracer,
+    stock_watch: StockWatch,
+    pending_restocks: PendingRestocks,
+    leaving: bool,
+    departing: bool,
+    /// Times in a row the leader turned the robot down since its id was in use
+    id_in_use: u32,
 }
 
 impl Actor for RobotConnectionHandler {
@@ -42,30 +88,144 @@ impl Actor for RobotConnectionHandler {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         start_robots_connection_listener(ctx.address(), self.my_id);
+        health::watch(ctx, |actor: &Self| actor.leads() || actor.leader.is_some());
+        let interval = Duration::from_millis(config::get().heartbeat.interval_ms);
+        ctx.run_interval(interval, |actor, ctx| actor.send_heartbeat(ctx));
+        let trace = &config::get().trace;
+        if let Some(file) = trace.file.clone() {
+            let interval = Duration::from_millis(trace.dump_interval_ms);
+            ctx.run_interval(interval, move |actor, _| actor.dump_token_trace(&file));
+        }
+        let maintenance = &config::get().maintenance;
+        if let Some(first) = maintenance::first_defrost_in(self.my_id, maintenance) {
+            let every = Duration::from_millis(maintenance.defrost_every_ms);
+            ctx.run_later(first, move |actor, ctx| {
+                actor.defrost();
+                ctx.run_interval(every, |actor, _| actor.defrost());
+            });
+        }
     }
 }
 
 impl RobotConnectionHandler {
-    pub fn new(order_manager: Addr<OrderManager>, my_id: usize) -> Self {
+    pub fn new(order_manager: Addr<OrderManager>, my_id: usize, ms_per_gram: usize) -> Self {
         Self {
             my_id,
+            ms_per_gram,
             order_manager,
-            leader_id: MAX_NUMBER_OF_ROBOTS,
+            state: RingState::Joining,
+            leader_epoch: 0,
+            result_retries: HashMap::new(),
             leader: None,
+            registry: Registry::new(),
             previous_robot: None,
             next_robot: None,
             next_robot_read: None,
-            next_robot_id: MAX_NUMBER_OF_ROBOTS,
+            next_robot_id: None,
+            control_lane: None,
+            opening_control_lane: false,
+            previous_control_lane: None,
             leader_backup: None,
-            leader_elector: LeaderElector::new(my_id),
+            backup_assembler: BackupAssembler::new(),
+            leader_elector: elector_for(config::get().cluster.election, my_id),
+            election_rounds: ElectionRounds::new(Duration::from_millis(
+                config::get().heartbeat.timeout_ms,
+            )),
             token_backup_msg: Vec::new(),
+            token_epochs: TokenEpochs::new(),
+            token_balancer: TokenBalancer::new(),
+            token_tracer: TokenTracer::new(),
+            stock_watch: StockWatch::new(&config::get().flavors),
+            pending_restocks: PendingRestocks::new(),
+            leaving: false,
+            departing: false,
+            id_in_use: 0,
         }
     }
 
+    /// Keeps the leader this robot runs in the registry of the robot, so the other actors can find it
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Returns the leader this robot runs, if it runs one
+    fn robot_leader(&self) -> Option<Addr<RobotLeader>> {
+        self.registry.addr(registry::LEADER)
+    }
+
+    /// Returns the leader the robot follows, itself if it leads
+    fn leader_id(&self) -> Option<usize> {
+        self.state.leader_id(self.my_id)
+    }
+
+    /// Returns true if the robot runs the leader of the ring
+    fn leads(&self) -> bool {
+        self.state.leads(self.my_id)
+    }
+
+    /// Moves the robot to the state the event leads to. An event that is not valid in the current state is logged and dropped,
+    /// it returns false then
+    fn transition(&mut self, event: RingEvent) -> bool {
+        match self.state.next(self.my_id, event) {
+            Ok(state) => {
+                self.state = state;
+                true
+            }
+            Err(e) => {
+                log::warn("RCH", format!("Ignoring a change of state: {}", e).yellow());
+                false
+            }
+        }
+    }
+
+    /// Keeps the connection to the leader and tells the leader how fast the robot scoops and which backups it reads,
+    /// and the orders the robot was preparing, if it has any
+    fn set_leader_connection(&mut self, leader: Addr<RobotToLeaderConnection>) {
+        if let Err(e) = leader.try_send(ScoopSpeed {
+            robot_id: self.my_id,
+            ms_per_gram: self.ms_per_gram,
+            compressions: Compression::SUPPORTED.to_vec(),
+        }) {
+            log::send_error("RCH", "ScoopSpeed", &e.to_string());
+        }
+        let (order_manager, connection, robot_id) =
+            (self.order_manager.clone(), leader.clone(), self.my_id);
+        actix::spawn(async move {
+            match order_manager.send(GetOrdersInFlight()).await {
+                Ok(orders) if orders.is_empty() => {}
+                Ok(orders) => {
+                    if let Err(e) = connection.try_send(OrdersInFlight { robot_id, orders }) {
+                        log::send_error("RCH", "OrdersInFlight", &e.to_string());
+                    }
+                }
+                Err(e) => log::send_error("RCH", "GetOrdersInFlight", &e.to_string()),
+            }
+        });
+        self.leader = Some(leader);
+    }
+
+    /// Connects to the leader, a failure comes back to the RCH as a ConnectionFailed message
+    fn start_leader_connection(&mut self, leader_id: usize, attempt: u32, ctx: &mut Context<Self>) {
+        let addr = ctx.address();
+        let my_id = self.my_id;
+        async move { connect_to_leader(leader_id, my_id, addr).await }
+            .into_actor(self)
+            .map(move |result, actor, ctx| match result {
+                Ok(pipo) => actor.set_leader_connection(pipo),
+                Err(error) => ctx.notify(ConnectionFailed {
+                    peer: Peer::Leader(leader_id),
+                    error,
+                    attempt,
+                }),
+            })
+            .wait(ctx);
+    }
+
     /// Function to send a message to the next robot in the ring
-    fn safe_send(&mut self, msg: String, ctx: &mut Context<Self>) -> bool {
-        let next_id = self.next_robot_id;
+    fn safe_send(&mut self, msg: Vec<u8>, ctx: &mut Context<Self>) -> bool {
         let my_id = self.my_id;
+        let next_id = self.next_robot_id.unwrap_or(my_id);
         let addr = ctx.address().clone();
 
         if self.next_robot.is_some() && self.next_robot_read.is_some() {
@@ -80,20 +240,22 @@ impl RobotConnectionHandler {
                     .into_actor(self)
                     .map(move |(next_read, next_write, next_id), actor, _| {
                         if next_id == actor.my_id {
-                            if actor.leader_id != actor.my_id {
+                            actor.next_robot_id = None;
+                            if !actor.leads() {
                                 if let Err(e) = addr.try_send(SetNewLeader {
                                     leader_id: actor.my_id,
                                     by_election: true,
+                                    epoch: actor.leader_epoch + 1,
                                 }) {
-                                    print_send_error("[RCH]", "SetNewLeader", &e.to_string());
+                                    log::send_error("RCH", "SetNewLeader", &e.to_string());
                                 }
                             }
-                            let line = "[RCH] Only robot in the ring, I am the Leader".to_string();
-                            println!("{}", line.bright_red());
+                            let line = "Only robot in the ring, I am the Leader".to_string();
+                            log::info("RCH", line.bright_red());
                         } else {
                             actor.next_robot = Some(next_write);
                             actor.next_robot_read = Some(next_read);
-                            actor.next_robot_id = next_id;
+                            actor.next_robot_id = Some(next_id);
                         }
                     })
                     .wait(ctx);
@@ -106,37 +268,159 @@ impl RobotConnectionHandler {
         false
     }
 
+    /// Sends a message of the elections or the leaders to the next robot through the control lane,
+    /// or through the ring connection while the lane is not open
+    fn safe_send_control(&mut self, msg: Vec<u8>, ctx: &mut Context<Self>) {
+        self.open_control_lane(ctx);
+        let msg = match &self.control_lane {
+            Some(lane) => match lane.send(msg) {
+                Ok(()) => return,
+                Err(msg) => {
+                    self.control_lane = None;
+                    msg
+                }
+            },
+            None => msg,
+        };
+        self.safe_send(msg, ctx);
+    }
+
+    /// Opens the control lane to the next robot if there is none to it, the lane to a robot that is no longer the next one is dropped
+    fn open_control_lane(&mut self, ctx: &mut Context<Self>) {
+        let next_id = match self.next_robot_id {
+            Some(next_id) if next_id != self.my_id => next_id,
+            _ => {
+                self.control_lane = None;
+                return;
+            }
+        };
+        let open = self
+            .control_lane
+            .as_ref()
+            .is_some_and(|lane| lane.next_id() == next_id);
+        if open || self.opening_control_lane {
+            return;
+        }
+        self.control_lane = None;
+        self.opening_control_lane = true;
+        ControlLane::open(self.my_id, next_id, ctx.address().recipient())
+            .into_actor(self)
+            .map(move |lane, actor, _| {
+                actor.opening_control_lane = false;
+                match lane {
+                    Ok(lane) if actor.next_robot_id == Some(next_id) => {
+                        actor.control_lane = Some(lane)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let line = format!(
+                            "Could not open the control lane to Robot {}: {}",
+                            next_id, e
+                        );
+                        log::warn("RCH", line.yellow());
+                    }
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Tells the leader this robot runs when the flavor of the token crossed its low watermark
+    fn watch_stock(&mut self, token: &FlavorToken) {
+        let level = match self.stock_watch.observe(token) {
+            Some(level) => level,
+            None => return,
+        };
+        if let Some(leader) = self.robot_leader() {
+            if let Err(e) = leader.try_send(StockLevelChanged { level }) {
+                log::send_error("RCH", "StockLevelChanged", &e.to_string());
+            }
+        }
+    }
+
+    /// Tells the leader this robot runs that grams were added to the token, so it credits them to the flavor
+    fn account_restock(&self, token: &FlavorToken, grams: usize) {
+        if let Some(leader) = self.robot_leader() {
+            let restocked = StockRestocked {
+                flavor: token.get_id(),
+                grams,
+            };
+            if let Err(e) = leader.try_send(restocked) {
+                log::send_error("RCH", "StockRestocked", &e.to_string());
+            }
+        }
+    }
+
+    /// Moves grams between the tokens of the same flavor when one has much more than another,
+    /// it is done by the leader's robot, that sees every token pass by
+    fn rebalance_token(&mut self, token: &mut FlavorToken) {
+        let threshold = config::get().tokens.rebalance_threshold;
+        let rebalance = self.token_balancer.balance(token, threshold);
+        if rebalance.merged > 0 {
+            let line = format!(
+                "Merged {} grams into the {} token {}",
+                rebalance.merged,
+                token.get_id(),
+                token.get_shard()
+            );
+            log::info("RCH", line.bright_green());
+        }
+        if let Some(((flavor, shard), amount)) = rebalance.split {
+            let line = format!(
+                "Split {} grams from the {} token {} for the token {}",
+                amount,
+                flavor,
+                token.get_shard(),
+                shard
+            );
+            log::info("RCH", line.bright_green());
+        }
+    }
+
+    /// Writes the trace of the tokens to the file, only the leader's robot sees every token
+    fn dump_token_trace(&self, file: &str) {
+        if !self.leads() {
+            return;
+        }
+        if let Err(e) = self.token_tracer.dump(file) {
+            let line = format!("Could not write the token trace to {}: {}", file, e);
+            log::warn("RCH", line.yellow());
+        }
+    }
+
     /// Function to send a token to the next robot in the ring
     fn safe_send_token(&mut self, token: FlavorToken, ctx: &mut Context<Self>) {
-        let token_msg = RobotCommand::TokenMessage { token }.to_string();
+        let token_msg = RobotCommand::TokenMessage {
+            token: Box::new(token.clone()),
+        }
+        .to_bytes();
         let msg = match token_msg {
-            Ok(r_msg) => r_msg + "\n",
+            Ok(r_msg) => r_msg,
             Err(e) => {
-                print_create_error("[RCH]", "TokenMessage", &e.to_string());
+                log::create_error("RCH", "TokenMessage", &e.to_string());
                 return;
             }
         };
 
         let could_send = self.safe_send(msg, ctx);
 
-        if self.next_robot_id == self.my_id || !could_send {
+        if self.next_robot_id.is_none() || !could_send {
             // let line = format!("RCH: No pude enviar el token al siguiente robot, Me lo mando a mi mismo");
             // println!("{}", line.bright_yellow());
             if let Err(e) = ctx.address().try_send(TransferToken {
                 flavor_token: token,
             }) {
-                print_send_error("[RCH]", "TransferToken", &e.to_string());
+                log::send_error("RCH", "TransferToken", &e.to_string());
             }
         }
     }
 
     /// Function to send the token backup to the next robot in the ring, to recover a lost token
     fn safe_send_token_backup(&mut self, token_backup: TokenBackup, ctx: &mut Context<Self>) {
-        let token_msg = RobotCommand::TokenBackupMsg { token_backup }.to_string();
+        let token_msg = RobotCommand::TokenBackupMsg { token_backup }.to_bytes();
         let msg = match token_msg {
-            Ok(r_msg) => r_msg + "\n",
+            Ok(r_msg) => r_msg,
             Err(e) => {
-                print_create_error("[RCH]", "TokenBackup", &e.to_string());
+                log::create_error("RCH", "TokenBackup", &e.to_string());
                 return;
             }
         };
@@ -144,27 +428,133 @@ impl RobotConnectionHandler {
         self.safe_send(msg, ctx);
     }
 
+    /// Sends again the result of an order that could not be sent to the leader, after the wait of the retry policy.
+    /// When the attempts run out the result is dropped, the screen sends the order again when it does not get it
+    fn retry_order_result<M>(
+        &mut self,
+        order_id: String,
+        name: &str,
+        msg: M,
+        ctx: &mut Context<Self>,
+    ) where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        Self: Handler<M>,
+    {
+        let attempt = self.result_retries.entry(order_id.clone()).or_insert(0);
+        if !should_retry(*attempt) {
+            self.result_retries.remove(&order_id);
+            let line = format!(
+                "Could not send {} of order {} to Leader, giving up",
+                name, order_id
+            );
+            log::error("RCH", line.red());
+            return;
+        }
+        let delay = retry_backoff(*attempt);
+        *attempt += 1;
+        let line = format!(
+            "Error trying to send {} to Leader. Retrying in {}ms (attempt {})",
+            name,
+            delay.as_millis(),
+            attempt
+        );
+        log::warn("RCH", line.bright_yellow());
+        metrics::get().retried("order_result");
+        ctx.notify_later(msg, delay);
+    }
+
     /// Function to send a message to the next robot to inform the new leader's id
-    fn safe_send_new_leader(&mut self, new_leader: usize, ctx: &mut Context<Self>) {
-        let leader_msg = RobotCommand::NewLeader { leader: new_leader }.to_string();
+    fn safe_send_new_leader(&mut self, new_leader: usize, epoch: u64, ctx: &mut Context<Self>) {
+        let leader_msg = RobotCommand::NewLeader {
+            leader: new_leader,
+            epoch,
+        }
+        .to_bytes();
         let msg = match leader_msg {
-            Ok(r_msg) => r_msg + "\n",
+            Ok(r_msg) => r_msg,
             Err(e) => {
-                print_create_error("[RCH]", "NewLeader", &e.to_string());
+                log::create_error("RCH", "NewLeader", &e.to_string());
                 return;
             }
         };
 
-        self.safe_send(msg, ctx);
+        self.safe_send_control(msg, ctx);
     }
 
-    /// Function to send the candidates of an election to the next robot
-    fn safe_send_election(&mut self, candidates: Vec<(usize, bool)>, ctx: &mut Context<Self>) {
-        let leader_msg = RobotCommand::NewElection { candidates }.to_string();
+    /// Function to send the candidates of a round of an election to the next robot, with the robots it went through
+    fn safe_send_election(
+        &mut self,
+        election: ElectionId,
+        hops: usize,
+        candidates: Vec<(usize, bool)>,
+        ctx: &mut Context<Self>,
+    ) {
+        let leader_msg = RobotCommand::NewElection {
+            election,
+            hops,
+            candidates,
+        }
+        .to_bytes();
         let msg = match leader_msg {
-            Ok(r_msg) => r_msg + "\n",
+            Ok(r_msg) => r_msg,
             Err(e) => {
-                print_create_error("[RCH]", "NewElection", &e.to_string());
+                log::create_error("RCH", "NewElection", &e.to_string());
+                return;
+            }
+        };
+
+        self.safe_send_control(msg, ctx);
+    }
+
+    /// Starts a defrost window, the OrderManager stops scooping and the leader is told not to give the robot orders.
+    /// A robot that is leaving the ring does not defrost, nor the robot of the leader, which gets no orders
+    fn defrost(&mut self) {
+        if self.leaving || self.departing || self.leads() {
+            return;
+        }
+        let defrost_ms = config::get().maintenance.defrost_ms;
+        if let Err(e) = self.order_manager.try_send(Defrost {
+            duration: Duration::from_millis(defrost_ms),
+        }) {
+            log::send_error("RCH", "Defrost", &e.to_string());
+        }
+        if let Some(leader) = &self.leader {
+            if let Err(e) = leader.try_send(RobotUnavailable {
+                robot_id: self.my_id,
+                until: maintenance::now_ms() + defrost_ms,
+            }) {
+                log::send_error("RCH", "RobotUnavailable", &e.to_string());
+            }
+        }
+    }
+
+    /// Function to send a heartbeat to the next robot.
+    /// If the next robot died the message can not be sent, so the ring is re-stitched without waiting for a token
+    fn send_heartbeat(&mut self, ctx: &mut Context<Self>) {
+        match self.next_robot_id {
+            Some(next_id) if next_id != self.my_id => {}
+            _ => return,
+        }
+        let heartbeat_msg = RobotCommand::Heartbeat { from: self.my_id }.to_bytes();
+        let msg = match heartbeat_msg {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RCH", "Heartbeat", &e.to_string());
+                return;
+            }
+        };
+
+        self.safe_send(msg, ctx);
+    }
+
+    /// Function to send a restock to the next robot, so it reaches the leader's robot
+    fn safe_send_restock(&mut self, flavor: FlavorID, amount: usize, ctx: &mut Context<Self>) {
+        let restock_msg = RobotCommand::RestockFlavor { flavor, amount }.to_bytes();
+        let msg = match restock_msg {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RCH", "RestockFlavor", &e.to_string());
                 return;
             }
         };
@@ -172,6 +562,74 @@ impl RobotConnectionHandler {
         self.safe_send(msg, ctx);
     }
 
+    /// Function to send to the next robot that a robot is leaving the ring, so its previous robot skips it
+    fn safe_send_leave_ring(&mut self, robot_id: usize, ctx: &mut Context<Self>) {
+        let leave_msg = RobotCommand::LeaveRing { robot_id }.to_bytes();
+        let msg = match leave_msg {
+            Ok(r_msg) => r_msg,
+            Err(e) => {
+                log::create_error("RCH", "LeaveRing", &e.to_string());
+                return;
+            }
+        };
+
+        self.safe_send_control(msg, ctx);
+    }
+
+    /// Waits until the OrderManager has no orders in progress to depart from the ring
+    fn wait_orders_to_leave(&mut self, ctx: &mut Context<Self>) {
+        self.order_manager
+            .send(GetOrdersInProgress())
+            .into_actor(self)
+            .map(|res, actor, ctx| match res {
+                Ok(0) => actor.depart(ctx),
+                Ok(_) => {
+                    let interval = Duration::from_millis(config::get().heartbeat.interval_ms);
+                    ctx.run_later(interval, |actor, ctx| actor.wait_orders_to_leave(ctx));
+                }
+                Err(e) => {
+                    log::send_error("RCH", "GetOrdersInProgress", &e.to_string());
+                    actor.depart(ctx);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Asks the previous robot to connect to the next one, from now on the tokens are forwarded right away
+    fn depart(&mut self, ctx: &mut Context<Self>) {
+        self.departing = true;
+        let line = "My orders are done, asking my previous robot to skip me".to_string();
+        log::info("RCH", line.bright_cyan());
+        self.safe_send_leave_ring(self.my_id, ctx);
+
+        let deadline = Instant::now() + Duration::from_millis(config::get().heartbeat.timeout_ms);
+        self.wait_previous_to_leave(deadline, ctx);
+    }
+
+    /// Exits once the previous robot closed its connection, or when the deadline passes
+    fn wait_previous_to_leave(&mut self, deadline: Instant, ctx: &mut Context<Self>) {
+        let interval = Duration::from_millis(config::get().heartbeat.interval_ms);
+        let connected = self
+            .previous_robot
+            .as_ref()
+            .map(|previous| previous.connected())
+            .unwrap_or(false);
+        if connected && Instant::now() < deadline {
+            ctx.run_later(interval, move |actor, ctx| {
+                actor.wait_previous_to_leave(deadline, ctx)
+            });
+            return;
+        }
+        if connected {
+            let line = "My previous robot did not skip me in time, leaving anyway".to_string();
+            log::warn("RCH", line.yellow());
+        }
+        let line = "Left the ring!".to_string();
+        log::info("RCH", line.bright_cyan());
+        // gives the last tokens forwarded some time to be written
+        ctx.run_later(interval, |_, _| System::current().stop());
+    }
+
     /// Creates the RobotToRobotConnection with the previous robot in the ring, to receive all the messages
     fn create_previous_robot_connection(
         &mut self,
@@ -180,7 +638,7 @@ impl RobotConnectionHandler {
         address: Addr<RobotConnectionHandler>,
     ) {
         let pipo = RobotToRobotConnection::create(|own_ctx| {
-            let lines = LinesStream::new(BufReader::new(read_half).lines());
+            let lines = codec::frames(read_half);
             let rpc = RobotToRobotConnection::new(address, Some(w_half));
             RobotToRobotConnection::add_stream(lines, own_ctx);
             rpc
@@ -188,92 +646,318 @@ impl RobotConnectionHandler {
         self.previous_robot = Some(pipo);
     }
 
-    /// Function to make the robot the leader of the ring
-    fn make_myself_leader(&mut self, by_election: bool, my_address: Addr<RobotConnectionHandler>) {
+    /// Opens a whole backup of the leader and stores it, also on disk. Returns false if it does not open with the key of the config
+    fn store_backup(&mut self, backup: SealedBackup, epoch: u64) -> bool {
+        let backup = match backup.open(epoch, &config::get().security) {
+            Ok(backup) => backup,
+            Err(e) => {
+                let line = format!("Rejecting a backup of epoch {}: {}", epoch, e);
+                log::warn("RCH", line.bright_red());
+                return false;
+            }
+        };
+        self.keep_backup(backup);
+        true
+    }
+
+    /// Opens an update of the backup of the leader and applies it to the backup the robot has.
+    /// If it does not follow the last one or does not match the backup of the leader, the backup is not valid for
+    /// an election until the leader sends it whole
+    fn store_backup_update(&mut self, backup: SealedBackup, epoch: u64) {
+        let update: BackupUpdate = match backup.open(epoch, &config::get().security) {
+            Ok(update) => update,
+            Err(e) => {
+                let line = format!("Rejecting a backup of epoch {}: {}", epoch, e);
+                log::warn("RCH", line.bright_red());
+                return;
+            }
+        };
+        match self
+            .backup_assembler
+            .apply(self.leader_backup.as_ref(), epoch, update)
+        {
+            Ok(backup) => self.keep_backup(backup),
+            Err(e) => {
+                let line = format!("Could not apply the backup of the Leader: {}", e);
+                log::warn("RCH", line.yellow());
+                self.leader_elector.invalidate_backup();
+                if !self.backup_assembler.ask_for_full() {
+                    return;
+                }
+                if let Some(leader) = &self.leader {
+                    if let Err(e) = leader.try_send(AskForFullBackup {
+                        robot_id: self.my_id,
+                    }) {
+                        log::send_error("RCH", "AskForFullBackup", &e.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores a backup of the leader, also on disk, it is valid for an election
+    fn keep_backup(&mut self, backup: LeaderBackup) {
+        persist_leader_backup(self.my_id, &backup);
+        self.leader_backup = Some(backup);
+        self.leader_elector.validate_backup();
+    }
+
+    /// Follows the leader that got the leadership of the current one, if it does not connect within the reconnect window
+    /// an election is started
+    fn follow_transferred_leader(
+        &mut self,
+        leader: usize,
+        next_epoch: u64,
+        ctx: &mut Context<Self>,
+    ) {
+        if !self.transition(RingEvent::Follow(leader)) {
+            return;
+        }
+        let line = format!(
+            "The Leader handed its leadership to Robot {} (epoch {})",
+            leader, next_epoch
+        );
+        log::info("RCH", line.bright_yellow());
+        ctx.run_later(reconnect_window(), move |actor, ctx| {
+            if actor.leader_id() == Some(leader) && actor.leader_epoch < next_epoch {
+                let line = format!(
+                    "Robot {} did not take the leadership, starting an election",
+                    leader
+                );
+                log::warn("RCH", line.bright_yellow());
+                ctx.notify(StartElection());
+            }
+        });
+    }
+
+    /// Function to make the robot the leader of the ring.
+    /// If it is the first leader and there is a snapshot on disk, it recovers the state of the previous run
+    fn make_myself_leader(
+        &mut self,
+        by_election: bool,
+        epoch: u64,
+        my_address: Addr<RobotConnectionHandler>,
+    ) {
         let my_id = self.my_id;
         if !by_election {
             Arbiter::new().spawn_fn(move || {
-                RobotLeader::new(my_id, Some(my_address)).start();
+                simulation::start_clock();
+                match RobotLeader::from_snapshot(my_id, Some(my_address.clone())) {
+                    Some(leader) => leader.in_epoch(epoch).start(),
+                    None => RobotLeader::new(my_id, Some(my_address))
+                        .in_epoch(epoch)
+                        .start(),
+                };
             });
             return;
         }
         if self.leader_backup.is_none() {
-            let line = "[RCH] Error! I dont have a backup to become leader!".to_string();
-            println!("{}", line.bright_yellow());
+            self.leader_backup = restore_leader_backup(my_id);
+        }
+        if self.leader_backup.is_none() {
+            let line = "Error! I dont have a backup to become leader!".to_string();
+            log::error("RCH", line.bright_yellow());
             return;
         }
 
-        if let Err(e) = self.order_manager.try_send(AbortCurrentOrder {}) {
-            print_send_error("[RCH]", "AbortCurrentOrder", &e.to_string());
+        if let Err(e) = self.order_manager.try_send(AbortCurrentOrders {}) {
+            log::send_error("RCH", "AbortCurrentOrders", &e.to_string());
         }
 
         if let Some(backup) = self.leader_backup.take() {
             Arbiter::new().spawn_fn(move || {
-                RobotLeader::from_backup(my_id, Some(my_address), backup).start();
+                simulation::start_clock();
+                RobotLeader::from_backup(my_id, Some(my_address), backup)
+                    .in_epoch(epoch)
+                    .start();
             });
         }
     }
 }
 
 /// Handles the SetNewLeader message, to set a new leader in the ring
+/// An election that chose the leader the robot already had ends without connecting to it again
 impl Handler<SetNewLeader> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: SetNewLeader, ctx: &mut Self::Context) -> Self::Result {
         let new_leader = msg.leader_id;
         let addr = ctx.address().clone();
         let my_id = self.my_id;
+        self.leader_epoch = self.leader_epoch.max(msg.epoch);
+        let event = match new_leader == my_id {
+            true => RingEvent::Lead,
+            false => RingEvent::Follow(new_leader),
+        };
 
-        if self.leader_id == new_leader {
+        if self.leader_id() == Some(new_leader) {
+            if let RingState::Electing { .. } = self.state {
+                self.transition(event);
+            }
+            return;
+        }
+        if !self.transition(event) {
             return;
         }
 
-        let line = format!("[RCH] The new Leader is: {}", new_leader);
-        println!("{}", line.bright_yellow());
-
-        self.leader_id = new_leader;
+        let line = format!(
+            "The new Leader is: {} (epoch {})",
+            new_leader, self.leader_epoch
+        );
+        log::info("RCH", line.bright_yellow());
 
         if new_leader == my_id {
-            self.make_myself_leader(msg.by_election, addr.clone());
+            self.make_myself_leader(msg.by_election, self.leader_epoch, addr.clone());
             return;
         }
 
-        async move { connect_to_leader(new_leader, my_id, addr).await }
-            .into_actor(self)
-            .map(|pipo, actor, _| {
-                if let Some(pipo) = pipo {
-                    actor.leader = Some(pipo);
+        self.start_leader_connection(new_leader, 0, ctx);
+    }
+}
+
+/// Handles a leader connection that could not be opened
+/// It is retried a few times with a growing wait, if the leader is still unreachable an election is started
+impl Handler<ConnectionFailed> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectionFailed, ctx: &mut Self::Context) -> Self::Result {
+        let leader_id = match msg.peer {
+            Peer::Leader(leader_id) if self.leader_id() == Some(leader_id) => leader_id,
+            _ => return,
+        };
+        if msg.error.is_transient() && should_retry(msg.attempt) {
+            let attempt = msg.attempt + 1;
+            let line = format!(
+                "Could not connect to the leader {}: {}, retrying (attempt {})",
+                leader_id, msg.error, attempt
+            );
+            log::warn("RCH", line.bright_yellow());
+            metrics::get().retried("leader_connection");
+            ctx.run_later(retry_backoff(msg.attempt), move |actor, ctx| {
+                if actor.leader_id() == Some(leader_id) {
+                    actor.start_leader_connection(leader_id, attempt, ctx);
                 }
-            })
-            .wait(ctx);
+            });
+            return;
+        }
+        let line = format!(
+            "Could not connect to the leader {}: {}, starting an election",
+            leader_id, msg.error
+        );
+        log::error("RCH", line.red());
+        ctx.notify(StartElection());
+    }
+}
+
+/// The leader turned the robot down since another robot with its id is connected.
+/// It may be the last run of this robot, whose connection the leader did not see closed yet, so the robot connects
+/// again after a longer wait each time, and only stops once every retry is turned down: two robots have the same id
+impl Handler<RobotIdInUse> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: RobotIdInUse, _ctx: &mut Self::Context) -> Self::Result {
+        self.id_in_use += 1;
+        if should_retry(self.id_in_use) {
+            let line = format!(
+                "The leader still has a robot connected as Robot {}, connecting again (attempt {})",
+                msg.robot_id, self.id_in_use
+            );
+            log::warn("RCH", line.yellow());
+            return;
+        }
+        let line = format!(
+            "Fatal configuration error: another robot is connected to the leader as Robot {}, check the id of each robot",
+            msg.robot_id
+        );
+        log::error("RCH", line.on_red().white());
+        System::current().stop();
+    }
+}
+
+/// Handles a closed connection with the leader, it is opened again after a short wait,
+/// an election only starts if every retry fails
+impl Handler<ConnectionLost> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectionLost, ctx: &mut Self::Context) -> Self::Result {
+        let leader_id = match msg.peer {
+            Peer::Leader(leader_id) if self.leader_id() == Some(leader_id) => leader_id,
+            _ => return,
+        };
+        if leader_id == self.my_id {
+            return;
+        }
+        self.leader = None;
+        let line = format!(
+            "Lost the connection with the leader {}, reconnecting",
+            leader_id
+        );
+        log::warn("RCH", line.bright_yellow());
+        ctx.run_later(retry_backoff(self.id_in_use), move |actor, ctx| {
+            if actor.leader_id() == Some(leader_id) && actor.leader.is_none() {
+                actor.start_leader_connection(leader_id, 0, ctx);
+            }
+        });
     }
 }
 
 /// Handles the NewLeaderElected message, to set a new leader in the ring,
 /// if the new leader is the robot itself, it becomes the leader
-/// it always sends the new leader to the next robot in the ring
+/// it always sends the new leader to the next robot in the ring, unless its epoch is older than the one the robot knows
 impl Handler<NewLeaderElected> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: NewLeaderElected, ctx: &mut Self::Context) -> Self::Result {
         let new_leader = msg.leader_id;
+        if msg.epoch < self.leader_epoch {
+            let line = format!(
+                "Dropping the election of Leader {} from epoch {}, the current epoch is {}",
+                new_leader, msg.epoch, self.leader_epoch
+            );
+            log::warn("RCH", line.yellow());
+            return;
+        }
+        self.leader_epoch = msg.epoch;
         if new_leader == self.my_id {
-            let line = "[RCH] I have been elected Leader!".to_string();
-            println!("{}", line.bright_yellow());
+            let line = "I have been elected Leader!".to_string();
+            log::info("RCH", line.bright_yellow());
             if let Err(e) = ctx.address().try_send(SetNewLeader {
                 leader_id: new_leader,
                 by_election: true,
+                epoch: msg.epoch,
             }) {
-                print_send_error("[RCH]", "SetNewLeader", &e.to_string());
+                log::send_error("RCH", "SetNewLeader", &e.to_string());
             }
             return;
         }
-        self.safe_send_new_leader(new_leader, ctx);
+        self.safe_send_new_leader(new_leader, msg.epoch, ctx);
     }
 }
 
 /// Handles the AddNewLeader message, creates the RobotToLeaderConnection with the new leader
+/// A leader with an older epoch than the one the robot knows is told to step down instead
 impl Handler<AddNewLeader> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: AddNewLeader, ctx: &mut Self::Context) -> Self::Result {
+        if msg.epoch < self.leader_epoch {
+            let line = format!(
+                "Rejecting Leader {} from epoch {}, the current epoch is {}",
+                msg.leader_id, msg.epoch, self.leader_epoch
+            );
+            log::warn("RCH", line.yellow());
+            let mut write_half = msg.write_half;
+            let stale = RobotCommand::StaleLeader {
+                epoch: self.leader_epoch,
+            };
+            actix::spawn(async move {
+                if let Err(e) = write_command(&mut write_half, stale).await {
+                    log::error("RCH", format!("Could not reject the stale Leader: {}", e));
+                }
+            });
+            return;
+        }
+        self.leader_epoch = msg.epoch;
+        if !self.transition(RingEvent::Follow(msg.leader_id)) {
+            return;
+        }
         if self.leader.is_some() {
             self.leader
                 .take()
@@ -281,13 +965,165 @@ impl Handler<AddNewLeader> for RobotConnectionHandler {
                 .do_send(Harakiri());
         }
         // println!("{}", "LLEGUE AL ADDNEWLEADER".bright_cyan());
-        self.leader = Some(RobotToLeaderConnection::create(|own_ctx| {
-            let lines = LinesStream::new(BufReader::new(msg.read_half).lines());
-            let rpc = RobotToLeaderConnection::new(ctx.address().clone(), Some(msg.write_half));
+        let leader = RobotToLeaderConnection::create(|own_ctx| {
+            let lines = codec::frames(msg.read_half);
+            let rpc = RobotToLeaderConnection::new(
+                ctx.address().clone(),
+                msg.leader_id,
+                Some(msg.write_half),
+            );
             RobotToLeaderConnection::add_stream(lines, own_ctx);
             rpc
-        }));
-        self.leader_id = msg.leader_id;
+        });
+        self.set_leader_connection(leader);
+    }
+}
+
+/// Handles a command of the leader, it is only run if the epoch of the leader is not older than the one the robot knows,
+/// otherwise the leader is told to step down. A robot that is joining or leads the ring follows no leader, so it turns the command down
+impl Handler<LeaderCommand> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: LeaderCommand, ctx: &mut Self::Context) -> Self::Result {
+        if msg.epoch < self.leader_epoch {
+            let line = format!(
+                "Rejecting a command of a Leader from epoch {}, the current epoch is {}",
+                msg.epoch, self.leader_epoch
+            );
+            log::warn("RCH", line.yellow());
+            if let Err(e) = msg.connection.try_send(RejectStaleLeader {
+                epoch: self.leader_epoch,
+            }) {
+                log::send_error("RCH", "RejectStaleLeader", &e.to_string());
+            }
+            return;
+        }
+        if !self.state.follows(self.my_id) {
+            let line = format!(
+                "Rejecting a command of a Leader from epoch {} while {}",
+                msg.epoch, self.state
+            );
+            log::warn("RCH", line.yellow());
+            return;
+        }
+        self.leader_epoch = msg.epoch;
+        self.id_in_use = 0;
+        match msg.command {
+            RobotCommand::NewOrder {
+                order,
+                order_id,
+                trace_id,
+                ..
+            } => ctx.notify(GetNewOrder {
+                new_order: order,
+                id: order_id,
+                trace_id,
+            }),
+            // applied right away, the changes of a backup have to be applied in the order they were sent
+            RobotCommand::ReceiveLeaderBackup { backup, epoch } => {
+                self.store_backup_update(backup, epoch)
+            }
+            RobotCommand::CancelOrder { order_id, .. } => {
+                if let Err(e) = self.order_manager.try_send(CancelOrder { order_id }) {
+                    log::send_error("RCH", "CancelOrder", &e.to_string());
+                }
+            }
+            RobotCommand::TakeLeadership {
+                backup,
+                next_epoch,
+                epoch,
+            } => {
+                if !self.store_backup(backup, epoch) {
+                    return;
+                }
+                let line = "The Leader handed its leadership to me!".to_string();
+                log::info("RCH", line.bright_yellow());
+                ctx.notify(SetNewLeader {
+                    leader_id: self.my_id,
+                    by_election: true,
+                    epoch: next_epoch,
+                });
+            }
+            RobotCommand::LeaderTransferred {
+                leader, next_epoch, ..
+            } => self.follow_transferred_leader(leader, next_epoch, ctx),
+            RobotCommand::StartTokens { tokens, .. } => {
+                let line = format!("The leader starts {} tokens at this robot", tokens.len());
+                log::info("RCH", line.bright_cyan());
+                ctx.notify(StartTokens { all_tokens: tokens });
+            }
+            RobotCommand::ResumeOrders { resume, drop, .. } => {
+                if let Err(e) = self.order_manager.try_send(ResumeOrders { resume, drop }) {
+                    log::send_error("RCH", "ResumeOrders", &e.to_string());
+                }
+            }
+            other => log::error(
+                "RCH",
+                format!("Unexpected command of the Leader: {:?}", other),
+            ),
+        }
+    }
+}
+
+/// Handles the step down of the leader of this robot, a leader with a newer epoch exists somewhere,
+/// so an election is started to find it. A robot that no longer leads, as it handed its leadership, starts none
+impl Handler<LeaderSteppedDown> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: LeaderSteppedDown, ctx: &mut Self::Context) -> Self::Result {
+        self.leader_epoch = self.leader_epoch.max(msg.epoch);
+        self.registry.unregister(registry::LEADER);
+        if !self.transition(RingEvent::StepDown) {
+            return;
+        }
+        let line = format!(
+            "I am no longer the Leader, epoch {} is newer",
+            self.leader_epoch
+        );
+        log::warn("RCH", line.bright_yellow());
+        ctx.notify(StartElection());
+    }
+}
+
+/// Handles the start of the leader this robot runs, it is kept to forward the commands of the admin
+impl Handler<LeaderStarted> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: LeaderStarted, _ctx: &mut Self::Context) -> Self::Result {
+        self.registry.register(registry::LEADER, msg.leader);
+        self.stock_watch.reset();
+    }
+}
+
+/// Handles the GetLeader message, it returns the leader this robot runs, if it is the leader
+impl Handler<GetLeader> for RobotConnectionHandler {
+    type Result = Option<Addr<RobotLeader>>;
+    fn handle(&mut self, _msg: GetLeader, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.leads() {
+            return None;
+        }
+        self.robot_leader()
+    }
+}
+
+/// Handles the handoff of the leader this robot ran, the robot connects to the new leader as any other robot
+impl Handler<LeadershipTransferred> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: LeadershipTransferred, ctx: &mut Self::Context) -> Self::Result {
+        let leader_id = msg.leader_id;
+        if !self.leads() {
+            let line = format!(
+                "Ignoring the handoff of the leadership to Robot {} while {}",
+                leader_id, self.state
+            );
+            log::warn("RCH", line.yellow());
+            return;
+        }
+        self.registry.unregister(registry::LEADER);
+        self.leader = None;
+        self.follow_transferred_leader(leader_id, msg.next_epoch, ctx);
+        ctx.run_later(retry_backoff(0), move |actor, ctx| {
+            if actor.leader_id() == Some(leader_id) && actor.leader.is_none() {
+                actor.start_leader_connection(leader_id, 0, ctx);
+            }
+        });
     }
 }
 
@@ -310,11 +1146,11 @@ impl Handler<AddPreviousRobot> for RobotConnectionHandler {
         }
 
         let mut w_half = msg.write_half;
-        let leader_id = self.leader_id;
+        let leader = self.leader_id();
         async move {
-            if let Err(e) = w_half.write_all(&[leader_id as u8]).await {
-                let line = format!("[RCH] Error trying to write to new leader ID: {}", e);
-                println!("{}", line.bright_yellow());
+            if let Err(e) = write_command(&mut w_half, RobotCommand::LeaderId { leader }).await {
+                let line = format!("Error trying to write to new leader ID: {}", e);
+                log::error("RCH", line.bright_yellow());
             }
             w_half
         }
@@ -328,7 +1164,7 @@ impl Handler<AddPreviousRobot> for RobotConnectionHandler {
 impl Handler<AddNextRobot> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: AddNextRobot, ctx: &mut Self::Context) -> Self::Result {
-        if self.next_robot_id == msg.robot_id {
+        if self.next_robot_id == Some(msg.robot_id) {
             return;
         }
 
@@ -338,21 +1174,55 @@ impl Handler<AddNextRobot> for RobotConnectionHandler {
                 msg.robot_id
             }
             .into_actor(self)
-            .map(|id, actor, _ctx| {
+            .map(|id, actor, ctx| {
                 actor.next_robot = Some(msg.write_half);
-                actor.next_robot_id = id;
+                actor.next_robot_id = Some(id);
                 actor.next_robot_read = Some(msg.read_half);
-                let line = format!(
-                    "[RCH] Connected to my next robot: {:?}",
-                    actor.next_robot_id
-                );
-                println!("{}", line.bright_cyan());
+                let line = format!("Connected to my next robot: {:?}", actor.next_robot_id);
+                log::info("RCH", line.bright_cyan());
+                actor.open_control_lane(ctx);
             })
             .wait(ctx);
         } else {
             self.next_robot = Some(msg.write_half);
-            self.next_robot_id = msg.robot_id;
+            self.next_robot_id = Some(msg.robot_id);
             self.next_robot_read = Some(msg.read_half);
+            self.open_control_lane(ctx);
+        }
+    }
+}
+
+/// Handles the control lane opened by the previous robot, the lane it had before is closed
+impl Handler<AddControlLane> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: AddControlLane, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(previous) = self.previous_control_lane.take() {
+            previous.do_send(Harakiri());
+        }
+        let addr = ctx.address();
+        let lane = RobotToRobotConnection::create(|own_ctx| {
+            RobotToRobotConnection::add_stream(codec::frames(msg.read_half), own_ctx);
+            RobotToRobotConnection::control_lane(addr, msg.write_half)
+        });
+        self.previous_control_lane = Some(lane);
+        let line = format!("Robot {} opened its control lane", msg.robot_id);
+        log::info("RCH", line.bright_cyan());
+    }
+}
+
+/// Handles the close of the control lane to the next robot, the messages it could not write go through the ring
+impl Handler<ControlLaneClosed> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: ControlLaneClosed, ctx: &mut Self::Context) -> Self::Result {
+        if self
+            .control_lane
+            .as_ref()
+            .is_some_and(|lane| lane.next_id() == msg.next_id)
+        {
+            self.control_lane = None;
+        }
+        for frame in msg.unsent {
+            self.safe_send(frame, ctx);
         }
     }
 }
@@ -360,19 +1230,35 @@ impl Handler<AddNextRobot> for RobotConnectionHandler {
 /// Handles the JoinRing message.
 /// The robot tries to connect with all the possible robots in the ring.
 /// If there is no robot to connect to, it declare itself the Leader
+/// If it waited for the cluster and the ring does not know the leader, the lowest robot that was up is the leader
+/// A robot that already joined does not join again
 impl Handler<JoinRing> for RobotConnectionHandler {
     type Result = ();
 
-    fn handle(&mut self, _msg: JoinRing, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: JoinRing, ctx: &mut Self::Context) -> Self::Result {
+        if self.state != RingState::Joining {
+            let line = format!("Ignoring a request to join the ring while {}", self.state);
+            log::warn("RCH", line.yellow());
+            return;
+        }
         let addr = ctx.address().clone();
         let my_id = self.my_id;
+        let waits_for_cluster = msg.wait_for.is_some();
+
+        let join = async move {
+            // the whole cluster waited for the same robots, so all of them pick the lowest id as the first leader
+            let first_leader = match msg.wait_for {
+                Some(wait) => wait_for_robots(my_id, wait)
+                    .await
+                    .and_then(|robots| robots.into_iter().min()),
+                None => None,
+            };
 
-        async move {
             let connected_to_prev = match connect_to_prev_robot(my_id, addr.clone()).await {
                 Ok(_) => true,
                 Err(e) => {
-                    let line = format!("[RCH] Could not connect to any previous robot: {}", e);
-                    println!("{}", line.bright_yellow());
+                    let line = format!("Could not connect to any previous robot: {}", e);
+                    log::error("RCH", line.bright_yellow());
                     false
                 }
             };
@@ -381,29 +1267,54 @@ impl Handler<JoinRing> for RobotConnectionHandler {
                 match connect_to_next_robot_and_get_leader(my_id, addr.clone()).await {
                     Ok(leader_id) => (true, leader_id),
                     Err(e) => {
-                        let line = format!("[RCH] Could not connect to any next robot: {}", e);
-                        println!("{}", line.bright_yellow());
-                        (false, MAX_NUMBER_OF_ROBOTS)
+                        let line = format!("Could not connect to any next robot: {}", e);
+                        log::error("RCH", line.bright_yellow());
+                        (false, None)
                     }
                 };
 
             if !connected_to_next && !connected_to_prev {
-                let line = "[RCH] Only robot in the ring, I am the Leader".to_string();
-                println!("{}", line.bright_yellow());
-                return my_id;
+                let line = "Only robot in the ring, I am the Leader".to_string();
+                log::info("RCH", line.bright_yellow());
+                return Some(my_id);
+            }
+            if let (None, Some(first_leader)) = (leader_id, first_leader) {
+                let line = format!(
+                    "The ring does not know the Leader yet, Robot {} leads the cluster",
+                    first_leader
+                );
+                log::info("RCH", line.bright_yellow());
+                return Some(first_leader);
             }
             leader_id
         }
         .into_actor(self)
-        .map(|leader_id, _, _ctx| {
-            if let Err(e) = _ctx.address().try_send(SetNewLeader {
-                leader_id,
-                by_election: false,
-            }) {
-                print_send_error("[RCH]", "SetNewLeader", &e.to_string());
+        .map(|leader_id, actor, ctx| match leader_id {
+            Some(leader_id) => {
+                let epoch = match leader_id == actor.my_id {
+                    true => actor.leader_epoch + 1,
+                    false => actor.leader_epoch,
+                };
+                if let Err(e) = ctx.address().try_send(SetNewLeader {
+                    leader_id,
+                    by_election: false,
+                    epoch,
+                }) {
+                    log::send_error("RCH", "SetNewLeader", &e.to_string());
+                }
             }
-        })
-        .wait(ctx);
+            None => {
+                let line = "The ring does not know the Leader yet".to_string();
+                log::warn("RCH", line.yellow());
+            }
+        });
+        // robots that waited for each other join at the same time, so they must answer their previous robot while joining
+        match waits_for_cluster {
+            true => {
+                ctx.spawn(join);
+            }
+            false => ctx.wait(join),
+        }
     }
 }
 
@@ -418,98 +1329,133 @@ impl Handler<StartTokens> for RobotConnectionHandler {
             if let Err(e) = self.order_manager.try_send(TransferToken {
                 flavor_token: token,
             }) {
-                print_send_error("[RCH]", "TransferToken", &e.to_string());
+                log::send_error("RCH", "TransferToken", &e.to_string());
             }
         }
     }
 }
 
+/// Handles the GetTokenTrace message, with the last hops of every token seen by the leader's robot
+impl Handler<GetTokenTrace> for RobotConnectionHandler {
+    type Result = MessageResult<GetTokenTrace>;
+
+    fn handle(&mut self, _msg: GetTokenTrace, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.token_tracer.traces())
+    }
+}
+
 /// Handles a message to transfer a token to the next robot
 impl Handler<TransferToken> for RobotConnectionHandler {
     type Result = ();
 
-    fn handle(&mut self, msg: TransferToken, _ctx: &mut Self::Context) -> Self::Result {
-        //so we dont flood
+    fn handle(&mut self, msg: TransferToken, ctx: &mut Self::Context) -> Self::Result {
+        let mut token = msg.flavor_token;
+        if !self.token_epochs.accept_token(&mut token) {
+            let line = format!(
+                "Dropping a stale copy of the {} token from epoch {}",
+                token.get_id(),
+                token.get_epoch()
+            );
+            log::warn("RCH", line.yellow());
+            return;
+        }
+        token.record_hop(self.my_id);
+        if self.departing {
+            self.safe_send_token(token, ctx);
+            return;
+        }
+        let restocked = self.pending_restocks.apply(&mut token);
+        if restocked > 0 {
+            let line = format!(
+                "Restocked {} grams of {}, it now has {} grams",
+                restocked,
+                token.get_id(),
+                token.get_amnt()
+            );
+            log::info("RCH", line.bright_green());
+            self.account_restock(&token, restocked);
+        }
+        if self.leads() {
+            self.rebalance_token(&mut token);
+            self.token_tracer.record(&token);
+            self.watch_stock(&token);
+        }
+
+        // a round of the ring ends at the leader's robot, or at every robot while there is no leader,
+        // a token that no robot needed in the whole round waits there so idle tokens do not flood the ring
+        let round_ends = self.leader_id().is_none() || self.leads();
+        if !round_ends || token.take_worked() {
+            if let Err(e) = self.order_manager.try_send(TransferToken {
+                flavor_token: token,
+            }) {
+                log::send_error("RCH", "TransferToken", &e.to_string());
+            }
+            return;
+        }
+        let pause = Duration::from_millis(config::get().tokens.idle_pause_ms);
         async move {
-            tokio::time::sleep(std::time::Duration::from_millis(
-                rand::thread_rng().gen_range(0.0..1000.0) as u64,
-            ))
-            .await;
+            tokio::time::sleep(pause).await;
         }
         .into_actor(self)
         .map(move |_, actor, _| {
             if let Err(e) = actor.order_manager.try_send(TransferToken {
-                flavor_token: msg.flavor_token,
+                flavor_token: token,
             }) {
-                print_send_error("[RCH]", "TransferToken", &e.to_string());
+                log::send_error("RCH", "TransferToken", &e.to_string());
             }
         })
-        .spawn(_ctx);
+        .spawn(ctx);
     }
 }
 
-/// Handles a message to send an order prepared to the leader
+/// Handles a message to send an order prepared to the leader, it is sent again following the retry policy if it fails
 impl Handler<OrderPrepared> for RobotConnectionHandler {
     type Result = ();
-    fn handle(&mut self, msg: OrderPrepared, _ctx: &mut Self::Context) -> Self::Result {
-        // println!("RCH: Pedido listo para ser enviado");
+    fn handle(&mut self, msg: OrderPrepared, ctx: &mut Self::Context) -> Self::Result {
         let order_id = msg.id.clone();
         if let Some(leader) = &self.leader {
             if leader
                 .try_send(OrderPrepared {
                     order_result: msg.order_result,
-                    id: msg.id,
+                    id: msg.id.clone(),
+                    substitutions: msg.substitutions.clone(),
                 })
                 .is_err()
             {
-                let line = "RCH: Error trying to send OrderPrepared to Leader. Retrying in 1s."
-                    .to_string();
-                println!("{}", line.bright_yellow());
-                _ctx.notify_later(
-                    OrderPrepared {
-                        order_result: msg.order_result,
-                        id: order_id,
-                    },
-                    std::time::Duration::from_secs(1),
-                );
+                self.retry_order_result(order_id, "OrderPrepared", msg, ctx);
+                return;
             }
+            self.result_retries.remove(&order_id);
             return;
         }
-        let line = "[RCH] I dont have a leader to send the OrderPrepared".to_string();
-        println!("{}", line.bright_yellow());
+        let line = "I dont have a leader to send the OrderPrepared".to_string();
+        log::warn("RCH", line.bright_yellow());
     }
 }
 
-/// Handles a message to send an OrderAborted to the leader
+/// Handles a message to send an OrderAborted to the leader, it is sent again following the retry policy if it fails
 impl Handler<OrderAborted> for RobotConnectionHandler {
     type Result = ();
-    fn handle(&mut self, msg: OrderAborted, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: OrderAborted, ctx: &mut Self::Context) -> Self::Result {
         let order_id = msg.id.clone();
         if let Some(leader) = &self.leader {
             if leader
                 .try_send(OrderAborted {
                     order_result: msg.order_result,
-                    id: msg.id,
-                    flavor: msg.flavor,
+                    id: msg.id.clone(),
+                    flavor: msg.flavor.clone(),
+                    reason: msg.reason,
                 })
                 .is_err()
             {
-                let line =
-                    "RCH: Error trying to send OrderAborted to Leader. Retrying in 1s.".to_string();
-                println!("{}", line.bright_yellow());
-                _ctx.notify_later(
-                    OrderAborted {
-                        order_result: msg.order_result,
-                        id: order_id,
-                        flavor: msg.flavor,
-                    },
-                    std::time::Duration::from_secs(1),
-                );
+                self.retry_order_result(order_id, "OrderAborted", msg, ctx);
+                return;
             }
+            self.result_retries.remove(&order_id);
             return;
         }
-        let line = "[RCH] I dont have a leader to send the OrderAborted".to_string();
-        println!("{}", line.bright_yellow());
+        let line = "I dont have a leader to send the OrderAborted".to_string();
+        log::warn("RCH", line.bright_yellow());
     }
 }
 
@@ -532,88 +1478,159 @@ impl Handler<GetNewOrder> for RobotConnectionHandler {
     fn handle(&mut self, msg: GetNewOrder, _ctx: &mut Self::Context) -> Self::Result {
         // println!("RCH: Recibi un nuevo pedido");
         if let Err(e) = self.order_manager.try_send(msg) {
-            print_send_error("[RCH]", "GetNewOrder", &e.to_string());
+            log::send_error("RCH", "GetNewOrder", &e.to_string());
         }
     }
 }
 
-/// Handles a message to store a backup of the leader
-impl Handler<StoreBackup> for RobotConnectionHandler {
-    type Result = ();
-    fn handle(&mut self, msg: StoreBackup, _ctx: &mut Self::Context) -> Self::Result {
-        self.leader_backup = Some(msg.backup);
-        self.leader_elector.validate_backup();
-    }
-}
-
 /// Handles a message to receive the candidates of a new election
-/// It checks if the round is finished, if it is, it chooses a new leader and sends it to the next robot
+/// A round whose candidates do not match its starter and hops is ignored.
+/// A round older than the leader or than the round the robot takes part in is dropped, so overlapping rounds merge into the greatest one.
+/// It checks if the round is finished, if it is, it chooses a new leader for the epoch of the round and sends it to the next robot.
+/// A round that comes back untouched lost the robot that had to finish it, and one that has the robot as a candidate
+/// from before it restarted can not be finished by it, so a new one is started
 impl Handler<ReceiveNewElection> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: ReceiveNewElection, ctx: &mut Self::Context) -> Self::Result {
-        if self
+        let round = msg.election;
+        if let Err(reason) = check_round_metadata(
+            self.leader_elector.as_ref(),
+            round,
+            msg.hops,
+            &msg.candidates,
+            config::number_of_robots(),
+        ) {
+            let line = format!("Ignoring the election round {}, {}", round, reason);
+            log::warn("RCH", line.bright_red());
+            return;
+        }
+        let check = self
+            .election_rounds
+            .check(round, &msg.candidates, self.leader_epoch);
+        if check == RoundCheck::Stale {
+            let line = format!("Dropping the stale election round {}", round);
+            log::info("RCH", line.bright_yellow());
+            return;
+        }
+        let finished = self
             .leader_elector
-            .check_round_finished(msg.candidates.clone())
-        {
-            let line = "[RCH] Round finished, choosing new leader".to_string();
-            println!("{}", line.bright_yellow());
+            .check_round_finished(msg.candidates.clone());
+        if finished && !self.election_rounds.took_part() {
+            let line = format!(
+                "Round {} has me as a candidate from before I rejoined, starting a new one",
+                round
+            );
+            log::warn("RCH", line.bright_yellow());
+            self.election_rounds.finish();
+            ctx.notify(StartElection());
+        } else if finished {
+            let line = format!("Round {} finished, choosing new leader", round);
+            log::info("RCH", line.bright_yellow());
+            self.election_rounds.finish();
             let new_leader = self.leader_elector.choose_leader(msg.candidates.clone());
+            let epoch = round.epoch;
 
             if self.my_id == new_leader {
                 if let Err(e) = ctx.address().try_send(SetNewLeader {
                     leader_id: new_leader,
                     by_election: true,
+                    epoch,
                 }) {
-                    print_send_error("[RCH]", "SetNewLeader", &e.to_string());
+                    log::send_error("RCH", "SetNewLeader", &e.to_string());
                 }
             } else {
-                self.safe_send_new_leader(new_leader, ctx);
+                self.leader_epoch = epoch;
+                self.safe_send_new_leader(new_leader, epoch, ctx);
             }
+        } else if check == RoundCheck::Returned {
+            let line = format!("Round {} came back unfinished, starting a new one", round);
+            log::warn("RCH", line.bright_yellow());
+            self.election_rounds.finish();
+            ctx.notify(StartElection());
         } else {
-            let line = "[RCH] Adding myself to the election candidates".to_string();
-            println!("{}", line.bright_yellow());
+            let line = "Adding myself to the election candidates".to_string();
+            log::info("RCH", line.bright_yellow());
+            self.transition(RingEvent::Elect);
             let candidates = self.leader_elector.add_candidate(msg.candidates.clone());
-            self.safe_send_election(candidates, ctx);
+            self.election_rounds.forwarded(&candidates);
+            self.safe_send_election(round, msg.hops + 1, candidates, ctx);
         }
     }
 }
 
 /// Handles a message to start a new election
+/// It is not started while the robot takes part in a round for a newer epoch than the leader, that round elects the leader
 impl Handler<StartElection> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, _msg: StartElection, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(round) = self.election_rounds.in_progress(self.leader_epoch) {
+            let line = format!("Election round {} in progress, not starting another", round);
+            log::info("RCH", line.bright_yellow());
+            return;
+        }
+        metrics::get().election_started();
+        self.transition(RingEvent::Elect);
         let candidates = self.leader_elector.start_election();
-        self.safe_send_election(candidates, ctx);
+        let round = self
+            .election_rounds
+            .start(self.my_id, self.leader_epoch, &candidates);
+        self.safe_send_election(round, 0, candidates, ctx);
     }
 }
 
 /// Handles a message to recover a lost token using a backup
+/// A recovery whose epoch was already seen is stale, the token was recovered by someone else, so it is dropped
 impl Handler<GetTokenBackup> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: GetTokenBackup, ctx: &mut Self::Context) -> Self::Result {
         let token_backup = msg.token_backup;
-        let flavor_id = token_backup.get_flavor_id();
+        let key = token_backup.key();
+        let started_by_me = token_backup.get_start_robot_id() == self.my_id;
 
-        if token_backup.get_start_robot_id() == self.my_id
-            && self.token_backup_msg.contains(&flavor_id)
+        if !self
+            .token_epochs
+            .is_recovery_current(&key, token_backup.get_epoch())
         {
-            let line = "[RCH] Round finished, restored token using backup".to_string();
-            println!("{}", line.bright_yellow());
-            self.token_backup_msg.retain(|&x| x != flavor_id);
-            let token = FlavorToken::new(flavor_id, token_backup.get_amount());
-            self.safe_send_token(token, ctx);
+            let line = format!(
+                "Dropping a stale recovery of {} ({}) from epoch {}",
+                key.0,
+                key.1,
+                token_backup.get_epoch()
+            );
+            log::info("RCH", line.bright_yellow());
+            if started_by_me {
+                self.token_backup_msg.retain(|x| *x != key);
+            }
             return;
         }
-        if token_backup.get_start_robot_id() > self.my_id
-            && self.token_backup_msg.contains(&flavor_id)
-        {
-            println!("[RCH] Another robot with higher ID is handeling the token recovery");
-            self.token_backup_msg.retain(|&x| x != flavor_id);
+
+        if started_by_me {
+            if !self.token_backup_msg.contains(&key) {
+                return;
+            }
+            let line = "Round finished, restored token using backup".to_string();
+            log::info("RCH", line.bright_yellow());
+            self.token_backup_msg.retain(|x| *x != key);
+            let mut token = FlavorToken::with_epoch(
+                key.0.clone(),
+                token_backup.get_amount(),
+                token_backup.get_epoch(),
+            )
+            .in_shard(key.1);
+            self.token_epochs.accept_token(&mut token);
+            self.safe_send_token(token, ctx);
             return;
         }
+        if token_backup.get_start_robot_id() > self.my_id && self.token_backup_msg.contains(&key) {
+            log::info(
+                "RCH",
+                "Another robot with higher ID is handeling the token recovery",
+            );
+            self.token_backup_msg.retain(|x| *x != key);
+        }
 
         if let Err(e) = self.order_manager.try_send(GetTokenBackup { token_backup }) {
-            print_send_error("[RCH]", "GetTokenBackUp", &e.to_string());
+            log::send_error("RCH", "GetTokenBackUp", &e.to_string());
         }
     }
 }
@@ -622,11 +1639,105 @@ impl Handler<GetTokenBackup> for RobotConnectionHandler {
 impl Handler<SendTokenBackup> for RobotConnectionHandler {
     type Result = ();
     fn handle(&mut self, msg: SendTokenBackup, ctx: &mut Self::Context) -> Self::Result {
-        let flavor_id = msg.token_backup.get_flavor_id();
-
         if msg.token_backup.get_start_robot_id() == self.my_id {
-            self.token_backup_msg.push(flavor_id);
+            self.token_backup_msg.push(msg.token_backup.key());
         }
         self.safe_send_token_backup(msg.token_backup, ctx);
     }
 }
+
+/// Handles a message to restock a flavor.
+/// The leader's robot keeps it until the token passes by, any other robot forwards it through the ring
+impl Handler<RestockFlavor> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: RestockFlavor, ctx: &mut Self::Context) -> Self::Result {
+        if !self.leads() {
+            self.safe_send_restock(msg.flavor, msg.amount, ctx);
+            return;
+        }
+        let line = format!(
+            "Restock of {} grams of {} waiting for its token",
+            msg.amount, msg.flavor
+        );
+        log::info("RCH", line.bright_green());
+        self.pending_restocks.add(msg.flavor, msg.amount);
+    }
+}
+
+/// Handles the GetRobotStatus message, it returns the place of the robot in the ring
+impl Handler<GetRobotStatus> for RobotConnectionHandler {
+    type Result = MessageResult<GetRobotStatus>;
+    fn handle(&mut self, _msg: GetRobotStatus, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(RobotStatus {
+            robot_id: self.my_id,
+            state: self.state,
+            leader_id: self.leader_id(),
+            leader_epoch: self.leader_epoch,
+            next_robot_id: self.next_robot_id,
+            is_leader: self.leads(),
+            leaving: self.leaving,
+            orders_in_progress: Vec::new(),
+        })
+    }
+}
+
+/// Handles the LeaveRing message, the robot stops getting new orders and leaves the ring once the ones it has are done
+/// The leader can not leave without an election, so it just exits
+impl Handler<LeaveRing> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, _msg: LeaveRing, ctx: &mut Self::Context) -> Self::Result {
+        if self.leaving {
+            return;
+        }
+        if self.leads() {
+            let line = "I am the Leader, leaving will start an election".to_string();
+            log::warn("RCH", line.yellow());
+            System::current().stop();
+            return;
+        }
+        self.leaving = true;
+        let line = "Leaving the ring, waiting for my orders to be done".to_string();
+        log::info("RCH", line.bright_cyan());
+
+        if let Some(leader) = &self.leader {
+            if let Err(e) = leader.try_send(RobotLeaving {
+                robot_id: self.my_id,
+            }) {
+                log::send_error("RCH", "RobotLeaving", &e.to_string());
+            }
+        }
+        self.wait_orders_to_leave(ctx);
+    }
+}
+
+/// Handles a robot leaving the ring.
+/// If it is the next robot, it connects to the one after it, if not, the message is forwarded through the ring
+impl Handler<RobotLeaving> for RobotConnectionHandler {
+    type Result = ();
+    fn handle(&mut self, msg: RobotLeaving, ctx: &mut Self::Context) -> Self::Result {
+        let leaving_id = msg.robot_id;
+        if leaving_id == self.my_id {
+            return;
+        }
+        if self.next_robot_id != Some(leaving_id) {
+            self.safe_send_leave_ring(leaving_id, ctx);
+            return;
+        }
+
+        let line = format!(
+            "Robot {} is leaving, connecting to the robot after it",
+            leaving_id
+        );
+        log::info("RCH", line.bright_cyan());
+        let addr = ctx.address();
+        let my_id = self.my_id;
+        async move {
+            if let Err(e) = connect_to_robot_after(leaving_id, my_id, addr).await {
+                let line = format!("Could not connect to a robot after {}: {}", leaving_id, e);
+                log::error("RCH", line.bright_yellow());
+            }
+        }
+        .into_actor(self)
+        .spawn(ctx);
+    }
+}
@@ -1,47 +1,106 @@
+use crate::common::log::{self, Level};
+use crate::common::mailbox::{self, Probe};
 use actix::prelude::*;
 use colored::*;
-use std::collections::{HashMap, VecDeque};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::tcp::OwnedReadHalf;
-use tokio::net::TcpStream;
-use tokio_stream::wrappers::LinesStream;
-
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::Instant;
+
+use crate::common::codec;
+use crate::common::error::FreddoError;
 use crate::common::flavor_id::FlavorID;
-use crate::config::MAX_NUMBER_OF_SCREENS;
+use crate::common::metrics;
+use crate::common::order::{Order, Substitution};
+use crate::common::order_source::OrderSource;
+use crate::common::utils::{connect, retry_backoff, should_retry};
+use crate::common::webhook;
+use crate::config;
+use crate::config::tokens::TokenStart;
+use crate::protocol::backup_delta::BackupStream;
+use crate::protocol::backup_seal::SealedBackup;
+use crate::protocol::compression::Compression;
+use crate::protocol::flavor_token::FlavorToken;
+use crate::protocol::leader_backup::LeaderBackup;
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::order_ledger::{OrderLedger, OrderState};
+use crate::protocol::order_waiting::OrderWaiting;
+use crate::protocol::robot_messages::AbortReason;
+use crate::robot::accounting::StockAccounts;
+use crate::robot::backup_store::{persist_leader_backup, restore_leader_backup};
+use crate::robot::cluster_state::{self, ClusterState, Completion, RobotOrders};
 use crate::robot::connections::leader_to_robot_connection::LeaderToRobotConnection;
 use crate::robot::connections::leader_to_screen_connection::LeaderToScreenConnection;
-use crate::robot::flavor_token::FlavorToken;
-use crate::robot::leader_backup::LeaderBackup;
+use crate::robot::connections::leader_to_shard_connection::LeaderToShardConnection;
+use crate::robot::consumption_report::{ConsumptionBook, ConsumptionReport};
+use crate::robot::dead_letters::DeadLetterBox;
+use crate::robot::maintenance::{self, Unavailability};
 use crate::robot::messages::*;
-use crate::robot::order_info::OrderInfo;
-use crate::robot::order_waiting::OrderWaiting;
+use crate::robot::order_journal::{self, JournalEvent};
+use crate::robot::queue_status::{self, QueueDepth};
 use crate::robot::robot_connection_handler::RobotConnectionHandler;
+use crate::robot::scheduler::Scheduler;
+use crate::robot::screen_sequences::ScreenSequences;
+use crate::robot::screen_stats::ScreenStatsBook;
+use crate::robot::shard_routes::{Part, RouteOutcome, ShardRoutes};
+use crate::robot::shop_book::ShopBook;
+use crate::robot::stock_watch::{self, StockAlert, StockLevel, Unservable};
+use crate::robot::token_placement::place_tokens;
 use crate::robot::utils::*;
 
-pub const INITIAL_TOKENS: &[(FlavorID, usize)] = &[
-    (FlavorID::Chocolate, INITIAL_AMOUNT - 2800),
-    (FlavorID::Vanilla, INITIAL_AMOUNT),
-    (FlavorID::Strawberry, INITIAL_AMOUNT),
-    (FlavorID::Mint, INITIAL_AMOUNT),
-    (FlavorID::Pistachio, INITIAL_AMOUNT),
-    (FlavorID::DulceDeLeche, INITIAL_AMOUNT),
-    (FlavorID::Lemon, INITIAL_AMOUNT),
-];
+/// How often the leader looks for orders that passed their deadline
+const DEADLINE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+/// Time the leader waits after handing off its leadership, so its last messages are written before it stops
+const HANDOFF_GRACE: Duration = Duration::from_millis(500);
 
 /// Actor that represents the Robot Leader, it manages the duties of the robots and the connection with the screens
 /// Can be initialized as the first leader or as a backup leader
 /// Receives Orders from the Screens, sends them to the RCH to be prepared and then informs the Screen if it was successfull or aborted
+/// Each robot can prepare up to `orders.max_concurrent` orders, so `available_robots` has a robot id once for every free slot it has
+/// New orders go to the robot with a free slot that is expected to finish its scoops first
+/// A robot that is leaving the ring gets no new orders, its slots are not freed again when its orders finish
+/// A robot or screen whose connection is closed has some time to connect again before it is treated as dead
+/// Its epoch goes in every message to the robots and screens, when one of them knows a newer epoch the leader steps down
+/// An order with a deadline that its robot did not finish in time is cancelled at the robot and aborted to the screen
+/// A new order that needs a flavor below its low watermark is rejected if the flavor is configured to do so
+/// The robots get the changes of each backup, and the whole backup when they connect or ask for it
+/// The results it could not send are tried again from time to time, and after too many attempts or too long they go to the dead letters
+/// It keeps the accounts of the stock of each flavor and checks them from time to time against the grams seen on the tokens
+/// An order with flavors of other leader shards is split, and each part is passed to the leader of its shard
 pub struct RobotLeader {
     my_id: usize,
+    epoch: u64,
     first_leader: bool,
     available_robots: Vec<usize>,
     orders_on_queue: VecDeque<OrderInfo>,
     my_robot: Option<Addr<RobotConnectionHandler>>,
     robots_connections: HashMap<usize, Addr<LeaderToRobotConnection>>,
-    robots_orders: HashMap<usize, OrderInfo>,
+    robots_orders: HashMap<usize, Vec<OrderInfo>>,
     screens_connections: HashMap<usize, Addr<LeaderToScreenConnection>>,
     screen_ids: Vec<usize>,
     orders_to_be_sent: Vec<OrderWaiting>,
+    scheduler: Scheduler,
+    ledger: OrderLedger,
+    leaving_robots: HashSet<usize>,
+    reconnecting_robots: HashSet<usize>,
+    reconnecting_screens: HashSet<usize>,
+    recent_completions: VecDeque<Completion>,
+    assigned_at: HashMap<String, Instant>,
+    rejecting: HashSet<FlavorID>,
+    compressions: HashMap<usize, Compression>,
+    backups: BackupStream,
+    screen_stats: ScreenStatsBook,
+    shops: ShopBook,
+    consumption: ConsumptionBook,
+    accounts: StockAccounts,
+    unavailable: Unavailability,
+    dead_letters: DeadLetterBox,
+    screen_sequences: ScreenSequences,
+    routes: ShardRoutes,
+    shard_links: HashMap<usize, Addr<LeaderToShardConnection>>,
+    linking_shards: HashSet<usize>,
 }
 
 impl Actor for RobotLeader {
@@ -50,17 +109,65 @@ impl Actor for RobotLeader {
     /// Starts the Leader, if it is the first leader it will start the tokens and connect to all screens
     /// If it is a backup leader it will connect to the robots and screens that were connected to the previous leader
     fn started(&mut self, ctx: &mut Self::Context) {
+        mailbox::watch("leader", ctx);
         start_leader_connection_listener(ctx.address(), self.my_id);
+        if let Some(my_robot) = &self.my_robot {
+            if let Err(e) = my_robot.try_send(LeaderStarted {
+                leader: ctx.address(),
+            }) {
+                log::send_error("RL", "LeaderStarted", &e.to_string());
+            }
+        }
+        self.start_restock_timer(ctx);
+        ctx.run_interval(DEADLINE_CHECK_INTERVAL, |actor, _| {
+            actor.abort_overdue_orders()
+        });
+        let check_interval = Duration::from_millis(config::get().dead_letter.check_interval_ms);
+        ctx.run_interval(check_interval, |actor, _| actor.retry_stashed_results());
+        let accounting = &config::get().accounting;
+        if accounting.check_interval_ms > 0 {
+            let check_interval = Duration::from_millis(accounting.check_interval_ms);
+            ctx.run_interval(check_interval, |actor, _| actor.check_stock_accounts());
+        }
+        let snapshot_ms = config::get().orders.stock_snapshot_ms;
+        if snapshot_ms > 0 {
+            let interval = Duration::from_millis(snapshot_ms);
+            ctx.run_interval(interval, |actor, _| actor.send_stock_snapshot());
+        }
         if self.first_leader {
-            self.start_tokens();
+            self.start_tokens(ctx);
             self.setup_all_screen_connections(ctx);
         } else {
             let line = "Creating leader from backup!".to_string();
-            println!("{}", line.bright_cyan());
+            log::info("RL", line.bright_cyan());
             self.setup_robot_connections(ctx);
             self.setup_screen_connections(ctx, self.screen_ids.clone());
             self.screen_ids.clear();
         }
+        for shard in self.routes.pending_shards() {
+            self.link_shard(shard, 0, ctx);
+        }
+    }
+}
+
+impl Handler<Probe> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Probe, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+/// Starts at its own robot the tokens a robot could not take
+impl Handler<TokensNotStarted> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: TokensNotStarted, _ctx: &mut Self::Context) -> Self::Result {
+        let line = format!(
+            "Robot {} could not take {} tokens, starting them here",
+            msg.robot_id,
+            msg.all_tokens.len()
+        );
+        log::warn("RL", line.yellow());
+        self.start_own_tokens(msg.all_tokens);
     }
 }
 
@@ -69,6 +176,7 @@ impl RobotLeader {
     pub fn new(my_id: usize, my_robot: Option<Addr<RobotConnectionHandler>>) -> Self {
         Self {
             my_id,
+            epoch: 0,
             first_leader: true,
             available_robots: Vec::new(),
             orders_on_queue: VecDeque::new(),
@@ -78,6 +186,26 @@ impl RobotLeader {
             screens_connections: HashMap::new(),
             screen_ids: Vec::new(),
             orders_to_be_sent: Vec::new(),
+            scheduler: Scheduler::new(),
+            ledger: OrderLedger::new(),
+            leaving_robots: HashSet::new(),
+            reconnecting_robots: HashSet::new(),
+            reconnecting_screens: HashSet::new(),
+            recent_completions: VecDeque::new(),
+            assigned_at: HashMap::new(),
+            rejecting: HashSet::new(),
+            compressions: HashMap::new(),
+            backups: BackupStream::new(config::get().backup.full_every),
+            screen_stats: ScreenStatsBook::new(),
+            shops: ShopBook::new(),
+            consumption: ConsumptionBook::new(),
+            accounts: StockAccounts::new(config::get().accounting.confirm_checks),
+            unavailable: Unavailability::new(),
+            dead_letters: DeadLetterBox::from_config(),
+            screen_sequences: ScreenSequences::new(),
+            routes: ShardRoutes::from_config(),
+            shard_links: HashMap::new(),
+            linking_shards: HashSet::new(),
         }
     }
 
@@ -88,8 +216,12 @@ impl RobotLeader {
         mut backup: LeaderBackup,
     ) -> Self {
         remove_me_from_backup(&mut backup, my_id);
+        recover_lost_orders(&mut backup);
+        let mut routes = ShardRoutes::from_config();
+        routes.restore(backup.routed);
         Self {
             my_id,
+            epoch: 0,
             first_leader: false,
             available_robots: backup.available_robots,
             orders_on_queue: backup.orders_on_queue,
@@ -99,25 +231,187 @@ impl RobotLeader {
             screens_connections: HashMap::new(),
             screen_ids: backup.screens,
             orders_to_be_sent: backup.orders_to_be_sent,
+            scheduler: Scheduler::new(),
+            ledger: backup.ledger,
+            leaving_robots: HashSet::new(),
+            reconnecting_robots: HashSet::new(),
+            reconnecting_screens: HashSet::new(),
+            recent_completions: VecDeque::new(),
+            assigned_at: HashMap::new(),
+            rejecting: HashSet::new(),
+            compressions: HashMap::new(),
+            backups: BackupStream::new(config::get().backup.full_every),
+            screen_stats: ScreenStatsBook::new(),
+            shops: ShopBook::from_counters(backup.shops),
+            consumption: ConsumptionBook::new(),
+            accounts: StockAccounts::new(config::get().accounting.confirm_checks),
+            unavailable: Unavailability::new(),
+            dead_letters: DeadLetterBox::from_config(),
+            screen_sequences: ScreenSequences::new(),
+            routes,
+            shard_links: HashMap::new(),
+            linking_shards: HashSet::new(),
+        }
+    }
+
+    /// Creates a new Robot Leader from the snapshot stored on disk, used after a full cluster restart.
+    /// Every robot was restarted, so the orders they were preparing are queued again and the tokens are started from scratch
+    pub fn from_snapshot(
+        my_id: usize,
+        my_robot: Option<Addr<RobotConnectionHandler>>,
+    ) -> Option<Self> {
+        let mut backup = restore_leader_backup(my_id)?;
+        let assigned_orders: Vec<OrderInfo> =
+            backup.robots_orders.drain().flat_map(|(_, o)| o).collect();
+        for order in assigned_orders {
+            backup.orders_on_queue.push_front(order);
         }
+        backup.available_robots.clear();
+        backup.screens.clear();
+
+        let line = format!(
+            "Restored {} orders from the snapshot on disk",
+            backup.orders_on_queue.len()
+        );
+        log::info("RL", line.bright_cyan());
+
+        let mut leader = Self::from_backup(my_id, my_robot, backup);
+        leader.first_leader = true;
+        Some(leader)
+    }
+
+    /// Sets the epoch of the leader, given by the election that chose it
+    pub fn in_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Passes the parts of the orders to the leaders of the given shards instead of the ones of the config,
+    /// the orders being routed are kept
+    pub fn with_routes(mut self, mut routes: ShardRoutes) -> Self {
+        routes.restore(self.routes.orders());
+        self.routes = routes;
+        self
     }
 
-    /// Sets up the connections to the screens that are passed as parameters
+    /// Sets up the connections to the screens that are passed as parameters,
+    /// the leaders of other shards connect again by themselves
     fn setup_screen_connections(&mut self, ctx: &mut Context<Self>, ids: Vec<usize>) {
-        println!("[RL] Connecting to Screens: {:?}", ids);
-        for screen_id in ids {
-            let addr = ctx.address().clone();
-            async move {
-                connect_to_screen(screen_id, addr).await;
-            }
+        log::info("RL", format!("Connecting to Screens: {:?}", ids));
+        for screen_id in ids.into_iter().filter(|id| !is_shard_link(*id)) {
+            self.start_screen_connection(screen_id, 0, ctx);
+        }
+    }
+
+    /// Connects to a screen, a failure comes back to the leader as a ConnectionFailed message
+    fn start_screen_connection(&mut self, screen_id: usize, attempt: u32, ctx: &mut Context<Self>) {
+        let addr = ctx.address();
+        async move { connect_to_screen(screen_id, addr).await }
             .into_actor(self)
+            .map(move |result, _, ctx| {
+                if let Err(error) = result {
+                    ctx.notify(ConnectionFailed {
+                        peer: Peer::Screen(screen_id),
+                        error,
+                        attempt,
+                    });
+                }
+            })
             .wait(ctx);
+    }
+
+    /// Gives back the free slots of a robot that reconnected, the orders it is preparing keep theirs
+    fn free_slots_of(&mut self, robot_id: usize) {
+        let busy = self
+            .robots_orders
+            .get(&robot_id)
+            .map_or(0, |orders| orders.len());
+        let free = config::get().orders.max_concurrent.saturating_sub(busy);
+        for _ in 0..free {
+            self.available_robots.push(robot_id);
+        }
+        for _ in 0..free {
+            self.assign_new_order();
+        }
+        self.make_and_send_backup();
+    }
+
+    /// Splits the orders in flight of a robot into the ones it resumes and the ones it drops.
+    /// It resumes the ones still assigned to it, and the ones that were queued again while it has free slots,
+    /// those are assigned to it again. The others were given to another robot or are finished
+    fn take_back_orders(
+        &mut self,
+        robot_id: usize,
+        orders: Vec<String>,
+    ) -> (Vec<String>, Vec<String>) {
+        let (mut resume, mut drop) = (vec![], vec![]);
+        for order_id in orders {
+            let assigned = self
+                .robots_orders
+                .get(&robot_id)
+                .is_some_and(|orders| orders.iter().any(|o| o.order_id == order_id));
+            if assigned {
+                resume.push(order_id);
+                continue;
+            }
+            let queued = self
+                .orders_on_queue
+                .iter()
+                .position(|o| o.order_id == order_id);
+            let slot = self.available_robots.iter().position(|&id| id == robot_id);
+            let order_info = match (queued, slot) {
+                (Some(i), Some(slot)) => {
+                    self.available_robots.remove(slot);
+                    self.orders_on_queue.remove(i)
+                }
+                _ => None,
+            };
+            match order_info {
+                Some(order_info) => {
+                    let line = format!(
+                        "Order {} goes back to Robot {}, that was preparing it",
+                        order_id, robot_id
+                    );
+                    log::traced(
+                        Level::Info,
+                        "RL",
+                        order_info.trace_id(),
+                        line.bright_green(),
+                    );
+                    self.scheduler.assigned(&order_id);
+                    self.assigned_at.insert(order_id.clone(), Instant::now());
+                    self.robots_orders
+                        .entry(robot_id)
+                        .or_default()
+                        .push(order_info);
+                    resume.push(order_id);
+                }
+                None => drop.push(order_id),
+            }
+        }
+        (resume, drop)
+    }
+
+    /// Removes a robot that is gone, its orders are queued again
+    fn remove_robot(&mut self, robot_id: usize) {
+        self.available_robots.retain(|&id| id != robot_id);
+        self.unavailable.remove(robot_id);
+        if let Some(orders) = self.robots_orders.remove(&robot_id) {
+            for order in orders.into_iter().rev() {
+                self.scheduler.finished(&order.order_id);
+                self.assigned_at.remove(&order.order_id);
+                self.orders_on_queue.push_front(order);
+                self.assign_new_order();
+            }
         }
+        self.robots_connections.remove(&robot_id);
+        self.compressions.remove(&robot_id);
+        self.make_and_send_backup();
     }
 
     /// Sets up the connections to all screens
     fn setup_all_screen_connections(&mut self, ctx: &mut Context<Self>) {
-        let screen_ids = (0..MAX_NUMBER_OF_SCREENS).collect::<Vec<usize>>();
+        let screen_ids = (0..config::number_of_screens()).collect::<Vec<usize>>();
         self.setup_screen_connections(ctx, screen_ids);
     }
 
@@ -125,40 +419,53 @@ impl RobotLeader {
     fn setup_robot_connections(&mut self, ctx: &mut Context<Self>) {
         let mut robots_ids = self.available_robots.clone();
         robots_ids.extend_from_slice(&self.robots_orders.keys().cloned().collect::<Vec<usize>>());
+        robots_ids.sort();
+        robots_ids.dedup();
 
-        let line = format!("[RL] Connecting to Robots: {:?}", robots_ids);
-        println!("{}", line.bright_cyan());
+        let line = format!("Connecting to Robots: {:?}", robots_ids);
+        log::info("RL", line.bright_cyan());
 
         for robot_id in robots_ids {
             let address = ctx.address().clone();
             let my_id = self.my_id;
+            let epoch = self.epoch;
 
             if my_id == robot_id {
                 continue;
             }
 
             async move {
-                match TcpStream::connect(id_to_robot_addr(robot_id)).await {
+                match connect(&id_to_robot_addr(robot_id)).await {
                     Ok(stream) => {
-                        let (read_half,mut write_half) = stream.into_split();
-                        if let Err(e) = write_half.write_all(&[NEW_ROBOT_LEADER as u8]).await {
-                            let line = format!("[RL] Error! Could not send new leader robot to robot {}. Error: {}", robot_id, e);
-                            println!("{}", line.bright_cyan());
-                            return;
-                        }
-                        if let Err(e) = write_half.write_all(&[my_id as u8]).await {
-                            let line = format!("[RL] Error! Could not send new leader ID robot to robot {}. Error: {}", robot_id, e);
-                            println!("{}", line.bright_cyan());
+                        let (read_half, mut write_half) = stream.into_split();
+                        let handshake = RobotCommand::NewLeaderConnection {
+                            leader_id: my_id,
+                            epoch,
+                        };
+                        if let Err(e) = write_handshake(&mut write_half, handshake).await {
+                            let line = format!(
+                                "Error! Could not send new leader robot to robot {}. Error: {}",
+                                robot_id, e
+                            );
+                            log::error("RL", line.bright_cyan());
                             return;
                         }
 
-                        if let Err(e) = address.try_send(AddNewRobot{robot_id, read_half, write_half, asked: true}) {
-                            print_send_error("[RL]", "AddNewRobot", &e.to_string());
+                        if let Err(e) = address.try_send(AddNewRobot {
+                            robot_id,
+                            read_half,
+                            write_half,
+                            asked: true,
+                        }) {
+                            log::send_error("RL", "AddNewRobot", &e.to_string());
                         }
-                    },
+                    }
                     Err(e) => {
-                        let line = format!("[RL] Error! Could not connecto to robot: {}. Error: {}", robot_id, e);
-                        println!("{}", line.bright_cyan());
+                        let line = format!(
+                            "Error! Could not connecto to robot: {}. Error: {}",
+                            robot_id, e
+                        );
+                        log::error("RL", line.bright_cyan());
                     }
                 };
             }
@@ -167,59 +474,174 @@ impl RobotLeader {
         }
     }
 
-    /// Starts the flavor tokens with the initial values
-    fn start_tokens(&mut self) {
-        let initial_tokens: Vec<FlavorToken> = INITIAL_TOKENS
-            .iter()
-            .map(|(flavor, amount)| FlavorToken::new(*flavor, *amount))
-            .collect();
+    /// Starts the timer that restocks every flavor of the catalog, if it is enabled in the config
+    fn start_restock_timer(&mut self, ctx: &mut Context<Self>) {
+        let restock = &config::get().restock;
+        if restock.interval_secs == 0 {
+            return;
+        }
+        let amount = restock.amount;
+        ctx.run_interval(Duration::from_secs(restock.interval_secs), move |_, ctx| {
+            for stock in config::get().flavors.flavors() {
+                ctx.notify(RestockFlavor {
+                    flavor: stock.flavor.clone(),
+                    amount,
+                });
+            }
+        });
+    }
+
+    /// Tells every screen the grams left of each flavor, so they turn away the orders that can not be served
+    /// before capturing their payment
+    fn send_stock_snapshot(&self) {
+        let grams = stock_watch::snapshot(&config::get().flavors, &metrics::get().stock());
+        for screen in self.screens_connections.values() {
+            if let Err(e) = screen.try_send(SendStockSnapshot {
+                grams: grams.clone(),
+            }) {
+                log::send_error("RL", "SendStockSnapshot", &e.to_string());
+            }
+        }
+    }
+
+    /// Starts the flavor tokens with the initial values of the flavor catalog.
+    /// Unless they all start at the leader's robot, the robots have the warm up of the config to connect first
+    fn start_tokens(&mut self, ctx: &mut Context<Self>) {
+        let tokens = &config::get().tokens;
+        if tokens.start == TokenStart::Leader {
+            self.spread_tokens();
+            return;
+        }
+        let line = format!(
+            "Waiting {} ms for the robots before starting the tokens ({:?})",
+            tokens.warm_up_ms, tokens.start
+        );
+        log::info("RL", line.bright_cyan());
+        ctx.run_later(Duration::from_millis(tokens.warm_up_ms), |actor, _| {
+            actor.spread_tokens()
+        });
+    }
+
+    /// Creates the flavor tokens and hands each one to the robot it starts at, following the config.
+    /// The tokens of a robot that can not be reached start at the leader's robot
+    fn spread_tokens(&mut self) {
+        let config = config::get();
+        let initial_tokens = FlavorToken::from_catalog(&config.flavors, config.tokens.per_flavor);
+        self.accounts.open(&config.flavors);
+        let connected: Vec<usize> = self.robots_connections.keys().copied().collect();
+        let placed = place_tokens(
+            initial_tokens,
+            config.tokens.start,
+            &config.tokens.owners,
+            self.my_id,
+            &connected,
+        );
+        let mut own_tokens = Vec::new();
+        for (robot_id, all_tokens) in placed {
+            let connection = self.robots_connections.get(&robot_id);
+            match connection.filter(|_| robot_id != self.my_id) {
+                Some(robot) => {
+                    let line =
+                        format!("Starting {} tokens at Robot {}", all_tokens.len(), robot_id);
+                    log::info("RL", line.bright_cyan());
+                    if let Err(e) = robot.try_send(StartTokens { all_tokens }) {
+                        log::send_error("RL", "StartTokens", &e.to_string());
+                        own_tokens.extend(e.into_inner().all_tokens);
+                    }
+                }
+                None => own_tokens.extend(all_tokens),
+            }
+        }
+        self.start_own_tokens(own_tokens);
+    }
+
+    /// Starts the tokens at the robot of the leader, the ones no other robot took
+    fn start_own_tokens(&self, own_tokens: Vec<FlavorToken>) {
+        if own_tokens.is_empty() {
+            return;
+        }
         if let Some(my_robot) = &self.my_robot {
             if let Err(e) = my_robot.try_send(StartTokens {
-                all_tokens: initial_tokens,
+                all_tokens: own_tokens,
             }) {
-                print_send_error("[RL]", "StartTokens", &e.to_string());
+                log::send_error("RL", "StartTokens", &e.to_string());
             }
         } else {
-            let line = "RL: Error! No robot connection handler found".to_string();
-            println!("{}", line.bright_cyan());
+            let line = "Error! No robot connection handler found".to_string();
+            log::error("RL", line.bright_cyan());
+        }
+    }
+
+    /// Checks the stock accounts against the grams seen on the tokens, the flavors of the orders the robots are
+    /// preparing, or their substitutes, are left for a later check. Each violation is logged and counted
+    fn check_stock_accounts(&mut self) {
+        let mut busy = HashSet::new();
+        for order in self.robots_orders.values().flatten() {
+            for (flavor, substitutes) in order.order.get_substitutes() {
+                busy.extend(substitutes.iter().map(|s| s.name().to_string()));
+                busy.insert(flavor.name().to_string());
+            }
+            for (flavor, _) in order.order.get_flavors() {
+                busy.insert(flavor.name().to_string());
+            }
+        }
+        for violation in self.accounts.check(&metrics::get().stock(), &busy) {
+            metrics::get().stock_violation(violation.discrepancy.label());
+            let line = format!(
+                "Stock invariant violated! {} grams of {} were expected and its tokens have {} ({:?})",
+                violation.expected, violation.flavor, violation.observed, violation.discrepancy
+            );
+            log::error("RL", line.on_red());
         }
     }
 
-    /// Assigns a new order to a robot
+    /// Assigns a new order to the robot that is expected to be free soonest
     /// If there are no orders or robots available it will print an error message and do nothing
     fn assign_new_order(&mut self) {
-        if self.orders_on_queue.is_empty() || self.available_robots.is_empty() {
-            let line = "[RL] No orders or robots available".to_string();
-            println!("{}", line.bright_magenta());
+        let assignable = self
+            .unavailable
+            .assignable(&self.available_robots, maintenance::now_ms());
+        if self.orders_on_queue.is_empty() || assignable.is_empty() {
+            let line = "No orders or robots available".to_string();
+            log::info("RL", line.bright_magenta());
             return;
         }
         let order_info = match self.orders_on_queue.pop_front() {
             Some(order) => order,
             None => {
-                let line = "[RL] Error! No orders available, but we should have!".to_string();
-                println!("{}", line.bright_cyan());
+                let line = "Error! No orders available, but we should have!".to_string();
+                log::error("RL", line.bright_cyan());
                 return;
             }
         };
 
-        let robot_id = match self.available_robots.pop() {
-            Some(id) => id,
-            None => {
-                let line = "[RL] Error! No robots available, but there should be!".to_string();
-                println!("{}", line.bright_cyan());
-                self.orders_on_queue.push_front(order_info);
-                return;
-            }
-        };
+        let robot_id =
+            match self
+                .scheduler
+                .pick_robot(&order_info, &assignable, &self.robots_orders)
+            {
+                Some(id) => {
+                    if let Some(i) = self.available_robots.iter().position(|&r| r == id) {
+                        self.available_robots.remove(i);
+                    }
+                    id
+                }
+                None => {
+                    let line = "Error! No robots available, but there should be!".to_string();
+                    log::error("RL", line.bright_cyan());
+                    self.orders_on_queue.push_front(order_info);
+                    return;
+                }
+            };
 
         let robot = match self.robots_connections.get(&robot_id) {
             Some(robot) => robot,
             None => {
                 let line = format!(
-                    "[RL] Error! Robot {} not found in connections and pushed order {} back into the front",
+                    "Error! Robot {} not found in connections and pushed order {} back into the front",
                     robot_id, order_info.order_id
                 );
-                println!("{}", line.bright_cyan());
+                log::error("RL", line.bright_cyan());
                 self.available_robots.push(robot_id);
                 self.orders_on_queue.push_front(order_info);
                 return;
@@ -229,48 +651,396 @@ impl RobotLeader {
         if let Err(e) = robot.try_send(SendNewOrder {
             new_order: order_info.order.clone(),
             order_id: order_info.order_id.clone(),
+            trace_id: order_info.trace_id().to_string(),
         }) {
-            print_send_error("[RL]", "SendNewOrder", &e.to_string());
+            log::send_error("RL", "SendNewOrder", &e.to_string());
         }
 
         let line = format!(
-            "[RL] Assigning order {} to Robot {}",
+            "Assigning order {} to Robot {}",
             order_info.order_id, robot_id
         );
-        println!("{}", line.bright_green());
-        self.robots_orders.insert(robot_id, order_info);
+        log::traced(
+            Level::Info,
+            "RL",
+            order_info.trace_id(),
+            line.bright_green(),
+        );
+        self.scheduler.assigned(&order_info.order_id);
+        self.assigned_at
+            .insert(order_info.order_id.clone(), Instant::now());
+        self.robots_orders
+            .entry(robot_id)
+            .or_default()
+            .push(order_info);
     }
 
-    /// Adds a new order to the queue
-    /// If there are robots available it will assign the order to one of them
-    fn add_new_order(&mut self, order_info: OrderInfo) {
-        self.orders_on_queue.push_back(order_info);
-        if !self.available_robots.is_empty() {
+    /// Adds the new orders to the queue, the parts of the ones with flavors of other shards are passed to their leaders
+    /// If there are robots available it will assign the orders to them
+    fn add_new_orders(&mut self, orders: Vec<OrderInfo>, ctx: &mut Context<Self>) {
+        let (routed, orders): (Vec<OrderInfo>, Vec<OrderInfo>) = orders
+            .into_iter()
+            .partition(|o| self.routes.needs_routing(&o.order));
+        for order in routed {
+            for part in self.routes.route(order) {
+                self.pass_part(part, ctx);
+            }
+        }
+        if orders.is_empty() {
+            return;
+        }
+        self.orders_on_queue.extend(orders);
+        let assignable = self
+            .unavailable
+            .assignable(&self.available_robots, maintenance::now_ms())
+            .len();
+        if assignable == 0 {
+            let line = "No robots available, orders pushed to queue".to_string();
+            log::info("RL", line.bright_magenta());
+            return;
+        }
+        for _ in 0..assignable.min(self.orders_on_queue.len()) {
             self.assign_new_order();
-        } else {
-            let line = "[RL] No robots available, order pushed to queue".to_string();
-            println!("{}", line.bright_magenta());
         }
     }
 
-    /// Creates a backup with the current state and sends it to all robots
-    fn make_and_send_backup(&self) {
+    /// Passes the part to the leader of its shard, if it is not connected the parts are passed once it is
+    fn pass_part(&mut self, part: Part, ctx: &mut Context<Self>) {
+        match self.shard_links.get(&part.shard) {
+            Some(link) => {
+                if let Err(e) = link.try_send(PassPart { part }) {
+                    log::send_error("RL", "PassPart", &e.to_string());
+                }
+            }
+            None => self.link_shard(part.shard, 0, ctx),
+        }
+    }
+
+    /// Connects to the leader of the shard as the link screen of the shard of this leader,
+    /// a failure comes back to the leader as a ConnectionFailed message.
+    /// It does not wait for the connection, the leader of its own shard is this one
+    fn link_shard(&mut self, shard: usize, attempt: u32, ctx: &mut Context<Self>) {
+        if self.shard_links.contains_key(&shard) || !self.linking_shards.insert(shard) {
+            return;
+        }
+        let addrs = self.routes.leader_addrs(shard, self.my_id);
+        let cluster = self.routes.cluster();
+        let screen_id = cluster.link_screen_id(cluster.shard);
+        let connecting = async move { connect_to_shard_leader(&addrs, screen_id).await }
+            .into_actor(self)
+            .map(move |result, actor, ctx| {
+                actor.linking_shards.remove(&shard);
+                match result {
+                    Ok((read_half, write_half)) => {
+                        actor.add_shard_link(shard, screen_id, read_half, write_half, ctx)
+                    }
+                    Err(error) => ctx.notify(ConnectionFailed {
+                        peer: Peer::Shard(shard),
+                        error,
+                        attempt,
+                    }),
+                }
+            });
+        ctx.spawn(connecting);
+    }
+
+    /// Creates the actor of the connection with the leader of the shard and passes it the parts it did not answer
+    fn add_shard_link(
+        &mut self,
+        shard: usize,
+        screen_id: usize,
+        read_half: OwnedReadHalf,
+        write_half: OwnedWriteHalf,
+        ctx: &mut Context<Self>,
+    ) {
+        let line = format!("Connected to the leader of shard {}", shard);
+        log::info("RL", line.bright_cyan());
+        let leader = ctx.address();
+        let link = LeaderToShardConnection::create(|own_ctx| {
+            LeaderToShardConnection::add_stream(codec::frames(read_half), own_ctx);
+            LeaderToShardConnection::new(shard, screen_id, leader, Some(write_half))
+        });
+        for part in self.routes.pending_of(shard) {
+            if let Err(e) = link.try_send(PassPart { part }) {
+                log::send_error("RL", "PassPart", &e.to_string());
+            }
+        }
+        self.shard_links.insert(shard, link);
+    }
+
+    /// Tries again to connect to the leader of the shard while it has parts to answer, its robots may be electing one
+    fn retry_shard_link(
+        &mut self,
+        shard: usize,
+        error: FreddoError,
+        attempt: u32,
+        ctx: &mut Context<Self>,
+    ) {
+        if self.routes.pending_of(shard).is_empty() {
+            return;
+        }
+        let line = format!(
+            "Could not connect to the leader of shard {}: {}, retrying (attempt {})",
+            shard,
+            error,
+            attempt + 1
+        );
+        log::warn("RL", line.bright_cyan());
+        metrics::get().retried("shard_connection");
+        ctx.run_later(retry_backoff(attempt), move |actor, ctx| {
+            actor.link_shard(shard, attempt + 1, ctx);
+        });
+    }
+
+    /// Answers an order whose parts were finished by the leaders of their shards, or tells its screen to send it
+    /// again later if a leader could not take a part, its grams are given back to its shop
+    fn finish_routed_order(&mut self, order: OrderInfo, outcome: RouteOutcome) {
+        let (completed, flavor, reason, substitutions) = match outcome {
+            RouteOutcome::Busy => {
+                self.count_finished(&order, false);
+                self.reject_busy(order.order_id, order.screen_id);
+                return;
+            }
+            RouteOutcome::Prepared(substitutions) => {
+                order_journal::record(JournalEvent::Completed {
+                    order_id: order.order_id.clone(),
+                    robot_id: self.my_id,
+                });
+                self.ledger.completed(&order.order_id);
+                (true, None, AbortReason::default(), substitutions)
+            }
+            RouteOutcome::Aborted(flavor, reason) => {
+                order_journal::record(JournalEvent::Aborted {
+                    order_id: order.order_id.clone(),
+                    robot_id: self.my_id,
+                    flavor: flavor.clone(),
+                });
+                self.ledger.aborted(&order.order_id, flavor.clone());
+                (false, Some(flavor), reason, vec![])
+            }
+        };
+        let line = format!(
+            "Order {} {} by the leaders of its shards",
+            order.order_id,
+            if completed { "completed" } else { "aborted" }
+        );
+        log::traced(Level::Info, "RL", order.trace_id(), line.bright_green());
+        self.count_finished(&order, completed);
+        let result = OrderWaiting {
+            id: order.order_id,
+            order_result: completed,
+            screen_id: order.screen_id,
+            flavor,
+            reason,
+            substitutions,
+            attempts: 0,
+            stashed_at_ms: maintenance::now_ms(),
+        };
+        if !self.send_waiting_result(result.screen_id, &result) {
+            self.orders_to_be_sent.push(result);
+        }
+    }
+
+    /// Checks the ledger for an order that arrived from a screen.
+    /// Returns the order to be queued if it is new, if it is in progress it is ignored and if it was finished its result is sent again
+    /// A new order is rejected if the queue, counting the `accepted` orders of the same message, reached `max_queued`,
+    /// if it needs a flavor that is low and rejects orders, or if it has no flavors or a flavor without a token
+    fn accept_new_order(
+        &mut self,
+        order_id: String,
+        order: Order,
+        screen_id: usize,
+        deadline_ms: Option<u64>,
+        source: Option<OrderSource>,
+        accepted: usize,
+    ) -> Option<OrderInfo> {
+        match self.ledger.state(&order_id) {
+            Some(OrderState::InProgress) if self.is_in_progress(&order_id) => {
+                let line = format!("Order {} is already in progress, ignoring it", order_id);
+                log::info("RL", line.bright_magenta());
+                return None;
+            }
+            Some(state) if state != OrderState::InProgress => {
+                let line = format!(
+                    "Order {} was already finished, sending its result",
+                    order_id
+                );
+                log::info("RL", line.bright_magenta());
+                self.send_cached_result(order_id, screen_id, state);
+                return None;
+            }
+            _ => {}
+        }
+
+        let catalog = &config::get().flavors;
+        if let Some(unservable) = stock_watch::unservable(&order, catalog, self.routes.cluster()) {
+            self.count_rejected(screen_id, source.as_ref());
+            self.reject_unservable(order_id, screen_id, unservable);
+            return None;
+        }
+
+        if let Some(flavor) = stock_watch::low_flavor_of(&order, &self.rejecting) {
+            self.count_rejected(screen_id, source.as_ref());
+            let line = format!("{} is running low, rejecting order {}", flavor, order_id);
+            log::warn("RL", line.bright_magenta());
+            self.reject_for_flavor(order_id, screen_id, flavor, AbortReason::LowStock);
+            return None;
+        }
+
+        let shop = source.as_ref().and_then(|s| s.shop.as_deref());
+        if let Some((shop, flavor)) = shop.and_then(|shop| {
+            let flavor = self
+                .shops
+                .over_quota(shop, &order, &config::get().tenants)?;
+            Some((shop.to_string(), flavor))
+        }) {
+            self.count_rejected(screen_id, source.as_ref());
+            let line = format!(
+                "Shop {} used up its quota of {}, rejecting order {}",
+                shop, flavor, order_id
+            );
+            log::warn("RL", line.bright_magenta());
+            self.reject_for_flavor(order_id, screen_id, flavor, AbortReason::OverQuota);
+            return None;
+        }
+
+        let max_queued = config::get().orders.max_queued;
+        if max_queued > 0 && self.orders_on_queue.len() + accepted >= max_queued {
+            self.count_rejected(screen_id, source.as_ref());
+            self.reject_busy(order_id, screen_id);
+            return None;
+        }
+
+        let order_info = OrderInfo {
+            order,
+            order_id,
+            screen_id,
+            deadline_ms,
+            source,
+        };
+        let line = format!("Assigning order {}", order_info.order_id);
+        log::traced(
+            Level::Info,
+            "RL",
+            order_info.trace_id(),
+            line.bright_magenta(),
+        );
+        order_journal::record(JournalEvent::Created {
+            order: order_info.clone(),
+        });
+        self.ledger.created(&order_info.order_id);
+        self.screen_stats.placed(&order_info);
+        self.shops.placed(&order_info);
+        if let Some(shop) = order_info.source.as_ref().and_then(|s| s.shop.as_deref()) {
+            metrics::get().shop_order(shop, "placed");
+        }
+        Some(order_info)
+    }
+
+    /// Counts an order the leader did not take for its screen, and for its shop if it has one
+    fn count_rejected(&mut self, screen_id: usize, source: Option<&OrderSource>) {
+        self.screen_stats.rejected(screen_id, source);
+        if let Some(shop) = source.and_then(|s| s.shop.as_deref()) {
+            self.shops.rejected(shop);
+            metrics::get().shop_order(shop, "rejected");
+        }
+    }
+
+    /// Counts a finished order for its screen and its shop
+    fn count_finished(&mut self, order: &OrderInfo, completed: bool) {
+        self.screen_stats.finished(order, completed);
+        self.shops.finished(order, completed);
+        if let Some(shop) = order.source.as_ref().and_then(|s| s.shop.as_deref()) {
+            let outcome = if completed { "completed" } else { "aborted" };
+            metrics::get().shop_order(shop, outcome);
+            if completed {
+                for (flavor, grams) in order.order.get_flavors() {
+                    metrics::get().shop_grams_used(shop, &flavor, grams);
+                }
+            }
+        }
+    }
+
+    /// Creates a backup with the current state and stores it on disk
+    fn make_backup(&self) -> LeaderBackup {
         let backup = LeaderBackup::new(
             self.available_robots.clone(),
             self.screen_ids.clone(),
             self.orders_on_queue.clone(),
             self.robots_orders.clone(),
             self.orders_to_be_sent.clone(),
-        );
-        for robot in self.robots_connections.values() {
-            if let Err(e) = robot.try_send(SendLeaderBackup {
-                backup: backup.clone(),
-            }) {
-                print_send_error("[RL]", "SendLeaderBackup", &e.to_string());
+            self.ledger.clone(),
+        )
+        .with_shops(self.shops.counters().clone())
+        .with_routed(self.routes.orders());
+        persist_leader_backup(self.my_id, &backup);
+        backup
+    }
+
+    /// Creates a backup with the current state, stores it on disk and sends it sealed to all robots,
+    /// as the changes since the previous one unless it is time for a whole one
+    /// It is sealed once for each compression the robots were given
+    fn make_and_send_backup(&mut self) {
+        let update = self.backups.next(self.make_backup());
+        let raw = bincode::serialized_size(&update).unwrap_or(0) as usize;
+        let mut sealed: HashMap<Compression, SealedBackup> = HashMap::new();
+        for (robot_id, robot) in &self.robots_connections {
+            let compression = self.compression_for(*robot_id);
+            let backup = match sealed.entry(compression) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    match SealedBackup::seal(
+                        &update,
+                        self.epoch,
+                        &config::get().security,
+                        compression,
+                    ) {
+                        Ok(backup) => entry.insert(backup).clone(),
+                        Err(e) => {
+                            log::create_error("RL", "SealedBackup", &e.to_string());
+                            return;
+                        }
+                    }
+                }
+            };
+            metrics::get().backup_sent("leader", raw, backup.payload_len());
+            if let Err(e) = robot.try_send(SendLeaderBackup { backup }) {
+                log::send_error("RL", "SendLeaderBackup", &e.to_string());
             }
         }
     }
 
+    /// Returns the compression the backups of the robot are sent with, none until the robot tells which ones it reads
+    fn compression_for(&self, robot_id: usize) -> Compression {
+        self.compressions
+            .get(&robot_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sends the last backup whole to the robot, it does not have the ones before
+    fn send_full_backup_to(&self, robot_id: usize) {
+        let (Some(update), Some(robot)) =
+            (self.backups.full(), self.robots_connections.get(&robot_id))
+        else {
+            return;
+        };
+        let compression = self.compression_for(robot_id);
+        let backup =
+            match SealedBackup::seal(&update, self.epoch, &config::get().security, compression) {
+                Ok(backup) => backup,
+                Err(e) => {
+                    log::create_error("RL", "SealedBackup", &e.to_string());
+                    return;
+                }
+            };
+        let raw = bincode::serialized_size(&update).unwrap_or(0) as usize;
+        metrics::get().backup_sent("leader", raw, backup.payload_len());
+        if let Err(e) = robot.try_send(SendLeaderBackup { backup }) {
+            log::send_error("RL", "SendLeaderBackup", &e.to_string());
+        }
+    }
+
     /// Stashes an order to be sent later
     fn stash_order_waiting(
         &mut self,
@@ -278,73 +1048,517 @@ impl RobotLeader {
         order_result: bool,
         screen_id: usize,
         flavor: Option<FlavorID>,
+        reason: AbortReason,
+        substitutions: Vec<Substitution>,
     ) {
         let order = OrderWaiting {
             id: id.clone(),
             order_result,
             screen_id,
             flavor,
+            reason,
+            substitutions,
+            attempts: 0,
+            stashed_at_ms: maintenance::now_ms(),
         };
         self.orders_to_be_sent.push(order);
     }
 
-    /// Gets the result of an order from a robot and returns the screen to send the result
-    fn get_order_result(
-        &mut self,
-        robot_id: usize,
-        order_result: bool,
-        flavor: Option<FlavorID>,
-    ) -> Option<(Addr<LeaderToScreenConnection>, OrderInfo)> {
-        self.available_robots.push(robot_id);
-        let order_info = self.robots_orders.remove(&robot_id);
-
-        let order = match order_info {
-            Some(order) => order,
-            None => {
-                let line = format!("[RL] Error! Order not found for robot {}", robot_id);
-                println!("{}", line.bright_cyan());
-                return None;
+    /// Tries again to send the stashed results to their screens, the ones that can not be sent yet and were tried
+    /// too many times or are waiting for too long are moved to the dead letters
+    fn retry_stashed_results(&mut self) {
+        if self.orders_to_be_sent.is_empty() {
+            return;
+        }
+        let policy = &config::get().dead_letter;
+        let now = maintenance::now_ms();
+        let mut changed = false;
+        for mut order in std::mem::take(&mut self.orders_to_be_sent) {
+            if order.stashed_at_ms == 0 {
+                order.stashed_at_ms = now;
             }
-        };
-
-        let screen_id = order.screen_id;
-        if let Some(screen) = self.screens_connections.get(&screen_id) {
-            Some((screen.clone(), order))
-        } else {
-            let line = format!(
-                "[RL] Error! Screen {} not found, saved order to send later",
-                screen_id
-            );
-            println!("{}", line.bright_cyan());
-            self.stash_order_waiting(order.order_id, order_result, screen_id, flavor);
-            None
+            if self.screens_connections.contains_key(&order.screen_id) {
+                if self.send_waiting_result(order.screen_id, &order) {
+                    changed = true;
+                    continue;
+                }
+                order.attempts += 1;
+                changed = true;
+            }
+            if policy.is_dead(order.attempts, now.saturating_sub(order.stashed_at_ms)) {
+                let line = format!(
+                    "Giving up sending the result of order {} to Screen {} after {} attempts, it goes to the dead letters",
+                    order.id, order.screen_id, order.attempts
+                );
+                log::warn("RL", line.yellow());
+                self.dead_letters.bury(order);
+                changed = true;
+                continue;
+            }
+            self.orders_to_be_sent.push(order);
+        }
+        if changed {
+            self.make_and_send_backup();
         }
     }
-}
+
+    /// Returns true if the order is queued, being prepared by a robot or its parts by the leaders of their shards
+    fn is_in_progress(&self, order_id: &str) -> bool {
+        self.orders_on_queue
+            .iter()
+            .chain(self.robots_orders.values().flatten())
+            .any(|o| o.order_id == order_id)
+            || self.routes.contains(order_id)
+    }
+
+    /// Sends a stashed result to the screen, returns false if the screen is not connected or it could not be sent
+    fn send_waiting_result(&self, screen_id: usize, order: &OrderWaiting) -> bool {
+        let screen = match self.screens_connections.get(&screen_id) {
+            Some(screen) => screen,
+            None => return false,
+        };
+        let sent = match &order.flavor {
+            Some(flavor) => screen
+                .try_send(OrderAborted {
+                    order_result: order.order_result,
+                    id: order.id.clone(),
+                    flavor: flavor.clone(),
+                    reason: order.reason,
+                })
+                .map_err(|e| e.to_string()),
+            None => screen
+                .try_send(OrderPrepared {
+                    order_result: order.order_result,
+                    id: order.id.clone(),
+                    substitutions: order.substitutions.clone(),
+                })
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = &sent {
+            log::send_error("RL", "Sending Order Completed", e);
+        }
+        sent.is_ok()
+    }
+
+    /// Tells the screen to forget the orders, since another screen handles them
+    fn release_orders(&self, screen_id: usize, order_ids: Vec<String>) {
+        if order_ids.is_empty() {
+            return;
+        }
+        let line = format!("Screen {} releases the orders {:?}", screen_id, order_ids);
+        log::info("RL", line.bright_cyan());
+        if let Some(screen) = self.screens_connections.get(&screen_id) {
+            if let Err(e) = screen.try_send(ReleaseOrders { order_ids }) {
+                log::send_error("RL", "ReleaseOrders", &e.to_string());
+            }
+        }
+    }
+
+    /// Tells the screen that the order can not be taken now, the screen sends it again later
+    fn reject_busy(&mut self, order_id: String, screen_id: usize) {
+        let line = format!(
+            "Too many orders on queue ({}), rejecting order {}",
+            self.orders_on_queue.len(),
+            order_id
+        );
+        log::warn("RL", line.bright_magenta());
+        match self.screens_connections.get(&screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(OrderRejectedBusy { id: order_id }) {
+                    log::send_error("RL", "OrderRejectedBusy", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", screen_id)),
+        }
+    }
+
+    /// Tells the screen that the order was not taken because of the flavor, as it is low or its shop used up its quota
+    fn reject_for_flavor(
+        &mut self,
+        order_id: String,
+        screen_id: usize,
+        flavor: FlavorID,
+        reason: AbortReason,
+    ) {
+        match self.screens_connections.get(&screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(OrderAborted {
+                    order_result: false,
+                    id: order_id,
+                    flavor,
+                    reason,
+                }) {
+                    log::send_error("RL", "OrderAborted", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", screen_id)),
+        }
+    }
+
+    /// Tells the screen that the order was not taken because no robot could ever finish it
+    fn reject_unservable(&mut self, order_id: String, screen_id: usize, unservable: Unservable) {
+        let line = format!("{}, rejecting order {}", unservable.describe(), order_id);
+        log::warn("RL", line.bright_magenta());
+        match self.screens_connections.get(&screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(OrderRejectedUnservable {
+                    id: order_id,
+                    reason: unservable.reason(),
+                    error: unservable.describe(),
+                    flavor: unservable.flavor(),
+                }) {
+                    log::send_error("RL", "OrderRejectedUnservable", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", screen_id)),
+        }
+    }
+
+    /// Answers a repeated order with the result it already had, without preparing it again
+    fn send_cached_result(&mut self, order_id: String, screen_id: usize, state: OrderState) {
+        let flavor = match state {
+            OrderState::Aborted(flavor) => Some(flavor),
+            _ => None,
+        };
+        let screen = match self.screens_connections.get(&screen_id) {
+            Some(screen) => screen,
+            None => {
+                self.stash_order_waiting(
+                    order_id,
+                    flavor.is_none(),
+                    screen_id,
+                    flavor,
+                    AbortReason::default(),
+                    vec![],
+                );
+                return;
+            }
+        };
+        let sent = match &flavor {
+            Some(flavor) => screen
+                .try_send(OrderAborted {
+                    order_result: false,
+                    id: order_id.clone(),
+                    flavor: flavor.clone(),
+                    reason: AbortReason::default(),
+                })
+                .map_err(|e| e.to_string()),
+            None => screen
+                .try_send(OrderPrepared {
+                    order_result: true,
+                    id: order_id.clone(),
+                    substitutions: vec![],
+                })
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = sent {
+            log::send_error("RL", "Sending cached order result", &e);
+            self.stash_order_waiting(
+                order_id,
+                flavor.is_none(),
+                screen_id,
+                flavor,
+                AbortReason::default(),
+                vec![],
+            );
+        }
+    }
+
+    /// Handles an abort of an order that was already confirmed to its screen, like one a robot still had after
+    /// another robot prepared it. The screen is told to refund the payment, the ledger keeps the abort
+    fn abort_after_confirm(&mut self, robot_id: usize, msg: GetAbortedOrder) {
+        self.ledger.aborted(&msg.order_id, msg.flavor.clone());
+        let order = match self.take_robot_order(robot_id, &msg.order_id) {
+            Some(order) => order,
+            None => {
+                let line = format!(
+                    "Order {} was aborted by Robot {} after it was confirmed, but it has no screen",
+                    msg.order_id, robot_id
+                );
+                log::warn("RL", line.bright_cyan());
+                return;
+            }
+        };
+        if !self.leaving_robots.contains(&robot_id) {
+            self.available_robots.push(robot_id);
+        }
+        let line = format!(
+            "Order {} was aborted by Robot {} after it was confirmed, asking Screen {} for a refund",
+            order.order_id, robot_id, order.screen_id
+        );
+        log::warn("RL", line.bright_cyan());
+        match self.screens_connections.get(&order.screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(OrderAbortedAfterConfirm {
+                    id: order.order_id,
+                    flavor: msg.flavor,
+                    reason: msg.reason,
+                }) {
+                    log::send_error("RL", "OrderAbortedAfterConfirm", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", order.screen_id)),
+        }
+        self.assign_new_order();
+        self.make_and_send_backup();
+    }
+
+    /// Removes the order from the ones assigned to the robot
+    fn take_robot_order(&mut self, robot_id: usize, order_id: &str) -> Option<OrderInfo> {
+        let orders = self.robots_orders.get_mut(&robot_id)?;
+        let i = orders.iter().position(|o| o.order_id == order_id)?;
+        let order = orders.remove(i);
+        if orders.is_empty() {
+            self.robots_orders.remove(&robot_id);
+        }
+        self.scheduler.finished(order_id);
+        self.assigned_at.remove(order_id);
+        Some(order)
+    }
+
+    /// Aborts the orders that their robots did not finish before their deadline.
+    /// The robot is told to drop the order, its slot is freed and the screen gets the abort
+    /// An order assigned by a previous leader gets its whole deadline again from the first time it is checked
+    fn abort_overdue_orders(&mut self) {
+        let now = Instant::now();
+        for order in self.robots_orders.values().flatten() {
+            self.assigned_at
+                .entry(order.order_id.clone())
+                .or_insert(now);
+        }
+        let overdue = overdue_orders(&self.robots_orders, &self.assigned_at, now);
+        if overdue.is_empty() {
+            return;
+        }
+        for (robot_id, order_id, flavor) in overdue {
+            let line = format!(
+                "Robot {} did not prepare order {} before its deadline, aborting it",
+                robot_id, order_id
+            );
+            log::warn("RL", line.bright_red());
+            if let Some(robot) = self.robots_connections.get(&robot_id) {
+                if let Err(e) = robot.try_send(CancelOrder {
+                    order_id: order_id.clone(),
+                }) {
+                    log::send_error("RL", "CancelOrder", &e.to_string());
+                }
+            }
+            order_journal::record(JournalEvent::Aborted {
+                order_id: order_id.clone(),
+                robot_id,
+                flavor: flavor.clone(),
+            });
+            self.ledger.aborted(&order_id, flavor.clone());
+            cluster_state::push_completion(
+                &mut self.recent_completions,
+                Completion::new(order_id.clone(), robot_id, Some(flavor.clone())),
+            );
+            if let Some((screen, order)) = self.get_order_result(
+                robot_id,
+                &order_id,
+                false,
+                Some(flavor.clone()),
+                AbortReason::TimedOut,
+                vec![],
+            ) {
+                if let Err(e) = screen.try_send(OrderAborted {
+                    order_result: false,
+                    id: order.order_id.clone(),
+                    flavor: flavor.clone(),
+                    reason: AbortReason::TimedOut,
+                }) {
+                    self.stash_order_waiting(
+                        order.order_id,
+                        false,
+                        order.screen_id,
+                        Some(flavor),
+                        AbortReason::TimedOut,
+                        vec![],
+                    );
+                    log::send_error("RL", "Sending Order Aborted", &e.to_string());
+                }
+            }
+            self.assign_new_order();
+        }
+        self.make_and_send_backup();
+    }
+
+    /// Gets the result of an order from a robot and returns the screen to send the result
+    /// The slot of the robot is freed, so it can be given another order
+    fn get_order_result(
+        &mut self,
+        robot_id: usize,
+        order_id: &str,
+        order_result: bool,
+        flavor: Option<FlavorID>,
+        reason: AbortReason,
+        substitutions: Vec<Substitution>,
+    ) -> Option<(Addr<LeaderToScreenConnection>, OrderInfo)> {
+        let order = match self.take_robot_order(robot_id, order_id) {
+            Some(order) => order,
+            None => {
+                let line = format!("Error! Order {} not found for robot {}", order_id, robot_id);
+                log::error("RL", line.bright_cyan());
+                return None;
+            }
+        };
+        let line = format!(
+            "Order {} {} by Robot {}",
+            order.order_id,
+            if order_result { "completed" } else { "aborted" },
+            robot_id
+        );
+        log::traced(Level::Info, "RL", order.trace_id(), line.bright_green());
+        self.count_finished(&order, order_result);
+        self.consumption
+            .finished(robot_id, &order, order_result, &substitutions);
+        if order_result {
+            self.accounts.consumed(&order.order, &substitutions);
+        }
+        if !self.leaving_robots.contains(&robot_id) {
+            self.available_robots.push(robot_id);
+        }
+
+        let screen_id = order.screen_id;
+        if let Some(screen) = self.screens_connections.get(&screen_id) {
+            Some((screen.clone(), order))
+        } else {
+            let line = format!(
+                "Error! Screen {} not found, saved order to send later",
+                screen_id
+            );
+            log::error("RL", line.bright_cyan());
+            self.stash_order_waiting(
+                order.order_id,
+                order_result,
+                screen_id,
+                flavor,
+                reason,
+                substitutions,
+            );
+            None
+        }
+    }
+}
+
+/// Returns the robot, the id and the first flavor of every order with a deadline that passed since it was assigned
+fn overdue_orders(
+    robots_orders: &HashMap<usize, Vec<OrderInfo>>,
+    assigned_at: &HashMap<String, Instant>,
+    now: Instant,
+) -> Vec<(usize, String, FlavorID)> {
+    let mut overdue = Vec::new();
+    for (robot_id, orders) in robots_orders {
+        for order in orders {
+            let (deadline_ms, assigned) =
+                match (order.deadline_ms, assigned_at.get(&order.order_id)) {
+                    (Some(deadline_ms), Some(assigned)) => (deadline_ms, assigned),
+                    _ => continue,
+                };
+            if now.duration_since(*assigned) < Duration::from_millis(deadline_ms) {
+                continue;
+            }
+            if let Some((flavor, _)) = order.order.get_flavors().first() {
+                overdue.push((*robot_id, order.order_id.clone(), flavor.clone()));
+            }
+        }
+    }
+    overdue
+}
+
+/// Queues the orders of the journal that are missing from the backup, they were lost during the failover.
+/// The ones with flavors of other shards are routed again
+fn recover_lost_orders(backup: &mut LeaderBackup) {
+    let mut known: HashSet<String> = backup
+        .orders_on_queue
+        .iter()
+        .chain(backup.robots_orders.values().flatten())
+        .chain(backup.routed.iter())
+        .map(|o| o.order_id.clone())
+        .collect();
+    known.extend(backup.orders_to_be_sent.iter().map(|o| o.id.clone()));
+
+    for order in order_journal::recover_lost_orders(&known) {
+        let line = format!(
+            "Order {} was lost during the failover, queueing it again",
+            order.order_id
+        );
+        log::warn("RL", line.bright_yellow());
+        backup.ledger.created(&order.order_id);
+        if config::get().cluster.needs_routing(&order.order) {
+            backup.routed.push(order);
+        } else {
+            backup.orders_on_queue.push_back(order);
+        }
+    }
+}
+
+/// Returns true if the screen id is the one the leader of a shard connects with, no screen reconnects to it
+fn is_shard_link(screen_id: usize) -> bool {
+    screen_id >= config::number_of_screens()
+}
 
 /// Removes the robot from the backup
 fn remove_me_from_backup(backup: &mut LeaderBackup, my_id: usize) {
     backup.available_robots.retain(|&id| id != my_id);
-    let my_order = backup.robots_orders.remove(&my_id);
-    if let Some(order) = my_order {
+    let my_orders = backup.robots_orders.remove(&my_id);
+    for order in my_orders.unwrap_or_default().into_iter().rev() {
         backup.orders_on_queue.push_front(order);
     }
 }
 
-/// Adds a new robot to the leader and creates an actor for the connection
+/// Turns down the connection of a robot that claims the id of another one, it is told why before it is closed
+fn reject_robot(msg: AddNewRobot) {
+    let line = format!(
+        "Rejecting a robot that connected as Robot {}, that id is in use",
+        msg.robot_id
+    );
+    log::error("RL", line.red());
+    let mut write_half = msg.write_half;
+    actix::spawn(async move {
+        let rejection = RobotCommand::RobotIdInUse {
+            robot_id: msg.robot_id,
+        };
+        if let Err(e) = write_command(&mut write_half, rejection).await {
+            log::error("RL", format!("Could not reject the Robot: {}", e));
+        }
+        let _ = write_half.shutdown().await;
+    });
+}
+
+/// Adds a new robot to the leader and creates an actor for the connection.
+/// A robot that connects with the id of a connected one is only added if that connection dies in a while,
+/// otherwise two robots were started with the same id and the new one is turned down
 impl Handler<AddNewRobot> for RobotLeader {
     type Result = ();
 
     fn handle(&mut self, msg: AddNewRobot, ctx: &mut Context<Self>) {
         let robot_id = msg.robot_id;
         let asked = msg.asked;
+        if !asked && robot_id == self.my_id {
+            reject_robot(msg);
+            return;
+        }
+        if !asked && self.robots_connections.contains_key(&robot_id) {
+            // a robot that is gone resets the connection when it gets the backup, so it is seen dead
+            self.send_full_backup_to(robot_id);
+            let grace = Duration::from_millis(config::get().heartbeat.interval_ms);
+            ctx.run_later(grace, move |actor, ctx| {
+                match actor.robots_connections.contains_key(&robot_id) {
+                    true => reject_robot(msg),
+                    false => ctx.notify(msg),
+                }
+            });
+            return;
+        }
         let addr = ctx.address();
+        let epoch = self.epoch;
 
         async move {
             let pipo = LeaderToRobotConnection::create(|own_ctx| {
-                let cr = LeaderToRobotConnection::new(addr, msg.robot_id, Some(msg.write_half));
-                let lines: LinesStream<BufReader<OwnedReadHalf>> =
-                    LinesStream::new(BufReader::new(msg.read_half).lines());
+                let cr =
+                    LeaderToRobotConnection::new(addr, msg.robot_id, epoch, Some(msg.write_half));
+                let lines = codec::frames(msg.read_half);
                 LeaderToRobotConnection::add_stream(lines, own_ctx);
                 cr
             });
@@ -353,13 +1567,24 @@ impl Handler<AddNewRobot> for RobotLeader {
         .into_actor(self)
         .map(move |(pipo, rob_id), actor, _ctx| {
             if let Some(pip) = pipo {
-                let line = format!("[RL] Connected to Robot {}", rob_id);
-                println!("{}", line.bright_cyan());
+                let line = format!("Connected to Robot {}", rob_id);
+                log::info("RL", line.bright_cyan());
                 actor.robots_connections.insert(rob_id, pip);
-
-                if !asked {
-                    actor.available_robots.push(rob_id);
-                    actor.assign_new_order();
+                actor.send_full_backup_to(rob_id);
+
+                actor.leaving_robots.remove(&rob_id);
+                if actor.reconnecting_robots.remove(&rob_id) {
+                    let line = format!("Robot {} reconnected", rob_id);
+                    log::info("RL", line.bright_cyan());
+                    actor.free_slots_of(rob_id);
+                } else if !asked {
+                    let slots = config::get().orders.max_concurrent;
+                    for _ in 0..slots {
+                        actor.available_robots.push(rob_id);
+                    }
+                    for _ in 0..slots {
+                        actor.assign_new_order();
+                    }
                     actor.make_and_send_backup();
                 }
             }
@@ -368,18 +1593,69 @@ impl Handler<AddNewRobot> for RobotLeader {
     }
 }
 
+/// Sends the last backup whole to a robot that could not apply the changes of one
+impl Handler<ResendBackup> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ResendBackup, _ctx: &mut Context<Self>) {
+        let line = format!("Robot {} asked for the whole backup", msg.robot_id);
+        log::info("RL", line.bright_cyan());
+        self.send_full_backup_to(msg.robot_id);
+    }
+}
+
 /// Connects to a new screen
 impl Handler<ConnectToScreen> for RobotLeader {
     type Result = ();
 
     fn handle(&mut self, msg: ConnectToScreen, ctx: &mut Context<Self>) {
-        let addr = ctx.address();
+        self.start_screen_connection(msg.screen_id, 0, ctx);
+    }
+}
 
-        async move {
-            connect_to_screen(msg.screen_id, addr).await;
+/// Handles a screen connection that could not be opened
+/// A screen that is not listening is not retried, it asks the leader to connect when it starts,
+/// unless its connection was just lost. Those and the connections that could not be handed to the leader
+/// are retried a few times with a growing wait
+impl Handler<ConnectionFailed> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectionFailed, ctx: &mut Context<Self>) {
+        let screen_id = match msg.peer {
+            Peer::Screen(screen_id) => screen_id,
+            Peer::Shard(shard) => return self.retry_shard_link(shard, msg.error, msg.attempt, ctx),
+            Peer::Leader(_) => return,
+        };
+        let retry = should_retry(msg.attempt)
+            && match msg.error {
+                FreddoError::Send(_) => true,
+                FreddoError::Connection(_) => self.reconnecting_screens.contains(&screen_id),
+                _ => false,
+            };
+        if retry {
+            let attempt = msg.attempt + 1;
+            let line = format!(
+                "Could not connect to Screen {}: {}, retrying (attempt {})",
+                screen_id, msg.error, attempt
+            );
+            log::warn("RL", line.bright_cyan());
+            metrics::get().retried("screen_connection");
+            ctx.run_later(retry_backoff(msg.attempt), move |actor, ctx| {
+                actor.start_screen_connection(screen_id, attempt, ctx);
+            });
+            return;
+        }
+        self.reconnecting_screens.remove(&screen_id);
+        match msg.error {
+            FreddoError::Connection(e) => {
+                let line = format!("Screen {} is not available: {}", screen_id, e);
+                log::debug("RL", line);
+            }
+            error => {
+                let line = format!("Could not connect to Screen {}: {}", screen_id, error);
+                log::error("RL", line.red());
+            }
         }
-        .into_actor(self)
-        .wait(ctx);
     }
 }
 
@@ -388,18 +1664,25 @@ impl Handler<AddNewScreen> for RobotLeader {
     type Result = ();
 
     fn handle(&mut self, msg: AddNewScreen, ctx: &mut Context<Self>) {
-        let line = format!("[RL] Connected to Screen: {}", msg.screen_id);
-        println!("{}", line.bright_cyan());
+        let line = format!("Connected to Screen: {}", msg.screen_id);
+        log::info("RL", line.bright_cyan());
 
         let pipo = LeaderToScreenConnection::create(|own_ctx| {
-            let lines = LinesStream::new(BufReader::new(msg.read_half).lines());
-            let rpc =
-                LeaderToScreenConnection::new(msg.screen_id, ctx.address(), Some(msg.write_half));
+            let lines = codec::frames(msg.read_half);
+            let rpc = LeaderToScreenConnection::new(
+                msg.screen_id,
+                ctx.address(),
+                self.my_id,
+                self.epoch,
+                Some(msg.write_half),
+            )
+            .with_sequences(self.screen_sequences.clone());
             LeaderToScreenConnection::add_stream(lines, own_ctx);
             rpc
         });
 
         self.screens_connections.insert(msg.screen_id, pipo);
+        self.reconnecting_screens.remove(&msg.screen_id);
 
         self.screen_ids.push(msg.screen_id);
 
@@ -413,39 +1696,67 @@ impl Handler<ConnectToNewScreen> for RobotLeader {
 
     fn handle(&mut self, msg: ConnectToNewScreen, ctx: &mut Context<Self>) {
         let line = format!(
-            "[RL] A Screen requested Leader to connect to new Screen witd id {}",
+            "A Screen requested Leader to connect to new Screen witd id {}",
             msg.screen_id
         );
-        println!("{}", line.bright_cyan());
+        log::info("RL", line.bright_cyan());
 
-        let address = ctx.address();
-        let screen_id = msg.screen_id;
-
-        async move {
-            connect_to_screen(screen_id, address).await;
-        }
-        .into_actor(self)
-        .wait(ctx);
+        self.start_screen_connection(msg.screen_id, 0, ctx);
     }
 }
 
 /// Handles the creation of a new order and assigns it to a robot
 impl Handler<CreateNewOrder> for RobotLeader {
     type Result = ();
-    fn handle(&mut self, msg: CreateNewOrder, _ctx: &mut Context<Self>) {
-        let order_id = msg.id.clone();
-
-        let line = format!("[RL] Assigning order {}", order_id);
-        println!("{}", line.bright_magenta());
+    fn handle(&mut self, msg: CreateNewOrder, ctx: &mut Context<Self>) {
+        if let Some(order_info) = self.accept_new_order(
+            msg.id,
+            msg.new_order,
+            msg.screen_id,
+            msg.deadline_ms,
+            msg.source,
+            0,
+        ) {
+            self.add_new_orders(vec![order_info], ctx);
+            self.make_and_send_backup();
+        }
+    }
+}
 
-        let order_info = OrderInfo {
-            order: msg.new_order.clone(),
-            order_id: order_id.clone(),
-            screen_id: msg.screen_id,
-        };
+/// Handles a batch of new orders from a screen, they are assigned together and backed up once
+impl Handler<CreateNewOrderBatch> for RobotLeader {
+    type Result = ();
+    fn handle(&mut self, msg: CreateNewOrderBatch, ctx: &mut Context<Self>) {
+        let line = format!(
+            "Got a batch of {} orders from Screen {}",
+            msg.orders.len(),
+            msg.screen_id
+        );
+        log::info("RL", line.bright_magenta());
 
-        self.add_new_order(order_info.clone());
-        self.make_and_send_backup();
+        let mut seen = HashSet::new();
+        let mut orders: Vec<OrderInfo> = Vec::new();
+        let mut sources = msg.sources;
+        for (order_id, order) in msg.orders {
+            if !seen.insert(order_id.clone()) {
+                continue;
+            }
+            let source = sources.remove(&order_id);
+            if let Some(order_info) = self.accept_new_order(
+                order_id,
+                order,
+                msg.screen_id,
+                msg.deadline_ms,
+                source,
+                orders.len(),
+            ) {
+                orders.push(order_info);
+            }
+        }
+        if !orders.is_empty() {
+            self.add_new_orders(orders, ctx);
+            self.make_and_send_backup();
+        }
     }
 }
 
@@ -454,21 +1765,40 @@ impl Handler<GetCompletedOrder> for RobotLeader {
     type Result = ();
     fn handle(&mut self, msg: GetCompletedOrder, _ctx: &mut Context<Self>) {
         let robot_id = msg.robot_id;
-        let line = format!("[RL] Got Order Completed from Robot {}", robot_id);
-        println!("{}", line.bright_green());
+        let line = format!("Got Order Completed from Robot {}", robot_id);
+        log::info("RL", line.bright_green());
+        order_journal::record(JournalEvent::Completed {
+            order_id: msg.order_id.clone(),
+            robot_id,
+        });
+        self.ledger.completed(&msg.order_id);
+        cluster_state::push_completion(
+            &mut self.recent_completions,
+            Completion::new(msg.order_id.clone(), robot_id, None),
+        );
 
-        if let Some((screen, order)) = self.get_order_result(robot_id, msg.order_result, None) {
+        if let Some((screen, order)) = self.get_order_result(
+            robot_id,
+            &msg.order_id,
+            msg.order_result,
+            None,
+            AbortReason::default(),
+            msg.substitutions.clone(),
+        ) {
             if let Err(e) = screen.try_send(OrderPrepared {
                 order_result: msg.order_result,
                 id: order.order_id.clone(),
+                substitutions: msg.substitutions.clone(),
             }) {
                 self.stash_order_waiting(
                     order.order_id.clone(),
                     msg.order_result,
                     order.screen_id,
                     None,
+                    AbortReason::default(),
+                    msg.substitutions,
                 );
-                print_send_error("[RL]", "Sending Order Completed", &e.to_string());
+                log::send_error("RL", "Sending Order Completed", &e.to_string());
             }
 
             self.assign_new_order();
@@ -482,24 +1812,46 @@ impl Handler<GetAbortedOrder> for RobotLeader {
     type Result = ();
     fn handle(&mut self, msg: GetAbortedOrder, _ctx: &mut Context<Self>) {
         let robot_id = msg.robot_id;
-        let line = format!("[RL] Got Order Aborted from Robot {}", robot_id);
-        println!("{}", line.bright_green());
+        let line = format!("Got Order Aborted from Robot {}", robot_id);
+        log::info("RL", line.bright_green());
+        order_journal::record(JournalEvent::Aborted {
+            order_id: msg.order_id.clone(),
+            robot_id,
+            flavor: msg.flavor.clone(),
+        });
+        if self.ledger.state(&msg.order_id) == Some(OrderState::Completed) {
+            self.abort_after_confirm(robot_id, msg);
+            return;
+        }
+        self.ledger.aborted(&msg.order_id, msg.flavor.clone());
+        cluster_state::push_completion(
+            &mut self.recent_completions,
+            Completion::new(msg.order_id.clone(), robot_id, Some(msg.flavor.clone())),
+        );
 
-        if let Some((screen, order)) =
-            self.get_order_result(robot_id, msg.order_result, Some(msg.flavor))
-        {
+        if let Some((screen, order)) = self.get_order_result(
+            robot_id,
+            &msg.order_id,
+            msg.order_result,
+            Some(msg.flavor.clone()),
+            msg.reason,
+            vec![],
+        ) {
             if let Err(e) = screen.try_send(OrderAborted {
                 order_result: msg.order_result,
                 id: order.order_id.clone(),
-                flavor: msg.flavor,
+                flavor: msg.flavor.clone(),
+                reason: msg.reason,
             }) {
                 self.stash_order_waiting(
                     order.order_id.clone(),
                     msg.order_result,
                     order.screen_id,
                     Some(msg.flavor),
+                    msg.reason,
+                    vec![],
                 );
-                print_send_error("[RL]", "Sending Order Completed", &e.to_string());
+                log::send_error("RL", "Sending Order Completed", &e.to_string());
             }
 
             self.assign_new_order();
@@ -508,93 +1860,526 @@ impl Handler<GetAbortedOrder> for RobotLeader {
     }
 }
 
-/// Handles the death of a robot and reassigns the order
+/// Handles a closed connection with a robot
+/// A robot leaving the ring is removed right away, any other robot gets no new orders while it has time to reconnect,
+/// if it does not, it is dead and its orders are reassigned
 impl Handler<RobotDied> for RobotLeader {
     type Result = ();
 
-    fn handle(&mut self, msg: RobotDied, _ctx: &mut Context<Self>) {
+    fn handle(&mut self, msg: RobotDied, ctx: &mut Context<Self>) {
         let robot_id = msg.robot_id;
-        let line = format!("[RL] Robot {} died! Reassigning order", robot_id);
-        println!("{}", line.bright_cyan());
+        if self.leaving_robots.remove(&robot_id) {
+            let line = format!("Robot {} left the ring", robot_id);
+            log::info("RL", line.bright_cyan());
+            self.remove_robot(robot_id);
+            return;
+        }
 
+        let line = format!(
+            "Lost the connection with Robot {}, waiting for it to reconnect",
+            robot_id
+        );
+        log::warn("RL", line.bright_cyan());
         self.available_robots.retain(|&id| id != robot_id);
-        if let Some(order) = self.robots_orders.remove(&robot_id) {
-            self.orders_on_queue.push_front(order);
-            self.assign_new_order();
-        }
         self.robots_connections.remove(&robot_id);
-        self.make_and_send_backup();
+        self.reconnecting_robots.insert(robot_id);
+        ctx.run_later(reconnect_window(), move |actor, _| {
+            if actor.reconnecting_robots.remove(&robot_id) {
+                let line = format!("Robot {} died! Reassigning order", robot_id);
+                log::error("RL", line.bright_cyan());
+                actor.remove_robot(robot_id);
+            }
+        });
     }
 }
 
-/// Handles the death of a screen and reassigns the order
-impl Handler<ScreenDied> for RobotLeader {
+/// Handles a robot that is leaving the ring, it is not given new orders but it finishes the ones it has
+impl Handler<RobotLeaving> for RobotLeader {
     type Result = ();
 
-    fn handle(&mut self, msg: ScreenDied, _ctx: &mut Context<Self>) {
-        let screen_id = msg.screen_id;
-        let line = format!("[RL] Screen {} died!", screen_id);
-        println!("{}", line.bright_cyan());
+    fn handle(&mut self, msg: RobotLeaving, _ctx: &mut Context<Self>) {
+        let robot_id = msg.robot_id;
+        let line = format!("Robot {} is leaving the ring", robot_id);
+        log::info("RL", line.bright_cyan());
 
-        self.screen_ids.retain(|&id| id != screen_id);
-        self.screens_connections.remove(&screen_id);
+        self.leaving_robots.insert(robot_id);
+        self.available_robots.retain(|&id| id != robot_id);
         self.make_and_send_backup();
     }
 }
 
-/// Handles the change of a screen due to a failure in one of them
-impl Handler<ChangeScreen> for RobotLeader {
+/// Handles a robot that is defrosting, it is not given new orders until its window ends.
+/// The orders it has stay with it, they are prepared once it scoops again
+impl Handler<RobotUnavailable> for RobotLeader {
     type Result = ();
 
-    fn handle(&mut self, msg: ChangeScreen, _ctx: &mut Context<Self>) {
-        let original_screen_id = msg.original_screen_id;
-        let new_screen_id = msg.new_screen_id;
+    fn handle(&mut self, msg: RobotUnavailable, ctx: &mut Context<Self>) {
+        let robot_id = msg.robot_id;
+        let wait = self
+            .unavailable
+            .mark(robot_id, msg.until, maintenance::now_ms());
         let line = format!(
-            "Screen {} reeplaces Screen {}",
-            new_screen_id, original_screen_id
+            "Robot {} is defrosting for {} ms, not giving it orders",
+            robot_id,
+            wait.as_millis()
         );
-        println!("{}", line.bright_cyan());
-
-        for order in self.orders_to_be_sent.iter_mut() {
-            if order.screen_id != original_screen_id {
-                continue;
+        log::info("RL", line.bright_cyan());
+        ctx.run_later(wait, move |actor, _| {
+            if !actor.unavailable.resume(robot_id, maintenance::now_ms()) {
+                return;
+            }
+            let line = format!("Robot {} finished defrosting", robot_id);
+            log::info("RL", line.bright_cyan());
+            let free = actor
+                .available_robots
+                .iter()
+                .filter(|&&id| id == robot_id)
+                .count();
+            for _ in 0..free {
+                actor.assign_new_order();
+            }
+            if free > 0 {
+                actor.make_and_send_backup();
             }
+        });
+    }
+}
 
-            let screen = match self.screens_connections.get(&new_screen_id) {
-                Some(screen) => screen,
-                None => {
-                    let line = format!(
-                        "[RL] Error! Screen {} not found saved order to send later",
-                        order.screen_id
-                    );
-                    println!("{}", line.bright_cyan());
-                    return;
-                }
-            };
+/// Handles the orders a robot was preparing when it restarted or lost the connection, it is told which ones it resumes
+impl Handler<OrdersInFlight> for RobotLeader {
+    type Result = ();
 
-            if let Some(flavor_id) = order.flavor {
-                if let Err(e) = screen.try_send(OrderAborted {
-                    order_result: order.order_result,
-                    id: order.id.clone(),
-                    flavor: flavor_id,
-                }) {
-                    print_send_error("[RL]", "Sending Order Completed", &e.to_string());
-                }
-            } else if let Err(e) = screen.try_send(OrderPrepared {
-                order_result: order.order_result,
-                id: order.id.clone(),
-            }) {
-                print_send_error("[RL]", "Sending Order Completed", &e.to_string());
+    fn handle(&mut self, msg: OrdersInFlight, _ctx: &mut Context<Self>) {
+        let robot_id = msg.robot_id;
+        let (resume, drop) = self.take_back_orders(robot_id, msg.orders);
+        let line = format!(
+            "Robot {} resumes orders {:?} and drops {:?}",
+            robot_id, resume, drop
+        );
+        log::info("RL", line.bright_cyan());
+        if let Some(robot) = self.robots_connections.get(&robot_id) {
+            if let Err(e) = robot.try_send(ResumeOrders { resume, drop }) {
+                log::send_error("RL", "ResumeOrders", &e.to_string());
             }
         }
-
+        self.make_and_send_backup();
+    }
+}
+
+/// Handles the speed a robot told when it connected, the scheduler uses it to estimate when the robot is free.
+/// Its backups are compressed from then on if it can read the compression of the config
+impl Handler<ScoopSpeed> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ScoopSpeed, _ctx: &mut Context<Self>) {
+        let compression =
+            Compression::negotiate(config::get().backup.compression, &msg.compressions);
+        let line = format!(
+            "Robot {} scoops a gram in {} ms, its backups are sent with compression {:?}",
+            msg.robot_id, msg.ms_per_gram, compression
+        );
+        log::info("RL", line.bright_cyan());
+        self.scheduler.set_speed(msg.robot_id, msg.ms_per_gram);
+        self.compressions.insert(msg.robot_id, compression);
+    }
+}
+
+/// Handles a request of the dashboard, it returns a snapshot of the state of the cluster
+impl Handler<GetClusterState> for RobotLeader {
+    type Result = MessageResult<GetClusterState>;
+
+    fn handle(&mut self, _msg: GetClusterState, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut available_robots = self.available_robots.clone();
+        available_robots.sort();
+        let mut robots_orders: Vec<RobotOrders> = self
+            .robots_orders
+            .iter()
+            .map(|(robot_id, orders)| RobotOrders {
+                robot_id: *robot_id,
+                orders: orders.iter().map(|o| o.order_id.clone()).collect(),
+            })
+            .collect();
+        robots_orders.sort_by_key(|r| r.robot_id);
+        let mut screens: Vec<usize> = self.screens_connections.keys().copied().collect();
+        screens.sort();
+
+        MessageResult(ClusterState {
+            leader_id: self.my_id,
+            available_robots,
+            robots_orders,
+            orders_on_queue: self.orders_on_queue.iter().cloned().collect(),
+            stock: metrics::get().stock(),
+            screens,
+            recent_completions: self.recent_completions.iter().cloned().collect(),
+        })
+    }
+}
+
+/// Handles a request of the admin, it returns how many orders are in each stage
+impl Handler<GetQueueDepth> for RobotLeader {
+    type Result = MessageResult<GetQueueDepth>;
+
+    fn handle(&mut self, _msg: GetQueueDepth, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(QueueDepth {
+            queued: self.orders_on_queue.len(),
+            assigned: self.robots_orders.values().map(Vec::len).sum(),
+            stashed: self.orders_to_be_sent.len(),
+            free_slots: self.available_robots.len(),
+        })
+    }
+}
+
+/// Handles a request of the admin, it returns how the orders of each screen went
+impl Handler<GetScreenStats> for RobotLeader {
+    type Result = MessageResult<GetScreenStats>;
+
+    fn handle(&mut self, _msg: GetScreenStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.screen_stats.stats())
+    }
+}
+
+/// Handles a request of the admin, it returns how the orders of each shop went
+impl Handler<GetShopStats> for RobotLeader {
+    type Result = MessageResult<GetShopStats>;
+
+    fn handle(&mut self, _msg: GetShopStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.shops.stats(&config::get().tenants))
+    }
+}
+
+/// Handles a request of the admin, it returns the orders the robots are preparing
+impl Handler<GetAssignments> for RobotLeader {
+    type Result = MessageResult<GetAssignments>;
+
+    fn handle(&mut self, _msg: GetAssignments, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(queue_status::assignments(
+            &self.robots_orders,
+            &self.assigned_at,
+            Instant::now(),
+        ))
+    }
+}
+
+/// Handles a request of the admin, it returns the results waiting for their screens
+impl Handler<GetStashedResults> for RobotLeader {
+    type Result = MessageResult<GetStashedResults>;
+
+    fn handle(&mut self, _msg: GetStashedResults, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(queue_status::stashed_results(&self.orders_to_be_sent))
+    }
+}
+
+/// Handles a request of the admin, it returns the results it gave up sending
+impl Handler<GetDeadLetters> for RobotLeader {
+    type Result = MessageResult<GetDeadLetters>;
+
+    fn handle(&mut self, _msg: GetDeadLetters, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.dead_letters.letters().to_vec())
+    }
+}
+
+/// Handles a request of the admin, the results taken from the dead letters are stashed again as new and sent right away
+impl Handler<ReplayDeadLetters> for RobotLeader {
+    type Result = usize;
+
+    fn handle(&mut self, msg: ReplayDeadLetters, _ctx: &mut Context<Self>) -> Self::Result {
+        let replayed = self.dead_letters.take(msg.order_id.as_deref());
+        let count = replayed.len();
+        let now = maintenance::now_ms();
+        for mut order in replayed {
+            order.attempts = 0;
+            order.stashed_at_ms = now;
+            self.orders_to_be_sent.push(order);
+        }
+        if count > 0 {
+            let line = format!("Replaying {} dead letters", count);
+            log::info("RL", line.bright_cyan());
+            self.retry_stashed_results();
+        }
+        count
+    }
+}
+
+/// Handles a request of the admin or of the shutdown of the robot, it writes the consumption report and returns it
+impl Handler<WriteConsumptionReport> for RobotLeader {
+    type Result = Result<ConsumptionReport, String>;
+
+    fn handle(&mut self, _msg: WriteConsumptionReport, _ctx: &mut Context<Self>) -> Self::Result {
+        let report_config = &config::get().report;
+        let report = self.consumption.report(self.my_id, &metrics::get().stock());
+        report
+            .write(Path::new(&report_config.file), report_config.format)
+            .map_err(|e| format!("Could not write {}: {}", report_config.file, e))?;
+        let line = format!("Consumption report written to {}", report_config.file);
+        log::info("RL", line.bright_cyan());
+        Ok(report)
+    }
+}
+
+/// Handles a screen that asks how many orders are ahead of one of its orders, the answer goes back to the screen
+impl Handler<QueryOrderStatus> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueryOrderStatus, _ctx: &mut Context<Self>) {
+        let orders_ahead =
+            queue_status::orders_ahead(&self.orders_on_queue, &self.robots_orders, &msg.order_id);
+        match self.screens_connections.get(&msg.screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(SendQueueStatus {
+                    order_id: msg.order_id,
+                    orders_ahead,
+                    queued: self.orders_on_queue.len(),
+                }) {
+                    log::send_error("RL", "SendQueueStatus", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", msg.screen_id)),
+        }
+    }
+}
+
+/// Handles a screen that handed an order to the customer, it is counted and closed in the journal
+impl Handler<OrderPickedUp> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: OrderPickedUp, _ctx: &mut Context<Self>) {
+        let line = format!(
+            "Order {} picked up on Screen {} after {} ms",
+            msg.order_id, msg.screen_id, msg.waited_ms
+        );
+        log::info("RL", line.bright_green());
+        self.screen_stats.picked_up(msg.screen_id, msg.waited_ms);
+        order_journal::record(JournalEvent::PickedUp {
+            order_id: msg.order_id,
+            screen_id: msg.screen_id,
+        });
+    }
+}
+
+/// Handles a screen that asks which flavors have stock, the answer goes back to the screen.
+/// The flavors of other shards are left to their leaders, so they are always on the menu
+impl Handler<QueryMenu> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueryMenu, _ctx: &mut Context<Self>) {
+        let mut in_stock = stock_watch::in_stock(
+            &config::get().flavors,
+            &metrics::get().stock(),
+            &self.rejecting,
+        );
+        in_stock.extend(self.routes.cluster().flavors_of_other_shards());
+        match self.screens_connections.get(&msg.screen_id) {
+            Some(screen) => {
+                if let Err(e) = screen.try_send(SendMenu { in_stock }) {
+                    log::send_error("RL", "SendMenu", &e.to_string());
+                }
+            }
+            None => log::error("RL", format!("Screen {} is not connected", msg.screen_id)),
+        }
+    }
+}
+
+/// Handles the death of a screen and reassigns the order
+impl Handler<ScreenDied> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ScreenDied, ctx: &mut Context<Self>) {
+        let screen_id = msg.screen_id;
+        let line = format!("Screen {} died! Trying to reconnect", screen_id);
+        log::error("RL", line.bright_cyan());
+
+        self.screen_ids.retain(|&id| id != screen_id);
+        self.screens_connections.remove(&screen_id);
+        if !is_shard_link(screen_id) && self.reconnecting_screens.insert(screen_id) {
+            ctx.run_later(retry_backoff(0), move |actor, ctx| {
+                if actor.reconnecting_screens.contains(&screen_id) {
+                    actor.start_screen_connection(screen_id, 0, ctx);
+                }
+            });
+        }
+        self.make_and_send_backup();
+    }
+}
+
+/// Handles the answer of the leader of a shard about a part, the order is answered to its screen once it is finished
+impl Handler<PartFinished> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: PartFinished, _ctx: &mut Context<Self>) {
+        let Some((order, outcome)) = self.routes.finished(&msg.part_id, msg.result) else {
+            return;
+        };
+        self.finish_routed_order(order, outcome);
+        self.make_and_send_backup();
+    }
+}
+
+/// Handles a closed connection with the leader of a shard, it is opened again while the shard has parts to answer
+impl Handler<ShardLinkClosed> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ShardLinkClosed, ctx: &mut Context<Self>) {
+        let shard = msg.shard;
+        let line = format!(
+            "The connection with the leader of shard {} was closed",
+            shard
+        );
+        log::warn("RL", line.bright_cyan());
+        self.shard_links.remove(&shard);
+        if !self.routes.pending_of(shard).is_empty() {
+            ctx.run_later(retry_backoff(0), move |actor, ctx| {
+                actor.link_shard(shard, 0, ctx);
+            });
+        }
+    }
+}
+
+/// Handles a robot or screen that knows a newer epoch, another leader was elected while this one was cut off
+/// The leader stops with its connections and its robot starts an election to find the new one
+impl Handler<StepDown> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: StepDown, ctx: &mut Context<Self>) {
+        if msg.epoch <= self.epoch {
+            return;
+        }
+        let line = format!(
+            "A Leader from epoch {} exists, stepping down from epoch {}",
+            msg.epoch, self.epoch
+        );
+        log::error("RL", line.bright_red());
+        if let Some(my_robot) = &self.my_robot {
+            if let Err(e) = my_robot.try_send(LeaderSteppedDown { epoch: msg.epoch }) {
+                log::send_error("RL", "LeaderSteppedDown", &e.to_string());
+            }
+        }
+        for robot in self.robots_connections.values() {
+            robot.do_send(Harakiri());
+        }
+        for screen in self.screens_connections.values() {
+            screen.do_send(Harakiri());
+        }
+        for link in self.shard_links.values() {
+            link.do_send(Harakiri());
+        }
+        ctx.stop();
+        Arbiter::current().stop();
+    }
+}
+
+/// Handles a planned transfer of the leadership, asked by an admin
+/// The target gets the last backup and becomes the leader of the next epoch, the other robots and the screens are told
+/// who the new leader is, so no election is needed. Then the leader stops and its robot follows the new one
+impl Handler<TransferLeadership> for RobotLeader {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: TransferLeadership, ctx: &mut Context<Self>) -> Self::Result {
+        let target = msg.target_robot;
+        if target == self.my_id {
+            return Err(format!("Robot {} is already the leader", target));
+        }
+        let target_connection = match self.robots_connections.get(&target) {
+            Some(connection) if !self.leaving_robots.contains(&target) => connection,
+            _ => return Err(format!("Robot {} is not connected to the leader", target)),
+        };
+        let compression = self.compression_for(target);
+        let security = &config::get().security;
+        let backup =
+            match SealedBackup::seal(&self.make_backup(), self.epoch, security, compression) {
+                Ok(backup) => backup,
+                Err(e) => return Err(format!("Could not seal the backup: {}", e)),
+            };
+        let next_epoch = self.epoch + 1;
+        let line = format!(
+            "Handing off the leadership to Robot {} (epoch {})",
+            target, next_epoch
+        );
+        log::info("RL", line.bright_cyan());
+
+        if let Err(e) = target_connection.try_send(HandOffLeadership { backup, next_epoch }) {
+            return Err(format!("Could not reach Robot {}: {}", target, e));
+        }
+        let announce = || AnnounceLeader {
+            leader_id: target,
+            next_epoch,
+        };
+        for (robot_id, robot) in &self.robots_connections {
+            if *robot_id != target {
+                if let Err(e) = robot.try_send(announce()) {
+                    log::send_error("RL", "AnnounceLeader", &e.to_string());
+                }
+            }
+        }
+        for screen in self.screens_connections.values() {
+            if let Err(e) = screen.try_send(announce()) {
+                log::send_error("RL", "AnnounceLeader", &e.to_string());
+            }
+        }
+        if let Some(my_robot) = &self.my_robot {
+            if let Err(e) = my_robot.try_send(LeadershipTransferred {
+                leader_id: target,
+                next_epoch,
+            }) {
+                log::send_error("RL", "LeadershipTransferred", &e.to_string());
+            }
+        }
+
+        for robot in self.robots_connections.values() {
+            robot.do_send(Harakiri());
+        }
+        for screen in self.screens_connections.values() {
+            screen.do_send(Harakiri());
+        }
+        for link in self.shard_links.values() {
+            link.do_send(Harakiri());
+        }
+        ctx.stop();
+        let arbiter = Arbiter::current();
+        actix::spawn(async move {
+            tokio::time::sleep(HANDOFF_GRACE).await;
+            arbiter.stop();
+        });
+        Ok(())
+    }
+}
+
+/// Handles the change of a screen due to a failure in one of them
+/// The results stashed for the screen that failed are sent to the new one, and its orders will be answered to the new one
+impl Handler<ChangeScreen> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChangeScreen, _ctx: &mut Context<Self>) {
+        let original_screen_id = msg.original_screen_id;
+        let new_screen_id = msg.new_screen_id;
+        let line = format!(
+            "Screen {} reeplaces Screen {}",
+            new_screen_id, original_screen_id
+        );
+        log::info("RL", line.bright_cyan());
+
+        if !self.screens_connections.contains_key(&new_screen_id) {
+            let line = format!(
+                "Error! Screen {} not found saved orders to send later",
+                new_screen_id
+            );
+            log::error("RL", line.bright_cyan());
+        }
+        for order in std::mem::take(&mut self.orders_to_be_sent) {
+            if order.screen_id == original_screen_id
+                && self.send_waiting_result(new_screen_id, &order)
+            {
+                continue;
+            }
+            self.orders_to_be_sent.push(order);
+        }
+
         for order in self.orders_on_queue.iter_mut() {
             if order.screen_id == original_screen_id {
                 order.screen_id = new_screen_id;
             }
         }
 
-        for (_, order) in self.robots_orders.iter_mut() {
+        for order in self.robots_orders.values_mut().flatten() {
             if order.screen_id == original_screen_id {
                 order.screen_id = new_screen_id;
             }
@@ -604,12 +2389,509 @@ impl Handler<ChangeScreen> for RobotLeader {
     }
 }
 
+/// Handles a screen that connects again, maybe after its orders were taken by another screen
+/// The orders that the screen still has captured go back to it and the screen that had them releases them,
+/// the results stashed for those orders are sent to it, and the orders that another screen already finished are released by it.
+/// The screen is told which screens had claimed its orders
+impl Handler<ScreenRejoined> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: ScreenRejoined, _ctx: &mut Context<Self>) {
+        let screen_id = msg.screen_id;
+        let line = format!(
+            "Screen {} rejoined with {} orders captured",
+            screen_id,
+            msg.order_ids.len()
+        );
+        log::info("RL", line.bright_cyan());
+
+        let mut captured: HashSet<String> = msg.order_ids.into_iter().collect();
+        let mut taken_from: HashMap<usize, Vec<String>> = HashMap::new();
+        let pending = self
+            .orders_on_queue
+            .iter_mut()
+            .chain(self.robots_orders.values_mut().flatten());
+        for order in pending {
+            if captured.remove(&order.order_id) && order.screen_id != screen_id {
+                taken_from
+                    .entry(order.screen_id)
+                    .or_default()
+                    .push(order.order_id.clone());
+                order.screen_id = screen_id;
+            }
+        }
+
+        for order in std::mem::take(&mut self.orders_to_be_sent) {
+            if captured.contains(&order.id) && self.send_waiting_result(screen_id, &order) {
+                captured.remove(&order.id);
+                if order.screen_id != screen_id {
+                    taken_from
+                        .entry(order.screen_id)
+                        .or_default()
+                        .push(order.id.clone());
+                }
+                continue;
+            }
+            self.orders_to_be_sent.push(order);
+        }
+
+        let mut taken_from: Vec<(usize, Vec<String>)> = taken_from.into_iter().collect();
+        taken_from.sort();
+        for (other_screen, order_ids) in taken_from.iter() {
+            self.release_orders(*other_screen, order_ids.clone());
+        }
+        let mut finished: Vec<String> = captured
+            .into_iter()
+            .filter(|id| matches!(self.ledger.state(id), Some(state) if state != OrderState::InProgress))
+            .collect();
+        finished.sort();
+        self.release_orders(screen_id, finished.clone());
+        if let Some(screen) = self.screens_connections.get(&screen_id) {
+            if let Err(e) = screen.try_send(SendOrdersReclaimed {
+                taken_from,
+                finished,
+            }) {
+                log::send_error("RL", "SendOrdersReclaimed", &e.to_string());
+            }
+        }
+
+        self.make_and_send_backup();
+    }
+}
+
 /// Handles the stash of orders to be sent to a screen that is dead
 /// If the screen is back online it will send the orders or if it gets a new screen it will send the orders to the new screen
 impl Handler<AddOrderToBeSent> for RobotLeader {
     type Result = ();
 
     fn handle(&mut self, msg: AddOrderToBeSent, _ctx: &mut Context<Self>) {
-        self.stash_order_waiting(msg.id.clone(), msg.order_result, msg.screen_id, msg.flavor);
+        self.stash_order_waiting(
+            msg.id.clone(),
+            msg.order_result,
+            msg.screen_id,
+            msg.flavor,
+            msg.reason,
+            msg.substitutions,
+        );
+    }
+}
+
+/// Handles a request of the admin, it returns the balance of the stock of each flavor and the violations found
+impl Handler<GetStockAccounts> for RobotLeader {
+    type Result = MessageResult<GetStockAccounts>;
+
+    fn handle(&mut self, _msg: GetStockAccounts, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.accounts.report())
+    }
+}
+
+/// Handles the grams of a restock added to a token by the leader's robot, they are credited to the flavor
+impl Handler<StockRestocked> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: StockRestocked, _ctx: &mut Context<Self>) {
+        self.accounts.restocked(msg.flavor.name(), msg.grams);
+    }
+}
+
+/// Handles a restock of a flavor, it is sent to the leader's own robot to be added to the token when it passes by
+impl Handler<RestockFlavor> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestockFlavor, _ctx: &mut Context<Self>) {
+        let line = format!("Restocking {} grams of {}", msg.amount, msg.flavor);
+        log::info("RL", line.bright_green());
+        match &self.my_robot {
+            Some(my_robot) => {
+                if let Err(e) = my_robot.try_send(msg) {
+                    log::send_error("RL", "RestockFlavor", &e.to_string());
+                }
+            }
+            None => log::error(
+                "RL",
+                "Error! No robot connection handler found".bright_cyan(),
+            ),
+        }
+    }
+}
+
+/// Handles a flavor that crossed its low watermark, the alert is logged, counted and posted to the webhook
+impl Handler<StockLevelChanged> for RobotLeader {
+    type Result = ();
+
+    fn handle(&mut self, msg: StockLevelChanged, _ctx: &mut Context<Self>) {
+        let (flavor, total, low) = match msg.level {
+            StockLevel::Low { flavor, total } => (flavor, total, true),
+            StockLevel::Recovered { flavor, total } => (flavor, total, false),
+        };
+        let stock = config::get()
+            .flavors
+            .watermarks()
+            .find(|stock| stock.flavor == flavor)
+            .cloned();
+        let stock = match stock {
+            Some(stock) => stock,
+            None => return,
+        };
+        if low {
+            let line = format!(
+                "Stock of {} is low, {} grams left of a watermark of {}",
+                flavor, total, stock.low_watermark
+            );
+            log::warn("RL", line.bright_red());
+            metrics::get().low_stock_alerted();
+            if stock.reject_when_low {
+                self.rejecting.insert(flavor.clone());
+            }
+        } else {
+            let line = format!("Stock of {} is back to {} grams", flavor, total);
+            log::info("RL", line.bright_green());
+            self.rejecting.remove(&flavor);
+        }
+        let subject = format!("the stock alert of {}", flavor);
+        let alert = StockAlert {
+            leader_id: self.my_id,
+            flavor,
+            total,
+            low_watermark: stock.low_watermark,
+            low,
+        };
+        webhook::send(alert, subject);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::order_source::OrderChannel;
+    use crate::common::utils::id_to_leader_addr;
+    use crate::config::cluster::ClusterConfig;
+    use crate::config::tenants::{ShopConfig, TenantsConfig};
+    use crate::protocol::robot_messages::RobotMessage;
+    use crate::robot::order_manager::OrderManager;
+    use crate::robot::order_preparer::OrderPreparer;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_only_orders_past_their_deadline_are_overdue() {
+        let order_info = |id: &str, deadline_ms: Option<u64>| OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms,
+            source: None,
+        };
+        let mut robots_orders = HashMap::new();
+        robots_orders.insert(
+            1,
+            vec![
+                order_info("late", Some(100)),
+                order_info("on time", Some(1000)),
+            ],
+        );
+        robots_orders.insert(2, vec![order_info("no deadline", None)]);
+
+        let now = Instant::now();
+        let assigned = now - Duration::from_millis(500);
+        let assigned_at: HashMap<String, Instant> = ["late", "on time", "no deadline"]
+            .iter()
+            .map(|id| (id.to_string(), assigned))
+            .collect();
+
+        assert_eq!(
+            overdue_orders(&robots_orders, &assigned_at, now),
+            vec![(1, "late".to_string(), FlavorID::Mint)]
+        );
+        assert!(overdue_orders(&robots_orders, &HashMap::new(), now).is_empty());
+    }
+
+    #[test]
+    fn test_the_same_number_from_two_screens_are_different_orders() {
+        let mut leader = RobotLeader::new(0, None);
+        let accept = |leader: &mut RobotLeader, order_id: &str, screen_id: usize| {
+            let order = Order::new_cucurucho(FlavorID::Mint);
+            leader.accept_new_order(order_id.to_string(), order, screen_id, None, None, 0)
+        };
+        assert!(accept(&mut leader, "0-1", 0).is_some());
+        assert!(accept(&mut leader, "1-1", 1).is_some());
+
+        leader.ledger.completed("0-1");
+        assert!(accept(&mut leader, "0-1", 0).is_none());
+        assert_eq!(leader.ledger.state("1-1"), Some(OrderState::InProgress));
+        let accepted = accept(&mut leader, "1-1", 1).unwrap();
+        assert_eq!((accepted.order_id.as_str(), accepted.screen_id), ("1-1", 1));
+    }
+
+    #[test]
+    fn test_a_robot_takes_back_the_orders_that_were_not_given_away() {
+        let mut leader = RobotLeader::new(0, None);
+        let order = |leader: &mut RobotLeader, order_id: &str| {
+            let order = Order::new_cucurucho(FlavorID::Mint);
+            let order_info = leader.accept_new_order(order_id.to_string(), order, 0, None, None, 0);
+            order_info.unwrap()
+        };
+        let assigned = order(&mut leader, "0-1");
+        leader.robots_orders.insert(1, vec![assigned]);
+        let elsewhere = order(&mut leader, "0-2");
+        leader.robots_orders.insert(2, vec![elsewhere]);
+        for order_id in ["0-3", "0-4"] {
+            let queued = order(&mut leader, order_id);
+            leader.orders_on_queue.push_back(queued);
+        }
+        leader.available_robots = vec![2, 1];
+
+        let in_flight = ["0-1", "0-2", "0-3", "0-4", "0-5"]
+            .map(String::from)
+            .to_vec();
+        let (resume, drop) = leader.take_back_orders(1, in_flight);
+        assert_eq!(resume, vec!["0-1", "0-3"]);
+        assert_eq!(drop, vec!["0-2", "0-4", "0-5"]);
+        assert_eq!(leader.robots_orders[&1].len(), 2);
+        assert_eq!(leader.available_robots, vec![2]);
+        assert_eq!(leader.orders_on_queue.len(), 1);
+    }
+
+    #[test]
+    fn test_the_quota_of_a_shop_survives_a_leader_change() {
+        let mut leader = RobotLeader::new(0, None);
+        let cucurucho = Order::new_cucurucho(FlavorID::Mint);
+        let order = |leader: &mut RobotLeader, order_id: &str| {
+            let mut source = OrderSource::new(0, OrderChannel::Api);
+            source.shop = Some("centro".to_string());
+            let order = Order::new_cucurucho(FlavorID::Mint);
+            let order_info =
+                leader.accept_new_order(order_id.to_string(), order, 0, None, Some(source), 0);
+            order_info.unwrap()
+        };
+        let completed = order(&mut leader, "5-1");
+        let in_progress = order(&mut leader, "5-2");
+        leader.robots_orders.insert(1, vec![completed, in_progress]);
+        leader.get_order_result(1, "5-1", true, None, AbortReason::default(), vec![]);
+
+        let new_leader = RobotLeader::from_backup(1, None, leader.make_backup());
+        let tenants = TenantsConfig {
+            shops: vec![ShopConfig {
+                id: "centro".to_string(),
+                screens: vec![0],
+                quotas: BTreeMap::from([("Mint".to_string(), 500)]),
+            }],
+        };
+        // the grams of the completed order and of the one in progress are still counted
+        let stats = new_leader.shops.stats(&tenants);
+        assert_eq!((stats[0].placed, stats[0].completed), (2, 1));
+        assert_eq!(
+            new_leader.shops.over_quota("centro", &cucurucho, &tenants),
+            Some(FlavorID::Mint)
+        );
+        let fresh_leader = RobotLeader::new(1, None);
+        assert_eq!(
+            fresh_leader
+                .shops
+                .over_quota("centro", &cucurucho, &tenants),
+            None
+        );
+    }
+
+    #[actix::test]
+    async fn test_a_second_robot_with_a_connected_id_is_rejected() {
+        let backup = LeaderBackup::new(
+            vec![],
+            vec![],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        );
+        let leader = RobotLeader::from_backup(9, None, backup).start();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut robots = vec![];
+        for _ in 0..2 {
+            let robot = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (read_half, write_half) = listener.accept().await.unwrap().0.into_split();
+            let add = AddNewRobot {
+                robot_id: 1,
+                write_half,
+                read_half,
+                asked: false,
+            };
+            leader.send(add).await.unwrap();
+            robots.push(robot);
+        }
+
+        let (mut read_half, _write_half) = robots.pop().unwrap().into_split();
+        let answer = read_command(&mut read_half).await.unwrap();
+        assert_eq!(answer, RobotCommand::RobotIdInUse { robot_id: 1 });
+    }
+
+    #[actix::test]
+    async fn test_a_restarted_robot_is_taken_once_its_old_connection_closes() {
+        let backup = LeaderBackup::new(
+            vec![],
+            vec![],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        );
+        let leader = RobotLeader::from_backup(9, None, backup).start();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connect = || async {
+            let robot = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (read_half, write_half) = listener.accept().await.unwrap().0.into_split();
+            let add = AddNewRobot {
+                robot_id: 1,
+                write_half,
+                read_half,
+                asked: false,
+            };
+            leader.send(add).await.unwrap();
+            robot
+        };
+        let first_run = connect().await;
+        drop(first_run);
+        let second_run = connect().await;
+
+        let (mut read_half, _write_half) = second_run.into_split();
+        let answer = read_command(&mut read_half).await.unwrap();
+        assert!(matches!(answer, RobotCommand::ReceiveLeaderBackup { .. }));
+    }
+
+    #[actix::test]
+    async fn test_tokens_a_robot_could_not_take_start_at_the_leaders_robot() {
+        let order_preparer = OrderPreparer::new(9).start();
+        let order_manager = OrderManager::new(order_preparer, 9).start();
+        let my_robot = RobotConnectionHandler::new(order_manager.clone(), 9, 0).start();
+        let backup = LeaderBackup::new(
+            vec![],
+            vec![],
+            VecDeque::new(),
+            HashMap::new(),
+            vec![],
+            OrderLedger::new(),
+        );
+        let leader = RobotLeader::from_backup(9, Some(my_robot), backup).start();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _robot = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_read_half, mut write_half) = listener.accept().await.unwrap().0.into_split();
+        write_half.shutdown().await.unwrap();
+        let connection = LeaderToRobotConnection::new(leader, 1, 1, Some(write_half)).start();
+
+        let token = FlavorToken::new(FlavorID::Mint, 500);
+        connection
+            .send(StartTokens {
+                all_tokens: vec![token],
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let holdings = order_manager.send(GetHoldings()).await.unwrap();
+        assert_eq!(holdings.len(), 1);
+        assert_eq!(holdings[0].flavor, FlavorID::Mint);
+        assert_eq!(holdings[0].amount, 500);
+    }
+
+    #[actix::test]
+    async fn test_an_order_placed_on_shard_0_is_served_by_the_leader_of_shard_1() {
+        let empty = || {
+            LeaderBackup::new(
+                vec![],
+                vec![],
+                VecDeque::new(),
+                HashMap::new(),
+                vec![],
+                OrderLedger::new(),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pair = || async {
+            let near = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let far = listener.accept().await.unwrap().0;
+            (near, far)
+        };
+
+        let shard_1 = RobotLeader::from_backup(7, None, empty()).start();
+        let (robot, far) = pair().await;
+        let (read_half, write_half) = far.into_split();
+        let add = AddNewRobot {
+            robot_id: 1,
+            write_half,
+            read_half,
+            asked: false,
+        };
+        shard_1.send(add).await.unwrap();
+
+        let cluster = ClusterConfig {
+            leader_shards: vec![vec![FlavorID::Mint], vec![FlavorID::Lemon]],
+            ..ClusterConfig::default()
+        };
+        let routes = ShardRoutes::new(cluster, vec![vec![], vec![id_to_leader_addr(7)]]);
+        let shard_0 = RobotLeader::from_backup(6, None, empty())
+            .with_routes(routes)
+            .start();
+        let (screen, far) = pair().await;
+        let (read_half, write_half) = far.into_split();
+        let add = AddNewScreen {
+            screen_id: 0,
+            write_half,
+            read_half,
+        };
+        shard_0.send(add).await.unwrap();
+        let order = CreateNewOrder {
+            id: "6-1".to_string(),
+            new_order: Order::new_cucurucho(FlavorID::Lemon),
+            screen_id: 0,
+            deadline_ms: None,
+            source: None,
+        };
+        shard_0.send(order).await.unwrap();
+
+        let (mut robot_read, mut robot_write) = robot.into_split();
+        let wait = Duration::from_secs(10);
+        let part = tokio::time::timeout(wait, async {
+            loop {
+                if let RobotCommand::NewOrder {
+                    order_id, order, ..
+                } = read_command(&mut robot_read).await.unwrap()
+                {
+                    return (order_id, order);
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            part,
+            ("6-1/1".to_string(), Order::new_cucurucho(FlavorID::Lemon))
+        );
+        let complete = RobotCommand::OrderComplete {
+            result: true,
+            order_id: part.0,
+            substitutions: vec![],
+        };
+        write_command(&mut robot_write, complete).await.unwrap();
+
+        let (mut screen_read, _screen_write) = screen.into_split();
+        let prepared = tokio::time::timeout(wait, async {
+            loop {
+                let frame = codec::read_frame(&mut screen_read).await.unwrap();
+                if let Ok(RobotMessage::OrderPrepared { order_id, .. }) =
+                    RobotMessage::from_bytes(&frame)
+                {
+                    return order_id;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(prepared, "6-1");
     }
 }
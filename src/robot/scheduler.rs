@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config;
+use crate::protocol::order_info::OrderInfo;
+
+/// Estimates how long it takes a robot that scoops a gram in `ms_per_gram` milliseconds to scoop all the flavors of an order
+pub fn scoop_time(order: &OrderInfo, ms_per_gram: usize) -> Duration {
+    let grams: usize = order
+        .order
+        .get_flavors()
+        .iter()
+        .map(|(_, amount)| amount)
+        .sum();
+    Duration::from_millis((grams * ms_per_gram) as u64)
+}
+
+/// Load aware scheduler used by the RobotLeader to choose which robot prepares each order
+/// It remembers when each order was assigned, so it can estimate how much scoop time each robot has left,
+/// and how fast each robot scoops, as the robots tell it when they connect
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    assigned_at: HashMap<String, Instant>,
+    speeds: HashMap<usize, usize>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers how many milliseconds the robot takes to scoop a gram
+    pub fn set_speed(&mut self, robot_id: usize, ms_per_gram: usize) {
+        self.speeds.insert(robot_id, ms_per_gram);
+    }
+
+    /// Milliseconds the robot takes to scoop a gram, the one of the config if the robot did not tell it
+    pub fn speed_of(&self, robot_id: usize) -> usize {
+        self.speeds
+            .get(&robot_id)
+            .copied()
+            .unwrap_or_else(|| config::get().scoops.ms_per_gram_of(robot_id))
+    }
+
+    /// Registers that the order was just given to a robot
+    pub fn assigned(&mut self, order_id: &str) {
+        self.assigned_at
+            .insert(order_id.to_string(), Instant::now());
+    }
+
+    /// Forgets the order, it was finished, aborted or taken back from a dead robot
+    pub fn finished(&mut self, order_id: &str) {
+        self.assigned_at.remove(order_id);
+    }
+
+    /// Estimates the scoop time left for the order at the given speed.
+    /// Orders assigned by a previous leader are considered as just started
+    pub fn remaining(&self, order: &OrderInfo, ms_per_gram: usize, now: Instant) -> Duration {
+        let elapsed = self
+            .assigned_at
+            .get(&order.order_id)
+            .map(|at| now.duration_since(*at))
+            .unwrap_or_default();
+        scoop_time(order, ms_per_gram).saturating_sub(elapsed)
+    }
+
+    /// Estimates the scoop time left for all the orders in flight of the robot
+    pub fn load(&self, robot_id: usize, orders: Option<&Vec<OrderInfo>>, now: Instant) -> Duration {
+        let ms_per_gram = self.speed_of(robot_id);
+        orders
+            .map(|orders| {
+                orders
+                    .iter()
+                    .map(|o| self.remaining(o, ms_per_gram, now))
+                    .sum()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Picks, among the robots with a free slot, the one that is expected to finish the order soonest,
+    /// counting the orders it already has and how fast it scoops. Ties are broken by the lowest robot id
+    pub fn pick_robot(
+        &self,
+        order: &OrderInfo,
+        available_robots: &[usize],
+        robots_orders: &HashMap<usize, Vec<OrderInfo>>,
+    ) -> Option<usize> {
+        let now = Instant::now();
+        available_robots
+            .iter()
+            .min_by_key(|id| {
+                let load = self.load(**id, robots_orders.get(id), now);
+                (load + scoop_time(order, self.speed_of(**id)), **id)
+            })
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+
+    fn order_info(id: &str, order: Order) -> OrderInfo {
+        OrderInfo {
+            order,
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_scoop_time_uses_the_grams_of_the_order() {
+        let order = order_info("1", Order::new_cucurucho(FlavorID::Mint));
+        assert_eq!(scoop_time(&order, 10), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn test_least_loaded_robot_is_picked() {
+        let mut scheduler = Scheduler::new();
+        let kilo = Order::new_kilo(vec![FlavorID::Mint; 4]).unwrap();
+        let mut robots_orders = HashMap::new();
+        robots_orders.insert(1, vec![order_info("1", kilo)]);
+        robots_orders.insert(
+            2,
+            vec![order_info("2", Order::new_cucurucho(FlavorID::Lemon))],
+        );
+        scheduler.assigned("1");
+        scheduler.assigned("2");
+
+        let new = order_info("3", Order::new_cucurucho(FlavorID::Mint));
+        assert_eq!(scheduler.pick_robot(&new, &[1, 2], &robots_orders), Some(2));
+        assert_eq!(
+            scheduler.pick_robot(&new, &[1, 3, 2], &robots_orders),
+            Some(3)
+        );
+        assert_eq!(scheduler.pick_robot(&new, &[], &robots_orders), None);
+    }
+
+    #[test]
+    fn test_a_faster_robot_is_picked_over_an_idle_slow_one() {
+        let mut scheduler = Scheduler::new();
+        let mut robots_orders = HashMap::new();
+        robots_orders.insert(
+            1,
+            vec![order_info("1", Order::new_cucurucho(FlavorID::Lemon))],
+        );
+        scheduler.assigned("1");
+        scheduler.set_speed(1, 2);
+        scheduler.set_speed(2, 40);
+
+        let new = order_info("2", Order::new_cucurucho(FlavorID::Mint));
+        assert_eq!(scheduler.pick_robot(&new, &[1, 2], &robots_orders), Some(1));
+        assert_eq!(scheduler.speed_of(3), config::get().scoops.ms_per_gram);
+    }
+}
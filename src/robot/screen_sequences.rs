@@ -0,0 +1,121 @@
+//! Checks the numbers the screens give the messages they send to the leader, so a message the leader already got,
+//! for example one repeated by a broken proxy, is not handled twice.
+//! Every connection of a screen draws a nonce and numbers its messages from 1. A message has to have a greater number
+//! than the previous one with its nonce, and a message with the nonce of a connection that was closed is a replay.
+//! A screen can have more than one connection open with the leader, each one is checked on its own.
+//! The checks are shared by every connection with the screens, so a message is not replayed through a new connection.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// How many connections of each screen are remembered
+const REMEMBERED_NONCES: usize = 16;
+
+/// What the leader does with a numbered message of a screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceCheck {
+    Accepted,
+    /// The message has a number the connection already used
+    Duplicate,
+    /// The message is of a connection that was closed
+    Replayed,
+}
+
+impl SequenceCheck {
+    /// Name of the reason a message is rejected, as used in the metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SequenceCheck::Accepted => "accepted",
+            SequenceCheck::Duplicate => "duplicate",
+            SequenceCheck::Replayed => "replayed",
+        }
+    }
+}
+
+/// The last number of a connection of a screen
+#[derive(Debug)]
+struct Connection {
+    nonce: u64,
+    last_seq: u64,
+    closed: bool,
+}
+
+/// The last number seen of each connection of each screen, cloning it gives another handle to the same numbers
+#[derive(Clone, Debug, Default)]
+pub struct ScreenSequences {
+    screens: Arc<Mutex<HashMap<usize, VecDeque<Connection>>>>,
+}
+
+impl ScreenSequences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the message `seq` of the connection `nonce` of the screen, and keeps its number if it is accepted
+    pub fn check(&self, screen_id: usize, nonce: u64, seq: u64) -> SequenceCheck {
+        let Ok(mut screens) = self.screens.lock() else {
+            return SequenceCheck::Accepted;
+        };
+        let connections = screens.entry(screen_id).or_default();
+        if let Some(connection) = connections.iter_mut().find(|c| c.nonce == nonce) {
+            if connection.closed {
+                return SequenceCheck::Replayed;
+            }
+            if seq <= connection.last_seq {
+                return SequenceCheck::Duplicate;
+            }
+            connection.last_seq = seq;
+            return SequenceCheck::Accepted;
+        }
+        if connections.len() == REMEMBERED_NONCES {
+            let oldest = connections.iter().position(|c| c.closed).unwrap_or(0);
+            connections.remove(oldest);
+        }
+        connections.push_back(Connection {
+            nonce,
+            last_seq: seq,
+            closed: false,
+        });
+        SequenceCheck::Accepted
+    }
+
+    /// Marks the connection as closed, the messages that come with its nonce afterwards are replays
+    pub fn close(&self, screen_id: usize, nonce: u64) {
+        let Ok(mut screens) = self.screens.lock() else {
+            return;
+        };
+        let connections = screens.entry(screen_id).or_default();
+        if let Some(connection) = connections.iter_mut().find(|c| c.nonce == nonce) {
+            connection.closed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_and_older_numbers_are_duplicates() {
+        let sequences = ScreenSequences::new();
+        assert_eq!(sequences.check(0, 7, 1), SequenceCheck::Accepted);
+        assert_eq!(sequences.check(0, 7, 2), SequenceCheck::Accepted);
+        assert_eq!(sequences.check(0, 7, 2), SequenceCheck::Duplicate);
+        assert_eq!(sequences.check(0, 7, 1), SequenceCheck::Duplicate);
+        assert_eq!(sequences.check(0, 7, 5), SequenceCheck::Accepted);
+        assert_eq!(sequences.check(1, 7, 1), SequenceCheck::Accepted);
+    }
+
+    #[test]
+    fn test_messages_of_a_closed_connection_are_replays() {
+        let sequences = ScreenSequences::new();
+        let other = sequences.clone();
+        assert_eq!(sequences.check(0, 7, 3), SequenceCheck::Accepted);
+        assert_eq!(other.check(0, 9, 1), SequenceCheck::Accepted);
+        assert_eq!(sequences.check(0, 7, 4), SequenceCheck::Accepted);
+
+        sequences.close(0, 7);
+        assert_eq!(other.check(0, 7, 5), SequenceCheck::Replayed);
+        assert_eq!(sequences.check(0, 9, 2), SequenceCheck::Accepted);
+    }
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::common::order_source::OrderSource;
+use crate::protocol::order_info::OrderInfo;
+
+/// How the orders placed on a screen went since the leader took over
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScreenStats {
+    pub screen_id: usize,
+    pub placed: u64,
+    pub completed: u64,
+    pub aborted: u64,
+    pub rejected: u64,
+    /// Average time from the placement of an order until it was completed, only of orders that had a source
+    pub average_latency_ms: Option<u64>,
+    #[serde(default)]
+    pub picked_up: u64,
+    /// Average time the orders picked up on the screen waited for the customer once they were ready
+    #[serde(default)]
+    pub average_pickup_wait_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct Counters {
+    placed: u64,
+    completed: u64,
+    aborted: u64,
+    rejected: u64,
+    latency_total_ms: u64,
+    latency_count: u64,
+    picked_up: u64,
+    pickup_wait_total_ms: u64,
+}
+
+/// Counters of the orders of each screen kept by the leader.
+/// Only the orders finished under this leader are counted, the stats of a screen restart from zero after an election
+#[derive(Default)]
+pub struct ScreenStatsBook {
+    screens: BTreeMap<usize, Counters>,
+}
+
+/// The screen an order is counted for, the one it was placed on if it is known
+fn screen_of(screen_id: usize, source: Option<&OrderSource>) -> usize {
+    source.map(|s| s.screen_id).unwrap_or(screen_id)
+}
+
+impl ScreenStatsBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counters(&mut self, screen_id: usize, source: Option<&OrderSource>) -> &mut Counters {
+        self.screens
+            .entry(screen_of(screen_id, source))
+            .or_default()
+    }
+
+    /// Counts an order the leader accepted
+    pub fn placed(&mut self, order: &OrderInfo) {
+        self.counters(order.screen_id, order.source.as_ref()).placed += 1;
+    }
+
+    /// Counts an order the leader did not accept
+    pub fn rejected(&mut self, screen_id: usize, source: Option<&OrderSource>) {
+        self.counters(screen_id, source).rejected += 1;
+    }
+
+    /// Counts a finished order, with the time since it was placed if it is completed and its source is known
+    pub fn finished(&mut self, order: &OrderInfo, completed: bool) {
+        let counters = self.counters(order.screen_id, order.source.as_ref());
+        if !completed {
+            counters.aborted += 1;
+            return;
+        }
+        counters.completed += 1;
+        if let Some(source) = &order.source {
+            counters.latency_total_ms += source.elapsed_ms();
+            counters.latency_count += 1;
+        }
+    }
+
+    /// Counts an order picked up on the screen, after waiting the given time since it was ready
+    pub fn picked_up(&mut self, screen_id: usize, waited_ms: u64) {
+        let counters = self.counters(screen_id, None);
+        counters.picked_up += 1;
+        counters.pickup_wait_total_ms += waited_ms;
+    }
+
+    /// Returns the stats of every screen that placed or handed out an order, sorted by screen
+    pub fn stats(&self) -> Vec<ScreenStats> {
+        self.screens
+            .iter()
+            .map(|(screen_id, c)| ScreenStats {
+                screen_id: *screen_id,
+                placed: c.placed,
+                completed: c.completed,
+                aborted: c.aborted,
+                rejected: c.rejected,
+                average_latency_ms: (c.latency_count > 0)
+                    .then(|| c.latency_total_ms / c.latency_count),
+                picked_up: c.picked_up,
+                average_pickup_wait_ms: (c.picked_up > 0)
+                    .then(|| c.pickup_wait_total_ms / c.picked_up),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use crate::common::order_source::OrderChannel;
+
+    fn order(id: &str, screen_id: usize, source: Option<OrderSource>) -> OrderInfo {
+        OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: id.to_string(),
+            screen_id,
+            deadline_ms: None,
+            source,
+        }
+    }
+
+    #[test]
+    fn test_orders_are_counted_for_the_screen_they_were_placed_on() {
+        let mut book = ScreenStatsBook::new();
+        let failover = OrderSource {
+            screen_id: 2,
+            channel: OrderChannel::Failover,
+            placed_at_ms: 0,
+            trace_id: String::new(),
+            shop: None,
+        };
+        book.placed(&order("1", 1, None));
+        book.placed(&order("2", 1, Some(failover.clone())));
+        book.rejected(1, Some(&failover));
+        book.finished(&order("1", 1, None), false);
+
+        let stats = book.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            (stats[0].screen_id, stats[0].placed, stats[0].aborted),
+            (1, 1, 1)
+        );
+        assert_eq!(
+            (stats[1].screen_id, stats[1].placed, stats[1].rejected),
+            (2, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_latency_is_averaged_over_completed_orders_with_a_source() {
+        let mut book = ScreenStatsBook::new();
+        let source = OrderSource::new(0, OrderChannel::Api);
+        book.finished(&order("1", 0, None), true);
+        assert_eq!(book.stats()[0].average_latency_ms, None);
+
+        book.finished(&order("2", 0, Some(source)), true);
+        let stats = &book.stats()[0];
+        assert_eq!(stats.completed, 2);
+        assert!(stats.average_latency_ms.is_some());
+    }
+
+    #[test]
+    fn test_pickup_wait_is_averaged_over_picked_up_orders() {
+        let mut book = ScreenStatsBook::new();
+        book.picked_up(3, 1000);
+        book.picked_up(3, 3000);
+        let stats = &book.stats()[0];
+        assert_eq!(stats.screen_id, 3);
+        assert_eq!(
+            (stats.picked_up, stats.average_pickup_wait_ms),
+            (2, Some(2000))
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Orders of the screens of this leader that have flavors of other leader shards.
+//! Only the leader of a shard has the tokens of its flavors, so the order is split in the part of each shard and
+//! every part is passed as an order of its own to the leader of its shard, the one of this shard too, so all the parts
+//! are prepared the same way. The order is prepared once every part is, and aborted as soon as one of them is.
+//! The orders go in the leader backup, a new leader passes their parts again and the leaders answer the parts
+//! they already finished with the result they had
+
+use std::collections::BTreeSet;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::{Order, Substitution};
+use crate::common::utils::id_to_leader_addr;
+use crate::config::{self, cluster::ClusterConfig};
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::robot_messages::AbortReason;
+
+/// Part of an order that is passed to the leader of a shard
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub shard: usize,
+    pub id: String,
+    pub order: Order,
+    pub deadline_ms: Option<u64>,
+}
+
+/// What the leader of a shard answered about a part
+#[derive(Clone, Debug, PartialEq)]
+pub enum PartResult {
+    Prepared(Vec<Substitution>),
+    /// With the flavor that could not be served, if the leader told it
+    Aborted(Option<FlavorID>, AbortReason),
+    /// The leader could not take the part now
+    Busy,
+}
+
+/// How a routed order ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteOutcome {
+    Prepared(Vec<Substitution>),
+    Aborted(FlavorID, AbortReason),
+    /// A part was not taken, the screen sends the order again later
+    Busy,
+}
+
+/// An order whose parts are being prepared by the leaders of their shards
+struct RoutedOrder {
+    info: OrderInfo,
+    parts: Vec<(usize, Order)>,
+    pending: BTreeSet<usize>,
+    substitutions: Vec<Substitution>,
+}
+
+/// Book of the orders whose parts are passed to the leaders of the shards, in the order they were taken
+pub struct ShardRoutes {
+    cluster: ClusterConfig,
+    leader_addrs: Vec<Vec<String>>,
+    orders: Vec<RoutedOrder>,
+}
+
+/// Returns the id of the part of the order that goes to the shard
+fn part_id(order_id: &str, shard: usize) -> String {
+    format!("{}/{}", order_id, shard)
+}
+
+impl ShardRoutes {
+    /// Creates the book with the shards of the config and the addresses their leaders can listen on
+    pub fn from_config() -> Self {
+        let config = config::get();
+        let leader_addrs = (0..config.cluster.shards())
+            .map(|shard| config.leader_addrs_of_shard(shard))
+            .collect();
+        Self::new(config.cluster.clone(), leader_addrs)
+    }
+
+    /// Creates the book with the given shards, `leader_addrs` has the addresses each shard's leader can listen on
+    pub fn new(cluster: ClusterConfig, leader_addrs: Vec<Vec<String>>) -> Self {
+        Self {
+            cluster,
+            leader_addrs,
+            orders: Vec::new(),
+        }
+    }
+
+    pub fn cluster(&self) -> &ClusterConfig {
+        &self.cluster
+    }
+
+    /// Returns the addresses the leader of the shard can be found on, only its own one for the shard of this leader
+    pub fn leader_addrs(&self, shard: usize, my_id: usize) -> Vec<String> {
+        if shard == self.cluster.shard {
+            return vec![id_to_leader_addr(my_id)];
+        }
+        self.leader_addrs.get(shard).cloned().unwrap_or_default()
+    }
+
+    /// Returns true if the order has flavors of other shards, so its parts are passed to their leaders
+    pub fn needs_routing(&self, order: &Order) -> bool {
+        self.cluster.needs_routing(order)
+    }
+
+    /// Returns true if the parts of the order are being prepared
+    pub fn contains(&self, order_id: &str) -> bool {
+        self.orders.iter().any(|o| o.info.order_id == order_id)
+    }
+
+    /// Takes the order and returns the parts to pass to the leaders, none if it was already taken
+    pub fn route(&mut self, info: OrderInfo) -> Vec<Part> {
+        if self.contains(&info.order_id) {
+            return Vec::new();
+        }
+        let parts = self.cluster.split(&info.order);
+        let routed = RoutedOrder {
+            pending: parts.iter().map(|(shard, _)| *shard).collect(),
+            parts,
+            info,
+            substitutions: Vec::new(),
+        };
+        let parts = Self::parts_of(&routed, None);
+        self.orders.push(routed);
+        parts
+    }
+
+    /// Returns the parts of the order that are still pending, of every shard or only of the given one
+    fn parts_of(routed: &RoutedOrder, shard: Option<usize>) -> Vec<Part> {
+        routed
+            .parts
+            .iter()
+            .filter(|(s, _)| routed.pending.contains(s) && shard.is_none_or(|shard| shard == *s))
+            .map(|(s, order)| Part {
+                shard: *s,
+                id: part_id(&routed.info.order_id, *s),
+                order: order.clone(),
+                deadline_ms: routed.info.deadline_ms,
+            })
+            .collect()
+    }
+
+    /// Returns the parts the leader of the shard did not answer yet, to pass them again when it is connected
+    pub fn pending_of(&self, shard: usize) -> Vec<Part> {
+        self.orders
+            .iter()
+            .flat_map(|o| Self::parts_of(o, Some(shard)))
+            .collect()
+    }
+
+    /// Returns the shards that have parts to answer
+    pub fn pending_shards(&self) -> BTreeSet<usize> {
+        self.orders
+            .iter()
+            .flat_map(|o| o.pending.iter().copied())
+            .collect()
+    }
+
+    /// Takes the answer of a leader about a part, returns the order once it is finished.
+    /// An aborted part that does not tell its flavor is aborted with the first one of the part
+    pub fn finished(
+        &mut self,
+        part_id: &str,
+        result: PartResult,
+    ) -> Option<(OrderInfo, RouteOutcome)> {
+        let (order_id, shard) = part_id.rsplit_once('/')?;
+        let shard: usize = shard.parse().ok()?;
+        let index = self
+            .orders
+            .iter()
+            .position(|o| o.info.order_id == order_id && o.pending.contains(&shard))?;
+        let outcome = match result {
+            PartResult::Prepared(substitutions) => {
+                let routed = &mut self.orders[index];
+                routed.pending.remove(&shard);
+                routed.substitutions.extend(substitutions);
+                if !routed.pending.is_empty() {
+                    return None;
+                }
+                RouteOutcome::Prepared(std::mem::take(&mut routed.substitutions))
+            }
+            PartResult::Aborted(flavor, reason) => {
+                let routed = &self.orders[index];
+                let first = routed
+                    .parts
+                    .iter()
+                    .find(|(s, _)| *s == shard)
+                    .and_then(|(_, part)| part.get_flavors().into_iter().next())
+                    .map(|(flavor, _)| flavor);
+                RouteOutcome::Aborted(flavor.or(first)?, reason)
+            }
+            PartResult::Busy => RouteOutcome::Busy,
+        };
+        Some((self.orders.remove(index).info, outcome))
+    }
+
+    /// Returns the orders being routed, for the backup
+    pub fn orders(&self) -> Vec<OrderInfo> {
+        self.orders.iter().map(|o| o.info.clone()).collect()
+    }
+
+    /// Takes the orders of a backup, all their parts are pending, a leader answers the ones it finished with their result
+    pub fn restore(&mut self, orders: Vec<OrderInfo>) {
+        for order in orders {
+            self.route(order);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> ShardRoutes {
+        let cluster = ClusterConfig {
+            leader_shards: vec![vec![FlavorID::Mint], vec![FlavorID::Lemon]],
+            ..ClusterConfig::default()
+        };
+        ShardRoutes::new(cluster, vec![vec![], vec!["127.0.0.1:1".to_string()]])
+    }
+
+    fn order(id: &str, order: Order) -> OrderInfo {
+        OrderInfo {
+            order,
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms: Some(100),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_an_order_is_prepared_once_every_part_is() {
+        let mut routes = routes();
+        let mixed = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        let parts = routes.route(order("a", mixed));
+        let ids: Vec<&str> = parts.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["a/0", "a/1"]);
+        assert!(parts.iter().all(|p| p.deadline_ms == Some(100)));
+        assert!(routes
+            .route(order("a", Order::new_cucurucho(FlavorID::Lemon)))
+            .is_empty());
+        assert_eq!(routes.pending_shards(), BTreeSet::from([0, 1]));
+
+        assert_eq!(routes.finished("a/1", PartResult::Prepared(vec![])), None);
+        assert_eq!(routes.finished("a/1", PartResult::Busy), None);
+        assert!(routes.pending_of(1).is_empty());
+        assert_eq!(routes.pending_of(0)[0].id, "a/0");
+
+        let (info, outcome) = routes
+            .finished("a/0", PartResult::Prepared(vec![]))
+            .unwrap();
+        assert_eq!(info.order_id, "a");
+        assert_eq!(outcome, RouteOutcome::Prepared(vec![]));
+        assert!(!routes.contains("a"));
+    }
+
+    #[test]
+    fn test_an_order_is_aborted_with_its_first_aborted_part() {
+        let mut routes = routes();
+        let mixed = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        routes.route(order("a", mixed.clone()));
+        let aborted = PartResult::Aborted(None, AbortReason::OutOfStock);
+        let (_, outcome) = routes.finished("a/1", aborted).unwrap();
+        assert_eq!(
+            outcome,
+            RouteOutcome::Aborted(FlavorID::Lemon, AbortReason::OutOfStock)
+        );
+        assert_eq!(routes.finished("a/0", PartResult::Prepared(vec![])), None);
+
+        routes.restore(vec![order("b", mixed)]);
+        assert_eq!(routes.orders()[0].order_id, "b");
+        let (_, outcome) = routes.finished("b/0", PartResult::Busy).unwrap();
+        assert_eq!(outcome, RouteOutcome::Busy);
+        assert!(routes.pending_shards().is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+//! Orders and grams of each shop, when the cluster serves more than one.
+//! The leader counts the grams of each flavor the orders of a shop took and the ones its orders in progress will take,
+//! and does not take an order that would leave the shop over its quota of a flavor.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::config::tenants::TenantsConfig;
+use crate::protocol::order_info::OrderInfo;
+use crate::protocol::shop_counters::ShopCounters;
+
+/// Grams of a flavor taken by the orders of a shop
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FlavorUsage {
+    /// Grams of the orders completed
+    pub used: usize,
+    /// Grams of the orders in progress
+    pub reserved: usize,
+    pub quota: Option<usize>,
+}
+
+/// How the orders of a shop went, counted by the leaders since the cluster started
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ShopStats {
+    pub shop: String,
+    pub placed: u64,
+    pub completed: u64,
+    pub aborted: u64,
+    pub rejected: u64,
+    pub grams: BTreeMap<String, FlavorUsage>,
+}
+
+/// Counters of the orders of each shop kept by the leader.
+/// They go in the leader backup, so a new leader keeps counting the quota of each shop from where the old one left it
+#[derive(Default)]
+pub struct ShopBook {
+    shops: BTreeMap<String, ShopCounters>,
+}
+
+/// The shop an order is counted for, if it has one
+fn shop_of(order: &OrderInfo) -> Option<&str> {
+    order.source.as_ref()?.shop.as_deref()
+}
+
+impl ShopBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the book with the counters of a leader backup
+    pub fn from_counters(shops: BTreeMap<String, ShopCounters>) -> Self {
+        Self { shops }
+    }
+
+    /// Returns the counters of every shop, to be kept in the leader backup
+    pub fn counters(&self) -> &BTreeMap<String, ShopCounters> {
+        &self.shops
+    }
+
+    /// Returns the first flavor of the order that would leave the shop over its quota
+    pub fn over_quota(
+        &self,
+        shop: &str,
+        order: &Order,
+        tenants: &TenantsConfig,
+    ) -> Option<FlavorID> {
+        let counters = self.shops.get(shop);
+        let taken = |flavor: &str| {
+            counters.map_or(0, |c| {
+                c.used.get(flavor).copied().unwrap_or(0)
+                    + c.reserved.get(flavor).copied().unwrap_or(0)
+            })
+        };
+        let mut asked: BTreeMap<String, (FlavorID, usize)> = BTreeMap::new();
+        for (flavor, grams) in order.get_flavors() {
+            asked
+                .entry(flavor.name().to_string())
+                .or_insert((flavor, 0))
+                .1 += grams;
+        }
+        asked.into_iter().find_map(|(name, (flavor, grams))| {
+            let quota = tenants.quota(shop, &name)?;
+            (taken(&name) + grams > quota).then_some(flavor)
+        })
+    }
+
+    /// Counts an order the leader accepted and reserves its grams
+    pub fn placed(&mut self, order: &OrderInfo) {
+        let Some(shop) = shop_of(order) else {
+            return;
+        };
+        let counters = self.shops.entry(shop.to_string()).or_default();
+        counters.placed += 1;
+        for (flavor, grams) in order.order.get_flavors() {
+            *counters
+                .reserved
+                .entry(flavor.name().to_string())
+                .or_insert(0) += grams;
+        }
+    }
+
+    /// Counts an order of the shop the leader did not accept
+    pub fn rejected(&mut self, shop: &str) {
+        self.shops.entry(shop.to_string()).or_default().rejected += 1;
+    }
+
+    /// Counts a finished order, its grams are used if it is completed and given back if not
+    pub fn finished(&mut self, order: &OrderInfo, completed: bool) {
+        let Some(shop) = shop_of(order) else {
+            return;
+        };
+        let counters = self.shops.entry(shop.to_string()).or_default();
+        match completed {
+            true => counters.completed += 1,
+            false => counters.aborted += 1,
+        }
+        for (flavor, grams) in order.order.get_flavors() {
+            let name = flavor.name().to_string();
+            if let Some(reserved) = counters.reserved.get_mut(&name) {
+                *reserved = reserved.saturating_sub(grams);
+            }
+            if completed {
+                *counters.used.entry(name).or_insert(0) += grams;
+            }
+        }
+    }
+
+    /// Returns the stats of every shop of the config and of every other shop that placed an order, sorted by shop
+    pub fn stats(&self, tenants: &TenantsConfig) -> Vec<ShopStats> {
+        let mut shops: Vec<&str> = tenants.shops.iter().map(|s| s.id.as_str()).collect();
+        shops.extend(self.shops.keys().map(String::as_str));
+        shops.sort();
+        shops.dedup();
+        shops
+            .into_iter()
+            .map(|shop| {
+                let empty = ShopCounters::default();
+                let c = self.shops.get(shop).unwrap_or(&empty);
+                let mut flavors: Vec<&String> = c.used.keys().chain(c.reserved.keys()).collect();
+                if let Some(config) = tenants.shops.iter().find(|s| s.id == shop) {
+                    flavors.extend(config.quotas.keys());
+                }
+                let grams = flavors
+                    .into_iter()
+                    .map(|flavor| {
+                        let usage = FlavorUsage {
+                            used: c.used.get(flavor).copied().unwrap_or(0),
+                            reserved: c.reserved.get(flavor).copied().unwrap_or(0),
+                            quota: tenants.quota(shop, flavor),
+                        };
+                        (flavor.clone(), usage)
+                    })
+                    .collect();
+                ShopStats {
+                    shop: shop.to_string(),
+                    placed: c.placed,
+                    completed: c.completed,
+                    aborted: c.aborted,
+                    rejected: c.rejected,
+                    grams,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::order_source::{OrderChannel, OrderSource};
+    use crate::config::tenants::ShopConfig;
+
+    fn tenants() -> TenantsConfig {
+        TenantsConfig {
+            shops: vec![ShopConfig {
+                id: "centro".to_string(),
+                screens: vec![0],
+                quotas: BTreeMap::from([("Mint".to_string(), 500)]),
+            }],
+        }
+    }
+
+    fn order(id: &str, shop: Option<&str>) -> OrderInfo {
+        let mut source = OrderSource::new(0, OrderChannel::Api);
+        source.shop = shop.map(str::to_string);
+        OrderInfo {
+            order: Order::new_cucurucho(FlavorID::Mint),
+            order_id: id.to_string(),
+            screen_id: 0,
+            deadline_ms: None,
+            source: Some(source),
+        }
+    }
+
+    #[test]
+    fn test_orders_over_the_quota_of_their_shop_are_found() {
+        let (tenants, mut book) = (tenants(), ShopBook::new());
+        let cucurucho = Order::new_cucurucho(FlavorID::Mint);
+        assert_eq!(book.over_quota("centro", &cucurucho, &tenants), None);
+
+        book.placed(&order("1", Some("centro")));
+        book.finished(&order("1", Some("centro")), true);
+        book.placed(&order("2", Some("centro")));
+        assert_eq!(
+            book.over_quota("centro", &cucurucho, &tenants),
+            Some(FlavorID::Mint)
+        );
+        assert_eq!(book.over_quota("palermo", &cucurucho, &tenants), None);
+
+        book.finished(&order("2", Some("centro")), false);
+        assert_eq!(book.over_quota("centro", &cucurucho, &tenants), None);
+    }
+
+    #[test]
+    fn test_stats_are_kept_for_each_shop() {
+        let (tenants, mut book) = (tenants(), ShopBook::new());
+        book.placed(&order("1", Some("centro")));
+        book.placed(&order("2", None));
+        book.rejected("palermo");
+
+        let stats = book.stats(&tenants);
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].shop.as_str(), stats[0].placed), ("centro", 1));
+        assert_eq!(
+            stats[0].grams.get("Mint"),
+            Some(&FlavorUsage {
+                used: 0,
+                reserved: 250,
+                quota: Some(500)
+            })
+        );
+        assert_eq!((stats[1].shop.as_str(), stats[1].rejected), ("palermo", 1));
+    }
+}
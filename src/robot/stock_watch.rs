@@ -0,0 +1,330 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::config::cluster::ClusterConfig;
+use crate::config::flavors::FlavorCatalog;
+use crate::protocol::flavor_token::{FlavorToken, TokenKey};
+use crate::protocol::robot_messages::AbortReason;
+
+/// A flavor that crossed its low watermark, with the grams left in all its shards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StockLevel {
+    Low { flavor: FlavorID, total: usize },
+    Recovered { flavor: FlavorID, total: usize },
+}
+
+/// Body posted to the webhook by the leader when a flavor crosses its low watermark
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct StockAlert {
+    pub leader_id: usize,
+    pub flavor: FlavorID,
+    pub total: usize,
+    pub low_watermark: usize,
+    pub low: bool,
+}
+
+/// Keeps the last amount of every token seen by the leader's robot, to tell when a flavor runs below its low watermark.
+/// A flavor in shards adds the amounts of all of them, so it is only low when the shop as a whole is
+#[derive(Debug)]
+pub struct StockWatch {
+    watermarks: HashMap<FlavorID, usize>,
+    amounts: HashMap<TokenKey, usize>,
+    low: HashSet<FlavorID>,
+}
+
+impl StockWatch {
+    pub fn new(catalog: &FlavorCatalog) -> Self {
+        Self {
+            watermarks: catalog
+                .watermarks()
+                .map(|stock| (stock.flavor.clone(), stock.low_watermark))
+                .collect(),
+            amounts: HashMap::new(),
+            low: HashSet::new(),
+        }
+    }
+
+    /// Keeps the amount of the token, and returns the new level of its flavor if it crossed the watermark
+    pub fn observe(&mut self, token: &FlavorToken) -> Option<StockLevel> {
+        let flavor = token.get_id();
+        let watermark = *self.watermarks.get(&flavor)?;
+        self.amounts.insert(token.key(), token.get_amnt());
+        let total = self
+            .amounts
+            .iter()
+            .filter(|((id, _), _)| *id == flavor)
+            .map(|(_, amount)| amount)
+            .sum();
+        if total < watermark && self.low.insert(flavor.clone()) {
+            return Some(StockLevel::Low { flavor, total });
+        }
+        if total >= watermark && self.low.remove(&flavor) {
+            return Some(StockLevel::Recovered { flavor, total });
+        }
+        None
+    }
+
+    /// Forgets what was seen, so a new leader announces again the flavors that are low
+    pub fn reset(&mut self) {
+        self.amounts.clear();
+        self.low.clear();
+    }
+}
+
+/// Returns a flavor of the order that is low and rejects orders, unless one of its substitutes is not low
+pub fn low_flavor_of(order: &Order, rejecting: &HashSet<FlavorID>) -> Option<FlavorID> {
+    let substitutes = order.get_substitutes();
+    order
+        .get_flavors()
+        .into_iter()
+        .map(|(flavor, _)| flavor)
+        .find(|flavor| {
+            rejecting.contains(flavor)
+                && substitutes
+                    .iter()
+                    .filter(|(id, _)| id == flavor)
+                    .flat_map(|(_, substitutes)| substitutes)
+                    .all(|substitute| rejecting.contains(substitute))
+        })
+}
+
+/// Why the leader can not serve an order with the tokens of the catalog
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Unservable {
+    /// The order has no flavors, no robot would ever finish it
+    NoFlavors,
+    /// The flavor has no token, a robot would wait for it forever
+    UnknownFlavor(FlavorID),
+}
+
+impl Unservable {
+    pub fn reason(&self) -> AbortReason {
+        match self {
+            Unservable::NoFlavors => AbortReason::NoFlavors,
+            Unservable::UnknownFlavor(_) => AbortReason::UnknownFlavor,
+        }
+    }
+
+    /// Returns the flavor that can not be served, none if it is the order as a whole
+    pub fn flavor(&self) -> Option<FlavorID> {
+        match self {
+            Unservable::NoFlavors => None,
+            Unservable::UnknownFlavor(flavor) => Some(flavor.clone()),
+        }
+    }
+
+    /// Returns the error shown to the client
+    pub fn describe(&self) -> String {
+        match self {
+            Unservable::NoFlavors => "Order Rejected because it has no flavors".to_string(),
+            Unservable::UnknownFlavor(flavor) => AbortReason::UnknownFlavor.describe(flavor),
+        }
+    }
+}
+
+/// Checks that the order has flavors and that all of them are in the catalog, so a token exists for each one.
+/// With leader shards the catalog only has the flavors of the shard of the leader, the ones of the other shards are
+/// left to their leaders.
+/// A substitute does not make up for a missing flavor, the robot waits for the token of the flavor before trying them
+pub fn unservable(
+    order: &Order,
+    catalog: &FlavorCatalog,
+    cluster: &ClusterConfig,
+) -> Option<Unservable> {
+    let flavors = order.get_flavors();
+    if flavors.is_empty() {
+        return Some(Unservable::NoFlavors);
+    }
+    flavors
+        .into_iter()
+        .map(|(flavor, _)| flavor)
+        .filter(|flavor| {
+            cluster
+                .shard_of(flavor)
+                .is_none_or(|shard| shard == cluster.shard)
+        })
+        .find(|flavor| catalog.initial_amount(flavor).is_none())
+        .map(Unservable::UnknownFlavor)
+}
+
+/// Returns the flavors of the catalog that have grams left and are not rejected for running low.
+/// `stock` has the grams of each flavor as last seen by the leader's robot, a flavor it has not seen yet has its initial amount
+pub fn in_stock(
+    catalog: &FlavorCatalog,
+    stock: &BTreeMap<String, usize>,
+    rejecting: &HashSet<FlavorID>,
+) -> Vec<FlavorID> {
+    catalog
+        .flavors()
+        .iter()
+        .filter(|flavor| !rejecting.contains(&flavor.flavor))
+        .filter(|flavor| {
+            let grams = stock.get(&flavor.flavor.to_string());
+            grams.copied().unwrap_or(flavor.amount) > 0
+        })
+        .map(|flavor| flavor.flavor.clone())
+        .collect()
+}
+
+/// Returns the grams left of each flavor of the catalog, a flavor the leader's robot has not seen yet has its initial amount
+pub fn snapshot(
+    catalog: &FlavorCatalog,
+    stock: &BTreeMap<String, usize>,
+) -> Vec<(FlavorID, usize)> {
+    catalog
+        .flavors()
+        .iter()
+        .map(|flavor| {
+            let grams = stock.get(&flavor.flavor.to_string());
+            (
+                flavor.flavor.clone(),
+                grams.copied().unwrap_or(flavor.amount),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::flavors::FlavorStock;
+
+    fn watch() -> StockWatch {
+        let mut mint = FlavorStock::new(FlavorID::Mint, 1000);
+        mint.low_watermark = 300;
+        StockWatch::new(&FlavorCatalog::new(vec![
+            mint,
+            FlavorStock::new(FlavorID::Lemon, 1000),
+        ]))
+    }
+
+    #[test]
+    fn test_flavor_is_announced_once_when_it_crosses_the_watermark() {
+        let mut watch = watch();
+        let mut token = FlavorToken::new(FlavorID::Mint, 500);
+
+        assert_eq!(watch.observe(&token), None);
+        token.try_serve(250).unwrap();
+        let low = watch.observe(&token);
+        assert_eq!(
+            low,
+            Some(StockLevel::Low {
+                flavor: FlavorID::Mint,
+                total: 250
+            })
+        );
+        assert_eq!(watch.observe(&token), None);
+        token.restock(100);
+        assert_eq!(
+            watch.observe(&token),
+            Some(StockLevel::Recovered {
+                flavor: FlavorID::Mint,
+                total: 350
+            })
+        );
+        assert_eq!(watch.observe(&FlavorToken::new(FlavorID::Lemon, 0)), None);
+    }
+
+    #[test]
+    fn test_shards_of_a_flavor_are_added() {
+        let mut watch = watch();
+
+        assert!(watch
+            .observe(&FlavorToken::new(FlavorID::Mint, 200).in_shard(0))
+            .is_some());
+        assert_eq!(
+            watch.observe(&FlavorToken::new(FlavorID::Mint, 200).in_shard(1)),
+            Some(StockLevel::Recovered {
+                flavor: FlavorID::Mint,
+                total: 400
+            })
+        );
+        watch.reset();
+        assert!(watch
+            .observe(&FlavorToken::new(FlavorID::Mint, 100).in_shard(0))
+            .is_some());
+    }
+
+    #[test]
+    fn test_order_is_only_rejected_if_no_substitute_is_left() {
+        let rejecting = HashSet::from([FlavorID::Mint, FlavorID::Lemon]);
+        let order = Order::new_cuarto(vec![FlavorID::Vanilla, FlavorID::Mint]).unwrap();
+
+        assert_eq!(low_flavor_of(&order, &rejecting), Some(FlavorID::Mint));
+        let order = order.with_substitutes(FlavorID::Mint, vec![FlavorID::Lemon]);
+        assert_eq!(low_flavor_of(&order, &rejecting), Some(FlavorID::Mint));
+        let order =
+            order.with_substitutes(FlavorID::Mint, vec![FlavorID::Lemon, FlavorID::Chocolate]);
+        assert_eq!(low_flavor_of(&order, &rejecting), None);
+    }
+
+    #[test]
+    fn test_orders_without_a_token_for_a_flavor_are_unservable() {
+        let catalog = FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 1000),
+            FlavorStock::new(FlavorID::Lemon, 1000),
+        ]);
+        let cluster = ClusterConfig::default();
+        let order = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        assert_eq!(unservable(&order, &catalog, &cluster), None);
+
+        let custom = FlavorID::Custom("Banana".to_string());
+        let order = Order::new_cuarto(vec![FlavorID::Mint, custom.clone()])
+            .unwrap()
+            .with_substitutes(custom.clone(), vec![FlavorID::Lemon]);
+        assert_eq!(
+            unservable(&order, &catalog, &cluster),
+            Some(Unservable::UnknownFlavor(custom))
+        );
+        assert_eq!(
+            unservable(&Order::Cuarto(vec![]), &catalog, &cluster),
+            Some(Unservable::NoFlavors)
+        );
+    }
+
+    #[test]
+    fn test_flavors_of_another_shard_are_left_to_its_leader() {
+        let catalog = FlavorCatalog::new(vec![FlavorStock::new(FlavorID::Mint, 1000)]);
+        let cluster = ClusterConfig {
+            leader_shards: vec![vec![FlavorID::Mint], vec![FlavorID::Lemon]],
+            ..ClusterConfig::default()
+        };
+        let order = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        assert_eq!(unservable(&order, &catalog, &cluster), None);
+        let order = Order::new_cuarto(vec![FlavorID::Lemon, FlavorID::Chocolate]).unwrap();
+        let rejected = unservable(&order, &catalog, &cluster).unwrap();
+        assert_eq!(rejected, Unservable::UnknownFlavor(FlavorID::Chocolate));
+        assert_eq!(rejected.reason(), AbortReason::UnknownFlavor);
+    }
+
+    #[test]
+    fn test_menu_has_the_flavors_with_grams_left() {
+        let catalog = FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 1000),
+            FlavorStock::new(FlavorID::Lemon, 1000),
+            FlavorStock::new(FlavorID::Vanilla, 1000),
+            FlavorStock::new(FlavorID::Chocolate, 1000),
+        ]);
+        let stock = BTreeMap::from([("Mint".to_string(), 0), ("Lemon".to_string(), 200)]);
+        let rejecting = HashSet::from([FlavorID::Vanilla]);
+        assert_eq!(
+            in_stock(&catalog, &stock, &rejecting),
+            vec![FlavorID::Lemon, FlavorID::Chocolate]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_has_the_grams_of_every_flavor() {
+        let catalog = FlavorCatalog::new(vec![
+            FlavorStock::new(FlavorID::Mint, 1000),
+            FlavorStock::new(FlavorID::Lemon, 1000),
+        ]);
+        let stock = BTreeMap::from([("Mint".to_string(), 150)]);
+        assert_eq!(
+            snapshot(&catalog, &stock),
+            vec![(FlavorID::Mint, 150), (FlavorID::Lemon, 1000)]
+        );
+    }
+}
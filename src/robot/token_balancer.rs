@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::protocol::flavor_token::{FlavorToken, TokenKey};
+
+/// What the balancer did with a token that passed by
+#[derive(Debug, Default, PartialEq)]
+pub struct Rebalance {
+    /// Grams added to the token, that were taken from a richer token of its flavor
+    pub merged: usize,
+    /// Grams taken from the token, and the token of its flavor that will get them
+    pub split: Option<(TokenKey, usize)>,
+}
+
+/// Keeps balanced the grams of the tokens of each flavor, so no robot waits for an empty token while another one is full.
+/// The leader's robot splits the extra grams of a token that has much more than the poorest token of its flavor,
+/// and merges them into that token the next time it passes by. Only the grams that are not reserved are balanced
+#[derive(Debug, Default)]
+pub struct TokenBalancer {
+    last_seen: HashMap<TokenKey, usize>,
+    in_transit: HashMap<TokenKey, usize>,
+}
+
+impl TokenBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merges the grams waiting for the token, and splits it if it has more than `threshold` grams
+    /// over the poorest token of its flavor that is not already getting grams
+    pub fn balance(&mut self, token: &mut FlavorToken, threshold: usize) -> Rebalance {
+        let mut rebalance = Rebalance::default();
+        let key = token.key();
+        if let Some(amount) = self.in_transit.remove(&key) {
+            token.restock(amount);
+            rebalance.merged = amount;
+        }
+        self.last_seen.insert(key.clone(), token.available());
+
+        let poorest = self
+            .last_seen
+            .iter()
+            .filter(|(other, _)| {
+                other.0 == key.0 && **other != key && !self.in_transit.contains_key(other)
+            })
+            .min_by_key(|(other, amount)| (**amount, other.1))
+            .map(|(other, amount)| (other.clone(), *amount));
+
+        if let Some((poorest, poorest_amount)) = poorest {
+            let difference = token.available().saturating_sub(poorest_amount);
+            if difference > threshold {
+                let amount = difference / 2;
+                if token.try_serve(amount).is_err() {
+                    return rebalance;
+                }
+                self.in_transit.insert(poorest.clone(), amount);
+                self.last_seen
+                    .insert(poorest.clone(), poorest_amount + amount);
+                self.last_seen.insert(key, token.available());
+                rebalance.split = Some((poorest, amount));
+            }
+        }
+        rebalance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+
+    #[test]
+    fn test_rich_token_is_split_into_the_poorest() {
+        let mut balancer = TokenBalancer::new();
+        let mut poor = FlavorToken::new(FlavorID::Mint, 100).in_shard(1);
+        let mut rich = FlavorToken::new(FlavorID::Mint, 1100);
+        let mut other_flavor = FlavorToken::new(FlavorID::Lemon, 0);
+
+        assert_eq!(balancer.balance(&mut poor, 500), Rebalance::default());
+        assert_eq!(
+            balancer.balance(&mut other_flavor, 500),
+            Rebalance::default()
+        );
+        let rebalance = balancer.balance(&mut rich, 500);
+        assert_eq!(rebalance.split, Some(((FlavorID::Mint, 1), 500)));
+        assert_eq!(rich.get_amnt(), 600);
+
+        assert_eq!(balancer.balance(&mut poor, 500).merged, 500);
+        assert_eq!(poor.get_amnt(), 600);
+    }
+
+    #[test]
+    fn test_small_differences_are_left_alone() {
+        let mut balancer = TokenBalancer::new();
+        let mut first = FlavorToken::new(FlavorID::Mint, 400);
+        let mut second = FlavorToken::new(FlavorID::Mint, 800).in_shard(1);
+
+        balancer.balance(&mut first, 500);
+        assert_eq!(balancer.balance(&mut second, 500), Rebalance::default());
+        assert_eq!(second.get_amnt(), 800);
+    }
+}
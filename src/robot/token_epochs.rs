@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use crate::protocol::flavor_token::{FlavorToken, TokenKey};
+
+/// Highest recovery epoch seen by the robot for every token.
+/// Every time a token is recovered its epoch grows, so a copy with a lower epoch is a stale one
+/// that was not really lost, it is dropped and its amount is merged into the current token
+#[derive(Debug, Default)]
+pub struct TokenEpochs {
+    current: HashMap<TokenKey, u64>,
+    stale_amounts: HashMap<TokenKey, usize>,
+}
+
+impl TokenEpochs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks a token that arrived, it returns false if it is a stale copy that has to be dropped.
+    /// The amount of a stale copy is merged into the next current copy of the token, keeping the smallest one
+    pub fn accept_token(&mut self, token: &mut FlavorToken) -> bool {
+        let key = token.key();
+        let current = self.current.entry(key.clone()).or_insert(0);
+        if token.get_epoch() < *current {
+            let stale = self.stale_amounts.entry(key).or_insert(token.get_amnt());
+            *stale = (*stale).min(token.get_amnt());
+            return false;
+        }
+        *current = token.get_epoch();
+        if let Some(amount) = self.stale_amounts.remove(&key) {
+            token.keep_lowest_amount(amount);
+        }
+        true
+    }
+
+    /// Checks if a recovery with the epoch is newer than every copy of the token seen,
+    /// if not, the token was already recovered by someone else
+    pub fn is_recovery_current(&self, key: &TokenKey, epoch: u64) -> bool {
+        epoch > self.current.get(key).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+
+    #[test]
+    fn test_stale_token_is_dropped_and_merged() {
+        let mut epochs = TokenEpochs::new();
+        let mut recovered = FlavorToken::with_epoch(FlavorID::Mint, 800, 1);
+        assert!(epochs.accept_token(&mut recovered));
+
+        let mut stale = FlavorToken::new(FlavorID::Mint, 500);
+        assert!(!epochs.accept_token(&mut stale));
+
+        assert!(epochs.accept_token(&mut recovered));
+        assert_eq!(recovered.get_amnt(), 500);
+    }
+
+    #[test]
+    fn test_recovery_is_stale_once_the_epoch_was_seen() {
+        let mut epochs = TokenEpochs::new();
+        assert!(epochs.is_recovery_current(&(FlavorID::Mint, 0), 1));
+
+        let mut recovered = FlavorToken::with_epoch(FlavorID::Mint, 800, 1);
+        epochs.accept_token(&mut recovered);
+        assert!(!epochs.is_recovery_current(&(FlavorID::Mint, 0), 1));
+        assert!(epochs.is_recovery_current(&(FlavorID::Mint, 1), 1));
+        assert!(epochs.is_recovery_current(&(FlavorID::Lemon, 0), 1));
+    }
+}
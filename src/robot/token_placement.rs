@@ -0,0 +1,82 @@
+//! Where the first leader starts the flavor tokens.
+//! Starting them all at the leader's robot makes every robot wait for the same tokens to go around the ring,
+//! so they can be dealt among the robots connected to the leader, or given to the robots the config says.
+
+use std::collections::BTreeMap;
+
+use crate::config::tokens::TokenStart;
+use crate::protocol::flavor_token::FlavorToken;
+
+/// Splits the tokens among the robots, by the robot each one starts at.
+/// A robot that is not connected gets none, its tokens start at the leader's robot
+pub fn place_tokens(
+    tokens: Vec<FlavorToken>,
+    start: TokenStart,
+    owners: &BTreeMap<String, usize>,
+    leader_robot: usize,
+    connected: &[usize],
+) -> BTreeMap<usize, Vec<FlavorToken>> {
+    let mut robots: Vec<usize> = connected.to_vec();
+    robots.push(leader_robot);
+    robots.sort();
+    robots.dedup();
+
+    let mut placed: BTreeMap<usize, Vec<FlavorToken>> = BTreeMap::new();
+    for (i, token) in tokens.into_iter().enumerate() {
+        let robot = match start {
+            TokenStart::Leader => leader_robot,
+            TokenStart::RoundRobin => robots[i % robots.len()],
+            TokenStart::Owners => owners
+                .get(token.get_id().name())
+                .copied()
+                .filter(|owner| robots.contains(owner))
+                .unwrap_or(leader_robot),
+        };
+        placed.entry(robot).or_default().push(token);
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+
+    fn tokens() -> Vec<FlavorToken> {
+        [FlavorID::Mint, FlavorID::Lemon, FlavorID::Vanilla]
+            .into_iter()
+            .map(|flavor| FlavorToken::new(flavor, 1000))
+            .collect()
+    }
+
+    fn flavors_of(placed: &BTreeMap<usize, Vec<FlavorToken>>, robot: usize) -> Vec<FlavorID> {
+        placed
+            .get(&robot)
+            .map(|tokens| tokens.iter().map(|t| t.get_id()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_tokens_are_dealt_to_the_connected_robots() {
+        let placed = place_tokens(tokens(), TokenStart::RoundRobin, &BTreeMap::new(), 2, &[0]);
+        assert_eq!(
+            flavors_of(&placed, 0),
+            vec![FlavorID::Mint, FlavorID::Vanilla]
+        );
+        assert_eq!(flavors_of(&placed, 2), vec![FlavorID::Lemon]);
+
+        let placed = place_tokens(tokens(), TokenStart::Leader, &BTreeMap::new(), 2, &[0]);
+        assert_eq!(placed.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn test_tokens_of_unowned_flavors_start_at_the_leader() {
+        let owners = BTreeMap::from([("Mint".to_string(), 1), ("Lemon".to_string(), 3)]);
+        let placed = place_tokens(tokens(), TokenStart::Owners, &owners, 0, &[1, 2]);
+        assert_eq!(flavors_of(&placed, 1), vec![FlavorID::Mint]);
+        assert_eq!(
+            flavors_of(&placed, 0),
+            vec![FlavorID::Lemon, FlavorID::Vanilla]
+        );
+    }
+}
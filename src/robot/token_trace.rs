@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::common::flavor_id::FlavorID;
+use crate::protocol::flavor_token::{FlavorToken, TokenKey};
+
+/// A hop of a token and how long it took to get to the next robot, unknown for the last one
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TraceHop {
+    pub robot_id: usize,
+    pub at_ms: u64,
+    pub held_ms: Option<u64>,
+}
+
+/// The last hops of a token, as seen the last time it went through the leader's robot
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TokenTrace {
+    pub flavor: FlavorID,
+    pub shard: usize,
+    pub epoch: u64,
+    pub amount: usize,
+    pub hops: Vec<TraceHop>,
+}
+
+/// Keeps the last copy of every token seen by the leader's robot, with the hops it carries.
+/// Since every token goes through the leader's robot, it shows how the whole ring is doing
+#[derive(Debug, Default)]
+pub struct TokenTracer {
+    last_seen: HashMap<TokenKey, FlavorToken>,
+}
+
+impl TokenTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the token as the last copy seen of it
+    pub fn record(&mut self, token: &FlavorToken) {
+        self.last_seen.insert(token.key(), token.clone());
+    }
+
+    /// Returns the trace of every token seen, sorted by flavor and shard
+    pub fn traces(&self) -> Vec<TokenTrace> {
+        let mut traces: Vec<TokenTrace> = self
+            .last_seen
+            .values()
+            .map(|token| {
+                let hops = token.get_hops();
+                let held = hops
+                    .windows(2)
+                    .map(|pair| Some(pair[1].at_ms.saturating_sub(pair[0].at_ms)))
+                    .chain([None]);
+                TokenTrace {
+                    flavor: token.get_id(),
+                    shard: token.get_shard(),
+                    epoch: token.get_epoch(),
+                    amount: token.get_amnt(),
+                    hops: hops
+                        .iter()
+                        .zip(held)
+                        .map(|(hop, held_ms)| TraceHop {
+                            robot_id: hop.robot_id,
+                            at_ms: hop.at_ms,
+                            held_ms,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        traces.sort_by_key(|trace| (trace.flavor.to_string(), trace.shard));
+        traces
+    }
+
+    /// Writes the traces to the file as JSON, replacing what it had
+    pub fn dump(&self, path: &str) -> io::Result<()> {
+        let json = serde_json::to_string(&self.traces())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        fs::write(path, json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_has_the_time_each_robot_held_the_token() {
+        let mut tracer = TokenTracer::new();
+        let mut token = FlavorToken::new(FlavorID::Mint, 500);
+        token.record_hop(1);
+        token.record_hop(2);
+        tracer.record(&token);
+        token.record_hop(3);
+        tracer.record(&FlavorToken::new(FlavorID::Lemon, 100));
+        tracer.record(&token);
+
+        let traces = tracer.traces();
+        assert_eq!(traces.len(), 2);
+        let mint = traces
+            .iter()
+            .find(|trace| trace.flavor == FlavorID::Mint)
+            .unwrap();
+        let robots: Vec<usize> = mint.hops.iter().map(|hop| hop.robot_id).collect();
+        assert_eq!(robots, vec![1, 2, 3]);
+        assert!(mint.hops[..2].iter().all(|hop| hop.held_ms.is_some()));
+        assert_eq!(mint.hops[2].held_ms, None);
+    }
+}
@@ -1,86 +1,176 @@
+use crate::common::log;
 use actix::prelude::*;
 use colored::*;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::{self};
 use tokio::time::{timeout_at, Instant};
-use tokio_stream::wrappers::LinesStream;
 
+use crate::common::chaos;
+use crate::common::codec;
+use crate::common::error::FreddoError;
+use crate::common::handshake::PendingHandshakes;
+use crate::common::health;
 use crate::common::order::KILO;
-use crate::common::utils::{id_to_leader_addr, id_to_screen_addr};
-use crate::config::MAX_NUMBER_OF_ROBOTS;
+use crate::common::registry::{self, Registry};
+use crate::common::utils::{connect, id_to_leader_addr, id_to_screen_addr};
+use crate::config;
+use crate::protocol;
+use crate::protocol::robot_messages::RobotMessage;
 use crate::robot::connections::robot_to_leader_connection::RobotToLeaderConnection;
-use crate::robot::errors::RobotConnectionError;
 use crate::robot::messages::*;
-use crate::robot::order_preparer::SCOOP_TIME_FACTOR;
 use crate::robot::robot_connection_handler::RobotConnectionHandler;
 use crate::robot::robot_leader::RobotLeader;
 
 use super::order_manager::OrderManager;
+use super::order_preparer::OrderPreparer;
 
-pub const INITIAL_AMOUNT: usize = 4000;
-
-pub const MAX_ROBOT_ID: usize = MAX_NUMBER_OF_ROBOTS - 1;
-
-pub const NEW_NEXT_ROBOT: char = 'n';
-pub const NEW_PREV_ROBOT: char = 'p';
+/// Byte sent to the screens by the leader after the version of the protocol, when it connects to them
 pub const NEW_ROBOT_LEADER: char = 'r';
+/// Byte sent to the leader by the dashboard after the version of the protocol, instead of a robot id
+pub const DASHBOARD_CONNECTION: u8 = b'd';
+/// Byte sent to the leader by a screen that connects to it after the version of the protocol, followed by the id of the screen
+pub const SCREEN_CONNECTION: u8 = b's';
+
+/// How often a robot waiting for the cluster checks which robots are up
+const CLUSTER_WAIT_POLL: Duration = Duration::from_millis(250);
+
+/// Starts the actors of the robot with the given id and makes it join the ring, after waiting for the cluster if `wait_for` is set.
+/// The robot scoops a gram in `ms_per_gram` milliseconds, or in the ones of the config for its id if it is not set.
+/// It has to be called from inside an actix System, it returns the actors that the control socket talks to
+pub fn start_robot(
+    id: usize,
+    wait_for: Option<ClusterWait>,
+    ms_per_gram: Option<usize>,
+) -> (Addr<RobotConnectionHandler>, Addr<OrderManager>) {
+    let ms_per_gram = ms_per_gram.unwrap_or_else(|| config::get().scoops.ms_per_gram_of(id));
+    let registry = Registry::new();
+    let order_preparer: Addr<OrderPreparer> = OrderPreparer::new(id)
+        .with_ms_per_gram(ms_per_gram)
+        .with_registry(registry.clone())
+        .start();
+    let o_manager: Addr<OrderManager> = OrderManager::new(order_preparer, id)
+        .with_registry(registry.clone())
+        .with_orders_in_flight()
+        .start();
+    registry.register(registry::ORDER_MANAGER, o_manager.clone());
+
+    let robot_connection_handler = RobotConnectionHandler::create(|ctx| {
+        registry.register(registry::RCH, ctx.address());
+        RobotConnectionHandler::new(o_manager.clone(), id, ms_per_gram)
+            .with_registry(registry.clone())
+    });
+    if let Err(e) = robot_connection_handler.try_send(JoinRing { wait_for }) {
+        log::send_error("Main", "JoinRing", &e.to_string());
+    }
+    (robot_connection_handler, o_manager)
+}
 
-const TIME_OUT: usize = ((MAX_NUMBER_OF_ROBOTS - 1) * SCOOP_TIME_FACTOR * KILO) / 2;
+/// Time the robot waits for the tokens of an order, enough for the other robots of the ring to scoop half a kilo each,
+/// at the speed the config gives each of them. `ring` has the other robots of the ring if they are known,
+/// if not every robot of the config is counted
+pub fn token_timeout(robot_id: usize, ring: Option<&[usize]>) -> Duration {
+    let scoops = &config::get().scoops;
+    let all: Vec<usize> = (0..config::number_of_robots()).collect();
+    let others = ring.unwrap_or(&all).iter().filter(|id| **id != robot_id);
+    let ms_per_gram = match others.map(|id| scoops.ms_per_gram_of(*id)).sum() {
+        0 => scoops.ms_per_gram_of(robot_id),
+        sum => sum,
+    };
+    Duration::from_millis((ms_per_gram * KILO / 2) as u64)
+}
+
+/// Time a peer that lost its connection has to open it again, it covers the first wait and every retry
+pub fn reconnect_window() -> Duration {
+    config::get().retry.window()
+}
 
 /// Returns the address of the robot with the given id.
 pub fn id_to_robot_addr(id: usize) -> String {
-    "127.0.0.1:807".to_owned() + &*id.to_string()
+    config::get().network.robot_addr(id)
+}
+
+/// Writes the handshake of a connection to a robot, the version of the protocol and the command that says who is connecting
+pub async fn write_handshake<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    command: RobotCommand,
+) -> Result<(), FreddoError> {
+    protocol::write_version(writer).await?;
+    write_command(writer, command).await
+}
+
+/// Reads the handshake of a connection to a robot, a peer that speaks another version of the protocol is turned down
+pub async fn read_handshake(reader: &mut OwnedReadHalf) -> Result<RobotCommand, FreddoError> {
+    protocol::read_version(reader).await?;
+    read_command(reader).await
+}
+
+/// Writes a single command to the connection
+pub async fn write_command<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    command: RobotCommand,
+) -> Result<(), FreddoError> {
+    let msg = command
+        .to_bytes()
+        .map_err(|err| FreddoError::Serialization(err.to_string()))?;
+    writer.write_all(&msg).await?;
+    Ok(())
 }
 
-/// Tries to write a message to the next robot, it returns false if the connection was closed.
+/// Reads the first command of a connection, the rest of the frames are left for the actor of the connection
+pub async fn read_command(reader: &mut OwnedReadHalf) -> Result<RobotCommand, FreddoError> {
+    let frame = codec::read_frame(reader).await?;
+    RobotCommand::from_bytes(&frame).map_err(|err| FreddoError::Serialization(err.to_string()))
+}
+
+/// Tries to write a message of the robot to the next robot, it fails if the connection was closed.
 pub async fn try_write_all(
     write_half: &mut OwnedWriteHalf,
     read_half: &OwnedReadHalf,
-    msg: &str,
-) -> bool {
+    msg: &[u8],
+    my_id: usize,
+) -> Result<(), FreddoError> {
     let mut buff: [u8; 1] = [0; 1];
     if let Ok(0) = read_half.try_read(buff.as_mut()) {
-        let line = "[RCH] The next robot closed the connection!".to_string();
-        println!("{}", line.red());
-        return false;
-    }
-
-    if let Err(e) = write_half.write_all(msg.as_bytes()).await {
-        let line = format!("Could not write! : {}", e);
-        println!("{}", line.red());
-        return false;
+        return Err(FreddoError::Connection(
+            "The next robot closed the connection".to_string(),
+        ));
     }
-    true
+    chaos::write_all(write_half, msg, "RING", my_id).await?;
+    Ok(())
 }
 
-/// Function that handles the timeout of the token.
-pub async fn token_lost_timeout(mut receiver: mpsc::Receiver<usize>, addr: Addr<OrderManager>) {
+/// Function that handles the timeout of the tokens needed by an order.
+/// The timeout is read again every time the timer is updated, in milliseconds, since it follows the size of the ring
+pub async fn token_lost_timeout(
+    mut receiver: mpsc::Receiver<usize>,
+    addr: Addr<OrderManager>,
+    order_id: String,
+    timeout_ms: Arc<AtomicU64>,
+) {
     loop {
-        match timeout_at(
-            Instant::now() + Duration::from_millis(TIME_OUT as u64),
-            receiver.recv(),
-        )
-        .await
-        {
+        let timeout = Duration::from_millis(timeout_ms.load(Ordering::Relaxed));
+        match timeout_at(Instant::now() + timeout, receiver.recv()).await {
             Ok(Some(0)) => {}
             Ok(Some(1)) => {
                 break;
             }
             Ok(Some(_)) => {
-                println!("Error: Unexpected message received!");
+                log::error("OM", "Unexpected message received!");
                 break;
             }
             Ok(None) => {
-                println!("Error: Message is None!");
+                log::error("OM", "Message is None!");
                 break;
             }
             Err(_) => {
-                if let Err(e) = addr.try_send(TimerWentOff {}) {
-                    print_send_error("[OM]", "TimerWentOff", &e.to_string());
+                if let Err(e) = addr.try_send(TimerWentOff { order_id }) {
+                    log::send_error("OM", "TimerWentOff", &e.to_string());
                 }
                 break;
             }
@@ -91,43 +181,43 @@ pub async fn token_lost_timeout(mut receiver: mpsc::Receiver<usize>, addr: Addr<
 /// Sends a message to the next robot, if the connection is closed it tries to connect to the next robot and so on.
 /// It returns the id of the robot that is now the next robot.
 pub async fn safe_send_next(
-    msg: String,
+    msg: Vec<u8>,
     next_robot: &mut OwnedWriteHalf,
     next_read: &mut OwnedReadHalf,
     my_id: usize,
     next_id: usize,
 ) -> usize {
-    let mut curr_next_id = (my_id + 1) % MAX_NUMBER_OF_ROBOTS;
-    if !try_write_all(next_robot, next_read, &msg).await {
-        let line =
-            "[RCH] Could not send message to the next robot! Trying to connect to the next one"
-                .to_string();
-        println!("{}", line.red());
+    let robots = config::number_of_robots();
+    let mut curr_next_id = (my_id + 1) % robots;
+    if let Err(e) = try_write_all(next_robot, next_read, &msg, my_id).await {
+        let line = format!(
+            "Could not send message to the next robot! Trying to connect to the next one: {}",
+            e
+        );
+        log::error("RCH", line.red());
 
         // println!("Next robot to try: {}", curr_next_id);
         while curr_next_id != my_id {
             let next_addr = format!("Trying to connect to {}:", curr_next_id);
-            println!("{}", next_addr.bright_cyan());
-            match TcpStream::connect(id_to_robot_addr(curr_next_id)).await {
+            log::info("RCH", next_addr.bright_cyan());
+            match connect(&id_to_robot_addr(curr_next_id)).await {
                 Ok(mut stream) => {
-                    if let Err(e) = stream.write_all(&[NEW_PREV_ROBOT as u8]).await {
-                        let line = format!(
-                            "[RCH] Error trying to send my id to the new next robot: {}",
-                            e
-                        );
-                        println!("{}", line.red());
-                        curr_next_id = (curr_next_id + 1) % MAX_NUMBER_OF_ROBOTS;
+                    let handshake = RobotCommand::NewPreviousRobot { robot_id: my_id };
+                    if let Err(e) = write_handshake(&mut stream, handshake).await {
+                        let line =
+                            format!("Error trying to send my id to the new next robot: {}", e);
+                        log::error("RCH", line.red());
+                        curr_next_id = (curr_next_id + 1) % robots;
                         continue;
                     }
                     let (read_half, mut write_half) = stream.into_split();
 
-                    let line = format!("[RCH] Connecting to next robot: {} !", curr_next_id);
-                    println!("{}", line.bright_cyan());
+                    let line = format!("Connecting to next robot: {} !", curr_next_id);
+                    log::info("RCH", line.bright_cyan());
 
-                    if !try_write_all(&mut write_half, &read_half, &msg).await {
-                        let line = "[RCH] The next robot closed the connection!".to_string();
-                        println!("{}", line.red());
-                        curr_next_id = (curr_next_id + 1) % MAX_NUMBER_OF_ROBOTS;
+                    if let Err(e) = try_write_all(&mut write_half, &read_half, &msg, my_id).await {
+                        log::error("RCH", e.to_string().red());
+                        curr_next_id = (curr_next_id + 1) % robots;
                         continue;
                     }
                     *next_robot = write_half;
@@ -135,7 +225,7 @@ pub async fn safe_send_next(
                     return curr_next_id;
                 }
                 Err(_) => {
-                    curr_next_id = (curr_next_id + 1) % MAX_NUMBER_OF_ROBOTS;
+                    curr_next_id = (curr_next_id + 1) % robots;
                     continue;
                 }
             }
@@ -151,192 +241,256 @@ pub async fn connect_to_leader(
     new_leader: usize,
     my_id: usize,
     addr: Addr<RobotConnectionHandler>,
-) -> Option<Addr<RobotToLeaderConnection>> {
-    match TcpStream::connect(id_to_leader_addr(new_leader)).await {
-        Ok(mut stream) => {
-            if let Err(e) = stream.write_all(&[my_id as u8]).await {
-                let line = format!("[RCH] Error trying to send my id to the new leader: {}", e);
-                println!("{}", line.red());
-                return None;
-            }
-            let (read_half, write_half) = stream.into_split();
-
-            let pipo = RobotToLeaderConnection::create(|own_ctx| {
-                let lines: LinesStream<BufReader<OwnedReadHalf>> =
-                    LinesStream::new(BufReader::new(read_half).lines());
-                let rpc = RobotToLeaderConnection::new(addr, Some(write_half));
-                RobotToLeaderConnection::add_stream(lines, own_ctx);
-                rpc
-            });
-            Some(pipo)
-        }
-        Err(_) => {
-            let line = "[RCH] Could not connect to the leader!".to_string();
-            println!("{}", line.red());
-            None
-        }
-    }
+) -> Result<Addr<RobotToLeaderConnection>, FreddoError> {
+    let mut stream = connect(&id_to_leader_addr(new_leader)).await?;
+    protocol::write_version(&mut stream).await?;
+    stream.write_all(&[my_id as u8]).await?;
+    let (read_half, write_half) = stream.into_split();
+
+    let pipo = RobotToLeaderConnection::create(|own_ctx| {
+        let lines = codec::frames(read_half);
+        let rpc = RobotToLeaderConnection::new(addr, new_leader, Some(write_half));
+        RobotToLeaderConnection::add_stream(lines, own_ctx);
+        rpc
+    });
+    Ok(pipo)
 }
 
 /// Connects to the screen with the given id.
-pub async fn connect_to_screen(screen_id: usize, address: Addr<RobotLeader>) {
-    let s_id = screen_id;
-    match TcpStream::connect(id_to_screen_addr(s_id)).await {
-        Ok(mut stream) => {
-            if (stream.write_all(&[NEW_ROBOT_LEADER as u8]).await).is_err() {
-                let line = "[RCH] Error trying to send my id to the new screen".to_string();
-                println!("{}", line.red());
-                return;
-            }
+pub async fn connect_to_screen(
+    screen_id: usize,
+    address: Addr<RobotLeader>,
+) -> Result<(), FreddoError> {
+    let mut stream = connect(&id_to_screen_addr(screen_id)).await?;
+    protocol::write_version(&mut stream).await?;
+    stream.write_all(&[NEW_ROBOT_LEADER as u8]).await?;
+
+    let (r_half, w_half) = stream.into_split();
+    address.try_send(AddNewScreen {
+        screen_id,
+        write_half: w_half,
+        read_half: r_half,
+    })?;
+    Ok(())
+}
 
-            let (r_half, w_half) = stream.into_split();
-            if let Err(e) = address.try_send(AddNewScreen {
-                screen_id: s_id,
-                write_half: w_half,
-                read_half: r_half,
-            }) {
-                print_send_error("[RL]", "AddNewScreen", &e.to_string());
-            }
-        }
-        Err(_) => {
-            // println!("Could not connect with screen: {}\n{}", screen_id, e);
+/// Connects to the leader of a shard as the screen `screen_id`, trying each address it can listen on.
+/// The robot that answers with a NewLeader is leading the shard, it sends nothing before it
+pub async fn connect_to_shard_leader(
+    addrs: &[String],
+    screen_id: usize,
+) -> Result<(OwnedReadHalf, OwnedWriteHalf), FreddoError> {
+    let mut last_error = FreddoError::Connection("The shard has no leader address".to_string());
+    for addr in addrs {
+        match shard_leader_handshake(addr, screen_id).await {
+            Ok(halves) => return Ok(halves),
+            Err(e) => last_error = e,
         }
     }
+    Err(last_error)
+}
+
+/// Opens a connection as a screen to the leader that may listen on the address and waits for its NewLeader
+async fn shard_leader_handshake(
+    addr: &str,
+    screen_id: usize,
+) -> Result<(OwnedReadHalf, OwnedWriteHalf), FreddoError> {
+    let mut stream = connect(addr).await?;
+    protocol::write_version(&mut stream).await?;
+    stream
+        .write_all(&[SCREEN_CONNECTION, screen_id as u8])
+        .await?;
+    let (mut read_half, write_half) = stream.into_split();
+    let wait = Duration::from_millis(config::get().heartbeat.timeout_ms);
+    let frame = tokio::time::timeout(wait, codec::read_frame(&mut read_half))
+        .await
+        .map_err(|_| FreddoError::Connection(format!("{} did not answer", addr)))??;
+    match RobotMessage::from_bytes(&frame) {
+        Ok(RobotMessage::NewLeader { .. }) => Ok((read_half, write_half)),
+        other => Err(FreddoError::Protocol(format!(
+            "Expected the leader of the shard, got: {:?}",
+            other
+        ))),
+    }
 }
 
 /// Asks for the leader of the next robot.
-async fn ask_for_leader(mut stream: TcpStream) -> Result<(TcpStream, usize), RobotConnectionError> {
-    let mut buff_leader_id = [0; 1];
-    // println!("Asking for leader");
-    if let Err(e) = stream.read_exact(&mut buff_leader_id).await {
-        return Err(RobotConnectionError::ColudNotConnectToRobot(e.to_string()));
+async fn ask_for_leader(read_half: &mut OwnedReadHalf) -> Result<Option<usize>, FreddoError> {
+    match read_command(read_half).await? {
+        RobotCommand::LeaderId { leader } => Ok(leader),
+        other => Err(FreddoError::Protocol(format!(
+            "Expected the leader id, got: {:?}",
+            other
+        ))),
     }
-    Ok((stream, buff_leader_id[0] as usize))
+}
+
+/// Place in the ring of the robot we connect to
+#[derive(Clone, Copy, PartialEq)]
+enum Neighbor {
+    Previous,
+    Next,
 }
 
 /// Connects to a robot it can be its previous and next.
+/// When connecting to the next robot it returns the leader it knows, if any.
 async fn connect_to_robot(
     id: usize,
     my_id: usize,
     address: Addr<RobotConnectionHandler>,
-    place: char,
-) -> Result<usize, RobotConnectionError> {
-    match TcpStream::connect(id_to_robot_addr(id)).await {
-        Ok(mut stream) => {
-            if place == NEW_PREV_ROBOT {
-                // println!("Trying to connect to previous robot: {}", id);
-                if let Err(err) = stream.write_all(&[NEW_NEXT_ROBOT as u8]).await {
-                    return Err(RobotConnectionError::ColudNotConnectToRobot(
-                        err.to_string(),
-                    ));
-                }
-                if let Err(err) = stream.write_all(&[my_id as u8]).await {
-                    return Err(RobotConnectionError::ColudNotConnectToRobot(
-                        err.to_string(),
-                    ));
-                }
-
-                let line = format!("[RCH] Connected to the previous robot, ID: {}!", id);
-                println!("{}", line.bright_cyan());
-
-                let (r_half, w_half) = stream.into_split();
-                if let Err(e) = address.try_send(AddPreviousRobot {
-                    write_half: w_half,
-                    read_half: r_half,
-                    asked: true,
-                }) {
-                    print_send_error("[RCH]", "AddPreviousRobot", &e.to_string());
-                }
-                Ok(MAX_NUMBER_OF_ROBOTS)
-            } else {
-                // println!("Trying to connect to next robot: {}", id);
-                if let Err(err) = stream.write_all(&[NEW_PREV_ROBOT as u8]).await {
-                    return Err(RobotConnectionError::ColudNotConnectToRobot(
-                        err.to_string(),
-                    ));
-                }
-
-                match ask_for_leader(stream).await {
-                    Ok((stream_2, leader_id)) => {
-                        let line = format!("[RCH] Connected to the next robot, ID: {}!", id);
-                        println!("{}", line.bright_cyan());
+    place: Neighbor,
+) -> Result<Option<usize>, FreddoError> {
+    let mut stream = connect(&id_to_robot_addr(id)).await?;
+
+    if place == Neighbor::Previous {
+        write_handshake(&mut stream, RobotCommand::NewNextRobot { robot_id: my_id }).await?;
+
+        let line = format!("Connected to the previous robot, ID: {}!", id);
+        log::info("RCH", line.bright_cyan());
+
+        let (r_half, w_half) = stream.into_split();
+        if let Err(e) = address.try_send(AddPreviousRobot {
+            write_half: w_half,
+            read_half: r_half,
+            asked: true,
+        }) {
+            log::send_error("RCH", "AddPreviousRobot", &e.to_string());
+        }
+        return Ok(None);
+    }
 
-                        let (r_half, w_half) = stream_2.into_split();
-                        if let Err(e) = address.try_send(AddNextRobot {
-                            robot_id: id,
-                            write_half: w_half,
-                            read_half: r_half,
-                        }) {
-                            print_send_error("[RCH]", "AddNextRobot", &e.to_string());
-                        }
-                        Ok(leader_id)
-                    }
-                    Err(e) => {
-                        println!("Could not ask for leader: {}", e);
-                        Err(e)
-                    }
-                }
+    write_handshake(
+        &mut stream,
+        RobotCommand::NewPreviousRobot { robot_id: my_id },
+    )
+    .await?;
+    let (mut r_half, w_half) = stream.into_split();
+    match ask_for_leader(&mut r_half).await {
+        Ok(leader_id) => {
+            let line = format!("Connected to the next robot, ID: {}!", id);
+            log::info("RCH", line.bright_cyan());
+
+            if let Err(e) = address.try_send(AddNextRobot {
+                robot_id: id,
+                write_half: w_half,
+                read_half: r_half,
+            }) {
+                log::send_error("RCH", "AddNextRobot", &e.to_string());
             }
+            Ok(leader_id)
         }
         Err(e) => {
-            // println!("Could not connect to robot: {}", e);
-            Err(RobotConnectionError::ColudNotConnectToRobot(e.to_string()))
+            log::error("RCH", format!("Could not ask for leader: {}", e));
+            Err(e)
         }
     }
 }
 
+fn no_robots_available() -> FreddoError {
+    FreddoError::Connection("No robots available to connect".to_string())
+}
+
 /// Connects to the next robot and gets the leader's id.
 pub async fn connect_to_next_robot_and_get_leader(
     my_id: usize,
     address: Addr<RobotConnectionHandler>,
-) -> Result<usize, RobotConnectionError> {
-    let line = "[RCH] Trying to connect to the next robot".to_string();
-    println!("{}", line.bright_cyan());
-    let mut curr_id = (my_id + 1) % MAX_NUMBER_OF_ROBOTS;
+) -> Result<Option<usize>, FreddoError> {
+    let line = "Trying to connect to the next robot".to_string();
+    log::info("RCH", line.bright_cyan());
+    let robots = config::number_of_robots();
+    let mut curr_id = (my_id + 1) % robots;
     while curr_id != my_id {
         if let Ok(leader_id) =
-            connect_to_robot(curr_id, my_id, address.clone(), NEW_NEXT_ROBOT).await
+            connect_to_robot(curr_id, my_id, address.clone(), Neighbor::Next).await
         {
             return Ok(leader_id);
         };
-        curr_id = (curr_id + 1) % MAX_NUMBER_OF_ROBOTS;
+        curr_id = (curr_id + 1) % robots;
     }
-    Err(RobotConnectionError::NoRobotsAvailableError())
+    Err(no_robots_available())
 }
 
 /// Connects to the previous robot.
 pub async fn connect_to_prev_robot(
     my_id: usize,
     address: Addr<RobotConnectionHandler>,
-) -> Result<(), RobotConnectionError> {
-    let line = "[RCH] Trying to connect to the previous robot".to_string();
-    println!("{}", line.bright_cyan());
-    let mut curr_id = (my_id + MAX_NUMBER_OF_ROBOTS - 1) % MAX_NUMBER_OF_ROBOTS;
+) -> Result<(), FreddoError> {
+    let line = "Trying to connect to the previous robot".to_string();
+    log::info("RCH", line.bright_cyan());
+    let robots = config::number_of_robots();
+    let mut curr_id = (my_id + robots - 1) % robots;
     while curr_id != my_id {
-        if (connect_to_robot(curr_id, my_id, address.clone(), NEW_PREV_ROBOT).await).is_ok() {
+        if (connect_to_robot(curr_id, my_id, address.clone(), Neighbor::Previous).await).is_ok() {
             return Ok(());
         };
-        curr_id = (curr_id + MAX_NUMBER_OF_ROBOTS - 1) % MAX_NUMBER_OF_ROBOTS;
+        curr_id = (curr_id + robots - 1) % robots;
     }
-    Err(RobotConnectionError::NoRobotsAvailableError())
+    Err(no_robots_available())
 }
 
-/// Prints an error message when trying to send a message.
-pub fn print_send_error(origin: &str, msg: &str, e: &str) {
-    let line = format!(
-        "{} Error trying to send {} Message. Message dumped\n {}",
-        origin, msg, e
-    );
-    println!("{}", line.red());
+/// Connects to the first robot after the one that is leaving the ring, so the ring is closed without it.
+pub async fn connect_to_robot_after(
+    leaving_id: usize,
+    my_id: usize,
+    address: Addr<RobotConnectionHandler>,
+) -> Result<usize, FreddoError> {
+    let robots = config::number_of_robots();
+    let mut curr_id = (leaving_id + 1) % robots;
+    while curr_id != my_id {
+        if connect_to_robot(curr_id, my_id, address.clone(), Neighbor::Next)
+            .await
+            .is_ok()
+        {
+            return Ok(curr_id);
+        };
+        curr_id = (curr_id + 1) % robots;
+    }
+    Err(no_robots_available())
+}
+
+/// Returns the ids of the robots whose listener is up, counting this one
+async fn reachable_robots(my_id: usize) -> Vec<usize> {
+    let mut reachable = vec![my_id];
+    for id in (0..config::number_of_robots()).filter(|&id| id != my_id) {
+        if let Ok(mut stream) = connect(&id_to_robot_addr(id)).await {
+            if write_handshake(&mut stream, RobotCommand::Probe { robot_id: my_id })
+                .await
+                .is_ok()
+            {
+                reachable.push(id);
+            }
+        }
+    }
+    reachable
 }
 
-/// Prints an error message when trying to create a message.
-pub fn print_create_error(origin: &str, msg: &str, e: &str) {
+/// Waits until the robots of the wait, counting this one, are up. Returns their ids, or None if the timeout passed first
+pub async fn wait_for_robots(my_id: usize, wait: ClusterWait) -> Option<Vec<usize>> {
     let line = format!(
-        "{} Error trying to create the {} Message. Message dumped\n {}",
-        origin, msg, e
+        "Waiting for {} robots to be up, for {} seconds at most",
+        wait.robots,
+        wait.timeout.as_secs()
     );
-    println!("{}", line.red());
+    log::info("RCH", line.bright_cyan());
+    let deadline = Instant::now() + wait.timeout;
+    loop {
+        let reachable = reachable_robots(my_id).await;
+        if reachable.len() >= wait.robots {
+            let line = format!("Robots {:?} are up, joining the ring", reachable);
+            log::info("RCH", line.bright_cyan());
+            return Some(reachable);
+        }
+        if Instant::now() + CLUSTER_WAIT_POLL > deadline {
+            let line = format!(
+                "Only {} of {} robots are up, joining the ring without them",
+                reachable.len(),
+                wait.robots
+            );
+            log::warn("RCH", line.yellow());
+            return None;
+        }
+        tokio::time::sleep(CLUSTER_WAIT_POLL).await;
+    }
 }
 
 /// Starts the listener for the robots.
@@ -347,82 +501,102 @@ pub fn start_robots_connection_listener(addr: Addr<RobotConnectionHandler>, id:
             Ok(l) => l,
             Err(e) => {
                 let line = format!("Error! Could not bind to port: {}", e);
-                println!("{}", line.red());
+                log::error("RCH", line.red());
                 return;
             }
         };
+        health::get().listening();
+        let handshakes = PendingHandshakes::new("RCH");
 
         loop {
             let (stream, src_addr) = match listener.accept().await {
                 Ok((s, a)) => (s, a),
                 Err(e) => {
                     let line = format!("Error! Could not accept connection: {}", e);
-                    println!("{}", line.red());
+                    log::error("RCH", line.red());
                     continue;
                 }
             };
-            let (mut r_half, w_half) = stream.into_split();
-
-            // println!(
-            //     "{}",
-            //     "RobotConnectionHandler: connection accepted".bright_cyan()
-            // );
-
-            let mut buf = vec![0; 1];
-            if let Err(e) = r_half.read_exact(buf.as_mut_slice()).await {
-                let line = format!("Error! Could not read from stream: {}", e);
-                println!("{}", line.red());
+            let Some(slot) = handshakes.admit(src_addr) else {
                 continue;
-            }
-
-            if buf[0] as char == NEW_NEXT_ROBOT {
-                // println!("RCH: Recibi un mensaje de NextRobot de: {}", src_addr);
-                if let Err(e) = r_half.read_exact(buf.as_mut_slice()).await {
-                    let line = format!("Error! Could not read from stream: {}", e);
-                    println!("{}", line.red());
-                    continue;
-                }
-                // println!("ID LEIDO DEL SIGUIENTE ROBOT: {}", buf[0]);
-                if let Err(e) = addr.try_send(AddNextRobot {
-                    robot_id: buf[0] as usize,
-                    write_half: w_half,
-                    read_half: r_half,
-                }) {
-                    print_send_error("[RCH]", "AddNextRobot", &e.to_string());
-                }
-            } else if buf[0] as char == NEW_PREV_ROBOT {
-                // println!("RCH: Recibi un mensaje de PreviousRobot de: {}", src_addr);
-                if let Err(e) = addr.try_send(AddPreviousRobot {
-                    write_half: w_half,
-                    read_half: r_half,
-                    asked: false,
-                }) {
-                    print_send_error("[RCH]", "AddPreviousRobot", &e.to_string());
-                }
-            } else if buf[0] as char == NEW_ROBOT_LEADER {
-                if let Err(e) = r_half.read_exact(buf.as_mut_slice()).await {
-                    let line = format!("Error! Could not read from stream: {}", e);
-                    println!("{}", line.red());
-                    continue;
-                }
-                println!(
-                    "[RCH] New message from RobotLeader: {} the ID is: {}",
-                    src_addr, buf[0] as usize
-                );
-                if let Err(e) = addr.try_send(AddNewLeader {
-                    write_half: w_half,
-                    read_half: r_half,
-                    leader_id: buf[0] as usize,
-                }) {
-                    print_send_error("[RCH]", "AddNewLeader", &e.to_string());
+            };
+            let (addr, handshakes) = (addr.clone(), handshakes.clone());
+
+            // The first frame of every connection says who is connecting,
+            // after it the same connection carries all the messages between both robots.
+            // It is read apart, so a peer that does not send it does not hold up the others
+            tokio::spawn(async move {
+                let (mut r_half, w_half) = stream.into_split();
+                let handshake = handshakes.read(src_addr, read_handshake(&mut r_half)).await;
+                drop(slot);
+                if let Some(handshake) = handshake {
+                    accept_robot_connection(&addr, handshake, r_half, w_half, src_addr);
                 }
-            } else {
-                println!("[RCH] Received something unexpected: {:?}", buf[0] as char);
-            }
+            });
         }
     });
 }
 
+/// Hands the connection to the RCH as what its handshake says it is
+fn accept_robot_connection(
+    addr: &Addr<RobotConnectionHandler>,
+    handshake: RobotCommand,
+    r_half: OwnedReadHalf,
+    w_half: OwnedWriteHalf,
+    src_addr: SocketAddr,
+) {
+    match handshake {
+        RobotCommand::NewNextRobot { robot_id } => {
+            if let Err(e) = addr.try_send(AddNextRobot {
+                robot_id,
+                write_half: w_half,
+                read_half: r_half,
+            }) {
+                log::send_error("RCH", "AddNextRobot", &e.to_string());
+            }
+        }
+        RobotCommand::NewPreviousRobot { .. } => {
+            if let Err(e) = addr.try_send(AddPreviousRobot {
+                write_half: w_half,
+                read_half: r_half,
+                asked: false,
+            }) {
+                log::send_error("RCH", "AddPreviousRobot", &e.to_string());
+            }
+        }
+        RobotCommand::NewControlLane { robot_id } => {
+            if let Err(e) = addr.try_send(AddControlLane {
+                robot_id,
+                read_half: r_half,
+                write_half: w_half,
+            }) {
+                log::send_error("RCH", "AddControlLane", &e.to_string());
+            }
+        }
+        RobotCommand::Probe { .. } => {}
+        RobotCommand::NewLeaderConnection { leader_id, epoch } => {
+            log::info(
+                "RCH",
+                format!(
+                    "New message from RobotLeader: {} the ID is: {}",
+                    src_addr, leader_id
+                ),
+            );
+            if let Err(e) = addr.try_send(AddNewLeader {
+                write_half: w_half,
+                read_half: r_half,
+                leader_id,
+                epoch,
+            }) {
+                log::send_error("RCH", "AddNewLeader", &e.to_string());
+            }
+        }
+        other => {
+            log::error("RCH", format!("Received something unexpected: {:?}", other));
+        }
+    }
+}
+
 /// Starts the listener for the leader connection.
 pub fn start_leader_connection_listener(addr: Addr<RobotLeader>, id: usize) {
     tokio::spawn(async move {
@@ -431,42 +605,173 @@ pub fn start_leader_connection_listener(addr: Addr<RobotLeader>, id: usize) {
             Ok(l) => l,
             Err(e) => {
                 let line = format!("Error! Could not bind to port: {}", e);
-                println!("{}", line.red());
+                log::error("RCH", line.red());
                 return;
             }
         };
+        let handshakes = PendingHandshakes::new("RL");
 
         loop {
-            let (stream, _) = match listener.accept().await {
+            let (stream, src_addr) = match listener.accept().await {
                 Ok((s, a)) => (s, a),
                 Err(e) => {
                     let line = format!("Error! Could not accept connection: {}", e);
-                    println!("{}", line.red());
+                    log::error("RCH", line.red());
                     continue;
                 }
             };
+            let Some(slot) = handshakes.admit(src_addr) else {
+                continue;
+            };
+            let (addr, handshakes) = (addr.clone(), handshakes.clone());
+
+            tokio::spawn(async move {
+                let (mut r_half, w_half) = stream.into_split();
+                let peer = handshakes
+                    .read(src_addr, read_leader_handshake(&mut r_half))
+                    .await;
+                drop(slot);
+                match peer {
+                    Some(LeaderPeer::Dashboard) => answer_dashboard(addr, w_half).await,
+                    Some(LeaderPeer::Screen(screen_id)) => {
+                        if let Err(e) = addr.try_send(AddNewScreen {
+                            screen_id,
+                            write_half: w_half,
+                            read_half: r_half,
+                        }) {
+                            log::send_error("RL", "AddNewScreen", &e.to_string());
+                        }
+                    }
+                    Some(LeaderPeer::Robot(robot_id)) => {
+                        if let Err(e) = addr.try_send(AddNewRobot {
+                            robot_id,
+                            write_half: w_half,
+                            read_half: r_half,
+                            asked: false,
+                        }) {
+                            log::send_error("RL", "AddNewRobot", &e.to_string());
+                        }
+                    }
+                    None => {}
+                }
+            });
+        }
+    });
+}
 
-            let (mut r_half, w_half) = stream.into_split();
+/// Who is connecting to the leader, as the first bytes of the connection say
+enum LeaderPeer {
+    Dashboard,
+    Screen(usize),
+    Robot(usize),
+}
 
-            // println!(
-            //     "{}",
-            //     "Robot Lider: connection accepted".bright_cyan()
-            // );
+/// Reads the version of the protocol and the byte after it of a connection to the leader,
+/// and the id of the screen after them if it is a screen
+async fn read_leader_handshake(r_half: &mut OwnedReadHalf) -> std::io::Result<LeaderPeer> {
+    protocol::read_version(r_half).await?;
+    let mut byte = [0; 1];
+    r_half.read_exact(&mut byte).await?;
+    match byte[0] {
+        DASHBOARD_CONNECTION => Ok(LeaderPeer::Dashboard),
+        SCREEN_CONNECTION => {
+            r_half.read_exact(&mut byte).await?;
+            Ok(LeaderPeer::Screen(byte[0] as usize))
+        }
+        robot_id => Ok(LeaderPeer::Robot(robot_id as usize)),
+    }
+}
 
-            let mut buf_id = vec![0; 1];
-            if let Err(e) = r_half.read_exact(buf_id.as_mut_slice()).await {
-                let line = format!("Error! Could not read from stream: {}", e);
-                println!("{}", line.red());
-                continue;
-            }
-            if let Err(e) = addr.try_send(AddNewRobot {
-                robot_id: buf_id[0] as usize,
-                write_half: w_half,
-                read_half: r_half,
-                asked: false,
-            }) {
-                print_send_error("[RL]", "AddNewRobot", &e.to_string());
+/// Sends a snapshot of the state of the cluster to the dashboard and closes the connection
+async fn answer_dashboard(addr: Addr<RobotLeader>, mut w_half: OwnedWriteHalf) {
+    let state = match addr.send(GetClusterState()).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::send_error("RL", "GetClusterState", &e.to_string());
+            return;
+        }
+    };
+    match codec::encode(&state) {
+        Ok(msg) => {
+            if let Err(e) = w_half.write_all(&msg).await {
+                log::error(
+                    "RL",
+                    format!("Error trying to send the state to the dashboard: {}", e),
+                );
             }
         }
-    });
+        Err(e) => log::create_error("RL", "ClusterState", &e.to_string()),
+    }
+    let _ = w_half.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils::retry_backoff;
+
+    #[test]
+    fn test_reconnect_window_covers_every_retry() {
+        let max_attempts = config::get().retry.max_attempts;
+        let retries: Duration = (0..max_attempts).map(retry_backoff).sum();
+        assert!(reconnect_window() > retry_backoff(0) + retries);
+        assert_eq!(retry_backoff(2), Duration::from_millis(800));
+    }
+
+    #[tokio::test]
+    async fn test_a_peer_that_speaks_another_version_is_turned_down() {
+        use crate::protocol::PROTOCOL_VERSION;
+        use tokio::net::TcpStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let accept = || async { listener.accept().await.unwrap().0.into_split() };
+        let mut old_robot = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let old_version = PROTOCOL_VERSION - 1;
+        old_robot
+            .write_all(&old_version.to_be_bytes())
+            .await
+            .unwrap();
+        old_robot.write_all(&[1]).await.unwrap();
+        let (mut r_half, _w_half) = accept().await;
+        let refused = read_leader_handshake(&mut r_half).await.err().unwrap();
+        let expected = format!("it speaks version {} of the protocol", old_version);
+        assert!(refused.to_string().contains(&expected));
+
+        let mut old_next = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        old_next
+            .write_all(&old_version.to_be_bytes())
+            .await
+            .unwrap();
+        write_command(&mut old_next, RobotCommand::NewNextRobot { robot_id: 1 })
+            .await
+            .unwrap();
+        let (mut r_half, _w_half) = accept().await;
+        assert!(read_handshake(&mut r_half).await.is_err());
+
+        let mut robot = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        protocol::write_version(&mut robot).await.unwrap();
+        robot.write_all(&[1]).await.unwrap();
+        let (mut r_half, _w_half) = accept().await;
+        let peer = read_leader_handshake(&mut r_half).await.unwrap();
+        assert!(matches!(peer, LeaderPeer::Robot(1)));
+    }
+
+    #[test]
+    fn test_token_timeout_follows_the_ring() {
+        let scoops = &config::get().scoops;
+        let one_robot = Duration::from_millis((scoops.ms_per_gram_of(1) * KILO / 2) as u64);
+        assert_eq!(token_timeout(0, Some(&[1])), one_robot);
+        assert_eq!(token_timeout(0, Some(&[1, 0])), one_robot);
+        assert_eq!(
+            token_timeout(0, Some(&[])),
+            Duration::from_millis((scoops.ms_per_gram_of(0) * KILO / 2) as u64)
+        );
+        assert!(token_timeout(0, None) >= one_robot);
+    }
 }
@@ -1,61 +1,98 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use crate::common::log;
+use crate::common::mailbox;
+use crate::config;
 use actix::prelude::*;
 use colored::Colorize;
+use tokio::time::Duration;
 
-use crate::{common::order::Order, screen::payments_gateway::HandleBackUp};
+use crate::{
+    common::order::Order,
+    protocol::screen_messages::BackupView,
+    screen::payments_gateway::{HandleBackUp, PaymentsGateway, RelayBackup},
+    screen::screen_connection_sender::SendMyBackup,
+};
 
-/// This actor is responsible for handling the backup of the screen.
-/// It stores the orders that are pending to be processed, the orders that are being processed and the orders that are pending to be prepared.
-/// It also stores the id of the backup.
-/// It can save a backup, send the backup to the payments gateway and set the payments gateway.
-/// It is used by the screen connection listener.
-pub struct BackUpHandler {
-    screen_backup_id: Option<usize>,
+/// The last backup of a screen this screen keeps, with the version the screen gave it
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ScreenBackup {
+    version: u64,
     orders_to_process: Vec<Order>,
     orders_processing: HashMap<String, Order>,
     orders_pending_to_prepare: Vec<(String, Order)>,
-    payments_gateway: Option<Recipient<HandleBackUp>>,
 }
 
-impl Actor for BackUpHandler {
-    type Context = Context<Self>;
+impl ScreenBackup {
+    fn is_empty(&self) -> bool {
+        self.orders_to_process.is_empty()
+            && self.orders_processing.is_empty()
+            && self.orders_pending_to_prepare.is_empty()
+    }
 }
 
-impl Default for BackUpHandler {
-    fn default() -> Self {
-        Self::new()
-    }
+/// This actor is responsible for handling the backups this screen keeps of the previous screens of the ring.
+/// It keeps the last backup of each screen, the orders that are pending to be processed, the orders that are being processed
+/// and the orders that are pending to be prepared, and replaces it only with a newer version.
+/// A backup that has to reach more screens is relayed to the next one through the payments gateway.
+/// It is used by the screen connection listener.
+pub struct BackUpHandler {
+    id: usize,
+    backups: BTreeMap<usize, ScreenBackup>,
+    payments_gateway: Option<Addr<PaymentsGateway>>,
+}
+
+impl Actor for BackUpHandler {
+    type Context = Context<Self>;
 }
 
 impl BackUpHandler {
-    pub fn new() -> BackUpHandler {
+    pub fn new(id: usize) -> BackUpHandler {
         BackUpHandler {
-            screen_backup_id: None,
-            orders_to_process: Vec::new(),
-            orders_processing: HashMap::new(),
-            orders_pending_to_prepare: Vec::new(),
+            id,
+            backups: BTreeMap::new(),
             payments_gateway: None,
         }
     }
 
-    pub fn are_empty(&self) -> bool {
-        self.orders_to_process.is_empty()
-            && self.orders_processing.is_empty()
-            && self.orders_pending_to_prepare.is_empty()
+    /// Returns the message that gives the backup of the screen to the payments gateway
+    fn hand_over(origin: usize, backup: &ScreenBackup, unless_alive: bool) -> HandleBackUp {
+        HandleBackUp::new(
+            backup.orders_to_process.clone(),
+            backup.orders_processing.clone(),
+            backup.orders_pending_to_prepare.clone(),
+            Some(origin),
+            backup.version,
+            unless_alive,
+        )
     }
 
-    pub fn same_backup(&self, msg: SaveBackup) -> bool {
-        self.orders_pending_to_prepare == msg.orders_pending_to_prepare
-            && self.orders_processing == msg.orders_processing
-            && self.orders_to_process == msg.orders_to_process
+    /// Gives the payments gateway the backups of the screens it does not know are alive.
+    /// A backup the gateway takes is forgotten, unless a newer one arrived meanwhile
+    fn hand_over_dead(&mut self, ctx: &mut Context<Self>) {
+        let Some(payments_gateway) = self.payments_gateway.clone() else {
+            return;
+        };
+        for (origin, backup) in self.backups.iter().filter(|(_, b)| !b.is_empty()) {
+            let (origin, version) = (*origin, backup.version);
+            payments_gateway
+                .send(Self::hand_over(origin, backup, true))
+                .into_actor(self)
+                .map(move |taken, act, _| {
+                    let same = act.backups.get(&origin).map(|b| b.version) == Some(version);
+                    if taken.unwrap_or(false) && same {
+                        act.backups.remove(&origin);
+                    }
+                })
+                .spawn(ctx);
+        }
     }
 }
 
-/// SaveBackup is a message that tells the BackUpHandler actor to save a backup.
+/// SaveBackup is a message that tells the BackUpHandler actor to save the backup of the screen `id_backup`.
 /// It contains the orders that are pending to be processed, the orders that are being processed and the orders that are pending to be prepared.
-/// It also contains the id of the backup.
-/// The actor will store the orders and the id of the backup.
+/// The backup is kept only if its version is newer than the one the actor has of that screen,
+/// and then it is relayed to the next screen if `hops_left` is more than 0.
 #[derive(Message, Clone)]
 #[rtype(result = "()")]
 pub struct SaveBackup {
@@ -63,6 +100,8 @@ pub struct SaveBackup {
     orders_processing: HashMap<String, Order>,
     orders_pending_to_prepare: Vec<(String, Order)>,
     id_backup: usize,
+    version: u64,
+    hops_left: usize,
 }
 
 impl SaveBackup {
@@ -71,12 +110,16 @@ impl SaveBackup {
         orders_processing: HashMap<String, Order>,
         orders_pending_to_prepare: Vec<(String, Order)>,
         id_backup: usize,
+        version: u64,
+        hops_left: usize,
     ) -> SaveBackup {
         SaveBackup {
             orders_to_process,
             orders_processing,
             orders_pending_to_prepare,
             id_backup,
+            version,
+            hops_left,
         }
     }
 }
@@ -85,53 +128,87 @@ impl Handler<SaveBackup> for BackUpHandler {
     type Result = ();
 
     fn handle(&mut self, msg: SaveBackup, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.same_backup(msg.clone()) {
+        if msg.id_backup == self.id {
             return;
         }
-        println!("[BCKUP]{}{}", "Backup saved from ".yellow(), msg.id_backup);
-        self.orders_to_process = msg.orders_to_process;
-        self.orders_processing = msg.orders_processing;
-        self.orders_pending_to_prepare = msg.orders_pending_to_prepare;
-        self.screen_backup_id = Some(msg.id_backup);
+        if let Some(backup) = self.backups.get(&msg.id_backup) {
+            if backup.version >= msg.version {
+                return;
+            }
+        }
+        let backup = ScreenBackup {
+            version: msg.version,
+            orders_to_process: msg.orders_to_process,
+            orders_processing: msg.orders_processing,
+            orders_pending_to_prepare: msg.orders_pending_to_prepare,
+        };
+        let changed = !backup.is_empty()
+            || self
+                .backups
+                .get(&msg.id_backup)
+                .is_some_and(|old| !old.is_empty());
+        if changed {
+            log::info(
+                "BCKUP",
+                format!(
+                    "Backup saved from {} (version {})",
+                    msg.id_backup, msg.version
+                )
+                .yellow(),
+            );
+        }
+        if msg.hops_left > 0 {
+            if let Some(payments_gateway) = &self.payments_gateway {
+                let relay = SendMyBackup::new(&BackupView {
+                    orders_to_process: backup.orders_to_process.iter().collect(),
+                    orders_processing: &backup.orders_processing,
+                    orders_pending_to_send: &backup.orders_pending_to_prepare,
+                    id_backup: msg.id_backup,
+                    version: msg.version,
+                    hops_left: msg.hops_left - 1,
+                    sender: self.id,
+                });
+                match relay {
+                    Ok(relay) => {
+                        mailbox::send_or_shed(payments_gateway, RelayBackup(relay), "RelayBackup")
+                    }
+                    Err(e) => log::error("BCKUP", format!("Error encoding the backup: {}", e)),
+                }
+            }
+        }
+        self.backups.insert(msg.id_backup, backup);
     }
 }
 
-/// SendBackupToGateway is a message that tells the BackUpHandler actor to send the backup to the payments gateway.
-/// This will happen when the previous screen is disconnected.
-/// The actor will send the backup to the payments gateway and clear the orders that are being processed and the orders that are pending to be processed.
+/// SendBackupToGateway is a message that tells the BackUpHandler actor to send backups to the payments gateway.
+/// This will happen when the previous screen is disconnected, `peer` is that screen if it is known.
+/// The actor sends the backup of the previous screen at once. The screens before it may have died with it,
+/// so once the membership had time to notice, the backups of the screens that are not alive are sent too.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SendBackupToGateway();
-
-impl Default for SendBackupToGateway {
-    fn default() -> Self {
-        Self::new()
-    }
+pub struct SendBackupToGateway {
+    peer: Option<usize>,
 }
 
 impl SendBackupToGateway {
-    pub fn new() -> SendBackupToGateway {
-        SendBackupToGateway {}
+    pub fn new(peer: Option<usize>) -> SendBackupToGateway {
+        SendBackupToGateway { peer }
     }
 }
 
 impl Handler<SendBackupToGateway> for BackUpHandler {
     type Result = ();
 
-    fn handle(&mut self, _msg: SendBackupToGateway, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.are_empty() {
-            return;
-        }
-        if let Some(payments_gateway) = &self.payments_gateway {
-            payments_gateway.do_send(HandleBackUp::new(
-                self.orders_to_process.clone(),
-                self.orders_processing.clone(),
-                self.orders_pending_to_prepare.clone(),
-                self.screen_backup_id,
-            ));
+    fn handle(&mut self, msg: SendBackupToGateway, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(peer) = msg.peer {
+            if let Some(backup) = self.backups.remove(&peer).filter(|b| !b.is_empty()) {
+                if let Some(payments_gateway) = &self.payments_gateway {
+                    payments_gateway.do_send(Self::hand_over(peer, &backup, false));
+                }
+            }
         }
-        self.orders_processing.clear();
-        self.orders_to_process.clear();
+        let wait = Duration::from_millis(2 * config::get().gossip.member_timeout_ms);
+        ctx.run_later(wait, |act, ctx| act.hand_over_dead(ctx));
     }
 }
 
@@ -139,11 +216,11 @@ impl Handler<SendBackupToGateway> for BackUpHandler {
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SetPaymentsGateway {
-    payments_gateway: Recipient<HandleBackUp>,
+    payments_gateway: Addr<PaymentsGateway>,
 }
 
 impl SetPaymentsGateway {
-    pub fn new(payments_gateway: Recipient<HandleBackUp>) -> SetPaymentsGateway {
+    pub fn new(payments_gateway: Addr<PaymentsGateway>) -> SetPaymentsGateway {
         SetPaymentsGateway { payments_gateway }
     }
 }
@@ -164,7 +241,7 @@ mod tests {
     use super::*;
     #[actix::test]
     async fn test_save_backup() {
-        let mut backup_handler = BackUpHandler::new();
+        let mut backup_handler = BackUpHandler::new(0);
         let orders_to_process = vec![Order::new_cucurucho(FlavorID::Chocolate)];
         let mut orders_processing = HashMap::new();
         orders_processing.insert("123".to_string(), Order::new_cucurucho(FlavorID::Vanilla));
@@ -173,15 +250,18 @@ mod tests {
             orders_processing.clone(),
             Vec::new(),
             123,
+            1,
+            0,
         );
         backup_handler.handle(save_backup, &mut Context::new());
-        assert_eq!(backup_handler.orders_to_process, orders_to_process);
-        assert_eq!(backup_handler.orders_processing, orders_processing);
+        let backup = &backup_handler.backups[&123];
+        assert_eq!(backup.orders_to_process, orders_to_process);
+        assert_eq!(backup.orders_processing, orders_processing);
     }
 
     #[actix::test]
     async fn test_overwrite_backup() {
-        let mut backup_handler = BackUpHandler::new();
+        let mut backup_handler = BackUpHandler::new(0);
         let orders_to_process = vec![Order::new_cucurucho(FlavorID::Chocolate)];
         let mut orders_processing = HashMap::new();
         orders_processing.insert("123".to_string(), Order::new_cucurucho(FlavorID::Vanilla));
@@ -190,6 +270,8 @@ mod tests {
             orders_processing.clone(),
             Vec::new(),
             123,
+            1,
+            0,
         );
         backup_handler.handle(save_backup, &mut Context::new());
         let orders_to_process = vec![Order::new_cucurucho(FlavorID::Strawberry)];
@@ -200,9 +282,43 @@ mod tests {
             orders_processing.clone(),
             Vec::new(),
             123,
+            2,
+            0,
         );
         backup_handler.handle(save_backup, &mut Context::new());
-        assert_eq!(backup_handler.orders_to_process, orders_to_process);
-        assert_eq!(backup_handler.orders_processing, orders_processing);
+        let backup = &backup_handler.backups[&123];
+        assert_eq!(backup.orders_to_process, orders_to_process);
+        assert_eq!(backup.orders_processing, orders_processing);
+    }
+
+    #[actix::test]
+    async fn test_an_older_backup_does_not_replace_a_newer_one() {
+        let mut backup_handler = BackUpHandler::new(0);
+        let newer = vec![Order::new_cucurucho(FlavorID::Chocolate)];
+        let older = vec![Order::new_cucurucho(FlavorID::Vanilla)];
+        for (orders, version) in [(newer.clone(), 5), (older, 4), (Vec::new(), 5)] {
+            backup_handler.handle(
+                SaveBackup::new(orders, HashMap::new(), Vec::new(), 1, version, 0),
+                &mut Context::new(),
+            );
+        }
+        assert_eq!(backup_handler.backups[&1].orders_to_process, newer);
+        assert_eq!(backup_handler.backups[&1].version, 5);
+    }
+
+    #[actix::test]
+    async fn test_backups_are_kept_per_screen() {
+        let mut backup_handler = BackUpHandler::new(0);
+        let first = vec![Order::new_cucurucho(FlavorID::Chocolate)];
+        let second = vec![Order::new_cucurucho(FlavorID::Vanilla)];
+        for (orders, origin) in [(first.clone(), 1), (second.clone(), 2), (first.clone(), 0)] {
+            backup_handler.handle(
+                SaveBackup::new(orders, HashMap::new(), Vec::new(), origin, 1, 1),
+                &mut Context::new(),
+            );
+        }
+        assert_eq!(backup_handler.backups.len(), 2);
+        assert_eq!(backup_handler.backups[&1].orders_to_process, first);
+        assert_eq!(backup_handler.backups[&2].orders_to_process, second);
     }
 }
@@ -1,5 +1,14 @@
+//! Setup of the actors and connections of a screen.
+//! Each screen listens for its previous screen, its next screen and the robot leader, connects to the first available
+//! screen that follows it in the ring and tells the ones before it that it is ready, so the alive screens form a single ring.
+
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::common::handshake::PendingHandshakes;
+use crate::common::health;
+use crate::common::log;
+use crate::common::order_source::OrderChannel;
 use actix::{Actor, Addr, StreamHandler};
 use colored::Colorize;
 use tokio::{
@@ -7,18 +16,33 @@ use tokio::{
     net::{TcpListener, TcpStream},
     sync::Mutex,
 };
-use tokio_stream::wrappers::LinesStream;
 
 use crate::{
-    common::utils::{id_to_screen_addr, ROBOT, SCREEN_NEXT, SCREEN_PREVIOUS},
-    config::MAX_NUMBER_OF_SCREENS,
+    common::codec,
+    common::error::FreddoError,
+    common::utils::{
+        connect, id_to_leader_addr, id_to_screen_addr, ROBOT, SCREEN_NEXT, SCREEN_PREVIOUS,
+    },
+    config,
+    protocol::{self, compression::Compression, screen_messages::ScreenMessage},
+    robot::utils::SCREEN_CONNECTION,
     screen::{
-        order_reader::ReadOrders, robot_connection_handler::RobotConnectionHandler,
+        menu::Menu,
+        order_api,
+        order_reader::{ReadOrders, WatchOrders},
+        payments_gateway::{
+            GetOrderHistory, GetPickups, InFailoverDrill, PickUpOrder, ReceiveOrders,
+            StartFailoverDrill,
+        },
+        robot_connection_handler::RobotConnectionHandler,
+        screen_command::{ScreenCommand, HELP},
         screen_connection_listener::ScreenConnectionListener,
-        screen_connection_sender::ScreenConnectionSender, screen_error::ScreenError,
+        screen_connection_sender::ScreenConnectionSender,
     },
 };
 
+#[cfg(feature = "tui")]
+use super::kiosk;
 use super::{
     backup_handler::{self, BackUpHandler, SetPaymentsGateway},
     order_reader::OrderReader,
@@ -26,36 +50,81 @@ use super::{
 };
 
 /// Starts the actors and connections for the screens.
-pub async fn start_actors_and_connections(num_screen: usize, order_file: String) {
-    let backup_handler = backup_handler::BackUpHandler::new().start();
-    let payments_gateway = PaymentsGateway::new(num_screen).start();
-    let _ = backup_handler
-        .send(SetPaymentsGateway::new(
-            payments_gateway.clone().recipient(),
-        ))
-        .await;
+/// In watch mode the order file is followed from the start, without waiting for the operator.
+/// If the API is enabled, orders are also taken over HTTP. Orders off the menu are turned away.
+/// In kiosk mode the operator uses the kiosk instead of typing commands.
+pub async fn start_actors_and_connections(
+    num_screen: usize,
+    order_file: String,
+    watch: bool,
+    menu: Menu,
+    kiosk: bool,
+) {
+    let (payments_gateway, backup_handler) = start_actors(num_screen, menu).await;
     let order_reader = OrderReader::new(order_file, payments_gateway.clone().recipient()).start();
+    if watch {
+        order_reader.do_send(WatchOrders());
+    }
     setup_connections(
         num_screen,
         payments_gateway,
         order_reader,
         backup_handler.clone(),
+        kiosk,
     )
     .await;
 }
 
+/// Starts a screen without an order file or an operator, the orders are given to the PaymentsGateway that is returned.
+/// The connections with the other screens and the robots are set up in the background
+pub async fn start_headless(num_screen: usize) -> Addr<PaymentsGateway> {
+    let (payments_gateway, backup_handler) = start_actors(num_screen, Menu::default()).await;
+    actix::spawn(connect_following_and_notify_previous(
+        num_screen,
+        payments_gateway.clone(),
+    ));
+    let server = start_server_and_handler(num_screen, backup_handler, payments_gateway.clone());
+    actix::spawn(async move {
+        if let Err(e) = server.await {
+            log::error("SCREEN", e.to_string().red());
+        }
+    });
+    payments_gateway
+}
+
+/// Starts the actors shared by every kind of screen, and the API if it is enabled
+async fn start_actors(
+    num_screen: usize,
+    menu: Menu,
+) -> (Addr<PaymentsGateway>, Addr<BackUpHandler>) {
+    let backup_handler = backup_handler::BackUpHandler::new(num_screen).start();
+    let payments_gateway = PaymentsGateway::new(num_screen).with_menu(menu).start();
+    let api_config = &config::get().api;
+    if api_config.enabled {
+        actix::spawn(order_api::serve(
+            api_config.screen_addr(num_screen),
+            payments_gateway.clone(),
+        ));
+    }
+    let _ = backup_handler
+        .send(SetPaymentsGateway::new(payments_gateway.clone()))
+        .await;
+    (payments_gateway, backup_handler)
+}
+
 /// Sets up the connections between the screens and the robots.
 async fn setup_connections(
     num_screen: usize,
     payments_gateway: Addr<PaymentsGateway>,
     order_reader: Addr<OrderReader>,
     backup_handler: Addr<BackUpHandler>,
+    kiosk: bool,
 ) {
     let screen_communication_future =
         connect_following_and_notify_previous(num_screen, payments_gateway.clone());
     let screen_listener_future =
-        start_server_and_handler(num_screen, backup_handler, payments_gateway);
-    let wait_input = wait_input(order_reader);
+        start_server_and_handler(num_screen, backup_handler, payments_gateway.clone());
+    let wait_input = take_input(order_reader, payments_gateway, kiosk);
     let _ = tokio::join!(
         screen_communication_future,
         screen_listener_future,
@@ -63,26 +132,112 @@ async fn setup_connections(
     );
 }
 
-/// Waits for the user to press 'p' to start processing the orders.
-/// Once the user presses 'p', the orders are read from the file and sent to the order reader.
-async fn wait_input(order_reader: Addr<OrderReader>) {
+/// Takes the orders and commands of the operator, from the kiosk in kiosk mode or typed on the terminal otherwise
+async fn take_input(
+    order_reader: Addr<OrderReader>,
+    payments_gateway: Addr<PaymentsGateway>,
+    kiosk: bool,
+) {
+    if kiosk {
+        #[cfg(feature = "tui")]
+        return kiosk::run(order_reader, payments_gateway).await;
+    }
+    wait_input(order_reader, payments_gateway).await
+}
+
+/// Reads the commands typed by the operator.
+/// With 'p' the orders are read from the file and sent to the order reader, with 'w' the file is watched for new orders,
+/// and with `order <type> <flavors...>` a new order is added to the payments gateway queue.
+/// With `drill [secs]` the screen plays dead for a while to rehearse a failover, and the report is shown when it rejoins.
+async fn wait_input(order_reader: Addr<OrderReader>, payments_gateway: Addr<PaymentsGateway>) {
     let stdin = tokio::io::stdin();
     let mut reader = BufReader::new(stdin);
-    println!("{}", "Press 'p' to start processing orders...".purple());
+    log::info("SCREEN", "Press 'p' to start processing orders...".purple());
 
     let _ = tokio::spawn(async move {
         let mut input = String::new();
+        let mut file_processed = false;
+        let mut watching = false;
         loop {
             input.clear();
-            reader
+            let read = reader
                 .read_line(&mut input)
                 .await
                 .expect("Failed to read line");
-            if input.trim() == "p" {
-                let _ = order_reader.send(ReadOrders()).await;
+            if read == 0 {
                 break;
             }
-            println!("{}", "Press 'p' to start processing orders".purple());
+            match input.parse::<ScreenCommand>() {
+                Ok(ScreenCommand::ProcessFile) if file_processed || watching => {
+                    log::warn("SCREEN", "The orders of the file were already processed");
+                }
+                Ok(ScreenCommand::Watch) => {
+                    order_reader.do_send(WatchOrders());
+                    watching = true;
+                }
+                Ok(ScreenCommand::ProcessFile) => {
+                    let _ = order_reader.send(ReadOrders()).await;
+                    file_processed = true;
+                }
+                Ok(ScreenCommand::NewOrder(order)) => {
+                    let line = format!("New order from the terminal: {:?}", order);
+                    log::info("SCREEN", line.purple());
+                    if let Err(e) = payments_gateway.try_send(ReceiveOrders::with_channel(
+                        vec![order],
+                        OrderChannel::Terminal,
+                    )) {
+                        log::send_error("SCREEN", "ReceiveOrders", &e.to_string());
+                    }
+                }
+                Ok(ScreenCommand::History(limit)) => {
+                    match payments_gateway.send(GetOrderHistory::new(limit)).await {
+                        Ok(records) if records.is_empty() => {
+                            log::info("SCREEN", "No orders in the history".purple())
+                        }
+                        Ok(records) => {
+                            for record in records {
+                                log::info("SCREEN", record.to_string().purple());
+                            }
+                        }
+                        Err(e) => log::send_error("SCREEN", "GetOrderHistory", &e.to_string()),
+                    }
+                }
+                Ok(ScreenCommand::Drill(secs)) => {
+                    let drill = StartFailoverDrill::new(Duration::from_secs(secs));
+                    match payments_gateway.send(drill).await {
+                        Ok(Ok(report)) => log::info("SCREEN", report.to_string().purple()),
+                        Ok(Err(e)) => log::warn("SCREEN", e.red()),
+                        Err(e) => log::send_error("SCREEN", "StartFailoverDrill", &e.to_string()),
+                    }
+                }
+                Ok(ScreenCommand::Pickups) => match payments_gateway.send(GetPickups()).await {
+                    Ok(pickups) if pickups.is_empty() => {
+                        log::info("SCREEN", "No orders waiting to be picked up".purple())
+                    }
+                    Ok(pickups) => {
+                        for pickup in pickups {
+                            let line = format!(
+                                "{}: order {} ready for {} ms",
+                                pickup.code, pickup.order_id, pickup.waiting_ms
+                            );
+                            log::info("SCREEN", line.purple());
+                        }
+                    }
+                    Err(e) => log::send_error("SCREEN", "GetPickups", &e.to_string()),
+                },
+                Ok(ScreenCommand::PickUp(code)) => {
+                    match payments_gateway.send(PickUpOrder::new(code)).await {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => log::warn("SCREEN", e.red()),
+                        Err(e) => log::send_error("SCREEN", "PickUpOrder", &e.to_string()),
+                    }
+                }
+                Ok(ScreenCommand::Help) => log::info("SCREEN", HELP),
+                Err(e) => {
+                    log::warn("SCREEN", e.red());
+                    log::info("SCREEN", "Type 'help' to see the commands".purple());
+                }
+            }
         }
     })
     .await;
@@ -92,31 +247,65 @@ async fn wait_input(order_reader: Addr<OrderReader>) {
 /// The server listens for connections from the previous screen, the next screen, and the robots.
 /// The handler processes the connections and creates the actors for the connections.
 /// The handler also sends the messages to the actors to handle the connections.
+/// While the screen plays dead in a failover drill, the connections are closed as soon as they are accepted.
 pub async fn start_server_and_handler(
     id: usize,
     backup_handler: Addr<BackUpHandler>,
     payments_gateway: Addr<PaymentsGateway>,
-) -> Result<(), ScreenError> {
+) -> Result<(), FreddoError> {
     let port = id_to_screen_addr(id);
-    let listener = TcpListener::bind(port.clone())
-        .await
-        .map_err(|_| ScreenError::TcpListenerError(id))?;
+    let listener = TcpListener::bind(port.clone()).await.map_err(|e| {
+        FreddoError::Connection(format!(
+            "Screen {}: Couldn't initialize listener: {}",
+            id, e
+        ))
+    })?;
+    health::get().listening();
+    let handshakes = PendingHandshakes::new("SCREEN");
     loop {
         match listener.accept().await {
-            Ok((stream, _)) => {
-                let (mut read, write_half) = split(stream);
-                let mut buf = [0; 1];
-                read.read_exact(&mut buf).await.expect("Failed to read");
-                if buf[0] as char == SCREEN_PREVIOUS {
-                    handle_previous_screen(read, &backup_handler, &payments_gateway);
-                } else if buf[0] as char == ROBOT {
-                    handle_robot_connection(read, write_half, &payments_gateway);
-                } else if buf[0] as char == SCREEN_NEXT {
-                    handle_next_screen(id, &payments_gateway).await;
+            Ok((stream, peer)) => {
+                if payments_gateway
+                    .send(InFailoverDrill())
+                    .await
+                    .unwrap_or(false)
+                {
+                    continue;
                 }
+                let Some(slot) = handshakes.admit(peer) else {
+                    continue;
+                };
+                let handshakes = handshakes.clone();
+                let (backup_handler, payments_gateway) =
+                    (backup_handler.clone(), payments_gateway.clone());
+                // Who is connecting is read apart, so a peer that does not say it does not hold up the others
+                actix::spawn(async move {
+                    let (mut read, write_half) = split(stream);
+                    let mut buf = [0; 1];
+                    let handshake = async {
+                        protocol::read_version(&mut read).await?;
+                        read.read_exact(&mut buf).await
+                    };
+                    let who = handshakes.read(peer, handshake).await;
+                    drop(slot);
+                    if who.is_none() {
+                        return;
+                    }
+                    if buf[0] as char == SCREEN_PREVIOUS {
+                        actix::spawn(send_accepted_compressions(write_half));
+                        handle_previous_screen(read, &backup_handler, &payments_gateway);
+                    } else if buf[0] as char == ROBOT {
+                        handle_robot_connection(read, write_half, &payments_gateway);
+                    } else if buf[0] as char == SCREEN_NEXT {
+                        handle_next_screen(id, &payments_gateway).await;
+                    }
+                });
             }
-            Err(_) => {
-                return Err(ScreenError::TcpListenerError(id));
+            Err(e) => {
+                return Err(FreddoError::Connection(format!(
+                    "Screen {}: Couldn't accept connections: {}",
+                    id, e
+                )));
             }
         }
     }
@@ -125,8 +314,7 @@ pub async fn start_server_and_handler(
 /// Handles the connection from the next screen.
 /// The connection is established with the next screen and the actor is created to handle the connection.
 async fn handle_next_screen(id: usize, payments_gateway: &Addr<PaymentsGateway>) {
-    let id_following = (id + 1) % MAX_NUMBER_OF_SCREENS;
-    let _ = connect_to_following_screen(id_following, payments_gateway.clone(), id).await;
+    let _ = connect_to_following_screen(id, payments_gateway.clone()).await;
 }
 
 /// Handles the connection from the robot.
@@ -137,12 +325,27 @@ fn handle_robot_connection(
     payments_gateway: &Addr<PaymentsGateway>,
 ) {
     let _ = RobotConnectionHandler::create(|ctx| {
-        RobotConnectionHandler::add_stream(LinesStream::new(BufReader::new(read).lines()), ctx);
+        RobotConnectionHandler::add_stream(codec::frames(read), ctx);
         let write = Arc::new(Mutex::new(write_half));
         RobotConnectionHandler::new(write, payments_gateway.clone())
     });
 }
 
+/// Connects to the robot leader, instead of waiting for it to connect to the screen.
+/// The leader takes the connection as if it had opened it.
+pub async fn connect_to_leader(
+    leader_id: usize,
+    my_id: usize,
+    payments_gateway: Addr<PaymentsGateway>,
+) -> Result<(), FreddoError> {
+    let mut stream = connect(&id_to_leader_addr(leader_id)).await?;
+    protocol::write_version(&mut stream).await?;
+    stream.write_all(&[SCREEN_CONNECTION, my_id as u8]).await?;
+    let (read, write_half) = split(stream);
+    handle_robot_connection(read, write_half, &payments_gateway);
+    Ok(())
+}
+
 /// Handles the connection from the previous screen.
 /// The connection is established with the previous screen and the actor is created to handle the connection.
 fn handle_previous_screen(
@@ -151,33 +354,65 @@ fn handle_previous_screen(
     payments_gateway: &Addr<PaymentsGateway>,
 ) {
     let _ = ScreenConnectionListener::create(|ctx| {
-        ScreenConnectionListener::add_stream(LinesStream::new(BufReader::new(read).lines()), ctx);
+        ScreenConnectionListener::add_stream(codec::frames(read), ctx);
         ScreenConnectionListener::new(backup_handler.clone(), payments_gateway.clone())
     });
 }
 
-/// Connects to the following screen.
-/// The function tries to connect to the following screen.
-/// If the connection is successful, the actor is created to handle the connection.
-/// If the connection is not successful, the function tries to connect to the next screen.
-/// The function returns the id of the screen that was connected to.
+/// Tells the previous screen which compressions of the backups this screen reads, it is the only frame sent to it
+async fn send_accepted_compressions(mut write_half: tokio::io::WriteHalf<TcpStream>) {
+    let msg = ScreenMessage::AcceptsCompression {
+        compressions: Compression::SUPPORTED.to_vec(),
+    };
+    match msg.to_bytes() {
+        Ok(bytes) => {
+            if let Err(e) = write_half.write_all(&bytes).await {
+                let line = format!(
+                    "Could not send the compressions to the previous screen: {}",
+                    e
+                );
+                log::warn("SCREEN", line.red());
+            }
+        }
+        Err(e) => log::create_error("SCREEN", "AcceptsCompression", &e.to_string()),
+    }
+}
+
+/// Returns the ids of the other screens in the order they follow the given one in the ring
+pub fn following_screens(my_id: usize, screens: usize) -> impl Iterator<Item = usize> {
+    (1..screens).map(move |i| (my_id + i) % screens)
+}
+
+/// Returns the ids of the other screens in the order they precede the given one in the ring
+pub fn previous_screens(my_id: usize, screens: usize) -> impl Iterator<Item = usize> {
+    (1..screens).map(move |i| (my_id + screens - i) % screens)
+}
+
+/// Connects to the first screen that follows this one in the ring and is available, and creates the actor of the connection.
+/// Returns the id of the screen that was connected to, or the id of this screen if no other one is available.
 pub async fn connect_to_following_screen(
-    id_following: usize,
+    my_id: usize,
     payments_gateway: Addr<PaymentsGateway>,
+) -> usize {
+    let screens = config::number_of_screens();
+    connect_to_first_following(my_id, screens, id_to_screen_addr, payments_gateway).await
+}
+
+/// Connects to the first of the `screens` that follows this one and is available, with the address of each screen
+async fn connect_to_first_following(
     my_id: usize,
+    screens: usize,
+    addr_of: impl Fn(usize) -> String,
+    payments_gateway: Addr<PaymentsGateway>,
 ) -> usize {
-    let mut next = id_following;
-    for i in 0..MAX_NUMBER_OF_SCREENS {
-        next = (next + i) % MAX_NUMBER_OF_SCREENS;
-        if next == my_id {
-            return next;
-        }
-        let port = id_to_screen_addr(next);
-        if let Some(value) = try_connection(port, &payments_gateway, my_id, next).await {
-            return value;
+    for next in following_screens(my_id, screens) {
+        let port = addr_of(next);
+        match try_connection(port, &payments_gateway, my_id).await {
+            Ok(()) => return next,
+            Err(e) => log::debug("SCREEN", format!("Screen {} is not available: {}", next, e)),
         }
     }
-    next
+    my_id
 }
 
 /// Tries to connect to the following screen.
@@ -185,60 +420,124 @@ async fn try_connection(
     port: String,
     payments_gateway: &Addr<PaymentsGateway>,
     my_id: usize,
-    next: usize,
-) -> Option<usize> {
-    if let Ok(mut stream) = TcpStream::connect(port.clone()).await {
-        stream
-            .write_all(&[SCREEN_PREVIOUS as u8])
-            .await
-            .expect("Failed to write");
-        let _ = ScreenConnectionSender::create(|ctx| {
-            let (read_half, write_half) = split(stream);
-            ScreenConnectionSender::add_stream(
-                LinesStream::new(BufReader::new(read_half).lines()),
-                ctx,
-            );
-            let write = Arc::new(Mutex::new(write_half));
-            ScreenConnectionSender::new(write, payments_gateway.clone(), my_id)
-        });
-        Some(next)
-    } else {
-        None
-    }
+) -> Result<(), FreddoError> {
+    let mut stream = connect(&port).await?;
+    protocol::write_version(&mut stream).await?;
+    stream.write_all(&[SCREEN_PREVIOUS as u8]).await?;
+    let _ = ScreenConnectionSender::create(|ctx| {
+        let (read_half, write_half) = split(stream);
+        ScreenConnectionSender::add_stream(codec::frames(read_half), ctx);
+        let write = Arc::new(Mutex::new(write_half));
+        ScreenConnectionSender::new(write, payments_gateway.clone(), my_id)
+    });
+    Ok(())
 }
 
 /// Notifies the previous screens that the screen is connected and it is ready to receive connections.
-async fn notify_previous_screens(my_id: usize) {
-    let mut previous = my_id;
-    for _ in 0..MAX_NUMBER_OF_SCREENS {
-        if previous == 0 {
-            previous = MAX_NUMBER_OF_SCREENS - 1;
-        } else {
-            previous -= 1;
-        }
-        if previous == my_id {
-            return;
-        }
-        let port = id_to_screen_addr(previous);
-        if let Ok(mut stream) = TcpStream::connect(port.clone()).await {
-            stream
-                .write_all(&[SCREEN_NEXT as u8])
-                .await
-                .expect("Failed to write");
+/// Every one is tried, the screens that are down are skipped.
+async fn notify_previous_screens(my_id: usize, screens: usize, addr_of: impl Fn(usize) -> String) {
+    for previous in previous_screens(my_id, screens) {
+        if let Err(e) = notify_screen(&addr_of(previous)).await {
+            let line = format!("Could not notify Screen {}: {}", previous, e);
+            log::debug("SCREEN", line);
         }
     }
 }
 
+/// Tells a previous screen that this screen is ready, so it connects to it.
+async fn notify_screen(addr: &str) -> Result<(), FreddoError> {
+    let mut stream = connect(addr).await?;
+    protocol::write_version(&mut stream).await?;
+    stream.write_all(&[SCREEN_NEXT as u8]).await?;
+    Ok(())
+}
+
 /// Connects to the following screen and notifies the previous screens.
 /// The function connects to the following screen and notifies the previous screens that the screen is connected.
-async fn connect_following_and_notify_previous(
+pub async fn connect_following_and_notify_previous(
     my_id: usize,
     payments_gateway: Addr<PaymentsGateway>,
 ) {
-    let id_following = (my_id + 1) % MAX_NUMBER_OF_SCREENS;
-    let id_connected = connect_to_following_screen(id_following, payments_gateway, my_id).await;
+    let id_connected = connect_to_following_screen(my_id, payments_gateway).await;
     if id_connected == my_id {
         return;
     }
-    let _ = notify_previous_screens(my_id).await;
+    notify_previous_screens(my_id, config::number_of_screens(), id_to_screen_addr).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::screen::payments_gateway::PaymentsGateway;
+    use std::collections::HashMap;
+    use tokio::io::AsyncReadExt;
+
+    /// Listens as the alive screens and gives the address of every screen, the ones that are down refuse connections
+    async fn screens_at(
+        alive: &[usize],
+        screens: usize,
+    ) -> (HashMap<usize, TcpListener>, Vec<String>) {
+        let mut listeners = HashMap::new();
+        let mut addrs = Vec::new();
+        for id in 0..screens {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap().to_string());
+            if alive.contains(&id) {
+                listeners.insert(id, listener);
+            }
+        }
+        (listeners, addrs)
+    }
+
+    /// Returns the byte a screen reads after the version of the protocol, the kind of connection
+    async fn kind_of_connection(listener: &TcpListener) -> u8 {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        protocol::read_version(&mut stream).await.unwrap();
+        let mut kind = [0; 1];
+        stream.read_exact(&mut kind).await.unwrap();
+        kind[0]
+    }
+
+    #[test]
+    fn test_screens_are_walked_in_ring_order() {
+        assert_eq!(following_screens(2, 4).collect::<Vec<_>>(), vec![3, 0, 1]);
+        assert_eq!(previous_screens(0, 3).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(following_screens(0, 1).count(), 0);
+    }
+
+    #[test]
+    fn test_every_screen_is_tried_before_giving_up() {
+        let tried: Vec<usize> = following_screens(0, 5).collect();
+        assert_eq!(tried, vec![1, 2, 3, 4]);
+    }
+
+    #[actix::test]
+    async fn test_screen_connects_past_a_following_screen_that_is_down() {
+        let (listeners, addrs) = screens_at(&[0, 2, 3], 4).await;
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let addr_of = |id: usize| addrs[id].clone();
+
+        let connected = connect_to_first_following(0, 4, addr_of, payments_gateway.clone()).await;
+        assert_eq!(connected, 2);
+        assert_eq!(
+            kind_of_connection(&listeners[&2]).await,
+            SCREEN_PREVIOUS as u8
+        );
+
+        let (_, addrs) = screens_at(&[0], 4).await;
+        let alone =
+            connect_to_first_following(0, 4, |id| addrs[id].clone(), payments_gateway).await;
+        assert_eq!(alone, 0);
+    }
+
+    #[actix::test]
+    async fn test_previous_screens_are_notified_past_one_that_is_down() {
+        let (listeners, addrs) = screens_at(&[0, 2, 3], 4).await;
+        notify_previous_screens(2, 4, |id| addrs[id].clone()).await;
+        for previous in [0, 3] {
+            let kind = kind_of_connection(&listeners[&previous]).await;
+            assert_eq!(kind, SCREEN_NEXT as u8);
+        }
+    }
 }
@@ -0,0 +1,196 @@
+//! Failover drill of a screen, so the operators can rehearse what happens when a screen dies.
+//! The screen closes its connections and refuses new ones for a while, as if it had died, so its next screen
+//! takes over its backup and asks the leader for its orders. Then the screen connects again, takes back the
+//! orders it still has captured, and reports which screen had claimed each one.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use actix::prelude::*;
+use tokio::sync::oneshot;
+
+/// How long the screen waits for the leader to answer after it rejoins
+pub const RECLAIM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// CloseConnection is a message that tells an actor of a connection to close it and stop, without handling it as a failure
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseConnection();
+
+/// What happened during a drill
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrillReport {
+    pub screen_id: usize,
+    pub down_for: Duration,
+    /// Orders that were not captured yet, handed to the next screen in the backup
+    pub handed_over_waiting: usize,
+    /// Orders captured but not sent to the leader yet, handed to the next screen in the backup
+    pub handed_over_pending: Vec<String>,
+    /// Orders captured when the screen went down
+    pub captured: Vec<String>,
+    /// Screens that had claimed the orders, as told by the leader when the screen rejoined
+    pub claimed: Vec<(usize, Vec<String>)>,
+    /// Orders another screen finished while the screen was down
+    pub finished: Vec<String>,
+    pub leader_answered: bool,
+}
+
+impl DrillReport {
+    pub fn new(
+        screen_id: usize,
+        down_for: Duration,
+        handed_over_waiting: usize,
+        handed_over_pending: Vec<String>,
+        mut captured: Vec<String>,
+    ) -> Self {
+        captured.sort();
+        Self {
+            screen_id,
+            down_for,
+            handed_over_waiting,
+            handed_over_pending,
+            captured,
+            claimed: Vec::new(),
+            finished: Vec::new(),
+            leader_answered: false,
+        }
+    }
+
+    /// Returns the captured orders that no other screen claimed nor finished
+    pub fn unclaimed(&self) -> Vec<String> {
+        let handled: HashSet<&String> = self
+            .claimed
+            .iter()
+            .flat_map(|(_, order_ids)| order_ids)
+            .chain(self.finished.iter())
+            .collect();
+        self.captured
+            .iter()
+            .filter(|id| !handled.contains(id))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if the leader answered and every captured order was claimed by another screen
+    pub fn passed(&self) -> bool {
+        self.leader_answered && self.unclaimed().is_empty()
+    }
+}
+
+impl fmt::Display for DrillReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Failover drill of Screen {}: down for {} s",
+            self.screen_id,
+            self.down_for.as_secs()
+        )?;
+        writeln!(
+            f,
+            "  handed over {} orders waiting and {} captured not sent to the leader",
+            self.handed_over_waiting,
+            self.handed_over_pending.len()
+        )?;
+        if !self.leader_answered {
+            return write!(
+                f,
+                "  FAILED: the leader did not answer after rejoining, {} captured orders unknown",
+                self.captured.len()
+            );
+        }
+        for (screen_id, order_ids) in &self.claimed {
+            writeln!(
+                f,
+                "  Screen {} had claimed {:?}, taken back",
+                screen_id, order_ids
+            )?;
+        }
+        if !self.finished.is_empty() {
+            writeln!(f, "  finished while down: {:?}", self.finished)?;
+        }
+        let unclaimed = self.unclaimed();
+        match (self.captured.is_empty(), unclaimed.is_empty()) {
+            (true, _) => write!(f, "  PASSED: no captured orders to take over"),
+            (false, true) => write!(
+                f,
+                "  PASSED: all {} captured orders were taken over",
+                self.captured.len()
+            ),
+            (false, false) => write!(f, "  FAILED: no screen claimed {:?}", unclaimed),
+        }
+    }
+}
+
+/// A drill in progress
+#[derive(Debug)]
+pub struct Drill {
+    pub report: DrillReport,
+    /// True while the screen plays dead, false once it is rejoining
+    pub down: bool,
+    answered: Option<oneshot::Sender<()>>,
+}
+
+impl Drill {
+    /// Starts a drill, the receiver is woken up when the leader answers the rejoin
+    pub fn start(report: DrillReport) -> (Self, oneshot::Receiver<()>) {
+        let (answered, receiver) = oneshot::channel();
+        let drill = Self {
+            report,
+            down: true,
+            answered: Some(answered),
+        };
+        (drill, receiver)
+    }
+
+    /// Registers the answer of the leader to the rejoin
+    pub fn reclaimed(&mut self, claimed: Vec<(usize, Vec<String>)>, finished: Vec<String>) {
+        self.report.claimed = claimed;
+        self.report.finished = finished;
+        self.report.leader_answered = true;
+        if let Some(answered) = self.answered.take() {
+            let _ = answered.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> DrillReport {
+        DrillReport::new(
+            1,
+            Duration::from_secs(5),
+            2,
+            Vec::new(),
+            vec!["b".to_string(), "a".to_string(), "c".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_drill_passes_if_every_captured_order_was_handled() {
+        let (mut drill, _answered) = Drill::start(report());
+        assert!(!drill.report.passed());
+
+        drill.reclaimed(
+            vec![(2, vec!["a".to_string(), "b".to_string()])],
+            vec!["c".to_string()],
+        );
+        assert!(drill.report.passed());
+        assert!(drill.report.to_string().contains("Screen 2 had claimed"));
+    }
+
+    #[test]
+    fn test_drill_fails_if_an_order_was_not_claimed() {
+        let (mut drill, _answered) = Drill::start(report());
+        drill.reclaimed(vec![(2, vec!["a".to_string()])], Vec::new());
+
+        assert_eq!(
+            drill.report.unclaimed(),
+            vec!["b".to_string(), "c".to_string()]
+        );
+        assert!(!drill.report.passed());
+        assert!(drill.report.to_string().contains("FAILED"));
+    }
+}
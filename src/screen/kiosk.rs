@@ -0,0 +1,449 @@
+//! Kiosk mode of a screen, a terminal UI for demos and self-service stands instead of the logs.
+//! It shows the orders waiting, captured, confirmed and aborted, the connection with the robot leader and the last
+//! log lines, and has a form to make new orders with the flavors of the menu.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::Addr;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph};
+use ratatui::Frame;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::log;
+use crate::common::order::Order;
+use crate::common::order_source::OrderChannel;
+use crate::config;
+use crate::screen::order_history::{HistoryEvent, OrderRecord};
+use crate::screen::order_reader::{OrderReader, ReadOrders, WatchOrders};
+use crate::screen::payments_gateway::{
+    GetScreenSnapshot, PaymentsGateway, ReceiveOrders, ScreenSnapshot,
+};
+use crate::screen::screen_command::new_order;
+
+/// Time the kiosk waits for a key before drawing the screen again
+const TICK: Duration = Duration::from_millis(250);
+/// Log lines kept to be shown
+const LOG_LINES: usize = 200;
+/// Orders with a result asked to the PaymentsGateway, the confirmed and aborted panels show them
+const FINISHED_SHOWN: usize = 50;
+
+const KEYS: &str = "←/→ size · ↑/↓ flavor · space pick · enter order · backspace clear · p process file · w watch · q quit";
+
+/// Keeps the last log lines, it is given to the log instead of stdout
+#[derive(Clone, Default)]
+pub struct LogTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    partial: String,
+}
+
+impl LogTail {
+    /// Returns the last lines, the newest last
+    pub fn lines(&self) -> Vec<String> {
+        match self.lines.lock() {
+            Ok(lines) => lines.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Write for LogTail {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            if let Ok(mut lines) = self.lines.lock() {
+                if lines.len() == LOG_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line.trim_end().to_string());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Form of a new order: its size, the flavor under the cursor and the flavors picked
+#[derive(Debug, Default, PartialEq)]
+pub struct OrderForm {
+    kind: usize,
+    cursor: usize,
+    chosen: Vec<FlavorID>,
+}
+
+impl OrderForm {
+    /// Name and most flavors of the size picked
+    fn kind(&self) -> (&'static str, usize) {
+        let (name, size) = config::get().sizes.all()[self.kind];
+        (name, size.max_flavors)
+    }
+
+    /// Picks the next or the previous size, the flavors it can not take are dropped
+    pub fn change_kind(&mut self, forward: bool) {
+        let kinds = config::get().sizes.all().len();
+        self.kind = match forward {
+            true => (self.kind + 1) % kinds,
+            false => (self.kind + kinds - 1) % kinds,
+        };
+        self.chosen.truncate(self.kind().1);
+    }
+
+    /// Moves the cursor up or down the flavors of the menu
+    pub fn move_cursor(&mut self, down: bool, flavors: usize) {
+        self.cursor = match down {
+            true => (self.cursor + 1).min(flavors.saturating_sub(1)),
+            false => self.cursor.saturating_sub(1),
+        };
+    }
+
+    /// Picks the flavor, or drops it if it was picked
+    pub fn toggle(&mut self, flavor: FlavorID) -> Result<(), String> {
+        if let Some(i) = self.chosen.iter().position(|chosen| *chosen == flavor) {
+            self.chosen.remove(i);
+            return Ok(());
+        }
+        let (name, max_flavors) = self.kind();
+        if self.chosen.len() == max_flavors {
+            return Err(format!("A {} takes up to {} flavors", name, max_flavors));
+        }
+        self.chosen.push(flavor);
+        Ok(())
+    }
+
+    /// Returns the order of the form
+    pub fn order(&self) -> Result<Order, String> {
+        new_order(self.kind().0, self.chosen.clone())
+    }
+
+    pub fn clear(&mut self) {
+        self.chosen.clear();
+    }
+}
+
+/// What the operator asked for with a key
+enum Action {
+    None,
+    Quit,
+    Submit(Order),
+    ProcessFile,
+    Watch,
+}
+
+/// State of the kiosk between two draws
+struct Kiosk {
+    form: OrderForm,
+    snapshot: ScreenSnapshot,
+    logs: LogTail,
+    notice: Option<(String, Color)>,
+    file_processed: bool,
+    watching: bool,
+}
+
+impl Kiosk {
+    fn new(logs: LogTail) -> Self {
+        Self {
+            form: OrderForm::default(),
+            snapshot: ScreenSnapshot::default(),
+            logs,
+            notice: None,
+            file_processed: false,
+            watching: false,
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let menu = &self.snapshot.menu;
+        self.form.cursor = self.form.cursor.min(menu.len().saturating_sub(1));
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Action::Quit,
+            KeyCode::Esc | KeyCode::Char('q') => Action::Quit,
+            KeyCode::Left => {
+                self.form.change_kind(false);
+                Action::None
+            }
+            KeyCode::Right => {
+                self.form.change_kind(true);
+                Action::None
+            }
+            KeyCode::Up => {
+                self.form.move_cursor(false, menu.len());
+                Action::None
+            }
+            KeyCode::Down => {
+                self.form.move_cursor(true, menu.len());
+                Action::None
+            }
+            KeyCode::Char(' ') => {
+                if let Some(flavor) = menu.get(self.form.cursor).cloned() {
+                    if let Err(e) = self.form.toggle(flavor) {
+                        self.notice = Some((e, Color::Red));
+                    }
+                }
+                Action::None
+            }
+            KeyCode::Backspace => {
+                self.form.clear();
+                Action::None
+            }
+            KeyCode::Enter => match self.form.order() {
+                Ok(order) => {
+                    self.form.clear();
+                    Action::Submit(order)
+                }
+                Err(e) => {
+                    self.notice = Some((e, Color::Red));
+                    Action::None
+                }
+            },
+            KeyCode::Char('p') if self.file_processed || self.watching => {
+                let notice = "The orders of the file were already processed".to_string();
+                self.notice = Some((notice, Color::Yellow));
+                Action::None
+            }
+            KeyCode::Char('p') => {
+                self.file_processed = true;
+                Action::ProcessFile
+            }
+            KeyCode::Char('w') => {
+                self.watching = true;
+                Action::Watch
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, orders, bottom, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(6),
+            Constraint::Length(12),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(Paragraph::new(self.status_line()), status);
+
+        let [waiting, captured, confirmed, aborted] =
+            Layout::horizontal([Constraint::Ratio(1, 4); 4]).areas(orders);
+        let snapshot = &self.snapshot;
+        let lines: Vec<String> = snapshot.waiting.iter().map(describe).collect();
+        render_list(frame, waiting, "Waiting", lines, Color::White);
+        let lines: Vec<String> = snapshot
+            .captured
+            .iter()
+            .map(|(id, order)| format!("{} {}", id, describe(order)))
+            .collect();
+        render_list(frame, captured, "Captured", lines, Color::Cyan);
+        let (confirmed_lines, aborted_lines) = finished_lines(&snapshot.finished);
+        render_list(frame, confirmed, "Confirmed", confirmed_lines, Color::Green);
+        render_list(frame, aborted, "Aborted", aborted_lines, Color::Red);
+
+        let [form, logs] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(bottom);
+        self.draw_form(frame, form);
+        let height = logs.height.saturating_sub(2) as usize;
+        let lines = self.logs.lines();
+        let skip = lines.len().saturating_sub(height);
+        let lines: Vec<Line> = lines.into_iter().skip(skip).map(Line::from).collect();
+        let block = Block::bordered().title("Log");
+        frame.render_widget(Paragraph::new(lines).block(block), logs);
+
+        let footer_line = match &self.notice {
+            Some((notice, color)) => Line::from(notice.as_str()).fg(*color),
+            None => Line::from(KEYS).dark_gray(),
+        };
+        frame.render_widget(Paragraph::new(footer_line), footer);
+    }
+
+    /// Line with the screen and its connection with the leader
+    fn status_line(&self) -> Line<'static> {
+        let snapshot = &self.snapshot;
+        let leader = match snapshot.leader {
+            Some((leader_id, epoch)) => format!("Robot {} (epoch {})", leader_id, epoch),
+            None => "unknown".to_string(),
+        };
+        let connection = match snapshot {
+            s if s.in_drill => Span::from("playing dead in a failover drill").magenta(),
+            s if s.leader_connected => Span::from("connected").green(),
+            s if s.connecting => Span::from("connecting...").yellow(),
+            _ => Span::from("not connected").red(),
+        };
+        Line::from(vec![
+            Span::from(format!("Screen {}", snapshot.screen_id)).bold(),
+            Span::from(format!(" · Leader: {} · ", leader)),
+            connection,
+        ])
+    }
+
+    fn draw_form(&self, frame: &mut Frame, area: Rect) {
+        let mut kinds = vec![];
+        for (i, (name, _)) in config::get().sizes.all().into_iter().enumerate() {
+            let span = match i == self.form.kind {
+                true => Span::from(format!(" {} ", name)).reversed(),
+                false => Span::from(format!(" {} ", name)),
+            };
+            kinds.push(span);
+        }
+        let mut lines = vec![Line::from(kinds), Line::default()];
+        for (i, flavor) in self.snapshot.menu.iter().enumerate() {
+            let mark = match self.form.chosen.contains(flavor) {
+                true => "[x]",
+                false => "[ ]",
+            };
+            let line = Line::from(format!("{} {}", mark, flavor));
+            lines.push(match i == self.form.cursor {
+                true => line.add_modifier(Modifier::REVERSED),
+                false => line,
+            });
+        }
+        let block = Block::bordered().title("New order");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+/// Describes the order with its size and the grams of each flavor
+fn describe(order: &Order) -> String {
+    let kind = match order {
+        Order::Cucurucho(_) => "Cucurucho",
+        Order::Cuarto(_) => "Cuarto",
+        Order::Medio(_) => "Medio",
+        Order::Kilo(_) => "Kilo",
+    };
+    let flavors: Vec<String> = order
+        .get_flavors()
+        .iter()
+        .map(|(flavor, grams)| format!("{} {}g", flavor, grams))
+        .collect();
+    format!("{}: {}", kind, flavors.join(", "))
+}
+
+/// Splits the orders with a result in the confirmed and the aborted ones, the newest first
+fn finished_lines(records: &[OrderRecord]) -> (Vec<String>, Vec<String>) {
+    let mut confirmed = vec![];
+    let mut aborted = vec![];
+    for record in records.iter().rev() {
+        let order = record.order.as_ref().map(describe).unwrap_or_default();
+        match &record.outcome {
+            Some((_, HistoryEvent::Confirmed { .. })) => {
+                confirmed.push(format!("{} {}", record.order_id, order))
+            }
+            Some((_, HistoryEvent::Aborted { reason }))
+            | Some((_, HistoryEvent::Refunded { reason })) => {
+                aborted.push(format!("{} {}: {}", record.order_id, order, reason))
+            }
+            _ => {}
+        }
+    }
+    (confirmed, aborted)
+}
+
+fn render_list(frame: &mut Frame, area: Rect, title: &str, lines: Vec<String>, color: Color) {
+    let title = format!("{} ({})", title, lines.len());
+    let items: Vec<ListItem> = lines.into_iter().map(ListItem::new).collect();
+    let list = List::new(items)
+        .style(Style::default().fg(color))
+        .block(Block::bordered().title(title));
+    frame.render_widget(list, area);
+}
+
+/// Waits a tick for a key or another event of the terminal
+fn next_event() -> Option<Event> {
+    match event::poll(TICK) {
+        Ok(true) => event::read().ok(),
+        _ => None,
+    }
+}
+
+/// Runs the kiosk until the operator quits, then the screen stops.
+/// The log lines meant for stdout are shown in the kiosk, the ones written to a file are left there
+pub async fn run(order_reader: Addr<OrderReader>, payments_gateway: Addr<PaymentsGateway>) {
+    let logs = LogTail::default();
+    log::redirect_terminal(Box::new(logs.clone()));
+    let mut terminal = ratatui::init();
+    let mut kiosk = Kiosk::new(logs);
+    loop {
+        if let Ok(snapshot) = payments_gateway
+            .send(GetScreenSnapshot::new(FINISHED_SHOWN))
+            .await
+        {
+            kiosk.snapshot = snapshot;
+        }
+        if let Err(e) = terminal.draw(|frame| kiosk.draw(frame)) {
+            log::error("KIOSK", format!("Could not draw the kiosk: {}", e));
+            break;
+        }
+        let event = tokio::task::spawn_blocking(next_event).await.ok().flatten();
+        let key = match event {
+            Some(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        kiosk.notice = None;
+        match kiosk.handle_key(key) {
+            Action::None => {}
+            Action::Quit => break,
+            Action::Submit(order) => {
+                let line = format!("New order from the kiosk: {:?}", order);
+                log::info("KIOSK", line);
+                let receive = ReceiveOrders::with_channel(vec![order], OrderChannel::Terminal);
+                if let Err(e) = payments_gateway.try_send(receive) {
+                    log::send_error("KIOSK", "ReceiveOrders", &e.to_string());
+                }
+            }
+            Action::ProcessFile => {
+                let _ = order_reader.send(ReadOrders()).await;
+            }
+            Action::Watch => order_reader.do_send(WatchOrders()),
+        }
+    }
+    ratatui::restore();
+    // the actors and connections of the screen stop with the process
+    process::exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_takes_the_flavors_its_size_allows() {
+        let mut form = OrderForm::default();
+        form.toggle(FlavorID::Mint).unwrap();
+        assert!(form.toggle(FlavorID::Lemon).is_err());
+        assert_eq!(form.order(), Ok(Order::new_cucurucho(FlavorID::Mint)));
+
+        form.change_kind(true);
+        form.toggle(FlavorID::Lemon).unwrap();
+        let cuarto = Order::new_cuarto(vec![FlavorID::Mint, FlavorID::Lemon]).unwrap();
+        assert_eq!(form.order(), Ok(cuarto));
+        form.toggle(FlavorID::Mint).unwrap();
+        form.change_kind(false);
+        form.change_kind(false);
+        assert_eq!(form.chosen, vec![FlavorID::Lemon]);
+        assert_eq!(form.kind().0, "kilo");
+        form.clear();
+        assert!(form.order().is_err());
+    }
+
+    #[test]
+    fn test_log_tail_keeps_whole_lines() {
+        let mut tail = LogTail::default();
+        write!(tail, "[GTW] Order ").unwrap();
+        writeln!(tail, "captured").unwrap();
+        tail.write_all(b"[RCH] Connected\n[RCH] Sent").unwrap();
+        assert_eq!(
+            tail.lines(),
+            vec!["[GTW] Order captured", "[RCH] Connected"]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What a screen knows of the cluster, it is gossiped to the next screen of the ring.
+/// Each screen counts its own heartbeats, the others keep the highest count they heard of each screen
+/// and when it grew, so a screen whose count stops growing is no longer alive.
+/// The leader is kept with its epoch, so a screen that rejoins can connect to it without waiting for it
+#[derive(Debug)]
+pub struct Membership {
+    my_id: usize,
+    heartbeat: u64,
+    members: HashMap<usize, (u64, Instant)>,
+    leader: Option<(usize, u64)>,
+}
+
+impl Membership {
+    pub fn new(my_id: usize) -> Self {
+        Self {
+            my_id,
+            heartbeat: 0,
+            members: HashMap::new(),
+            leader: None,
+        }
+    }
+
+    /// Counts a new heartbeat of the screen and returns the members to gossip,
+    /// the screen itself and the screens heard of within the timeout, with their heartbeats
+    pub fn tick(&mut self, timeout: Duration) -> Vec<(usize, u64)> {
+        self.heartbeat += 1;
+        let mut members: Vec<(usize, u64)> = self
+            .members
+            .iter()
+            .filter(|(_, (_, seen))| seen.elapsed() < timeout)
+            .map(|(id, (heartbeat, _))| (*id, *heartbeat))
+            .chain([(self.my_id, self.heartbeat)])
+            .collect();
+        members.sort();
+        members
+    }
+
+    /// Keeps the newest heartbeat of each member and the leader with the newest epoch.
+    /// Returns true if the leader changed
+    pub fn merge(&mut self, members: &[(usize, u64)], leader: Option<(usize, u64)>) -> bool {
+        for (id, heartbeat) in members {
+            if *id == self.my_id {
+                continue;
+            }
+            let newer = self
+                .members
+                .get(id)
+                .is_none_or(|(known, _)| heartbeat > known);
+            if newer {
+                self.members.insert(*id, (*heartbeat, Instant::now()));
+            }
+        }
+        match leader {
+            Some((leader_id, epoch)) => self.set_leader(leader_id, epoch),
+            None => false,
+        }
+    }
+
+    /// Sets the leader if its epoch is not older than the one known, returns true if it changed
+    pub fn set_leader(&mut self, leader_id: usize, epoch: u64) -> bool {
+        match self.leader {
+            Some((_, known_epoch)) if epoch < known_epoch => false,
+            Some((known, _)) if known == leader_id => {
+                self.leader = Some((leader_id, epoch));
+                false
+            }
+            _ => {
+                self.leader = Some((leader_id, epoch));
+                true
+            }
+        }
+    }
+
+    /// Returns the leader known and its epoch
+    pub fn leader(&self) -> Option<(usize, u64)> {
+        self.leader
+    }
+
+    /// Returns the ids of the screens heard of within the timeout, the screen itself included
+    pub fn alive(&self, timeout: Duration) -> Vec<usize> {
+        let mut alive: Vec<usize> = self
+            .members
+            .iter()
+            .filter(|(_, (_, seen))| seen.elapsed() < timeout)
+            .map(|(id, _)| *id)
+            .chain([self.my_id])
+            .collect();
+        alive.sort();
+        alive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_members_are_alive_while_their_heartbeat_grows() {
+        let timeout = Duration::from_secs(60);
+        let mut membership = Membership::new(0);
+        membership.merge(&[(1, 3), (2, 1)], None);
+        assert_eq!(membership.alive(timeout), vec![0, 1, 2]);
+        assert_eq!(membership.tick(timeout), vec![(0, 1), (1, 3), (2, 1)]);
+
+        membership.merge(&[(1, 2), (0, 10)], None);
+        assert_eq!(membership.tick(timeout), vec![(0, 2), (1, 3), (2, 1)]);
+        assert_eq!(membership.alive(Duration::ZERO), vec![0]);
+    }
+
+    #[test]
+    fn test_leader_with_older_epoch_is_ignored() {
+        let mut membership = Membership::new(0);
+        assert!(membership.merge(&[], Some((3, 2))));
+        assert!(!membership.merge(&[], Some((1, 1))));
+        assert!(!membership.set_leader(3, 4));
+        assert_eq!(membership.leader(), Some((3, 4)));
+        assert!(membership.set_leader(2, 4));
+        assert_eq!(membership.leader(), Some((2, 4)));
+    }
+}
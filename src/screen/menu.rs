@@ -0,0 +1,144 @@
+//! Flavors a screen offers. An order with a flavor off the menu, or with more grams of a flavor than the leader has left,
+//! is turned away before its payment is captured.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::screen::screen_command::parse_flavor;
+
+/// The flavors the screen was started with, all the ones of the catalog if none were given,
+/// without the ones the leader said have no stock.
+/// `grams` are the grams left of each flavor in the last snapshot of the leader, if one arrived
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Menu {
+    offered: Option<HashSet<FlavorID>>,
+    in_stock: Option<HashSet<FlavorID>>,
+    grams: Option<HashMap<FlavorID, usize>>,
+    from_leader: bool,
+}
+
+impl Menu {
+    /// Offers only the given flavors, or every one if the list is empty.
+    /// With `from_leader` the screen asks the leader which ones have stock when it connects
+    pub fn new(flavors: Vec<FlavorID>, from_leader: bool) -> Self {
+        Self {
+            offered: (!flavors.is_empty()).then(|| flavors.into_iter().collect()),
+            in_stock: None,
+            grams: None,
+            from_leader,
+        }
+    }
+
+    /// Parses the names of the flavors of the menu, as they are typed on the terminal
+    pub fn parse(names: &[String], from_leader: bool) -> Result<Self, String> {
+        let flavors = names
+            .iter()
+            .map(|name| parse_flavor(name.trim()))
+            .collect::<Result<Vec<FlavorID>, String>>()?;
+        Ok(Self::new(flavors, from_leader))
+    }
+
+    /// Returns true if the stock has to be asked to the leader
+    pub fn from_leader(&self) -> bool {
+        self.from_leader
+    }
+
+    /// Keeps on the menu only the flavors the leader has stock of
+    pub fn update_stock(&mut self, in_stock: Vec<FlavorID>) {
+        self.in_stock = Some(in_stock.into_iter().collect());
+    }
+
+    /// Keeps the grams left of each flavor of the last snapshot of the leader
+    pub fn update_grams(&mut self, grams: Vec<(FlavorID, usize)>) {
+        self.grams = Some(grams.into_iter().collect());
+    }
+
+    /// Returns true if the leader has the grams of the flavor, or if it did not say how many it has
+    fn has_grams(&self, flavor: &FlavorID, grams: usize) -> bool {
+        self.grams
+            .as_ref()
+            .and_then(|left| left.get(flavor))
+            .is_none_or(|left| *left >= grams)
+    }
+
+    /// Returns true if the flavor is on the menu
+    pub fn offers(&self, flavor: &FlavorID) -> bool {
+        let offered = |flavors: &Option<HashSet<FlavorID>>| {
+            flavors
+                .as_ref()
+                .is_none_or(|flavors| flavors.contains(flavor))
+        };
+        offered(&self.offered) && offered(&self.in_stock)
+    }
+
+    /// Checks that every flavor of the order is on the menu and that the leader has its grams or the ones of a substitute
+    pub fn check(&self, order: &Order) -> Result<(), String> {
+        if let Some((flavor, _)) = order
+            .get_flavors()
+            .into_iter()
+            .find(|(flavor, _)| !self.offers(flavor))
+        {
+            return Err(format!("{} is not on the menu", flavor));
+        }
+        let mut asked: HashMap<FlavorID, usize> = HashMap::new();
+        for (flavor, grams) in order.get_flavors() {
+            *asked.entry(flavor).or_insert(0) += grams;
+        }
+        let substitutes = order.get_substitutes();
+        let short = asked.into_iter().find(|(flavor, grams)| {
+            !self.has_grams(flavor, *grams)
+                && substitutes
+                    .iter()
+                    .filter(|(id, _)| id == flavor)
+                    .flat_map(|(_, substitutes)| substitutes)
+                    .all(|substitute| {
+                        !self.offers(substitute) || !self.has_grams(substitute, *grams)
+                    })
+        });
+        match short {
+            Some((flavor, _)) => Err(format!("There is not enough {} left", flavor)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders_off_the_menu_are_turned_away() {
+        let mut menu =
+            Menu::parse(&["mint".to_string(), "Dulce_De_Leche".to_string()], true).unwrap();
+        assert!(menu.check(&Order::new_cucurucho(FlavorID::Mint)).is_ok());
+        assert_eq!(
+            menu.check(&Order::new_cucurucho(FlavorID::Lemon)),
+            Err("Lemon is not on the menu".to_string())
+        );
+
+        menu.update_stock(vec![FlavorID::DulceDeLeche, FlavorID::Lemon]);
+        assert!(!menu.offers(&FlavorID::Mint));
+        assert!(menu.offers(&FlavorID::DulceDeLeche));
+        assert!(!menu.offers(&FlavorID::Lemon));
+        assert!(Menu::parse(&["peach".to_string()], false).is_err());
+        assert!(Menu::default().offers(&FlavorID::Lemon));
+    }
+
+    #[test]
+    fn test_orders_with_more_grams_than_left_are_turned_away() {
+        let mut menu = Menu::default();
+        let cucurucho = Order::new_cucurucho(FlavorID::Mint);
+        assert!(menu.check(&cucurucho).is_ok());
+
+        menu.update_grams(vec![(FlavorID::Mint, 100), (FlavorID::Lemon, 1000)]);
+        assert_eq!(
+            menu.check(&cucurucho),
+            Err("There is not enough Mint left".to_string())
+        );
+        assert!(menu.check(&Order::new_cucurucho(FlavorID::Lemon)).is_ok());
+        assert!(menu.check(&Order::new_cucurucho(FlavorID::Vanilla)).is_ok());
+        let substituted = cucurucho.with_substitutes(FlavorID::Mint, vec![FlavorID::Lemon]);
+        assert!(menu.check(&substituted).is_ok());
+    }
+}
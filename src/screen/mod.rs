@@ -1,8 +1,21 @@
 pub mod backup_handler;
 pub mod communication;
+pub mod failover_drill;
+#[cfg(feature = "tui")]
+pub mod kiosk;
+pub mod membership;
+pub mod menu;
+pub mod order_api;
+pub mod order_history;
+pub mod order_ids;
+pub mod order_queue;
 pub mod order_reader;
+pub mod order_watcher;
+pub mod payment_scenario;
 pub mod payments_gateway;
+pub mod pickup;
 pub mod robot_connection_handler;
+pub mod screen_command;
 pub mod screen_connection_listener;
 pub mod screen_connection_sender;
-pub mod screen_error;
+pub mod webhook;
@@ -0,0 +1,371 @@
+//! HTTP API of a screen, so other systems can send orders besides the order file.
+//! `POST /orders` takes an order in JSON and answers with its id, and `GET /orders/{id}` answers with its status.
+//! `GET /pickups` answers with the orders waiting to be picked up, and `POST /pickups/{code}` hands out the order with the code.
+
+use crate::common::log;
+use crate::common::order::Substitution;
+use crate::screen::order_reader::parse_order_line;
+use crate::screen::payments_gateway::{
+    GetOrderStatus, GetPickups, PaymentsGateway, PickUpOrder, SubmitOrder,
+};
+use actix::Addr;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Biggest request that is read, an order is much smaller
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// How long a client has to send the whole request before the connection is closed
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Status of an order submitted through the API
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// The payment was not captured yet
+    Waiting,
+    /// The card was declined, the order is not prepared
+    Declined,
+    /// The payment was captured and the order is being prepared, with the orders ahead of it the last time the leader was asked
+    Captured {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        orders_ahead: Option<usize>,
+    },
+    /// The order was prepared and the payment confirmed, with the flavors that were replaced by a substitute
+    /// and the code to pick it up
+    Confirmed {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        substitutions: Vec<Substitution>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pickup_code: Option<String>,
+    },
+    /// The order was handed to the customer
+    PickedUp,
+    /// The order could not be prepared and the payment was aborted
+    Aborted { error: String },
+    /// The order was aborted after the payment was confirmed, and the payment was refunded
+    Refunded { error: String },
+    /// Another screen took charge of the order
+    Released,
+}
+
+impl OrderStatus {
+    /// Returns true if the screen is done with the order, only a confirmed one can still be picked up
+    pub fn is_final(&self) -> bool {
+        !matches!(self, OrderStatus::Waiting | OrderStatus::Captured { .. })
+    }
+}
+
+/// Answer to a submitted order
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Submitted {
+    pub id: String,
+}
+
+/// Answer to a request that failed
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ApiError {
+    pub error: String,
+}
+
+/// Method, path and body of an HTTP request
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// Answer to a request, with its status code and a JSON body
+#[derive(Debug, PartialEq)]
+struct Response {
+    code: u16,
+    body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(code: u16, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self { code, body },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(code: u16, error: &str) -> Self {
+        let error = ApiError {
+            error: error.to_string(),
+        };
+        Self {
+            code,
+            body: serde_json::to_string(&error).unwrap_or_default(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.code {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Serves the API of the screen on the given address, the orders are given to the PaymentsGateway
+pub async fn serve(addr: String, payments_gateway: Addr<PaymentsGateway>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            let line = format!("Could not listen on {}: {}", addr, e);
+            log::error("API", line.red());
+            return;
+        }
+    };
+    let line = format!("Taking orders on http://{}/orders", addr);
+    log::info("API", line.bright_blue());
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer_request(stream, payments_gateway.clone()));
+    }
+}
+
+/// Answers a single request, the connection is closed after it
+async fn answer_request(mut stream: TcpStream, payments_gateway: Addr<PaymentsGateway>) {
+    let response = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => route(request, &payments_gateway).await,
+        Ok(Err(response)) => response,
+        Err(_) => Response::error(408, "Request not received in time"),
+    };
+    let http = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.code,
+        response.reason(),
+        response.body.len(),
+        response.body
+    );
+    let _ = stream.write_all(http.as_bytes()).await;
+}
+
+/// Reads the head of the request and as much of the body as its Content-Length says,
+/// or returns the error to answer if the request is malformed or too big
+async fn read_request<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Request, Response> {
+    let malformed = || Response::error(400, "Malformed request");
+    let too_large = || Response::error(413, "Request too large");
+    let mut raw = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let read = stream.read(&mut buf).await.map_err(|_| malformed())?;
+        if read == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buf[..read]);
+        let Some(head_end) = find_head_end(&raw) else {
+            if raw.len() > MAX_REQUEST_SIZE {
+                return Err(too_large());
+            }
+            continue;
+        };
+        let request_size = head_end
+            .checked_add(content_length(&raw[..head_end]))
+            .filter(|size| *size <= MAX_REQUEST_SIZE)
+            .ok_or_else(too_large)?;
+        if raw.len() >= request_size {
+            break;
+        }
+    }
+    parse_request(&String::from_utf8_lossy(&raw)).ok_or_else(malformed)
+}
+
+/// Returns where the body of the request starts, if the whole head was read
+fn find_head_end(raw: &[u8]) -> Option<usize> {
+    raw.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+/// Returns the Content-Length of the head of a request, or 0 if it has none
+fn content_length(head: &[u8]) -> usize {
+    String::from_utf8_lossy(head)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Parses the request line and the body of a request
+fn parse_request(raw: &str) -> Option<Request> {
+    let (head, body) = raw.split_once("\r\n\r\n").unwrap_or((raw, ""));
+    let mut request_line = head.lines().next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    Some(Request {
+        method,
+        path,
+        body: body.to_string(),
+    })
+}
+
+/// Runs the request and returns its answer
+async fn route(request: Request, payments_gateway: &Addr<PaymentsGateway>) -> Response {
+    let path = request.path.trim_end_matches('/');
+    if let Some(rest) = path.strip_prefix("/pickups") {
+        return route_pickups(&request.method, rest, payments_gateway).await;
+    }
+    match (request.method.as_str(), path.strip_prefix("/orders")) {
+        ("POST", Some("")) => {
+            let order = match parse_order_line(&request.body) {
+                Ok(order) => order,
+                Err(e) => return Response::error(400, &format!("Invalid order: {}", e)),
+            };
+            match payments_gateway.send(SubmitOrder::new(order)).await {
+                Ok(Ok(id)) => Response::json(202, &Submitted { id }),
+                Ok(Err(e)) => Response::error(400, &format!("Invalid order: {}", e)),
+                Err(e) => Response::error(500, &e.to_string()),
+            }
+        }
+        ("GET", Some(id)) if id.starts_with('/') => {
+            let id = id.trim_start_matches('/').to_string();
+            match payments_gateway.send(GetOrderStatus::new(id)).await {
+                Ok(Some(status)) => Response::json(200, &status),
+                Ok(None) => Response::error(404, "Unknown order"),
+                Err(e) => Response::error(500, &e.to_string()),
+            }
+        }
+        (_, Some(rest)) if rest.is_empty() || rest.starts_with('/') => {
+            Response::error(405, "Method not allowed")
+        }
+        _ => Response::error(404, "Unknown path"),
+    }
+}
+
+/// Runs a request to the pickups, the path is what follows `/pickups`
+async fn route_pickups(
+    method: &str,
+    rest: &str,
+    payments_gateway: &Addr<PaymentsGateway>,
+) -> Response {
+    match (method, rest) {
+        ("GET", "") => match payments_gateway.send(GetPickups()).await {
+            Ok(pickups) => Response::json(200, &pickups),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ("POST", code) if code.len() > 1 && code.starts_with('/') => {
+            let code = code.trim_start_matches('/').to_string();
+            match payments_gateway.send(PickUpOrder::new(code)).await {
+                Ok(Ok(pickup)) => Response::json(200, &pickup),
+                Ok(Err(e)) => Response::error(404, &e),
+                Err(e) => Response::error(500, &e.to_string()),
+            }
+        }
+        (_, rest) if rest.is_empty() || rest.starts_with('/') => {
+            Response::error(405, "Method not allowed")
+        }
+        _ => Response::error(404, "Unknown path"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order::Order;
+    use actix::Actor;
+
+    #[test]
+    fn test_request_is_parsed_with_its_body() {
+        let raw = "POST /orders HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(content_length(raw.as_bytes()), 2);
+        assert_eq!(find_head_end(raw.as_bytes()), Some(raw.len() - 2));
+        assert_eq!(
+            parse_request(raw),
+            Some(Request {
+                method: "POST".to_string(),
+                path: "/orders".to_string(),
+                body: "{}".to_string(),
+            })
+        );
+        assert_eq!(parse_request(""), None);
+    }
+
+    #[actix::test]
+    async fn test_oversized_requests_are_rejected() {
+        let read = |raw: String| async move {
+            let (mut client, mut server) = tokio::io::duplex(MAX_REQUEST_SIZE * 2);
+            client.write_all(raw.as_bytes()).await.unwrap();
+            drop(client);
+            read_request(&mut server)
+                .await
+                .map_err(|response| response.code)
+        };
+        let head = |length: &str| {
+            format!(
+                "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                length
+            )
+        };
+
+        assert!(read(head("2") + "{}").await.is_ok());
+        assert_eq!(read(head(&usize::MAX.to_string())).await, Err(413));
+        assert_eq!(
+            read(head(&(MAX_REQUEST_SIZE + 1).to_string())).await,
+            Err(413)
+        );
+        assert_eq!(read("X".repeat(MAX_REQUEST_SIZE + 1)).await, Err(413));
+        assert_eq!(read(String::new()).await, Err(400));
+    }
+
+    #[actix::test]
+    async fn test_submitted_order_can_be_polled() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let order = serde_json::to_string(&Order::new_cucurucho(FlavorID::Mint)).unwrap();
+        let submit = |body: &str| Request {
+            method: "POST".to_string(),
+            path: "/orders".to_string(),
+            body: body.to_string(),
+        };
+        assert_eq!(route(submit("nope"), &payments_gateway).await.code, 400);
+
+        let response = route(submit(&order), &payments_gateway).await;
+        assert_eq!(response.code, 202);
+        let id = serde_json::from_str::<Submitted>(&response.body)
+            .unwrap()
+            .id;
+
+        let poll = |id: &str| Request {
+            method: "GET".to_string(),
+            path: format!("/orders/{}", id),
+            body: String::new(),
+        };
+        let response = route(poll(&id), &payments_gateway).await;
+        assert_eq!(response.code, 200);
+        assert_eq!(
+            serde_json::from_str::<OrderStatus>(&response.body).unwrap(),
+            OrderStatus::Waiting
+        );
+        assert_eq!(route(poll("unknown"), &payments_gateway).await.code, 404);
+    }
+
+    #[actix::test]
+    async fn test_ready_orders_are_picked_up_by_their_code() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let request = |method: &str, path: &str| Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: String::new(),
+        };
+        let response = route(request("GET", "/pickups"), &payments_gateway).await;
+        assert_eq!((response.code, response.body.as_str()), (200, "[]"));
+        let response = route(request("POST", "/pickups/A001"), &payments_gateway).await;
+        assert_eq!(response.code, 404);
+        let response = route(request("DELETE", "/pickups"), &payments_gateway).await;
+        assert_eq!(response.code, 405);
+    }
+}
@@ -0,0 +1,349 @@
+//! History of the orders handled by a screen, so it is not lost when the screen restarts.
+//! The captures, confirmations, aborts and refunds are appended to a store, by default a file with one JSON entry per line.
+
+use crate::common::log;
+use crate::common::persistence_writer;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::order::{Order, Substitution};
+use crate::config;
+
+/// Entries of the history a store keeps in memory, the older ones are only kept in the file
+pub const ENTRIES_IN_MEMORY: usize = 10_000;
+
+/// Error type for the order history stored by a screen
+#[derive(Debug, PartialEq)]
+pub enum OrderHistoryError {
+    CouldNotWrite(String),
+    CouldNotRead(String),
+    ErrorParsing(String),
+}
+
+impl fmt::Display for OrderHistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderHistoryError::CouldNotWrite(err) => {
+                write!(f, "Could not write order history: {}", err)
+            }
+            OrderHistoryError::CouldNotRead(err) => {
+                write!(f, "Could not read order history: {}", err)
+            }
+            OrderHistoryError::ErrorParsing(err) => {
+                write!(f, "Could not parse order history entry: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderHistoryError {}
+
+/// Something that happened to the payment of an order
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum HistoryEvent {
+    Captured {
+        order: Order,
+    },
+    Confirmed {
+        substitutions: Vec<Substitution>,
+    },
+    Aborted {
+        reason: String,
+    },
+    /// The order was aborted after its payment was confirmed, so the payment was given back
+    Refunded {
+        reason: String,
+    },
+}
+
+/// An entry of the history
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub timestamp_ms: u64,
+    pub order_id: String,
+    pub event: HistoryEvent,
+}
+
+impl HistoryEntry {
+    pub fn new(order_id: &str, event: HistoryEvent) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            order_id: order_id.to_string(),
+            event,
+        }
+    }
+}
+
+/// Where the history of a screen is kept
+pub trait OrderStore {
+    /// Adds an entry at the end of the history
+    fn append(&mut self, entry: &HistoryEntry) -> Result<(), OrderHistoryError>;
+
+    /// Returns the entries of the history kept in memory, in the order they were added
+    fn load(&self) -> Result<Vec<HistoryEntry>, OrderHistoryError>;
+
+    /// Returns the last confirmation, abort or refund of the order, without copying the rest of the history
+    fn outcome(&self, order_id: &str) -> Option<HistoryEvent>;
+}
+
+/// Adds the entry to the ones kept in memory, forgetting the oldest one if there are too many
+fn keep(entries: &mut VecDeque<HistoryEntry>, entry: &HistoryEntry) {
+    if entries.len() == ENTRIES_IN_MEMORY {
+        entries.pop_front();
+    }
+    entries.push_back(entry.clone());
+}
+
+/// Looks for the last outcome of the order, from the newest entry
+fn last_outcome(entries: &VecDeque<HistoryEntry>, order_id: &str) -> Option<HistoryEvent> {
+    entries
+        .iter()
+        .rev()
+        .find(|entry| {
+            entry.order_id == order_id && !matches!(entry.event, HistoryEvent::Captured { .. })
+        })
+        .map(|entry| entry.event.clone())
+}
+
+/// Keeps the history in memory, it is lost when the screen stops
+#[derive(Debug, Default)]
+pub struct MemoryOrderStore {
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl OrderStore for MemoryOrderStore {
+    fn append(&mut self, entry: &HistoryEntry) -> Result<(), OrderHistoryError> {
+        keep(&mut self.entries, entry);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<HistoryEntry>, OrderHistoryError> {
+        Ok(self.entries.iter().cloned().collect())
+    }
+
+    fn outcome(&self, order_id: &str) -> Option<HistoryEvent> {
+        last_outcome(&self.entries, order_id)
+    }
+}
+
+/// Keeps the history in an append-only file of the data directory, one JSON entry per line.
+/// The file is read once when the store is created and the entries are written by the persistence writer
+/// of the process, so the history is answered from memory and includes the entries that are not flushed yet.
+/// Only the last `ENTRIES_IN_MEMORY` entries are kept in memory
+#[derive(Debug)]
+pub struct FileOrderStore {
+    path: PathBuf,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl FileOrderStore {
+    /// Creates the store and loads the history the screen had before restarting
+    pub fn new(data_dir: &str, screen_id: usize) -> Result<Self, OrderHistoryError> {
+        let path = Path::new(data_dir).join(format!("order_history_{}.jsonl", screen_id));
+        let mut entries = VecDeque::from(read_entries(&path)?);
+        entries.drain(..entries.len().saturating_sub(ENTRIES_IN_MEMORY));
+        Ok(Self { path, entries })
+    }
+}
+
+/// Reads every entry of the file, in the order they were written.
+/// A line that can not be parsed, like one cut by a crash while it was written, is skipped
+fn read_entries(path: &Path) -> Result<Vec<HistoryEntry>, OrderHistoryError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(OrderHistoryError::CouldNotRead(e.to_string())),
+    };
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn("OH", format!("Skipping history line: {}", e)),
+        }
+    }
+    Ok(entries)
+}
+
+impl OrderStore for FileOrderStore {
+    fn append(&mut self, entry: &HistoryEntry) -> Result<(), OrderHistoryError> {
+        let line = serde_json::to_string(entry)
+            .map_err(|e| OrderHistoryError::ErrorParsing(e.to_string()))?;
+        persistence_writer::append(self.path.clone(), line);
+        keep(&mut self.entries, entry);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<HistoryEntry>, OrderHistoryError> {
+        Ok(self.entries.iter().cloned().collect())
+    }
+
+    fn outcome(&self, order_id: &str) -> Option<HistoryEvent> {
+        last_outcome(&self.entries, order_id)
+    }
+}
+
+/// Returns the store of the screen, a file if persistence is enabled in the config
+pub fn store_for(screen_id: usize) -> Box<dyn OrderStore> {
+    let persistence = &config::get().persistence;
+    if !persistence.enabled || cfg!(test) {
+        return Box::new(MemoryOrderStore::default());
+    }
+    match FileOrderStore::new(&persistence.data_dir, screen_id) {
+        Ok(store) => Box::new(store),
+        Err(e) => {
+            log::error("OH", format!("Error! {}", e));
+            Box::new(MemoryOrderStore::default())
+        }
+    }
+}
+
+/// What is known of an order from its entries of the history
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderRecord {
+    pub order_id: String,
+    pub order: Option<Order>,
+    pub captured_ms: Option<u64>,
+    /// Last confirmation, abort or refund of the order, with when it happened
+    pub outcome: Option<(u64, HistoryEvent)>,
+}
+
+impl fmt::Display for OrderRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcome = match &self.outcome {
+            Some((_, HistoryEvent::Confirmed { substitutions })) if !substitutions.is_empty() => {
+                let substitutions: Vec<String> = substitutions
+                    .iter()
+                    .map(|s| format!("{} instead of {}", s.substitute, s.flavor))
+                    .collect();
+                format!("confirmed ({})", substitutions.join(", "))
+            }
+            Some((_, HistoryEvent::Confirmed { .. })) => "confirmed".to_string(),
+            Some((_, HistoryEvent::Aborted { reason })) => format!("aborted: {}", reason),
+            Some((_, HistoryEvent::Refunded { reason })) => format!("refunded: {}", reason),
+            _ => "captured".to_string(),
+        };
+        let elapsed = match (self.captured_ms, &self.outcome) {
+            (Some(captured), Some((finished, _))) => {
+                format!(" in {} ms", finished.saturating_sub(captured))
+            }
+            _ => String::new(),
+        };
+        match &self.order {
+            Some(order) => write!(f, "{} {:?} {}{}", self.order_id, order, outcome, elapsed),
+            None => write!(f, "{} {}{}", self.order_id, outcome, elapsed),
+        }
+    }
+}
+
+/// Groups the entries by order, the orders are sorted by the first time they appear in the history
+pub fn summarize(entries: &[HistoryEntry]) -> Vec<OrderRecord> {
+    let mut records: Vec<OrderRecord> = Vec::new();
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for entry in entries {
+        let position = *positions.entry(&entry.order_id).or_insert_with(|| {
+            records.push(OrderRecord {
+                order_id: entry.order_id.clone(),
+                order: None,
+                captured_ms: None,
+                outcome: None,
+            });
+            records.len() - 1
+        });
+        let record = &mut records[position];
+        match &entry.event {
+            HistoryEvent::Captured { order } => {
+                record.order = Some(order.clone());
+                record.captured_ms = Some(entry.timestamp_ms);
+            }
+            event => record.outcome = Some((entry.timestamp_ms, event.clone())),
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+
+    fn entry(order_id: &str, timestamp_ms: u64, event: HistoryEvent) -> HistoryEntry {
+        HistoryEntry {
+            timestamp_ms,
+            order_id: order_id.to_string(),
+            event,
+        }
+    }
+
+    #[test]
+    fn test_file_store_keeps_the_entries_after_a_restart() {
+        let dir = std::env::temp_dir()
+            .join(format!("freddo_order_history_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_dir_all(&dir);
+        let captured = entry(
+            "1",
+            10,
+            HistoryEvent::Captured {
+                order: Order::new_cucurucho(FlavorID::Mint),
+            },
+        );
+        FileOrderStore::new(&dir, 0)
+            .unwrap()
+            .append(&captured)
+            .unwrap();
+
+        let store = FileOrderStore::new(&dir, 0).unwrap();
+        assert_eq!(store.load().unwrap(), vec![captured]);
+        assert!(FileOrderStore::new(&dir, 1)
+            .unwrap()
+            .load()
+            .unwrap()
+            .is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_entries_are_grouped_by_order() {
+        let entries = vec![
+            entry(
+                "1",
+                10,
+                HistoryEvent::Captured {
+                    order: Order::new_cucurucho(FlavorID::Mint),
+                },
+            ),
+            entry(
+                "2",
+                20,
+                HistoryEvent::Captured {
+                    order: Order::new_cucurucho(FlavorID::Lemon),
+                },
+            ),
+            entry(
+                "1",
+                50,
+                HistoryEvent::Aborted {
+                    reason: "no leader".to_string(),
+                },
+            ),
+        ];
+        let records = summarize(&entries);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].order_id, "1");
+        assert!(records[0]
+            .to_string()
+            .contains("aborted: no leader in 40 ms"));
+        assert_eq!(records[1].outcome, None);
+    }
+}
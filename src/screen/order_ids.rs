@@ -0,0 +1,136 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use uuid::Builder;
+
+use crate::common::simulation;
+use crate::config::orders::OrderIdScheme;
+use crate::screen::order_history::HistoryEntry;
+
+/// Names the orders a screen captures, following the configured scheme.
+/// With `screenid-seq` the ids carry the number of the screen among the screens of every leader shard,
+/// so two screens never give the same one, not even in different shards,
+/// and the numbers go on after the highest one of the history, so a screen that restarts with its history
+/// does not name a new order like one it already sent to the leader.
+/// With `Uuid` the ids are random, and in simulation mode they come from the seeded generator of the screen,
+/// so two runs with the same seed name their orders the same.
+/// The leader takes the ids as they come, it only compares them
+#[derive(Debug)]
+pub struct OrderIds {
+    screen_id: usize,
+    scheme: OrderIdScheme,
+    last: u64,
+    rng: StdRng,
+}
+
+impl OrderIds {
+    pub fn new(screen_id: usize, scheme: OrderIdScheme) -> Self {
+        Self {
+            screen_id,
+            scheme,
+            last: 0,
+            rng: simulation::rng("IDS", screen_id),
+        }
+    }
+
+    /// Replaces the random generator of the uuids, so the ids can be reproduced
+    pub fn with_rng(mut self, rng: StdRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Goes on after the highest number of the ids of this screen in the history
+    pub fn resume_after(mut self, history: &[HistoryEntry]) -> Self {
+        self.last = history
+            .iter()
+            .filter_map(|entry| self.number_of(&entry.order_id))
+            .fold(self.last, u64::max);
+        self
+    }
+
+    /// Returns the number of an id of this screen, None if it is not one
+    fn number_of(&self, order_id: &str) -> Option<u64> {
+        let (screen_id, number) = order_id.split_once('-')?;
+        if screen_id.parse::<usize>().ok()? != self.screen_id {
+            return None;
+        }
+        number.parse().ok()
+    }
+
+    /// Returns a new id, skipping the ones that are still in use by the screen
+    pub fn next(&mut self, in_use: impl Fn(&str) -> bool) -> String {
+        loop {
+            let id = match self.scheme {
+                OrderIdScheme::Uuid => Builder::from_random_bytes(self.rng.gen())
+                    .into_uuid()
+                    .to_string(),
+                OrderIdScheme::ScreenSeq => {
+                    self.last += 1;
+                    format!("{}-{}", self.screen_id, self.last)
+                }
+            };
+            if !in_use(&id) {
+                return id;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::order_history::HistoryEvent;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_screens_never_give_the_same_id() {
+        let mut first = OrderIds::new(1, OrderIdScheme::ScreenSeq);
+        let mut second = OrderIds::new(11, OrderIdScheme::ScreenSeq);
+        let ids: HashSet<String> = (0..100)
+            .flat_map(|_| [first.next(|_| false), second.next(|_| false)])
+            .collect();
+        assert_eq!(ids.len(), 200);
+        assert!(ids.contains("1-1") && ids.contains("11-1") && ids.contains("1-100"));
+    }
+
+    #[test]
+    fn test_ids_go_on_after_the_history_and_skip_the_ones_in_use() {
+        let history = vec![
+            HistoryEntry::new(
+                "2-7",
+                HistoryEvent::Aborted {
+                    reason: String::new(),
+                },
+            ),
+            HistoryEntry::new(
+                "12-40",
+                HistoryEvent::Aborted {
+                    reason: String::new(),
+                },
+            ),
+            HistoryEntry::new(
+                "0b4e7a4c-uuid",
+                HistoryEvent::Aborted {
+                    reason: String::new(),
+                },
+            ),
+        ];
+        let mut ids = OrderIds::new(2, OrderIdScheme::ScreenSeq).resume_after(&history);
+        assert_eq!(ids.next(|_| false), "2-8");
+        assert_eq!(ids.next(|id| id == "2-9"), "2-10");
+
+        let mut uuids = OrderIds::new(2, OrderIdScheme::Uuid);
+        assert_ne!(uuids.next(|_| false), uuids.next(|_| false));
+    }
+
+    #[test]
+    fn test_seeded_uuids_are_reproduced() {
+        let seeded =
+            || OrderIds::new(2, OrderIdScheme::Uuid).with_rng(simulation::seeded_rng(7, "IDS", 2));
+        let (mut first, mut second) = (seeded(), seeded());
+        let first: Vec<String> = (0..3).map(|_| first.next(|_| false)).collect();
+        let second: Vec<String> = (0..3).map(|_| second.next(|_| false)).collect();
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert!(first.iter().all(|id| id.len() == 36));
+    }
+}
@@ -0,0 +1,77 @@
+//! Orders a screen received and has not captured yet, each one with where and when it got to the screen.
+
+use std::collections::VecDeque;
+
+use crate::common::order::Order;
+use crate::common::order_source::OrderSource;
+
+/// Queue of the orders waiting to be captured, taken from the oldest one.
+/// The orders are moved in and out of it, so receiving or capturing one does not copy the others
+#[derive(Debug, Default)]
+pub struct OrderQueue {
+    orders: VecDeque<(Order, OrderSource)>,
+}
+
+impl OrderQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the orders at the end of the queue, each one with the source made for it
+    pub fn extend(
+        &mut self,
+        orders: impl IntoIterator<Item = Order>,
+        mut source: impl FnMut() -> OrderSource,
+    ) {
+        self.orders
+            .extend(orders.into_iter().map(|order| (order, source())));
+    }
+
+    /// Takes the oldest order of the queue
+    pub fn pop_front(&mut self) -> Option<(Order, OrderSource)> {
+        self.orders.pop_front()
+    }
+
+    /// Forgets the `count` oldest orders, or every one if there are fewer
+    pub fn drop_front(&mut self, count: usize) {
+        self.orders.drain(..count.min(self.orders.len()));
+    }
+
+    /// Returns the orders from the oldest one, without their sources
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter().map(|(order, _)| order)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order_source::OrderChannel;
+
+    #[test]
+    fn test_orders_are_taken_in_the_order_they_arrived() {
+        let mut queue = OrderQueue::new();
+        let orders = [FlavorID::Chocolate, FlavorID::Vanilla, FlavorID::Strawberry]
+            .map(Order::new_cucurucho);
+        queue.extend(orders.clone(), || OrderSource::new(1, OrderChannel::File));
+        queue.drop_front(1);
+        assert_eq!(
+            queue.orders().collect::<Vec<_>>(),
+            vec![&orders[1], &orders[2]]
+        );
+        let (order, source) = queue.pop_front().unwrap();
+        assert_eq!(order, orders[1]);
+        assert_eq!(source.channel, OrderChannel::File);
+        queue.drop_front(5);
+        assert!(queue.is_empty());
+    }
+}
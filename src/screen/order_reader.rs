@@ -1,18 +1,30 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::{BufRead, BufReader},
+    time::Duration,
 };
 
+use crate::common::flavor_id::FlavorID;
+use crate::common::log;
 use crate::common::order::Order;
+use crate::config;
+use crate::config::flavors::FlavorCatalog;
 use actix::prelude::*;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
 
+use super::order_watcher::OrderWatcher;
 use super::payments_gateway::ReceiveOrders;
 /// OrderReader is an actor that reads a file with orders, processes them and sends them to the PaymentsGateway actor.
+/// A huge file can be streamed instead, a chunk at a time, see StreamOrders.
 ///
 /// # Attributes
 ///
 /// * `orders` - A vector of orders read from the file.
 /// * `file_name` - The name of the file to read.
+/// * `watcher` - Follows the file, or directory, in watch mode, so the new orders are sent as they are added.
 ///
 ///
 /// # Panics
@@ -23,20 +35,41 @@ use super::payments_gateway::ReceiveOrders;
 ///
 /// If the file is empty, the actor will return an empty vector.
 ///
-/// If the file contains an invalid order, the actor will ignore it.
+/// If the file contains an invalid order, the actor will skip it and log the line and the reason.
 ///
 pub struct OrderReader {
     orders: Vec<Order>,
     file_name: String,
     payments_gateway: Recipient<ReceiveOrders>,
+    watcher: OrderWatcher,
+    watching: bool,
 }
 
 impl OrderReader {
     pub fn new(file_name: String, payments_gateway: Recipient<ReceiveOrders>) -> OrderReader {
         OrderReader {
             orders: Vec::new(),
+            watcher: OrderWatcher::new(&file_name),
             file_name,
             payments_gateway,
+            watching: false,
+        }
+    }
+
+    /// Sends the orders added to the watched files since the last time
+    fn send_new_orders(&mut self) {
+        let orders = self.watcher.new_orders();
+        if orders.is_empty() {
+            return;
+        }
+        let line = format!("{} new orders in {}", orders.len(), self.file_name);
+        log::info("OR", line.purple());
+        if self
+            .payments_gateway
+            .try_send(ReceiveOrders::new(orders))
+            .is_err()
+        {
+            log::error("OR", "Error sending orders to PaymentsGateway");
         }
     }
 }
@@ -45,6 +78,131 @@ impl Actor for OrderReader {
     type Context = Context<Self>;
 }
 
+/// Result of checking a file of orders without sending them
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Number of valid orders
+    pub orders: usize,
+    /// Number of the line and the reason of every line that is not a valid order
+    pub malformed: Vec<(usize, String)>,
+    /// Grams asked of each flavor by the valid orders
+    pub grams: HashMap<FlavorID, usize>,
+}
+
+impl ValidationReport {
+    /// Returns the flavors whose grams asked exceed the stock of the catalog, with the grams asked and the stock
+    pub fn over_stock(&self, catalog: &FlavorCatalog) -> Vec<(FlavorID, usize, usize)> {
+        let mut over: Vec<(FlavorID, usize, usize)> = self
+            .grams
+            .iter()
+            .map(|(flavor, grams)| {
+                let stock = catalog.initial_amount(flavor).unwrap_or(0);
+                (flavor.clone(), *grams, stock)
+            })
+            .filter(|(_, grams, stock)| grams > stock)
+            .collect();
+        over.sort_by_key(|(flavor, _, _)| flavor.to_string());
+        over
+    }
+
+    /// Returns true if every line is a valid order and there is stock for all of them
+    pub fn is_valid(&self, catalog: &FlavorCatalog) -> bool {
+        self.malformed.is_empty() && self.over_stock(catalog).is_empty()
+    }
+}
+
+/// An order of a file with the time it arrives, in milliseconds since the first order, if the file has it.
+/// A line with the time is written as `{"arrival_ms":120,"order":{"Cucurucho":["Mint",250]}}`,
+/// the screens send it like any other order and the benchmark submits it at that time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimedOrder {
+    pub arrival_ms: Option<u64>,
+    pub order: Order,
+}
+
+/// A line of an order file that has the time the order arrives
+#[derive(Serialize, Deserialize)]
+struct TimedLine {
+    arrival_ms: u64,
+    order: Order,
+}
+
+impl TimedOrder {
+    pub fn new(order: Order, arrival_ms: Option<u64>) -> Self {
+        Self { arrival_ms, order }
+    }
+
+    /// Returns the line of the order file, just the order if it has no time
+    pub fn to_line(&self) -> String {
+        let json = match self.arrival_ms {
+            Some(arrival_ms) => serde_json::to_string(&TimedLine {
+                arrival_ms,
+                order: self.order.clone(),
+            }),
+            None => serde_json::to_string(&self.order),
+        };
+        json.unwrap_or_default()
+    }
+}
+
+/// Parses a line of an order file, with or without the time the order arrives.
+/// An order that can not be prepared as it is is an error too
+pub fn parse_timed_order_line(line: &str) -> Result<TimedOrder, String> {
+    let timed = match serde_json::from_str::<Order>(line) {
+        Ok(order) => TimedOrder::new(order, None),
+        Err(err) => match serde_json::from_str::<TimedLine>(line) {
+            Ok(timed) => TimedOrder::new(timed.order, Some(timed.arrival_ms)),
+            Err(_) => return Err(err.to_string()),
+        },
+    };
+    timed.order.validate().map_err(|err| err.to_string())?;
+    Ok(timed)
+}
+
+/// Parses a line of an order file, an order that can not be prepared as it is is an error too
+pub fn parse_order_line(line: &str) -> Result<Order, String> {
+    parse_timed_order_line(line).map(|timed| timed.order)
+}
+
+/// Reads every order of the file with the time it arrives, the lines that are not valid orders are errors
+pub fn read_timed_orders(file_name: &str) -> Result<Vec<TimedOrder>, String> {
+    let contents = std::fs::read_to_string(file_name)
+        .map_err(|e| format!("Could not read {}: {}", file_name, e))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_timed_order_line(line)
+                .map_err(|e| format!("Line {} of {}: {}", i + 1, file_name, e))
+        })
+        .collect()
+}
+
+impl OrderReader {
+    /// Parses the whole file, reporting the lines that are not valid orders instead of skipping them
+    pub fn validate(file_name: &str) -> Result<ValidationReport, std::io::Error> {
+        let reader = BufReader::new(File::open(file_name)?);
+        let mut report = ValidationReport::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_order_line(&line) {
+                Ok(order) => {
+                    report.orders += 1;
+                    for (flavor, grams) in order.get_flavors() {
+                        *report.grams.entry(flavor).or_insert(0) += grams;
+                    }
+                }
+                Err(e) => report.malformed.push((i + 1, e)),
+            }
+        }
+        Ok(report)
+    }
+}
+
 // /// ReadOrders is a message that tells the OrderReader actor to read the orders from the file.
 // struct ReadOrders();
 
@@ -55,22 +213,145 @@ pub struct ReadOrders();
 impl Handler<ReadOrders> for OrderReader {
     type Result = Result<Vec<Order>, std::io::Error>;
 
-    fn handle(&mut self, _msg: ReadOrders, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, _msg: ReadOrders, ctx: &mut Context<Self>) -> Self::Result {
+        let orders = &config::get().orders;
+        if orders.stream_chunk > 0 {
+            File::open(&self.file_name)?;
+            ctx.notify(StreamOrders::new(
+                orders.stream_chunk,
+                orders.stream_max_waiting,
+            ));
+            return Ok(Vec::new());
+        }
         let file = File::open(&self.file_name)?;
         let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(order) = serde_json::from_str(&line?) {
-                self.orders.push(order);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_order_line(&line) {
+                Ok(order) => self.orders.push(order),
+                Err(e) => {
+                    let line = format!("Line {} of {} skipped: {}", i + 1, self.file_name, e);
+                    log::warn("OR", line.yellow());
+                }
             }
         }
-        match _ctx.address().try_send(SendOrdersToPaymentsGateway()) {
+        self.watcher.skip_current();
+        match ctx.address().try_send(SendOrdersToPaymentsGateway()) {
             Ok(_) => (),
-            Err(_) => println!("Error sending orders to PaymentsGateway"),
+            Err(_) => log::error("OR", "Error sending orders to PaymentsGateway"),
         };
         Ok(self.orders.clone())
     }
 }
 
+/// StreamOrders is a message that tells the OrderReader actor to send the orders of the file `chunk` at a time,
+/// so the whole file is never in memory nor in the mailbox of the PaymentsGateway.
+/// Each chunk is sent once the PaymentsGateway answers that it has no more than `max_waiting` orders waiting,
+/// so no more are read while it is behind. The number of orders sent is returned.
+#[derive(Message)]
+#[rtype(result = "Result<usize, std::io::Error>")]
+pub struct StreamOrders {
+    chunk: usize,
+    max_waiting: usize,
+}
+
+impl StreamOrders {
+    pub fn new(chunk: usize, max_waiting: usize) -> StreamOrders {
+        StreamOrders {
+            chunk: chunk.max(1),
+            max_waiting,
+        }
+    }
+}
+
+/// Reads the file a chunk at a time and sends each one to the PaymentsGateway, waiting for it to have room
+async fn stream_orders(
+    file_name: String,
+    payments_gateway: Recipient<ReceiveOrders>,
+    msg: StreamOrders,
+) -> Result<usize, std::io::Error> {
+    let file = tokio::fs::File::open(&file_name).await?;
+    let mut lines = tokio::io::BufReader::new(file).lines();
+    let (mut number, mut sent) = (0, 0);
+    loop {
+        let mut chunk = Vec::with_capacity(msg.chunk);
+        while chunk.len() < msg.chunk {
+            let Some(line) = lines.next_line().await? else {
+                break;
+            };
+            number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_order_line(&line) {
+                Ok(order) => chunk.push(order),
+                Err(e) => {
+                    let line = format!("Line {} of {} skipped: {}", number, file_name, e);
+                    log::warn("OR", line.yellow());
+                }
+            }
+        }
+        if chunk.is_empty() {
+            return Ok(sent);
+        }
+        sent += chunk.len();
+        payments_gateway
+            .send(ReceiveOrders::new(chunk).waiting_for_room(msg.max_waiting))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))??;
+    }
+}
+
+impl Handler<StreamOrders> for OrderReader {
+    type Result = ResponseActFuture<Self, Result<usize, std::io::Error>>;
+
+    fn handle(&mut self, msg: StreamOrders, _ctx: &mut Context<Self>) -> Self::Result {
+        let line = format!("Streaming {} by {} orders", self.file_name, msg.chunk);
+        log::info("OR", line.purple());
+        let stream = stream_orders(self.file_name.clone(), self.payments_gateway.clone(), msg);
+        Box::pin(stream.into_actor(self).map(|sent, actor, _| {
+            actor.watcher.skip_current();
+            match &sent {
+                Ok(sent) => {
+                    let line = format!("{} orders of {} streamed", sent, actor.file_name);
+                    log::info("OR", line.purple());
+                }
+                Err(e) => {
+                    let line = format!("Streaming {} stopped: {}", actor.file_name, e);
+                    log::error("OR", line);
+                }
+            }
+            sent
+        }))
+    }
+}
+
+/// WatchOrders is a message that tells the OrderReader actor to keep looking for new orders in the file,
+/// or in the files of the directory, and send them to the PaymentsGateway actor as they are added.
+/// The orders already processed with ReadOrders are not sent again.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchOrders();
+
+impl Handler<WatchOrders> for OrderReader {
+    type Result = ();
+
+    fn handle(&mut self, _msg: WatchOrders, ctx: &mut Context<Self>) -> Self::Result {
+        if self.watching {
+            return;
+        }
+        self.watching = true;
+        let line = format!("Watching {} for new orders", self.file_name);
+        log::info("OR", line.purple());
+        self.send_new_orders();
+        let interval = Duration::from_millis(config::get().orders.watch_interval_ms);
+        ctx.run_interval(interval, |actor, _| actor.send_new_orders());
+    }
+}
+
 /// SendOrdersToPaymentsGateway is a message that tells the OrderReader actor to send the orders to the PaymentsGateway actor.
 #[derive(Message)]
 #[rtype(result = "Vec<Order>")]
@@ -89,7 +370,7 @@ impl Handler<SendOrdersToPaymentsGateway> for OrderReader {
             .try_send(ReceiveOrders::new(self.orders.clone()))
         {
             Ok(_) => (),
-            Err(_) => println!("Error sending orders to PaymentsGateway"),
+            Err(_) => log::error("OR", "Error sending orders to PaymentsGateway"),
         };
         self.orders.clone()
     }
@@ -98,7 +379,9 @@ impl Handler<SendOrdersToPaymentsGateway> for OrderReader {
 #[cfg(test)]
 mod tests {
 
-    use crate::{common::flavor_id::FlavorID, screen::payments_gateway::PaymentsGateway};
+    use crate::config::flavors::FlavorStock;
+    use crate::ordergen::{self, OrderGenOptions};
+    use crate::screen::payments_gateway::{CaptureNewOrder, GetOrdersWaiting, PaymentsGateway};
 
     use super::*;
 
@@ -174,6 +457,106 @@ mod tests {
         );
     }
 
+    #[actix::test]
+    async fn test_order_reader_streams_a_file_in_chunks() {
+        let options = OrderGenOptions {
+            count: 25,
+            ..OrderGenOptions::default()
+        };
+        let generated = ordergen::generate(&options, &FlavorCatalog::default()).unwrap();
+        let file_name = std::env::temp_dir().join(format!("stream_{}.txt", std::process::id()));
+        let file_name = file_name.to_string_lossy().to_string();
+        ordergen::write_orders(&generated, &file_name).unwrap();
+
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let order_reader =
+            OrderReader::new(file_name.clone(), payments_gateway.clone().recipient()).start();
+        let sent = order_reader.send(StreamOrders::new(4, 100)).await.unwrap();
+        let _ = std::fs::remove_file(&file_name);
+        assert_eq!(sent.unwrap(), 25);
+
+        // the gateway of the tests captures the oldest order of each chunk it takes
+        let waiting = payments_gateway.send(GetOrdersWaiting()).await.unwrap();
+        let expected: Vec<Order> = generated[7..].iter().map(|t| t.order.clone()).collect();
+        assert_eq!(waiting, expected);
+    }
+
+    #[actix::test]
+    async fn test_stream_waits_for_the_gateway_to_have_room() {
+        let options = OrderGenOptions {
+            count: 8,
+            ..OrderGenOptions::default()
+        };
+        let generated = ordergen::generate(&options, &FlavorCatalog::default()).unwrap();
+        let file_name = std::env::temp_dir().join(format!("room_{}.txt", std::process::id()));
+        let file_name = file_name.to_string_lossy().to_string();
+        ordergen::write_orders(&generated, &file_name).unwrap();
+
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let order_reader =
+            OrderReader::new(file_name.clone(), payments_gateway.clone().recipient()).start();
+        let mut stream = Box::pin(order_reader.send(StreamOrders::new(4, 2)));
+        let capture = || CaptureNewOrder::new(0.5, "id".to_string());
+        let pause = Duration::from_millis(100);
+
+        // the gateway of the tests captures one order of the chunk, one more is needed to make room
+        assert!(tokio::time::timeout(pause, &mut stream).await.is_err());
+        let waiting = payments_gateway.send(GetOrdersWaiting()).await.unwrap();
+        assert_eq!(waiting.len(), 3);
+
+        payments_gateway.send(capture()).await.unwrap();
+        assert!(tokio::time::timeout(pause, &mut stream).await.is_err());
+        let waiting = payments_gateway.send(GetOrdersWaiting()).await.unwrap();
+        assert_eq!(waiting.len(), 5);
+
+        for _ in 0..3 {
+            payments_gateway.send(capture()).await.unwrap();
+        }
+        let sent = tokio::time::timeout(pause, &mut stream).await;
+        let _ = std::fs::remove_file(&file_name);
+        assert_eq!(sent.unwrap().unwrap().unwrap(), 8);
+    }
+
+    #[test]
+    fn test_validation_reports_malformed_lines_and_grams() {
+        let report =
+            OrderReader::validate("./src/orders_samples/order_sample_invalid.txt").unwrap();
+        assert_eq!(report.orders, 1);
+        assert_eq!(report.malformed.len(), 1);
+        assert_eq!(report.malformed[0].0, 1);
+        assert_eq!(report.grams.get(&FlavorID::Chocolate), Some(&125));
+
+        let catalog = FlavorCatalog::new(vec![FlavorStock::new(FlavorID::Chocolate, 100)]);
+        assert_eq!(
+            report.over_stock(&catalog),
+            vec![(FlavorID::Chocolate, 125, 100), (FlavorID::Vanilla, 125, 0)]
+        );
+        assert!(!report.is_valid(&catalog));
+    }
+
+    #[actix::test]
+    async fn test_order_reader_reads_a_generated_file() {
+        let options = OrderGenOptions {
+            count: 25,
+            rate: Some(5.0),
+            ..OrderGenOptions::default()
+        };
+        let generated = ordergen::generate(&options, &FlavorCatalog::default()).unwrap();
+        let file_name = std::env::temp_dir().join(format!("ordergen_{}.txt", std::process::id()));
+        let file_name = file_name.to_string_lossy().to_string();
+        ordergen::write_orders(&generated, &file_name).unwrap();
+
+        let payments_gateway_recipient = PaymentsGateway::new(0).start().recipient();
+        let order_reader = OrderReader::new(file_name.clone(), payments_gateway_recipient).start();
+        let orders = order_reader.send(ReadOrders()).await.unwrap().unwrap();
+        let timed = read_timed_orders(&file_name).unwrap();
+        let _ = std::fs::remove_file(&file_name);
+
+        let expected: Vec<Order> = generated.iter().map(|t| t.order.clone()).collect();
+        assert_eq!(orders, expected);
+        assert_eq!(timed, generated);
+    }
+
     #[actix::test]
     async fn test_order_reader_sends_orders_to_payments_gateway_correctly() {
         let payments_gateway_recipient = PaymentsGateway::new(0).start().recipient();
@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::common::log;
+use crate::common::order::Order;
+use crate::screen::order_reader::parse_order_line;
+
+/// Follows a file of orders, or every file of a directory, remembering how much of each file was already read.
+/// Only complete lines are read, a line that is still being written is left for the next time.
+/// A file that gets shorter is read again from the start, since it was replaced.
+pub struct OrderWatcher {
+    path: PathBuf,
+    offsets: HashMap<PathBuf, u64>,
+}
+
+impl OrderWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            offsets: HashMap::new(),
+        }
+    }
+
+    /// Marks everything that the files have now as read
+    pub fn skip_current(&mut self) {
+        for file in self.files() {
+            if let Ok(metadata) = fs::metadata(&file) {
+                self.offsets.insert(file, metadata.len());
+            }
+        }
+    }
+
+    /// Returns the orders added since the last time, files that can not be read are skipped until they can
+    pub fn new_orders(&mut self) -> Vec<Order> {
+        let mut orders = Vec::new();
+        for file in self.files() {
+            if let Ok(mut new) = self.read_new_lines(&file) {
+                orders.append(&mut new);
+            }
+        }
+        orders
+    }
+
+    /// Returns the watched file, or the files of the watched directory sorted by name
+    fn files(&self) -> Vec<PathBuf> {
+        if !self.path.is_dir() {
+            return vec![self.path.clone()];
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_file())
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    /// Reads the complete lines added to the file after its offset, the invalid orders are skipped and logged
+    fn read_new_lines(&mut self, file: &Path) -> io::Result<Vec<Order>> {
+        let mut reader = File::open(file)?;
+        let len = reader.metadata()?.len();
+        let mut offset = self.offsets.get(file).copied().unwrap_or(0);
+        if len < offset {
+            offset = 0;
+        }
+
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let complete = match bytes.iter().rposition(|&byte| byte == b'\n') {
+            Some(last) => &bytes[..=last],
+            None => return Ok(Vec::new()),
+        };
+        self.offsets
+            .insert(file.to_path_buf(), offset + complete.len() as u64);
+
+        Ok(String::from_utf8_lossy(complete)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match parse_order_line(line) {
+                Ok(order) => Some(order),
+                Err(e) => {
+                    let line = format!("Order of {} skipped: {}", file.display(), e);
+                    log::warn("OR", line);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use std::io::Write;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("freddo_watch_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(file: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn order_line(flavor: FlavorID) -> String {
+        serde_json::to_string(&Order::new_cucurucho(flavor)).unwrap()
+    }
+
+    #[test]
+    fn test_only_new_complete_lines_are_read() {
+        let dir = temp_dir("file");
+        let file = dir.join("orders.txt");
+        append(&file, &format!("{}\n", order_line(FlavorID::Mint)));
+        let mut watcher = OrderWatcher::new(&file);
+
+        assert_eq!(
+            watcher.new_orders(),
+            vec![Order::new_cucurucho(FlavorID::Mint)]
+        );
+        assert!(watcher.new_orders().is_empty());
+
+        let lemon = order_line(FlavorID::Lemon);
+        let (start, end) = lemon.split_at(5);
+        append(&file, start);
+        assert!(watcher.new_orders().is_empty());
+        append(&file, &format!("{}\n", end));
+        assert_eq!(
+            watcher.new_orders(),
+            vec![Order::new_cucurucho(FlavorID::Lemon)]
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_new_files_of_the_directory_are_read() {
+        let dir = temp_dir("dir");
+        append(
+            &dir.join("a.txt"),
+            &format!("{}\n", order_line(FlavorID::Mint)),
+        );
+        let mut watcher = OrderWatcher::new(&dir);
+        watcher.skip_current();
+        assert!(watcher.new_orders().is_empty());
+
+        append(
+            &dir.join("b.txt"),
+            &format!("{}\n", order_line(FlavorID::Lemon)),
+        );
+        assert_eq!(
+            watcher.new_orders(),
+            vec![Order::new_cucurucho(FlavorID::Lemon)]
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,88 @@
+//! Decides which cards the payments gateway declines and how long charging them takes.
+//! Besides the random declines, a scenario can decline every few cards or the orders with some ids,
+//! so the tests and the demos can make the payments fail when they need to.
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::HashSet;
+use tokio::time::Duration;
+
+use crate::config::payments::PaymentsConfig;
+
+/// How the cards of a screen are charged
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentScenario {
+    decline_rate: f64,
+    processing: Duration,
+    decline_every: usize,
+    decline_ids: HashSet<String>,
+    charged: usize,
+}
+
+impl PaymentScenario {
+    pub fn new(config: &PaymentsConfig) -> Self {
+        Self {
+            decline_rate: config.decline_rate,
+            processing: Duration::from_millis(config.processing_ms),
+            decline_every: config.decline_every,
+            decline_ids: config.decline_ids.iter().cloned().collect(),
+            charged: 0,
+        }
+    }
+
+    /// A scenario that never declines a card by chance, only the ones it is told to
+    pub fn scripted(decline_every: usize, decline_ids: &[&str]) -> Self {
+        Self::new(&PaymentsConfig {
+            decline_rate: 0.0,
+            decline_every,
+            decline_ids: decline_ids.iter().map(|id| id.to_string()).collect(),
+            ..PaymentsConfig::default()
+        })
+    }
+
+    /// Time it takes to charge a card
+    pub fn processing(&self) -> Duration {
+        self.processing
+    }
+
+    /// Charges the card of an order, and returns true if it is declined.
+    /// The generator is drawn for every card, so a seeded gateway declines the same ones with or without a script
+    pub fn declines(&mut self, id: &str, rng: &mut StdRng) -> bool {
+        self.charged += 1;
+        let by_chance = rng.gen_range(0.0..1.0) <= self.decline_rate && self.decline_rate > 0.0;
+        let nth = self.decline_every > 0 && self.charged.is_multiple_of(self.decline_every);
+        by_chance || nth || self.decline_ids.contains(id)
+    }
+}
+
+impl Default for PaymentScenario {
+    fn default() -> Self {
+        Self::new(&PaymentsConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::simulation;
+
+    #[test]
+    fn test_scripted_scenario_declines_only_what_it_is_told() {
+        let mut scenario = PaymentScenario::scripted(3, &["0-2"]);
+        let mut rng = simulation::seeded_rng(7, "GTW", 0);
+        let declined: Vec<bool> = (1..=7)
+            .map(|n| scenario.declines(&format!("0-{}", n), &mut rng))
+            .collect();
+        assert_eq!(declined, vec![false, true, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn test_every_card_is_declined_with_rate_one() {
+        let mut scenario = PaymentScenario::new(&PaymentsConfig {
+            decline_rate: 1.0,
+            ..PaymentsConfig::default()
+        });
+        let mut rng = simulation::seeded_rng(7, "GTW", 0);
+        assert!((0..20).all(|n| scenario.declines(&n.to_string(), &mut rng)));
+    }
+}
@@ -1,52 +1,170 @@
+use crate::common::error::FreddoError;
+use crate::common::flavor_id::FlavorID;
+use crate::common::health;
+use crate::common::log::{self, Level};
+use crate::common::mailbox::{self, Probe};
 use actix::prelude::*;
-use rand::Rng;
+use rand::rngs::StdRng;
 
 use super::{
     robot_connection_handler::{
-        RobotConnectionHandler, SendOrderToRobotLeader, SendRequestToRobotLeader,
+        RobotConnectionHandler, SendOrderPickedUp, SendOrderToRobotLeader, SendQueryMenu,
+        SendQueryStatus, SendRequestToRobotLeader, SendScreenRejoined,
     },
+    screen_connection_listener::ScreenConnectionListener,
     screen_connection_sender::{
-        RequestRobotLeaderConnection, ScreenConnectionSender, SendMyBackup,
+        RequestRobotLeaderConnection, ScreenConnectionSender, SendGossip, SendMyBackup,
     },
 };
-use crate::common::order::Order;
+use crate::common::metrics;
+use crate::common::order::{Order, Substitution};
+use crate::common::order_source::{OrderChannel, OrderSource};
+use crate::common::simulation;
+use crate::common::utils::{retry_backoff, should_retry};
+use crate::config;
+use crate::protocol::screen_messages::BackupView;
+use crate::screen::communication::{connect_following_and_notify_previous, connect_to_leader};
+use crate::screen::failover_drill::{CloseConnection, Drill, DrillReport, RECLAIM_TIMEOUT};
+use crate::screen::membership::Membership;
+use crate::screen::menu::Menu;
+use crate::screen::order_api::OrderStatus;
+use crate::screen::order_history::{self, HistoryEntry, HistoryEvent, OrderRecord, OrderStore};
+use crate::screen::order_ids::OrderIds;
+use crate::screen::order_queue::OrderQueue;
+use crate::screen::payment_scenario::PaymentScenario;
+use crate::screen::pickup::{Pickup, PickupBoard};
 use crate::screen::robot_connection_handler::AskRobotForScreenOrders;
+use crate::screen::webhook;
 use actix::prelude::AsyncContext;
 use colored::Colorize;
-use std::collections::HashMap;
-#[cfg(not(test))]
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
 use tokio::time::Duration;
-use uuid::Uuid;
+
+/// How many orders that finished the screen keeps the status of, the oldest ones are forgotten first
+pub const FINISHED_STATUSES: usize = 1000;
+
 /// PaymentsGateway is an actor that is in charge of capturing the orders and processing the payments.
 /// This actor will receive the orders from the OrderReader actor and will capture them one by one.
 /// After the order is prepared, it will confirm the payment.
+/// If the result of a captured order does not arrive in time, the order is sent again to the robot leader, and after a few retries the payment is aborted.
+/// If the robot leader is too busy to take an order, it is sent again after a while, and after a few rejections the payment is aborted.
+/// The orders submitted through the API are captured first, with the id given to the caller, and their status is kept so it can be asked.
+/// It gossips to the next screen the screens it knows are alive and the leader, and connects to the leader it hears of if it has no connection with one.
+/// When the connection with the leader is lost, or orders are pending without one, it keeps trying to connect to the leader it knows
+/// and asks the next screen to have the leader connect to it, until a leader is connected again.
+/// The captures, confirmations and aborts are kept in the order history, so they can be asked after a restart.
+/// The confirmations and aborts are also posted to the webhook, if there is one.
+/// An order aborted after its payment was confirmed gets a refund, which is kept in the history so it is given only once.
+/// During a failover drill it plays dead: it closes its connections and captures nothing until it rejoins.
+/// An order with a flavor off the menu of the screen is turned away before it is captured.
+/// Which cards are declined, and how long charging them takes, is up to its payment scenario.
+/// A confirmed order gets a pickup code, and when it is picked up the leader is told.
 pub struct PaymentsGateway {
     id: usize,
-    orders_waiting: Vec<Order>,
+    orders_waiting: OrderQueue,
+    room_waiters: Vec<(usize, oneshot::Sender<usize>)>,
+    orders_submitted: VecDeque<(String, Order)>,
+    sources: HashMap<String, OrderSource>,
+    order_statuses: HashMap<String, OrderStatus>,
+    finished: VecDeque<String>,
     orders_captured: HashMap<String, Order>,
+    retries: HashMap<String, usize>,
+    busy_retries: HashMap<String, usize>,
     robot_connection_handler: Option<Addr<RobotConnectionHandler>>,
     screen_connection_sender: Option<Addr<ScreenConnectionSender>>,
+    screen_connection_listener: Option<Addr<ScreenConnectionListener>>,
     orders_pending_to_prepare: Vec<(String, Order)>,
+    leader_epoch: u64,
+    membership: Membership,
+    connecting_to_leader: bool,
+    reconnecting: bool,
+    history: Box<dyn OrderStore>,
+    order_ids: OrderIds,
+    backup_version: u64,
+    backups_taken: HashMap<usize, u64>,
+    backup_scheduled: bool,
+    rng: StdRng,
+    drill: Option<Drill>,
+    menu: Menu,
+    payments: PaymentScenario,
+    pickups: PickupBoard,
 }
 
 impl PaymentsGateway {
     pub fn new(id: usize) -> PaymentsGateway {
+        let history = order_history::store_for(id);
+        let number = config::get().cluster.screen_number(id);
+        let order_ids = OrderIds::new(number, config::get().orders.id_scheme)
+            .resume_after(&history.load().unwrap_or_default());
         PaymentsGateway {
             id,
-            orders_waiting: Vec::new(),
+            orders_waiting: OrderQueue::new(),
+            room_waiters: Vec::new(),
+            orders_submitted: VecDeque::new(),
+            sources: HashMap::new(),
+            order_statuses: HashMap::new(),
+            finished: VecDeque::new(),
             orders_captured: HashMap::new(),
+            retries: HashMap::new(),
+            busy_retries: HashMap::new(),
             orders_pending_to_prepare: Vec::new(),
             robot_connection_handler: None,
             screen_connection_sender: None,
+            screen_connection_listener: None,
+            leader_epoch: 0,
+            membership: Membership::new(id),
+            connecting_to_leader: false,
+            reconnecting: false,
+            history,
+            order_ids,
+            backup_version: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            backups_taken: HashMap::new(),
+            backup_scheduled: false,
+            rng: simulation::rng("GTW", id),
+            drill: None,
+            menu: Menu::default(),
+            payments: PaymentScenario::new(&config::get().payments),
+            pickups: PickupBoard::new(number),
         }
     }
 
+    /// Replaces the random generator used to decline cards, so the captures can be reproduced
+    pub fn with_rng(mut self, rng: StdRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Offers only the flavors of the menu
+    pub fn with_menu(mut self, menu: Menu) -> Self {
+        self.menu = menu;
+        self
+    }
+
+    /// Charges the cards as the scenario says, instead of as the configuration does
+    pub fn with_payments(mut self, payments: PaymentScenario) -> Self {
+        self.payments = payments;
+        self
+    }
+
+    /// Returns a new id for an order, one the screen is not using
+    fn new_order_id(&mut self) -> String {
+        let statuses = &self.order_statuses;
+        let captured = &self.orders_captured;
+        self.order_ids
+            .next(|id| statuses.contains_key(id) || captured.contains_key(id))
+    }
+
     /// This method will send a ProcessNewOrder message to itself.
     fn process_new_order(&mut self, _ctx: &mut Context<PaymentsGateway>) {
         #[cfg(not(test))]
         match _ctx.address().try_send(ProcessNewOrder()) {
             Ok(_) => (),
-            Err(_) => println!("Error sending ProcessNewOrder"),
+            Err(_) => log::error("GTW", "Error sending ProcessNewOrder"),
         };
     }
 
@@ -68,13 +186,194 @@ impl PaymentsGateway {
                 }
             }
         } else if let Some(handler) = self.robot_connection_handler.clone() {
-            handler.do_send(SendOrderToRobotLeader::new(order, id.clone(), self.id));
+            let source = self.sources.get(&id).cloned();
+            handler.do_send(SendOrderToRobotLeader::new(order, id, self.id, source));
+        }
+    }
+
+    /// Takes the first order waiting to be captured, with where and when it got to the screen
+    fn take_waiting(&mut self) -> Option<(Order, OrderSource)> {
+        let taken = self.orders_waiting.pop_front();
+        self.release_room();
+        taken
+    }
+
+    /// Answers the senders of orders that were waiting for the queue to go down to their room
+    fn release_room(&mut self) {
+        let waiting = self.orders_waiting.len();
+        let (ready, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.room_waiters)
+            .into_iter()
+            .partition(|(room, _)| waiting <= *room);
+        self.room_waiters = pending;
+        for (_, waiter) in ready {
+            let _ = waiter.send(waiting);
+        }
+    }
+
+    /// Returns true if there is an open connection with the robot leader
+    fn leader_connected(&self) -> bool {
+        self.robot_connection_handler
+            .as_ref()
+            .is_some_and(|handler| handler.connected())
+    }
+
+    /// Returns true while the screen plays dead in a failover drill
+    fn down(&self) -> bool {
+        self.drill.as_ref().is_some_and(|drill| drill.down)
+    }
+
+    /// Sends what the screen knows of the cluster to the next screen
+    fn gossip(&mut self) {
+        if self.down() {
+            return;
+        }
+        let timeout = Duration::from_millis(config::get().gossip.member_timeout_ms);
+        let members = self.membership.tick(timeout);
+        if let Some(sender) = self.screen_connection_sender.as_ref() {
+            sender.do_send(SendGossip::new(self.id, members, self.membership.leader()));
+        }
+    }
+
+    /// Connects to the leader known by the gossip, if the screen has no connection with a leader
+    fn connect_to_known_leader(&mut self, ctx: &mut Context<Self>) {
+        let leader_id = match self.membership.leader() {
+            Some((leader_id, _)) => leader_id,
+            None => return,
+        };
+        if self.connecting_to_leader || self.leader_connected() || self.down() {
+            return;
         }
+        self.connecting_to_leader = true;
+        let output = format!("Connecting to the robot leader {} heard of", leader_id);
+        log::info("GTW", output.bright_cyan());
+        connect_to_leader(leader_id, self.id, ctx.address())
+            .into_actor(self)
+            .map(move |result, actor, _| {
+                actor.connecting_to_leader = false;
+                if let Err(e) = result {
+                    let output =
+                        format!("Could not connect to the robot leader {}: {}", leader_id, e);
+                    log::warn("GTW", output);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Tries to get back a connection with the robot leader while the screen has none.
+    /// The leader it knows is tried following the retry policy, and once the attempts run out, or if no leader is known,
+    /// the next screen is asked to have the leader connect to this one and the attempts start over after a wait
+    fn reconnect_to_leader(&mut self, attempt: u32, ctx: &mut Context<Self>) {
+        if self.leader_connected() || self.down() {
+            self.reconnecting = false;
+            return;
+        }
+        self.reconnecting = true;
+        let known_leader = self
+            .membership
+            .leader()
+            .map(|(leader_id, _)| leader_id)
+            .filter(|_| should_retry(attempt));
+        let leader_id = match known_leader {
+            Some(_) if self.connecting_to_leader => {
+                ctx.run_later(retry_backoff(attempt), move |actor, ctx| {
+                    actor.reconnect_to_leader(attempt, ctx)
+                });
+                return;
+            }
+            Some(leader_id) => leader_id,
+            None => {
+                log::debug("GTW", "Asking the next screen for the robot leader");
+                if let Some(sender) = self.screen_connection_sender.as_ref() {
+                    sender.do_send(RequestRobotLeaderConnection::new(self.id));
+                }
+                ctx.run_later(retry_backoff(attempt), |actor, ctx| {
+                    actor.reconnect_to_leader(0, ctx)
+                });
+                return;
+            }
+        };
+        self.connecting_to_leader = true;
+        let output = format!(
+            "Reconnecting to the robot leader {} (attempt {})",
+            leader_id,
+            attempt + 1
+        );
+        log::info("GTW", output.bright_cyan());
+        connect_to_leader(leader_id, self.id, ctx.address())
+            .into_actor(self)
+            .map(move |result, actor, ctx| {
+                actor.connecting_to_leader = false;
+                match result {
+                    Ok(()) => actor.reconnecting = false,
+                    Err(e) => {
+                        let output = format!(
+                            "Could not reconnect to the robot leader {}: {}",
+                            leader_id, e
+                        );
+                        log::warn("GTW", output);
+                        metrics::get().retried("screen_leader_link");
+                        ctx.run_later(retry_backoff(attempt), move |actor, ctx| {
+                            actor.reconnect_to_leader(attempt + 1, ctx)
+                        });
+                    }
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Starts reconnecting to the leader if there are orders waiting to be sent and no connection to send them
+    fn check_leader_link(&mut self, ctx: &mut Context<Self>) {
+        if self.reconnecting || self.orders_pending_to_prepare.is_empty() || self.leader_connected()
+        {
+            return;
+        }
+        log::warn("GTW", "Orders are pending without a robot leader");
+        self.reconnect_to_leader(0, ctx);
+    }
+
+    /// Returns a new version of the backup of this screen, the orders waiting, captured and pending to prepare,
+    /// to be kept by the next `backup.screen_replicas` screens. It is encoded from the orders the gateway keeps.
+    /// The versions start at the time the gateway starts, so they keep growing after a restart
+    fn my_backup(&mut self) -> Option<SendMyBackup> {
+        self.backup_version += 1;
+        let backup = BackupView {
+            orders_to_process: self.orders_waiting.orders().collect(),
+            orders_processing: &self.orders_captured,
+            orders_pending_to_send: &self.orders_pending_to_prepare,
+            id_backup: self.id,
+            version: self.backup_version,
+            hops_left: config::get().backup.screen_replicas - 1,
+            sender: self.id,
+        };
+        match SendMyBackup::new(&backup) {
+            Ok(backup) => Some(backup),
+            Err(e) => {
+                log::error("GTW", format!("Error encoding the backup: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Sends a backup with the changes made within `backup.screen_coalesce_ms`, so a burst of orders
+    /// does not copy the whole state of the screen once per order
+    fn send_backup(&mut self, ctx: &mut Context<PaymentsGateway>) {
+        let window = config::get().backup.screen_coalesce_ms;
+        if window == 0 {
+            self.flush_backup();
+            return;
+        }
+        if self.backup_scheduled {
+            return;
+        }
+        self.backup_scheduled = true;
+        ctx.run_later(Duration::from_millis(window), |gateway, _ctx| {
+            gateway.backup_scheduled = false;
+            gateway.flush_backup();
+        });
     }
 
-    /// This method will send a backup to the screen connection sender.
-    /// It will send the orders_waiting, orders_captured and orders_pending_to_prepare vectors.
-    fn send_backup(&mut self) {
+    /// Sends a backup to the screen connection sender right away.
+    fn flush_backup(&mut self) {
         if self.screen_connection_sender.is_none()
             || !self
                 .screen_connection_sender
@@ -84,60 +383,337 @@ impl PaymentsGateway {
         {
             return;
         }
-        self.screen_connection_sender
-            .clone()
-            .expect("This should never happen")
-            .do_send(SendMyBackup::new(
-                self.orders_waiting.clone(),
-                self.orders_captured.clone(),
-                self.orders_pending_to_prepare.clone(),
-                self.id,
-            ));
+        if let Some(backup) = self.my_backup() {
+            self.screen_connection_sender
+                .clone()
+                .expect("This should never happen")
+                .do_send(backup);
+        }
+    }
+
+    /// Starts the timer that waits for the result of a captured order, if it is enabled in the config
+    fn start_result_timer(&mut self, id: String, ctx: &mut Context<PaymentsGateway>) {
+        let timeout = config::get().orders.result_timeout_secs;
+        if timeout == 0 {
+            return;
+        }
+        let attempt = *self.retries.entry(id.clone()).or_insert(0);
+        ctx.notify_later(
+            OrderResultTimeout { id, attempt },
+            Duration::from_secs(timeout),
+        );
+    }
+
+    /// Updates the status of an order submitted through the API, the other orders are not tracked.
+    /// Only the last `FINISHED_STATUSES` orders that finished are remembered
+    fn set_status(&mut self, id: &str, status: OrderStatus) {
+        let Some(current) = self.order_statuses.get_mut(id) else {
+            return;
+        };
+        if status.is_final() && !current.is_final() {
+            self.finished.push_back(id.to_string());
+        }
+        *current = status;
+        while self.finished.len() > FINISHED_STATUSES {
+            if let Some(oldest) = self.finished.pop_front() {
+                self.order_statuses.remove(&oldest);
+            }
+        }
+    }
+
+    /// Adds an event of the order to the history
+    fn record(&mut self, id: &str, event: HistoryEvent) {
+        if let Err(e) = self.history.append(&HistoryEntry::new(id, event)) {
+            log::error("GTW", format!("Error! {}", e));
+        }
+    }
+
+    /// Returns the trace id of the order, empty if the screen does not have its source anymore
+    fn trace_of(&self, id: &str) -> String {
+        self.sources
+            .get(id)
+            .map(|source| source.trace_id.clone())
+            .unwrap_or_default()
+    }
+
+    /// Aborts the payment of a captured order
+    fn abort_payment(&mut self, id: &str, error: &str) {
+        self.record(
+            id,
+            HistoryEvent::Aborted {
+                reason: error.to_string(),
+            },
+        );
+        let status = OrderStatus::Aborted {
+            error: error.to_string(),
+        };
+        webhook::notify(self.id, id, status.clone());
+        self.set_status(id, status);
+        let trace_id = self.trace_of(id);
+        self.orders_captured.remove(id);
+        self.sources.remove(id);
+        self.retries.remove(id);
+        self.busy_retries.remove(id);
+        metrics::get().order_aborted();
+        let output = format!("Order: {:?} aborted, reason: {:?}", id, error);
+        log::traced(Level::Warn, "GTW", &trace_id, output.red());
+        self.check_all_processed();
+    }
+
+    /// Gives back the payment of an order that was aborted after it was confirmed.
+    /// An order that is still captured is just aborted, and one that was not confirmed or was already refunded is left as it is
+    fn refund_payment(&mut self, id: &str, error: &str) {
+        if self.orders_captured.contains_key(id) {
+            self.abort_payment(id, error);
+            return;
+        }
+        match self.history.outcome(id) {
+            Some(HistoryEvent::Confirmed { .. }) => {}
+            Some(HistoryEvent::Refunded { .. }) => {
+                let output = format!("Order: {:?} was already refunded", id);
+                log::info("GTW", output.yellow());
+                return;
+            }
+            _ => {
+                let output = format!("Order: {:?} was not confirmed, nothing to refund", id);
+                log::warn("GTW", output.yellow());
+                return;
+            }
+        }
+        self.record(
+            id,
+            HistoryEvent::Refunded {
+                reason: error.to_string(),
+            },
+        );
+        let status = OrderStatus::Refunded {
+            error: error.to_string(),
+        };
+        webhook::notify(self.id, id, status.clone());
+        self.set_status(id, status);
+        self.pickups.forget(id);
+        metrics::get().order_refunded();
+        let output = format!("Order: {:?} refunded, reason: {:?}", id, error);
+        log::warn("GTW", output.red());
+    }
+
+    /// Ends the time the screen plays dead in a drill.
+    /// The orders handed over in the backup are left to the next screen, and the screen connects again to the
+    /// next screen and the leader, which tells it which screen had claimed its captured orders
+    fn rejoin_after_drill(&mut self, ctx: &mut Context<Self>) {
+        let drill = match self.drill.as_mut() {
+            Some(drill) => drill,
+            None => return,
+        };
+        drill.down = false;
+        let handed_over_pending = drill.report.handed_over_pending.clone();
+        self.orders_waiting
+            .drop_front(drill.report.handed_over_waiting);
+        self.release_room();
+        self.orders_pending_to_prepare
+            .retain(|(id, _)| !handed_over_pending.contains(id));
+
+        log::info("GTW", "Failover drill: rejoining".bright_yellow());
+        actix::spawn(connect_following_and_notify_previous(
+            self.id,
+            ctx.address(),
+        ));
+        self.connect_to_known_leader(ctx);
+        self.process_new_order(ctx);
     }
 
     fn check_all_processed(&mut self) {
-        if self.orders_captured.is_empty() && self.orders_waiting.is_empty() {
-            println!("{}", "All orders processed".bright_green());
+        if self.orders_captured.is_empty()
+            && self.orders_waiting.is_empty()
+            && self.orders_submitted.is_empty()
+        {
+            log::info("GTW", "All orders processed".bright_green());
         }
     }
 }
 
 impl Actor for PaymentsGateway {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        mailbox::watch("gateway", ctx);
+        health::watch(ctx, |actor: &Self| actor.robot_connection_handler.is_some());
+        let interval = Duration::from_millis(config::get().gossip.interval_ms);
+        ctx.run_interval(interval, |actor, _| actor.gossip());
+        let interval = Duration::from_millis(config::get().heartbeat.interval_ms);
+        ctx.run_interval(interval, |actor, ctx| actor.check_leader_link(ctx));
+    }
+}
+
+impl Handler<Probe> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Probe, _ctx: &mut Self::Context) -> Self::Result {}
 }
 
 /// ReceiveOrders is a message that tells the PaymentsGateway actor to receive the orders from the OrderReader actor.
-/// The orders are stored in the orders_waiting vector.
+/// The orders are moved to the queue of orders waiting, with the channel they came from, and the number of orders waiting is returned.
+/// With a room, the answer is held until at most that many orders are waiting, so a reader streaming a file
+/// sends the next chunk only when the screen has captured enough of the previous ones.
 #[derive(Message)]
-#[rtype(result = "Result<Vec<Order>, std::io::Error>")]
+#[rtype(result = "Result<usize, std::io::Error>")]
 pub struct ReceiveOrders {
     orders: Vec<Order>,
+    channel: OrderChannel,
+    room: Option<usize>,
 }
 
 impl ReceiveOrders {
     pub fn new(orders: Vec<Order>) -> ReceiveOrders {
-        ReceiveOrders { orders }
+        ReceiveOrders::with_channel(orders, OrderChannel::File)
+    }
+
+    pub fn with_channel(orders: Vec<Order>, channel: OrderChannel) -> ReceiveOrders {
+        ReceiveOrders {
+            orders,
+            channel,
+            room: None,
+        }
+    }
+
+    /// Holds the answer until no more than `room` orders are waiting
+    pub fn waiting_for_room(mut self, room: usize) -> ReceiveOrders {
+        self.room = Some(room);
+        self
     }
 }
 
 impl Handler<ReceiveOrders> for PaymentsGateway {
-    type Result = Result<Vec<Order>, std::io::Error>;
+    type Result = ResponseFuture<Result<usize, std::io::Error>>;
 
     fn handle(&mut self, msg: ReceiveOrders, _ctx: &mut Context<Self>) -> Self::Result {
-        self.orders_waiting = self
-            .orders_waiting
-            .clone()
-            .into_iter()
-            .chain(msg.orders.clone())
-            .collect();
-        #[cfg(not(test))]
-        if _ctx.address().try_send(ProcessNewOrder()).is_err() {
-            println!("Error sending ProcessNewOrder");
+        if !msg.orders.is_empty() {
+            metrics::get().order_received(msg.orders.len());
+            let (id, channel) = (self.id, msg.channel);
+            let menu = &self.menu;
+            let on_menu = msg
+                .orders
+                .into_iter()
+                .filter(|order| match menu.check(order) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        let line = format!("Order {:?} turned away: {}", order, e);
+                        log::warn("GTW", line.yellow());
+                        false
+                    }
+                });
+            self.orders_waiting
+                .extend(on_menu, || OrderSource::new(id, channel));
+            #[cfg(not(test))]
+            if _ctx.address().try_send(ProcessNewOrder()).is_err() {
+                log::error("GTW", "Error sending ProcessNewOrder");
+            }
+            #[cfg(test)]
+            _ctx.address()
+                .do_send(CaptureNewOrder::new(0.5, "id".to_string()));
+        }
+        let waiting = self.orders_waiting.len();
+        match msg.room {
+            Some(room) if waiting > room => {
+                let (waiter, room_left) = oneshot::channel();
+                self.room_waiters.push((room, waiter));
+                Box::pin(async move {
+                    room_left
+                        .await
+                        .map_err(|e| std::io::Error::other(e.to_string()))
+                })
+            }
+            _ => Box::pin(async move { Ok(waiting) }),
+        }
+    }
+}
+
+/// SubmitOrder is a message that gives the PaymentsGateway an order taken by the API.
+/// The order is captured before the ones of the file, and its id is returned so the caller can ask for its status.
+/// An order with a flavor off the menu is turned away with the reason.
+#[derive(Message)]
+#[rtype(result = "Result<String, String>")]
+pub struct SubmitOrder {
+    order: Order,
+}
+
+impl SubmitOrder {
+    pub fn new(order: Order) -> SubmitOrder {
+        SubmitOrder { order }
+    }
+}
+
+impl Handler<SubmitOrder> for PaymentsGateway {
+    type Result = Result<String, String>;
+
+    fn handle(&mut self, msg: SubmitOrder, ctx: &mut Context<Self>) -> Self::Result {
+        metrics::get().order_received(1);
+        self.menu.check(&msg.order)?;
+        let id = self.new_order_id();
+        self.order_statuses.insert(id.clone(), OrderStatus::Waiting);
+        self.sources
+            .insert(id.clone(), OrderSource::new(self.id, OrderChannel::Api));
+        self.orders_submitted.push_back((id.clone(), msg.order));
+        let output = format!("Order: {:?} submitted through the API", id);
+        log::info("GTW", output.purple());
+        self.process_new_order(ctx);
+        Ok(id)
+    }
+}
+
+/// GetOrderStatus is a message that asks the PaymentsGateway for the status of an order submitted through the API.
+#[derive(Message)]
+#[rtype(result = "Option<OrderStatus>")]
+pub struct GetOrderStatus {
+    id: String,
+}
+
+impl GetOrderStatus {
+    pub fn new(id: String) -> GetOrderStatus {
+        GetOrderStatus { id }
+    }
+}
+
+impl Handler<GetOrderStatus> for PaymentsGateway {
+    type Result = Option<OrderStatus>;
+
+    fn handle(&mut self, msg: GetOrderStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        let status = self.order_statuses.get(&msg.id).cloned();
+        if let (Some(OrderStatus::Captured { .. }), Some(robot_connection_handler)) =
+            (&status, &self.robot_connection_handler)
+        {
+            robot_connection_handler.do_send(SendQueryStatus::new(self.id, msg.id));
+        }
+        status
+    }
+}
+
+/// QueuePosition is a message that tells the PaymentsGateway how many orders the leader has ahead of a captured order.
+/// It is kept in the status of the order, so the next time it is asked the position is shown.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueuePosition {
+    id: String,
+    orders_ahead: Option<usize>,
+}
+
+impl QueuePosition {
+    pub fn new(id: String, orders_ahead: Option<usize>) -> QueuePosition {
+        QueuePosition { id, orders_ahead }
+    }
+}
+
+impl Handler<QueuePosition> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueuePosition, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(OrderStatus::Captured { orders_ahead }) = self.order_statuses.get_mut(&msg.id) {
+            *orders_ahead = msg.orders_ahead;
+        }
+        if let Some(ahead) = msg.orders_ahead {
+            let output = format!("Order: {:?} has {} orders ahead", msg.id, ahead);
+            log::info("GTW", output.bright_cyan());
         }
-        #[cfg(test)]
-        _ctx.address()
-            .do_send(CaptureNewOrder::new(0.5, "id".to_string()));
-        Ok(self.orders_waiting.clone())
     }
 }
 
@@ -150,13 +726,13 @@ impl Handler<GetOrdersWaiting> for PaymentsGateway {
     type Result = Vec<Order>;
 
     fn handle(&mut self, _msg: GetOrdersWaiting, _ctx: &mut Context<Self>) -> Self::Result {
-        self.orders_waiting.clone()
+        self.orders_waiting.orders().cloned().collect()
     }
 }
 
 /// ProcessNewOrder is a message that tells the PaymentsGateway actor to capture a new order.
-/// This will happen when the orders_waiting vector is not empty.
-/// The actor will wait for 2 seconds to simulate the payment processing.
+/// This will happen when there are orders waiting.
+/// The actor will wait for the processing time of its payment scenario to simulate the payment processing.
 #[cfg(not(test))]
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -167,13 +743,14 @@ impl Handler<ProcessNewOrder> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, _msg: ProcessNewOrder, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.orders_waiting.is_empty() {
+        if (self.orders_waiting.is_empty() && self.orders_submitted.is_empty()) || self.down() {
             return;
         }
+        let processing = self.payments.processing();
         async move {
             let output = "Processing new order...".to_string();
-            println!("[GTW] {}", output.yellow());
-            tokio::time::sleep(Duration::from_secs(2)).await;
+            log::info("GTW", output.yellow());
+            tokio::time::sleep(processing).await;
         }
         .into_actor(self)
         .wait(_ctx);
@@ -182,8 +759,8 @@ impl Handler<ProcessNewOrder> for PaymentsGateway {
 }
 
 /// CaptureOrder is a message that tells the PaymentsGateway actor to capture a new order.
-/// This is made by removing the first order from the orders_waiting vector and adding it to the orders_captured hashmap.
-/// If the card is invalid, the order is not captured and the method returns None. It depends on a random number generator to simulate the card validation.
+/// This is made by removing the first order submitted through the API, or else the oldest order waiting, and adding it to the orders_captured hashmap.
+/// If the card is declined, the order is not captured. The payment scenario decides it, with a random number generator to simulate the card validation.
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct CaptureOrder();
@@ -192,27 +769,56 @@ impl Handler<CaptureOrder> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, _msg: CaptureOrder, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.orders_waiting.is_empty() {
+        let (id, order) = match self.orders_submitted.pop_front() {
+            Some(submitted) => submitted,
+            None => match self.take_waiting() {
+                Some((order, source)) => {
+                    let id = self.new_order_id();
+                    self.sources.insert(id.clone(), source);
+                    (id, order)
+                }
+                None => return,
+            },
+        };
+        if let Err(e) = self.menu.check(&order) {
+            let trace_id = self.trace_of(&id);
+            self.sources.remove(&id);
+            self.set_status(&id, OrderStatus::Aborted { error: e.clone() });
+            let output = format!(
+                "Order: {:?} turned away before capturing its payment: {}",
+                id, e
+            );
+            log::traced(Level::Warn, "GTW", &trace_id, output.yellow());
+            self.process_new_order(_ctx);
             return;
         }
-        let order = self.orders_waiting.remove(0);
-        let id = Uuid::new_v4().to_string();
-        if rand::thread_rng().gen_range(0.0..1.0) <= 0.1 {
-            let output = format!(" Order: {:?} aborted, card declined", id);
-            println!("[GTW]{}", output.red());
+        if self.payments.declines(&id, &mut self.rng) {
+            let trace_id = self.trace_of(&id);
+            self.sources.remove(&id);
+            self.set_status(&id, OrderStatus::Declined);
+            let output = format!("Order: {:?} aborted, card declined", id);
+            log::traced(Level::Warn, "GTW", &trace_id, output.red());
             #[cfg(not(test))]
             if let Err(err) = _ctx.address().try_send(ProcessNewOrder()) {
-                println!("Failed to capture order: {:?}", err);
+                log::error("GTW", format!("Failed to capture order: {:?}", err));
                 return;
             }
             return;
         }
         self.orders_captured.insert(id.clone(), order.clone());
-        self.send_backup();
+        self.set_status(&id, OrderStatus::Captured { orders_ahead: None });
+        self.record(
+            &id,
+            HistoryEvent::Captured {
+                order: order.clone(),
+            },
+        );
+        self.send_backup(_ctx);
         let id_clone_output = id.clone();
-        let output = format!(" Order: {:?} captured", id_clone_output);
-        println!("[GTW]{}", output.green());
-        self.check_robot_connection_and_send_order(id, order);
+        let output = format!("Order: {:?} captured", id_clone_output);
+        log::traced(Level::Info, "GTW", &self.trace_of(&id), output.green());
+        self.check_robot_connection_and_send_order(id.clone(), order);
+        self.start_result_timer(id, _ctx);
         self.process_new_order(_ctx);
     }
 }
@@ -220,15 +826,17 @@ impl Handler<CaptureOrder> for PaymentsGateway {
 /// ConfirmOrder is a message that tells the PaymentsGateway actor to confirm the payment of an order.
 /// This is made by removing the order from the orders_captured hashmap.
 /// If the hashmap is empty, it will print a message saying that all orders have been processed.
+/// It carries the flavors that were replaced by a substitute, since they ran out.
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ConfirmOrder {
     id: String,
+    substitutions: Vec<Substitution>,
 }
 
 impl ConfirmOrder {
-    pub fn new(id: String) -> ConfirmOrder {
-        ConfirmOrder { id }
+    pub fn new(id: String, substitutions: Vec<Substitution>) -> ConfirmOrder {
+        ConfirmOrder { id, substitutions }
     }
 }
 
@@ -236,9 +844,36 @@ impl Handler<ConfirmOrder> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, msg: ConfirmOrder, _ctx: &mut Context<Self>) -> Self::Result {
+        for substitution in &msg.substitutions {
+            let line = format!(
+                "Order: {:?} got {} instead of {}",
+                msg.id, substitution.substitute, substitution.flavor
+            );
+            log::info("GTW", line.bright_cyan());
+        }
+        self.record(
+            &msg.id,
+            HistoryEvent::Confirmed {
+                substitutions: msg.substitutions.clone(),
+            },
+        );
+        let pickup_code = self.pickups.assign(&msg.id);
+        let status = OrderStatus::Confirmed {
+            substitutions: msg.substitutions,
+            pickup_code: Some(pickup_code.clone()),
+        };
+        webhook::notify(self.id, &msg.id, status.clone());
+        self.set_status(&msg.id, status);
+        let trace_id = self.trace_of(&msg.id);
         self.orders_captured.remove(&msg.id);
-        let output = format!(" Order: {:?} confirmed", msg.id);
-        println!("[GTW]{}", output.bright_cyan());
+        self.orders_pending_to_prepare
+            .retain(|(id, _)| *id != msg.id);
+        self.sources.remove(&msg.id);
+        self.retries.remove(&msg.id);
+        self.busy_retries.remove(&msg.id);
+        metrics::get().order_completed();
+        let output = format!("Order: {:?} confirmed, pickup code {}", msg.id, pickup_code);
+        log::traced(Level::Info, "GTW", &trace_id, output.bright_cyan());
         self.check_all_processed();
     }
 }
@@ -263,126 +898,675 @@ impl Handler<AbortOrder> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, msg: AbortOrder, _ctx: &mut Context<Self>) -> Self::Result {
-        self.orders_captured.remove(&msg.id);
-        let output = format!("[GTW] Order: {:?} aborted, reason: {:?}", msg.id, msg.error);
-        println!("{}", output.red());
-        self.check_all_processed();
+        if !self.orders_captured.contains_key(&msg.id)
+            && matches!(
+                self.history.outcome(&msg.id),
+                Some(HistoryEvent::Confirmed { .. })
+            )
+        {
+            self.refund_payment(&msg.id, &msg.error);
+            return;
+        }
+        self.abort_payment(&msg.id, &msg.error);
     }
 }
 
-/// This message is used to register the robot connection handler.
+/// IssueRefund is a message that tells the PaymentsGateway actor to give back the payment of an order
+/// that was aborted after it was confirmed, it is only given once
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct RegisterRobotConnection {
-    robot_connection_handler: Addr<RobotConnectionHandler>,
+pub struct IssueRefund {
+    id: String,
+    error: String,
 }
 
-impl RegisterRobotConnection {
-    pub fn new(robot_connection_handler: Addr<RobotConnectionHandler>) -> RegisterRobotConnection {
-        RegisterRobotConnection {
-            robot_connection_handler,
-        }
+impl IssueRefund {
+    pub fn new(id: String, error: String) -> IssueRefund {
+        IssueRefund { id, error }
     }
 }
 
-impl Handler<RegisterRobotConnection> for PaymentsGateway {
+impl Handler<IssueRefund> for PaymentsGateway {
     type Result = ();
 
-    fn handle(&mut self, msg: RegisterRobotConnection, _ctx: &mut Context<Self>) -> Self::Result {
-        self.robot_connection_handler = Some(msg.robot_connection_handler);
-        if !self.orders_pending_to_prepare.is_empty() {
-            _ctx.address().do_send(SendPendingOrdersToRobot());
-        }
+    fn handle(&mut self, msg: IssueRefund, _ctx: &mut Context<Self>) -> Self::Result {
+        self.refund_payment(&msg.id, &msg.error);
     }
 }
 
-/// This message is used to register the screen connection sender.
-/// It will send a SendMyBackup message to the screen connection sender.
+/// OrderResultTimeout is a message that the PaymentsGateway sends to itself when the result of an order takes too long.
+/// If the order is still captured, it is sent again to the robot leader with the same id, the leader ignores it if it already has it.
+/// After `max_retries` retries the payment is aborted.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct RegisterScreenConnection {
-    screen_connection_sender: Addr<ScreenConnectionSender>,
+pub struct OrderResultTimeout {
+    id: String,
+    attempt: usize,
 }
 
-impl RegisterScreenConnection {
-    pub fn new(screen_connection_sender: Addr<ScreenConnectionSender>) -> RegisterScreenConnection {
-        RegisterScreenConnection {
-            screen_connection_sender,
+impl Handler<OrderResultTimeout> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: OrderResultTimeout, ctx: &mut Context<Self>) -> Self::Result {
+        let order = match self.orders_captured.get(&msg.id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        if self.retries.get(&msg.id).copied().unwrap_or(0) != msg.attempt {
+            return;
+        }
+        if msg.attempt >= config::get().orders.max_retries {
+            self.abort_payment(&msg.id, "No result from the robot leader");
+            return;
         }
+
+        self.retries.insert(msg.id.clone(), msg.attempt + 1);
+        let output = format!(
+            "Order: {:?} without result, sending it again (retry {})",
+            msg.id,
+            msg.attempt + 1
+        );
+        log::warn("GTW", output.yellow());
+        self.check_robot_connection_and_send_order(msg.id.clone(), order);
+        self.start_result_timer(msg.id, ctx);
     }
 }
 
-impl Handler<RegisterScreenConnection> for PaymentsGateway {
+/// OrderRejectedBusy is a message that tells the PaymentsGateway actor that the robot leader has too many orders on queue to take an order.
+/// The order is sent again after `busy_retry_ms`, and after `max_busy_retries` rejections the payment is aborted.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrderRejectedBusy {
+    id: String,
+}
+
+impl OrderRejectedBusy {
+    pub fn new(id: String) -> OrderRejectedBusy {
+        OrderRejectedBusy { id }
+    }
+}
+
+impl Handler<OrderRejectedBusy> for PaymentsGateway {
     type Result = ();
 
-    fn handle(&mut self, msg: RegisterScreenConnection, _ctx: &mut Context<Self>) -> Self::Result {
-        self.screen_connection_sender = Some(msg.screen_connection_sender);
-        if let Some(sender) = self.screen_connection_sender.as_ref() {
-            sender.do_send(SendMyBackup::new(
-                self.orders_waiting.clone(),
-                self.orders_captured.clone(),
-                self.orders_pending_to_prepare.clone(),
-                self.id,
-            ))
+    fn handle(&mut self, msg: OrderRejectedBusy, ctx: &mut Context<Self>) -> Self::Result {
+        if !self.orders_captured.contains_key(&msg.id) {
+            return;
+        }
+        let orders_config = &config::get().orders;
+        let rejections = self.busy_retries.entry(msg.id.clone()).or_insert(0);
+        if *rejections >= orders_config.max_busy_retries {
+            self.abort_payment(&msg.id, "The robot leader is too busy");
+            return;
         }
+        *rejections += 1;
+        let output = format!(
+            "Order: {:?} rejected, the robot leader is busy (retry {})",
+            msg.id, rejections
+        );
+        log::warn("GTW", output.yellow());
+        ctx.notify_later(
+            RetryBusyOrder { id: msg.id },
+            Duration::from_millis(orders_config.busy_retry_ms),
+        );
     }
 }
 
-/// This message is used to send the pending orders to the robot leader.
-/// It will send the orders that are pending to be prepared to the robot leader.
-/// This occurs when the gateway processes orders and the robot connection handler is not active.
+/// RetryBusyOrder is a message that the PaymentsGateway sends to itself to send again an order that the robot leader rejected.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SendPendingOrdersToRobot();
-impl Handler<SendPendingOrdersToRobot> for PaymentsGateway {
+struct RetryBusyOrder {
+    id: String,
+}
+
+impl Handler<RetryBusyOrder> for PaymentsGateway {
     type Result = ();
 
-    fn handle(&mut self, _msg: SendPendingOrdersToRobot, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.robot_connection_handler.is_none()
-            || !self
-                .robot_connection_handler
-                .clone()
-                .expect("This should never happen")
-                .connected()
-        {
-            return;
-        }
-        for (id, order) in self.orders_pending_to_prepare.clone() {
-            if let Some(handler) = self.robot_connection_handler.as_ref() {
-                if let Err(err) = handler.try_send(SendOrderToRobotLeader::new(order, id, self.id))
-                {
-                    println!("Failed to send order to robot leader: {:?}", err);
-                }
-            }
+    fn handle(&mut self, msg: RetryBusyOrder, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(order) = self.orders_captured.get(&msg.id).cloned() {
+            self.check_robot_connection_and_send_order(msg.id, order);
         }
-
-        self.orders_pending_to_prepare.clear();
     }
 }
 
-/// This message is used to send a request to the screen connection sender.
-/// When a screen does not have connection with robot leader, it will send a request to the screen connection sender.
+/// OrdersNotSent is a message that tells the PaymentsGateway actor that some orders could not be sent to the robot leader.
+/// If the error is transient the connection with the leader is dropped, and the orders wait until a new connection is registered.
+/// Otherwise sending them again would fail the same way, so their payments are aborted.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SendRequestFromScreen {
-    id: usize,
+pub struct OrdersNotSent {
+    orders: Vec<(String, Order)>,
+    error: FreddoError,
 }
 
-impl SendRequestFromScreen {
-    pub fn new(id: usize) -> SendRequestFromScreen {
-        SendRequestFromScreen { id }
+impl OrdersNotSent {
+    pub fn new(orders: Vec<(String, Order)>, error: FreddoError) -> OrdersNotSent {
+        OrdersNotSent { orders, error }
     }
 }
 
-impl Handler<SendRequestFromScreen> for PaymentsGateway {
+impl Handler<OrdersNotSent> for PaymentsGateway {
     type Result = ();
 
-    fn handle(&mut self, msg: SendRequestFromScreen, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.robot_connection_handler.is_none()
-            || !self
-                .robot_connection_handler
-                .clone()
-                .expect("This should never happen")
+    fn handle(&mut self, msg: OrdersNotSent, _ctx: &mut Context<Self>) -> Self::Result {
+        let output = format!(
+            "{} orders could not be sent to the robot leader: {}",
+            msg.orders.len(),
+            msg.error
+        );
+        log::warn("GTW", output.red());
+        if !msg.error.is_transient() {
+            for (id, _) in msg.orders {
+                self.abort_payment(&id, &msg.error.to_string());
+            }
+            return;
+        }
+        self.robot_connection_handler = None;
+        self.orders_pending_to_prepare.extend(msg.orders);
+        self.send_backup(_ctx);
+        if let Some(sender) = self.screen_connection_sender.clone() {
+            sender.do_send(RequestRobotLeaderConnection::new(self.id));
+        }
+    }
+}
+
+/// ReleaseOrders is a message that tells the PaymentsGateway actor to forget orders that another screen handles.
+/// The robot leader sends it when a screen rejoins, so the result of each order is only handled by one screen.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReleaseOrders {
+    order_ids: Vec<String>,
+}
+
+impl ReleaseOrders {
+    pub fn new(order_ids: Vec<String>) -> ReleaseOrders {
+        ReleaseOrders { order_ids }
+    }
+}
+
+impl Handler<ReleaseOrders> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReleaseOrders, _ctx: &mut Context<Self>) -> Self::Result {
+        for id in msg.order_ids.iter() {
+            self.set_status(id, OrderStatus::Released);
+            self.orders_captured.remove(id);
+            self.sources.remove(id);
+            self.retries.remove(id);
+            self.busy_retries.remove(id);
+        }
+        self.orders_pending_to_prepare
+            .retain(|(id, _)| !msg.order_ids.contains(id));
+        let output = format!("Orders {:?} released to another screen", msg.order_ids);
+        log::info("GTW", output.bright_cyan());
+        self.send_backup(_ctx);
+        self.check_all_processed();
+    }
+}
+
+/// This message is used to register the robot connection handler.
+/// The screen announces itself to the robot leader with the orders it has captured.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterRobotConnection {
+    robot_connection_handler: Addr<RobotConnectionHandler>,
+}
+
+impl RegisterRobotConnection {
+    pub fn new(robot_connection_handler: Addr<RobotConnectionHandler>) -> RegisterRobotConnection {
+        RegisterRobotConnection {
+            robot_connection_handler,
+        }
+    }
+}
+
+impl Handler<RegisterRobotConnection> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterRobotConnection, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.down() {
+            msg.robot_connection_handler.do_send(CloseConnection());
+            return;
+        }
+        let order_ids = self.orders_captured.keys().cloned().collect();
+        msg.robot_connection_handler
+            .do_send(SendScreenRejoined::new(self.id, order_ids));
+        if self.menu.from_leader() {
+            msg.robot_connection_handler
+                .do_send(SendQueryMenu::new(self.id));
+        }
+        self.robot_connection_handler = Some(msg.robot_connection_handler);
+        if !self.orders_pending_to_prepare.is_empty() {
+            _ctx.address().do_send(SendPendingOrdersToRobot());
+        }
+    }
+}
+
+/// MenuInStock is a message that tells the PaymentsGateway which flavors the leader has stock of, the others are taken off the menu.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MenuInStock {
+    in_stock: Vec<FlavorID>,
+}
+
+impl MenuInStock {
+    pub fn new(in_stock: Vec<FlavorID>) -> MenuInStock {
+        MenuInStock { in_stock }
+    }
+}
+
+impl Handler<MenuInStock> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: MenuInStock, _ctx: &mut Context<Self>) -> Self::Result {
+        let line = format!("The leader has stock of {:?}", msg.in_stock);
+        log::info("GTW", line.bright_cyan());
+        self.menu.update_stock(msg.in_stock);
+    }
+}
+
+/// StockLeft is a message that tells the PaymentsGateway the grams the leader has left of each flavor.
+/// The orders that need more grams than there are left are turned away before their payment is captured.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StockLeft {
+    grams: Vec<(FlavorID, usize)>,
+}
+
+impl StockLeft {
+    pub fn new(grams: Vec<(FlavorID, usize)>) -> StockLeft {
+        StockLeft { grams }
+    }
+}
+
+impl Handler<StockLeft> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: StockLeft, _ctx: &mut Context<Self>) -> Self::Result {
+        let line = format!("The leader has {:?} grams left", msg.grams);
+        log::debug("GTW", line.normal());
+        self.menu.update_grams(msg.grams);
+    }
+}
+
+/// RobotConnectionLost is a message that tells the PaymentsGateway that a connection with the robot leader was closed.
+/// If it was the one in use, the screen starts reconnecting to the leader right away.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RobotConnectionLost {
+    robot_connection_handler: Addr<RobotConnectionHandler>,
+}
+
+impl RobotConnectionLost {
+    pub fn new(robot_connection_handler: Addr<RobotConnectionHandler>) -> RobotConnectionLost {
+        RobotConnectionLost {
+            robot_connection_handler,
+        }
+    }
+}
+
+impl Handler<RobotConnectionLost> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: RobotConnectionLost, ctx: &mut Context<Self>) -> Self::Result {
+        if self.robot_connection_handler.as_ref() != Some(&msg.robot_connection_handler) {
+            return;
+        }
+        self.robot_connection_handler = None;
+        if self.down() || self.reconnecting {
+            return;
+        }
+        log::warn("GTW", "Lost the connection with the robot leader".red());
+        self.reconnect_to_leader(0, ctx);
+    }
+}
+
+/// CheckLeaderEpoch is a message that asks the PaymentsGateway if a message of a leader with the given epoch can be accepted.
+/// It returns Ok(true) if it is the first message of a newer leader, and the newest epoch known if the leader is stale.
+#[derive(Message)]
+#[rtype(result = "Result<bool, u64>")]
+pub struct CheckLeaderEpoch {
+    epoch: u64,
+}
+
+impl CheckLeaderEpoch {
+    pub fn new(epoch: u64) -> CheckLeaderEpoch {
+        CheckLeaderEpoch { epoch }
+    }
+}
+
+impl Handler<CheckLeaderEpoch> for PaymentsGateway {
+    type Result = Result<bool, u64>;
+
+    fn handle(&mut self, msg: CheckLeaderEpoch, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.epoch < self.leader_epoch {
+            let output = format!(
+                "Message of a stale leader rejected (epoch {} < {})",
+                msg.epoch, self.leader_epoch
+            );
+            log::warn("GTW", output);
+            return Err(self.leader_epoch);
+        }
+        let newer = msg.epoch > self.leader_epoch;
+        self.leader_epoch = msg.epoch;
+        Ok(newer)
+    }
+}
+
+/// ReceiveGossip is a message with what the previous screen knows of the cluster.
+/// If it tells of a leader and the screen has no connection with one, the screen connects to it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReceiveGossip {
+    members: Vec<(usize, u64)>,
+    leader: Option<(usize, u64)>,
+}
+
+impl ReceiveGossip {
+    pub fn new(members: Vec<(usize, u64)>, leader: Option<(usize, u64)>) -> ReceiveGossip {
+        ReceiveGossip { members, leader }
+    }
+}
+
+impl Handler<ReceiveGossip> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReceiveGossip, ctx: &mut Context<Self>) -> Self::Result {
+        if self.membership.merge(&msg.members, msg.leader) {
+            if let Some((leader_id, epoch)) = self.membership.leader() {
+                let output = format!("Heard of robot leader {} (epoch {})", leader_id, epoch);
+                log::info("GTW", output.bright_cyan());
+            }
+        }
+        self.connect_to_known_leader(ctx);
+    }
+}
+
+/// LeaderAnnounced is a message that tells the PaymentsGateway which robot is the leader connected to the screen.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaderAnnounced {
+    leader_id: usize,
+    epoch: u64,
+}
+
+impl LeaderAnnounced {
+    pub fn new(leader_id: usize, epoch: u64) -> LeaderAnnounced {
+        LeaderAnnounced { leader_id, epoch }
+    }
+}
+
+impl Handler<LeaderAnnounced> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaderAnnounced, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.membership.set_leader(msg.leader_id, msg.epoch) {
+            self.gossip();
+        }
+    }
+}
+
+/// PickUpOrder is a message that tells the PaymentsGateway that the order with a pickup code was handed to the customer.
+/// The leader is told, so it counts it and closes the order in its journal.
+#[derive(Message)]
+#[rtype(result = "Result<Pickup, String>")]
+pub struct PickUpOrder {
+    code: String,
+}
+
+impl PickUpOrder {
+    pub fn new(code: String) -> PickUpOrder {
+        PickUpOrder { code }
+    }
+}
+
+impl Handler<PickUpOrder> for PaymentsGateway {
+    type Result = Result<Pickup, String>;
+
+    fn handle(&mut self, msg: PickUpOrder, _ctx: &mut Context<Self>) -> Self::Result {
+        let Some(pickup) = self.pickups.pick_up(&msg.code) else {
+            return Err(format!("No order is waiting with code {}", msg.code.trim()));
+        };
+        self.set_status(&pickup.order_id, OrderStatus::PickedUp);
+        match &self.robot_connection_handler {
+            Some(robot_connection_handler) => robot_connection_handler.do_send(
+                SendOrderPickedUp::new(self.id, pickup.order_id.clone(), pickup.waiting_ms),
+            ),
+            None => {
+                let output = format!(
+                    "Order: {:?} picked up without a leader, it is not told",
+                    pickup.order_id
+                );
+                log::warn("GTW", output.yellow());
+            }
+        }
+        let output = format!(
+            "Order: {:?} picked up with code {} after {} ms",
+            pickup.order_id, pickup.code, pickup.waiting_ms
+        );
+        log::info("GTW", output.bright_cyan());
+        Ok(pickup)
+    }
+}
+
+/// GetPickups is a message that asks the PaymentsGateway for the orders waiting to be picked up, sorted by code.
+#[derive(Message)]
+#[rtype(result = "Vec<Pickup>")]
+pub struct GetPickups();
+
+impl Handler<GetPickups> for PaymentsGateway {
+    type Result = MessageResult<GetPickups>;
+
+    fn handle(&mut self, _msg: GetPickups, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.pickups.waiting())
+    }
+}
+
+/// GetOrderHistory is a message that asks the PaymentsGateway for the last orders of its history, the newest last.
+#[derive(Message)]
+#[rtype(result = "Vec<OrderRecord>")]
+pub struct GetOrderHistory {
+    limit: usize,
+}
+
+impl GetOrderHistory {
+    pub fn new(limit: usize) -> GetOrderHistory {
+        GetOrderHistory { limit }
+    }
+}
+
+impl Handler<GetOrderHistory> for PaymentsGateway {
+    type Result = MessageResult<GetOrderHistory>;
+
+    fn handle(&mut self, msg: GetOrderHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        let entries = match self.history.load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error("GTW", format!("Error! {}", e));
+                Vec::new()
+            }
+        };
+        let mut records = order_history::summarize(&entries);
+        let skip = records.len().saturating_sub(msg.limit);
+        MessageResult(records.split_off(skip))
+    }
+}
+
+/// What the screen is doing, shown by its kiosk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScreenSnapshot {
+    pub screen_id: usize,
+    pub waiting: Vec<Order>,
+    pub captured: Vec<(String, Order)>,
+    /// Last orders of the history that were confirmed, aborted or refunded, the newest last
+    pub finished: Vec<OrderRecord>,
+    pub leader: Option<(usize, u64)>,
+    pub leader_connected: bool,
+    pub connecting: bool,
+    pub in_drill: bool,
+    /// Flavors of the catalog that are on the menu
+    pub menu: Vec<FlavorID>,
+}
+
+/// GetScreenSnapshot is a message that asks the PaymentsGateway for its orders, its connection with the leader and its menu.
+/// Up to `finished` orders that got a result are given.
+#[derive(Message)]
+#[rtype(result = "ScreenSnapshot")]
+pub struct GetScreenSnapshot {
+    finished: usize,
+}
+
+impl GetScreenSnapshot {
+    pub fn new(finished: usize) -> GetScreenSnapshot {
+        GetScreenSnapshot { finished }
+    }
+}
+
+impl Handler<GetScreenSnapshot> for PaymentsGateway {
+    type Result = MessageResult<GetScreenSnapshot>;
+
+    fn handle(&mut self, msg: GetScreenSnapshot, _ctx: &mut Context<Self>) -> Self::Result {
+        let entries = self.history.load().unwrap_or_default();
+        let mut finished: Vec<OrderRecord> = order_history::summarize(&entries)
+            .into_iter()
+            .filter(|record| record.outcome.is_some())
+            .collect();
+        let skip = finished.len().saturating_sub(msg.finished);
+        let mut captured: Vec<(String, Order)> = self
+            .orders_captured
+            .iter()
+            .map(|(id, order)| (id.clone(), order.clone()))
+            .collect();
+        captured.sort_by(|(a, _), (b, _)| a.cmp(b));
+        MessageResult(ScreenSnapshot {
+            screen_id: self.id,
+            waiting: self.orders_waiting.orders().cloned().collect(),
+            captured,
+            finished: finished.split_off(skip),
+            leader: self.membership.leader(),
+            leader_connected: self.leader_connected(),
+            connecting: self.connecting_to_leader || self.reconnecting,
+            in_drill: self.down(),
+            menu: config::get()
+                .flavors
+                .flavors()
+                .iter()
+                .map(|stock| stock.flavor.clone())
+                .filter(|flavor| self.menu.offers(flavor))
+                .collect(),
+        })
+    }
+}
+
+/// GetMembership is a message that asks the PaymentsGateway for the screens it knows are alive and the leader.
+#[derive(Message)]
+#[rtype(result = "(Vec<usize>, Option<usize>)")]
+pub struct GetMembership();
+
+impl Handler<GetMembership> for PaymentsGateway {
+    type Result = MessageResult<GetMembership>;
+
+    fn handle(&mut self, _msg: GetMembership, _ctx: &mut Context<Self>) -> Self::Result {
+        let timeout = Duration::from_millis(config::get().gossip.member_timeout_ms);
+        let leader = self.membership.leader().map(|(leader_id, _)| leader_id);
+        MessageResult((self.membership.alive(timeout), leader))
+    }
+}
+
+/// This message is used to register the screen connection sender.
+/// It will send a SendMyBackup message to the screen connection sender.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterScreenConnection {
+    screen_connection_sender: Addr<ScreenConnectionSender>,
+}
+
+impl RegisterScreenConnection {
+    pub fn new(screen_connection_sender: Addr<ScreenConnectionSender>) -> RegisterScreenConnection {
+        RegisterScreenConnection {
+            screen_connection_sender,
+        }
+    }
+}
+
+impl Handler<RegisterScreenConnection> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterScreenConnection, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.down() {
+            msg.screen_connection_sender.do_send(CloseConnection());
+            return;
+        }
+        self.screen_connection_sender = Some(msg.screen_connection_sender);
+        self.gossip();
+        if let (Some(backup), Some(sender)) =
+            (self.my_backup(), self.screen_connection_sender.as_ref())
+        {
+            sender.do_send(backup)
+        }
+    }
+}
+
+/// This message is used to send the pending orders to the robot leader.
+/// It will send the orders that are pending to be prepared to the robot leader.
+/// This occurs when the gateway processes orders and the robot connection handler is not active.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendPendingOrdersToRobot();
+impl Handler<SendPendingOrdersToRobot> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, _msg: SendPendingOrdersToRobot, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.robot_connection_handler.is_none()
+            || !self
+                .robot_connection_handler
+                .clone()
+                .expect("This should never happen")
+                .connected()
+        {
+            return;
+        }
+        for (id, order) in self.orders_pending_to_prepare.clone() {
+            if let Some(handler) = self.robot_connection_handler.as_ref() {
+                let source = self.sources.get(&id).cloned();
+                if let Err(err) =
+                    handler.try_send(SendOrderToRobotLeader::new(order, id, self.id, source))
+                {
+                    log::error(
+                        "GTW",
+                        format!("Failed to send order to robot leader: {:?}", err),
+                    );
+                }
+            }
+        }
+
+        self.orders_pending_to_prepare.clear();
+    }
+}
+
+/// This message is used to send a request to the screen connection sender.
+/// When a screen does not have connection with robot leader, it will send a request to the screen connection sender.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendRequestFromScreen {
+    id: usize,
+}
+
+impl SendRequestFromScreen {
+    pub fn new(id: usize) -> SendRequestFromScreen {
+        SendRequestFromScreen { id }
+    }
+}
+
+impl Handler<SendRequestFromScreen> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendRequestFromScreen, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.robot_connection_handler.is_none()
+            || !self
+                .robot_connection_handler
+                .clone()
+                .expect("This should never happen")
                 .connected()
         {
         } else if let Some(handler) = self.robot_connection_handler.as_ref() {
@@ -396,13 +1580,17 @@ impl Handler<SendRequestFromScreen> for PaymentsGateway {
 
 /// This message is used to handle a backup from a screen.
 /// When the previous screen disconnects, the gateway will handle the orders of it.
+/// A backup of a screen is taken only once per version, and with `unless_alive` it is not taken if the screen is alive.
+/// It returns whether the backup was taken.
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "bool")]
 pub struct HandleBackUp {
     orders_to_process: Vec<Order>,
     orders_processing: HashMap<String, Order>,
     orders_pending_to_prepare: Vec<(String, Order)>,
     screen_backup_id: Option<usize>,
+    version: u64,
+    unless_alive: bool,
 }
 
 impl HandleBackUp {
@@ -411,47 +1599,108 @@ impl HandleBackUp {
         orders_processing: HashMap<String, Order>,
         orders_pending_to_prepare: Vec<(String, Order)>,
         screen_backup_id: Option<usize>,
+        version: u64,
+        unless_alive: bool,
     ) -> HandleBackUp {
         HandleBackUp {
             orders_to_process,
             orders_processing,
             screen_backup_id,
             orders_pending_to_prepare,
+            version,
+            unless_alive,
         }
     }
 }
 
 impl Handler<HandleBackUp> for PaymentsGateway {
-    type Result = ();
+    type Result = bool;
 
     fn handle(&mut self, msg: HandleBackUp, _ctx: &mut Context<Self>) -> Self::Result {
-        if msg.screen_backup_id.is_none() {
-            return;
+        let Some(screen_backup_id) = msg.screen_backup_id else {
+            return false;
+        };
+        if self.down() || screen_backup_id == self.id {
+            return false;
+        }
+        if self
+            .backups_taken
+            .get(&screen_backup_id)
+            .is_some_and(|taken| *taken >= msg.version)
+        {
+            return false;
+        }
+        let timeout = Duration::from_millis(config::get().gossip.member_timeout_ms);
+        if msg.unless_alive && self.membership.alive(timeout).contains(&screen_backup_id) {
+            return false;
+        }
+        self.backups_taken.insert(screen_backup_id, msg.version);
+        log::info(
+            "GTW",
+            format!(
+                "Handling backup of Screen {} (version {})",
+                screen_backup_id, msg.version
+            )
+            .bright_yellow(),
+        );
+        for id in msg.orders_processing.keys() {
+            self.start_result_timer(id.clone(), _ctx);
+        }
+        // each order gets its own trace id, the backup does not carry the ones of the dead screen
+        let my_id = self.id;
+        let failover = || OrderSource::new(my_id, OrderChannel::Failover);
+        self.orders_waiting.extend(msg.orders_to_process, failover);
+        for (id, _) in msg.orders_pending_to_prepare.iter() {
+            self.sources.insert(id.clone(), failover());
+        }
+        for id in msg.orders_processing.keys() {
+            self.sources.insert(id.clone(), failover());
         }
-        println!("[GTW]{}", "Handling backup".bright_yellow());
-        self.orders_waiting.extend(msg.orders_to_process);
         self.orders_captured.extend(msg.orders_processing);
         self.orders_pending_to_prepare
             .extend(msg.orders_pending_to_prepare.clone());
-        if let Some(screen_backup_id) = msg.screen_backup_id {
-            if let Some(screen_connection_sender) = self.screen_connection_sender.clone() {
-                screen_connection_sender.do_send(SendMyBackup::new(
-                    self.orders_waiting.clone(),
-                    self.orders_captured.clone(),
-                    self.orders_pending_to_prepare.clone(),
-                    screen_backup_id,
-                ))
+        let backup = self.my_backup();
+        if let Some(screen_connection_sender) = self.screen_connection_sender.clone() {
+            if let Some(backup) = backup {
+                screen_connection_sender.do_send(backup);
+            }
+            // The other replicas forget the backup that was taken, so it is not taken twice
+            let forgotten = BackupView {
+                orders_to_process: Vec::new(),
+                orders_processing: &HashMap::new(),
+                orders_pending_to_send: &[],
+                id_backup: screen_backup_id,
+                version: msg.version + 1,
+                hops_left: config::get().backup.screen_replicas - 1,
+                sender: self.id,
+            };
+            if let Ok(forgotten) = SendMyBackup::new(&forgotten) {
+                screen_connection_sender.do_send(forgotten);
             }
         }
         if let Some(robot_connection_handler) = self.robot_connection_handler.clone() {
-            robot_connection_handler.do_send(AskRobotForScreenOrders::new(
-                self.id,
-                msg.screen_backup_id.expect("this should never fail"),
-            ))
+            robot_connection_handler
+                .do_send(AskRobotForScreenOrders::new(self.id, screen_backup_id))
         }
         #[cfg(not(test))]
         if _ctx.address().try_send(ProcessNewOrder()).is_err() {
-            println!("Error sending ProcessNewOrder");
+            log::error("GTW", "Error sending ProcessNewOrder");
+        }
+        true
+    }
+}
+
+/// Sends the backup of another screen to the next screen, for a backup that has to be kept by more screens
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RelayBackup(pub SendMyBackup);
+
+impl Handler<RelayBackup> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: RelayBackup, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(sender) = self.screen_connection_sender.as_ref() {
+            sender.do_send(msg.0);
         }
     }
 }
@@ -465,11 +1714,10 @@ impl Handler<StartProcessingIfWaiting> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, _msg: StartProcessingIfWaiting, _ctx: &mut Context<Self>) -> Self::Result {
-        if !self.orders_waiting.is_empty() {
-            return;
-        }
         #[cfg(not(test))]
-        _ctx.address().do_send(ProcessNewOrder());
+        if self.orders_waiting.is_empty() {
+            _ctx.address().do_send(ProcessNewOrder());
+        }
     }
 }
 
@@ -485,10 +1733,7 @@ pub struct CaptureNewOrder {
 #[cfg(test)]
 impl CaptureNewOrder {
     pub fn new(probability: f32, key: String) -> CaptureNewOrder {
-        CaptureNewOrder {
-            probability: probability,
-            key: key,
-        }
+        CaptureNewOrder { probability, key }
     }
 }
 
@@ -497,10 +1742,7 @@ impl Handler<CaptureNewOrder> for PaymentsGateway {
     type Result = Option<(String, Order)>;
 
     fn handle(&mut self, msg: CaptureNewOrder, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.orders_waiting.is_empty() {
-            return None;
-        }
-        let order = self.orders_waiting.remove(0);
+        let (order, _) = self.take_waiting()?;
         if msg.probability <= 0.2 {
             return None;
         }
@@ -509,6 +1751,218 @@ impl Handler<CaptureNewOrder> for PaymentsGateway {
     }
 }
 
+/// Returns the ids of the orders captured. It is only for testing purposes.
+#[cfg(test)]
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct GetOrdersCaptured();
+
+#[cfg(test)]
+impl Handler<GetOrdersCaptured> for PaymentsGateway {
+    type Result = Vec<String>;
+
+    fn handle(&mut self, _msg: GetOrdersCaptured, _ctx: &mut Context<Self>) -> Self::Result {
+        self.orders_captured.keys().cloned().collect()
+    }
+}
+
+/// Returns how many statuses of orders submitted through the API the gateway keeps. It is only for testing purposes.
+#[cfg(test)]
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct GetStoredStatuses();
+
+#[cfg(test)]
+impl Handler<GetStoredStatuses> for PaymentsGateway {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetStoredStatuses, _ctx: &mut Context<Self>) -> Self::Result {
+        self.order_statuses.len()
+    }
+}
+
+/// Returns how many orders and history entries the gateway keeps in memory. It is only for testing purposes.
+#[cfg(test)]
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct GetStoredEntries();
+
+#[cfg(test)]
+impl Handler<GetStoredEntries> for PaymentsGateway {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetStoredEntries, _ctx: &mut Context<Self>) -> Self::Result {
+        self.orders_waiting.len()
+            + self.orders_submitted.len()
+            + self.sources.len()
+            + self.order_statuses.len()
+            + self.orders_captured.len()
+            + self.retries.len()
+            + self.busy_retries.len()
+            + self.orders_pending_to_prepare.len()
+            + self
+                .history
+                .load()
+                .map(|entries| entries.len())
+                .unwrap_or(0)
+    }
+}
+
+/// RegisterScreenListener is a message that registers the connection with the previous screen, so a drill can close it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterScreenListener {
+    screen_connection_listener: Addr<ScreenConnectionListener>,
+}
+
+impl RegisterScreenListener {
+    pub fn new(
+        screen_connection_listener: Addr<ScreenConnectionListener>,
+    ) -> RegisterScreenListener {
+        RegisterScreenListener {
+            screen_connection_listener,
+        }
+    }
+}
+
+impl Handler<RegisterScreenListener> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterScreenListener, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.down() {
+            msg.screen_connection_listener.do_send(CloseConnection());
+            return;
+        }
+        self.screen_connection_listener = Some(msg.screen_connection_listener);
+    }
+}
+
+/// StartFailoverDrill is a message that makes the screen play dead for a while and then rejoin.
+/// It answers with the report of the drill once the leader tells which screen had claimed the orders,
+/// or after a while if the leader does not answer.
+#[derive(Message)]
+#[rtype(result = "Result<DrillReport, String>")]
+pub struct StartFailoverDrill {
+    down_for: Duration,
+}
+
+impl StartFailoverDrill {
+    pub fn new(down_for: Duration) -> StartFailoverDrill {
+        StartFailoverDrill { down_for }
+    }
+}
+
+impl Handler<StartFailoverDrill> for PaymentsGateway {
+    type Result = ResponseActFuture<Self, Result<DrillReport, String>>;
+
+    fn handle(&mut self, msg: StartFailoverDrill, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.drill.is_some() {
+            return Box::pin(fut::ready(Err(
+                "A failover drill is already running".to_string()
+            )));
+        }
+        let next_screen = match self.screen_connection_sender.clone() {
+            Some(sender) if sender.connected() => sender,
+            _ => {
+                let error = "There is no next screen to take over the backup".to_string();
+                return Box::pin(fut::ready(Err(error)));
+            }
+        };
+        self.flush_backup();
+        let report = DrillReport::new(
+            self.id,
+            msg.down_for,
+            self.orders_waiting.len(),
+            self.orders_pending_to_prepare
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect(),
+            self.orders_captured.keys().cloned().collect(),
+        );
+        let (drill, answered) = Drill::start(report);
+        self.drill = Some(drill);
+        let output = format!("Failover drill: down for {} s", msg.down_for.as_secs());
+        log::warn("GTW", output.bright_yellow());
+
+        self.screen_connection_sender = None;
+        let leader = self.robot_connection_handler.take();
+        let previous_screen = self.screen_connection_listener.take();
+        let play_dead = async move {
+            let _ = next_screen.send(CloseConnection()).await;
+            if let Some(leader) = leader {
+                let _ = leader.send(CloseConnection()).await;
+            }
+            if let Some(previous_screen) = previous_screen {
+                let _ = previous_screen.send(CloseConnection()).await;
+            }
+            tokio::time::sleep(msg.down_for).await;
+        };
+        Box::pin(
+            play_dead
+                .into_actor(self)
+                .then(move |_, actor, ctx| {
+                    actor.rejoin_after_drill(ctx);
+                    tokio::time::timeout(RECLAIM_TIMEOUT, answered).into_actor(actor)
+                })
+                .map(|_, actor, _| {
+                    let drill = actor
+                        .drill
+                        .take()
+                        .ok_or_else(|| "The failover drill was lost".to_string())?;
+                    Ok(drill.report)
+                }),
+        )
+    }
+}
+
+/// InFailoverDrill is a message that asks the PaymentsGateway if the screen is playing dead, so new connections are refused.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct InFailoverDrill();
+
+impl Handler<InFailoverDrill> for PaymentsGateway {
+    type Result = bool;
+
+    fn handle(&mut self, _msg: InFailoverDrill, _ctx: &mut Context<Self>) -> Self::Result {
+        self.down()
+    }
+}
+
+/// OrdersReclaimed is a message with the answer of the leader to the rejoin of the screen:
+/// the screens that had claimed its captured orders, which go back to it, and the orders another screen finished.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OrdersReclaimed {
+    taken_from: Vec<(usize, Vec<String>)>,
+    finished: Vec<String>,
+}
+
+impl OrdersReclaimed {
+    pub fn new(taken_from: Vec<(usize, Vec<String>)>, finished: Vec<String>) -> OrdersReclaimed {
+        OrdersReclaimed {
+            taken_from,
+            finished,
+        }
+    }
+}
+
+impl Handler<OrdersReclaimed> for PaymentsGateway {
+    type Result = ();
+
+    fn handle(&mut self, msg: OrdersReclaimed, _ctx: &mut Context<Self>) -> Self::Result {
+        for (screen_id, order_ids) in msg.taken_from.iter() {
+            let output = format!(
+                "Orders {:?} taken back from Screen {}",
+                order_ids, screen_id
+            );
+            log::info("GTW", output.bright_cyan());
+        }
+        if let Some(drill) = self.drill.as_mut().filter(|drill| !drill.down) {
+            drill.reclaimed(msg.taken_from, msg.finished);
+        }
+    }
+}
+
 pub struct SendBackupToNewScreen();
 
 impl Message for SendBackupToNewScreen {
@@ -519,23 +1973,18 @@ impl Handler<SendBackupToNewScreen> for PaymentsGateway {
     type Result = ();
 
     fn handle(&mut self, _msg: SendBackupToNewScreen, _ctx: &mut Context<Self>) -> Self::Result {
-        if let Some(sender) = self.screen_connection_sender.clone() {
-            sender.do_send(SendMyBackup::new(
-                self.orders_waiting.clone(),
-                self.orders_captured.clone(),
-                self.orders_pending_to_prepare.clone(),
-                self.id,
-            ));
+        if let (Some(backup), Some(sender)) =
+            (self.my_backup(), self.screen_connection_sender.as_ref())
+        {
+            sender.do_send(backup);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-
-    use crate::common::flavor_id::FlavorID;
-
     use super::*;
+    use rand::Rng;
     #[actix::test]
     async fn test_payments_gateway_receives_orders_from_order_reader_successfully() {
         let payments_gateway = PaymentsGateway::new(0).start();
@@ -546,7 +1995,201 @@ mod tests {
             .await
             .unwrap()
             .unwrap();
-        assert_eq!(orders_received, orders);
+        assert_eq!(orders_received, orders.len());
+    }
+
+    #[actix::test]
+    async fn test_orders_off_the_menu_are_not_captured() {
+        let menu = Menu::new(vec![FlavorID::Mint], false);
+        let payments_gateway = PaymentsGateway::new(0).with_menu(menu).start();
+        let orders = vec![
+            Order::new_cucurucho(FlavorID::Mint),
+            Order::new_cucurucho(FlavorID::Lemon),
+        ];
+        let orders_waiting = payments_gateway
+            .send(ReceiveOrders::new(orders))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(orders_waiting, 1);
+        let submitted = payments_gateway
+            .send(SubmitOrder::new(Order::new_cucurucho(FlavorID::Lemon)))
+            .await
+            .unwrap();
+        assert_eq!(submitted, Err("Lemon is not on the menu".to_string()));
+    }
+
+    #[actix::test]
+    async fn test_memory_stays_bounded_while_processing_many_orders() {
+        const ORDERS: usize = 100_000;
+        const BATCH: usize = 1_000;
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let mut most_stored = 0;
+        for batch in 0..ORDERS / BATCH {
+            let orders = vec![Order::new_cucurucho(FlavorID::Chocolate); BATCH];
+            payments_gateway
+                .send(ReceiveOrders::new(orders))
+                .await
+                .unwrap()
+                .unwrap();
+            most_stored = most_stored.max(payments_gateway.send(GetStoredEntries()).await.unwrap());
+            for order in 0.. {
+                let key = format!("{}-{}", batch, order);
+                match payments_gateway
+                    .send(CaptureNewOrder::new(1.0, key))
+                    .await
+                    .unwrap()
+                {
+                    Some((id, _)) => payments_gateway
+                        .send(ConfirmOrder::new(id, vec![]))
+                        .await
+                        .unwrap(),
+                    None => break,
+                }
+            }
+        }
+        assert!(most_stored <= 2 * BATCH + order_history::ENTRIES_IN_MEMORY);
+        assert!(payments_gateway
+            .send(GetOrdersWaiting())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix::test]
+    async fn test_statuses_of_submitted_orders_stay_bounded() {
+        const ORDERS: usize = FINISHED_STATUSES + FINISHED_STATUSES / 2;
+        let payments_gateway = PaymentsGateway::new(0)
+            .with_payments(PaymentScenario::scripted(0, &[]))
+            .start();
+        let mut ids = Vec::new();
+        let mut most_stored = 0;
+        for _ in 0..ORDERS {
+            let order = Order::new_cucurucho(FlavorID::Chocolate);
+            let id = payments_gateway
+                .send(SubmitOrder::new(order))
+                .await
+                .unwrap()
+                .unwrap();
+            payments_gateway.send(CaptureOrder()).await.unwrap();
+            payments_gateway
+                .send(ConfirmOrder::new(id.clone(), vec![]))
+                .await
+                .unwrap();
+            let stored = payments_gateway.send(GetStoredStatuses()).await.unwrap();
+            most_stored = most_stored.max(stored);
+            ids.push(id);
+        }
+        // the finished orders that are remembered, and the one that was in flight
+        assert!(most_stored <= FINISHED_STATUSES + 1);
+
+        let oldest = payments_gateway.send(GetOrderStatus::new(ids[0].clone()));
+        assert_eq!(oldest.await.unwrap(), None);
+        let newest = payments_gateway.send(GetOrderStatus::new(ids[ORDERS - 1].clone()));
+        assert!(matches!(
+            newest.await.unwrap(),
+            Some(OrderStatus::Confirmed { .. })
+        ));
+    }
+
+    #[actix::test]
+    async fn test_confirmed_orders_are_kept_in_the_history() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        for id in ["a", "b"] {
+            payments_gateway
+                .send(ConfirmOrder::new(id.to_string(), vec![]))
+                .await
+                .unwrap();
+        }
+        let records = payments_gateway
+            .send(GetOrderHistory::new(1))
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].order_id, "b");
+        assert!(matches!(
+            records[0].outcome,
+            Some((_, HistoryEvent::Confirmed { .. }))
+        ));
+    }
+
+    #[actix::test]
+    async fn test_confirmed_order_is_refunded_once() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        payments_gateway
+            .send(ConfirmOrder::new("a".to_string(), vec![]))
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            payments_gateway
+                .send(IssueRefund::new("a".to_string(), "late abort".to_string()))
+                .await
+                .unwrap();
+        }
+        payments_gateway
+            .send(IssueRefund::new("b".to_string(), "late abort".to_string()))
+            .await
+            .unwrap();
+        let entries = payments_gateway
+            .send(GetOrderHistory::new(10))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(
+            entries[0].outcome,
+            Some((_, HistoryEvent::Refunded { .. }))
+        ));
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_learns_the_cluster_from_gossip() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        payments_gateway
+            .send(ReceiveGossip::new(vec![(1, 4), (2, 1)], Some((3, 1))))
+            .await
+            .unwrap();
+        payments_gateway
+            .send(LeaderAnnounced::new(4, 0))
+            .await
+            .unwrap();
+        let (alive, leader) = payments_gateway.send(GetMembership()).await.unwrap();
+        assert_eq!(alive, vec![0, 1, 2]);
+        assert_eq!(leader, Some(3));
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_reconnects_to_the_leader_when_the_connection_is_lost() {
+        use crate::common::utils::id_to_leader_addr;
+        use crate::robot::utils::SCREEN_CONNECTION;
+        use std::sync::Arc;
+        use tokio::io::{split, AsyncReadExt};
+        use tokio::net::{TcpListener, TcpStream};
+        use tokio::sync::Mutex;
+
+        let leader = TcpListener::bind(id_to_leader_addr(3)).await.unwrap();
+        let payments_gateway = PaymentsGateway::new(1).start();
+        payments_gateway
+            .send(LeaderAnnounced::new(3, 0))
+            .await
+            .unwrap();
+
+        let old_leader = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(old_leader.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_, write_half) = split(stream);
+        let gateway = payments_gateway.clone();
+        let handler = RobotConnectionHandler::create(move |_| {
+            RobotConnectionHandler::new(Arc::new(Mutex::new(write_half)), gateway)
+        });
+        handler.send(CloseConnection()).await.unwrap();
+
+        let accepted = tokio::time::timeout(Duration::from_secs(5), leader.accept()).await;
+        let (mut stream, _) = accepted.unwrap().unwrap();
+        crate::protocol::read_version(&mut stream).await.unwrap();
+        let mut handshake = [0; 2];
+        stream.read_exact(&mut handshake).await.unwrap();
+        assert_eq!(handshake, [SCREEN_CONNECTION, 1]);
     }
 
     #[actix::test]
@@ -584,7 +2227,9 @@ mod tests {
             .await
             .unwrap()
             .unwrap();
-        let _ = payments_gateway.send(ConfirmOrder::new(next_order.0)).await;
+        let _ = payments_gateway
+            .send(ConfirmOrder::new(next_order.0, vec![]))
+            .await;
         let orders_received = payments_gateway.send(GetOrdersWaiting()).await.unwrap();
         assert_eq!(orders_received, vec![]);
     }
@@ -605,4 +2250,275 @@ mod tests {
         let orders_received = payments_gateway.send(GetOrdersWaiting()).await.unwrap();
         assert_eq!(orders_received, vec![]);
     }
+
+    #[actix::test]
+    async fn test_payments_gateway_retries_order_without_result_and_then_aborts() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let orders_waiting = ReceiveOrders::new(vec![Order::new_cucurucho(FlavorID::Chocolate)]);
+        payments_gateway.do_send(orders_waiting);
+        let next_order = payments_gateway
+            .send(CaptureNewOrder::new(0.5, "id5".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let max_retries = config::get().orders.max_retries;
+        for attempt in 0..=max_retries {
+            let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+            assert_eq!(captured, vec![next_order.0.clone()]);
+            let _ = payments_gateway
+                .send(OrderResultTimeout {
+                    id: next_order.0.clone(),
+                    attempt,
+                })
+                .await;
+        }
+        let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+        assert!(captured.is_empty());
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_aborts_order_rejected_too_many_times() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let orders_waiting = ReceiveOrders::new(vec![Order::new_cucurucho(FlavorID::Chocolate)]);
+        payments_gateway.do_send(orders_waiting);
+        let next_order = payments_gateway
+            .send(CaptureNewOrder::new(0.5, "id6".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        for _ in 0..config::get().orders.max_busy_retries {
+            let _ = payments_gateway
+                .send(OrderRejectedBusy::new(next_order.0.clone()))
+                .await;
+            let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+            assert_eq!(captured, vec![next_order.0.clone()]);
+        }
+        let _ = payments_gateway
+            .send(OrderRejectedBusy::new(next_order.0.clone()))
+            .await;
+        let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+        assert!(captured.is_empty());
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_keeps_orders_not_sent_only_on_transient_errors() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        // the test version of ReceiveOrders captures one of the orders by itself
+        let orders = vec![Order::new_cucurucho(FlavorID::Chocolate); 3];
+        payments_gateway.do_send(ReceiveOrders::new(orders.clone()));
+        let first = payments_gateway
+            .send(CaptureNewOrder::new(0.5, "id8".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let second = payments_gateway
+            .send(CaptureNewOrder::new(0.5, "id9".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let _ = payments_gateway
+            .send(OrdersNotSent::new(
+                vec![first.clone()],
+                FreddoError::Connection("reset".to_string()),
+            ))
+            .await;
+        let _ = payments_gateway
+            .send(OrdersNotSent::new(
+                vec![second.clone()],
+                FreddoError::Serialization("too big".to_string()),
+            ))
+            .await;
+        let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+        assert!(captured.contains(&first.0));
+        assert!(!captured.contains(&second.0));
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_forgets_released_orders() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let orders_waiting = ReceiveOrders::new(vec![Order::new_cucurucho(FlavorID::Chocolate)]);
+        payments_gateway.do_send(orders_waiting);
+        let next_order = payments_gateway
+            .send(CaptureNewOrder::new(0.5, "id7".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let _ = payments_gateway
+            .send(ReleaseOrders::new(vec!["other".to_string()]))
+            .await;
+        let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+        assert_eq!(captured, vec![next_order.0.clone()]);
+        let _ = payments_gateway
+            .send(ReleaseOrders::new(vec![next_order.0]))
+            .await;
+        let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+        assert!(captured.is_empty());
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_tracks_submitted_orders() {
+        let payments_gateway = PaymentsGateway::new(0)
+            .with_rng(simulation::seeded_rng(42, "GTW", 0))
+            .start();
+        let id = payments_gateway
+            .send(SubmitOrder::new(Order::new_cucurucho(FlavorID::Mint)))
+            .await
+            .unwrap()
+            .unwrap();
+        let status = |id: &str| payments_gateway.send(GetOrderStatus::new(id.to_string()));
+        assert_eq!(status(&id).await.unwrap(), Some(OrderStatus::Waiting));
+        assert_eq!(status("unknown").await.unwrap(), None);
+
+        let _ = payments_gateway.send(CaptureOrder()).await;
+        assert_eq!(
+            status(&id).await.unwrap(),
+            Some(OrderStatus::Captured { orders_ahead: None })
+        );
+        let _ = payments_gateway
+            .send(QueuePosition::new(id.clone(), Some(3)))
+            .await;
+        assert_eq!(
+            status(&id).await.unwrap(),
+            Some(OrderStatus::Captured {
+                orders_ahead: Some(3)
+            })
+        );
+        let substitutions = vec![Substitution {
+            flavor: FlavorID::Mint,
+            substitute: FlavorID::Lemon,
+        }];
+        let _ = payments_gateway
+            .send(ConfirmOrder::new(id.clone(), substitutions.clone()))
+            .await;
+        assert_eq!(
+            status(&id).await.unwrap(),
+            Some(OrderStatus::Confirmed {
+                substitutions,
+                pickup_code: Some("A001".to_string())
+            })
+        );
+    }
+
+    #[actix::test]
+    async fn test_confirmed_orders_are_picked_up_with_their_code() {
+        let payments_gateway = PaymentsGateway::new(1).start();
+        let id = payments_gateway
+            .send(SubmitOrder::new(Order::new_cucurucho(FlavorID::Mint)))
+            .await
+            .unwrap()
+            .unwrap();
+        let _ = payments_gateway
+            .send(ConfirmOrder::new(id.clone(), vec![]))
+            .await;
+        let pickups = payments_gateway.send(GetPickups()).await.unwrap();
+        assert_eq!(pickups.len(), 1);
+        assert_eq!(
+            (pickups[0].code.as_str(), &pickups[0].order_id),
+            ("B001", &id)
+        );
+
+        let pick_up = |code: &str| payments_gateway.send(PickUpOrder::new(code.to_string()));
+        assert!(pick_up("B002").await.unwrap().is_err());
+        assert_eq!(pick_up("b001").await.unwrap().unwrap().order_id, id);
+        assert!(pick_up("B001").await.unwrap().is_err());
+        assert_eq!(
+            payments_gateway
+                .send(GetOrderStatus::new(id))
+                .await
+                .unwrap(),
+            Some(OrderStatus::PickedUp)
+        );
+        assert!(payments_gateway
+            .send(GetPickups())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_rejects_stale_leaders() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let check = |epoch| payments_gateway.send(CheckLeaderEpoch::new(epoch));
+        assert_eq!(check(2).await.unwrap(), Ok(true));
+        assert_eq!(check(2).await.unwrap(), Ok(false));
+        assert_eq!(check(1).await.unwrap(), Err(2));
+        assert_eq!(check(3).await.unwrap(), Ok(true));
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_takes_each_backup_once() {
+        let payments_gateway = PaymentsGateway::new(0).start();
+        let backup = |screen_id, version, unless_alive| {
+            payments_gateway.send(HandleBackUp::new(
+                vec![Order::new_cucurucho(FlavorID::Chocolate)],
+                HashMap::new(),
+                Vec::new(),
+                Some(screen_id),
+                version,
+                unless_alive,
+            ))
+        };
+        assert!(backup(1, 5, false).await.unwrap());
+        assert!(!backup(1, 5, false).await.unwrap());
+        assert!(!backup(1, 4, false).await.unwrap());
+        assert!(backup(1, 6, false).await.unwrap());
+        assert!(!backup(0, 1, false).await.unwrap());
+
+        payments_gateway
+            .send(ReceiveGossip::new(vec![(2, 1)], None))
+            .await
+            .unwrap();
+        assert!(!backup(2, 1, true).await.unwrap());
+        assert!(backup(3, 1, true).await.unwrap());
+    }
+
+    #[actix::test]
+    async fn test_seeded_payments_gateway_declines_the_same_cards() {
+        tokio::time::pause();
+        let orders = vec![Order::new_cucurucho(FlavorID::Chocolate); 20];
+        let mut rng = simulation::seeded_rng(42, "GTW", 0);
+        // the first order is captured by the test version of ReceiveOrders
+        let declined = (1..orders.len())
+            .filter(|_| rng.gen_range(0.0..1.0) <= 0.1)
+            .count();
+
+        for _ in 0..2 {
+            let payments_gateway = PaymentsGateway::new(0)
+                .with_rng(simulation::seeded_rng(42, "GTW", 0))
+                .start();
+            let _ = payments_gateway
+                .send(ReceiveOrders::new(orders.clone()))
+                .await
+                .unwrap();
+            for _ in 1..orders.len() {
+                payments_gateway.send(CaptureOrder()).await.unwrap();
+            }
+            let captured = payments_gateway.send(GetOrdersCaptured()).await.unwrap();
+            assert_eq!(captured.len(), orders.len() - declined);
+        }
+    }
+
+    #[actix::test]
+    async fn test_payments_gateway_declines_the_cards_of_its_scenario() {
+        tokio::time::pause();
+        let payments_gateway = PaymentsGateway::new(0)
+            .with_payments(PaymentScenario::scripted(3, &[]))
+            .start();
+        let mut ids = vec![];
+        for _ in 0..6 {
+            let order = SubmitOrder::new(Order::new_cucurucho(FlavorID::Mint));
+            ids.push(payments_gateway.send(order).await.unwrap().unwrap());
+        }
+        for _ in 0..ids.len() {
+            payments_gateway.send(CaptureOrder()).await.unwrap();
+        }
+        let mut declined = vec![];
+        for id in &ids {
+            let status = payments_gateway.send(GetOrderStatus::new(id.clone()));
+            if status.await.unwrap() == Some(OrderStatus::Declined) {
+                declined.push(id.clone());
+            }
+        }
+        assert_eq!(declined, vec![ids[2].clone(), ids[5].clone()]);
+    }
 }
@@ -0,0 +1,184 @@
+//! Pickup of the orders of a screen.
+//! When the payment of an order is confirmed the screen gives it a short code, the customer shows it at the counter
+//! and the order is picked up. The codes start with the letters of the screen, A to Z and then AA, AB and so on,
+//! so two screens of a cluster do not hand out the same one. With leader shards the screens are numbered among
+//! the screens of every shard, so neither do two screens of different shards.
+//! The orders waiting to be picked up are not part of the history, a screen that restarts forgets them.
+//! When every code is taken the next one is given again, and the order that had it can not be picked up anymore.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tokio::time::Instant;
+
+/// Numbers a screen gives before it starts again from the first one
+pub const CODES_PER_SCREEN: u32 = 999;
+
+/// An order waiting to be picked up
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Pickup {
+    pub code: String,
+    pub order_id: String,
+    pub waiting_ms: u64,
+}
+
+/// Orders of a screen that are ready, by their pickup code
+#[derive(Debug)]
+pub struct PickupBoard {
+    prefix: String,
+    last: u32,
+    ready: BTreeMap<String, (String, Instant)>,
+    codes: HashMap<String, String>,
+}
+
+/// Returns the letters of the screen, numbering the screens as the columns of a spreadsheet
+fn prefix_of(screen_id: usize) -> String {
+    let mut letters = Vec::new();
+    let mut n = screen_id + 1;
+    while n > 0 {
+        n -= 1;
+        letters.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+impl PickupBoard {
+    pub fn new(screen_id: usize) -> Self {
+        Self {
+            prefix: prefix_of(screen_id),
+            last: 0,
+            ready: BTreeMap::new(),
+            codes: HashMap::new(),
+        }
+    }
+
+    fn next_code(&mut self) -> String {
+        self.last = self.last % CODES_PER_SCREEN + 1;
+        format!("{}{:03}", self.prefix, self.last)
+    }
+
+    /// Gives the order a code that no order waiting has, the same one if it already has a code.
+    /// If every code is taken, it takes the next one from the order that had it
+    pub fn assign(&mut self, order_id: &str) -> String {
+        if let Some(code) = self.codes.get(order_id) {
+            return code.clone();
+        }
+        let code = if self.ready.len() < CODES_PER_SCREEN as usize {
+            let mut code = self.next_code();
+            while self.ready.contains_key(&code) {
+                code = self.next_code();
+            }
+            code
+        } else {
+            let code = self.next_code();
+            if let Some((previous, _)) = self.ready.remove(&code) {
+                self.codes.remove(&previous);
+            }
+            code
+        };
+        self.ready
+            .insert(code.clone(), (order_id.to_string(), Instant::now()));
+        self.codes.insert(order_id.to_string(), code.clone());
+        code
+    }
+
+    /// Returns the code of the order, if it is waiting to be picked up
+    pub fn code_of(&self, order_id: &str) -> Option<&String> {
+        self.codes.get(order_id)
+    }
+
+    /// Hands out the order with the code, ignoring its case, and returns it with how long it waited
+    pub fn pick_up(&mut self, code: &str) -> Option<Pickup> {
+        let code = code.trim().to_uppercase();
+        let (order_id, ready_at) = self.ready.remove(&code)?;
+        self.codes.remove(&order_id);
+        Some(Pickup {
+            code,
+            order_id,
+            waiting_ms: ready_at.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Forgets the order, it is not going to be picked up
+    pub fn forget(&mut self, order_id: &str) {
+        if let Some(code) = self.codes.remove(order_id) {
+            self.ready.remove(&code);
+        }
+    }
+
+    /// Returns the orders waiting to be picked up, sorted by code
+    pub fn waiting(&self) -> Vec<Pickup> {
+        self.ready
+            .iter()
+            .map(|(code, (order_id, ready_at))| Pickup {
+                code: code.clone(),
+                order_id: order_id.clone(),
+                waiting_ms: ready_at.elapsed().as_millis() as u64,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::config::cluster::{ClusterConfig, MAX_CLUSTER_SIZE};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_each_order_gets_its_own_code() {
+        let mut board = PickupBoard::new(1);
+        assert_eq!(board.assign("1-1"), "B001");
+        assert_eq!(board.assign("1-2"), "B002");
+        assert_eq!(board.assign("1-1"), "B001");
+        assert_eq!(board.code_of("1-2"), Some(&"B002".to_string()));
+
+        let pickup = board.pick_up(" b001 ").unwrap();
+        assert_eq!(pickup.order_id, "1-1");
+        assert!(board.pick_up("B001").is_none());
+        assert_eq!(board.waiting().len(), 1);
+    }
+
+    #[test]
+    fn test_codes_of_waiting_orders_are_not_given_again() {
+        let mut board = PickupBoard::new(0);
+        for n in 1..=CODES_PER_SCREEN {
+            board.assign(&n.to_string());
+        }
+        board.pick_up("A002");
+        assert_eq!(board.assign("new"), "A002");
+        assert_eq!(board.assign("newer"), "A003");
+        assert!(board.code_of("3").is_none());
+        assert_eq!(board.waiting().len(), CODES_PER_SCREEN as usize);
+        board.forget("new");
+        assert!(board.code_of("new").is_none());
+        assert!(board.pick_up("A002").is_none());
+    }
+
+    #[test]
+    fn test_screens_past_the_alphabet_get_two_letters() {
+        assert_eq!(PickupBoard::new(0).assign("0-1"), "A001");
+        assert_eq!(PickupBoard::new(26).assign("26-1"), "AA001");
+        assert_eq!(prefix_of(25), "Z");
+        assert_eq!(prefix_of(52), "BA");
+
+        let prefixes: HashSet<String> = (0..MAX_CLUSTER_SIZE).map(prefix_of).collect();
+        assert_eq!(prefixes.len(), MAX_CLUSTER_SIZE);
+    }
+
+    #[test]
+    fn test_screens_of_two_shards_get_different_codes() {
+        let shard = |shard| ClusterConfig {
+            leader_shards: vec![vec![FlavorID::Mint], vec![FlavorID::Lemon]],
+            shard,
+            ..ClusterConfig::default()
+        };
+        let (first, second) = (shard(0), shard(1));
+        let codes: HashSet<String> = (0..first.screens)
+            .flat_map(|id| [first.screen_number(id), second.screen_number(id)])
+            .map(|number| PickupBoard::new(number).assign("0-1"))
+            .collect();
+        assert_eq!(codes.len(), 2 * first.screens);
+    }
+}
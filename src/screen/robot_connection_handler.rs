@@ -1,13 +1,25 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::common::error::FreddoError;
+use crate::common::log;
+use crate::common::metrics;
+use crate::common::utils::{retry_backoff, should_retry};
 use actix::prelude::*;
 use fut::wrap_future;
 
 use crate::common::order::Order;
-use crate::common::robot_messages::RobotMessage;
-use crate::common::screen_messages::ScreenMessage;
+use crate::common::order_source::OrderSource;
+use crate::config;
+use crate::protocol::robot_messages::RobotMessage;
+use crate::protocol::screen_messages::ScreenMessage;
+use crate::screen::failover_drill::CloseConnection;
 use crate::screen::payments_gateway::{
-    AbortOrder, ConfirmOrder, PaymentsGateway, RegisterRobotConnection,
+    AbortOrder, CheckLeaderEpoch, ConfirmOrder, IssueRefund, LeaderAnnounced, MenuInStock,
+    OrderRejectedBusy, OrdersNotSent, OrdersReclaimed, PaymentsGateway, QueuePosition,
+    RegisterRobotConnection, ReleaseOrders, RobotConnectionLost, StockLeft,
 };
 
 use tokio::io::{AsyncWriteExt, WriteHalf};
@@ -19,25 +31,35 @@ use super::payments_gateway::StartProcessingIfWaiting;
 /// RobotConnectionHandler is an actor that handles the connection between the robot and the screen.
 /// It receives messages from the robot, processes them and sends them to the PaymentsGateway actor.
 /// It also sends messages to the robot.
+/// The orders are not sent one by one, the ones that arrive within a short window are sent together in a batch.
+/// Every message to the robot is numbered, with a nonce of the connection, so the leader drops the ones it already got.
 pub struct RobotConnectionHandler {
     socket_write: Arc<Mutex<WriteHalf<TcpStream>>>,
     payments_gateway: Addr<PaymentsGateway>,
+    batch: Vec<(String, Order)>,
+    batch_sources: HashMap<String, OrderSource>,
+    batch_screen_id: usize,
+    nonce: u64,
+    last_seq: Arc<AtomicU64>,
 }
 
 impl Actor for RobotConnectionHandler {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        if let Err(err) = self
-            .payments_gateway
-            .try_send(RegisterRobotConnection::new(ctx.address()))
-        {
-            println!("Error sending message to payments gateway: {}", err);
-        }
+        self.register(ctx);
         if let Err(err) = self.payments_gateway.try_send(StartProcessingIfWaiting()) {
-            println!("Error sending message to payments gateway: {}", err);
+            log::error(
+                "RCH",
+                format!("Error sending message to payments gateway: {}", err),
+            );
         }
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.payments_gateway
+            .do_send(RobotConnectionLost::new(ctx.address()));
+    }
 }
 
 impl RobotConnectionHandler {
@@ -48,19 +70,70 @@ impl RobotConnectionHandler {
         RobotConnectionHandler {
             socket_write,
             payments_gateway,
+            batch: Vec::new(),
+            batch_sources: HashMap::new(),
+            batch_screen_id: 0,
+            // not drawn from the seeded generator, a connection with the nonce of an older one would be taken as a replay
+            nonce: rand::random(),
+            last_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Writes the message with the next number of the connection.
+    /// The number is taken once the connection is locked, so the messages are written in the order of their numbers
+    fn write_sequenced(
+        &self,
+        message: ScreenMessage,
+    ) -> impl std::future::Future<Output = Result<(), FreddoError>> {
+        let writer = self.socket_write.clone();
+        let nonce = self.nonce;
+        let last_seq = self.last_seq.clone();
+        async move {
+            let mut writer = writer.lock().await;
+            let msg = ScreenMessage::Sequenced {
+                nonce,
+                seq: last_seq.fetch_add(1, Ordering::Relaxed) + 1,
+                message: Box::new(message),
+            }
+            .to_bytes()
+            .map_err(|err| FreddoError::Serialization(err.to_string()))?;
+            writer.write_all(&msg).await?;
+            Ok(())
+        }
+    }
+
+    /// Sends the orders of the batch to the robot leader
+    /// If they can not be sent, they are given back to the PaymentsGateway with the error
+    fn flush_batch(&mut self, ctx: &mut Context<Self>) {
+        if self.batch.is_empty() {
+            return;
         }
+        let orders = std::mem::take(&mut self.batch);
+        let sources = std::mem::take(&mut self.batch_sources);
+        let payments_gateway = self.payments_gateway.clone();
+        let write = self.write_sequenced(prepare_message(
+            self.batch_screen_id,
+            orders.clone(),
+            sources,
+        ));
+        wrap_future::<_, Self>(async move {
+            if let Err(e) = write.await {
+                payments_gateway.do_send(OrdersNotSent::new(orders, e));
+            }
+        })
+        .spawn(ctx);
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for RobotConnectionHandler {
-    fn handle(&mut self, msg: Result<String, std::io::Error>, ctx: &mut Self::Context) {
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for RobotConnectionHandler {
+    fn handle(&mut self, msg: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
         if let Ok(msg) = msg {
             if ctx
                 .address()
                 .try_send(HandleRobotMsg { received_msg: msg })
                 .is_err()
             {
-                println!("Error sending msg to handler");
+                log::error("RCH", "Error sending msg to handler");
             }
         }
     }
@@ -69,34 +142,171 @@ impl StreamHandler<Result<String, std::io::Error>> for RobotConnectionHandler {
 /// Handle every message received from the robot.
 /// If the message is an OrderPrepared message, send a ConfirmOrder message to the PaymentsGateway.
 /// If the message is an OrderAborted message, send an AbortOrder message to the PaymentsGateway.
+/// If the message is an OrderAbortedAfterConfirm message, send an IssueRefund message to the PaymentsGateway.
+/// If the message is an OrderRejectedBusy message, send an OrderRejectedBusy message to the PaymentsGateway.
+/// If the message is an OrdersReleased message, send a ReleaseOrders message to the PaymentsGateway.
+/// Messages of a leader with an epoch older than the newest one known are not handled, the leader is told to step down.
 #[derive(Message)]
 #[rtype(result = "Result<(), String>")]
 pub struct HandleRobotMsg {
-    received_msg: String,
+    received_msg: Vec<u8>,
 }
 
 impl Handler<HandleRobotMsg> for RobotConnectionHandler {
     type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: HandleRobotMsg, _ctx: &mut Context<Self>) -> Self::Result {
-        match RobotMessage::from_string(&msg.received_msg).map_err(|err| err.to_string())? {
-            RobotMessage::OrderPrepared { order_id } => {
-                if let Err(err) = self.payments_gateway.try_send(ConfirmOrder::new(order_id)) {
-                    println!("Error sending message to payments gateway: {}", err);
-                }
-                Ok(())
-            }
-            RobotMessage::OrderAborted { order_id, error } => {
-                if let Err(err) = self
-                    .payments_gateway
-                    .try_send(AbortOrder::new(order_id, error))
-                {
-                    println!("Error sending message to payments gateway: {}", err);
+    fn handle(&mut self, msg: HandleRobotMsg, ctx: &mut Context<Self>) -> Self::Result {
+        let message = RobotMessage::from_bytes(&msg.received_msg).map_err(|err| err.to_string())?;
+        self.payments_gateway
+            .send(CheckLeaderEpoch::new(message.epoch()))
+            .into_actor(self)
+            .map(move |checked, actor, ctx| match checked {
+                Ok(Ok(newer)) => {
+                    if newer {
+                        actor.register(ctx);
+                    }
+                    actor.forward(message, ctx);
                 }
+                Ok(Err(epoch)) => actor.reject_stale_leader(epoch, ctx),
+                Err(err) => log::error(
+                    "RCH",
+                    format!("Error sending message to payments gateway: {}", err),
+                ),
+            })
+            .wait(ctx);
+        Ok(())
+    }
+}
+
+impl RobotConnectionHandler {
+    /// Registers this connection as the one to the robot leader
+    fn register(&self, ctx: &mut Context<Self>) {
+        if let Err(err) = self
+            .payments_gateway
+            .try_send(RegisterRobotConnection::new(ctx.address()))
+        {
+            log::error(
+                "RCH",
+                format!("Error sending message to payments gateway: {}", err),
+            );
+        }
+    }
+
+    /// Sends the message of the leader to the PaymentsGateway, a Ping is answered to the leader
+    fn forward(&self, message: RobotMessage, ctx: &mut Context<Self>) {
+        let sent: Result<(), FreddoError> = match message {
+            RobotMessage::Ping { epoch } => {
+                self.send_message(ScreenMessage::Pong { epoch }, 0, ctx);
                 Ok(())
             }
+            RobotMessage::NewLeader { leader_id, epoch } => self
+                .payments_gateway
+                .try_send(LeaderAnnounced::new(leader_id, epoch))
+                .map_err(FreddoError::from),
+            RobotMessage::LeaderTransferred {
+                leader_id,
+                next_epoch,
+                ..
+            } => self
+                .payments_gateway
+                .try_send(LeaderAnnounced::new(leader_id, next_epoch))
+                .map_err(FreddoError::from),
+            RobotMessage::OrderPrepared {
+                order_id,
+                substitutions,
+                ..
+            } => self
+                .payments_gateway
+                .try_send(ConfirmOrder::new(order_id, substitutions))
+                .map_err(FreddoError::from),
+            RobotMessage::OrderAborted {
+                order_id, error, ..
+            } => self
+                .payments_gateway
+                .try_send(AbortOrder::new(order_id, error))
+                .map_err(FreddoError::from),
+            RobotMessage::OrderAbortedAfterConfirm {
+                order_id, error, ..
+            } => self
+                .payments_gateway
+                .try_send(IssueRefund::new(order_id, error))
+                .map_err(FreddoError::from),
+            RobotMessage::OrderRejectedBusy { order_id, .. } => self
+                .payments_gateway
+                .try_send(OrderRejectedBusy::new(order_id))
+                .map_err(FreddoError::from),
+            RobotMessage::OrdersReleased { order_ids, .. } => self
+                .payments_gateway
+                .try_send(ReleaseOrders::new(order_ids))
+                .map_err(FreddoError::from),
+            RobotMessage::QueueStatus {
+                order_id,
+                orders_ahead,
+                ..
+            } => self
+                .payments_gateway
+                .try_send(QueuePosition::new(order_id, orders_ahead))
+                .map_err(FreddoError::from),
+            RobotMessage::OrdersReclaimed {
+                taken_from,
+                finished,
+                ..
+            } => self
+                .payments_gateway
+                .try_send(OrdersReclaimed::new(taken_from, finished))
+                .map_err(FreddoError::from),
+            RobotMessage::Menu { in_stock, .. } => self
+                .payments_gateway
+                .try_send(MenuInStock::new(in_stock))
+                .map_err(FreddoError::from),
+            RobotMessage::StockSnapshot { grams, .. } => self
+                .payments_gateway
+                .try_send(StockLeft::new(grams))
+                .map_err(FreddoError::from),
+        };
+        if let Err(err) = sent {
+            log::error(
+                "RCH",
+                format!("Error sending message to payments gateway: {}", err),
+            );
         }
     }
+
+    /// Tells a leader older than the newest one known that it has to step down
+    fn reject_stale_leader(&self, epoch: u64, ctx: &mut Context<Self>) {
+        self.send_message(ScreenMessage::StaleLeader { epoch }, 0, ctx);
+    }
+
+    /// Writes a message to the robot leader, if it fails it is written again following the retry policy
+    fn send_message(&self, message: ScreenMessage, attempt: u32, ctx: &mut Context<Self>) {
+        wrap_future::<_, Self>(self.write_sequenced(message.clone()))
+            .map(move |written, _, ctx| {
+                let err = match written {
+                    Ok(()) => return,
+                    Err(FreddoError::Serialization(err)) => {
+                        log::error("RCH", format!("Error encoding message: {}", err));
+                        return;
+                    }
+                    Err(err) => err,
+                };
+                if !should_retry(attempt) {
+                    let line = format!("Could not send {:?} to the robot leader: {}", message, err);
+                    log::error("RCH", line);
+                    return;
+                }
+                let line = format!(
+                    "Could not send a message to the robot leader: {}, retrying (attempt {})",
+                    err,
+                    attempt + 1
+                );
+                log::warn("RCH", line);
+                metrics::get().retried("screen_message");
+                ctx.run_later(retry_backoff(attempt), move |actor, ctx| {
+                    actor.send_message(message, attempt + 1, ctx)
+                });
+            })
+            .spawn(ctx);
+    }
 }
 
 /// SendOrderToRobotLeader is a message that tells the RobotConnectionHandler actor to send an order to the robot leader.
@@ -106,14 +316,21 @@ pub struct SendOrderToRobotLeader {
     order: Order,
     id_order: String,
     id_screen: usize,
+    source: Option<OrderSource>,
 }
 
 impl SendOrderToRobotLeader {
-    pub fn new(order: Order, id_order: String, id_screen: usize) -> SendOrderToRobotLeader {
+    pub fn new(
+        order: Order,
+        id_order: String,
+        id_screen: usize,
+        source: Option<OrderSource>,
+    ) -> SendOrderToRobotLeader {
         SendOrderToRobotLeader {
             order,
             id_order,
             id_screen,
+            source,
         }
     }
 }
@@ -122,37 +339,50 @@ impl Handler<SendOrderToRobotLeader> for RobotConnectionHandler {
     type Result = ();
 
     fn handle(&mut self, msg: SendOrderToRobotLeader, _ctx: &mut Context<Self>) -> Self::Result {
-        let msg = match prepare_message(msg) {
-            Some(value) => value,
-            None => return,
-        };
-        let arc = self.socket_write.clone();
-        wrap_future::<_, Self>(async move {
-            arc.lock()
-                .await
-                .write_all(msg.as_bytes())
-                .await
-                .expect("should have sent")
-        })
-        .spawn(_ctx);
+        let orders_config = &config::get().orders;
+        self.batch_screen_id = msg.id_screen;
+        if let Some(source) = msg.source {
+            self.batch_sources.insert(msg.id_order.clone(), source);
+        }
+        self.batch.push((msg.id_order, msg.order));
+
+        if self.batch.len() >= orders_config.batch_size || orders_config.batch_window_ms == 0 {
+            self.flush_batch(_ctx);
+        } else if self.batch.len() == 1 {
+            _ctx.run_later(
+                Duration::from_millis(orders_config.batch_window_ms),
+                |actor, ctx| actor.flush_batch(ctx),
+            );
+        }
     }
 }
 
-/// Receives a SendOrderToRobotLeader message and prepares the message to be sent to the robot leader.
-fn prepare_message(msg: SendOrderToRobotLeader) -> Option<String> {
-    let msg = ScreenMessage::PrepareNewOrder {
-        order_id: msg.id_order,
-        order: msg.order,
-        screen_id: msg.id_screen,
-    };
-    let msg = match msg.to_string() {
-        Ok(msg) => msg + "\n",
-        Err(err) => {
-            println!("Error converting message to string: {}", err);
-            return None;
+/// Prepares the message with the orders to be sent to the robot leader, with the deadline of the config
+/// and the sources of the orders that have one.
+/// A single order is sent as a PrepareNewOrder message, more than one as a PrepareNewOrderBatch
+fn prepare_message(
+    screen_id: usize,
+    mut orders: Vec<(String, Order)>,
+    mut sources: HashMap<String, OrderSource>,
+) -> ScreenMessage {
+    let deadline_ms = config::get().orders.deadline_ms;
+    if orders.len() == 1 {
+        let (order_id, order) = orders.remove(0);
+        ScreenMessage::PrepareNewOrder {
+            source: sources.remove(&order_id),
+            order_id,
+            order,
+            screen_id,
+            deadline_ms,
+        }
+    } else {
+        ScreenMessage::PrepareNewOrderBatch {
+            screen_id,
+            orders,
+            deadline_ms,
+            sources,
         }
-    };
-    Some(msg)
+    }
 }
 
 /// SendRequestToRobotLeader is a message that tells the RobotConnectionHandler actor to send a request to the robot leader.
@@ -174,19 +404,7 @@ impl Handler<SendRequestToRobotLeader> for RobotConnectionHandler {
 
     fn handle(&mut self, msg: SendRequestToRobotLeader, _ctx: &mut Context<Self>) -> Self::Result {
         let message = ScreenMessage::RequestRobotLeaderConnection { screen_id: msg.id };
-        let msg = match message.to_string() {
-            Ok(msg) => msg,
-            Err(err) => {
-                println!("Error converting message to string: {}", err);
-                return;
-            }
-        };
-        let msg = msg + "\n";
-        let writer = self.socket_write.clone();
-        wrap_future::<_, Self>(async move {
-            let _ = writer.lock().await.write_all(msg.as_bytes()).await;
-        })
-        .spawn(_ctx);
+        self.send_message(message, 0, _ctx);
     }
 }
 
@@ -216,18 +434,170 @@ impl Handler<AskRobotForScreenOrders> for RobotConnectionHandler {
             my_id: msg.new_screen_id,
             death_id: msg.death_screen_id,
         };
-        let msg = match message.to_string() {
-            Ok(msg) => msg,
-            Err(err) => {
-                println!("Error converting message to string: {}", err);
-                return;
-            }
+        self.send_message(message, 0, _ctx);
+    }
+}
+
+/// SendScreenRejoined is a message that tells the RobotConnectionHandler actor to announce the screen to the robot leader.
+/// It carries the ids of the orders the screen has captured, so the leader gives them back to this screen if another one took them.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendScreenRejoined {
+    screen_id: usize,
+    order_ids: Vec<String>,
+}
+
+impl SendScreenRejoined {
+    pub fn new(screen_id: usize, order_ids: Vec<String>) -> SendScreenRejoined {
+        SendScreenRejoined {
+            screen_id,
+            order_ids,
+        }
+    }
+}
+
+impl Handler<SendScreenRejoined> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendScreenRejoined, _ctx: &mut Context<Self>) -> Self::Result {
+        let message = ScreenMessage::ScreenRejoined {
+            screen_id: msg.screen_id,
+            order_ids: msg.order_ids,
+        };
+        self.send_message(message, 0, _ctx);
+    }
+}
+
+/// SendQueryStatus is a message that tells the RobotConnectionHandler actor to ask the robot leader how many orders are ahead of an order.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendQueryStatus {
+    screen_id: usize,
+    order_id: String,
+}
+
+impl SendQueryStatus {
+    pub fn new(screen_id: usize, order_id: String) -> SendQueryStatus {
+        SendQueryStatus {
+            screen_id,
+            order_id,
+        }
+    }
+}
+
+impl Handler<SendQueryStatus> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendQueryStatus, ctx: &mut Context<Self>) -> Self::Result {
+        let message = ScreenMessage::QueryStatus {
+            screen_id: msg.screen_id,
+            order_id: msg.order_id,
         };
-        let msg = msg + "\n";
+        self.send_message(message, 0, ctx);
+    }
+}
+
+/// SendOrderPickedUp is a message that tells the RobotConnectionHandler actor to tell the robot leader that an order was picked up.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendOrderPickedUp {
+    screen_id: usize,
+    order_id: String,
+    waited_ms: u64,
+}
+
+impl SendOrderPickedUp {
+    pub fn new(screen_id: usize, order_id: String, waited_ms: u64) -> SendOrderPickedUp {
+        SendOrderPickedUp {
+            screen_id,
+            order_id,
+            waited_ms,
+        }
+    }
+}
+
+impl Handler<SendOrderPickedUp> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendOrderPickedUp, ctx: &mut Context<Self>) -> Self::Result {
+        let message = ScreenMessage::OrderPickedUp {
+            screen_id: msg.screen_id,
+            order_id: msg.order_id,
+            waited_ms: msg.waited_ms,
+        };
+        self.send_message(message, 0, ctx);
+    }
+}
+
+/// SendQueryMenu is a message that tells the RobotConnectionHandler actor to ask the robot leader which flavors have stock.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendQueryMenu {
+    screen_id: usize,
+}
+
+impl SendQueryMenu {
+    pub fn new(screen_id: usize) -> SendQueryMenu {
+        SendQueryMenu { screen_id }
+    }
+}
+
+impl Handler<SendQueryMenu> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendQueryMenu, ctx: &mut Context<Self>) -> Self::Result {
+        let message = ScreenMessage::QueryMenu {
+            screen_id: msg.screen_id,
+        };
+        self.send_message(message, 0, ctx);
+    }
+}
+
+/// Closes the connection with the robot leader once the messages being written are sent
+impl Handler<CloseConnection> for RobotConnectionHandler {
+    type Result = ();
+
+    fn handle(&mut self, _msg: CloseConnection, ctx: &mut Context<Self>) -> Self::Result {
         let writer = self.socket_write.clone();
         wrap_future::<_, Self>(async move {
-            let _ = writer.lock().await.write_all(msg.as_bytes()).await;
+            let _ = writer.lock().await.shutdown().await;
         })
-        .spawn(_ctx);
+        .map(|_, _, ctx| ctx.stop())
+        .wait(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::flavor_id::FlavorID;
+    use crate::common::order_source::OrderChannel;
+
+    #[test]
+    fn test_several_orders_are_sent_in_a_batch() {
+        let order = Order::new_cucurucho(FlavorID::Mint);
+        let source = OrderSource::new(1, OrderChannel::Terminal);
+        let single = prepare_message(
+            1,
+            vec![("a".to_string(), order.clone())],
+            HashMap::from([("a".to_string(), source.clone())]),
+        );
+        assert!(matches!(
+            single,
+            ScreenMessage::PrepareNewOrder { source: Some(s), .. } if s == source
+        ));
+
+        let orders = vec![("a".to_string(), order.clone()), ("b".to_string(), order)];
+        let sources = HashMap::from([("b".to_string(), source)]);
+        let batch = prepare_message(1, orders.clone(), sources.clone());
+        assert_eq!(
+            batch,
+            ScreenMessage::PrepareNewOrderBatch {
+                screen_id: 1,
+                orders,
+                deadline_ms: None,
+                sources,
+            }
+        );
     }
 }
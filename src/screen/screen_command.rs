@@ -0,0 +1,194 @@
+use std::str::FromStr;
+
+use crate::common::flavor_id::FlavorID;
+use crate::common::order::Order;
+use crate::config;
+
+pub const HELP: &str = "Commands:
+  p                                   process the orders of the file
+  w                                   watch the file for new orders
+  order cucurucho <flavor>            add a cucurucho
+  order cuarto <flavor> [x2]          add a cuarto with up to 2 flavors
+  order medio <flavor> [x3]           add a medio with up to 3 flavors
+  order kilo <flavor> [x4]            add a kilo with up to 4 flavors
+  history [n]                         show the last n orders handled, 10 by default
+  drill [secs]                        play dead for secs seconds, 10 by default, to rehearse a failover
+  pickups                             show the orders waiting to be picked up
+  pickup <code>                       hand out the order with the pickup code
+  help                                show this message";
+
+/// Orders shown by the history command when no amount is given
+pub const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// Seconds a failover drill lasts when no amount is given
+pub const DEFAULT_DRILL_SECS: u64 = 10;
+
+/// Command typed by the operator on the screen's terminal
+#[derive(Debug, PartialEq)]
+pub enum ScreenCommand {
+    ProcessFile,
+    Watch,
+    NewOrder(Order),
+    History(usize),
+    Drill(u64),
+    Pickups,
+    PickUp(String),
+    Help,
+}
+
+impl FromStr for ScreenCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["p"] => Ok(ScreenCommand::ProcessFile),
+            ["w"] => Ok(ScreenCommand::Watch),
+            ["help"] => Ok(ScreenCommand::Help),
+            ["history"] => Ok(ScreenCommand::History(DEFAULT_HISTORY_LIMIT)),
+            ["history", limit] => limit
+                .parse()
+                .map(ScreenCommand::History)
+                .map_err(|_| format!("Invalid amount of orders: {}", limit)),
+            ["drill"] => Ok(ScreenCommand::Drill(DEFAULT_DRILL_SECS)),
+            ["drill", secs] => match secs.parse() {
+                Ok(0) | Err(_) => Err(format!("Invalid amount of seconds: {}", secs)),
+                Ok(secs) => Ok(ScreenCommand::Drill(secs)),
+            },
+            ["pickups"] => Ok(ScreenCommand::Pickups),
+            ["pickup", code] => Ok(ScreenCommand::PickUp(code.to_string())),
+            ["order", kind, flavors @ ..] => {
+                parse_order(kind, flavors).map(ScreenCommand::NewOrder)
+            }
+            _ => Err(format!("Unknown command: {}", s.trim())),
+        }
+    }
+}
+
+/// Parses an order of the given kind, a cucurucho has exactly one flavor and the other kinds split their grams between theirs
+fn parse_order(kind: &str, flavors: &[&str]) -> Result<Order, String> {
+    let flavors = flavors
+        .iter()
+        .map(|flavor| parse_flavor(flavor))
+        .collect::<Result<Vec<FlavorID>, String>>()?;
+    new_order(kind, flavors)
+}
+
+/// Creates an order of the given kind, as typed on the terminal, with the flavors
+pub fn new_order(kind: &str, flavors: Vec<FlavorID>) -> Result<Order, String> {
+    let order = match kind.to_lowercase().as_str() {
+        "cucurucho" => match flavors.as_slice() {
+            [flavor] => Ok(Order::new_cucurucho(flavor.clone())),
+            _ => return Err("A cucurucho needs 1 flavor".to_string()),
+        },
+        "cuarto" => Order::new_cuarto(flavors),
+        "medio" => Order::new_medio(flavors),
+        "kilo" => Order::new_kilo(flavors),
+        _ => return Err(format!("Unknown order type: {}", kind)),
+    };
+    order.map_err(|err| err.to_string())
+}
+
+/// Parses a flavor name, ignoring case and underscores
+/// Parses a standard flavor or a custom one of the catalog, ignoring case and underscores
+pub fn parse_flavor(name: &str) -> Result<FlavorID, String> {
+    let normalize = |name: &str| name.to_lowercase().replace('_', "");
+    let wanted = normalize(name);
+    let custom = config::get()
+        .flavors
+        .flavors()
+        .iter()
+        .map(|stock| stock.flavor.clone());
+    FlavorID::STANDARD
+        .into_iter()
+        .chain(custom)
+        .find(|flavor| normalize(flavor.name()) == wanted)
+        .ok_or_else(|| format!("Unknown flavor: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kilo_order() {
+        let command = "order kilo chocolate vanilla mint lemon".parse::<ScreenCommand>();
+        let expected = Order::new_kilo(vec![
+            FlavorID::Chocolate,
+            FlavorID::Vanilla,
+            FlavorID::Mint,
+            FlavorID::Lemon,
+        ])
+        .unwrap();
+        assert_eq!(command, Ok(ScreenCommand::NewOrder(expected)));
+    }
+
+    #[test]
+    fn test_parse_cucurucho_order() {
+        let command = "order Cucurucho dulce_de_leche".parse::<ScreenCommand>();
+        assert_eq!(
+            command,
+            Ok(ScreenCommand::NewOrder(Order::new_cucurucho(
+                FlavorID::DulceDeLeche
+            )))
+        );
+    }
+
+    #[test]
+    fn test_wrong_amount_of_flavors_fails() {
+        assert!("order cucurucho mint lemon"
+            .parse::<ScreenCommand>()
+            .is_err());
+        assert!("order cuarto".parse::<ScreenCommand>().is_err());
+        assert!("order kilo mint lemon vanilla chocolate pistachio"
+            .parse::<ScreenCommand>()
+            .is_err());
+        assert!("order kilo mint mint mint mint mint"
+            .parse::<ScreenCommand>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_parse_history() {
+        assert_eq!(
+            "history".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::History(DEFAULT_HISTORY_LIMIT))
+        );
+        assert_eq!(
+            "history 3".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::History(3))
+        );
+        assert!("history all".parse::<ScreenCommand>().is_err());
+    }
+
+    #[test]
+    fn test_parse_drill() {
+        assert_eq!(
+            "drill".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::Drill(DEFAULT_DRILL_SECS))
+        );
+        assert_eq!(
+            "drill 3".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::Drill(3))
+        );
+        assert!("drill 0".parse::<ScreenCommand>().is_err());
+    }
+
+    #[test]
+    fn test_parse_pickup() {
+        assert_eq!(
+            "pickups".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::Pickups)
+        );
+        assert_eq!(
+            "pickup A001".parse::<ScreenCommand>(),
+            Ok(ScreenCommand::PickUp("A001".to_string()))
+        );
+        assert!("pickup".parse::<ScreenCommand>().is_err());
+    }
+
+    #[test]
+    fn test_unknown_flavor_fails() {
+        assert!("order cucurucho banana".parse::<ScreenCommand>().is_err());
+    }
+}
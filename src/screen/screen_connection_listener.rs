@@ -1,20 +1,31 @@
 use actix::prelude::*;
 
-use crate::common::screen_messages::ScreenMessage;
+use crate::common::log;
+use crate::protocol::screen_messages::ScreenMessage;
 use crate::screen::backup_handler::SendBackupToGateway;
 
 use super::backup_handler::{BackUpHandler, SaveBackup};
-use super::payments_gateway::{PaymentsGateway, SendRequestFromScreen};
+use super::failover_drill::CloseConnection;
+use super::payments_gateway::{
+    PaymentsGateway, ReceiveGossip, RegisterScreenListener, SendRequestFromScreen,
+};
 
 /// ScreenConnectionListener is an actor that listens to the connection with the previous screen.
 /// It receives backups from the previous screen and sends them to the BackUpHandler actor.
+/// It learns the id of the previous screen from its messages, so its backup is the one taken over when it disconnects.
 pub struct ScreenConnectionListener {
     backup_handler: Addr<BackUpHandler>,
     payments_gateway: Addr<PaymentsGateway>,
+    peer: Option<usize>,
 }
 
 impl Actor for ScreenConnectionListener {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.payments_gateway
+            .do_send(RegisterScreenListener::new(ctx.address()));
+    }
 }
 
 impl ScreenConnectionListener {
@@ -25,58 +36,68 @@ impl ScreenConnectionListener {
         ScreenConnectionListener {
             backup_handler,
             payments_gateway,
+            peer: None,
         }
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for ScreenConnectionListener {
-    fn handle(&mut self, msg: Result<String, std::io::Error>, ctx: &mut Self::Context) {
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for ScreenConnectionListener {
+    fn handle(&mut self, msg: Result<Vec<u8>, std::io::Error>, ctx: &mut Self::Context) {
         if let Ok(msg) = msg {
             if ctx
                 .address()
                 .try_send(HandleScreenMsg { received_msg: msg })
                 .is_err()
             {
-                println!("Error sending msg to handler");
+                log::error("SCL", "Error sending msg to handler");
             }
         } else {
-            println!("Error reading message from screen. finishing");
+            log::error("SCL", "Error reading message from screen. finishing");
             if self
                 .backup_handler
-                .try_send(SendBackupToGateway::new())
+                .try_send(SendBackupToGateway::new(self.peer))
                 .is_err()
             {
-                println!("Error sending backup to gateway");
+                log::error("SCL", "Error sending backup to gateway");
             }
             ctx.stop();
         }
     }
 
     fn finished(&mut self, _ctx: &mut Self::Context) {
-        self.backup_handler.do_send(SendBackupToGateway::new())
+        self.backup_handler
+            .do_send(SendBackupToGateway::new(self.peer))
     }
 }
 
 /// HandleScreenMsg is a message that tells the ScreenConnectionListener actor to handle a message from the screen.
 /// The message is a string that is parsed into a ScreenMessage.
-/// This could be a backup message or a request from the previous robot for a connection with the robot leader.
+/// This could be a backup message, compressed or not, a request from the previous robot for a connection with the robot leader,
+/// or the gossip of what the previous screen knows of the cluster.
 #[derive(Message)]
 #[rtype(result = "Result<(), String>")]
 pub struct HandleScreenMsg {
-    received_msg: String,
+    received_msg: Vec<u8>,
 }
 
 impl Handler<HandleScreenMsg> for ScreenConnectionListener {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: HandleScreenMsg, _ctx: &mut Context<Self>) -> Self::Result {
-        match ScreenMessage::from_string(&msg.received_msg).map_err(|err| err.to_string())? {
+        let message = ScreenMessage::from_bytes(&msg.received_msg)
+            .and_then(ScreenMessage::decompress)
+            .map_err(|err| err.to_string())?;
+        match message {
             ScreenMessage::TakeMyBackup {
                 orders_processing,
                 orders_to_process,
                 id_backup,
                 orders_pending_to_send,
+                version,
+                hops_left,
+                sender,
             } => {
+                self.peer = Some(sender);
                 if self
                     .backup_handler
                     .try_send(SaveBackup::new(
@@ -84,10 +105,12 @@ impl Handler<HandleScreenMsg> for ScreenConnectionListener {
                         orders_processing,
                         orders_pending_to_send,
                         id_backup,
+                        version,
+                        hops_left,
                     ))
                     .is_err()
                 {
-                    println!("Error sending backup to handler");
+                    log::error("SCL", "Error sending backup to handler");
                 }
                 Ok(())
             }
@@ -97,14 +120,38 @@ impl Handler<HandleScreenMsg> for ScreenConnectionListener {
                     .try_send(SendRequestFromScreen::new(screen_id))
                     .is_err()
                 {
-                    println!("Error sending request to payments gateway");
+                    log::error("SCL", "Error sending request to payments gateway");
+                }
+                Ok(())
+            }
+            ScreenMessage::MembershipGossip {
+                screen_id,
+                members,
+                leader,
+            } => {
+                self.peer = Some(screen_id);
+                if self
+                    .payments_gateway
+                    .try_send(ReceiveGossip::new(members, leader))
+                    .is_err()
+                {
+                    log::error("SCL", "Error sending gossip to payments gateway");
                 }
                 Ok(())
             }
             _ => {
-                println!("Message not recognized");
+                log::error("SCL", "Message not recognized");
                 Ok(())
             }
         }
     }
 }
+
+/// Closes the connection with the previous screen, without taking over its backup
+impl Handler<CloseConnection> for ScreenConnectionListener {
+    type Result = ();
+
+    fn handle(&mut self, _msg: CloseConnection, ctx: &mut Context<Self>) -> Self::Result {
+        ctx.stop();
+    }
+}
@@ -1,27 +1,32 @@
-use std::collections::HashMap;
+use crate::common::codec;
+use crate::common::log;
 use std::sync::Arc;
 
 use actix::prelude::*;
 use actix::ActorFutureExt;
 use fut::wrap_future;
 
-use crate::common::order::Order;
-use crate::common::screen_messages::ScreenMessage;
-use crate::config::MAX_NUMBER_OF_SCREENS;
+use crate::common::metrics;
+use crate::config;
+use crate::protocol::compression::Compression;
+use crate::protocol::screen_messages::{BackupView, ScreenMessage, ScreenMessageError};
 use crate::screen::payments_gateway::{PaymentsGateway, RegisterScreenConnection};
 use tokio::io::{AsyncWriteExt, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 use super::communication::connect_to_following_screen;
+use super::failover_drill::CloseConnection;
 use super::payments_gateway::SendBackupToNewScreen;
 
 /// ScreenConnectionSender is an actor that sends messages to the next screen.
-/// It sends backups to the next screen.
+/// It sends backups to the next screen, the ones of this screen and the ones it relays.
+/// They are compressed once the next screen tells it can read the compression of the config.
 pub struct ScreenConnectionSender {
     my_id: usize,
     socket_write: Arc<Mutex<WriteHalf<TcpStream>>>,
     payments_gateway: Addr<PaymentsGateway>,
+    compression: Compression,
 }
 
 impl Actor for ScreenConnectionSender {
@@ -44,18 +49,30 @@ impl ScreenConnectionSender {
             my_id,
             socket_write,
             payments_gateway,
+            compression: Compression::None,
         }
     }
 }
 
-impl StreamHandler<Result<String, std::io::Error>> for ScreenConnectionSender {
-    fn handle(&mut self, _msg: Result<String, std::io::Error>, _ctx: &mut Self::Context) {}
+impl StreamHandler<Result<Vec<u8>, std::io::Error>> for ScreenConnectionSender {
+    fn handle(&mut self, msg: Result<Vec<u8>, std::io::Error>, _ctx: &mut Self::Context) {
+        let Ok(frame) = msg else {
+            return;
+        };
+        if let Ok(ScreenMessage::AcceptsCompression { compressions }) =
+            ScreenMessage::from_bytes(&frame)
+        {
+            self.compression =
+                Compression::negotiate(config::get().backup.compression, &compressions);
+            let line = format!("Backups are sent with compression {:?}", self.compression);
+            log::debug("SCS", line);
+        }
+    }
     fn finished(&mut self, ctx: &mut Self::Context) {
         let my_id = self.my_id;
-        let following = (my_id + 1) % MAX_NUMBER_OF_SCREENS;
         let payments_gateway = self.payments_gateway.clone();
         async move {
-            connect_to_following_screen(following, payments_gateway, my_id).await;
+            connect_to_following_screen(my_id, payments_gateway).await;
         }
         .into_actor(self)
         .map(|_, _, ctx| {
@@ -66,28 +83,20 @@ impl StreamHandler<Result<String, std::io::Error>> for ScreenConnectionSender {
 }
 
 /// SendMyBackup is a message that tells the ScreenConnectionSender actor to send a backup to the next screen.
-#[derive(Message)]
+/// The backup is of the screen `id_backup`, this one or one whose backup is relayed, and the next screen relays it
+/// `hops_left` more times. An empty backup is sent too, so the replicas forget the orders that finished.
+/// It is encoded when it is made, from the orders the screen keeps, so they are not copied for every backup
+#[derive(Message, Clone, Debug, PartialEq)]
 #[rtype(result = "()")]
 pub struct SendMyBackup {
-    pub orders_to_process: Vec<Order>,
-    pub orders_processing: HashMap<String, Order>,
-    pub orders_pending_to_send: Vec<(String, Order)>,
-    pub id_backup: usize,
+    encoded: Vec<u8>,
 }
 
 impl SendMyBackup {
-    pub fn new(
-        orders_to_process: Vec<Order>,
-        orders_processing: HashMap<String, Order>,
-        orders_pending_to_send: Vec<(String, Order)>,
-        id_backup: usize,
-    ) -> SendMyBackup {
-        SendMyBackup {
-            orders_to_process,
-            orders_processing,
-            id_backup,
-            orders_pending_to_send,
-        }
+    pub fn new(backup: &BackupView) -> Result<SendMyBackup, ScreenMessageError> {
+        Ok(SendMyBackup {
+            encoded: backup.encode()?,
+        })
     }
 }
 
@@ -95,34 +104,78 @@ impl Handler<SendMyBackup> for ScreenConnectionSender {
     type Result = ();
 
     fn handle(&mut self, msg: SendMyBackup, _ctx: &mut Context<Self>) -> Self::Result {
-        if msg.orders_processing.is_empty()
-            && msg.orders_to_process.is_empty()
-            && msg.orders_pending_to_send.is_empty()
-        {
-            return;
-        }
-        let msg = ScreenMessage::TakeMyBackup {
-            orders_to_process: msg.orders_to_process,
-            orders_processing: msg.orders_processing,
-            orders_pending_to_send: msg.orders_pending_to_send,
-            id_backup: msg.id_backup,
+        let raw = msg.encoded.len();
+        let encoded = match self.compression {
+            Compression::None => codec::encode_bincode::<ScreenMessage>(&msg.encoded)
+                .map_err(|err| ScreenMessageError::ErrorParsing(err.to_string())),
+            compression => ScreenMessage::compressed(&msg.encoded, compression)
+                .and_then(|compressed| compressed.to_bytes()),
         };
-        let msg = match msg.to_string() {
-            Ok(string) => string,
+        let msg = match encoded {
+            Ok(bytes) => {
+                metrics::get().backup_sent("screen", raw, bytes.len());
+                bytes
+            }
             Err(err) => {
-                println!("Error converting message to string: {}", err);
+                log::error("SCS", format!("Error encoding message: {}", err));
                 return;
             }
         };
-        let msg = msg + "\n";
         let writer = self.socket_write.clone();
         wrap_future::<_, Self>(async move {
-            let _ = writer.lock().await.write_all(msg.as_bytes()).await;
+            let _ = writer.lock().await.write_all(&msg).await;
         })
         .spawn(_ctx);
     }
 }
 
+/// SendGossip is a message that tells the ScreenConnectionSender actor to send what the screen knows of the cluster to the next screen.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendGossip {
+    screen_id: usize,
+    members: Vec<(usize, u64)>,
+    leader: Option<(usize, u64)>,
+}
+
+impl SendGossip {
+    pub fn new(
+        screen_id: usize,
+        members: Vec<(usize, u64)>,
+        leader: Option<(usize, u64)>,
+    ) -> SendGossip {
+        SendGossip {
+            screen_id,
+            members,
+            leader,
+        }
+    }
+}
+
+impl Handler<SendGossip> for ScreenConnectionSender {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendGossip, ctx: &mut Context<Self>) -> Self::Result {
+        let message = ScreenMessage::MembershipGossip {
+            screen_id: msg.screen_id,
+            members: msg.members,
+            leader: msg.leader,
+        };
+        let msg = match message.to_bytes() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                log::error("SCS", format!("Error encoding message: {}", err));
+                return;
+            }
+        };
+        let writer = self.socket_write.clone();
+        wrap_future::<_, Self>(async move {
+            let _ = writer.lock().await.write_all(&msg).await;
+        })
+        .spawn(ctx);
+    }
+}
+
 /// RequestRobotLeaderConnection is a message that tells the ScreenConnectionSender actor to request a connection with the robot leader.
 /// This happens when this screen connects after the robot leader.
 #[derive(Message)]
@@ -146,18 +199,31 @@ impl Handler<RequestRobotLeaderConnection> for ScreenConnectionSender {
         _ctx: &mut Context<Self>,
     ) -> Self::Result {
         let message = ScreenMessage::RequestRobotLeaderConnection { screen_id: msg.id };
-        let msg = match message.to_string() {
-            Ok(string) => string,
+        let msg = match message.to_bytes() {
+            Ok(bytes) => bytes,
             Err(err) => {
-                println!("Error converting message to string: {}", err);
+                log::error("SCS", format!("Error encoding message: {}", err));
                 return;
             }
         };
-        let msg = msg + "\n";
         let writer = self.socket_write.clone();
         wrap_future::<_, Self>(async move {
-            let _ = writer.lock().await.write_all(msg.as_bytes()).await;
+            let _ = writer.lock().await.write_all(&msg).await;
         })
         .spawn(_ctx);
     }
 }
+
+/// Closes the connection with the next screen once the backups being written are sent, without connecting to another one
+impl Handler<CloseConnection> for ScreenConnectionSender {
+    type Result = ();
+
+    fn handle(&mut self, _msg: CloseConnection, ctx: &mut Context<Self>) -> Self::Result {
+        let writer = self.socket_write.clone();
+        wrap_future::<_, Self>(async move {
+            let _ = writer.lock().await.shutdown().await;
+        })
+        .map(|_, _, ctx| ctx.stop())
+        .wait(ctx);
+    }
+}
@@ -0,0 +1,38 @@
+//! Webhook of a screen, so other systems, like a point of sale, can react to the result of the orders without reading its output.
+//! Each confirmed or aborted order is posted to the webhook of the config.
+
+use crate::common::webhook;
+use crate::screen::order_api::OrderStatus;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Body posted to the webhook when an order is confirmed or aborted
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OrderOutcome {
+    pub screen_id: usize,
+    pub order_id: String,
+    pub timestamp_ms: u64,
+    pub outcome: OrderStatus,
+}
+
+impl OrderOutcome {
+    pub fn new(screen_id: usize, order_id: &str, outcome: OrderStatus) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            screen_id,
+            order_id: order_id.to_string(),
+            timestamp_ms,
+            outcome,
+        }
+    }
+}
+
+/// Posts the outcome of the order to the webhook of the config, in the background.
+/// Nothing is posted if there is no webhook
+pub fn notify(screen_id: usize, order_id: &str, outcome: OrderStatus) {
+    let subject = format!("the outcome of order {:?}", order_id);
+    webhook::send(OrderOutcome::new(screen_id, order_id, outcome), subject);
+}